@@ -0,0 +1,138 @@
+// Compares applying updates to books keyed by Decimal prices and by i64 ticks, with the
+// levels in a BTreeMap or a sorted Vec.
+// The number of updates is set with BENCH_UPDATES.
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::collections::BTreeMap;
+use std::env;
+use std::io;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Instant;
+
+use rust_decimal::Decimal;
+use rust_order_book_practice::order_book::book_side::BookSide;
+use rust_order_book_practice::order_book::order_book::OrderBook;
+use rust_order_book_practice::order_book::price::{PriceKey, Ticks};
+use rust_order_book_practice::order_book::sorted_vec_side::SortedVecSide;
+use rust_order_book_practice::order_book::units::Qty;
+use rust_order_book_practice::parsing::order_book_snapshot::{Level, OrderBookSnapshot};
+use rust_order_book_practice::parsing::order_book_update::{
+    OrderBookUpdate, OrderBookUpdateParser, UpdateFormat,
+};
+use rust_order_book_practice::parsing::parser::Parser;
+
+// Counts the allocations made while a book is built and updated
+struct CountingAllocator;
+
+static ALLOCATIONS: AtomicU64 = AtomicU64::new(0);
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        unsafe { System.alloc(layout) }
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        unsafe { System.dealloc(ptr, layout) }
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        unsafe { System.realloc(ptr, layout, new_size) }
+    }
+}
+
+#[global_allocator]
+static GLOBAL: CountingAllocator = CountingAllocator;
+
+const DEFAULT_UPDATES: u64 = 200_000;
+const LEVELS_PER_UPDATE: u64 = 4;
+// Levels of each side the updates move across
+const PRICE_RANGE: u64 = 500;
+
+fn snapshot() -> OrderBookSnapshot {
+    let level = |price: f64| Level {
+        price,
+        qty: Qty::from(100),
+    };
+    OrderBookSnapshot {
+        timestamp: 1705717810000,
+        seq_no: 0,
+        security_id: 1,
+        bids: vec![
+            level(4999.99),
+            level(4999.98),
+            level(4999.97),
+            level(4999.96),
+            level(4999.95),
+        ],
+        asks: vec![
+            level(5000.00),
+            level(5000.01),
+            level(5000.02),
+            level(5000.03),
+            level(5000.04),
+        ],
+    }
+}
+
+fn read_updates(count: u64) -> Vec<OrderBookUpdate> {
+    let mut data = Vec::new();
+    for i in 0..count {
+        data.extend_from_slice(&(1705717810000 + i).to_le_bytes()); // timestamp
+        data.extend_from_slice(&(i + 1).to_le_bytes()); // seq_no
+        data.extend_from_slice(&1u64.to_le_bytes()); // security_id
+        data.extend_from_slice(&LEVELS_PER_UPDATE.to_le_bytes()); // num_updates
+        for level in 0..LEVELS_PER_UPDATE {
+            let side = level % 2;
+            let offset = (i * 7 + level * 13) % PRICE_RANGE;
+            let price = if side == 0 {
+                4999.99 - offset as f64 * 0.01
+            } else {
+                5000.00 + offset as f64 * 0.01
+            };
+            data.push(side as u8);
+            data.extend_from_slice(&price.to_le_bytes());
+            data.extend_from_slice(&((i + level) % 50).to_le_bytes()); // qty, 0 removes
+        }
+    }
+    let mut parser = OrderBookUpdateParser::new(UpdateFormat::V1);
+    let mut reader = data.as_slice();
+    (0..count)
+        .map(|_| parser.read(&mut reader).expect("Generated data is valid"))
+        .collect()
+}
+
+fn run<P: PriceKey, S: BookSide<P>>(name: &str, updates: &[OrderBookUpdate]) {
+    let allocations = ALLOCATIONS.load(Ordering::Relaxed);
+    let mut book = OrderBook::<P, S>::from_snapshot(&snapshot()).expect("Snapshot is valid");
+    let start = Instant::now();
+    for update in updates {
+        book.apply_update(update).expect("Update is valid");
+    }
+    let elapsed = start.elapsed();
+    let allocations = ALLOCATIONS.load(Ordering::Relaxed) - allocations;
+    println!(
+        "{:<10} {:>10} updates in {:>8.3}s, {:>10.0} updates/s, {:>8} allocations (checksum {})",
+        name,
+        updates.len(),
+        elapsed.as_secs_f64(),
+        updates.len() as f64 / elapsed.as_secs_f64(),
+        allocations,
+        book.checksum()
+    );
+}
+
+fn main() -> io::Result<()> {
+    let count = env::var("BENCH_UPDATES")
+        .ok()
+        .and_then(|count| count.parse().ok())
+        .unwrap_or(DEFAULT_UPDATES);
+    let updates = read_updates(count);
+
+    for _ in 0..2 {
+        run::<Decimal, BTreeMap<_, _>>("decimal", &updates);
+        run::<Ticks, BTreeMap<_, _>>("ticks", &updates);
+        run::<Ticks, SortedVecSide<_, _>>("ticks_vec", &updates);
+    }
+    Ok(())
+}
@@ -0,0 +1,68 @@
+// Compares the BookSide storages of the levels of one book side on a top-of-book workload:
+// levels set and removed near the best price, then the best level read.
+// The number of operations is set with BENCH_OPERATIONS.
+use std::collections::BTreeMap;
+use std::env;
+use std::hint::black_box;
+use std::time::Instant;
+
+use rust_order_book_practice::order_book::book_side::BookSide;
+use rust_order_book_practice::order_book::order_book::Side;
+use rust_order_book_practice::order_book::price::Ticks;
+use rust_order_book_practice::order_book::sorted_vec_side::SortedVecSide;
+use rust_order_book_practice::order_book::units::Qty;
+
+const DEFAULT_OPERATIONS: u64 = 10_000_000;
+// Levels kept in the book, and how far from the best price they change
+const DEPTH: u64 = 20;
+
+// Deterministic price near the best and quantity, 0 removing the level
+fn operation(i: u64) -> (Ticks, u64) {
+    let offset = (i.wrapping_mul(2654435761) >> 7) % DEPTH;
+    (Ticks(500_000 + offset as i64), i % 4)
+}
+
+fn report(name: &str, operations: u64, start: Instant, checksum: u64) {
+    let elapsed = start.elapsed();
+    println!(
+        "{:<10} {:>10} operations in {:>8.3}s, {:>12.0} operations/s (checksum {})",
+        name,
+        operations,
+        elapsed.as_secs_f64(),
+        operations as f64 / elapsed.as_secs_f64(),
+        checksum
+    );
+}
+
+fn run<S: BookSide<Ticks>>(name: &str, mut side: S, operations: u64) {
+    let mut checksum = 0u64;
+    let start = Instant::now();
+    for i in 0..operations {
+        let (price, qty) = operation(i);
+        if qty == 0 {
+            side.remove(&price);
+        } else {
+            side.insert(price, Qty::from(qty));
+        }
+        if let Some((best, qty)) = side.best(Side::Ask) {
+            checksum = checksum.wrapping_add(best.0 as u64 ^ qty.value());
+        }
+    }
+    report(name, operations, start, black_box(checksum));
+}
+
+fn main() {
+    let operations = env::var("BENCH_OPERATIONS")
+        .ok()
+        .and_then(|operations| operations.parse().ok())
+        .unwrap_or(DEFAULT_OPERATIONS);
+
+    for _ in 0..2 {
+        run("btree_map", BTreeMap::new(), operations);
+        run(
+            "sorted_vec",
+            SortedVecSide::with_capacity(DEPTH as usize),
+            operations,
+        );
+    }
+}
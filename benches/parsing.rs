@@ -0,0 +1,148 @@
+// Compares the buffered, batched and memory-mapped parsing of an incremental file.
+// The size of the generated file is set with BENCH_FILE_MB, e.g. for multi-GB runs:
+//   BENCH_FILE_MB=4096 cargo bench --bench parsing
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::env;
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Instant;
+
+use rust_order_book_practice::parsing::binary_file_iterator::BinaryFileIterator;
+use rust_order_book_practice::parsing::mmap_file_iterator::MmapFileIterator;
+use rust_order_book_practice::parsing::order_book_update::{
+    OrderBookUpdate, OrderBookUpdateParser, UpdateFormat,
+};
+
+// Counts the allocations made while parsing
+struct CountingAllocator;
+
+static ALLOCATIONS: AtomicU64 = AtomicU64::new(0);
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        unsafe { System.alloc(layout) }
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        unsafe { System.dealloc(ptr, layout) }
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        unsafe { System.realloc(ptr, layout, new_size) }
+    }
+}
+
+#[global_allocator]
+static GLOBAL: CountingAllocator = CountingAllocator;
+
+const DEFAULT_FILE_MB: u64 = 512;
+const LEVELS_PER_UPDATE: u64 = 4;
+const SECURITIES: u64 = 16;
+const BATCH_SIZE: usize = 64;
+
+fn write_updates(path: &Path, size: u64) -> io::Result<u64> {
+    let mut writer = BufWriter::new(File::create(path)?);
+    let record_size = 32 + LEVELS_PER_UPDATE * 17;
+    let records = size / record_size;
+    for i in 0..records {
+        writer.write_all(&(1705717810000 + i).to_le_bytes())?; // timestamp
+        writer.write_all(&(i / SECURITIES).to_le_bytes())?; // seq_no
+        writer.write_all(&(i % SECURITIES).to_le_bytes())?; // security_id
+        writer.write_all(&LEVELS_PER_UPDATE.to_le_bytes())?; // num_updates
+        for level in 0..LEVELS_PER_UPDATE {
+            writer.write_all(&[(level % 2) as u8])?; // side
+            writer.write_all(&(5000.0 + level as f64 * 0.25).to_le_bytes())?; // price
+            writer.write_all(&(100 + i % 1000).to_le_bytes())?; // qty
+        }
+    }
+    writer.flush()?;
+    Ok(records)
+}
+
+// Records read and the sum of their quantities
+#[derive(Default)]
+struct Totals {
+    count: u64,
+    qty: u64,
+}
+
+impl Totals {
+    fn add(&mut self, record: &OrderBookUpdate) {
+        self.count += 1;
+        record
+            .updates
+            .for_each(|level| {
+                self.qty = self.qty.wrapping_add(level.qty.value());
+                Ok::<(), ()>(())
+            })
+            .unwrap();
+    }
+}
+
+fn measure(name: &str, size: u64, read: impl FnOnce(&mut Totals)) {
+    let allocations = ALLOCATIONS.load(Ordering::Relaxed);
+    let start = Instant::now();
+    let mut totals = Totals::default();
+    read(&mut totals);
+    let elapsed = start.elapsed();
+    let allocations = ALLOCATIONS.load(Ordering::Relaxed) - allocations;
+    println!(
+        "{:<10} {:>10} records in {:>8.3}s, {:>8.1} MB/s, {:>8} allocations (checksum {})",
+        name,
+        totals.count,
+        elapsed.as_secs_f64(),
+        size as f64 / (1 << 20) as f64 / elapsed.as_secs_f64(),
+        allocations,
+        totals.qty
+    );
+}
+
+fn run<I: Iterator<Item = io::Result<OrderBookUpdate>>>(name: &str, records: I, size: u64) {
+    measure(name, size, |totals| {
+        for record in records {
+            totals.add(&record.expect("Generated file is valid"));
+        }
+    });
+}
+
+fn run_batched(mut records: BinaryFileIterator<OrderBookUpdate>, size: u64) {
+    measure("batched", size, |totals| {
+        let mut batch = Vec::with_capacity(BATCH_SIZE);
+        while records
+            .fill_batch(&mut batch)
+            .expect("Generated file is valid")
+            > 0
+        {
+            for record in batch.drain(..) {
+                totals.add(&record);
+            }
+        }
+    });
+}
+
+fn main() -> io::Result<()> {
+    let size_mb = env::var("BENCH_FILE_MB")
+        .ok()
+        .and_then(|size| size.parse().ok())
+        .unwrap_or(DEFAULT_FILE_MB);
+    let path = env::temp_dir().join("rust_order_book_practice_bench_updates.bin");
+    let records = write_updates(&path, size_mb << 20)?;
+    let size = fs::metadata(&path)?.len();
+    println!("{} updates, {} MB", records, size >> 20);
+
+    // Twice each, the first runs also warm up the page cache
+    for _ in 0..2 {
+        let parser = OrderBookUpdateParser::new(UpdateFormat::V1);
+        run("buffered", BinaryFileIterator::open(&path, parser)?, size);
+        let parser = OrderBookUpdateParser::new(UpdateFormat::V1);
+        run_batched(BinaryFileIterator::open(&path, parser)?, size);
+        let parser = OrderBookUpdateParser::new(UpdateFormat::V1);
+        run("mmap", MmapFileIterator::open(&path, parser)?, size);
+    }
+
+    fs::remove_file(&path)
+}
@@ -0,0 +1,38 @@
+// Generates the gRPC service of proto/book_service.proto when built with the grpc feature.
+// The service is described here rather than compiled from the .proto file so building
+// needs no protoc, the messages being declared by hand in src/output/grpc_service.rs.
+fn main() {
+    println!("cargo:rerun-if-changed=build.rs");
+    #[cfg(feature = "grpc")]
+    generate_book_service();
+}
+
+#[cfg(feature = "grpc")]
+fn generate_book_service() {
+    use tonic_build::manual::{Builder, Method, Service};
+
+    let method = |name: &str, route_name: &str, input_type: &str, output_type: &str| {
+        Method::builder()
+            .name(name)
+            .route_name(route_name)
+            .input_type(format!("crate::output::grpc_service::{}", input_type))
+            .output_type(format!("crate::output::grpc_service::{}", output_type))
+            .codec_path("tonic_prost::ProstCodec")
+    };
+    let service = Service::builder()
+        .name("BookService")
+        .package("order_book")
+        .method(method("get_book", "GetBook", "BookRequest", "Book").build())
+        .method(
+            method(
+                "stream_top_of_book",
+                "StreamTopOfBook",
+                "TopOfBookRequest",
+                "TopOfBook",
+            )
+            .server_streaming()
+            .build(),
+        )
+        .build();
+    Builder::new().compile(&[service]);
+}
@@ -0,0 +1,8 @@
+pub mod book_metrics;
+pub mod candles;
+pub mod latency;
+pub mod midprice;
+pub mod order_flow;
+pub mod pricing;
+pub mod spread_stats;
+pub mod trade_inference;
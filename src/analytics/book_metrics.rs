@@ -0,0 +1,219 @@
+use rust_decimal::Decimal;
+use std::fmt::Display;
+
+use crate::order_book::order_book::{OrderBook, Side};
+use crate::order_book::price::PRICE_TICK;
+
+// Distance from the best price of a side
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PriceBand {
+    // Number of PRICE_TICK steps
+    Ticks(u64),
+    Price(Decimal),
+}
+
+impl PriceBand {
+    pub fn width(&self) -> Decimal {
+        match self {
+            PriceBand::Ticks(ticks) => PRICE_TICK * Decimal::from(*ticks),
+            PriceBand::Price(price) => *price,
+        }
+    }
+}
+
+impl OrderBook {
+    // Volume-weighted average price of the best `levels` levels of the side
+    pub fn vwap(&self, side: Side, levels: usize) -> Option<Decimal> {
+        let mut notional = Decimal::ZERO;
+        let mut total_qty = Decimal::ZERO;
+        for level in self.top_levels(side, levels) {
+            let qty = Decimal::from(level.qty.value());
+            notional += level.price.value() * qty;
+            total_qty += qty;
+        }
+        if total_qty.is_zero() {
+            return None;
+        }
+        Some(notional / total_qty)
+    }
+
+    fn side_qty(&self, side: Side, levels: usize) -> Decimal {
+        self.top_levels(side, levels)
+            .iter()
+            .map(|level| Decimal::from(level.qty.value()))
+            .sum()
+    }
+
+    // (bid_qty - ask_qty) / (bid_qty + ask_qty) over the best `levels` levels, in [-1, 1]
+    pub fn imbalance(&self, levels: usize) -> Option<Decimal> {
+        let bid_qty = self.side_qty(Side::Bid, levels);
+        let ask_qty = self.side_qty(Side::Ask, levels);
+        let total_qty = bid_qty + ask_qty;
+        if total_qty.is_zero() {
+            return None;
+        }
+        Some((bid_qty - ask_qty) / total_qty)
+    }
+
+    pub fn mid_price(&self) -> Option<Decimal> {
+        let (bid_price, _) = self.best_bid()?;
+        let (ask_price, _) = self.best_ask()?;
+        Some((bid_price + ask_price) / Decimal::TWO)
+    }
+
+    // Best bid and ask prices weighted by the opposite side quantity
+    pub fn microprice(&self) -> Option<Decimal> {
+        let (bid_price, bid_qty) = self.best_bid()?;
+        let (ask_price, ask_qty) = self.best_ask()?;
+        let bid_qty = Decimal::from(bid_qty.value());
+        let ask_qty = Decimal::from(ask_qty.value());
+        Some((bid_price * ask_qty + ask_price * bid_qty) / (bid_qty + ask_qty))
+    }
+
+    // Total bid and ask quantity priced within the band of the best price of their side,
+    // the best level included. An empty side has none.
+    pub fn depth_within(&self, band: PriceBand) -> (u64, u64) {
+        let width = band.width();
+        let bid_qty = match self.best_bid() {
+            Some((best, _)) => self
+                .bids
+                .range(best - width..)
+                .map(|(_, qty)| qty.value())
+                .sum(),
+            None => 0,
+        };
+        let ask_qty = match self.best_ask() {
+            Some((best, _)) => self
+                .asks
+                .range(..=best + width)
+                .map(|(_, qty)| qty.value())
+                .sum(),
+            None => 0,
+        };
+        (bid_qty, ask_qty)
+    }
+
+    // The microprice over the VWAPs and quantities of the best `levels` levels of each
+    // side, the same as microprice() for a single level
+    pub fn weighted_mid(&self, levels: usize) -> Option<Decimal> {
+        let bid_vwap = self.vwap(Side::Bid, levels)?;
+        let ask_vwap = self.vwap(Side::Ask, levels)?;
+        let bid_qty = self.side_qty(Side::Bid, levels);
+        let ask_qty = self.side_qty(Side::Ask, levels);
+        Some((bid_vwap * ask_qty + ask_vwap * bid_qty) / (bid_qty + ask_qty))
+    }
+}
+
+#[derive(Debug)]
+pub struct BookMetrics {
+    pub security_id: u64,
+    pub levels: usize,
+    pub bid_vwap: Option<Decimal>,
+    pub ask_vwap: Option<Decimal>,
+    pub imbalance: Option<Decimal>,
+    pub microprice: Option<Decimal>,
+}
+
+impl BookMetrics {
+    pub fn compute(book: &OrderBook, levels: usize) -> Self {
+        Self {
+            security_id: book.security_id,
+            levels,
+            bid_vwap: book.vwap(Side::Bid, levels),
+            ask_vwap: book.vwap(Side::Ask, levels),
+            imbalance: book.imbalance(levels),
+            microprice: book.microprice(),
+        }
+    }
+}
+
+impl Display for BookMetrics {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let fmt_value = |value: Option<Decimal>| {
+            value
+                .map(|value| format!("{:.4}", value))
+                .unwrap_or_else(|| "n/a".to_string())
+        };
+        writeln!(f, "BookMetrics {{")?;
+        writeln!(f, "  security_id: {}", self.security_id)?;
+        writeln!(f, "  levels: {}", self.levels)?;
+        writeln!(f, "  bid_vwap: {}", fmt_value(self.bid_vwap))?;
+        writeln!(f, "  ask_vwap: {}", fmt_value(self.ask_vwap))?;
+        writeln!(f, "  imbalance: {}", fmt_value(self.imbalance))?;
+        writeln!(f, "  microprice: {}", fmt_value(self.microprice))?;
+        writeln!(f, "}}")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parsing::order_book_snapshot::test_support::create_test_snapshot;
+    use rust_decimal::dec;
+
+    #[test]
+    fn test_vwap() {
+        let book = OrderBook::new(&create_test_snapshot(1001, 100)).unwrap();
+
+        // (100 * 10 + 99 * 20 + 98 * 30 + 97 * 40) / 100
+        assert_eq!(book.vwap(Side::Bid, 4), Some(dec!(98)));
+        // (101 * 15 + 102 * 25) / 40
+        assert_eq!(book.vwap(Side::Ask, 2), Some(dec!(101.625)));
+        // Only the best level
+        assert_eq!(book.vwap(Side::Ask, 1), Some(dec!(101)));
+        assert_eq!(book.vwap(Side::Ask, 0), None);
+    }
+
+    #[test]
+    fn test_imbalance() {
+        let book = OrderBook::new(&create_test_snapshot(1001, 100)).unwrap();
+
+        // (10 - 15) / 25 at the touch
+        assert_eq!(book.imbalance(1), Some(dec!(-0.2)));
+        // (150 - 175) / 325 over the whole book
+        assert_eq!(book.imbalance(5), Some(dec!(-1) / dec!(13)));
+    }
+
+    #[test]
+    fn test_mid_price_and_microprice() {
+        let book = OrderBook::new(&create_test_snapshot(1001, 100)).unwrap();
+
+        assert_eq!(book.mid_price(), Some(dec!(100.5)));
+        // (100 * 15 + 101 * 10) / 25, pulled towards the bid by the larger ask size
+        assert_eq!(book.microprice(), Some(dec!(100.4)));
+    }
+
+    #[test]
+    fn test_depth_within() {
+        let book = OrderBook::new(&create_test_snapshot(1001, 100)).unwrap();
+
+        assert_eq!(book.depth_within(PriceBand::Ticks(0)), (10, 15));
+        // 99 and 102 are 100 ticks away
+        assert_eq!(book.depth_within(PriceBand::Ticks(99)), (10, 15));
+        assert_eq!(book.depth_within(PriceBand::Ticks(100)), (30, 40));
+        assert_eq!(book.depth_within(PriceBand::Price(dec!(0.5))), (10, 15));
+        assert_eq!(book.depth_within(PriceBand::Price(dec!(1000))), (150, 175));
+    }
+
+    #[test]
+    fn test_weighted_mid() {
+        let book = OrderBook::new(&create_test_snapshot(1001, 100)).unwrap();
+
+        assert_eq!(book.weighted_mid(1), book.microprice());
+        // VWAPs of 99.3333 and 101.625 over 30 and 40 lots
+        let weighted_mid = book.weighted_mid(2).unwrap();
+        assert_eq!(weighted_mid.round_dp(4), dec!(100.3155));
+        assert_eq!(book.weighted_mid(0), None);
+    }
+
+    #[test]
+    fn test_metrics_on_one_sided_book() {
+        let mut book = OrderBook::new(&create_test_snapshot(1001, 100)).unwrap();
+        book.asks.clear();
+
+        let metrics = BookMetrics::compute(&book, 5);
+        assert_eq!(metrics.ask_vwap, None);
+        assert_eq!(metrics.microprice, None);
+        assert_eq!(metrics.imbalance, Some(dec!(1)));
+    }
+}
@@ -0,0 +1,351 @@
+use rust_decimal::Decimal;
+use std::collections::BTreeMap;
+use std::io::{self, Write};
+use std::str::FromStr;
+
+use crate::clock::Clock;
+use crate::order_book::order_book::OrderBook;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CandleInterval {
+    OneSecond,
+    OneMinute,
+}
+
+impl CandleInterval {
+    // Timestamps are in milliseconds
+    pub fn millis(&self) -> u64 {
+        match self {
+            CandleInterval::OneSecond => 1_000,
+            CandleInterval::OneMinute => 60_000,
+        }
+    }
+}
+
+impl FromStr for CandleInterval {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "1s" => Ok(CandleInterval::OneSecond),
+            "1m" => Ok(CandleInterval::OneMinute),
+            _ => Err(format!("Unknown candle interval: {}", s)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Ohlc {
+    pub open: Decimal,
+    pub high: Decimal,
+    pub low: Decimal,
+    pub close: Decimal,
+}
+
+impl Ohlc {
+    fn new(value: Decimal) -> Self {
+        Self {
+            open: value,
+            high: value,
+            low: value,
+            close: value,
+        }
+    }
+
+    fn update(&mut self, value: Decimal) {
+        self.high = self.high.max(value);
+        self.low = self.low.min(value);
+        self.close = value;
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Candle {
+    pub security_id: u64,
+    pub start_timestamp: u64,
+    pub mid: Ohlc,
+    pub spread: Option<Ohlc>,
+}
+
+impl Candle {
+    pub fn write_csv_header<W: Write>(writer: &mut W, with_spread: bool) -> io::Result<()> {
+        write!(writer, "security_id,start_timestamp,open,high,low,close")?;
+        if with_spread {
+            write!(writer, ",spread_open,spread_high,spread_low,spread_close")?;
+        }
+        writeln!(writer)
+    }
+
+    pub fn write_csv_row<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        write!(
+            writer,
+            "{},{},{},{},{},{}",
+            self.security_id,
+            self.start_timestamp,
+            self.mid.open,
+            self.mid.high,
+            self.mid.low,
+            self.mid.close
+        )?;
+        if let Some(spread) = &self.spread {
+            write!(
+                writer,
+                ",{},{},{},{}",
+                spread.open, spread.high, spread.low, spread.close
+            )?;
+        }
+        writeln!(writer)
+    }
+}
+
+// Builds OHLC bars of the mid-price (and optionally of the spread) per security from
+// the states of the books, emitting each bar once a state falls into a later interval.
+// Books without both sides are skipped.
+pub struct CandleAggregator {
+    interval: CandleInterval,
+    with_spread: bool,
+    open_candles: BTreeMap<u64, Candle>,
+    on_candle: Box<dyn FnMut(&Candle)>,
+}
+
+impl CandleAggregator {
+    pub fn new(
+        interval: CandleInterval,
+        with_spread: bool,
+        on_candle: Box<dyn FnMut(&Candle)>,
+    ) -> Self {
+        Self {
+            interval,
+            with_spread,
+            open_candles: BTreeMap::new(),
+            on_candle,
+        }
+    }
+
+    pub fn observe(&mut self, book: &OrderBook) {
+        let (Some((bid_price, _)), Some((ask_price, _))) = (book.best_bid(), book.best_ask())
+        else {
+            return;
+        };
+        let mid = ((bid_price + ask_price) / Decimal::TWO).normalize();
+        let spread = ask_price - bid_price;
+        let start_timestamp = book.timestamp - book.timestamp % self.interval.millis();
+
+        if let Some(candle) = self.open_candles.get_mut(&book.security_id) {
+            // Out of order timestamps are folded into the current bar
+            if start_timestamp <= candle.start_timestamp {
+                candle.mid.update(mid);
+                if let Some(spread_ohlc) = candle.spread.as_mut() {
+                    spread_ohlc.update(spread);
+                }
+                return;
+            }
+            (self.on_candle)(candle);
+        }
+
+        self.open_candles.insert(
+            book.security_id,
+            Candle {
+                security_id: book.security_id,
+                start_timestamp,
+                mid: Ohlc::new(mid),
+                spread: self.with_spread.then(|| Ohlc::new(spread)),
+            },
+        );
+    }
+
+    // Emits the bars whose interval has ended by the clock, so that bars of securities
+    // without updates are not held back until their next update
+    pub fn close_expired(&mut self, clock: &dyn Clock) {
+        let now_millis = clock.now_millis();
+        let interval_millis = self.interval.millis();
+        let on_candle = &mut self.on_candle;
+        self.open_candles.retain(|_, candle| {
+            if candle.start_timestamp + interval_millis <= now_millis {
+                on_candle(candle);
+                return false;
+            }
+            true
+        });
+    }
+
+    // Emits the bars still being built, e.g. at the end of the input
+    pub fn flush(&mut self) {
+        for candle in std::mem::take(&mut self.open_candles).values() {
+            (self.on_candle)(candle);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::batched_deque::batched_deque::BatchedDeque;
+    use crate::clock::TestClock;
+    use crate::order_book::units::Qty;
+    use crate::parsing::order_book_snapshot::test_support::create_test_snapshot;
+    use crate::parsing::order_book_update::OrderBookUpdate;
+    use crate::parsing::order_book_update::{Level as UpdateLevel, Side};
+    use rust_decimal::dec;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    fn create_test_update(
+        security_id: u64,
+        seq_no: u64,
+        timestamp: u64,
+        levels: Vec<(Side, f64, u64)>,
+    ) -> OrderBookUpdate {
+        let deque = BatchedDeque::new(10);
+        let levels = levels.into_iter().map(|(side, price, qty)| {
+            Ok::<UpdateLevel, ()>(UpdateLevel {
+                side,
+                price,
+                qty: Qty(qty),
+                metadata: None,
+            })
+        });
+
+        OrderBookUpdate {
+            timestamp,
+            capture_timestamp: None,
+            seq_no,
+            security_id,
+            thinned_from: None,
+            updates: deque.push_back_batch(levels).unwrap(),
+        }
+    }
+
+    fn create_test_aggregator(
+        interval: CandleInterval,
+        with_spread: bool,
+    ) -> (CandleAggregator, Rc<RefCell<Vec<Candle>>>) {
+        let candles = Rc::new(RefCell::new(Vec::new()));
+        let emitted = candles.clone();
+        let aggregator = CandleAggregator::new(
+            interval,
+            with_spread,
+            Box::new(move |candle: &Candle| emitted.borrow_mut().push(*candle)),
+        );
+        (aggregator, candles)
+    }
+
+    #[test]
+    fn test_candle_ohlc_within_interval() {
+        let mut book = OrderBook::new(&create_test_snapshot(1001, 100)).unwrap();
+        let (mut aggregator, candles) = create_test_aggregator(CandleInterval::OneSecond, true);
+
+        book.timestamp = 5_000;
+        aggregator.observe(&book);
+        // Best ask moves to 102.00: mid 101.00, spread 2.00
+        let update = create_test_update(1001, 101, 5_400, vec![(Side::Ask, 101.00, 0)]);
+        book.apply_update(&update).unwrap();
+        aggregator.observe(&book);
+        // Best ask back at 101.00, best bid moves to 99.00: mid 100.00, spread 2.00
+        let update = create_test_update(
+            1001,
+            102,
+            5_900,
+            vec![(Side::Ask, 101.00, 5), (Side::Bid, 100.00, 0)],
+        );
+        book.apply_update(&update).unwrap();
+        aggregator.observe(&book);
+        assert!(candles.borrow().is_empty());
+
+        aggregator.flush();
+        assert_eq!(
+            *candles.borrow(),
+            vec![Candle {
+                security_id: 1001,
+                start_timestamp: 5_000,
+                mid: Ohlc {
+                    open: dec!(100.5),
+                    high: dec!(101),
+                    low: dec!(100),
+                    close: dec!(100),
+                },
+                spread: Some(Ohlc {
+                    open: dec!(1),
+                    high: dec!(2),
+                    low: dec!(1),
+                    close: dec!(2),
+                }),
+            }]
+        );
+    }
+
+    #[test]
+    fn test_candle_emitted_on_next_interval() {
+        let mut book = OrderBook::new(&create_test_snapshot(1001, 100)).unwrap();
+        let (mut aggregator, candles) = create_test_aggregator(CandleInterval::OneMinute, false);
+
+        book.timestamp = 59_999;
+        aggregator.observe(&book);
+        let update = create_test_update(1001, 101, 60_000, vec![(Side::Ask, 101.00, 0)]);
+        book.apply_update(&update).unwrap();
+        aggregator.observe(&book);
+
+        assert_eq!(candles.borrow().len(), 1);
+        assert_eq!(candles.borrow()[0].start_timestamp, 0);
+        assert_eq!(candles.borrow()[0].mid.close, dec!(100.5));
+        assert_eq!(candles.borrow()[0].spread, None);
+
+        aggregator.flush();
+        assert_eq!(candles.borrow().len(), 2);
+        assert_eq!(candles.borrow()[1].start_timestamp, 60_000);
+        assert_eq!(candles.borrow()[1].mid.open, dec!(101));
+    }
+
+    #[test]
+    fn test_close_expired() {
+        let mut book = OrderBook::new(&create_test_snapshot(1001, 100)).unwrap();
+        let (mut aggregator, candles) = create_test_aggregator(CandleInterval::OneSecond, false);
+        let clock = TestClock::new(5_500);
+
+        book.timestamp = 5_000;
+        aggregator.observe(&book);
+        aggregator.close_expired(&clock);
+        assert!(candles.borrow().is_empty());
+
+        clock.advance(500);
+        aggregator.close_expired(&clock);
+        assert_eq!(candles.borrow().len(), 1);
+        assert_eq!(candles.borrow()[0].start_timestamp, 5_000);
+
+        // Nothing left to emit
+        aggregator.flush();
+        assert_eq!(candles.borrow().len(), 1);
+    }
+
+    #[test]
+    fn test_one_sided_book_skipped() {
+        let mut book = OrderBook::new(&create_test_snapshot(1001, 100)).unwrap();
+        let (mut aggregator, candles) = create_test_aggregator(CandleInterval::OneSecond, false);
+
+        book.bids.clear();
+        aggregator.observe(&book);
+        aggregator.flush();
+
+        assert!(candles.borrow().is_empty());
+    }
+
+    #[test]
+    fn test_write_csv() {
+        let candle = Candle {
+            security_id: 1,
+            start_timestamp: 1000,
+            mid: Ohlc::new(dec!(100.5)),
+            spread: Some(Ohlc::new(dec!(1))),
+        };
+
+        let mut output = Vec::new();
+        Candle::write_csv_header(&mut output, true).unwrap();
+        candle.write_csv_row(&mut output).unwrap();
+
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "security_id,start_timestamp,open,high,low,close,spread_open,spread_high,spread_low,spread_close\n\
+             1,1000,100.5,100.5,100.5,100.5,1,1,1,1\n"
+        );
+    }
+}
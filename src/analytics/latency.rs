@@ -0,0 +1,156 @@
+use std::collections::BTreeMap;
+use std::fmt::Display;
+use std::io::{self, Write};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct LatencySample {
+    exchange_timestamp: u64,
+    capture_timestamp: u64,
+}
+
+impl LatencySample {
+    fn latency(&self) -> i64 {
+        self.capture_timestamp as i64 - self.exchange_timestamp as i64
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LatencySummary {
+    pub security_id: u64,
+    pub count: usize,
+    pub min: i64,
+    pub p50: i64,
+    pub p90: i64,
+    pub p99: i64,
+    pub max: i64,
+}
+
+impl Display for LatencySummary {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Latency {{ security_id: {}, count: {}, min: {}, p50: {}, p90: {}, p99: {}, max: {} }}",
+            self.security_id, self.count, self.min, self.p50, self.p90, self.p99, self.max
+        )
+    }
+}
+
+// Collects the capture - exchange timestamp difference of every event per security
+#[derive(Debug, Default)]
+pub struct LatencyStats {
+    samples: BTreeMap<u64, Vec<LatencySample>>,
+}
+
+impl LatencyStats {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn record(&mut self, security_id: u64, exchange_timestamp: u64, capture_timestamp: u64) {
+        self.samples
+            .entry(security_id)
+            .or_default()
+            .push(LatencySample {
+                exchange_timestamp,
+                capture_timestamp,
+            });
+    }
+
+    pub fn summary(&self, security_id: u64) -> Option<LatencySummary> {
+        let samples = self.samples.get(&security_id)?;
+        let mut latencies: Vec<i64> = samples.iter().map(LatencySample::latency).collect();
+        latencies.sort_unstable();
+        let percentile = |p: usize| {
+            // Nearest-rank percentile
+            let rank = (p * latencies.len()).div_ceil(100).max(1);
+            latencies[rank - 1]
+        };
+        Some(LatencySummary {
+            security_id,
+            count: latencies.len(),
+            min: latencies[0],
+            p50: percentile(50),
+            p90: percentile(90),
+            p99: percentile(99),
+            max: latencies[latencies.len() - 1],
+        })
+    }
+
+    pub fn summaries(&self) -> Vec<LatencySummary> {
+        self.samples
+            .keys()
+            .filter_map(|security_id| self.summary(*security_id))
+            .collect()
+    }
+
+    // Writes every sample as CSV, grouped by security
+    pub fn write_csv<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        writeln!(
+            writer,
+            "security_id,exchange_timestamp,capture_timestamp,latency"
+        )?;
+        for (security_id, samples) in &self.samples {
+            for sample in samples {
+                writeln!(
+                    writer,
+                    "{},{},{},{}",
+                    security_id,
+                    sample.exchange_timestamp,
+                    sample.capture_timestamp,
+                    sample.latency()
+                )?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_summary_percentiles() {
+        let mut stats = LatencyStats::new();
+        for latency in 1..=100 {
+            stats.record(1, 1000, 1000 + latency);
+        }
+
+        let summary = stats.summary(1).unwrap();
+        assert_eq!(summary.count, 100);
+        assert_eq!(summary.min, 1);
+        assert_eq!(summary.p50, 50);
+        assert_eq!(summary.p90, 90);
+        assert_eq!(summary.p99, 99);
+        assert_eq!(summary.max, 100);
+    }
+
+    #[test]
+    fn test_negative_latency_and_unknown_security() {
+        let mut stats = LatencyStats::new();
+        // Capture clock behind the exchange clock
+        stats.record(1, 1000, 995);
+
+        let summary = stats.summary(1).unwrap();
+        assert_eq!(summary.min, -5);
+        assert_eq!(summary.p50, -5);
+        assert!(stats.summary(2).is_none());
+    }
+
+    #[test]
+    fn test_write_csv() {
+        let mut stats = LatencyStats::new();
+        stats.record(2, 1000, 1003);
+        stats.record(1, 2000, 2001);
+
+        let mut output = Vec::new();
+        stats.write_csv(&mut output).unwrap();
+
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "security_id,exchange_timestamp,capture_timestamp,latency\n\
+             1,2000,2001,1\n\
+             2,1000,1003,3\n"
+        );
+    }
+}
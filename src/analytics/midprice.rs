@@ -0,0 +1,142 @@
+use rust_decimal::Decimal;
+use std::collections::HashMap;
+use std::fmt::Display;
+
+use crate::order_book::level_ttl::ExpiredLevel;
+use crate::order_book::listener::BookListener;
+use crate::order_book::order_book::OrderBook;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MidPricePoint {
+    pub timestamp: u64,
+    pub security_id: u64,
+    pub mid: Decimal,
+    pub spread: Decimal,
+}
+
+impl Display for MidPricePoint {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "MidPrice {{ timestamp: {}, security_id: {}, mid: {}, spread: {} }}",
+            self.timestamp, self.security_id, self.mid, self.spread
+        )
+    }
+}
+
+// Hands the mid and the spread of a book to the callback every time its best bid or ask
+// price changes. Books with an empty side have no mid and produce nothing until both
+// sides are back. Registered on a Manager as a BookListener.
+pub struct MidPriceSeries {
+    // Best bid and ask prices last handed out for each security
+    last: HashMap<u64, (Decimal, Decimal)>,
+    on_point: Box<dyn FnMut(&MidPricePoint)>,
+}
+
+impl MidPriceSeries {
+    pub fn new(on_point: Box<dyn FnMut(&MidPricePoint)>) -> Self {
+        Self {
+            last: HashMap::new(),
+            on_point,
+        }
+    }
+
+    fn observe(&mut self, book: &OrderBook) {
+        let (Some((bid, _)), Some((ask, _))) = (book.best_bid(), book.best_ask()) else {
+            self.last.remove(&book.security_id);
+            return;
+        };
+        if self.last.insert(book.security_id, (bid, ask)) == Some((bid, ask)) {
+            return;
+        }
+        (self.on_point)(&MidPricePoint {
+            timestamp: book.timestamp,
+            security_id: book.security_id,
+            mid: ((bid + ask) / Decimal::TWO).normalize(),
+            spread: (ask - bid).normalize(),
+        });
+    }
+}
+
+impl BookListener for MidPriceSeries {
+    fn on_snapshot_applied(&mut self, book: &OrderBook) {
+        self.observe(book);
+    }
+
+    fn on_update_applied(&mut self, book: &OrderBook) {
+        self.observe(book);
+    }
+
+    fn on_levels_expired(&mut self, book: &OrderBook, _expired: &[ExpiredLevel]) {
+        self.observe(book);
+    }
+
+    fn on_book_evicted(&mut self, book: &OrderBook) {
+        self.last.remove(&book.security_id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parsing::order_book_snapshot::{
+        OrderBookSnapshot, test_support::create_test_snapshot,
+    };
+    use rust_decimal::dec;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    fn create_test_book(seq_no: u64, best_bid: f64) -> OrderBook {
+        let mut snapshot = OrderBookSnapshot {
+            timestamp: 1627846265 + seq_no,
+            ..create_test_snapshot(1001, seq_no)
+        };
+        // An empty side for a zero price
+        if best_bid > 0.0 {
+            snapshot.bids[0].price = best_bid;
+        } else {
+            snapshot.bids.clear();
+        }
+        OrderBook::new(&snapshot).unwrap()
+    }
+
+    #[test]
+    fn test_mid_price_series() {
+        let points = Rc::new(RefCell::new(Vec::new()));
+        let sink = points.clone();
+        let mut series = MidPriceSeries::new(Box::new(move |point: &MidPricePoint| {
+            sink.borrow_mut().push(*point)
+        }));
+        series.on_snapshot_applied(&create_test_book(100, 100.0));
+        // The top didn't change
+        series.on_snapshot_applied(&create_test_book(101, 100.0));
+        series.on_snapshot_applied(&create_test_book(102, 100.5));
+        // No bids, no mid
+        series.on_snapshot_applied(&create_test_book(103, 0.0));
+        series.on_snapshot_applied(&create_test_book(104, 100.5));
+
+        assert_eq!(
+            *points.borrow(),
+            vec![
+                MidPricePoint {
+                    timestamp: 1627846365,
+                    security_id: 1001,
+                    mid: dec!(100.5),
+                    spread: dec!(1),
+                },
+                MidPricePoint {
+                    timestamp: 1627846367,
+                    security_id: 1001,
+                    mid: dec!(100.75),
+                    spread: dec!(0.5),
+                },
+                MidPricePoint {
+                    timestamp: 1627846369,
+                    security_id: 1001,
+                    mid: dec!(100.75),
+                    spread: dec!(0.5),
+                },
+            ]
+        );
+    }
+}
@@ -0,0 +1,175 @@
+use rust_decimal::Decimal;
+use std::collections::HashMap;
+use std::fmt::Display;
+
+use crate::order_book::level_ttl::ExpiredLevel;
+use crate::order_book::listener::BookListener;
+use crate::order_book::order_book::OrderBook;
+use crate::order_book::units::Qty;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct OrderFlowImbalance {
+    pub timestamp: u64,
+    pub seq_no: u64,
+    pub security_id: u64,
+    // Quantity added to the bid side at the best price, negative when removed
+    pub bid_flow: i64,
+    // Same for the ask side
+    pub ask_flow: i64,
+}
+
+impl OrderFlowImbalance {
+    // Positive for buying pressure, negative for selling pressure
+    pub fn ofi(&self) -> i64 {
+        self.bid_flow - self.ask_flow
+    }
+}
+
+impl Display for OrderFlowImbalance {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "OrderFlow {{ timestamp: {}, seq_no: {}, security_id: {}, bid_flow: {}, ask_flow: {}, ofi: {} }}",
+            self.timestamp,
+            self.seq_no,
+            self.security_id,
+            self.bid_flow,
+            self.ask_flow,
+            self.ofi()
+        )
+    }
+}
+
+// Price and quantity at the best price of a side, None when the side is empty
+type BestLevel = Option<(Decimal, Qty)>;
+
+// Signed change of one side at its best price between two tops, as in Cont, Kukanov and
+// Stoikov: a better price adds its whole quantity, a worse one removes the whole previous
+// quantity and the same price adds the difference. `improves` tells whether the first
+// price is better than the second for the side, an empty side is worse than any price.
+fn side_flow(
+    previous: BestLevel,
+    current: BestLevel,
+    improves: impl Fn(Decimal, Decimal) -> bool,
+) -> i64 {
+    match (previous, current) {
+        (Some((previous_price, previous_qty)), Some((price, qty))) => {
+            let (previous_qty, qty) = (previous_qty.value(), qty.value());
+            if price == previous_price {
+                qty as i64 - previous_qty as i64
+            } else if improves(price, previous_price) {
+                qty as i64
+            } else {
+                -(previous_qty as i64)
+            }
+        }
+        (None, Some((_, qty))) => qty.value() as i64,
+        (Some((_, previous_qty)), None) => -(previous_qty.value() as i64),
+        (None, None) => 0,
+    }
+}
+
+// Hands the order-flow imbalance of every applied update to the callback, from the best
+// bid and ask before and after it. Snapshots and expired levels only set the top the next
+// update is compared with. Registered on a Manager as a BookListener.
+pub struct OrderFlowTracker {
+    // Best bid and ask after the last applied record of each security
+    last_tops: HashMap<u64, (BestLevel, BestLevel)>,
+    on_imbalance: Box<dyn FnMut(&OrderFlowImbalance)>,
+}
+
+impl OrderFlowTracker {
+    pub fn new(on_imbalance: Box<dyn FnMut(&OrderFlowImbalance)>) -> Self {
+        Self {
+            last_tops: HashMap::new(),
+            on_imbalance,
+        }
+    }
+
+    fn reset(&mut self, book: &OrderBook) {
+        self.last_tops
+            .insert(book.security_id, (book.best_bid(), book.best_ask()));
+    }
+}
+
+impl BookListener for OrderFlowTracker {
+    fn on_snapshot_applied(&mut self, book: &OrderBook) {
+        self.reset(book);
+    }
+
+    fn on_update_applied(&mut self, book: &OrderBook) {
+        let (bid, ask) = (book.best_bid(), book.best_ask());
+        let Some((previous_bid, previous_ask)) =
+            self.last_tops.insert(book.security_id, (bid, ask))
+        else {
+            return;
+        };
+        (self.on_imbalance)(&OrderFlowImbalance {
+            timestamp: book.timestamp,
+            seq_no: book.seq_no,
+            security_id: book.security_id,
+            bid_flow: side_flow(previous_bid, bid, |price, previous| price > previous),
+            ask_flow: side_flow(previous_ask, ask, |price, previous| price < previous),
+        });
+    }
+
+    fn on_levels_expired(&mut self, book: &OrderBook, _expired: &[ExpiredLevel]) {
+        self.reset(book);
+    }
+
+    fn on_book_evicted(&mut self, book: &OrderBook) {
+        self.last_tops.remove(&book.security_id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rust_decimal::dec;
+
+    #[test]
+    fn test_side_flow() {
+        let bid_flow = |previous, current| {
+            side_flow(previous, current, |price: Decimal, previous| {
+                price > previous
+            })
+        };
+        assert_eq!(
+            bid_flow(Some((dec!(100), Qty(10))), Some((dec!(100), Qty(25)))),
+            15
+        );
+        assert_eq!(
+            bid_flow(Some((dec!(100), Qty(10))), Some((dec!(100.5), Qty(5)))),
+            5
+        );
+        assert_eq!(
+            bid_flow(Some((dec!(100), Qty(10))), Some((dec!(99.5), Qty(30)))),
+            -10
+        );
+        assert_eq!(bid_flow(Some((dec!(100), Qty(10))), None), -10);
+        assert_eq!(bid_flow(None, Some((dec!(100), Qty(10)))), 10);
+
+        let ask_flow = |previous, current| {
+            side_flow(previous, current, |price: Decimal, previous| {
+                price < previous
+            })
+        };
+        assert_eq!(
+            ask_flow(Some((dec!(101), Qty(15))), Some((dec!(100.5), Qty(5)))),
+            5
+        );
+        assert_eq!(
+            ask_flow(Some((dec!(101), Qty(15))), Some((dec!(101.5), Qty(5)))),
+            -15
+        );
+
+        let imbalance = OrderFlowImbalance {
+            timestamp: 1627846266,
+            seq_no: 101,
+            security_id: 1001,
+            bid_flow: 15,
+            ask_flow: -15,
+        };
+        assert_eq!(imbalance.ofi(), 30);
+    }
+}
@@ -0,0 +1,321 @@
+use rust_decimal::Decimal;
+use std::collections::HashMap;
+use std::fmt::Display;
+use std::str::FromStr;
+
+use crate::analytics::trade_inference::TradeInference;
+use crate::order_book::listener::BookListener;
+use crate::order_book::order_book::OrderBook;
+
+// Fair value of a book, written as in the configuration:
+// "mid", "weighted-mid", "depth:N" or "last-trade:WEIGHT:FUNCTION"
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FairValue {
+    Mid,
+    // Best prices weighted by the opposite side quantity, the microprice
+    WeightedMid,
+    // Same over the VWAPs and quantities of the best N levels
+    DepthWeighted(usize),
+    // WEIGHT * last inferred trade price + (1 - WEIGHT) * FUNCTION. Just FUNCTION until
+    // the first trade of the security.
+    LastTradeBlend {
+        weight: Decimal,
+        base: Box<FairValue>,
+    },
+}
+
+impl FairValue {
+    pub fn compute(&self, book: &OrderBook, last_trade: Option<Decimal>) -> Option<Decimal> {
+        match self {
+            FairValue::Mid => book.mid_price(),
+            FairValue::WeightedMid => book.microprice(),
+            FairValue::DepthWeighted(levels) => book.weighted_mid(*levels),
+            FairValue::LastTradeBlend { weight, base } => {
+                let base_value = base.compute(book, last_trade)?;
+                match last_trade {
+                    Some(price) => Some(*weight * price + (Decimal::ONE - *weight) * base_value),
+                    None => Some(base_value),
+                }
+            }
+        }
+    }
+
+    pub fn uses_last_trade(&self) -> bool {
+        matches!(self, FairValue::LastTradeBlend { .. })
+    }
+}
+
+impl FromStr for FairValue {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.split_once(':') {
+            None if s == "mid" => Ok(FairValue::Mid),
+            None if s == "weighted-mid" => Ok(FairValue::WeightedMid),
+            Some(("depth", levels)) => match levels.parse() {
+                Ok(levels) if levels > 0 => Ok(FairValue::DepthWeighted(levels)),
+                _ => Err(format!("Invalid number of levels: {}", levels)),
+            },
+            Some(("last-trade", rest)) => {
+                let (weight, base) = rest
+                    .split_once(':')
+                    .ok_or_else(|| format!("Expected last-trade:WEIGHT:FUNCTION, got {}", s))?;
+                let weight = match Decimal::from_str(weight) {
+                    Ok(weight) if weight >= Decimal::ZERO && weight <= Decimal::ONE => weight,
+                    _ => return Err(format!("Weight must be between 0 and 1, got {}", weight)),
+                };
+                Ok(FairValue::LastTradeBlend {
+                    weight,
+                    base: Box::new(base.parse()?),
+                })
+            }
+            _ => Err(format!("Unknown fair value function: {}", s)),
+        }
+    }
+}
+
+impl Display for FairValue {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            FairValue::Mid => write!(f, "mid"),
+            FairValue::WeightedMid => write!(f, "weighted-mid"),
+            FairValue::DepthWeighted(levels) => write!(f, "depth:{}", levels),
+            FairValue::LastTradeBlend { weight, base } => {
+                write!(f, "last-trade:{}:{}", weight, base)
+            }
+        }
+    }
+}
+
+// Function of one security, or of every security without one, as [SECURITY_ID=]FUNCTION
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FairValueConfig {
+    pub security_id: Option<u64>,
+    pub function: FairValue,
+}
+
+impl FromStr for FairValueConfig {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.split_once('=') {
+            Some((security_id, function)) => Ok(FairValueConfig {
+                security_id: Some(
+                    security_id
+                        .parse()
+                        .map_err(|e| format!("Invalid security id {}: {}", security_id, e))?,
+                ),
+                function: function.parse()?,
+            }),
+            None => Ok(FairValueConfig {
+                security_id: None,
+                function: s.parse()?,
+            }),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FairValuePoint<'a> {
+    pub timestamp: u64,
+    pub seq_no: u64,
+    pub security_id: u64,
+    pub function: &'a FairValue,
+    pub value: Decimal,
+}
+
+impl Display for FairValuePoint<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "FairValue {{ timestamp: {}, seq_no: {}, security_id: {}, function: {}, value: {:.4} }}",
+            self.timestamp, self.seq_no, self.security_id, self.function, self.value
+        )
+    }
+}
+
+// Computes the configured fair value of every book after each applied record and hands
+// it to the callback. Registered on a Manager as a BookListener.
+pub struct Pricer {
+    default: Option<FairValue>,
+    functions: HashMap<u64, FairValue>,
+    trade_inference: TradeInference,
+    last_trades: HashMap<u64, Decimal>,
+    on_value: Box<dyn FnMut(&FairValuePoint)>,
+}
+
+impl Pricer {
+    // A later configuration of the same security replaces an earlier one
+    pub fn new(configs: &[FairValueConfig], on_value: Box<dyn FnMut(&FairValuePoint)>) -> Self {
+        let mut pricer = Self {
+            default: None,
+            functions: HashMap::new(),
+            trade_inference: TradeInference::new(),
+            last_trades: HashMap::new(),
+            on_value,
+        };
+        for config in configs {
+            match config.security_id {
+                Some(security_id) => {
+                    pricer
+                        .functions
+                        .insert(security_id, config.function.clone());
+                }
+                None => pricer.default = Some(config.function.clone()),
+            }
+        }
+        pricer
+    }
+
+    pub fn function(&self, security_id: u64) -> Option<&FairValue> {
+        self.functions.get(&security_id).or(self.default.as_ref())
+    }
+
+    fn observe(&mut self, book: &OrderBook, is_snapshot: bool) {
+        let Some(function) = self
+            .functions
+            .get(&book.security_id)
+            .or(self.default.as_ref())
+        else {
+            return;
+        };
+        if function.uses_last_trade() {
+            if is_snapshot {
+                self.trade_inference.reset(book);
+            } else if let Some(trade) = self.trade_inference.observe(book).last() {
+                self.last_trades.insert(book.security_id, trade.price);
+            }
+        }
+        let last_trade = self.last_trades.get(&book.security_id).copied();
+        if let Some(value) = function.compute(book, last_trade) {
+            (self.on_value)(&FairValuePoint {
+                timestamp: book.timestamp,
+                seq_no: book.seq_no,
+                security_id: book.security_id,
+                function,
+                value,
+            });
+        }
+    }
+}
+
+impl BookListener for Pricer {
+    fn on_snapshot_applied(&mut self, book: &OrderBook) {
+        self.observe(book, true);
+    }
+
+    fn on_update_applied(&mut self, book: &OrderBook) {
+        self.observe(book, false);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::batched_deque::batched_deque::BatchedDeque;
+    use crate::order_book::manager::Manager;
+    use crate::order_book::units::Qty;
+    use crate::parsing::order_book_snapshot::test_support::create_test_snapshot;
+    use crate::parsing::order_book_update::{Level as UpdateLevel, OrderBookUpdate, Side};
+    use rust_decimal::dec;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    // Trades 5 at the best ask
+    fn create_test_update(security_id: u64, seq_no: u64) -> OrderBookUpdate {
+        let deque = BatchedDeque::new(10);
+        let level = UpdateLevel {
+            side: Side::Ask,
+            price: 101.00,
+            qty: Qty(10),
+            metadata: None,
+        };
+        OrderBookUpdate {
+            timestamp: 1627846266,
+            capture_timestamp: None,
+            seq_no,
+            security_id,
+            thinned_from: None,
+            updates: deque
+                .push_back_batch([Ok::<_, ()>(level)].into_iter())
+                .unwrap(),
+        }
+    }
+
+    #[test]
+    fn test_parse_functions() {
+        for function in ["mid", "weighted-mid", "depth:5", "last-trade:0.3:depth:5"] {
+            assert_eq!(function.parse::<FairValue>().unwrap().to_string(), function);
+        }
+        assert!("depth:0".parse::<FairValue>().is_err());
+        assert!("last-trade:1.5:mid".parse::<FairValue>().is_err());
+        assert!("median".parse::<FairValue>().is_err());
+
+        assert_eq!(
+            "1001=weighted-mid".parse::<FairValueConfig>(),
+            Ok(FairValueConfig {
+                security_id: Some(1001),
+                function: FairValue::WeightedMid,
+            })
+        );
+    }
+
+    #[test]
+    fn test_compute() {
+        let book = OrderBook::new(&create_test_snapshot(1001, 100)).unwrap();
+        assert_eq!(FairValue::Mid.compute(&book, None), Some(dec!(100.5)));
+        assert_eq!(
+            FairValue::WeightedMid.compute(&book, None),
+            Some(dec!(100.4))
+        );
+        // Same as the microprice over one level
+        assert_eq!(
+            FairValue::DepthWeighted(1).compute(&book, None),
+            Some(dec!(100.4))
+        );
+        // Bid VWAP 97.33 over 150, ask VWAP 103.57 over 175
+        let depth_weighted = FairValue::DepthWeighted(5).compute(&book, None).unwrap();
+        assert_eq!(depth_weighted.round_dp(4), dec!(100.2125));
+
+        let blend: FairValue = "last-trade:0.25:mid".parse().unwrap();
+        assert_eq!(blend.compute(&book, None), Some(dec!(100.5)));
+        assert_eq!(blend.compute(&book, Some(dec!(102.5))), Some(dec!(101.0)));
+    }
+
+    #[test]
+    fn test_pricer_listener() {
+        let configs = vec![
+            "mid".parse::<FairValueConfig>().unwrap(),
+            "1002=last-trade:0.5:mid".parse().unwrap(),
+        ];
+        let values = Rc::new(RefCell::new(Vec::new()));
+        let pricer_values = values.clone();
+        let pricer = Pricer::new(
+            &configs,
+            Box::new(move |point: &FairValuePoint| {
+                pricer_values
+                    .borrow_mut()
+                    .push((point.security_id, point.seq_no, point.value))
+            }),
+        );
+        assert_eq!(pricer.function(1003), Some(&FairValue::Mid));
+
+        let mut manager = Manager::default();
+        manager.add_listener(Box::new(pricer));
+        manager.apply_snapshot(&create_test_snapshot(1001, 100));
+        manager.apply_snapshot(&create_test_snapshot(1002, 100));
+        manager.apply_update(create_test_update(1001, 101));
+        manager.apply_update(create_test_update(1002, 101));
+
+        assert_eq!(
+            *values.borrow(),
+            vec![
+                (1001, 100, dec!(100.5)),
+                (1002, 100, dec!(100.5)),
+                (1001, 101, dec!(100.5)),
+                // Half the mid and half the trade at 101
+                (1002, 101, dec!(100.75)),
+            ]
+        );
+    }
+}
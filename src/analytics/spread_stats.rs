@@ -0,0 +1,199 @@
+use rust_decimal::Decimal;
+use std::collections::BTreeMap;
+use std::fmt::Display;
+
+use crate::order_book::level_ttl::ExpiredLevel;
+use crate::order_book::listener::BookListener;
+use crate::order_book::order_book::OrderBook;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SpreadSummary {
+    pub security_id: u64,
+    // Time covered, from the first applied record of the security to the last
+    pub duration: u64,
+    // Over the time both sides had a price, None when that time is 0
+    pub twa_spread: Option<Decimal>,
+    pub min_spread: Option<Decimal>,
+    pub max_spread: Option<Decimal>,
+    // Percentages of the duration, None when it is 0
+    pub one_sided_pct: Option<Decimal>,
+    pub crossed_pct: Option<Decimal>,
+}
+
+impl Display for SpreadSummary {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let fmt_value = |value: Option<Decimal>| {
+            value
+                .map(|value| value.normalize().to_string())
+                .unwrap_or_else(|| "n/a".to_string())
+        };
+        let fmt_pct = |value: Option<Decimal>| match value {
+            Some(value) => format!("{}%", value.normalize()),
+            None => "n/a".to_string(),
+        };
+        write!(
+            f,
+            "SpreadStats {{ security_id: {}, duration: {}, twa_spread: {}, min: {}, max: {}, one_sided: {}, crossed: {} }}",
+            self.security_id,
+            self.duration,
+            fmt_value(self.twa_spread),
+            fmt_value(self.min_spread),
+            fmt_value(self.max_spread),
+            fmt_pct(self.one_sided_pct),
+            fmt_pct(self.crossed_pct)
+        )
+    }
+}
+
+#[derive(Debug, Default)]
+struct SpreadAccumulator {
+    duration: u64,
+    two_sided_duration: u64,
+    one_sided_duration: u64,
+    crossed_duration: u64,
+    // Sum of spread * time it lasted
+    weighted_spread: Decimal,
+    min_spread: Option<Decimal>,
+    max_spread: Option<Decimal>,
+    // Timestamp and spread of the last applied record, the spread is None while a side
+    // is empty
+    last: Option<(u64, Option<Decimal>)>,
+}
+
+impl SpreadAccumulator {
+    fn observe(&mut self, mut timestamp: u64, spread: Option<Decimal>) {
+        if let Some((last_timestamp, last_spread)) = self.last {
+            // A timestamp going backwards adds no time
+            timestamp = timestamp.max(last_timestamp);
+            let elapsed = timestamp - last_timestamp;
+            self.duration += elapsed;
+            match last_spread {
+                Some(spread) => {
+                    self.two_sided_duration += elapsed;
+                    self.weighted_spread += spread * Decimal::from(elapsed);
+                    if spread <= Decimal::ZERO {
+                        self.crossed_duration += elapsed;
+                    }
+                }
+                None => self.one_sided_duration += elapsed,
+            }
+        }
+        if let Some(spread) = spread {
+            self.min_spread = Some(self.min_spread.map_or(spread, |min| min.min(spread)));
+            self.max_spread = Some(self.max_spread.map_or(spread, |max| max.max(spread)));
+        }
+        self.last = Some((timestamp, spread));
+    }
+
+    fn summary(&self, security_id: u64) -> SpreadSummary {
+        let pct = |duration: u64| {
+            (self.duration > 0).then(|| {
+                (Decimal::from(duration) * Decimal::ONE_HUNDRED / Decimal::from(self.duration))
+                    .round_dp(2)
+            })
+        };
+        SpreadSummary {
+            security_id,
+            duration: self.duration,
+            twa_spread: (self.two_sided_duration > 0).then(|| {
+                (self.weighted_spread / Decimal::from(self.two_sided_duration)).round_dp(8)
+            }),
+            min_spread: self.min_spread,
+            max_spread: self.max_spread,
+            one_sided_pct: pct(self.one_sided_duration),
+            crossed_pct: pct(self.crossed_duration),
+        }
+    }
+}
+
+// Spread statistics of every security, updated as records are applied: the spread
+// weighted by the time it lasted, its extremes and the share of the time the book had
+// an empty side or was crossed, a best bid at or above the best ask. A state lasts
+// until the timestamp of the next applied record of the security. Registered on a
+// Manager as a BookListener.
+#[derive(Debug, Default)]
+pub struct SpreadStats {
+    securities: BTreeMap<u64, SpreadAccumulator>,
+}
+
+impl SpreadStats {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn observe(&mut self, book: &OrderBook) {
+        let spread = match (book.best_bid(), book.best_ask()) {
+            (Some((bid, _)), Some((ask, _))) => Some(ask - bid),
+            _ => None,
+        };
+        self.securities
+            .entry(book.security_id)
+            .or_default()
+            .observe(book.timestamp, spread);
+    }
+
+    pub fn summary(&self, security_id: u64) -> Option<SpreadSummary> {
+        self.securities
+            .get(&security_id)
+            .map(|accumulator| accumulator.summary(security_id))
+    }
+
+    pub fn summaries(&self) -> Vec<SpreadSummary> {
+        self.securities
+            .iter()
+            .map(|(security_id, accumulator)| accumulator.summary(*security_id))
+            .collect()
+    }
+}
+
+impl BookListener for SpreadStats {
+    fn on_snapshot_applied(&mut self, book: &OrderBook) {
+        self.observe(book);
+    }
+
+    fn on_update_applied(&mut self, book: &OrderBook) {
+        self.observe(book);
+    }
+
+    fn on_levels_expired(&mut self, book: &OrderBook, _expired: &[ExpiredLevel]) {
+        self.observe(book);
+    }
+
+    // The time until the book is back isn't counted
+    fn on_book_evicted(&mut self, book: &OrderBook) {
+        if let Some(accumulator) = self.securities.get_mut(&book.security_id) {
+            accumulator.last = None;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rust_decimal::dec;
+
+    #[test]
+    fn test_spread_summary() {
+        let mut accumulator = SpreadAccumulator::default();
+        accumulator.observe(1000, Some(dec!(1)));
+        accumulator.observe(1030, Some(dec!(0.5)));
+        accumulator.observe(1050, None);
+        accumulator.observe(1060, Some(dec!(-0.25)));
+        accumulator.observe(1070, Some(dec!(0.5)));
+        // Goes back in time, adds nothing
+        accumulator.observe(1065, Some(dec!(0.5)));
+        accumulator.observe(1100, Some(dec!(0.5)));
+
+        let summary = accumulator.summary(1001);
+        assert_eq!(summary.duration, 100);
+        // (1 * 30 + 0.5 * 20 - 0.25 * 10 + 0.5 * 30) / 90
+        assert_eq!(summary.twa_spread, Some(dec!(0.58333333)));
+        assert_eq!(summary.min_spread, Some(dec!(-0.25)));
+        assert_eq!(summary.max_spread, Some(dec!(1)));
+        assert_eq!(summary.one_sided_pct, Some(dec!(10)));
+        assert_eq!(summary.crossed_pct, Some(dec!(10)));
+
+        let summary = SpreadAccumulator::default().summary(1001);
+        assert_eq!((summary.twa_spread, summary.one_sided_pct), (None, None));
+    }
+}
@@ -0,0 +1,212 @@
+use rust_decimal::Decimal;
+use std::collections::HashMap;
+use std::fmt::Display;
+
+use crate::order_book::order_book::OrderBook;
+use crate::order_book::units::Qty;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Aggressor {
+    Buyer,
+    Seller,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Trade {
+    pub timestamp: u64,
+    pub seq_no: u64,
+    pub security_id: u64,
+    pub price: Decimal,
+    pub qty: u64,
+    pub aggressor: Aggressor,
+}
+
+impl Display for Trade {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Trade {{ timestamp: {}, seq_no: {}, security_id: {}, price: {:.2}, qty: {}, aggressor: {:?} }}",
+            self.timestamp, self.seq_no, self.security_id, self.price, self.qty, self.aggressor
+        )
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+struct TopOfBook {
+    best_bid: Option<(Decimal, Qty)>,
+    best_ask: Option<(Decimal, Qty)>,
+}
+
+impl TopOfBook {
+    fn of(book: &OrderBook) -> Self {
+        Self {
+            best_bid: book.best_bid(),
+            best_ask: book.best_ask(),
+        }
+    }
+}
+
+// Infers executions from consecutive states of the books: a decrease of the quantity
+// at the touch (or the touch level disappearing without a better price replacing it)
+// is reported as a trade against that level.
+#[derive(Debug, Default)]
+pub struct TradeInference {
+    last_top_of_book: HashMap<u64, TopOfBook>,
+}
+
+impl TradeInference {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Sets the reference state without inferring trades, e.g. after a snapshot
+    pub fn reset(&mut self, book: &OrderBook) {
+        self.last_top_of_book
+            .insert(book.security_id, TopOfBook::of(book));
+    }
+
+    pub fn observe(&mut self, book: &OrderBook) -> Vec<Trade> {
+        let current = TopOfBook::of(book);
+        let previous = match self.last_top_of_book.insert(book.security_id, current) {
+            Some(previous) => previous,
+            None => return Vec::new(),
+        };
+
+        let mut trades = Vec::new();
+        let mut push_trade = |price: Decimal, qty: Qty, aggressor: Aggressor| {
+            trades.push(Trade {
+                timestamp: book.timestamp,
+                seq_no: book.seq_no,
+                security_id: book.security_id,
+                price,
+                qty: qty.value(),
+                aggressor,
+            });
+        };
+
+        if let Some((prev_price, prev_qty)) = previous.best_bid {
+            match current.best_bid {
+                Some((price, qty)) if price == prev_price && qty < prev_qty => {
+                    push_trade(price, prev_qty - qty, Aggressor::Seller)
+                }
+                Some((price, _)) if price < prev_price => {
+                    push_trade(prev_price, prev_qty, Aggressor::Seller)
+                }
+                None => push_trade(prev_price, prev_qty, Aggressor::Seller),
+                _ => {}
+            }
+        }
+        if let Some((prev_price, prev_qty)) = previous.best_ask {
+            match current.best_ask {
+                Some((price, qty)) if price == prev_price && qty < prev_qty => {
+                    push_trade(price, prev_qty - qty, Aggressor::Buyer)
+                }
+                Some((price, _)) if price > prev_price => {
+                    push_trade(prev_price, prev_qty, Aggressor::Buyer)
+                }
+                None => push_trade(prev_price, prev_qty, Aggressor::Buyer),
+                _ => {}
+            }
+        }
+
+        trades
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::batched_deque::batched_deque::BatchedDeque;
+    use crate::parsing::order_book_snapshot::test_support::create_test_snapshot;
+    use crate::parsing::order_book_update::OrderBookUpdate;
+    use crate::parsing::order_book_update::{Level as UpdateLevel, Side};
+    use rust_decimal::dec;
+
+    fn create_test_update(
+        security_id: u64,
+        seq_no: u64,
+        levels: Vec<(Side, f64, u64)>,
+    ) -> OrderBookUpdate {
+        let deque = BatchedDeque::new(10);
+        let levels = levels.into_iter().map(|(side, price, qty)| {
+            Ok::<UpdateLevel, ()>(UpdateLevel {
+                side,
+                price,
+                qty: Qty(qty),
+                metadata: None,
+            })
+        });
+
+        OrderBookUpdate {
+            timestamp: 1627846266,
+            capture_timestamp: None,
+            seq_no,
+            security_id,
+            thinned_from: None,
+            updates: deque.push_back_batch(levels).unwrap(),
+        }
+    }
+
+    #[test]
+    fn test_no_trades_without_reference_state() {
+        let book = OrderBook::new(&create_test_snapshot(1001, 100)).unwrap();
+        let mut inference = TradeInference::new();
+
+        assert!(inference.observe(&book).is_empty());
+    }
+
+    #[test]
+    fn test_partial_fill_at_best_ask() {
+        let mut book = OrderBook::new(&create_test_snapshot(1001, 100)).unwrap();
+        let mut inference = TradeInference::new();
+        inference.reset(&book);
+
+        let update = create_test_update(1001, 101, vec![(Side::Ask, 101.00, 5)]);
+        book.apply_update(&update).unwrap();
+
+        assert_eq!(
+            inference.observe(&book),
+            vec![Trade {
+                timestamp: 1627846266,
+                seq_no: 101,
+                security_id: 1001,
+                price: dec!(101),
+                qty: 10,
+                aggressor: Aggressor::Buyer,
+            }]
+        );
+    }
+
+    #[test]
+    fn test_best_bid_level_consumed() {
+        let mut book = OrderBook::new(&create_test_snapshot(1001, 100)).unwrap();
+        let mut inference = TradeInference::new();
+        inference.reset(&book);
+
+        let update = create_test_update(1001, 101, vec![(Side::Bid, 100.00, 0)]);
+        book.apply_update(&update).unwrap();
+
+        let trades = inference.observe(&book);
+        assert_eq!(trades.len(), 1);
+        assert_eq!(trades[0].price, dec!(100));
+        assert_eq!(trades[0].qty, 10);
+        assert_eq!(trades[0].aggressor, Aggressor::Seller);
+    }
+
+    #[test]
+    fn test_quantity_increase_and_better_price_are_not_trades() {
+        let mut book = OrderBook::new(&create_test_snapshot(1001, 100)).unwrap();
+        let mut inference = TradeInference::new();
+        inference.reset(&book);
+
+        // More size at the best bid and a new, better ask
+        let update = create_test_update(
+            1001,
+            101,
+            vec![(Side::Bid, 100.00, 50), (Side::Ask, 100.50, 5)],
+        );
+        book.apply_update(&update).unwrap();
+
+        assert!(inference.observe(&book).is_empty());
+    }
+}
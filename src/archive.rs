@@ -0,0 +1,2 @@
+pub mod thinning;
+pub mod transcode;
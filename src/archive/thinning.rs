@@ -0,0 +1,275 @@
+use std::collections::BTreeMap;
+use std::io::{self, Write};
+use std::str::FromStr;
+
+use crate::batched_deque::batched_deque::BatchedDeque;
+use crate::order_book::book_diff::BookDiff;
+use crate::order_book::buffered_order_book::BufferedOrderBook;
+use crate::order_book::errors::Errors;
+use crate::order_book::order_book::OrderBook;
+use crate::parsing::order_book_snapshot::OrderBookSnapshot;
+use crate::parsing::order_book_update::Level as UpdateLevel;
+use crate::parsing::order_book_update::{OrderBookUpdate, UpdateFormat};
+
+const DIFF_DEQUE_CAPACITY: usize = 1_000;
+
+// Exchange timestamps in millis, both ends included
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TimeWindow {
+    pub start: u64,
+    pub end: u64,
+}
+
+impl TimeWindow {
+    pub fn contains(&self, timestamp: u64) -> bool {
+        self.start <= timestamp && timestamp <= self.end
+    }
+}
+
+impl FromStr for TimeWindow {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (start, end) = s
+            .split_once(':')
+            .ok_or_else(|| format!("Expected <START>:<END>, got {}", s))?;
+        let start = start
+            .parse::<u64>()
+            .map_err(|e| format!("Invalid window start {}: {}", start, e))?;
+        let end = end
+            .parse::<u64>()
+            .map_err(|e| format!("Invalid window end {}: {}", end, e))?;
+        if start > end {
+            return Err(format!("Window ends before it starts: {}", s));
+        }
+        Ok(TimeWindow { start, end })
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct ThinningConfig {
+    // Updates inside these windows are kept as they are
+    pub windows: Vec<TimeWindow>,
+    // Elsewhere the books are written at most once per interval
+    pub interval_millis: u64,
+}
+
+impl ThinningConfig {
+    fn keeps_all(&self, timestamp: u64) -> bool {
+        self.windows.iter().any(|window| window.contains(timestamp))
+    }
+}
+
+struct ThinnedSecurity {
+    book: BufferedOrderBook,
+    // State the written records lead to, None while it is the current book
+    written: Option<OrderBook>,
+    written_timestamp: u64,
+}
+
+// Rewrites an incremental file as V2 records. The snapshot file stays as it is, the
+// updates between the kept ones are conflated into thinned records, see
+// UpdateLevel::thinning_marker.
+pub struct Thinner<W: Write> {
+    config: ThinningConfig,
+    writer: W,
+    deque: BatchedDeque<UpdateLevel>,
+    securities: BTreeMap<u64, ThinnedSecurity>,
+    pub updates_read: usize,
+    pub records_written: usize,
+}
+
+impl<W: Write> Thinner<W> {
+    pub fn new(config: ThinningConfig, writer: W) -> Self {
+        Self {
+            config,
+            writer,
+            deque: BatchedDeque::new(DIFF_DEQUE_CAPACITY),
+            securities: BTreeMap::new(),
+            updates_read: 0,
+            records_written: 0,
+        }
+    }
+
+    pub fn apply_snapshot(&mut self, snapshot: &OrderBookSnapshot) -> Result<(), Errors> {
+        match self.securities.get_mut(&snapshot.security_id) {
+            Some(security) => security.book.apply_snapshot(snapshot)?,
+            None => {
+                let book = BufferedOrderBook::new(OrderBook::new(snapshot)?);
+                self.securities.insert(
+                    snapshot.security_id,
+                    ThinnedSecurity {
+                        book,
+                        written: None,
+                        written_timestamp: snapshot.timestamp,
+                    },
+                );
+            }
+        }
+        Ok(())
+    }
+
+    pub fn push(&mut self, update: OrderBookUpdate) -> io::Result<()> {
+        self.updates_read += 1;
+        let Some(security) = self.securities.get_mut(&update.security_id) else {
+            // The replay ignores it as well
+            self.records_written += 1;
+            return update.write(&mut self.writer, UpdateFormat::V2);
+        };
+
+        if self.config.keeps_all(update.timestamp) {
+            if Self::write_thinned(security, &self.deque, &mut self.writer)? {
+                self.records_written += 1;
+            }
+            update.write(&mut self.writer, UpdateFormat::V2)?;
+            self.records_written += 1;
+            // Rejected updates are rejected by the replay the same way
+            let _ = security.book.apply_update(update);
+            return Ok(());
+        }
+
+        if security.written.is_none() {
+            security.written = Some(security.book.order_book.clone());
+        }
+        let _ = security.book.apply_update(update);
+        let timestamp = security.book.order_book.timestamp;
+        if timestamp >= security.written_timestamp + self.config.interval_millis
+            && Self::write_thinned(security, &self.deque, &mut self.writer)?
+        {
+            self.records_written += 1;
+        }
+        Ok(())
+    }
+
+    // Writes what is left to catch up with the final books
+    pub fn finish(&mut self) -> io::Result<()> {
+        for security in self.securities.values_mut() {
+            if Self::write_thinned(security, &self.deque, &mut self.writer)? {
+                self.records_written += 1;
+            }
+        }
+        self.writer.flush()
+    }
+
+    pub fn into_writer(self) -> W {
+        self.writer
+    }
+
+    fn write_thinned(
+        security: &mut ThinnedSecurity,
+        deque: &BatchedDeque<UpdateLevel>,
+        writer: &mut W,
+    ) -> io::Result<bool> {
+        let Some(written) = security.written.take() else {
+            return Ok(false);
+        };
+        let book = &security.book.order_book;
+        if written.seq_no == book.seq_no {
+            return Ok(false);
+        }
+        let diff = BookDiff::between(&written, book).expect("Same security");
+        diff.to_thinned_update(deque)
+            .write(writer, UpdateFormat::V2)?;
+        security.written_timestamp = book.timestamp;
+        Ok(true)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::order_book::manager::Manager;
+    use crate::order_book::units::Qty;
+    use crate::parsing::order_book_snapshot::test_support::create_test_snapshot;
+    use crate::parsing::order_book_update::{OrderBookUpdateParser, Side};
+    use crate::parsing::parser::Parser;
+    use std::io::Cursor;
+
+    fn create_test_update(
+        deque: &BatchedDeque<UpdateLevel>,
+        seq_no: u64,
+        timestamp: u64,
+    ) -> OrderBookUpdate {
+        // A new bid level per update
+        let level = UpdateLevel {
+            side: Side::Bid,
+            price: 90.00 + seq_no as f64 / 100.0,
+            qty: Qty(seq_no),
+            metadata: None,
+        };
+        OrderBookUpdate {
+            timestamp,
+            capture_timestamp: None,
+            seq_no,
+            security_id: 1001,
+            thinned_from: None,
+            updates: deque
+                .push_back_batch([Ok::<_, ()>(level)].into_iter())
+                .unwrap(),
+        }
+    }
+
+    fn replay(snapshot: &OrderBookSnapshot, updates: &[u8]) -> (Manager, usize) {
+        let mut manager = Manager::default();
+        manager.apply_snapshot(snapshot);
+        let mut parser = OrderBookUpdateParser::new(UpdateFormat::V2);
+        let mut reader = Cursor::new(updates);
+        let mut records = 0;
+        while let Ok(update) = parser.read(&mut reader) {
+            records += 1;
+            assert!(manager.apply_update(update).is_applied());
+        }
+        (manager, records)
+    }
+
+    #[test]
+    fn test_time_window_from_str() {
+        assert_eq!(
+            "10:20".parse::<TimeWindow>(),
+            Ok(TimeWindow { start: 10, end: 20 })
+        );
+        assert!("20:10".parse::<TimeWindow>().is_err());
+        assert!("10".parse::<TimeWindow>().is_err());
+    }
+
+    #[test]
+    fn test_thinning_keeps_windows_and_final_state() {
+        let snapshot = create_test_snapshot(1001, 100);
+        let start = snapshot.timestamp;
+        let config = ThinningConfig {
+            windows: vec![TimeWindow {
+                start: start + 5_000,
+                end: start + 5_999,
+            }],
+            interval_millis: 2_000,
+        };
+        let mut thinner = Thinner::new(config, Vec::new());
+        thinner.apply_snapshot(&snapshot).unwrap();
+
+        // One update every 500ms, the ones at 5000 and 5500 fall in the window
+        let deque = BatchedDeque::new(10);
+        let mut full = Manager::default();
+        full.apply_snapshot(&snapshot);
+        for i in 1..=20 {
+            thinner
+                .push(create_test_update(&deque, 100 + i, start + i * 500))
+                .unwrap();
+            full.apply_update(create_test_update(&deque, 100 + i, start + i * 500));
+        }
+        assert_eq!(thinner.updates_read, 20);
+        thinner.finish().unwrap();
+        let records_written = thinner.records_written;
+
+        let (manager, records) = replay(&snapshot, &thinner.into_writer());
+        assert_eq!(records, records_written);
+        // Thinned records at 2000 and 4000, catching up at 5000 before the window, the two
+        // kept updates, thinned records at 6500 and 8500 and the rest at the end
+        assert_eq!(records, 8);
+
+        let book = &manager.buffered_order_books[&1001].order_book;
+        let expected = &full.buffered_order_books[&1001].order_book;
+        assert_eq!(book.seq_no, 120);
+        assert_eq!(book.bids, expected.bids);
+        assert_eq!(book.asks, expected.asks);
+    }
+}
@@ -0,0 +1,266 @@
+use num_traits::ToPrimitive;
+use std::fmt::Display;
+use std::io::{self, Write};
+
+use crate::order_book::price::{PRICE_TICK, PriceKey, Ticks};
+use crate::parsing::dedup::Deduplicator;
+use crate::parsing::file_header::{HEADER_SIZE, write_file_header};
+use crate::parsing::order_book_snapshot::{OrderBookSnapshot, SnapshotFormat};
+use crate::parsing::order_book_update::{OrderBookUpdate, UpdateFormat};
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct TranscodeStats {
+    pub records: usize,
+    pub written: usize,
+    pub duplicates: usize,
+    // Prices that are not a multiple of PRICE_TICK, written as they were read
+    pub off_tick_prices: usize,
+}
+
+impl Display for TranscodeStats {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} records: {} written, {} duplicates removed, {} prices off the {} tick",
+            self.records, self.written, self.duplicates, self.off_tick_prices, PRICE_TICK
+        )
+    }
+}
+
+// Writes records of any input format in the binary format of the crate, in a file
+// starting with a header: prices are rounded to the nearest multiple of PRICE_TICK,
+// removing the representation errors of vendor captures, empty snapshot levels get a
+// zero price, and records already written can be dropped. Optionally writes the
+// offset, length and CRC32 of each record as CSV, so that archives can be checked
+// record by record.
+pub struct Transcoder<W: Write> {
+    writer: W,
+    update_format: UpdateFormat,
+    snapshot_format: SnapshotFormat,
+    deduplicator: Option<Deduplicator>,
+    checksums: Option<Box<dyn Write>>,
+    // Offset in the output of the next record
+    offset: u64,
+    record: Vec<u8>,
+    pub stats: TranscodeStats,
+}
+
+impl<W: Write> Transcoder<W> {
+    pub fn new(writer: W) -> Self {
+        Self {
+            writer,
+            update_format: UpdateFormat::V1,
+            snapshot_format: SnapshotFormat::V1,
+            deduplicator: None,
+            checksums: None,
+            offset: 0,
+            record: Vec::new(),
+            stats: TranscodeStats::default(),
+        }
+    }
+
+    // Layout of the updates written, V2 keeps the capture timestamps and level metadata
+    pub fn with_update_format(mut self, update_format: UpdateFormat) -> Self {
+        self.update_format = update_format;
+        self
+    }
+
+    // Layout of the snapshots written, V2 keeps the depth of every snapshot
+    pub fn with_snapshot_format(mut self, snapshot_format: SnapshotFormat) -> Self {
+        self.snapshot_format = snapshot_format;
+        self
+    }
+
+    pub fn with_deduplicator(mut self, deduplicator: Deduplicator) -> Self {
+        self.deduplicator = Some(deduplicator);
+        self
+    }
+
+    pub fn with_checksums(mut self, checksums: Box<dyn Write>) -> Self {
+        self.checksums = Some(checksums);
+        self
+    }
+
+    pub fn write_header(&mut self) -> io::Result<()> {
+        write_file_header(&mut self.writer)?;
+        self.offset = HEADER_SIZE as u64;
+        if let Some(checksums) = self.checksums.as_mut() {
+            writeln!(checksums, "offset,length,crc32")?;
+        }
+        Ok(())
+    }
+
+    fn normalize_price(&mut self, price: f64) -> f64 {
+        match Ticks::from_f64(price) {
+            Ok(ticks) => ticks.to_decimal().to_f64().unwrap_or(price),
+            Err(_) => {
+                self.stats.off_tick_prices += 1;
+                price
+            }
+        }
+    }
+
+    fn write_record(&mut self) -> io::Result<()> {
+        self.writer.write_all(&self.record)?;
+        if let Some(checksums) = self.checksums.as_mut() {
+            writeln!(
+                checksums,
+                "{},{},{:08x}",
+                self.offset,
+                self.record.len(),
+                crc32fast::hash(&self.record)
+            )?;
+        }
+        self.offset += self.record.len() as u64;
+        self.stats.written += 1;
+        Ok(())
+    }
+
+    pub fn write_snapshot(&mut self, mut snapshot: OrderBookSnapshot) -> io::Result<()> {
+        self.stats.records += 1;
+        if let Some(deduplicator) = self.deduplicator.as_mut()
+            && deduplicator.is_duplicate(&snapshot)
+        {
+            self.stats.duplicates += 1;
+            return Ok(());
+        }
+        for level in snapshot.bids.iter_mut().chain(snapshot.asks.iter_mut()) {
+            level.price = if level.qty.is_zero() {
+                0.0
+            } else {
+                self.normalize_price(level.price)
+            };
+        }
+        self.record.clear();
+        snapshot.write(&mut self.record, self.snapshot_format)?;
+        self.write_record()
+    }
+
+    pub fn write_update(&mut self, update: &OrderBookUpdate) -> io::Result<()> {
+        self.stats.records += 1;
+        if let Some(deduplicator) = self.deduplicator.as_mut()
+            && deduplicator.is_duplicate(update)
+        {
+            self.stats.duplicates += 1;
+            return Ok(());
+        }
+        let mut update = update.with_owned_levels();
+        for level in update.updates.iter_mut() {
+            level.price = self.normalize_price(level.price);
+        }
+        self.record.clear();
+        update.write(&mut self.record, self.update_format)?;
+        self.write_record()
+    }
+
+    pub fn finish(mut self) -> io::Result<W> {
+        if let Some(checksums) = self.checksums.as_mut() {
+            checksums.flush()?;
+        }
+        self.writer.flush()?;
+        Ok(self.writer)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::order_book::units::Qty;
+    use crate::parsing::file_header::strip_file_header;
+    use crate::parsing::order_book_snapshot::Level as SnapshotLevel;
+    use crate::parsing::order_book_snapshot::OrderBookSnapshotParser;
+    use crate::parsing::order_book_snapshot::test_support::create_test_snapshot;
+    use crate::parsing::order_book_update::OrderBookUpdateParser;
+    use crate::parsing::parser::{Endianness, Parser};
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    // A checksum sink the test can read back
+    #[derive(Clone, Default)]
+    struct SharedBuffer(Rc<RefCell<Vec<u8>>>);
+
+    impl Write for SharedBuffer {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.borrow_mut().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn update_bytes(seq_no: u64, price: f64) -> Vec<u8> {
+        let mut data = Vec::new();
+        data.extend_from_slice(&1627846266u64.to_le_bytes()); // timestamp
+        data.extend_from_slice(&seq_no.to_le_bytes());
+        data.extend_from_slice(&1001u64.to_le_bytes()); // security_id
+        data.extend_from_slice(&1u64.to_le_bytes()); // num_updates
+        data.push(0); // side
+        data.extend_from_slice(&price.to_le_bytes());
+        data.extend_from_slice(&10u64.to_le_bytes()); // qty
+        data
+    }
+
+    #[test]
+    fn test_transcode_updates() {
+        let mut data = update_bytes(1, 100.10000000000001);
+        data.extend(update_bytes(1, 100.10000000000001));
+        data.extend(update_bytes(2, 100.005));
+        let checksums = SharedBuffer::default();
+        let mut transcoder = Transcoder::new(Vec::new())
+            .with_deduplicator(Deduplicator::default())
+            .with_checksums(Box::new(checksums.clone()));
+        transcoder.write_header().unwrap();
+        let mut parser = OrderBookUpdateParser::new(UpdateFormat::V1);
+        let mut reader = data.as_slice();
+        while let Ok(update) = parser.read(&mut reader) {
+            transcoder.write_update(&update).unwrap();
+        }
+        assert_eq!(
+            transcoder.stats,
+            TranscodeStats {
+                records: 3,
+                written: 2,
+                duplicates: 1,
+                off_tick_prices: 1,
+            }
+        );
+
+        let output = transcoder.finish().unwrap();
+        let (_, mut reader) = strip_file_header(output.as_slice()).unwrap();
+        let first = parser.read(&mut reader).unwrap();
+        assert_eq!(first.updates.iter().next().unwrap().price, 100.1);
+        let second = parser.read(&mut reader).unwrap();
+        assert_eq!(second.updates.iter().next().unwrap().price, 100.005);
+
+        let record = &output[HEADER_SIZE..HEADER_SIZE + 49];
+        assert_eq!(
+            String::from_utf8(checksums.0.borrow().clone()).unwrap(),
+            format!(
+                "offset,length,crc32\n8,49,{:08x}\n57,49,{:08x}\n",
+                crc32fast::hash(record),
+                crc32fast::hash(&output[HEADER_SIZE + 49..])
+            )
+        );
+    }
+
+    #[test]
+    fn test_transcode_snapshot() {
+        let mut snapshot = create_test_snapshot(1001, 100);
+        snapshot.bids[0].price = 99.99999999999999;
+        snapshot.bids[1] = SnapshotLevel {
+            price: -0.0,
+            qty: Qty::ZERO,
+        };
+        snapshot.asks[1].qty = Qty::ZERO;
+        let mut transcoder = Transcoder::new(Vec::new());
+        transcoder.write_snapshot(snapshot).unwrap();
+        let output = transcoder.finish().unwrap();
+        let snapshot = OrderBookSnapshotParser::new(Endianness::Little)
+            .read(&mut output.as_slice())
+            .unwrap();
+        assert_eq!(snapshot.bids[0].price, 100.0);
+        assert_eq!(snapshot.bids[1].price.to_bits(), 0.0f64.to_bits());
+        assert_eq!(snapshot.asks[1].price, 0.0);
+    }
+}
@@ -1,4 +1,4 @@
-use std::cell::RefCell;
+use std::cell::{Ref, RefCell};
 use std::collections::VecDeque;
 use std::rc::Rc;
 
@@ -14,6 +14,15 @@ struct Item<T> {
     batch_header: Option<BatchHeader>,
 }
 
+// Why a batch was not pushed
+#[derive(Debug, PartialEq)]
+pub enum PushError<E> {
+    // The iterator of the batch failed
+    Item(E),
+    // The batch would take the deque past the max_len set with with_max_len
+    Full { max_len: usize },
+}
+
 #[derive(Debug)]
 pub struct BatchedDeque<T> {
     state: Rc<RefCell<BatchedDequeState<T>>>,
@@ -26,22 +35,48 @@ impl<T> BatchedDeque<T> {
         }
     }
 
+    // Bounds the items held, so that guards kept alive can't grow the deque without end.
+    // A batch that would go past the bound is not pushed and fails with PushError::Full.
+    // Failing is the only policy: blocking would deadlock, as the guards are held by the
+    // same thread, and dropping the oldest batches frees nothing while an older guard is
+    // alive, as removed batches are reclaimed from the front only.
+    pub fn with_max_len(self, max_len: usize) -> Self {
+        self.state.borrow_mut().max_len = Some(max_len);
+        self
+    }
+
     pub fn push_back_batch<E, I: Iterator<Item = Result<T, E>>>(
         &self,
         iter: I,
-    ) -> Result<BatchGuard<T>, E> {
+    ) -> Result<BatchGuard<T>, PushError<E>> {
         let batch = self.state.borrow_mut().push_back_batch(iter)?;
         Ok(BatchGuard {
             deque: self.state.clone(),
             batch,
         })
     }
+
+    // Items held, including those of dropped batches not yet reclaimed because a batch
+    // pushed before them is still alive
+    pub fn len(&self) -> usize {
+        self.state.borrow().buffer.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    // Items the deque holds before it grows
+    pub fn capacity(&self) -> usize {
+        self.state.borrow().buffer.capacity()
+    }
 }
 
 #[derive(Debug)]
 struct BatchedDequeState<T> {
     buffer: VecDeque<Item<T>>,
     start_index: usize,
+    max_len: Option<usize>,
 }
 
 #[derive(Debug)]
@@ -61,6 +96,31 @@ impl<T> BatchGuard<T> {
         }
         Ok(())
     }
+
+    pub fn len(&self) -> usize {
+        self.batch.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.batch.len == 0
+    }
+
+    // The deque stays borrowed while an item is held, so batches can't be pushed then
+    pub fn get(&self, index: usize) -> Option<Ref<'_, T>> {
+        if index >= self.batch.len {
+            return None;
+        }
+        let index = self.batch.start_index + index;
+        Some(Ref::map(self.deque.borrow(), |deque| {
+            deque
+                .get(index)
+                .unwrap_or_else(|| panic!("Expected item to exist at index {}", index))
+        }))
+    }
+
+    pub fn iter(&self) -> impl DoubleEndedIterator<Item = Ref<'_, T>> + ExactSizeIterator {
+        (0..self.batch.len).map(|i| self.get(i).expect("Index within the batch"))
+    }
 }
 
 impl<T> Drop for BatchGuard<T> {
@@ -81,16 +141,23 @@ impl<T> BatchedDequeState<T> {
         Self {
             buffer: VecDeque::with_capacity(capacity),
             start_index: 0,
+            max_len: None,
         }
     }
 
     pub fn push_back_batch<E, I: Iterator<Item = Result<T, E>>>(
         &mut self,
         iter: I,
-    ) -> Result<Batch, E> {
+    ) -> Result<Batch, PushError<E>> {
         let batch_start = self.buffer.len();
         let mut batch_len = 0;
         for item in iter {
+            if let Some(max_len) = self.max_len
+                && self.buffer.len() >= max_len
+            {
+                self.buffer.truncate(batch_start);
+                return Err(PushError::Full { max_len });
+            }
             match item {
                 Ok(item) => {
                     self.buffer.push_back(Item {
@@ -101,7 +168,7 @@ impl<T> BatchedDequeState<T> {
                 }
                 Err(e) => {
                     self.buffer.truncate(batch_start);
-                    return Err(e);
+                    return Err(PushError::Item(e));
                 }
             }
         }
@@ -159,17 +226,17 @@ impl<T> BatchedDequeState<T> {
 
     fn cleanup_removed_batchs(&mut self) {
         while let Some(front) = self.buffer.front() {
-            if let Some(header) = &front.batch_header {
-                if header.is_removed {
-                    let batch_len = header.len;
-                    assert!(
-                        batch_len <= self.buffer.len(),
-                        "Batch length is greater than the buffer length"
-                    );
-                    self.buffer.drain(0..batch_len);
-                    self.start_index += batch_len;
-                    continue;
-                }
+            if let Some(header) = &front.batch_header
+                && header.is_removed
+            {
+                let batch_len = header.len;
+                assert!(
+                    batch_len <= self.buffer.len(),
+                    "Batch length is greater than the buffer length"
+                );
+                self.buffer.drain(0..batch_len);
+                self.start_index += batch_len;
+                continue;
             }
             break;
         }
@@ -237,6 +304,31 @@ mod tests {
         assert_eq!(vec, data);
     }
 
+    #[test]
+    fn test_batch_guard_iter() {
+        let deque = BatchedDeque::<i32>::new(10);
+        let _before = deque
+            .push_back_batch([0].into_iter().map(Ok::<i32, ()>))
+            .unwrap();
+        let batch_guard = deque
+            .push_back_batch([1, 2, 3].into_iter().map(Ok::<i32, ()>))
+            .unwrap();
+
+        let vec = batch_guard.iter().map(|item| *item).collect::<Vec<_>>();
+        assert_eq!(vec, [1, 2, 3]);
+        assert_eq!(batch_guard.len(), 3);
+        assert_eq!(batch_guard.get(1).map(|item| *item), Some(2));
+        assert!(batch_guard.get(3).is_none());
+        assert_eq!(
+            batch_guard
+                .iter()
+                .rev()
+                .map(|item| *item)
+                .collect::<Vec<_>>(),
+            [3, 2, 1]
+        );
+    }
+
     #[test]
     fn test_batch_guard_drop() {
         let deque = BatchedDeque::<i32>::new(10);
@@ -463,4 +555,89 @@ mod tests {
         assert_eq!(deque.state.borrow().buffer.len(), 0);
         assert_eq!(deque.state.borrow().start_index, 0);
     }
+
+    #[test]
+    fn test_len_and_capacity() {
+        let deque = BatchedDeque::<i32>::new(10);
+        assert!(deque.is_empty());
+        assert!(deque.capacity() >= 10);
+
+        let first = deque
+            .push_back_batch([1, 2].into_iter().map(Ok::<i32, ()>))
+            .unwrap();
+        let second = deque
+            .push_back_batch([3, 4, 5].into_iter().map(Ok::<i32, ()>))
+            .unwrap();
+        assert_eq!(deque.len(), 5);
+
+        // Not reclaimed while the batch pushed before it is alive
+        drop(second);
+        assert_eq!(deque.len(), 5);
+        drop(first);
+        assert!(deque.is_empty());
+    }
+
+    #[test]
+    fn test_max_len() {
+        let deque = BatchedDeque::<i32>::new(4).with_max_len(5);
+        let held = deque
+            .push_back_batch([1, 2, 3].into_iter().map(Ok::<i32, ()>))
+            .unwrap();
+        let released = deque
+            .push_back_batch([4].into_iter().map(Ok::<i32, ()>))
+            .unwrap();
+        drop(released);
+
+        // The dropped batch is still held behind the guard of the first one
+        assert_eq!(
+            deque
+                .push_back_batch([5, 6].into_iter().map(Ok::<i32, ()>))
+                .unwrap_err(),
+            PushError::Full { max_len: 5 }
+        );
+        assert_eq!(deque.len(), 4);
+        let last = deque
+            .push_back_batch([5].into_iter().map(Ok::<i32, ()>))
+            .unwrap();
+        assert_eq!(*last.get(0).unwrap(), 5);
+        assert_eq!(
+            deque
+                .push_back_batch([6].into_iter().map(Ok::<i32, ()>))
+                .unwrap_err(),
+            PushError::Full { max_len: 5 }
+        );
+
+        drop(held);
+        drop(last);
+        assert!(deque.is_empty());
+        let batch = deque
+            .push_back_batch([6, 7, 8, 9, 10].into_iter().map(Ok::<i32, ()>))
+            .unwrap();
+        assert_eq!(batch.len(), 5);
+    }
+
+    #[test]
+    fn test_storage_reused_after_batches_drop() {
+        let deque = BatchedDeque::<i32>::new(8);
+        let mut pending = VecDeque::new();
+        let mut capacity = 0;
+        for i in 0..1000 {
+            if i == 10 {
+                capacity = deque.state.borrow().buffer.capacity();
+            }
+            pending.push_back(
+                deque
+                    .push_back_batch((0..3).map(|x| Ok::<i32, ()>(i + x)))
+                    .unwrap(),
+            );
+            // Up to two batches in flight, as with a short backlog of pending updates
+            if pending.len() > 2 {
+                pending.pop_front();
+            }
+        }
+        assert_eq!(deque.state.borrow().buffer.len(), 6);
+        // Grown once to hold three batches while the third is pushed, then reused
+        assert_eq!(deque.state.borrow().buffer.capacity(), capacity);
+        assert!(capacity < 20);
+    }
 }
@@ -0,0 +1,163 @@
+use clap::Parser;
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::PathBuf;
+use std::process::ExitCode;
+
+use rust_order_book_practice::archive::thinning::{Thinner, ThinningConfig, TimeWindow};
+use rust_order_book_practice::parsing::binary_file_iterator::BinaryFileIterator;
+use rust_order_book_practice::parsing::file_header::write_file_header;
+use rust_order_book_practice::parsing::order_book_snapshot::{
+    OrderBookSnapshot, OrderBookSnapshotParser,
+};
+use rust_order_book_practice::parsing::order_book_update::{
+    OrderBookUpdate, OrderBookUpdateParser, UpdateFormat,
+};
+use rust_order_book_practice::parsing::parser::Endianness;
+
+#[derive(Parser, Debug)]
+#[clap(
+    about = "Rewrites an incremental file for archiving, keeping every update only inside the given windows. The output is in the v2 format and replays on top of the same snapshot file."
+)]
+struct Args {
+    #[clap(help = "Snapshot file, plain or gzip/zstd compressed")]
+    path_to_snapshot: PathBuf,
+    #[clap(help = "Incremental file, plain or gzip/zstd compressed")]
+    path_to_incremental: PathBuf,
+    #[clap(long, value_name = "PATH", help = "Thinned incremental file to write")]
+    out: PathBuf,
+    #[clap(
+        long,
+        value_name = "START:END",
+        multiple_occurrences = true,
+        help = "Keep every update with an exchange timestamp in the window, in millis"
+    )]
+    window: Vec<TimeWindow>,
+    #[clap(
+        long,
+        value_name = "MILLIS",
+        default_value = "60000",
+        help = "Outside the windows, write the net change of the books at most this often"
+    )]
+    interval: u64,
+    #[clap(
+        long,
+        default_value = "v1",
+        possible_values = ["v1", "v2"],
+        help = "Layout of the input incremental file"
+    )]
+    update_format: UpdateFormat,
+    #[clap(
+        long,
+        default_value = "little",
+        possible_values = ["little", "big"],
+        help = "Byte order of the numbers in the input files, the output is little-endian"
+    )]
+    endianness: Endianness,
+    #[clap(
+        long,
+        help = "Start the output file with a header holding the format version"
+    )]
+    write_header: bool,
+}
+
+fn main() -> ExitCode {
+    let args = Args::parse();
+
+    let writer = File::create(&args.out).and_then(|file| {
+        let mut writer = BufWriter::new(file);
+        if args.write_header {
+            write_file_header(&mut writer)?;
+        }
+        Ok(writer)
+    });
+    let writer = match writer {
+        Ok(writer) => writer,
+        Err(e) => {
+            eprintln!("Failed to create file {}: {}", args.out.display(), e);
+            return ExitCode::FAILURE;
+        }
+    };
+    let config = ThinningConfig {
+        windows: args.window,
+        interval_millis: args.interval,
+    };
+    let mut thinner = Thinner::new(config, writer);
+
+    let snapshots = BinaryFileIterator::<OrderBookSnapshot>::open(
+        &args.path_to_snapshot,
+        OrderBookSnapshotParser::new(args.endianness),
+    );
+    let snapshots = match snapshots {
+        Ok(snapshots) => snapshots,
+        Err(e) => {
+            eprintln!(
+                "Failed to open file {}: {}",
+                args.path_to_snapshot.display(),
+                e
+            );
+            return ExitCode::FAILURE;
+        }
+    };
+    for snapshot in snapshots {
+        match snapshot {
+            // Invalid snapshots are skipped by the replay as well
+            Ok(snapshot) => {
+                let _ = thinner.apply_snapshot(&snapshot);
+            }
+            Err(e) => {
+                eprintln!(
+                    "Failed to read next snapshot from the file: {}. The file {} is corrupted.",
+                    e,
+                    args.path_to_snapshot.display()
+                );
+                return ExitCode::FAILURE;
+            }
+        }
+    }
+
+    let updates = BinaryFileIterator::<OrderBookUpdate>::open(
+        &args.path_to_incremental,
+        OrderBookUpdateParser::new(args.update_format).with_endianness(args.endianness),
+    );
+    let updates = match updates {
+        Ok(updates) => updates,
+        Err(e) => {
+            eprintln!(
+                "Failed to open file {}: {}",
+                args.path_to_incremental.display(),
+                e
+            );
+            return ExitCode::FAILURE;
+        }
+    };
+    for update in updates {
+        let written = match update {
+            Ok(update) => thinner.push(update),
+            Err(e) => {
+                eprintln!(
+                    "Failed to read next update from the file: {}. The file {} is corrupted.",
+                    e,
+                    args.path_to_incremental.display()
+                );
+                return ExitCode::FAILURE;
+            }
+        };
+        if let Err(e) = written {
+            eprintln!("Failed to write to {}: {}", args.out.display(), e);
+            return ExitCode::FAILURE;
+        }
+    }
+
+    if let Err(e) = thinner.finish() {
+        eprintln!("Failed to write to {}: {}", args.out.display(), e);
+        return ExitCode::FAILURE;
+    }
+    println!(
+        "Wrote {} records for {} updates to {}",
+        thinner.records_written,
+        thinner.updates_read,
+        args.out.display()
+    );
+    ExitCode::SUCCESS
+}
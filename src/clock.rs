@@ -0,0 +1,105 @@
+use std::cell::Cell;
+use std::rc::Rc;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// Source of the current time in milliseconds since the Unix epoch, the unit of the
+// exchange timestamps. Time-based features take a clock so tests can control time.
+pub trait Clock {
+    fn now_millis(&self) -> u64;
+    fn sleep_millis(&self, millis: u64);
+}
+
+impl<C: Clock + ?Sized> Clock for &C {
+    fn now_millis(&self) -> u64 {
+        (**self).now_millis()
+    }
+
+    fn sleep_millis(&self, millis: u64) {
+        (**self).sleep_millis(millis)
+    }
+}
+
+// So that a clock handed over can still be moved by its owner
+impl<C: Clock + ?Sized> Clock for Rc<C> {
+    fn now_millis(&self) -> u64 {
+        (**self).now_millis()
+    }
+
+    fn sleep_millis(&self, millis: u64) {
+        (**self).sleep_millis(millis)
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now_millis(&self) -> u64 {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|duration| duration.as_millis() as u64)
+            .unwrap_or(0)
+    }
+
+    fn sleep_millis(&self, millis: u64) {
+        std::thread::sleep(std::time::Duration::from_millis(millis));
+    }
+}
+
+// Clock that only moves when told to, sleeping advances it instantly
+#[derive(Debug, Default)]
+pub struct TestClock {
+    now_millis: Cell<u64>,
+}
+
+impl TestClock {
+    pub fn new(now_millis: u64) -> Self {
+        Self {
+            now_millis: Cell::new(now_millis),
+        }
+    }
+
+    pub fn set(&self, now_millis: u64) {
+        self.now_millis.set(now_millis);
+    }
+
+    pub fn advance(&self, millis: u64) {
+        self.now_millis.set(self.now_millis.get() + millis);
+    }
+}
+
+impl Clock for TestClock {
+    fn now_millis(&self) -> u64 {
+        self.now_millis.get()
+    }
+
+    fn sleep_millis(&self, millis: u64) {
+        self.advance(millis);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_test_clock() {
+        let clock = TestClock::new(1000);
+        assert_eq!(clock.now_millis(), 1000);
+
+        clock.advance(500);
+        assert_eq!(clock.now_millis(), 1500);
+
+        clock.set(10);
+        assert_eq!(clock.now_millis(), 10);
+
+        clock.sleep_millis(5);
+        assert_eq!(clock.now_millis(), 15);
+    }
+
+    #[test]
+    fn test_system_clock_is_after_2020() {
+        // 2020-01-01T00:00:00Z
+        assert!(SystemClock.now_millis() > 1_577_836_800_000);
+    }
+}
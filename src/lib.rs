@@ -0,0 +1,10 @@
+pub mod analytics;
+pub mod archive;
+pub mod batched_deque;
+pub mod clock;
+pub mod matching;
+pub mod order_book;
+pub mod output;
+pub mod parsing;
+pub mod replay;
+pub mod spsc_ring;
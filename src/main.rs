@@ -1,48 +1,1141 @@
-use clap::Parser;
-use std::fmt::Debug;
-use std::fs::File;
-use std::path::PathBuf;
+use clap::parser::ValueSource;
+use clap::{ArgMatches, CommandFactory, FromArgMatches, Parser, Subcommand};
+use clap_complete::{Shell, generate};
+use rust_decimal::Decimal;
+use std::cell::RefCell;
+use std::fmt::{Debug, Display};
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, IsTerminal, Read, Write};
+use std::iter;
+use std::net::TcpListener;
+use std::path::{Path, PathBuf};
 use std::process::ExitCode;
+use std::rc::Rc;
+use std::str::FromStr;
+use std::thread;
+use std::time::Duration;
+use tracing::{debug, debug_span, error, info, info_span, warn};
+use tracing_subscriber::filter::LevelFilter;
 
-mod batched_deque;
-mod order_book;
-mod parsing;
+use rust_order_book_practice::analytics::book_metrics::BookMetrics;
+use rust_order_book_practice::analytics::candles::{Candle, CandleAggregator, CandleInterval};
+use rust_order_book_practice::analytics::latency::LatencyStats;
+use rust_order_book_practice::analytics::midprice::{MidPricePoint, MidPriceSeries};
+use rust_order_book_practice::analytics::order_flow::{OrderFlowImbalance, OrderFlowTracker};
+use rust_order_book_practice::analytics::pricing::{FairValueConfig, FairValuePoint, Pricer};
+use rust_order_book_practice::analytics::spread_stats::SpreadStats;
+use rust_order_book_practice::analytics::trade_inference::{Trade, TradeInference};
+use rust_order_book_practice::archive::transcode::{TranscodeStats, Transcoder};
+use rust_order_book_practice::clock::SystemClock;
+use rust_order_book_practice::order_book::book_diff::{BookDiff, DeltaPublisher};
+use rust_order_book_practice::order_book::buffered_order_book::{
+    BufferedOrderBook, OverflowPolicy,
+};
+use rust_order_book_practice::order_book::checkpoint::{
+    CheckpointInterval, CheckpointWriter, find_checkpoint,
+};
+use rust_order_book_practice::order_book::events::{AppliedRecord, BookEvent};
+use rust_order_book_practice::order_book::journal::{JournalWriter, replay_journal};
+use rust_order_book_practice::order_book::level_ttl::{ExpiredLevel, LevelTtlConfig};
+use rust_order_book_practice::order_book::listener::BookListener;
+use rust_order_book_practice::order_book::manager::{
+    GapInfo, Manager as OrderBookManager, ManagerOutcome, PendingTtl,
+};
+use rust_order_book_practice::order_book::order_book::{LevelPolicy, OrderBook, TimestampPolicy};
+use rust_order_book_practice::order_book::security_registry::SecurityRegistry;
+use rust_order_book_practice::order_book::sharded_manager::ShardedManager;
+use rust_order_book_practice::output::error_sink::{
+    ErrorSink, JsonErrorSink, ReplayError, ReplayErrorKind,
+};
+#[cfg(feature = "grpc")]
+use rust_order_book_practice::output::grpc_service::{GrpcBooks, serve_grpc};
+#[cfg(feature = "http")]
+use rust_order_book_practice::output::http_api::{HttpBooks, serve_http};
+use rust_order_book_practice::output::ladder::{Alignment, LadderFormat, LadderLayout};
+use rust_order_book_practice::output::query_server::{SharedBooks, serve};
+#[cfg(feature = "redis")]
+use rust_order_book_practice::output::redis_publisher::{DEFAULT_CHANNEL_PREFIX, RedisPublisher};
+use rust_order_book_practice::output::reject_log::{
+    RecordStatus, RejectLog, RejectReason, RejectedRecord,
+};
+use rust_order_book_practice::output::report::ReplayReport;
+use rust_order_book_practice::output::signing::{
+    generate_key, read_signing_key, read_verifying_key, sign_file, verify_file,
+};
+use rust_order_book_practice::output::sink::{CsvSink, OutputEvent, OutputSink, TextSink};
+use rust_order_book_practice::parsing::binary_file_iterator::{BinaryFileIterator, LastFrame};
+use rust_order_book_practice::parsing::compression::{is_stdin, open_file};
+use rust_order_book_practice::parsing::csv_parser::{CsvSnapshotParser, CsvUpdateParser};
+use rust_order_book_practice::parsing::dedup::{DedupKey, Deduplicator};
+use rust_order_book_practice::parsing::file_header::{
+    HEADER_SIZE, UNVERSIONED, strip_file_header, write_file_header,
+};
+use rust_order_book_practice::parsing::follow::TailReader;
+use rust_order_book_practice::parsing::framing::Framing;
+use rust_order_book_practice::parsing::json_parser::{JsonSnapshotParser, JsonUpdateParser};
+use rust_order_book_practice::parsing::merged_files::MergedUpdateFiles;
+use rust_order_book_practice::parsing::mmap_file_iterator::MmapFileIterator;
+use rust_order_book_practice::parsing::order_book_snapshot::{
+    OrderBookSnapshot, OrderBookSnapshotParser, SnapshotFormat,
+};
+use rust_order_book_practice::parsing::order_book_update::{
+    OrderBookUpdate, OrderBookUpdateParser, UpdateFormat,
+};
+use rust_order_book_practice::parsing::parser::{
+    DefaultParser, Endianness, Parser as RecordParser, ParserError, SecurityFilter, TimeWindow,
+};
+use rust_order_book_practice::parsing::pre_scan::pre_scan_updates;
+#[cfg(feature = "proto")]
+use rust_order_book_practice::parsing::proto_parser::{ProtoSnapshotParser, ProtoUpdateParser};
+use rust_order_book_practice::parsing::text_file_iterator::TextFileIterator;
+use rust_order_book_practice::parsing::validation::{FileValidation, validate_records};
+use rust_order_book_practice::replay::{AsOf, Pacer, SeqRange, parse_event_time};
 
-use order_book::errors::Errors as OrderBookErrors;
-use order_book::manager::Manager as OrderBookManager;
-use parsing::binary_file_iterator::BinaryFileIterator;
-use parsing::order_book_snapshot::OrderBookSnapshot;
-use parsing::order_book_update::OrderBookUpdate;
-use parsing::parser::DefaultParser;
+const EXAMPLES: &str = "EXAMPLES:
+    Print the final books:
+        rust_order_book_practice snapshot.bin incremental.bin
+
+    Print trades and top 3 level metrics of a compressed v2 capture:
+        rust_order_book_practice snapshot.bin.gz incremental.bin.zst --update-format v2 \\
+            --infer-trades --metrics 3
+
+    Write 1s bars with spreads and per-record decisions:
+        rust_order_book_practice snapshot.bin incremental.bin --candles-out candles.csv \\
+            --candle-interval 1s --candle-spread --debug-events events.jsonl
+
+    Share a data-quality run as a single page:
+        rust_order_book_practice snapshot.bin incremental.bin --report-out report.html
+
+    Spread the books of many securities over 8 threads:
+        rust_order_book_practice snapshot.bin incremental.bin --threads 8
+
+    Replay a day captured by several feed handlers, read in parallel:
+        rust_order_book_practice snapshot.bin captures/
+
+    Replay at twice the recorded pace:
+        rust_order_book_practice snapshot.bin incremental.bin --speed 2
+
+    Continue a replay where a previous one stopped:
+        rust_order_book_practice snapshot.bin incremental.bin --pending-out pending.bin
+        rust_order_book_practice snapshot.bin next.bin --pending-in pending.bin";
+
+const KEYGEN_EXAMPLES: &str = "EXAMPLES:
+    rust_order_book_practice keygen replay.key replay.pub
+    rust_order_book_practice snapshot.bin incremental.bin --snapshots-out final.bin \\
+        --signing-key replay.key
+    rust_order_book_practice final.bin next.bin --verify-key replay.pub";
+
+const VALIDATE_EXAMPLES: &str = "EXAMPLES:
+    rust_order_book_practice validate snapshot.bin incremental.bin
+    rust_order_book_practice validate snapshot.csv incremental.csv --format csv | jq .valid";
+
+const TRANSCODE_EXAMPLES: &str = "EXAMPLES:
+    rust_order_book_practice transcode snapshot.csv incremental.csv --format csv \\
+        --snapshot-out snapshot.bin --incremental-out incremental.bin --dedup --checksums";
+
+const COMPLETIONS_EXAMPLES: &str = "EXAMPLES:
+    rust_order_book_practice completions bash > /etc/bash_completion.d/rust_order_book_practice
+    rust_order_book_practice completions zsh > ~/.zfunc/_rust_order_book_practice
+    rust_order_book_practice completions fish | source";
 
 #[derive(Parser, Debug)]
-#[clap(about = "Processes snapshot and incremental files")]
+#[clap(
+    about = "Processes snapshot and incremental files",
+    after_help = EXAMPLES,
+    args_conflicts_with_subcommands = true,
+    subcommand_negates_reqs = true
+)]
 struct Args {
-    path_to_snapshot: PathBuf,
-    path_to_incremental: PathBuf,
+    #[clap(subcommand)]
+    command: Option<Command>,
+    #[clap(
+        required = true,
+        help = "Snapshot file, plain or gzip/zstd compressed, or - for the standard input"
+    )]
+    path_to_snapshot: Option<PathBuf>,
+    #[clap(
+        required = true,
+        min_values = 1,
+        help = "Incremental files, directories or glob patterns of them, plain or gzip/zstd \
+            compressed, or - for the standard input. Several files are read in parallel and \
+            merged by timestamp"
+    )]
+    path_to_incremental: Vec<PathBuf>,
     #[clap(short, long, help = "Enable verbose output")]
     verbose: bool,
+    #[clap(
+        long,
+        default_value = "v1",
+        possible_values = ["v1", "v2"],
+        help = "Incremental file layout, v2 adds order count and action per level"
+    )]
+    update_format: UpdateFormat,
+    #[clap(
+        long,
+        default_value = "little",
+        possible_values = ["little", "big"],
+        help = "Byte order of the numbers in the input files"
+    )]
+    endianness: Endianness,
+    #[clap(
+        long,
+        default_value = "v1",
+        possible_values = ["v1", "v2"],
+        help = "Snapshot file layout, v2 gives the number of levels of each snapshot, also of the snapshots written"
+    )]
+    snapshot_format: SnapshotFormat,
+    #[clap(
+        long,
+        value_name = "LEVELS",
+        default_value = "5",
+        possible_values = ["5", "10", "20"],
+        help = "Levels a side of the v1 snapshots read, and of the snapshots written"
+    )]
+    snapshot_depth: usize,
+    #[clap(
+        long,
+        default_value = "atomic",
+        possible_values = ["atomic", "best-effort"],
+        help = "Reject updates with an invalid level, or apply their valid levels and report the rest"
+    )]
+    level_policy: LevelPolicy,
+    #[clap(
+        long,
+        default_value = "clear-all",
+        possible_values = ["clear-all", "drop-oldest", "reject-new"],
+        help = "When a book has too many pending updates, drop them all, the oldest one or the new update"
+    )]
+    pending_overflow: OverflowPolicy,
+    #[clap(
+        long,
+        value_name = "N",
+        help = "Stop reading the incremental files with an error once the updates of a security, \
+            mostly its pending ones, hold more than N levels in memory"
+    )]
+    max_held_levels: Option<usize>,
+    #[clap(
+        long,
+        value_name = "SECURITY_ID:MILLIS",
+        multiple_occurrences = true,
+        help = "Remove levels of the security not refreshed for longer than the TTL, for expiring quotes"
+    )]
+    level_ttl: Vec<LevelTtlConfig>,
+    #[clap(
+        long,
+        value_name = "N",
+        help = "Keep at most N levels a side in every book, dropping the worst ones after each record"
+    )]
+    max_depth: Option<usize>,
+    #[clap(
+        long,
+        value_name = "PATH",
+        help = "Security reference data as CSV or JSON, to check prices against tick sizes and bands and print symbols"
+    )]
+    reference_data: Option<PathBuf>,
+    #[clap(
+        long,
+        value_name = "PERCENT",
+        help = "Reject update levels priced more than PERCENT away from the mid of their book"
+    )]
+    max_price_deviation: Option<Decimal>,
+    #[clap(
+        long,
+        default_value = "ignore",
+        possible_values = ["ignore", "warn", "reject"],
+        help = "Apply updates older than their book silently, apply them with a warning, or reject them"
+    )]
+    timestamp_policy: TimestampPolicy,
+    #[clap(
+        long,
+        help = "Check the invariants of the book after every applied record and stop at the first broken one"
+    )]
+    self_check: bool,
+    #[clap(
+        long,
+        requires = "self-check",
+        help = "Accept books whose best bid meets or passes the best ask in the self-check"
+    )]
+    allow_crossed: bool,
+    #[clap(
+        long,
+        help = "Print trades inferred from changes at the top of the books"
+    )]
+    infer_trades: bool,
+    #[clap(
+        long,
+        value_name = "LEVELS",
+        help = "Print VWAP, imbalance and microprice of each book over the given depth"
+    )]
+    metrics: Option<usize>,
+    #[clap(
+        long,
+        value_name = "PATH",
+        help = "Write capture latency samples as CSV and print per-security percentiles"
+    )]
+    latency_out: Option<PathBuf>,
+    #[clap(
+        long,
+        help = "Print the time-weighted, min and max spread of each security and the share of the time it was one-sided or crossed"
+    )]
+    spread_stats: bool,
+    #[clap(
+        long,
+        help = "Print the number of gaps of each security, the largest one and the pending updates recovered and dropped"
+    )]
+    gap_stats: bool,
+    #[clap(
+        long,
+        value_name = "PATH",
+        help = "Write OHLC bars of the mid-price of each book as CSV"
+    )]
+    candles_out: Option<PathBuf>,
+    #[clap(
+        long,
+        default_value = "1m",
+        possible_values = ["1s", "1m"],
+        help = "Interval of the bars written to --candles-out"
+    )]
+    candle_interval: CandleInterval,
+    #[clap(long, help = "Add OHLC of the spread to the bars")]
+    candle_spread: bool,
+    #[clap(
+        long,
+        value_name = "[SECURITY_ID=]FUNCTION",
+        multiple_occurrences = true,
+        requires = "fair-value-out",
+        help = "Fair value of the security, or of all without one: mid, weighted-mid, depth:N or last-trade:WEIGHT:FUNCTION"
+    )]
+    fair_value: Vec<FairValueConfig>,
+    #[clap(
+        long,
+        value_name = "PATH",
+        requires = "fair-value",
+        help = "Write the fair value of each book after every applied record as CSV"
+    )]
+    fair_value_out: Option<PathBuf>,
+    #[clap(
+        long,
+        value_name = "PATH",
+        help = "Write the levels each applied record changed as CSV, a row per level"
+    )]
+    deltas_out: Option<PathBuf>,
+    #[clap(
+        long,
+        value_name = "PATH",
+        help = "Write the mid and spread of each book as CSV every time its top changes"
+    )]
+    midprice_out: Option<PathBuf>,
+    #[clap(
+        long,
+        value_name = "PATH",
+        help = "Write the order-flow imbalance at the best bid and ask of each applied update as CSV"
+    )]
+    ofi_out: Option<PathBuf>,
+    #[clap(
+        long,
+        value_name = "PATH",
+        help = "Append every applied record to a journal, in apply order, for deterministic re-runs"
+    )]
+    journal_out: Option<PathBuf>,
+    #[clap(
+        long,
+        value_name = "RECORDS|SECONDSs",
+        default_value = "10000",
+        requires = "journal-out",
+        help = "Store the checksums of the books in the journal every number of records, or of seconds of event time"
+    )]
+    journal_checksum_every: CheckpointInterval,
+    #[clap(
+        long,
+        value_name = "PATH",
+        help = "Start from the books the records of a journal build, checked against its checksums"
+    )]
+    journal_in: Option<PathBuf>,
+    #[clap(
+        long,
+        value_name = "PATH",
+        help = "Write an HTML report of the replay with stats, gaps and spreads of every security"
+    )]
+    report_out: Option<PathBuf>,
+    #[clap(
+        long,
+        value_name = "PATH",
+        help = "Restore updates saved with --pending-out after applying the snapshots"
+    )]
+    pending_in: Option<PathBuf>,
+    #[clap(
+        long,
+        value_name = "PATH",
+        help = "Save the updates still waiting for a gap to close on exit"
+    )]
+    pending_out: Option<PathBuf>,
+    #[clap(
+        long,
+        value_name = "SEQ_NOS",
+        help = "Report securities falling behind by more seq_nos than this, as a snapshot is needed"
+    )]
+    max_gap: Option<u64>,
+    #[clap(
+        long,
+        value_name = "event:MILLIS|wall:MILLIS",
+        help = "Drop pending updates waiting longer than this, by their timestamps or by the wall clock"
+    )]
+    pending_ttl: Option<PendingTtl>,
+    #[clap(
+        long,
+        value_name = "PATH",
+        help = "Write the invalid records and levels and the corrupted files as JSON lines"
+    )]
+    errors_out: Option<PathBuf>,
+    #[clap(
+        long,
+        value_name = "PATH",
+        help = "Write every record not applied when it arrived as JSON lines with a reason code, \
+            whether it was rejected or buffered, its offset and bytes"
+    )]
+    rejects_out: Option<PathBuf>,
+    #[clap(
+        long,
+        help = "Fail the run when any record is not applied, any level or file is skipped or any update is left pending"
+    )]
+    strict: bool,
+    #[clap(
+        long,
+        value_name = "N",
+        default_value = "10",
+        requires = "strict",
+        help = "Failures listed in the summary of --strict"
+    )]
+    max_failures: usize,
+    #[clap(
+        long,
+        help = "Scan the incremental file first to size the buffers for the replay"
+    )]
+    pre_scan: bool,
+    #[clap(
+        long,
+        help = "Parse uncompressed input files from memory maps instead of buffered reads"
+    )]
+    mmap: bool,
+    #[clap(
+        long,
+        value_name = "FORMAT",
+        default_value = "binary",
+        help = "Format of the input files: binary, csv or jsonl with one record per line, or proto"
+    )]
+    format: InputFormat,
+    #[clap(
+        long,
+        help = "Drop records already seen in the inputs (same security_id, seq_no and content), \
+            e.g. where captures overlap"
+    )]
+    dedup: bool,
+    #[clap(
+        long,
+        value_name = "seq_no:N|timestamp:T",
+        help = "Only apply the records up to a seq_no of each security or a timestamp, in millis or RFC 3339, to see the books at that moment"
+    )]
+    as_of: Option<AsOf>,
+    #[clap(
+        long,
+        value_name = "SEQ_NO",
+        help = "Skip the updates of each security before this seq_no"
+    )]
+    start_seq: Option<u64>,
+    #[clap(
+        long,
+        value_name = "SEQ_NO",
+        help = "Skip the updates of each security after this seq_no"
+    )]
+    end_seq: Option<u64>,
+    #[clap(
+        long,
+        value_name = "TIME",
+        parse(try_from_str = parse_event_time),
+        help = "Skip the records sent before this time, in millis or RFC 3339"
+    )]
+    from: Option<u64>,
+    #[clap(
+        long,
+        value_name = "TIME",
+        parse(try_from_str = parse_event_time),
+        help = "Skip the records sent after this time, in millis or RFC 3339"
+    )]
+    to: Option<u64>,
+    #[clap(
+        long,
+        value_name = "PATH",
+        help = "Write every level of the books to a checkpoint store during the replay"
+    )]
+    checkpoint_out: Option<PathBuf>,
+    #[clap(
+        long,
+        value_name = "RECORDS|SECONDSs",
+        default_value = "10000",
+        requires = "checkpoint-out",
+        help = "Take a checkpoint every number of records, or of seconds of event time as in 60s"
+    )]
+    checkpoint_every: CheckpointInterval,
+    #[clap(
+        long,
+        value_name = "PATH",
+        help = "Start from the last checkpoint of the store, or the last one before --as-of"
+    )]
+    checkpoint_in: Option<PathBuf>,
+    #[clap(
+        long,
+        value_name = "N",
+        help = "Apply the records on N threads, each owning the books of a share of the securities. \
+            Only takes the flags reading the files and writing the final books"
+    )]
+    threads: Option<usize>,
+    #[clap(
+        long,
+        value_name = "ADDRESS",
+        help = "Answer BOOK <security_id> [depth] requests with ladders of the books over TCP, \
+            e.g. on 127.0.0.1:7000, during the replay and after it until stopped. An address \
+            such as http://127.0.0.1:8080 serves the JSON API of the http feature instead"
+    )]
+    serve: Option<String>,
+    #[cfg(feature = "grpc")]
+    #[clap(
+        long,
+        value_name = "ADDRESS",
+        help = "Serve the GetBook and StreamTopOfBook RPCs of proto/book_service.proto, e.g. on \
+            127.0.0.1:50051, during the replay and after it until stopped"
+    )]
+    grpc: Option<String>,
+    #[cfg(feature = "redis")]
+    #[clap(
+        long,
+        value_name = "ADDRESS",
+        help = "Publish each change of the best bid or ask of a book to the Redis channel of its \
+            security, e.g. at 127.0.0.1:6379"
+    )]
+    redis: Option<String>,
+    #[cfg(feature = "redis")]
+    #[clap(
+        long,
+        value_name = "PREFIX",
+        default_value = DEFAULT_CHANNEL_PREFIX,
+        requires = "redis",
+        help = "Name the Redis channels by the prefix followed by the security_id"
+    )]
+    redis_channel_prefix: String,
+    #[clap(
+        long,
+        value_name = "FACTOR",
+        help = "Replay each file at its recorded pace scaled by the factor, 1 is real time"
+    )]
+    speed: Option<f64>,
+    #[clap(
+        long,
+        help = "Keep applying the updates appended to the incremental file, or to the files of \
+            a directory in name order, as a live capture writes them"
+    )]
+    follow: bool,
+    #[clap(
+        long,
+        value_name = "SECONDS",
+        requires = "follow",
+        help = "Stop following after this many seconds without new updates and print the books"
+    )]
+    follow_timeout: Option<u64>,
+    #[clap(
+        long,
+        value_name = "PATH",
+        help = "Write what happened to every record as JSON lines, for debugging"
+    )]
+    debug_events: Option<PathBuf>,
+    #[clap(
+        long,
+        value_name = "PATH",
+        help = "Write the top --snapshot-depth levels of the final books as a snapshot file"
+    )]
+    snapshots_out: Option<PathBuf>,
+    #[clap(
+        long,
+        requires = "snapshots-out",
+        help = "Start the --snapshots-out file with a header holding the format version"
+    )]
+    write_header: bool,
+    #[clap(
+        long,
+        value_name = "PATH",
+        requires = "snapshots-out",
+        help = "Sign the --snapshots-out file with the key from keygen and make it read-only"
+    )]
+    signing_key: Option<PathBuf>,
+    #[clap(
+        long,
+        value_name = "PATH",
+        help = "Write the top --snapshot-depth levels of every book as snapshot records at intervals, for consumers joining late"
+    )]
+    periodic_snapshots_out: Option<PathBuf>,
+    #[clap(
+        long,
+        value_name = "RECORDS|SECONDSs",
+        default_value = "10000",
+        requires = "periodic-snapshots-out",
+        help = "Write the --periodic-snapshots-out records every number of records, or of seconds of event time"
+    )]
+    snapshot_every: CheckpointInterval,
+    #[clap(
+        long,
+        value_name = "PATH",
+        help = "Only load a snapshot file whose signature matches the public key"
+    )]
+    verify_key: Option<PathBuf>,
+    #[clap(
+        long,
+        value_name = "LEVEL",
+        default_value = "info",
+        help = "Most verbose diagnostics written to stderr: off, error, warn, info, debug or trace"
+    )]
+    log_level: LevelFilter,
+    #[clap(
+        long,
+        value_name = "FORMAT",
+        default_value = "text",
+        help = "Format of the diagnostics: text or json"
+    )]
+    log_format: LogFormat,
+    #[clap(
+        long,
+        value_name = "IDS",
+        use_value_delimiter = true,
+        help = "Print the final books and metrics of these comma-separated securities only"
+    )]
+    print_securities: Option<Vec<u64>>,
+    #[clap(
+        long,
+        value_name = "IDS",
+        use_value_delimiter = true,
+        help = "Only read and apply the records of these comma-separated securities"
+    )]
+    securities: Option<Vec<u64>>,
+    #[clap(
+        long,
+        help = "Print the final books as price ladders of the best levels instead of all levels"
+    )]
+    ladder: bool,
+    #[clap(
+        long,
+        value_name = "LEVELS",
+        default_value = "5",
+        requires = "ladder",
+        help = "Levels of each side shown in a ladder"
+    )]
+    ladder_depth: usize,
+    #[clap(
+        long,
+        value_name = "LAYOUT",
+        default_value = "rows",
+        possible_values = ["rows", "columns"],
+        requires = "ladder",
+        help = "A ladder row per level with its side, or the bids left and the asks right of the prices"
+    )]
+    ladder_layout: LadderLayout,
+    #[clap(
+        long,
+        value_name = "ALIGNMENT",
+        default_value = "right",
+        possible_values = ["left", "right"],
+        requires = "ladder",
+        help = "Alignment of the ladder columns"
+    )]
+    ladder_align: Alignment,
+    #[clap(
+        long,
+        requires = "ladder",
+        help = "Add the quantity up to each level from the best one to the ladder"
+    )]
+    cumulative: bool,
+    #[clap(
+        long,
+        requires = "ladder",
+        help = "Add a bar of the quantity of each level, or of its cumulative quantity, to the ladder"
+    )]
+    ladder_bars: bool,
+    #[clap(
+        long,
+        value_name = "WHEN",
+        default_value = "auto",
+        possible_values = ["auto", "always", "never"],
+        requires = "ladder",
+        help = "Color the bid and ask rows of the ladder, auto when stdout is a terminal"
+    )]
+    color: ColorMode,
 }
 
-fn print_records_from_file<T: Debug + DefaultParser<T>>(path: &PathBuf) {
-    println!("Printing records from file: {}", path.display());
-    let file = File::open(path);
-    if file.is_err() {
-        eprintln!("Failed to open file: {}", path.display());
-        return;
+#[derive(Debug, Clone, Copy)]
+enum ColorMode {
+    Auto,
+    Always,
+    Never,
+}
+
+impl FromStr for ColorMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "auto" => Ok(ColorMode::Auto),
+            "always" => Ok(ColorMode::Always),
+            "never" => Ok(ColorMode::Never),
+            _ => Err(format!("Unknown color mode: {}", s)),
+        }
+    }
+}
+
+impl ColorMode {
+    fn enabled(self) -> bool {
+        match self {
+            ColorMode::Auto => io::stdout().is_terminal(),
+            ColorMode::Always => true,
+            ColorMode::Never => false,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum InputFormat {
+    Binary,
+    Csv,
+    Jsonl,
+    #[cfg(feature = "proto")]
+    Proto,
+}
+
+impl FromStr for InputFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "binary" => Ok(InputFormat::Binary),
+            "csv" => Ok(InputFormat::Csv),
+            "jsonl" => Ok(InputFormat::Jsonl),
+            #[cfg(feature = "proto")]
+            "proto" => Ok(InputFormat::Proto),
+            #[cfg(not(feature = "proto"))]
+            "proto" => Err("Built without the proto feature".to_string()),
+            _ => Err(format!("Unknown input format: {}", s)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+enum LogFormat {
+    Text,
+    Json,
+}
+
+impl FromStr for LogFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "text" => Ok(LogFormat::Text),
+            "json" => Ok(LogFormat::Json),
+            _ => Err(format!("Unknown log format: {}", s)),
+        }
+    }
+}
+
+// Diagnostics go to stderr so they never mix with the books on stdout
+fn init_logging(level: LevelFilter, format: LogFormat) {
+    let subscriber = tracing_subscriber::fmt()
+        .with_max_level(level)
+        .with_writer(io::stderr)
+        .with_target(false);
+    match format {
+        LogFormat::Text => subscriber.init(),
+        LogFormat::Json => subscriber.json().init(),
+    }
+}
+
+#[derive(Default)]
+struct Analytics {
+    trade_inference: Option<TradeInference>,
+    latency_stats: Option<LatencyStats>,
+    checkpoints: Option<CheckpointWriter<BufWriter<File>>>,
+    journal: Option<Rc<RefCell<JournalWriter<BufWriter<File>>>>>,
+    rejects: Option<RejectLog<BufWriter<File>>>,
+    strict: Option<StrictFailures>,
+}
+
+// Reports the levels removed by --level-ttl
+struct ExpiryPrinter;
+
+impl BookListener for ExpiryPrinter {
+    fn on_levels_expired(&mut self, _book: &OrderBook, expired: &[ExpiredLevel]) {
+        for level in expired {
+            println!(
+                "Level {:?} {} x {} of security {} expired, last refreshed at {}",
+                level.side, level.price, level.qty, level.security_id, level.refreshed_at
+            );
+        }
+    }
+}
+
+// Reports the updates applied with an older timestamp than their book by
+// --timestamp-policy warn
+struct TimestampWarner;
+
+impl BookListener for TimestampWarner {
+    fn on_timestamp_regression(&mut self, book: &OrderBook, previous_timestamp: u64) {
+        warn!(
+            security_id = book.security_id,
+            seq_no = book.seq_no,
+            timestamp = book.timestamp,
+            previous_timestamp,
+            "Update older than its book"
+        );
+    }
+}
+
+// Exit code of a run failed by --strict, the errors that stop a run exit with 1
+const STRICT_FAILURE_EXIT_CODE: u8 = 3;
+
+// What a --strict run failed on, with the first failures for the summary
+struct StrictFailures {
+    count: usize,
+    first: Vec<String>,
+    max_listed: usize,
+}
+
+impl StrictFailures {
+    fn new(max_listed: usize) -> Self {
+        Self {
+            count: 0,
+            first: Vec::new(),
+            max_listed,
+        }
+    }
+
+    fn record(&mut self, failure: impl Display) {
+        self.count += 1;
+        if self.first.len() < self.max_listed {
+            self.first.push(failure.to_string());
+        }
+    }
+
+    // Updates that were buffered and never applied
+    fn record_pending(&mut self, order_book_manager: &OrderBookManager) {
+        for (security_id, buffered_order_book) in &order_book_manager.buffered_order_books {
+            let dropped = buffered_order_book.stats().dropped;
+            if dropped > 0 {
+                self.record(format!(
+                    "{} pending updates of security {} dropped",
+                    dropped, security_id
+                ));
+            }
+            let pending = buffered_order_book.pending_updates.len();
+            if pending > 0 {
+                self.record(format!(
+                    "{} updates of security {} still pending",
+                    pending, security_id
+                ));
+            }
+        }
+    }
+
+    fn print_summary(&self) {
+        eprintln!(
+            "Strict mode: {} failures, the first {}:",
+            self.count,
+            self.first.len()
+        );
+        for failure in &self.first {
+            eprintln!("  {}", failure);
+        }
+    }
+}
+
+// Logs the records skipped by the replay with the diagnostics
+struct LogErrorSink;
+
+impl ErrorSink for LogErrorSink {
+    fn report(&mut self, error: &ReplayError) {
+        let (security_id, seq_no) = (error.security_id, error.seq_no);
+        match error.kind {
+            ReplayErrorKind::InvalidRecord => warn!(
+                security_id,
+                seq_no,
+                error = %error.message,
+                "Invalid record, the record will be ignored"
+            ),
+            ReplayErrorKind::InvalidLevel => warn!(
+                security_id,
+                seq_no,
+                level = error.level,
+                error = %error.message,
+                "Invalid level, the level will be ignored"
+            ),
+            ReplayErrorKind::PendingOverflow => warn!(
+                security_id,
+                seq_no, "Too many pending updates, the update will be dropped"
+            ),
+            ReplayErrorKind::Internal => error!(error = %error.message, "Internal error"),
+            ReplayErrorKind::Corrupted => error!(
+                path = %error.source,
+                error = %error.message,
+                "Failed to read next record, the file is corrupted"
+            ),
+        }
+    }
+}
+
+// Reports the pending updates dropped by --pending-ttl
+struct PendingExpiryWarner;
+
+impl BookListener for PendingExpiryWarner {
+    fn on_pending_expired(&mut self, security_id: u64, seq_nos: &[u64]) {
+        warn!(
+            security_id,
+            count = seq_nos.len(),
+            first_seq_no = seq_nos.first(),
+            last_seq_no = seq_nos.last(),
+            "Pending updates expired, the updates will be dropped"
+        );
+    }
+}
+
+type Records<T> = Box<dyn Iterator<Item = io::Result<T>>>;
+// With the frame of the last record read when the frames are kept, see ReplayOptions
+type FramedRecords<T> = (Records<T>, Option<LastFrame>);
+
+// How the records are stored in the input files
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum InputEncoding {
+    Buffered,
+    Mmap,
+    Csv,
+    Jsonl,
+    #[cfg(feature = "proto")]
+    Proto,
+}
+
+// Records of an input file in one of the other formats, configured as the binary parser
+trait FormatInput: DefaultParser<Self, ParserType: Framing<Self>> + Sized {
+    type CsvParser: RecordParser<Self> + 'static;
+    type JsonParser: RecordParser<Self> + 'static;
+    #[cfg(feature = "proto")]
+    type ProtoParser: RecordParser<Self> + 'static;
+
+    fn csv_parser(parser: &Self::ParserType) -> Self::CsvParser;
+
+    fn json_parser(parser: &Self::ParserType) -> Self::JsonParser;
+
+    #[cfg(feature = "proto")]
+    fn proto_parser(parser: &Self::ParserType) -> Self::ProtoParser;
+
+    fn security_filter(parser: &Self::ParserType) -> &SecurityFilter;
+
+    fn time_window(parser: &Self::ParserType) -> TimeWindow;
+
+    fn security_id(&self) -> u64;
+
+    fn timestamp(&self) -> u64;
+}
+
+impl FormatInput for OrderBookSnapshot {
+    type CsvParser = CsvSnapshotParser;
+    type JsonParser = JsonSnapshotParser;
+    #[cfg(feature = "proto")]
+    type ProtoParser = ProtoSnapshotParser;
+
+    fn csv_parser(_parser: &OrderBookSnapshotParser) -> CsvSnapshotParser {
+        CsvSnapshotParser::default()
+    }
+
+    fn json_parser(_parser: &OrderBookSnapshotParser) -> JsonSnapshotParser {
+        JsonSnapshotParser::default()
+    }
+
+    #[cfg(feature = "proto")]
+    fn proto_parser(_parser: &OrderBookSnapshotParser) -> ProtoSnapshotParser {
+        ProtoSnapshotParser::default()
+    }
+
+    fn security_filter(parser: &OrderBookSnapshotParser) -> &SecurityFilter {
+        parser.security_filter()
+    }
+
+    fn time_window(parser: &OrderBookSnapshotParser) -> TimeWindow {
+        parser.time_window()
+    }
+
+    fn security_id(&self) -> u64 {
+        self.security_id
+    }
+
+    fn timestamp(&self) -> u64 {
+        self.timestamp
+    }
+}
+
+impl FormatInput for OrderBookUpdate {
+    type CsvParser = CsvUpdateParser;
+    type JsonParser = JsonUpdateParser;
+    #[cfg(feature = "proto")]
+    type ProtoParser = ProtoUpdateParser;
+
+    fn csv_parser(parser: &OrderBookUpdateParser) -> CsvUpdateParser {
+        CsvUpdateParser::new(parser.format()).with_max_held_levels(parser.max_held_levels())
+    }
+
+    fn json_parser(parser: &OrderBookUpdateParser) -> JsonUpdateParser {
+        JsonUpdateParser::default().with_max_held_levels(parser.max_held_levels())
+    }
+
+    #[cfg(feature = "proto")]
+    fn proto_parser(parser: &OrderBookUpdateParser) -> ProtoUpdateParser {
+        ProtoUpdateParser::default().with_max_held_levels(parser.max_held_levels())
     }
 
+    fn security_filter(parser: &OrderBookUpdateParser) -> &SecurityFilter {
+        parser.security_filter()
+    }
+
+    fn time_window(parser: &OrderBookUpdateParser) -> TimeWindow {
+        parser.time_window()
+    }
+
+    fn security_id(&self) -> u64 {
+        self.security_id
+    }
+
+    fn timestamp(&self) -> u64 {
+        self.timestamp
+    }
+}
+
+fn open_records<T: FormatInput + 'static>(
+    path: &Path,
+    parser: T::ParserType,
+    encoding: InputEncoding,
+) -> io::Result<Records<T>>
+where
+    T::ParserType: 'static,
+{
+    open_framed_records(path, parser, encoding, false).map(|(records, _)| records)
+}
+
+// The records, with the frame of the last one read when `frames` is set
+fn open_framed_records<T: FormatInput + 'static>(
+    path: &Path,
+    parser: T::ParserType,
+    encoding: InputEncoding,
+    frames: bool,
+) -> io::Result<FramedRecords<T>>
+where
+    T::ParserType: 'static,
+{
+    if encoding == InputEncoding::Mmap {
+        // The binary parsers skip the filtered records themselves
+        let records = MmapFileIterator::<T>::open(path, parser)?;
+        if frames {
+            let frame = LastFrame::default();
+            return Ok((Box::new(records.with_frames(frame.clone())), Some(frame)));
+        }
+        return Ok((Box::new(records), None));
+    }
+    framed_records_from_reader(open_file(path)?, parser, encoding, frames)
+}
+
+// Records of a byte stream, such as a decompressed file or a tailed capture
+fn framed_records_from_reader<T: FormatInput + 'static>(
+    reader: Box<dyn Read>,
+    parser: T::ParserType,
+    encoding: InputEncoding,
+    frames: bool,
+) -> io::Result<FramedRecords<T>>
+where
+    T::ParserType: 'static,
+{
+    let frame = frames.then(LastFrame::default);
+    let records: Records<T> = match encoding {
+        // The binary parsers skip the filtered records themselves
+        InputEncoding::Buffered => {
+            let records = BinaryFileIterator::<T>::from_reader(reader, parser)?;
+            return Ok(match frame {
+                Some(frame) => (Box::new(records.with_frames(frame.clone())), Some(frame)),
+                None => (Box::new(records), None),
+            });
+        }
+        InputEncoding::Mmap => {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "Streams can't be memory-mapped",
+            ));
+        }
+        InputEncoding::Csv => {
+            let records = TextFileIterator::from_reader(reader, T::csv_parser(&parser));
+            match frame.clone() {
+                Some(frame) => Box::new(records.with_line_frames(frame)),
+                None => Box::new(records),
+            }
+        }
+        InputEncoding::Jsonl => {
+            let records = TextFileIterator::from_reader(reader, T::json_parser(&parser));
+            match frame.clone() {
+                Some(frame) => Box::new(records.with_line_frames(frame)),
+                None => Box::new(records),
+            }
+        }
+        #[cfg(feature = "proto")]
+        InputEncoding::Proto => {
+            let records = TextFileIterator::from_reader(reader, T::proto_parser(&parser));
+            match frame.clone() {
+                Some(frame) => Box::new(records.with_frames(frame)),
+                None => Box::new(records),
+            }
+        }
+    };
+    let security_filter = T::security_filter(&parser).clone();
+    let time_window = T::time_window(&parser);
+    if security_filter.accepts_all() && time_window.is_unbounded() {
+        return Ok((records, frame));
+    }
+    let records = Box::new(records.filter(move |record| match record {
+        Ok(record) => {
+            security_filter.accepts(record.security_id())
+                && time_window.includes(record.timestamp())
+        }
+        Err(_) => true,
+    }));
+    Ok((records, frame))
+}
+
+fn print_records_from_file<T: Debug + FormatInput + 'static>(
+    path: &Path,
+    parser: T::ParserType,
+    encoding: InputEncoding,
+) where
+    T::ParserType: 'static,
+{
+    println!("Printing records from file: {}", path.display());
+    let records = match open_records::<T>(path, parser, encoding) {
+        Ok(records) => records,
+        Err(e) => {
+            error!(path = %path.display(), error = %e, "Failed to open file");
+            return;
+        }
+    };
+
     let mut record_count = 0;
-    for record in BinaryFileIterator::<T>::new(file.unwrap()) {
+    for record in records {
         match record {
             Ok(record) => {
                 println!("{:#?}", &record);
                 record_count += 1;
             }
             Err(e) => {
-                eprintln!(
-                    "Failed to read next record from the file: {}. The file is corrupted.",
-                    e
+                error!(
+                    path = %path.display(),
+                    error = %e,
+                    "Failed to read next record, the file is corrupted"
                 );
                 return;
             }
@@ -52,114 +1145,1754 @@ fn print_records_from_file<T: Debug + DefaultParser<T>>(path: &PathBuf) {
 }
 
 trait ApplyToOrderBook {
-    fn apply_to_order_book(self, manager: &mut OrderBookManager) -> Result<(), OrderBookErrors>;
+    fn apply_to_order_book(self, manager: &mut OrderBookManager) -> ManagerOutcome;
     fn get_record_type() -> &'static str;
+    fn get_security_id(&self) -> u64;
+    fn get_seq_no(&self) -> u64;
+    fn get_timestamp(&self) -> u64;
+    fn get_capture_timestamps(&self) -> Option<(u64, u64)>;
+    fn infer_trades(trade_inference: &mut TradeInference, order_book: &OrderBook) -> Vec<Trade>;
 }
 
 impl ApplyToOrderBook for OrderBookSnapshot {
-    fn apply_to_order_book(self, manager: &mut OrderBookManager) -> Result<(), OrderBookErrors> {
+    fn apply_to_order_book(self, manager: &mut OrderBookManager) -> ManagerOutcome {
         manager.apply_snapshot(&self)
     }
 
     fn get_record_type() -> &'static str {
         "Snapshot"
     }
+
+    fn get_security_id(&self) -> u64 {
+        self.security_id
+    }
+
+    fn get_seq_no(&self) -> u64 {
+        self.seq_no
+    }
+
+    fn get_timestamp(&self) -> u64 {
+        self.timestamp
+    }
+
+    fn get_capture_timestamps(&self) -> Option<(u64, u64)> {
+        None
+    }
+
+    fn infer_trades(trade_inference: &mut TradeInference, order_book: &OrderBook) -> Vec<Trade> {
+        // A snapshot may be far ahead of the previous state, so it only resets the reference
+        trade_inference.reset(order_book);
+        Vec::new()
+    }
 }
 
 impl ApplyToOrderBook for OrderBookUpdate {
-    fn apply_to_order_book(self, manager: &mut OrderBookManager) -> Result<(), OrderBookErrors> {
+    fn apply_to_order_book(self, manager: &mut OrderBookManager) -> ManagerOutcome {
         manager.apply_update(self)
     }
 
     fn get_record_type() -> &'static str {
         "Update"
     }
+
+    fn get_security_id(&self) -> u64 {
+        self.security_id
+    }
+
+    fn get_seq_no(&self) -> u64 {
+        self.seq_no
+    }
+
+    fn get_timestamp(&self) -> u64 {
+        self.timestamp
+    }
+
+    fn get_capture_timestamps(&self) -> Option<(u64, u64)> {
+        self.capture_timestamp
+            .map(|capture_timestamp| (self.timestamp, capture_timestamp))
+    }
+
+    fn infer_trades(trade_inference: &mut TradeInference, order_book: &OrderBook) -> Vec<Trade> {
+        trade_inference.observe(order_book)
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct ReplayOptions {
+    encoding: InputEncoding,
+    speed: Option<f64>,
+    dedup: bool,
+    as_of: Option<AsOf>,
+    // Only applied to the updates
+    seq_range: SeqRange,
+    self_check: bool,
+    // Keep the frame of every record read, the rejects log needs them
+    frames: bool,
 }
 
-fn apply_order_book_records_from_file<T: ApplyToOrderBook + DefaultParser<T>>(
-    path: &PathBuf,
+fn apply_order_book_records_from_file<T: ApplyToOrderBook + DedupKey + FormatInput + 'static>(
+    path: &Path,
+    parser: T::ParserType,
     order_book_manager: &mut OrderBookManager,
-) -> bool {
-    let file = File::open(path);
-    if file.is_err() {
-        eprintln!("Failed to open file: {}", path.display());
-        return false;
+    analytics: &mut Analytics,
+    output: &mut dyn OutputSink,
+    errors: &mut dyn ErrorSink,
+    options: ReplayOptions,
+) -> bool
+where
+    T::ParserType: 'static,
+{
+    match open_framed_records::<T>(path, parser, options.encoding, options.frames) {
+        Ok(records) => apply_order_book_records(
+            records,
+            &path.display().to_string(),
+            order_book_manager,
+            analytics,
+            output,
+            errors,
+            options,
+        ),
+        Err(e) => {
+            error!(path = %path.display(), error = %e, "Failed to open file");
+            false
+        }
     }
+}
 
-    for record in BinaryFileIterator::<T>::new(file.unwrap()) {
+// Applies the records read from the source, a file or the files merged into one stream
+fn apply_order_book_records<T: ApplyToOrderBook + DedupKey>(
+    (records, frame): FramedRecords<T>,
+    source: &str,
+    order_book_manager: &mut OrderBookManager,
+    analytics: &mut Analytics,
+    output: &mut dyn OutputSink,
+    errors: &mut dyn ErrorSink,
+    options: ReplayOptions,
+) -> bool {
+    let _file_span = info_span!(
+        "replay_file",
+        path = %source,
+        record_type = T::get_record_type()
+    )
+    .entered();
+    let mut pacer = options.speed.map(|speed| Pacer::new(SystemClock, speed));
+    let mut deduplicator = options.dedup.then(Deduplicator::default);
+
+    for record in records {
         match record {
             Ok(record) => {
-                if let Err(e) = record.apply_to_order_book(order_book_manager) {
-                    match e {
-                        OrderBookErrors::InvalidPrice(update_msg_info, msg) => {
-                            eprintln!(
-                                "{} for security {} with seq_no {} has invalid price: {}. The record will be ignored.",
-                                T::get_record_type(),
-                                update_msg_info.security_id,
-                                update_msg_info.seq_no,
-                                msg
+                if let Some(deduplicator) = deduplicator.as_mut()
+                    && deduplicator.is_duplicate(&record)
+                {
+                    continue;
+                }
+                if let Some(as_of) = options.as_of
+                    && !as_of.includes(record.get_seq_no(), record.get_timestamp())
+                {
+                    continue;
+                }
+                if !options.seq_range.includes(record.get_seq_no()) {
+                    continue;
+                }
+                if let Some(pacer) = pacer.as_mut() {
+                    pacer.wait_until(record.get_timestamp());
+                }
+                let security_id = record.get_security_id();
+                let _record_span =
+                    debug_span!("record", security_id, seq_no = record.get_seq_no()).entered();
+                if let Some(latency_stats) = analytics.latency_stats.as_mut()
+                    && let Some((timestamp, capture_timestamp)) = record.get_capture_timestamps()
+                {
+                    latency_stats.record(security_id, timestamp, capture_timestamp);
+                }
+                let seq_no = record.get_seq_no();
+                let outcome = record.apply_to_order_book(order_book_manager);
+                debug!(?outcome, "Record handled");
+                if let Some(rejects) = analytics.rejects.as_mut()
+                    && let Some(reason) = RejectReason::of(&outcome)
+                    && let Some(status) = RecordStatus::of(&outcome)
+                {
+                    // The frame is that of the record as long as the next one isn't read
+                    let frame = frame.as_ref().map(LastFrame::get);
+                    rejects.write(&RejectedRecord {
+                        reason,
+                        status,
+                        source: frame
+                            .as_ref()
+                            .and_then(|frame| frame.source.as_deref())
+                            .unwrap_or(source),
+                        security_id,
+                        seq_no,
+                        offset: frame.as_ref().map(|frame| frame.offset),
+                        raw: frame.as_ref().map_or(&[], |frame| &frame.bytes),
+                    });
+                }
+                // Buffered updates fail the run if they are still pending at its end
+                if let Some(strict) = analytics.strict.as_mut()
+                    && let Some(reason) = RejectReason::of(&outcome)
+                    && !matches!(reason, RejectReason::Gap | RejectReason::AwaitingSnapshot)
+                {
+                    strict.record(format!(
+                        "{:?} in {}, security {} seq_no {}",
+                        reason, source, security_id, seq_no
+                    ));
+                }
+                if let Some(checkpoints) = analytics.checkpoints.as_mut()
+                    && let Err(e) = checkpoints.on_record(order_book_manager)
+                {
+                    error!(error = %e, "Failed to write checkpoint");
+                    return false;
+                }
+                if let Some(journal) = &analytics.journal {
+                    journal.borrow_mut().on_record(order_book_manager);
+                }
+                match outcome {
+                    ManagerOutcome::Applied => {
+                        if options.self_check
+                            && let Some(buffered_order_book) =
+                                order_book_manager.buffered_order_books.get(&security_id)
+                            && let Err(violation) = buffered_order_book.validate()
+                        {
+                            error!(
+                                security_id,
+                                seq_no = buffered_order_book.order_book.seq_no,
+                                %violation,
+                                "Book invariant broken"
                             );
+                            return false;
                         }
-                        OrderBookErrors::InvalidSide(update_msg_info, msg) => {
-                            eprintln!(
-                                "{} for security {} with seq_no {} has invalid side: {}. The record will be ignored.",
-                                T::get_record_type(),
-                                update_msg_info.security_id,
-                                update_msg_info.seq_no,
-                                msg
-                            );
+                        if let Some(trade_inference) = analytics.trade_inference.as_mut()
+                            && let Some(buffered_order_book) =
+                                order_book_manager.buffered_order_books.get(&security_id)
+                        {
+                            let order_book = &buffered_order_book.order_book;
+                            for trade in T::infer_trades(trade_inference, order_book) {
+                                if let Err(e) = output.write_event(OutputEvent::Trade(&trade)) {
+                                    error!(error = %e, "Failed to write trade");
+                                }
+                            }
                         }
-                        OrderBookErrors::SecurityIdMismatch => {
-                            eprintln!("Internal error: Security ID mismatch.");
+                    }
+                    ManagerOutcome::Buffered(_)
+                    | ManagerOutcome::IgnoredOld
+                    | ManagerOutcome::IgnoredUnknownSecurity => {}
+                    ManagerOutcome::Rejected(e) => {
+                        // The other errors are reported as the other outcomes
+                        if let Some(error) = ReplayError::rejected(source, &e) {
+                            errors.report(&error);
                         }
-                        OrderBookErrors::OrderBookNotFound => {}
-                        OrderBookErrors::SequenceNumberGap => {}
-                        OrderBookErrors::OldSequenceNumber => {}
                     }
                 }
+                for level in order_book_manager.drain_quarantined() {
+                    let error = ReplayError::quarantined(source, &level);
+                    if let Some(strict) = analytics.strict.as_mut() {
+                        strict.record(&error);
+                    }
+                    errors.report(&error);
+                }
             }
             Err(e) => {
-                eprintln!(
-                    "Failed to read next {} from the file: {}. The file {} is corrupted.",
-                    T::get_record_type(),
-                    e,
-                    path.display()
-                );
-                return true;
+                let error = ReplayError::corrupted(source, e.to_string());
+                if let Some(strict) = analytics.strict.as_mut() {
+                    strict.record(&error);
+                }
+                errors.report(&error);
+                break;
             }
         }
     }
+
+    if let Some(deduplicator) = deduplicator {
+        println!("{} in {}", deduplicator.stats, source);
+    }
     true
 }
 
+#[derive(Subcommand, Debug)]
+enum Command {
+    #[clap(
+        about = "Print a completion script for the shell",
+        after_help = COMPLETIONS_EXAMPLES
+    )]
+    Completions {
+        #[clap(value_enum)]
+        shell: Shell,
+    },
+    #[clap(
+        about = "Generate an ed25519 key pair for --signing-key and --verify-key",
+        after_help = KEYGEN_EXAMPLES
+    )]
+    Keygen {
+        #[clap(value_name = "PRIVATE_KEY")]
+        private_key: PathBuf,
+        #[clap(value_name = "PUBLIC_KEY")]
+        public_key: PathBuf,
+    },
+    #[clap(
+        about = "Check the records of both files without building books",
+        long_about = "Check the records of both files without building books. Prints a JSON \
+            summary per file with structural errors, the truncation point, out-of-order and \
+            duplicate seq_nos, and exits with an error if any file has issues.",
+        after_help = VALIDATE_EXAMPLES
+    )]
+    Validate {
+        #[clap(help = "Snapshot file, plain or gzip/zstd compressed")]
+        path_to_snapshot: PathBuf,
+        #[clap(help = "Incremental file, plain or gzip/zstd compressed")]
+        path_to_incremental: PathBuf,
+        #[clap(
+            long,
+            default_value = "v1",
+            possible_values = ["v1", "v2"],
+            help = "Incremental file layout, v2 adds order count and action per level"
+        )]
+        update_format: UpdateFormat,
+        #[clap(
+            long,
+            default_value = "little",
+            possible_values = ["little", "big"],
+            help = "Byte order of the numbers in the input files"
+        )]
+        endianness: Endianness,
+        #[clap(
+            long,
+            default_value = "v1",
+            possible_values = ["v1", "v2"],
+            help = "Snapshot file layout, v2 gives the number of levels of each snapshot"
+        )]
+        snapshot_format: SnapshotFormat,
+        #[clap(
+            long,
+            value_name = "LEVELS",
+            default_value = "5",
+            possible_values = ["5", "10", "20"],
+            help = "Levels a side of the v1 snapshots"
+        )]
+        snapshot_depth: usize,
+        #[clap(
+            long,
+            value_name = "FORMAT",
+            default_value = "binary",
+            help = "Format of the input files: binary, csv, jsonl or proto"
+        )]
+        format: InputFormat,
+    },
+    #[clap(
+        about = "Rewrite both files in the binary format of the crate",
+        long_about = "Rewrite both files in the binary format of the crate, with a file header, \
+            prices rounded to the nearest multiple of the price tick and zero prices for empty \
+            snapshot levels. Useful for cleaning vendor captures of any input format.",
+        after_help = TRANSCODE_EXAMPLES
+    )]
+    Transcode {
+        #[clap(help = "Snapshot file, plain or gzip/zstd compressed")]
+        path_to_snapshot: PathBuf,
+        #[clap(help = "Incremental file, plain or gzip/zstd compressed")]
+        path_to_incremental: PathBuf,
+        #[clap(long, value_name = "PATH", help = "Snapshot file to write")]
+        snapshot_out: PathBuf,
+        #[clap(long, value_name = "PATH", help = "Incremental file to write")]
+        incremental_out: PathBuf,
+        #[clap(
+            long,
+            default_value = "v1",
+            possible_values = ["v1", "v2"],
+            help = "Incremental file layout of the input and the output, v2 adds order count and \
+                action per level"
+        )]
+        update_format: UpdateFormat,
+        #[clap(
+            long,
+            default_value = "little",
+            possible_values = ["little", "big"],
+            help = "Byte order of the numbers in the input files, the output is little-endian"
+        )]
+        endianness: Endianness,
+        #[clap(
+            long,
+            default_value = "v1",
+            possible_values = ["v1", "v2"],
+            help = "Snapshot file layout of the input, v2 gives the number of levels of each snapshot"
+        )]
+        snapshot_format: SnapshotFormat,
+        #[clap(
+            long,
+            possible_values = ["v1", "v2"],
+            help = "Snapshot file layout of the output, the one of the input unless given"
+        )]
+        snapshot_format_out: Option<SnapshotFormat>,
+        #[clap(
+            long,
+            value_name = "LEVELS",
+            default_value = "5",
+            possible_values = ["5", "10", "20"],
+            help = "Levels a side of the v1 snapshots of the input and the output"
+        )]
+        snapshot_depth: usize,
+        #[clap(
+            long,
+            value_name = "FORMAT",
+            default_value = "binary",
+            help = "Format of the input files: binary, csv, jsonl or proto"
+        )]
+        format: InputFormat,
+        #[clap(
+            long,
+            help = "Drop records already written, e.g. where captures overlap"
+        )]
+        dedup: bool,
+        #[clap(
+            long,
+            help = "Write the offset, length and CRC32 of every record written to <PATH>.crc32.csv \
+                next to each output file"
+        )]
+        checksums: bool,
+    },
+}
+
+fn validate_file<T: FormatInput + DedupKey>(
+    path: &Path,
+    mut parser: T::ParserType,
+    format: InputFormat,
+) -> io::Result<FileValidation> {
+    // Malformed lines of the text formats are skipped, the other records have no delimiters
+    // to resync on
+    Ok(match format {
+        InputFormat::Binary => {
+            let (version, reader) = open_file(path).and_then(strip_file_header)?;
+            let offset = if version == UNVERSIONED {
+                0
+            } else {
+                HEADER_SIZE as u64
+            };
+            validate_records(&mut BufReader::new(reader), &mut parser, offset, false)
+        }
+        InputFormat::Csv => validate_records(
+            &mut BufReader::new(open_file(path)?),
+            &mut T::csv_parser(&parser),
+            0,
+            true,
+        ),
+        InputFormat::Jsonl => validate_records(
+            &mut BufReader::new(open_file(path)?),
+            &mut T::json_parser(&parser),
+            0,
+            true,
+        ),
+        #[cfg(feature = "proto")]
+        InputFormat::Proto => validate_records(
+            &mut BufReader::new(open_file(path)?),
+            &mut T::proto_parser(&parser),
+            0,
+            false,
+        ),
+    })
+}
+
+// The parser of the snapshot layout given on the command line
+fn snapshot_parser(
+    endianness: Endianness,
+    format: SnapshotFormat,
+    depth: usize,
+) -> Option<OrderBookSnapshotParser> {
+    match OrderBookSnapshotParser::new(endianness)
+        .with_format(format)
+        .with_depth(depth)
+    {
+        Ok(parser) => Some(parser),
+        Err(e) => {
+            error!(error = ?e, "Invalid snapshot depth");
+            None
+        }
+    }
+}
+
+fn validate_files(
+    path_to_snapshot: &Path,
+    path_to_incremental: &Path,
+    snapshot_parser: OrderBookSnapshotParser,
+    update_format: UpdateFormat,
+    endianness: Endianness,
+    format: InputFormat,
+) -> ExitCode {
+    let validations = [
+        (
+            path_to_snapshot,
+            validate_file::<OrderBookSnapshot>(path_to_snapshot, snapshot_parser, format),
+        ),
+        (
+            path_to_incremental,
+            validate_file::<OrderBookUpdate>(
+                path_to_incremental,
+                OrderBookUpdateParser::new(update_format).with_endianness(endianness),
+                format,
+            ),
+        ),
+    ];
+
+    let mut valid = true;
+    for (path, validation) in validations {
+        let validation = match validation {
+            Ok(validation) => validation,
+            Err(e) => {
+                error!(path = %path.display(), error = %e, "Failed to open file");
+                valid = false;
+                continue;
+            }
+        };
+        if !validation.is_valid() {
+            error!(
+                path = %path.display(),
+                structural_errors = validation.structural_errors.count,
+                truncated_at = ?validation.truncated_at,
+                out_of_order = validation.out_of_order.count,
+                duplicates = validation.duplicates.count,
+                "File failed validation"
+            );
+            valid = false;
+        }
+        // One line of the validate output
+        let mut summary = serde_json::Map::new();
+        summary.insert("path".to_string(), path.display().to_string().into());
+        summary.insert("valid".to_string(), validation.is_valid().into());
+        if let serde_json::Value::Object(fields) = validation.to_json() {
+            summary.extend(fields);
+        }
+        match serde_json::to_string(&summary) {
+            Ok(line) => println!("{}", line),
+            Err(e) => error!(error = %e, "Failed to write validation summary"),
+        }
+    }
+    if valid {
+        ExitCode::SUCCESS
+    } else {
+        ExitCode::FAILURE
+    }
+}
+
+fn input_encoding(format: InputFormat, mmap: bool) -> InputEncoding {
+    match format {
+        InputFormat::Csv => InputEncoding::Csv,
+        InputFormat::Jsonl => InputEncoding::Jsonl,
+        #[cfg(feature = "proto")]
+        InputFormat::Proto => InputEncoding::Proto,
+        InputFormat::Binary if mmap => InputEncoding::Mmap,
+        InputFormat::Binary => InputEncoding::Buffered,
+    }
+}
+
+struct TranscodeOptions {
+    update_format: UpdateFormat,
+    snapshot_format: SnapshotFormat,
+    encoding: InputEncoding,
+    dedup: bool,
+    checksums: bool,
+}
+
+fn transcode_file<T: FormatInput + 'static>(
+    path: &Path,
+    parser: T::ParserType,
+    out: &Path,
+    options: &TranscodeOptions,
+    write: impl Fn(&mut Transcoder<BufWriter<File>>, T) -> io::Result<()>,
+) -> io::Result<TranscodeStats>
+where
+    T::ParserType: 'static,
+{
+    let records = open_records::<T>(path, parser, options.encoding)?;
+    let mut transcoder = Transcoder::new(BufWriter::new(File::create(out)?))
+        .with_update_format(options.update_format)
+        .with_snapshot_format(options.snapshot_format);
+    if options.dedup {
+        transcoder = transcoder.with_deduplicator(Deduplicator::default());
+    }
+    if options.checksums {
+        let mut checksums_path = out.as_os_str().to_owned();
+        checksums_path.push(".crc32.csv");
+        let checksums = BufWriter::new(File::create(checksums_path)?);
+        transcoder = transcoder.with_checksums(Box::new(checksums));
+    }
+    transcoder.write_header()?;
+    for record in records {
+        write(&mut transcoder, record?)?;
+    }
+    let stats = transcoder.stats;
+    transcoder.finish()?;
+    Ok(stats)
+}
+
+fn transcode_files(
+    path_to_snapshot: &Path,
+    path_to_incremental: &Path,
+    snapshot_out: &Path,
+    incremental_out: &Path,
+    snapshot_parser: OrderBookSnapshotParser,
+    endianness: Endianness,
+    options: &TranscodeOptions,
+) -> ExitCode {
+    let transcoded = [
+        (
+            path_to_snapshot,
+            transcode_file::<OrderBookSnapshot>(
+                path_to_snapshot,
+                snapshot_parser,
+                snapshot_out,
+                options,
+                |transcoder, snapshot| transcoder.write_snapshot(snapshot),
+            ),
+        ),
+        (
+            path_to_incremental,
+            transcode_file::<OrderBookUpdate>(
+                path_to_incremental,
+                OrderBookUpdateParser::new(options.update_format).with_endianness(endianness),
+                incremental_out,
+                options,
+                |transcoder, update| transcoder.write_update(&update),
+            ),
+        ),
+    ];
+    let mut succeeded = true;
+    for (path, stats) in transcoded {
+        match stats {
+            Ok(stats) => println!("{} in {}", stats, path.display()),
+            Err(e) => {
+                error!(path = %path.display(), error = %e, "Failed to transcode file");
+                succeeded = false;
+            }
+        }
+    }
+    if succeeded {
+        ExitCode::SUCCESS
+    } else {
+        ExitCode::FAILURE
+    }
+}
+
+// The given files, with the regular files of each directory or glob pattern, such as
+// updates-*.bin.gz, in name order in its place
+fn incremental_files(paths: &[PathBuf]) -> io::Result<Vec<PathBuf>> {
+    let mut files = Vec::new();
+    for path in paths {
+        if let Some(pattern) = path.to_str()
+            && !path.exists()
+            && pattern.contains(['*', '?', '['])
+        {
+            let mut entries = glob::glob(pattern)
+                .map_err(|e| {
+                    io::Error::new(io::ErrorKind::InvalidInput, format!("{}: {}", pattern, e))
+                })?
+                .collect::<Result<Vec<_>, _>>()
+                .map_err(|e| io::Error::new(e.error().kind(), e.to_string()))?;
+            entries.retain(|entry| entry.is_file());
+            entries.sort();
+            files.extend(entries);
+            continue;
+        }
+        if !path.is_dir() {
+            files.push(path.clone());
+            continue;
+        }
+        let mut entries = fs::read_dir(path)
+            .and_then(|entries| {
+                entries
+                    .map(|entry| entry.map(|entry| entry.path()))
+                    .collect::<io::Result<Vec<_>>>()
+            })
+            .map_err(|e| io::Error::new(e.kind(), format!("{}: {}", path.display(), e)))?;
+        entries.retain(|entry| entry.is_file());
+        entries.sort();
+        files.extend(entries);
+    }
+    Ok(files)
+}
+
+// The final books of a replay on worker threads, without the pending updates
+fn apply_order_book_records_sharded(
+    path_to_snapshot: &Path,
+    path_to_incremental: &Path,
+    threads: usize,
+    update_format: UpdateFormat,
+    endianness: Endianness,
+    verbose: bool,
+) -> Option<OrderBookManager> {
+    let mut manager = ShardedManager::new(threads, update_format, endianness);
+    let inputs = [(path_to_snapshot, false), (path_to_incremental, true)];
+    for (path, is_incremental) in inputs {
+        let _file_span = info_span!("dispatch_file", path = %path.display()).entered();
+        let mut reader = match open_file(path).and_then(strip_file_header) {
+            Ok((_, reader)) => BufReader::new(reader),
+            Err(e) => {
+                error!(path = %path.display(), error = %e, "Failed to open file");
+                return None;
+            }
+        };
+        let dispatched = if is_incremental {
+            manager.dispatch_updates(&mut reader)
+        } else {
+            manager.dispatch_snapshots(&mut reader)
+        };
+        // The records before the corrupted one are applied as in a single-threaded replay
+        if let Err(e) = dispatched {
+            error!(
+                path = %path.display(),
+                error = ?e,
+                "Failed to read next record, the file is corrupted"
+            );
+        }
+    }
+
+    let replay = manager.finish();
+    if verbose {
+        for (shard, stats) in replay.stats.iter().enumerate() {
+            println!("Shard {}: {}", shard, stats);
+        }
+    }
+    Some(replay.into_manager())
+}
+
+// Flags a replay on worker threads honors. The shards apply the records without the
+// settings, listeners, outputs and error reporting of the manager on the main thread, so
+// any other flag given with --threads is refused rather than ignored.
+const SHARDED_FLAGS: &[&str] = &[
+    "path-to-snapshot",
+    "path-to-incremental",
+    "threads",
+    "format",
+    "update-format",
+    "endianness",
+    "verbose",
+    "log-level",
+    "log-format",
+    "verify-key",
+    "print-securities",
+    "metrics",
+    "ladder",
+    "ladder-depth",
+    "ladder-layout",
+    "ladder-align",
+    "cumulative",
+    "ladder-bars",
+    "color",
+    "snapshots-out",
+    "write-header",
+    "signing-key",
+];
+
+// The first flag of the command line a sharded replay doesn't honor
+fn unsupported_sharded_flag(matches: &ArgMatches) -> Option<String> {
+    Args::command()
+        .get_arguments()
+        .map(|arg| arg.get_id())
+        // Handled by clap before the matches are built
+        .filter(|id| !matches!(*id, "help" | "version"))
+        .find(|id| {
+            matches.value_source(id) == Some(ValueSource::CommandLine)
+                && !SHARDED_FLAGS.contains(id)
+        })
+        .map(str::to_string)
+}
+
+// Creates the CSV file of a *-out flag, logging the failure
+fn open_csv_sink(path: &Path) -> Option<CsvSink<BufWriter<File>>> {
+    match File::create(path) {
+        Ok(file) => Some(CsvSink::new(BufWriter::new(file))),
+        Err(e) => {
+            error!(path = %path.display(), error = %e, "Failed to create file");
+            None
+        }
+    }
+}
+
+// Writes the rows of a *-out flag to its CSV file as they come. A failed write is
+// logged, the replay goes on without the rows.
+fn csv_rows(path: &Path, rows: &'static str) -> Option<impl FnMut(OutputEvent<'_>) + use<>> {
+    let mut sink = open_csv_sink(path)?;
+    let path = path.to_path_buf();
+    Some(move |event: OutputEvent<'_>| {
+        let written = sink.write_event(event).and_then(|_| sink.flush());
+        if let Err(e) = written {
+            error!(path = %path.display(), error = %e, "Failed to write {}", rows);
+        }
+    })
+}
+
 fn main() -> ExitCode {
-    let args = Args::parse();
+    let matches = Args::command().get_matches();
+    let args = Args::from_arg_matches(&matches).unwrap_or_else(|e| e.exit());
+    init_logging(args.log_level, args.log_format);
+
+    match &args.command {
+        Some(Command::Completions { shell }) => {
+            let mut command = Args::command();
+            let name = command.get_name().to_string();
+            generate(*shell, &mut command, name, &mut io::stdout());
+            return ExitCode::SUCCESS;
+        }
+        Some(Command::Keygen {
+            private_key,
+            public_key,
+        }) => {
+            if let Err(e) = generate_key(private_key, public_key) {
+                error!(error = %e, "Failed to generate keys");
+                return ExitCode::FAILURE;
+            }
+            return ExitCode::SUCCESS;
+        }
+        Some(Command::Validate {
+            path_to_snapshot,
+            path_to_incremental,
+            update_format,
+            endianness,
+            snapshot_format,
+            snapshot_depth,
+            format,
+        }) => {
+            let Some(snapshot_parser) =
+                snapshot_parser(*endianness, *snapshot_format, *snapshot_depth)
+            else {
+                return ExitCode::FAILURE;
+            };
+            return validate_files(
+                path_to_snapshot,
+                path_to_incremental,
+                snapshot_parser,
+                *update_format,
+                *endianness,
+                *format,
+            );
+        }
+        Some(Command::Transcode {
+            path_to_snapshot,
+            path_to_incremental,
+            snapshot_out,
+            incremental_out,
+            update_format,
+            endianness,
+            snapshot_format,
+            snapshot_format_out,
+            snapshot_depth,
+            format,
+            dedup,
+            checksums,
+        }) => {
+            let Some(snapshot_parser) =
+                snapshot_parser(*endianness, *snapshot_format, *snapshot_depth)
+            else {
+                return ExitCode::FAILURE;
+            };
+            let options = TranscodeOptions {
+                update_format: *update_format,
+                snapshot_format: snapshot_format_out.unwrap_or(*snapshot_format),
+                encoding: input_encoding(*format, false),
+                dedup: *dedup,
+                checksums: *checksums,
+            };
+            return transcode_files(
+                path_to_snapshot,
+                path_to_incremental,
+                snapshot_out,
+                incremental_out,
+                snapshot_parser,
+                *endianness,
+                &options,
+            );
+        }
+        None => {}
+    }
+    // Required by clap without a subcommand
+    let Some(path_to_snapshot) = &args.path_to_snapshot else {
+        unreachable!("Missing input files")
+    };
+    let incremental_files = match incremental_files(&args.path_to_incremental) {
+        Ok(files) if !files.is_empty() => files,
+        Ok(_) => {
+            error!("No incremental files found");
+            return ExitCode::FAILURE;
+        }
+        Err(e) => {
+            error!(error = %e, "Failed to list incremental files");
+            return ExitCode::FAILURE;
+        }
+    };
+    let path_to_incremental = incremental_files[0].as_path();
+    let merge_incremental = !args.follow && incremental_files.len() > 1;
+
+    // A followed directory is read file after file, as the capture writes them
+    if args.follow
+        && (args.path_to_incremental.len() != 1
+            || is_stdin(&args.path_to_incremental[0])
+            || args.mmap
+            || args.pre_scan)
+    {
+        error!(
+            "--follow needs a single incremental file or directory, without --mmap and --pre-scan"
+        );
+        return ExitCode::FAILURE;
+    }
+
+    // The standard input can only be read once, from the start
+    let stdin_inputs = iter::once(path_to_snapshot.as_path())
+        .chain(incremental_files.iter().map(PathBuf::as_path))
+        .filter(|path| is_stdin(path))
+        .count();
+    if stdin_inputs > 1
+        || (stdin_inputs == 1
+            && (args.verbose || args.mmap || args.pre_scan || args.verify_key.is_some()))
+    {
+        error!(
+            "Only one input can be read from -, without --verbose, --mmap, --pre-scan and --verify-key"
+        );
+        return ExitCode::FAILURE;
+    }
+
+    if let Some(key_path) = &args.verify_key {
+        let verified =
+            read_verifying_key(key_path).and_then(|key| verify_file(path_to_snapshot, &key));
+        if let Err(e) = verified {
+            error!(
+                path = %path_to_snapshot.display(),
+                error = %e,
+                "Failed to verify snapshot file"
+            );
+            return ExitCode::FAILURE;
+        }
+    }
+
+    let signing_key = match args.signing_key.as_deref().map(read_signing_key) {
+        Some(Ok(key)) => Some(key),
+        Some(Err(e)) => {
+            error!(error = %e, "Failed to read signing key");
+            return ExitCode::FAILURE;
+        }
+        None => None,
+    };
+
+    // The other formats are parsed record by record from a stream, so only binary files can
+    // be memory mapped, pre-scanned or split across threads
+    if args.format != InputFormat::Binary && (args.mmap || args.pre_scan || args.threads.is_some())
+    {
+        error!(
+            format = ?args.format,
+            "--mmap, --pre-scan and --threads need binary input files"
+        );
+        return ExitCode::FAILURE;
+    }
+
+    // The files are merged from frames read in parallel, which needs the binary layout and
+    // takes the place of the single-file readers
+    if merge_incremental
+        && (args.format != InputFormat::Binary
+            || args.mmap
+            || args.pre_scan
+            || args.threads.is_some())
+    {
+        error!(
+            files = incremental_files.len(),
+            "Several incremental files need binary input without --mmap, --pre-scan and --threads"
+        );
+        return ExitCode::FAILURE;
+    }
+
+    let encoding = input_encoding(args.format, args.mmap);
+
+    let security_filter = match &args.securities {
+        Some(security_ids) => SecurityFilter::only(security_ids.iter().copied()),
+        None => SecurityFilter::default(),
+    };
+    let time_window = match TimeWindow::new(args.from, args.to) {
+        Ok(time_window) => time_window,
+        Err(e) => {
+            error!(error = %e, "Invalid time window");
+            return ExitCode::FAILURE;
+        }
+    };
+    let Some(snapshot_parser) =
+        snapshot_parser(args.endianness, args.snapshot_format, args.snapshot_depth)
+    else {
+        return ExitCode::FAILURE;
+    };
+    let snapshot_parser = || {
+        snapshot_parser
+            .clone()
+            .with_security_filter(security_filter.clone())
+            .with_time_window(time_window)
+    };
 
     if args.verbose {
-        print_records_from_file::<OrderBookSnapshot>(&args.path_to_snapshot);
-        print_records_from_file::<OrderBookUpdate>(&args.path_to_incremental);
+        print_records_from_file::<OrderBookSnapshot>(path_to_snapshot, snapshot_parser(), encoding);
+        for path in &incremental_files {
+            print_records_from_file::<OrderBookUpdate>(
+                path,
+                OrderBookUpdateParser::new(args.update_format)
+                    .with_endianness(args.endianness)
+                    .with_security_filter(security_filter.clone())
+                    .with_time_window(time_window)
+                    .with_max_held_levels(args.max_held_levels),
+                encoding,
+            );
+        }
+    }
+
+    if let Some(speed) = args.speed
+        && !(speed > 0.0 && speed.is_finite())
+    {
+        error!(speed, "Replay speed must be a positive number");
+        return ExitCode::FAILURE;
+    }
+
+    if args.threads == Some(0) {
+        error!("Number of threads must be positive");
+        return ExitCode::FAILURE;
+    }
+    if args.threads.is_some()
+        && let Some(flag) = unsupported_sharded_flag(&matches)
+    {
+        error!(
+            flag,
+            "--threads doesn't support the flag, the records would skip it"
+        );
+        return ExitCode::FAILURE;
+    }
+
+    if let Some(max_price_deviation) = args.max_price_deviation
+        && max_price_deviation <= Decimal::ZERO
+    {
+        error!(%max_price_deviation, "Maximum price deviation must be positive");
+        return ExitCode::FAILURE;
+    }
+
+    if args.max_depth == Some(0) {
+        error!("Maximum depth must be positive");
+        return ExitCode::FAILURE;
     }
 
+    let seq_range = match SeqRange::new(args.start_seq, args.end_seq) {
+        Ok(seq_range) => seq_range,
+        Err(e) => {
+            error!(error = %e, "Invalid sequence range");
+            return ExitCode::FAILURE;
+        }
+    };
+    let replay_options = ReplayOptions {
+        encoding,
+        speed: args.speed,
+        dedup: args.dedup,
+        as_of: args.as_of,
+        seq_range,
+        self_check: args.self_check,
+        frames: args.rejects_out.is_some(),
+    };
     let mut order_book_manager = OrderBookManager::default();
+    order_book_manager.set_level_policy(args.level_policy);
+    order_book_manager.set_overflow_policy(args.pending_overflow);
+    order_book_manager.set_max_depth(args.max_depth);
+    order_book_manager.set_max_price_deviation(args.max_price_deviation);
+    order_book_manager.set_allow_crossed(args.allow_crossed);
+    order_book_manager.set_timestamp_policy(args.timestamp_policy);
+    order_book_manager.set_snapshot_depth(args.snapshot_depth);
+    order_book_manager.set_snapshot_format(args.snapshot_format);
+    if let Some(path) = &args.reference_data {
+        match SecurityRegistry::load(path) {
+            Ok(registry) => order_book_manager.set_security_registry(registry),
+            Err(e) => {
+                error!(path = %path.display(), error = %e, "Failed to read reference data");
+                return ExitCode::FAILURE;
+            }
+        }
+    }
+    for config in &args.level_ttl {
+        order_book_manager.set_level_ttl(config.security_id, config.ttl_millis);
+    }
+    if let Some(max_gap) = args.max_gap {
+        order_book_manager.set_recovery_hook(
+            max_gap,
+            Box::new(|security_id, gap_info: &GapInfo| {
+                warn!(
+                    security_id,
+                    from_seq_no = gap_info.expected_seq_no,
+                    to_seq_no = gap_info.received_seq_no - 1,
+                    "Missing updates, a new snapshot is needed"
+                );
+            }),
+        );
+    }
+    if !args.level_ttl.is_empty() {
+        order_book_manager.add_listener(Box::new(ExpiryPrinter));
+    }
+    if let Some(pending_ttl) = args.pending_ttl {
+        order_book_manager.set_pending_ttl(pending_ttl, Box::new(SystemClock));
+        order_book_manager.add_listener(Box::new(PendingExpiryWarner));
+    }
+    if args.timestamp_policy == TimestampPolicy::Warn {
+        order_book_manager.add_listener(Box::new(TimestampWarner));
+    }
+    let mut update_parser = OrderBookUpdateParser::new(args.update_format)
+        .with_endianness(args.endianness)
+        .with_security_filter(security_filter.clone())
+        .with_time_window(time_window)
+        .with_max_held_levels(args.max_held_levels);
 
-    // Process snapshot file
-    if !apply_order_book_records_from_file::<OrderBookSnapshot>(
-        &args.path_to_snapshot,
-        &mut order_book_manager,
-    ) {
+    let ladder_format = LadderFormat {
+        depth: args.ladder_depth,
+        layout: args.ladder_layout,
+        alignment: args.ladder_align,
+        cumulative: args.cumulative,
+        bars: args.ladder_bars,
+        color: args.color.enabled(),
+    };
+    let mut stdout_sink = TextSink::stdout();
+    let mut error_sinks: Vec<Box<dyn ErrorSink>> = vec![Box::new(LogErrorSink)];
+    if let Some(path) = &args.errors_out {
+        match File::create(path) {
+            Ok(file) => error_sinks.push(Box::new(JsonErrorSink::new(BufWriter::new(file)))),
+            Err(e) => {
+                error!(path = %path.display(), error = %e, "Failed to create file");
+                return ExitCode::FAILURE;
+            }
+        }
+    }
+    if args.ladder {
+        stdout_sink = stdout_sink.with_ladder(ladder_format);
+    }
+
+    let mut servers = Vec::new();
+    if let Some(address) = &args.serve {
+        let (http, address) = match address.strip_prefix("http://") {
+            Some(address) => (true, address),
+            None => (false, address.as_str()),
+        };
+        if http && cfg!(not(feature = "http")) {
+            error!("Serving HTTP requires building with the http feature");
+            return ExitCode::FAILURE;
+        }
+        match TcpListener::bind(address) {
+            #[cfg(feature = "http")]
+            Ok(listener) if http => {
+                info!(address = %address, "Answering HTTP requests");
+                let books = HttpBooks::default();
+                order_book_manager.add_listener(Box::new(books.clone()));
+                servers.push(thread::spawn(move || serve_http(listener, books)));
+            }
+            Ok(listener) => {
+                info!(address = %address, "Answering book queries");
+                let books = SharedBooks::default();
+                order_book_manager.add_listener(Box::new(books.clone()));
+                servers.push(thread::spawn(move || serve(listener, books, ladder_format)));
+            }
+            Err(e) => {
+                error!(address = %address, error = %e, "Failed to listen for book queries");
+                return ExitCode::FAILURE;
+            }
+        }
+    }
+    #[cfg(feature = "grpc")]
+    if let Some(address) = &args.grpc {
+        match TcpListener::bind(address) {
+            Ok(listener) => {
+                info!(address = %address, "Answering gRPC book requests");
+                let books = GrpcBooks::default();
+                let service = books.service();
+                order_book_manager.add_listener(Box::new(books));
+                servers.push(thread::spawn(move || serve_grpc(listener, service)));
+            }
+            Err(e) => {
+                error!(address = %address, error = %e, "Failed to listen for gRPC requests");
+                return ExitCode::FAILURE;
+            }
+        }
+    }
+    #[cfg(feature = "redis")]
+    let redis_publisher = match &args.redis {
+        Some(address) => match RedisPublisher::connect(address) {
+            Ok(publisher) => {
+                info!(address = %address, "Publishing top of book changes to Redis");
+                let publisher = Rc::new(RefCell::new(
+                    publisher.with_channel_prefix(&args.redis_channel_prefix),
+                ));
+                order_book_manager.add_listener(Box::new(publisher.clone()));
+                Some(publisher)
+            }
+            Err(e) => {
+                error!(address = %address, error = %e, "Failed to connect to Redis");
+                return ExitCode::FAILURE;
+            }
+        },
+        None => None,
+    };
+
+    let debug_events_sink = match &args.debug_events {
+        Some(path) => match File::create(path) {
+            Ok(file) => {
+                let sink = Rc::new(RefCell::new(TextSink::new(BufWriter::new(file))));
+                let event_sink = sink.clone();
+                order_book_manager.set_event_sink(Box::new(move |event: &BookEvent| {
+                    // Errors are reported when the sink is closed
+                    let _ = event_sink
+                        .borrow_mut()
+                        .write_event(OutputEvent::Book(event));
+                }));
+                Some((path, sink))
+            }
+            Err(e) => {
+                error!(path = %path.display(), error = %e, "Failed to create file");
+                return ExitCode::FAILURE;
+            }
+        },
+        None => None,
+    };
+
+    if args.pre_scan {
+        let capacity_hints = open_file(path_to_incremental)
+            .and_then(strip_file_header)
+            .map_err(ParserError::Io)
+            .and_then(|(_, reader)| {
+                pre_scan_updates(
+                    &mut BufReader::new(reader),
+                    args.update_format,
+                    args.endianness,
+                )
+            });
+        match capacity_hints {
+            Ok(capacity_hints) => {
+                update_parser =
+                    OrderBookUpdateParser::with_capacity_hints(args.update_format, &capacity_hints)
+                        .with_endianness(args.endianness)
+                        .with_security_filter(security_filter.clone())
+                        .with_time_window(time_window)
+                        .with_max_held_levels(args.max_held_levels);
+                order_book_manager.set_capacity_hints(capacity_hints);
+            }
+            Err(e) => {
+                // The replay reports the problem, it just runs without the hints
+                warn!(
+                    path = %path_to_incremental.display(),
+                    error = ?e,
+                    "Failed to pre-scan file"
+                );
+            }
+        }
+    }
+
+    let candle_aggregator = match &args.candles_out {
+        Some(path) => {
+            let Some(mut write) = csv_rows(path, "candles") else {
+                return ExitCode::FAILURE;
+            };
+            let aggregator = Rc::new(RefCell::new(CandleAggregator::new(
+                args.candle_interval,
+                args.candle_spread,
+                Box::new(move |candle: &Candle| write(OutputEvent::Candle(candle))),
+            )));
+            let hook_aggregator = aggregator.clone();
+            order_book_manager.add_apply_hook(Box::new(move |book: &OrderBook| {
+                hook_aggregator.borrow_mut().observe(book)
+            }));
+            Some(aggregator)
+        }
+        None => None,
+    };
+
+    if let Some(path) = &args.fair_value_out {
+        let Some(mut write) = csv_rows(path, "fair values") else {
+            return ExitCode::FAILURE;
+        };
+        let pricer = Pricer::new(
+            &args.fair_value,
+            Box::new(move |point: &FairValuePoint| write(OutputEvent::FairValue(point))),
+        );
+        order_book_manager.add_listener(Box::new(pricer));
+    }
+
+    if let Some(path) = &args.deltas_out {
+        let Some(mut write) = csv_rows(path, "deltas") else {
+            return ExitCode::FAILURE;
+        };
+        let publisher = DeltaPublisher::new(Box::new(move |diff: &BookDiff| {
+            write(OutputEvent::Delta(diff))
+        }));
+        order_book_manager.add_listener(Box::new(publisher));
+    }
+
+    if let Some(path) = &args.midprice_out {
+        let Some(mut write) = csv_rows(path, "mid prices") else {
+            return ExitCode::FAILURE;
+        };
+        let series = MidPriceSeries::new(Box::new(move |point: &MidPricePoint| {
+            write(OutputEvent::MidPrice(point))
+        }));
+        order_book_manager.add_listener(Box::new(series));
+    }
+
+    if let Some(path) = &args.ofi_out {
+        let Some(mut write) = csv_rows(path, "order flow") else {
+            return ExitCode::FAILURE;
+        };
+        let tracker = OrderFlowTracker::new(Box::new(move |imbalance: &OrderFlowImbalance| {
+            write(OutputEvent::OrderFlow(imbalance))
+        }));
+        order_book_manager.add_listener(Box::new(tracker));
+    }
+
+    if let Some(path) = &args.periodic_snapshots_out {
+        let mut writer = match File::create(path) {
+            Ok(file) => BufWriter::new(file),
+            Err(e) => {
+                error!(path = %path.display(), error = %e, "Failed to create file");
+                return ExitCode::FAILURE;
+            }
+        };
+        let (path, format) = (path.clone(), args.snapshot_format);
+        order_book_manager.set_periodic_snapshots(
+            args.snapshot_every,
+            Box::new(move |snapshot: &OrderBookSnapshot| {
+                let written = snapshot
+                    .write(&mut writer, format)
+                    .and_then(|_| writer.flush());
+                if let Err(e) = written {
+                    error!(path = %path.display(), error = %e, "Failed to write periodic snapshots");
+                }
+            }),
+        );
+    }
+
+    let journal = match &args.journal_out {
+        Some(path) => match JournalWriter::open(path) {
+            Ok(journal) => {
+                let journal = journal.with_checksums(args.journal_checksum_every);
+                let journal = Rc::new(RefCell::new(journal));
+                let hook_journal = journal.clone();
+                order_book_manager.add_record_hook(Box::new(move |record: &AppliedRecord| {
+                    hook_journal.borrow_mut().write_record(record)
+                }));
+                Some((path, journal))
+            }
+            Err(e) => {
+                error!(path = %path.display(), error = %e, "Failed to open journal");
+                return ExitCode::FAILURE;
+            }
+        },
+        None => None,
+    };
+
+    let spread_stats = args.spread_stats.then(|| {
+        let spread_stats = Rc::new(RefCell::new(SpreadStats::new()));
+        order_book_manager.add_listener(Box::new(spread_stats.clone()));
+        spread_stats
+    });
+
+    let incremental_source = incremental_files
+        .iter()
+        .map(|path| path.display().to_string())
+        .collect::<Vec<_>>()
+        .join(", ");
+    let report = args.report_out.as_ref().map(|_| {
+        let report = Rc::new(RefCell::new(ReplayReport::new(&format!(
+            "Replay of {} and {}",
+            path_to_snapshot.display(),
+            incremental_source
+        ))));
+        order_book_manager.add_listener(Box::new(report.clone()));
+        report
+    });
+
+    let mut analytics = Analytics {
+        trade_inference: args.infer_trades.then(TradeInference::new),
+        latency_stats: args.latency_out.as_ref().map(|_| LatencyStats::new()),
+        checkpoints: None,
+        journal: journal.as_ref().map(|(_, journal)| journal.clone()),
+        rejects: None,
+        strict: args.strict.then(|| StrictFailures::new(args.max_failures)),
+    };
+    if let Some(path) = &args.rejects_out {
+        match File::create(path) {
+            Ok(file) => analytics.rejects = Some(RejectLog::new(BufWriter::new(file))),
+            Err(e) => {
+                error!(path = %path.display(), error = %e, "Failed to create file");
+                return ExitCode::FAILURE;
+            }
+        }
+    }
+
+    if let Some(path) = &args.journal_in {
+        let replayed = open_file(path)
+            .map_err(ParserError::Io)
+            .and_then(|reader| replay_journal(BufReader::new(reader), &mut order_book_manager));
+        match replayed {
+            Ok(stats) => {
+                if args.verbose {
+                    println!(
+                        "Replayed {} records from {}, {} checksums verified",
+                        stats.records,
+                        path.display(),
+                        stats.verified_checksums
+                    );
+                }
+            }
+            Err(e) => {
+                error!(path = %path.display(), error = ?e, "Failed to replay journal");
+                return ExitCode::FAILURE;
+            }
+        }
+    }
+
+    if let Some(path) = &args.checkpoint_in {
+        let found = open_file(path)
+            .map_err(ParserError::Io)
+            .and_then(|reader| find_checkpoint(&mut BufReader::new(reader), args.as_of));
+        match found {
+            Ok(Some(checkpoint)) => {
+                if args.verbose {
+                    println!(
+                        "Restored {} books at {} from {}",
+                        checkpoint.books.len(),
+                        checkpoint.data_time,
+                        path.display()
+                    );
+                }
+                order_book_manager.restore_books(checkpoint.books);
+            }
+            Ok(None) => warn!(path = %path.display(), "No checkpoint to restore"),
+            Err(e) => {
+                error!(path = %path.display(), error = ?e, "Failed to read checkpoints");
+                return ExitCode::FAILURE;
+            }
+        }
+    }
+
+    if let Some(path) = &args.checkpoint_out {
+        match File::create(path) {
+            Ok(file) => {
+                analytics.checkpoints = Some(CheckpointWriter::new(
+                    BufWriter::new(file),
+                    args.checkpoint_every,
+                ))
+            }
+            Err(e) => {
+                error!(path = %path.display(), error = %e, "Failed to create checkpoint file");
+                return ExitCode::FAILURE;
+            }
+        }
+    }
+
+    if let Some(threads) = args.threads {
+        match apply_order_book_records_sharded(
+            path_to_snapshot,
+            path_to_incremental,
+            threads,
+            args.update_format,
+            args.endianness,
+            args.verbose,
+        ) {
+            Some(manager) => order_book_manager = manager,
+            None => return ExitCode::FAILURE,
+        }
+    } else {
+        // Process snapshot file
+        if !apply_order_book_records_from_file::<OrderBookSnapshot>(
+            path_to_snapshot,
+            snapshot_parser(),
+            &mut order_book_manager,
+            &mut analytics,
+            &mut stdout_sink,
+            &mut error_sinks,
+            ReplayOptions {
+                seq_range: SeqRange::default(),
+                ..replay_options
+            },
+        ) {
+            return ExitCode::FAILURE;
+        }
+
+        if let Some(path) = &args.pending_in {
+            let restored = open_file(path)
+                .map_err(|e| e.to_string())
+                .and_then(|reader| {
+                    order_book_manager
+                        .restore_pending_updates(&mut BufReader::new(reader), args.update_format)
+                        .map_err(|e| format!("{:?}", e))
+                });
+            match restored {
+                Ok(count) => {
+                    if args.verbose {
+                        println!("Restored {} pending updates from {}", count, path.display());
+                    }
+                }
+                Err(e) => {
+                    error!(
+                        path = %path.display(),
+                        error = %e,
+                        "Failed to restore pending updates"
+                    );
+                    return ExitCode::FAILURE;
+                }
+            }
+        }
+
+        // Process incremental files
+        let applied = if merge_incremental {
+            match MergedUpdateFiles::open(&incremental_files, args.update_format, args.endianness) {
+                Ok(records) => {
+                    let records = records
+                        .with_security_filter(security_filter.clone())
+                        .with_time_window(time_window)
+                        .with_max_held_levels(args.max_held_levels);
+                    let frame = replay_options.frames.then(LastFrame::default);
+                    let records = match frame.clone() {
+                        Some(frame) => records.with_frames(frame),
+                        None => records,
+                    };
+                    apply_order_book_records::<OrderBookUpdate>(
+                        (Box::new(records), frame),
+                        &incremental_source,
+                        &mut order_book_manager,
+                        &mut analytics,
+                        &mut stdout_sink,
+                        &mut error_sinks,
+                        replay_options,
+                    )
+                }
+                Err(e) => {
+                    error!(error = %e, "Failed to start reading incremental files");
+                    false
+                }
+            }
+        } else if args.follow {
+            let path = &args.path_to_incremental[0];
+            let records = TailReader::open(path)
+                .map(|reader| match args.follow_timeout {
+                    Some(seconds) => reader.with_idle_timeout(Duration::from_secs(seconds)),
+                    None => reader,
+                })
+                .and_then(|reader| {
+                    framed_records_from_reader::<OrderBookUpdate>(
+                        Box::new(reader),
+                        update_parser,
+                        encoding,
+                        replay_options.frames,
+                    )
+                });
+            match records {
+                Ok(records) => apply_order_book_records::<OrderBookUpdate>(
+                    records,
+                    &path.display().to_string(),
+                    &mut order_book_manager,
+                    &mut analytics,
+                    &mut stdout_sink,
+                    &mut error_sinks,
+                    replay_options,
+                ),
+                Err(e) => {
+                    error!(path = %path.display(), error = %e, "Failed to follow incremental files");
+                    false
+                }
+            }
+        } else {
+            apply_order_book_records_from_file::<OrderBookUpdate>(
+                path_to_incremental,
+                update_parser,
+                &mut order_book_manager,
+                &mut analytics,
+                &mut stdout_sink,
+                &mut error_sinks,
+                replay_options,
+            )
+        };
+        if !applied {
+            return ExitCode::FAILURE;
+        }
+    }
+
+    if let Some(candle_aggregator) = &candle_aggregator {
+        candle_aggregator.borrow_mut().flush();
+    }
+
+    if let Err(e) = error_sinks.flush() {
+        error!(error = %e, "Failed to write errors");
         return ExitCode::FAILURE;
     }
+    if let Some(rejects) = analytics.rejects.as_mut() {
+        if let Err(e) = rejects.flush() {
+            error!(error = %e, "Failed to write rejected records");
+            return ExitCode::FAILURE;
+        }
+        if args.verbose {
+            println!(
+                "Rejected {} records, buffered {} more",
+                rejects.written() - rejects.buffered(),
+                rejects.buffered()
+            );
+        }
+    }
+
+    if let (Some(path), Some(report)) = (&args.report_out, &report) {
+        for gap_stats in order_book_manager.gap_stats() {
+            report.borrow_mut().set_gap_stats(gap_stats);
+        }
+        let written = File::create(path).and_then(|file| {
+            let mut writer = BufWriter::new(file);
+            report.borrow().write_html(&mut writer)?;
+            writer.flush()
+        });
+        if let Err(e) = written {
+            error!(path = %path.display(), error = %e, "Failed to write report");
+            return ExitCode::FAILURE;
+        }
+    }
 
-    // Process incremental file
-    if !apply_order_book_records_from_file::<OrderBookUpdate>(
-        &args.path_to_incremental,
-        &mut order_book_manager,
-    ) {
+    if let Some((path, sink)) = &debug_events_sink
+        && let Err(e) = sink.borrow_mut().close()
+    {
+        error!(path = %path.display(), error = %e, "Failed to write debug events");
         return ExitCode::FAILURE;
     }
 
-    // Print all order books
-    print!("{}", order_book_manager);
+    // Print the order books, all of them unless some are selected
+    let printed_books = || -> Box<dyn Iterator<Item = &BufferedOrderBook>> {
+        match &args.print_securities {
+            Some(security_ids) => Box::new(order_book_manager.filtered_books(security_ids)),
+            None => Box::new(order_book_manager.buffered_order_books.values()),
+        }
+    };
+    let mut printed = printed_books().try_for_each(|buffered_order_book| {
+        stdout_sink.write_book_state(&buffered_order_book.order_book)
+    });
+
+    if let Some(levels) = args.metrics {
+        printed = printed.and_then(|_| {
+            printed_books().try_for_each(|buffered_order_book| {
+                let metrics = BookMetrics::compute(&buffered_order_book.order_book, levels);
+                stdout_sink.write_event(OutputEvent::Metrics(&metrics))
+            })
+        });
+    }
 
+    if let (Some(path), Some(latency_stats)) = (&args.latency_out, &analytics.latency_stats) {
+        let written = File::create(path).and_then(|mut file| latency_stats.write_csv(&mut file));
+        if let Err(e) = written {
+            error!(
+                path = %path.display(),
+                error = %e,
+                "Failed to write latency samples"
+            );
+            return ExitCode::FAILURE;
+        }
+        printed = printed.and_then(|_| {
+            latency_stats.summaries().iter().try_for_each(|summary| {
+                stdout_sink.write_event(OutputEvent::LatencySummary(summary))
+            })
+        });
+    }
+
+    if let Some(spread_stats) = &spread_stats {
+        printed = printed.and_then(|_| {
+            spread_stats
+                .borrow()
+                .summaries()
+                .iter()
+                .filter(|summary| {
+                    args.print_securities
+                        .as_ref()
+                        .is_none_or(|security_ids| security_ids.contains(&summary.security_id))
+                })
+                .try_for_each(|summary| {
+                    stdout_sink.write_event(OutputEvent::SpreadSummary(summary))
+                })
+        });
+    }
+
+    if args.gap_stats {
+        printed = printed.and_then(|_| {
+            printed_books().try_for_each(|buffered_order_book| {
+                stdout_sink.write_event(OutputEvent::GapStats(&buffered_order_book.stats()))
+            })
+        });
+    }
+
+    if let Err(e) = printed.and_then(|_| stdout_sink.close()) {
+        error!(error = %e, "Failed to write to stdout");
+        return ExitCode::FAILURE;
+    }
+
+    if let Some((path, journal)) = &journal {
+        let mut journal = journal.borrow_mut();
+        // The books as the run leaves them
+        journal.write_checksums(&order_book_manager);
+        let written = match journal.take_error() {
+            Some(e) => Err(e),
+            None => journal.flush(),
+        };
+        if let Err(e) = written {
+            error!(path = %path.display(), error = %e, "Failed to write journal");
+            return ExitCode::FAILURE;
+        }
+    }
+
+    if let Some(path) = &args.snapshots_out {
+        let written = File::create(path).and_then(|file| {
+            let mut writer = BufWriter::new(file);
+            if args.write_header {
+                write_file_header(&mut writer)?;
+            }
+            order_book_manager.write_snapshots(&mut writer)?;
+            writer.flush()
+        });
+        let written = match &signing_key {
+            Some(key) => written.and_then(|_| sign_file(path, key)),
+            None => written,
+        };
+        if let Err(e) = written {
+            error!(path = %path.display(), error = %e, "Failed to write snapshots");
+            return ExitCode::FAILURE;
+        }
+    }
+
+    if let Some(path) = &args.pending_out {
+        let written = File::create(path).and_then(|file| {
+            let mut writer = BufWriter::new(file);
+            order_book_manager.write_pending_updates(&mut writer, args.update_format)?;
+            writer.flush()
+        });
+        if let Err(e) = written {
+            error!(
+                path = %path.display(),
+                error = %e,
+                "Failed to write pending updates"
+            );
+            return ExitCode::FAILURE;
+        }
+    }
+
+    #[cfg(feature = "redis")]
+    if let Some(e) = redis_publisher.and_then(|publisher| publisher.borrow_mut().take_error()) {
+        error!(error = %e, "Failed to publish to Redis");
+        return ExitCode::FAILURE;
+    }
+
+    if let Some(strict) = analytics.strict.as_mut() {
+        strict.record_pending(&order_book_manager);
+    }
+
+    // The final books stay available until the process is stopped
+    if !servers.is_empty() {
+        info!("Replay done, still answering book queries");
+    }
+    for server in servers {
+        match server.join() {
+            Ok(Err(e)) => {
+                error!(error = %e, "Failed to answer book queries");
+                return ExitCode::FAILURE;
+            }
+            Ok(Ok(())) => {}
+            Err(_) => return ExitCode::FAILURE,
+        }
+    }
+
+    if let Some(strict) = &analytics.strict
+        && strict.count > 0
+    {
+        strict.print_summary();
+        return ExitCode::from(STRICT_FAILURE_EXIT_CODE);
+    }
     ExitCode::SUCCESS
 }
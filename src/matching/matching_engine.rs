@@ -0,0 +1,464 @@
+use rust_decimal::Decimal;
+use std::collections::{BTreeMap, VecDeque};
+
+use crate::order_book::order_book::{OrderBook, Side};
+use crate::order_book::units::Qty;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OrderSide {
+    Buy,
+    Sell,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OrderType {
+    Limit(Decimal),
+    Market,
+}
+
+#[derive(Debug, Clone)]
+pub struct Order {
+    pub id: u64,
+    pub side: OrderSide,
+    pub order_type: OrderType,
+    pub qty: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Fill {
+    pub price: Decimal,
+    pub qty: u64,
+    // None when the fill was taken from the market liquidity of the book
+    pub maker_order_id: Option<u64>,
+}
+
+#[derive(Debug)]
+pub struct ExecutionReport {
+    pub order_id: u64,
+    pub fills: Vec<Fill>,
+    pub remaining_qty: u64,
+    pub is_resting: bool,
+}
+
+impl ExecutionReport {
+    pub fn filled_qty(&self) -> u64 {
+        self.fills.iter().map(|fill| fill.qty).sum()
+    }
+}
+
+#[derive(Debug)]
+struct RestingOrder {
+    id: u64,
+    qty: u64,
+}
+
+#[derive(Debug, Default)]
+pub struct MatchingEngine {
+    resting_bids: BTreeMap<Decimal, VecDeque<RestingOrder>>,
+    resting_asks: BTreeMap<Decimal, VecDeque<RestingOrder>>,
+}
+
+impl MatchingEngine {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Matches the order against the opposite side of the book and the resting orders of the
+    // engine. Liquidity taken from the book is removed from it, so the book reflects the
+    // simulated executions until the next update overwrites the affected levels.
+    pub fn submit(&mut self, book: &mut OrderBook, order: Order) -> ExecutionReport {
+        let limit_price = match order.order_type {
+            OrderType::Limit(price) => Some(price),
+            OrderType::Market => None,
+        };
+
+        // The side of the book the order takes liquidity from
+        let book_level_side = match order.side {
+            OrderSide::Buy => Side::Ask,
+            OrderSide::Sell => Side::Bid,
+        };
+        let mut fills = Vec::new();
+        let mut remaining_qty = order.qty;
+
+        while remaining_qty > 0 {
+            let (book_side, resting_side) = match order.side {
+                OrderSide::Buy => (&mut book.asks, &mut self.resting_asks),
+                OrderSide::Sell => (&mut book.bids, &mut self.resting_bids),
+            };
+
+            let price = match Self::best_price(order.side, book_side, resting_side) {
+                Some(price) => price,
+                None => break,
+            };
+            if let Some(limit_price) = limit_price
+                && !Self::is_marketable(order.side, price, limit_price)
+            {
+                break;
+            }
+
+            // Market liquidity was in the book before any of our resting orders,
+            // so it has time priority at the same price
+            if let Some(book_qty) = book_side.get_mut(&price) {
+                let fill_qty = remaining_qty.min(book_qty.value());
+                *book_qty -= Qty(fill_qty);
+                remaining_qty -= fill_qty;
+                fills.push(Fill {
+                    price,
+                    qty: fill_qty,
+                    maker_order_id: None,
+                });
+                if book_qty.is_zero() {
+                    book.remove_level(book_level_side, price);
+                }
+            }
+
+            if let Some(queue) = resting_side.get_mut(&price) {
+                while remaining_qty > 0 {
+                    let resting_order = match queue.front_mut() {
+                        Some(resting_order) => resting_order,
+                        None => break,
+                    };
+                    let fill_qty = remaining_qty.min(resting_order.qty);
+                    resting_order.qty -= fill_qty;
+                    remaining_qty -= fill_qty;
+                    fills.push(Fill {
+                        price,
+                        qty: fill_qty,
+                        maker_order_id: Some(resting_order.id),
+                    });
+                    if resting_order.qty == 0 {
+                        queue.pop_front();
+                    }
+                }
+                if queue.is_empty() {
+                    resting_side.remove(&price);
+                }
+            }
+        }
+
+        let is_resting = match limit_price {
+            Some(limit_price) if remaining_qty > 0 => {
+                let resting_side = match order.side {
+                    OrderSide::Buy => &mut self.resting_bids,
+                    OrderSide::Sell => &mut self.resting_asks,
+                };
+                resting_side
+                    .entry(limit_price)
+                    .or_default()
+                    .push_back(RestingOrder {
+                        id: order.id,
+                        qty: remaining_qty,
+                    });
+                true
+            }
+            _ => false,
+        };
+
+        ExecutionReport {
+            order_id: order.id,
+            fills,
+            remaining_qty,
+            is_resting,
+        }
+    }
+
+    pub fn cancel(&mut self, order_id: u64) -> Option<u64> {
+        for resting_side in [&mut self.resting_bids, &mut self.resting_asks] {
+            let mut found = None;
+            for (price, queue) in resting_side.iter_mut() {
+                if let Some(pos) = queue.iter().position(|order| order.id == order_id) {
+                    found = Some((*price, queue.remove(pos).map(|order| order.qty)));
+                    break;
+                }
+            }
+            if let Some((price, qty)) = found {
+                if resting_side
+                    .get(&price)
+                    .is_some_and(|queue| queue.is_empty())
+                {
+                    resting_side.remove(&price);
+                }
+                return qty;
+            }
+        }
+        None
+    }
+
+    pub fn resting_qty(&self, side: OrderSide, price: Decimal) -> u64 {
+        let resting_side = match side {
+            OrderSide::Buy => &self.resting_bids,
+            OrderSide::Sell => &self.resting_asks,
+        };
+        resting_side
+            .get(&price)
+            .map(|queue| queue.iter().map(|order| order.qty).sum())
+            .unwrap_or(0)
+    }
+
+    fn best_price(
+        side: OrderSide,
+        book_side: &BTreeMap<Decimal, Qty>,
+        resting_side: &BTreeMap<Decimal, VecDeque<RestingOrder>>,
+    ) -> Option<Decimal> {
+        match side {
+            OrderSide::Buy => {
+                let book_best = book_side.keys().next().copied();
+                let resting_best = resting_side.keys().next().copied();
+                match (book_best, resting_best) {
+                    (Some(a), Some(b)) => Some(a.min(b)),
+                    (a, b) => a.or(b),
+                }
+            }
+            OrderSide::Sell => {
+                let book_best = book_side.keys().next_back().copied();
+                let resting_best = resting_side.keys().next_back().copied();
+                match (book_best, resting_best) {
+                    (Some(a), Some(b)) => Some(a.max(b)),
+                    (a, b) => a.or(b),
+                }
+            }
+        }
+    }
+
+    fn is_marketable(side: OrderSide, price: Decimal, limit_price: Decimal) -> bool {
+        match side {
+            OrderSide::Buy => price <= limit_price,
+            OrderSide::Sell => price >= limit_price,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parsing::order_book_snapshot::test_support::create_test_snapshot;
+    use crate::parsing::order_book_update::LevelMetadata;
+    use rust_decimal::dec;
+
+    fn create_test_book() -> OrderBook {
+        OrderBook::new(&create_test_snapshot(1001, 100)).unwrap()
+    }
+
+    #[test]
+    fn test_market_order_sweeps_levels() {
+        let mut book = create_test_book();
+        let mut engine = MatchingEngine::new();
+
+        let report = engine.submit(
+            &mut book,
+            Order {
+                id: 1,
+                side: OrderSide::Buy,
+                order_type: OrderType::Market,
+                qty: 20,
+            },
+        );
+
+        assert_eq!(report.remaining_qty, 0);
+        assert!(!report.is_resting);
+        assert_eq!(
+            report.fills,
+            vec![
+                Fill {
+                    price: dec!(101),
+                    qty: 15,
+                    maker_order_id: None,
+                },
+                Fill {
+                    price: dec!(102),
+                    qty: 5,
+                    maker_order_id: None,
+                },
+            ]
+        );
+
+        // The consumed liquidity is removed from the book
+        assert!(!book.asks.contains_key(&dec!(101)));
+        assert_eq!(book.asks.get(&dec!(102)), Some(&Qty(20)));
+    }
+
+    #[test]
+    fn test_fill_removes_level_metadata() {
+        let mut book = create_test_book();
+        book.ask_metadata.insert(
+            dec!(101),
+            LevelMetadata {
+                order_count: 3,
+                action: 0,
+            },
+        );
+        let mut engine = MatchingEngine::new();
+
+        engine.submit(
+            &mut book,
+            Order {
+                id: 1,
+                side: OrderSide::Buy,
+                order_type: OrderType::Market,
+                qty: 15,
+            },
+        );
+
+        assert!(!book.ask_metadata.contains_key(&dec!(101)));
+        assert!(book.validate().is_ok());
+    }
+
+    #[test]
+    fn test_market_order_on_empty_side() {
+        let mut book = create_test_book();
+        book.bids.clear();
+        let mut engine = MatchingEngine::new();
+
+        let report = engine.submit(
+            &mut book,
+            Order {
+                id: 1,
+                side: OrderSide::Sell,
+                order_type: OrderType::Market,
+                qty: 10,
+            },
+        );
+
+        assert!(report.fills.is_empty());
+        assert_eq!(report.remaining_qty, 10);
+        assert!(!report.is_resting);
+    }
+
+    #[test]
+    fn test_limit_order_partial_fill_rests() {
+        let mut book = create_test_book();
+        let mut engine = MatchingEngine::new();
+
+        let report = engine.submit(
+            &mut book,
+            Order {
+                id: 1,
+                side: OrderSide::Sell,
+                order_type: OrderType::Limit(dec!(99)),
+                qty: 40,
+            },
+        );
+
+        // Fills 10 @ 100 and 20 @ 99, then rests the remainder at the limit price
+        assert_eq!(report.filled_qty(), 30);
+        assert_eq!(report.remaining_qty, 10);
+        assert!(report.is_resting);
+        assert_eq!(engine.resting_qty(OrderSide::Sell, dec!(99)), 10);
+        assert_eq!(book.bids.keys().next_back(), Some(&dec!(98)));
+    }
+
+    #[test]
+    fn test_price_time_priority() {
+        let mut book = create_test_book();
+        let mut engine = MatchingEngine::new();
+
+        // Two resting buy orders at the same price, below the market
+        for id in [1, 2] {
+            let report = engine.submit(
+                &mut book,
+                Order {
+                    id,
+                    side: OrderSide::Buy,
+                    order_type: OrderType::Limit(dec!(100.50)),
+                    qty: 5,
+                },
+            );
+            assert!(report.is_resting);
+        }
+
+        // The better priced resting orders are hit before the book level at 100.00,
+        // and the earlier order is filled first
+        let report = engine.submit(
+            &mut book,
+            Order {
+                id: 3,
+                side: OrderSide::Sell,
+                order_type: OrderType::Market,
+                qty: 7,
+            },
+        );
+
+        assert_eq!(
+            report.fills,
+            vec![
+                Fill {
+                    price: dec!(100.50),
+                    qty: 5,
+                    maker_order_id: Some(1),
+                },
+                Fill {
+                    price: dec!(100.50),
+                    qty: 2,
+                    maker_order_id: Some(2),
+                },
+            ]
+        );
+        assert_eq!(engine.resting_qty(OrderSide::Buy, dec!(100.50)), 3);
+        assert_eq!(book.bids.get(&dec!(100)), Some(&Qty(10)));
+    }
+
+    #[test]
+    fn test_book_liquidity_ahead_of_resting_orders() {
+        let mut book = create_test_book();
+        let mut engine = MatchingEngine::new();
+
+        // Join the best bid
+        engine.submit(
+            &mut book,
+            Order {
+                id: 1,
+                side: OrderSide::Buy,
+                order_type: OrderType::Limit(dec!(100)),
+                qty: 5,
+            },
+        );
+
+        let report = engine.submit(
+            &mut book,
+            Order {
+                id: 2,
+                side: OrderSide::Sell,
+                order_type: OrderType::Limit(dec!(100)),
+                qty: 12,
+            },
+        );
+
+        assert_eq!(
+            report.fills,
+            vec![
+                Fill {
+                    price: dec!(100),
+                    qty: 10,
+                    maker_order_id: None,
+                },
+                Fill {
+                    price: dec!(100),
+                    qty: 2,
+                    maker_order_id: Some(1),
+                },
+            ]
+        );
+        assert_eq!(report.remaining_qty, 0);
+        assert_eq!(engine.resting_qty(OrderSide::Buy, dec!(100)), 3);
+    }
+
+    #[test]
+    fn test_cancel_resting_order() {
+        let mut book = create_test_book();
+        let mut engine = MatchingEngine::new();
+
+        engine.submit(
+            &mut book,
+            Order {
+                id: 1,
+                side: OrderSide::Sell,
+                order_type: OrderType::Limit(dec!(110)),
+                qty: 5,
+            },
+        );
+
+        assert_eq!(engine.cancel(1), Some(5));
+        assert_eq!(engine.cancel(1), None);
+        assert_eq!(engine.resting_qty(OrderSide::Sell, dec!(110)), 0);
+    }
+}
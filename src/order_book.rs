@@ -1,5 +1,20 @@
+pub mod admin;
+#[cfg(feature = "async")]
+pub mod async_driver;
+pub mod book_diff;
+pub mod book_side;
 pub mod buffered_order_book;
+pub mod checkpoint;
 pub mod errors;
+pub mod events;
+pub mod journal;
+pub mod level_ttl;
+pub mod listener;
 pub mod manager;
 #[allow(clippy::module_inception)]
 pub mod order_book;
+pub mod price;
+pub mod security_registry;
+pub mod sharded_manager;
+pub mod sorted_vec_side;
+pub mod units;
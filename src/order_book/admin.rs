@@ -0,0 +1,51 @@
+use std::str::FromStr;
+
+// Operator commands, one per line, e.g. `resync 42`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AdminCommand {
+    // Clears the book and waits for the next snapshot
+    Resync(u64),
+}
+
+impl FromStr for AdminCommand {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut words = s.split_whitespace();
+        match (words.next(), words.next(), words.next()) {
+            (Some("resync"), Some(security_id), None) => security_id
+                .parse()
+                .map(AdminCommand::Resync)
+                .map_err(|_| format!("Invalid security id: {}", security_id)),
+            (Some("resync"), _, _) => Err("Usage: resync <security_id>".to_string()),
+            (Some(command), _, _) => Err(format!("Unknown command: {}", command)),
+            (None, _, _) => Err("Empty command".to_string()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_resync() {
+        assert_eq!(
+            "resync 42".parse::<AdminCommand>(),
+            Ok(AdminCommand::Resync(42))
+        );
+        assert_eq!(
+            "  resync\t7 ".parse::<AdminCommand>(),
+            Ok(AdminCommand::Resync(7))
+        );
+    }
+
+    #[test]
+    fn test_parse_invalid_commands() {
+        assert!("resync".parse::<AdminCommand>().is_err());
+        assert!("resync abc".parse::<AdminCommand>().is_err());
+        assert!("resync 1 2".parse::<AdminCommand>().is_err());
+        assert!("restart 1".parse::<AdminCommand>().is_err());
+        assert!("".parse::<AdminCommand>().is_err());
+    }
+}
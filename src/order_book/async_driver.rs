@@ -0,0 +1,132 @@
+use futures_util::stream;
+use futures_util::{Stream, StreamExt};
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::rc::Rc;
+
+use crate::order_book::manager::{Manager, ManagerOutcome};
+use crate::order_book::order_book::OrderBook;
+use crate::parsing::order_book_snapshot::OrderBookSnapshot;
+use crate::parsing::order_book_update::OrderBookUpdate;
+use crate::parsing::parser::ParserError;
+
+#[derive(Debug)]
+pub enum ManagerInput {
+    Snapshot(OrderBookSnapshot),
+    Update(OrderBookUpdate),
+}
+
+impl From<OrderBookSnapshot> for ManagerInput {
+    fn from(snapshot: OrderBookSnapshot) -> Self {
+        ManagerInput::Snapshot(snapshot)
+    }
+}
+
+impl From<OrderBookUpdate> for ManagerInput {
+    fn from(update: OrderBookUpdate) -> Self {
+        ManagerInput::Update(update)
+    }
+}
+
+// Feeds a Manager from an async stream of records, e.g. from an AsyncRecordReader, a
+// UDP socket or a channel. The records are not Send, so it runs on a current-thread
+// runtime or a LocalSet.
+pub struct AsyncDriver {
+    pub manager: Manager,
+    applied: Rc<RefCell<VecDeque<OrderBook>>>,
+}
+
+impl AsyncDriver {
+    pub fn new(mut manager: Manager) -> Self {
+        let applied = Rc::new(RefCell::new(VecDeque::new()));
+        let hook_applied = applied.clone();
+        manager.add_apply_hook(Box::new(move |book: &OrderBook| {
+            hook_applied.borrow_mut().push_back(book.clone())
+        }));
+        Self { manager, applied }
+    }
+
+    pub fn apply(&mut self, input: ManagerInput) -> ManagerOutcome {
+        match input {
+            ManagerInput::Snapshot(snapshot) => self.manager.apply_snapshot(&snapshot),
+            ManagerInput::Update(update) => self.manager.apply_update(update),
+        }
+    }
+
+    // Applies the records as they arrive and yields the state of the book after every
+    // applied record, including the pending updates replayed after it
+    pub fn applied_books<'a, S>(
+        &'a mut self,
+        input: S,
+    ) -> impl Stream<Item = Result<OrderBook, ParserError>> + 'a
+    where
+        S: Stream<Item = Result<ManagerInput, ParserError>> + Unpin + 'a,
+    {
+        stream::unfold((self, input), |(driver, mut input)| async move {
+            loop {
+                let applied = driver.applied.borrow_mut().pop_front();
+                if let Some(book) = applied {
+                    return Some((Ok(book), (driver, input)));
+                }
+                match input.next().await? {
+                    Ok(record) => {
+                        driver.apply(record);
+                    }
+                    Err(e) => return Some((Err(e), (driver, input))),
+                }
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parsing::async_parser::AsyncRecordReader;
+    use crate::parsing::order_book_snapshot::test_support::create_test_snapshot;
+    use crate::parsing::order_book_update::OrderBookUpdateParser;
+
+    fn create_test_update_data(data: &mut Vec<u8>, seq_no: u64, qty: u64) {
+        data.extend_from_slice(&1627846266u64.to_le_bytes()); // timestamp
+        data.extend_from_slice(&seq_no.to_le_bytes()); // seq_no
+        data.extend_from_slice(&1001u64.to_le_bytes()); // security_id
+        data.extend_from_slice(&1u64.to_le_bytes()); // num_updates
+        data.push(0); // side
+        data.extend_from_slice(&100.0f64.to_le_bytes()); // price
+        data.extend_from_slice(&qty.to_le_bytes()); // qty
+    }
+
+    #[tokio::test]
+    async fn test_applied_books() {
+        let mut driver = AsyncDriver::new(Manager::default());
+        driver.apply(create_test_snapshot(1001, 100).into());
+
+        // 103 waits for 102, 99 is old
+        let mut data = Vec::new();
+        create_test_update_data(&mut data, 101, 11);
+        create_test_update_data(&mut data, 103, 13);
+        create_test_update_data(&mut data, 99, 9);
+        create_test_update_data(&mut data, 102, 12);
+        let reader = AsyncRecordReader::new(data.as_slice(), OrderBookUpdateParser::default());
+        let updates = Box::pin(
+            reader
+                .into_stream::<OrderBookUpdate>()
+                .map(|update| update.map(ManagerInput::from)),
+        );
+
+        // The snapshot is yielded first
+        let books: Vec<(u64, u64)> = driver
+            .applied_books(updates)
+            .map(|book| {
+                let book = book.unwrap();
+                (book.seq_no, book.bids[&rust_decimal::dec!(100)].value())
+            })
+            .collect()
+            .await;
+        assert_eq!(books, vec![(100, 10), (101, 11), (102, 12), (103, 13)]);
+        assert_eq!(
+            driver.manager.buffered_order_books[&1001].order_book.seq_no,
+            103
+        );
+    }
+}
@@ -0,0 +1,463 @@
+use num_traits::ToPrimitive;
+use rust_decimal::Decimal;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::Display;
+
+use crate::batched_deque::batched_deque::BatchedDeque;
+use crate::order_book::errors::{Errors, UpdateMessageInfo};
+use crate::order_book::level_ttl::ExpiredLevel;
+use crate::order_book::listener::BookListener;
+use crate::order_book::order_book::{OrderBook, Side};
+use crate::order_book::units::Qty;
+use crate::parsing::order_book_update::Level as UpdateLevel;
+use crate::parsing::order_book_update::LevelMetadata;
+use crate::parsing::order_book_update::OrderBookUpdate;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LevelChange {
+    Added,
+    Removed,
+    Changed,
+}
+
+// New state of one level, qty is 0 for removed levels
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LevelDelta {
+    pub side: Side,
+    pub price: Decimal,
+    pub qty: Qty,
+    pub metadata: Option<LevelMetadata>,
+    pub change: LevelChange,
+}
+
+// L2 delta turning the book at `from_seq_no` into the book at `to_seq_no`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BookDiff {
+    pub security_id: u64,
+    pub from_seq_no: u64,
+    pub to_seq_no: u64,
+    pub timestamp: u64,
+    pub capture_timestamp: Option<u64>,
+    // Bids first, each side in ascending price order
+    pub levels: Vec<LevelDelta>,
+}
+
+impl BookDiff {
+    pub fn between(old: &OrderBook, new: &OrderBook) -> Result<Self, Errors> {
+        if old.security_id != new.security_id {
+            return Err(Errors::SecurityIdMismatch(UpdateMessageInfo::new(
+                new.security_id,
+                new.seq_no,
+            )));
+        }
+        let mut levels = Vec::new();
+        Self::diff_side(
+            Side::Bid,
+            (&old.bids, &old.bid_metadata),
+            (&new.bids, &new.bid_metadata),
+            &mut levels,
+        );
+        Self::diff_side(
+            Side::Ask,
+            (&old.asks, &old.ask_metadata),
+            (&new.asks, &new.ask_metadata),
+            &mut levels,
+        );
+        Ok(Self {
+            security_id: new.security_id,
+            from_seq_no: old.seq_no,
+            to_seq_no: new.seq_no,
+            timestamp: new.timestamp,
+            capture_timestamp: new.capture_timestamp,
+            levels,
+        })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.levels.is_empty()
+    }
+
+    // The diff as an update record. It only follows the old book directly, and can be
+    // applied with OrderBook::apply_update, when the two states are consecutive.
+    pub fn to_update(&self, deque: &BatchedDeque<UpdateLevel>) -> OrderBookUpdate {
+        self.build_update(None, deque)
+    }
+
+    // The diff as a thinned V2 record, applicable whatever the seq_no gap is
+    pub fn to_thinned_update(&self, deque: &BatchedDeque<UpdateLevel>) -> OrderBookUpdate {
+        self.build_update(Some(self.from_seq_no), deque)
+    }
+
+    fn build_update(
+        &self,
+        thinned_from: Option<u64>,
+        deque: &BatchedDeque<UpdateLevel>,
+    ) -> OrderBookUpdate {
+        let levels = self.levels.iter().map(|level| {
+            Ok::<UpdateLevel, ()>(UpdateLevel {
+                side: level.side,
+                price: level.price.to_f64().unwrap_or(0.0),
+                qty: level.qty,
+                metadata: level.metadata,
+            })
+        });
+        OrderBookUpdate {
+            timestamp: self.timestamp,
+            capture_timestamp: self.capture_timestamp,
+            seq_no: self.to_seq_no,
+            security_id: self.security_id,
+            thinned_from,
+            updates: deque
+                .push_back_batch(levels)
+                .expect("Diff levels are always valid"),
+        }
+    }
+
+    fn diff_side(
+        side: Side,
+        (old_levels, old_metadata): (&BTreeMap<Decimal, Qty>, &BTreeMap<Decimal, LevelMetadata>),
+        (new_levels, new_metadata): (&BTreeMap<Decimal, Qty>, &BTreeMap<Decimal, LevelMetadata>),
+        deltas: &mut Vec<LevelDelta>,
+    ) {
+        let mut prices: Vec<&Decimal> = old_levels.keys().chain(new_levels.keys()).collect();
+        prices.sort_unstable();
+        prices.dedup();
+
+        for price in prices {
+            let old = old_levels
+                .get(price)
+                .map(|qty| (*qty, old_metadata.get(price).copied()));
+            let new = new_levels
+                .get(price)
+                .map(|qty| (*qty, new_metadata.get(price).copied()));
+            let (qty, metadata, change) = match (old, new) {
+                (None, Some((qty, metadata))) => (qty, metadata, LevelChange::Added),
+                (Some(_), None) => (Qty::ZERO, None, LevelChange::Removed),
+                (Some(old), Some((qty, metadata))) if old != (qty, metadata) => {
+                    (qty, metadata, LevelChange::Changed)
+                }
+                _ => continue,
+            };
+            deltas.push(LevelDelta {
+                side,
+                price: *price,
+                qty,
+                metadata,
+                change,
+            });
+        }
+    }
+}
+
+impl Display for BookDiff {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "security_id: {} seq_no: {} -> {} timestamp: {}",
+            self.security_id, self.from_seq_no, self.to_seq_no, self.timestamp
+        )?;
+        for level in &self.levels {
+            write!(
+                f,
+                " | {:?} {} x {} {:?}",
+                level.side, level.price, level.qty, level.change
+            )?;
+        }
+        Ok(())
+    }
+}
+
+pub type DeltaCallback = Box<dyn FnMut(&BookDiff)>;
+
+// Emits the levels each applied record changed in its book, after validation and with
+// the buffered updates replayed once their gap closes, so consumers downstream don't
+// have to track the books to tell what changed. Records changing no level emit nothing.
+// The first record of a book emits all its levels as added, from seq_no 0.
+pub struct DeltaPublisher {
+    // The books as last emitted
+    books: HashMap<u64, OrderBook>,
+    on_delta: DeltaCallback,
+}
+
+impl DeltaPublisher {
+    pub fn new(on_delta: DeltaCallback) -> Self {
+        Self {
+            books: HashMap::new(),
+            on_delta,
+        }
+    }
+
+    fn publish(&mut self, book: &OrderBook) {
+        let previous = self
+            .books
+            .remove(&book.security_id)
+            .unwrap_or_else(|| OrderBook::empty(book.security_id, 0, 0));
+        let diff = BookDiff::between(&previous, book).expect("Same security");
+        if !diff.is_empty() {
+            (self.on_delta)(&diff);
+        }
+        self.books.insert(book.security_id, book.clone());
+    }
+}
+
+impl BookListener for DeltaPublisher {
+    fn on_snapshot_applied(&mut self, book: &OrderBook) {
+        self.publish(book);
+    }
+
+    fn on_update_applied(&mut self, book: &OrderBook) {
+        self.publish(book);
+    }
+
+    fn on_levels_expired(&mut self, book: &OrderBook, _expired: &[ExpiredLevel]) {
+        self.publish(book);
+    }
+
+    fn on_book_evicted(&mut self, book: &OrderBook) {
+        self.books.remove(&book.security_id);
+    }
+}
+
+impl OrderBook {
+    // Applies a diff computed against the current state of the book, e.g. a conflated
+    // delta covering several updates
+    pub fn apply_diff(&mut self, diff: &BookDiff) -> Result<(), Errors> {
+        let info = UpdateMessageInfo::new(diff.security_id, diff.to_seq_no);
+        if diff.security_id != self.security_id {
+            return Err(Errors::SecurityIdMismatch(info));
+        }
+        if diff.to_seq_no <= self.seq_no {
+            return Err(Errors::OldSequenceNumber(info));
+        }
+        if diff.from_seq_no != self.seq_no {
+            return Err(Errors::SequenceNumberGap(info));
+        }
+
+        for level in &diff.levels {
+            let (levels, levels_metadata) = match level.side {
+                Side::Bid => (&mut self.bids, &mut self.bid_metadata),
+                Side::Ask => (&mut self.asks, &mut self.ask_metadata),
+            };
+            if level.qty.is_zero() {
+                levels.remove(&level.price);
+            } else {
+                levels.insert(level.price, level.qty);
+            }
+            match level.metadata {
+                Some(metadata) if !level.qty.is_zero() => {
+                    levels_metadata.insert(level.price, metadata)
+                }
+                _ => levels_metadata.remove(&level.price),
+            };
+        }
+
+        self.timestamp = diff.timestamp;
+        self.capture_timestamp = diff.capture_timestamp;
+        self.seq_no = diff.to_seq_no;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::order_book::manager::Manager;
+    use crate::parsing::order_book_snapshot::test_support::create_test_snapshot;
+    use rust_decimal::dec;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    fn create_test_update(
+        security_id: u64,
+        seq_no: u64,
+        levels: Vec<(Side, f64, u64)>,
+    ) -> OrderBookUpdate {
+        let deque = BatchedDeque::new(10);
+        let levels = levels.into_iter().map(|(side, price, qty)| {
+            Ok::<UpdateLevel, ()>(UpdateLevel {
+                side,
+                price,
+                qty: Qty(qty),
+                metadata: None,
+            })
+        });
+
+        OrderBookUpdate {
+            timestamp: 1627846266,
+            capture_timestamp: None,
+            seq_no,
+            security_id,
+            thinned_from: None,
+            updates: deque.push_back_batch(levels).unwrap(),
+        }
+    }
+
+    #[test]
+    fn test_diff_added_removed_changed() {
+        let old = OrderBook::new(&create_test_snapshot(1001, 100)).unwrap();
+        let mut new = old.clone();
+        let update = create_test_update(
+            1001,
+            101,
+            vec![(Side::Bid, 100.50, 5), (Side::Bid, 96.00, 0)],
+        );
+        new.apply_update(&update).unwrap();
+        let update = create_test_update(1001, 102, vec![(Side::Ask, 101.00, 7)]);
+        new.apply_update(&update).unwrap();
+
+        let diff = BookDiff::between(&old, &new).unwrap();
+        assert_eq!((diff.from_seq_no, diff.to_seq_no), (100, 102));
+        assert_eq!(
+            diff.levels,
+            vec![
+                LevelDelta {
+                    side: Side::Bid,
+                    price: dec!(96),
+                    qty: Qty(0),
+                    metadata: None,
+                    change: LevelChange::Removed,
+                },
+                LevelDelta {
+                    side: Side::Bid,
+                    price: dec!(100.5),
+                    qty: Qty(5),
+                    metadata: None,
+                    change: LevelChange::Added,
+                },
+                LevelDelta {
+                    side: Side::Ask,
+                    price: dec!(101),
+                    qty: Qty(7),
+                    metadata: None,
+                    change: LevelChange::Changed,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_apply_diff_reproduces_new_state() {
+        let mut old = OrderBook::new(&create_test_snapshot(1001, 100)).unwrap();
+        let mut new = old.clone();
+        let update = create_test_update(
+            1001,
+            101,
+            vec![(Side::Bid, 100.00, 0), (Side::Ask, 101.50, 3)],
+        );
+        new.apply_update(&update).unwrap();
+        let update = create_test_update(1001, 102, vec![(Side::Ask, 105.00, 0)]);
+        new.apply_update(&update).unwrap();
+
+        let diff = BookDiff::between(&old, &new).unwrap();
+        old.apply_diff(&diff).unwrap();
+        assert_eq!(old.seq_no, 102);
+        assert_eq!(old.bids, new.bids);
+        assert_eq!(old.asks, new.asks);
+        assert!(BookDiff::between(&old, &new).unwrap().is_empty());
+
+        // Not based on the current state anymore
+        assert!(matches!(
+            old.apply_diff(&diff),
+            Err(Errors::OldSequenceNumber(_))
+        ));
+    }
+
+    #[test]
+    fn test_diff_as_update() {
+        let mut old = OrderBook::new(&create_test_snapshot(1001, 100)).unwrap();
+        let mut new = old.clone();
+        let update = create_test_update(
+            1001,
+            101,
+            vec![(Side::Bid, 99.00, 0), (Side::Ask, 101.00, 1)],
+        );
+        new.apply_update(&update).unwrap();
+
+        let deque = BatchedDeque::new(10);
+        let update = BookDiff::between(&old, &new).unwrap().to_update(&deque);
+        old.apply_update(&update).unwrap();
+        assert_eq!(old.bids, new.bids);
+        assert_eq!(old.asks, new.asks);
+    }
+
+    #[test]
+    fn test_diff_as_thinned_update() {
+        let mut old = OrderBook::new(&create_test_snapshot(1001, 100)).unwrap();
+        let mut new = old.clone();
+        let update = create_test_update(1001, 101, vec![(Side::Bid, 99.00, 0)]);
+        new.apply_update(&update).unwrap();
+        let update = create_test_update(1001, 102, vec![(Side::Ask, 101.00, 1)]);
+        new.apply_update(&update).unwrap();
+
+        let deque = BatchedDeque::new(10);
+        let update = BookDiff::between(&old, &new).unwrap().to_update(&deque);
+        assert!(matches!(
+            old.clone().apply_update(&update),
+            Err(Errors::SequenceNumberGap(_))
+        ));
+
+        let update = BookDiff::between(&old, &new)
+            .unwrap()
+            .to_thinned_update(&deque);
+        assert_eq!(update.thinned_from, Some(100));
+        old.apply_update(&update).unwrap();
+        assert_eq!(old.seq_no, 102);
+        assert_eq!(old.bids, new.bids);
+        assert_eq!(old.asks, new.asks);
+    }
+
+    #[test]
+    fn test_diff_security_id_mismatch() {
+        let old = OrderBook::new(&create_test_snapshot(1001, 100)).unwrap();
+        let new = OrderBook::new(&create_test_snapshot(1002, 100)).unwrap();
+
+        assert!(matches!(
+            BookDiff::between(&old, &new),
+            Err(Errors::SecurityIdMismatch(_))
+        ));
+    }
+
+    #[test]
+    fn test_delta_publisher() {
+        let deltas = Rc::new(RefCell::new(Vec::new()));
+        let published = deltas.clone();
+        let mut manager = Manager::default();
+        manager.add_listener(Box::new(DeltaPublisher::new(Box::new(
+            move |diff: &BookDiff| published.borrow_mut().push(diff.clone()),
+        ))));
+
+        manager.apply_snapshot(&create_test_snapshot(1001, 100));
+        // Buffered until 101 closes the gap, then applied after it
+        manager.apply_update(create_test_update(1001, 102, vec![(Side::Ask, 101.00, 5)]));
+        manager.apply_update(create_test_update(1001, 101, vec![(Side::Bid, 99.00, 0)]));
+        // Changes no level
+        manager.apply_update(create_test_update(1001, 103, vec![(Side::Bid, 100.00, 10)]));
+        // Rejected
+        manager.apply_update(create_test_update(
+            1001,
+            104,
+            vec![(Side::Bid, 100.005, 10)],
+        ));
+
+        let deltas = deltas.borrow();
+        assert_eq!(
+            deltas
+                .iter()
+                .map(|diff| (diff.from_seq_no, diff.to_seq_no, diff.levels.len()))
+                .collect::<Vec<_>>(),
+            vec![(0, 100, 10), (100, 101, 1), (101, 102, 1)]
+        );
+        assert_eq!(
+            deltas[1].levels[0],
+            LevelDelta {
+                side: Side::Bid,
+                price: dec!(99.00),
+                qty: Qty(0),
+                metadata: None,
+                change: LevelChange::Removed,
+            }
+        );
+        assert_eq!(deltas[2].levels[0].change, LevelChange::Changed);
+    }
+}
@@ -0,0 +1,132 @@
+use std::collections::BTreeMap;
+use std::collections::btree_map;
+
+use crate::order_book::order_book::Side;
+use crate::order_book::sorted_vec_side::{self, SortedVecSide};
+use crate::order_book::units::Qty;
+
+// Storage of the levels of one side of a book, price to quantity. OrderBook only goes
+// through these methods, so the storage can be swapped without touching how records are
+// applied.
+pub trait BookSide<P>: Default {
+    type Iter<'a>: DoubleEndedIterator<Item = (&'a P, &'a Qty)>
+    where
+        Self: 'a,
+        P: 'a;
+
+    // Returns the previous quantity of the level
+    fn insert(&mut self, price: P, qty: Qty) -> Option<Qty>;
+
+    fn remove(&mut self, price: &P) -> Option<Qty>;
+
+    fn get(&self, price: &P) -> Option<&Qty>;
+
+    fn pop_first(&mut self) -> Option<(P, Qty)>;
+
+    fn pop_last(&mut self) -> Option<(P, Qty)>;
+
+    fn clear(&mut self);
+
+    fn len(&self) -> usize;
+
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    // Lowest price first
+    fn iter(&self) -> Self::Iter<'_>;
+
+    // The highest bid or the lowest ask
+    fn best(&self, side: Side) -> Option<(&P, &Qty)> {
+        match side {
+            Side::Bid => self.iter().next_back(),
+            Side::Ask => self.iter().next(),
+        }
+    }
+
+    // Removes the lowest bid or the highest ask
+    fn pop_worst(&mut self, side: Side) -> Option<(P, Qty)> {
+        match side {
+            Side::Bid => self.pop_first(),
+            Side::Ask => self.pop_last(),
+        }
+    }
+}
+
+impl<P: Ord> BookSide<P> for BTreeMap<P, Qty> {
+    type Iter<'a>
+        = btree_map::Iter<'a, P, Qty>
+    where
+        P: 'a;
+
+    fn insert(&mut self, price: P, qty: Qty) -> Option<Qty> {
+        BTreeMap::insert(self, price, qty)
+    }
+
+    fn remove(&mut self, price: &P) -> Option<Qty> {
+        BTreeMap::remove(self, price)
+    }
+
+    fn get(&self, price: &P) -> Option<&Qty> {
+        BTreeMap::get(self, price)
+    }
+
+    fn pop_first(&mut self) -> Option<(P, Qty)> {
+        BTreeMap::pop_first(self)
+    }
+
+    fn pop_last(&mut self) -> Option<(P, Qty)> {
+        BTreeMap::pop_last(self)
+    }
+
+    fn clear(&mut self) {
+        BTreeMap::clear(self)
+    }
+
+    fn len(&self) -> usize {
+        BTreeMap::len(self)
+    }
+
+    fn iter(&self) -> Self::Iter<'_> {
+        BTreeMap::iter(self)
+    }
+}
+
+impl<P: Ord> BookSide<P> for SortedVecSide<P, Qty> {
+    type Iter<'a>
+        = sorted_vec_side::Iter<'a, P, Qty>
+    where
+        P: 'a;
+
+    fn insert(&mut self, price: P, qty: Qty) -> Option<Qty> {
+        SortedVecSide::insert(self, price, qty)
+    }
+
+    fn remove(&mut self, price: &P) -> Option<Qty> {
+        SortedVecSide::remove(self, price)
+    }
+
+    fn get(&self, price: &P) -> Option<&Qty> {
+        SortedVecSide::get(self, price)
+    }
+
+    fn pop_first(&mut self) -> Option<(P, Qty)> {
+        SortedVecSide::pop_first(self)
+    }
+
+    fn pop_last(&mut self) -> Option<(P, Qty)> {
+        SortedVecSide::pop_last(self)
+    }
+
+    fn clear(&mut self) {
+        SortedVecSide::clear(self)
+    }
+
+    fn len(&self) -> usize {
+        SortedVecSide::len(self)
+    }
+
+    fn iter(&self) -> Self::Iter<'_> {
+        SortedVecSide::iter(self)
+    }
+}
@@ -1,13 +1,86 @@
-use crate::order_book::errors::Errors;
-use crate::order_book::order_book::OrderBook;
+use crate::order_book::errors::{Errors, UpdateMessageInfo};
+use crate::order_book::events::AppliedRecord;
+use crate::order_book::level_ttl::LevelTtl;
+use crate::order_book::order_book::{LevelPolicy, OrderBook, QuarantinedLevel};
 use crate::parsing::order_book_snapshot::OrderBookSnapshot;
-use crate::parsing::order_book_update::OrderBookUpdate;
+use crate::parsing::order_book_update::{OrderBookUpdate, UpdateFormat};
 use std::collections::HashMap;
 use std::fmt::Display;
+use std::io::{self, Write};
+use std::str::FromStr;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum BookState {
+    #[default]
+    Live,
+    // The book was resynced and its levels can't be trusted until the next snapshot
+    AwaitingSnapshot,
+}
+
+// What to do with an update that has to wait while MAX_PENDING_UPDATES are pending
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum OverflowPolicy {
+    // Every pending update is dropped, the next snapshot most likely covers them
+    #[default]
+    ClearAll,
+    // The pending update with the lowest seq_no is dropped
+    DropOldest,
+    // The new update is dropped and reported as Errors::PendingOverflow
+    RejectNew,
+}
+
+impl FromStr for OverflowPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "clear-all" => Ok(OverflowPolicy::ClearAll),
+            "drop-oldest" => Ok(OverflowPolicy::DropOldest),
+            "reject-new" => Ok(OverflowPolicy::RejectNew),
+            _ => Err(format!("Unknown overflow policy: {}", s)),
+        }
+    }
+}
+
+// How the gaps of a book went since it was created
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct GapStats {
+    pub security_id: u64,
+    // Times an update had to wait while none was pending
+    pub gaps: u64,
+    // Most seq_nos missing before a buffered update
+    pub largest_gap: u64,
+    // Pending updates applied once their gap closed
+    pub recovered: u64,
+    // Pending updates dropped without being applied: covered by a snapshot, replaced,
+    // overflowed, expired, rejected or dropped by a resync
+    pub dropped: u64,
+}
+
+impl Display for GapStats {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "GapStats {{ security_id: {}, gaps: {}, largest_gap: {}, recovered: {}, dropped: {} }}",
+            self.security_id, self.gaps, self.largest_gap, self.recovered, self.dropped
+        )
+    }
+}
 
 pub struct BufferedOrderBook {
     pub order_book: OrderBook,
     pub pending_updates: HashMap<u64, OrderBookUpdate>,
+    pub state: BookState,
+    pub level_policy: LevelPolicy,
+    pub overflow_policy: OverflowPolicy,
+    // Levels left out of updates by LevelPolicy::BestEffort, until the owner takes them
+    pub quarantined: Vec<QuarantinedLevel>,
+    // Set for securities whose quotes expire, see Manager::set_level_ttl
+    pub level_ttl: Option<LevelTtl>,
+    // The recovery hook of the manager was called for the current gap
+    pub recovery_requested: bool,
+    gap_stats: GapStats,
 }
 
 impl BufferedOrderBook {
@@ -17,24 +90,90 @@ impl BufferedOrderBook {
         Self {
             order_book,
             pending_updates: HashMap::new(),
+            state: BookState::Live,
+            level_policy: LevelPolicy::Atomic,
+            overflow_policy: OverflowPolicy::ClearAll,
+            quarantined: Vec::new(),
+            level_ttl: None,
+            recovery_requested: false,
+            gap_stats: GapStats::default(),
+        }
+    }
+
+    pub fn with_pending_capacity(order_book: OrderBook, pending_capacity: usize) -> Self {
+        Self {
+            order_book,
+            pending_updates: HashMap::with_capacity(
+                pending_capacity.min(Self::MAX_PENDING_UPDATES),
+            ),
+            state: BookState::Live,
+            level_policy: LevelPolicy::Atomic,
+            overflow_policy: OverflowPolicy::ClearAll,
+            quarantined: Vec::new(),
+            level_ttl: None,
+            recovery_requested: false,
+            gap_stats: GapStats::default(),
         }
     }
 
+    pub fn stats(&self) -> GapStats {
+        GapStats {
+            security_id: self.order_book.security_id,
+            ..self.gap_stats
+        }
+    }
+
+    pub fn with_overflow_policy(mut self, overflow_policy: OverflowPolicy) -> Self {
+        self.overflow_policy = overflow_policy;
+        self
+    }
+
     pub fn apply_update(&mut self, update: OrderBookUpdate) -> Result<(), Errors> {
-        match self.order_book.apply_update(&update) {
+        self.apply_update_with(update, &mut |_, _| {})
+    }
+
+    // Same as apply_update, calling `on_applied` with the book and the record after the
+    // update and after every pending update that could be applied following it
+    pub fn apply_update_with(
+        &mut self,
+        update: OrderBookUpdate,
+        on_applied: &mut dyn FnMut(&OrderBook, AppliedRecord),
+    ) -> Result<(), Errors> {
+        if self.state == BookState::AwaitingSnapshot {
+            let info = UpdateMessageInfo::new(update.security_id, update.seq_no);
+            // Kept for replay on top of the snapshot
+            if !self.buffer_update(update) {
+                return Err(Errors::PendingOverflow(info));
+            }
+            return Err(Errors::AwaitingSnapshot(info));
+        }
+
+        match self.order_book.apply_update_with_policy(
+            &update,
+            self.level_policy,
+            &mut self.quarantined,
+        ) {
             Ok(_) => {
-                self.try_apply_pending_updates();
+                if let Some(level_ttl) = self.level_ttl.as_mut() {
+                    level_ttl.on_update(&update, &self.order_book);
+                }
+                on_applied(&self.order_book, AppliedRecord::Update(&update));
+                self.try_apply_pending_updates(on_applied);
+                if self.pending_updates.is_empty() {
+                    self.recovery_requested = false;
+                }
                 Ok(())
             }
             Err(e) => match e {
-                Errors::SequenceNumberGap => {
-                    if self.pending_updates.len() >= Self::MAX_PENDING_UPDATES {
-                        // In the real world, with the snapshot and update streams open,
-                        // this most likely means that most of the updates are old and we
-                        // can just drop them because the next snapshot will include them all.
-                        self.pending_updates.clear();
+                Errors::SequenceNumberGap(info) => {
+                    if self.pending_updates.is_empty() {
+                        self.gap_stats.gaps += 1;
+                    }
+                    let missing = update.seq_no - self.order_book.seq_no - 1;
+                    self.gap_stats.largest_gap = self.gap_stats.largest_gap.max(missing);
+                    if !self.buffer_update(update) {
+                        return Err(Errors::PendingOverflow(info));
                     }
-                    self.pending_updates.insert(update.seq_no, update);
                     Err(e)
                 }
                 _ => Err(e),
@@ -43,29 +182,160 @@ impl BufferedOrderBook {
     }
 
     pub fn apply_snapshot(&mut self, snapshot: &OrderBookSnapshot) -> Result<(), Errors> {
-        let old_seq_no = self.order_book.seq_no;
+        self.apply_snapshot_with(snapshot, &mut |_, _| {})
+    }
 
+    pub fn apply_snapshot_with(
+        &mut self,
+        snapshot: &OrderBookSnapshot,
+        on_applied: &mut dyn FnMut(&OrderBook, AppliedRecord),
+    ) -> Result<(), Errors> {
         match self.order_book.apply_snapshot(snapshot) {
             Ok(_) => {
-                // Remove all pending updates that are now in the snapshot
-                for seq_no in old_seq_no..snapshot.seq_no {
-                    self.pending_updates.remove(&seq_no);
+                // Remove all pending updates that are now in the snapshot, including the
+                // old ones buffered while awaiting it
+                let pending_before = self.pending_updates.len();
+                self.pending_updates
+                    .retain(|seq_no, _| *seq_no > snapshot.seq_no);
+                self.gap_stats.dropped += (pending_before - self.pending_updates.len()) as u64;
+                self.state = BookState::Live;
+                self.recovery_requested = false;
+                if let Some(level_ttl) = self.level_ttl.as_mut() {
+                    level_ttl.on_snapshot(&self.order_book);
                 }
-                self.try_apply_pending_updates();
+                on_applied(&self.order_book, AppliedRecord::Snapshot(snapshot));
+                self.try_apply_pending_updates(on_applied);
                 Ok(())
             }
             Err(e) => Err(e),
         }
     }
 
-    fn try_apply_pending_updates(&mut self) {
+    // OrderBook::validate, and while live that every pending update is still waiting for
+    // a missing seq_no, as the ones up to the next seq_no are applied or dropped
+    pub fn validate(&self) -> Result<(), String> {
+        self.order_book.validate()?;
+        if self.pending_updates.len() > Self::MAX_PENDING_UPDATES {
+            return Err(format!(
+                "{} pending updates, more than {}",
+                self.pending_updates.len(),
+                Self::MAX_PENDING_UPDATES
+            ));
+        }
+        if self.state == BookState::Live
+            && let Some(seq_no) = self
+                .pending_updates
+                .keys()
+                .find(|seq_no| **seq_no <= self.order_book.seq_no + 1)
+        {
+            return Err(format!(
+                "The update {} is pending while the book is at seq_no {}",
+                seq_no, self.order_book.seq_no
+            ));
+        }
+        Ok(())
+    }
+
+    // Drops the levels and the pending updates and waits for the next snapshot,
+    // for when the book is known to be wrong
+    pub fn resync(&mut self) {
+        self.order_book.clear_levels();
+        self.gap_stats.dropped += self.pending_updates.len() as u64;
+        self.pending_updates.clear();
+        self.recovery_requested = false;
+        if let Some(level_ttl) = self.level_ttl.as_mut() {
+            level_ttl.on_snapshot(&self.order_book);
+        }
+        self.state = BookState::AwaitingSnapshot;
+    }
+
+    // Drops the pending updates for which `expired` is true, returning their seq_nos in
+    // ascending order
+    pub fn drop_pending_where(
+        &mut self,
+        mut expired: impl FnMut(u64, &OrderBookUpdate) -> bool,
+    ) -> Vec<u64> {
+        let mut dropped = Vec::new();
+        self.pending_updates.retain(|seq_no, update| {
+            if expired(*seq_no, update) {
+                dropped.push(*seq_no);
+                return false;
+            }
+            true
+        });
+        dropped.sort_unstable();
+        self.gap_stats.dropped += dropped.len() as u64;
+        if self.pending_updates.is_empty() {
+            self.recovery_requested = false;
+        }
+        dropped
+    }
+
+    // Writes the pending updates ordered by seq_no, so that a restart during a gap can
+    // restore them and still close the gap once the snapshot arrives
+    pub fn write_pending_updates<W: Write>(
+        &self,
+        writer: &mut W,
+        format: UpdateFormat,
+    ) -> io::Result<()> {
+        let mut seq_nos: Vec<&u64> = self.pending_updates.keys().collect();
+        seq_nos.sort_unstable();
+        for seq_no in seq_nos {
+            self.pending_updates[seq_no].write(writer, format)?;
+        }
+        Ok(())
+    }
+
+    // Returns false when the update was dropped by OverflowPolicy::RejectNew
+    fn buffer_update(&mut self, update: OrderBookUpdate) -> bool {
+        if self.pending_updates.len() >= Self::MAX_PENDING_UPDATES
+            && !self.pending_updates.contains_key(&update.seq_no)
+        {
+            match self.overflow_policy {
+                // In the real world, with the snapshot and update streams open,
+                // this most likely means that most of the updates are old and we
+                // can just drop them because the next snapshot will include them all.
+                OverflowPolicy::ClearAll => {
+                    self.gap_stats.dropped += self.pending_updates.len() as u64;
+                    self.pending_updates.clear();
+                }
+                OverflowPolicy::DropOldest => {
+                    if let Some(oldest) = self.pending_updates.keys().min().copied() {
+                        self.gap_stats.dropped += 1;
+                        self.pending_updates.remove(&oldest);
+                    }
+                }
+                OverflowPolicy::RejectNew => {
+                    self.gap_stats.dropped += 1;
+                    return false;
+                }
+            }
+        }
+        if self.pending_updates.insert(update.seq_no, update).is_some() {
+            self.gap_stats.dropped += 1;
+        }
+        true
+    }
+
+    fn try_apply_pending_updates(&mut self, on_applied: &mut dyn FnMut(&OrderBook, AppliedRecord)) {
         loop {
             let next_seq_no = self.order_book.seq_no + 1;
 
             if let Some(update) = self.pending_updates.remove(&next_seq_no) {
-                if self.order_book.apply_update(&update).is_err() {
+                let applied = self.order_book.apply_update_with_policy(
+                    &update,
+                    self.level_policy,
+                    &mut self.quarantined,
+                );
+                if applied.is_err() {
+                    self.gap_stats.dropped += 1;
                     break;
                 }
+                self.gap_stats.recovered += 1;
+                if let Some(level_ttl) = self.level_ttl.as_mut() {
+                    level_ttl.on_update(&update, &self.order_book);
+                }
+                on_applied(&self.order_book, AppliedRecord::Update(&update));
             } else {
                 break;
             }
@@ -84,8 +354,9 @@ impl Display for BufferedOrderBook {
 mod tests {
     use super::*;
     use crate::batched_deque::batched_deque::BatchedDeque;
+    use crate::order_book::units::Qty;
     use crate::parsing::order_book_snapshot::Level as SnapshotLevel;
-    use crate::parsing::order_book_update::Level as UpdateLevel;
+    use crate::parsing::order_book_update::{Level as UpdateLevel, Side};
     use num_traits::FromPrimitive;
     use rust_decimal::Decimal;
 
@@ -94,46 +365,50 @@ mod tests {
             timestamp: 1627846265,
             seq_no,
             security_id,
-            bid1: SnapshotLevel {
-                price: 100.00,
-                qty: 10,
-            },
-            ask1: SnapshotLevel {
-                price: 101.00,
-                qty: 15,
-            },
-            bid2: SnapshotLevel {
-                price: 99.00,
-                qty: 20,
-            },
-            ask2: SnapshotLevel {
-                price: 102.00,
-                qty: 25,
-            },
-            bid3: SnapshotLevel {
-                price: 98.00,
-                qty: 30,
-            },
-            ask3: SnapshotLevel {
-                price: 103.00,
-                qty: 35,
-            },
-            bid4: SnapshotLevel {
-                price: 97.00,
-                qty: 40,
-            },
-            ask4: SnapshotLevel {
-                price: 104.00,
-                qty: 45,
-            },
-            bid5: SnapshotLevel {
-                price: 96.00,
-                qty: 50,
-            },
-            ask5: SnapshotLevel {
-                price: 105.00,
-                qty: 55,
-            },
+            bids: vec![
+                SnapshotLevel {
+                    price: 100.00,
+                    qty: Qty(10),
+                },
+                SnapshotLevel {
+                    price: 99.00,
+                    qty: Qty(20),
+                },
+                SnapshotLevel {
+                    price: 98.00,
+                    qty: Qty(30),
+                },
+                SnapshotLevel {
+                    price: 97.00,
+                    qty: Qty(40),
+                },
+                SnapshotLevel {
+                    price: 96.00,
+                    qty: Qty(50),
+                },
+            ],
+            asks: vec![
+                SnapshotLevel {
+                    price: 101.00,
+                    qty: Qty(15),
+                },
+                SnapshotLevel {
+                    price: 102.00,
+                    qty: Qty(25),
+                },
+                SnapshotLevel {
+                    price: 103.00,
+                    qty: Qty(35),
+                },
+                SnapshotLevel {
+                    price: 104.00,
+                    qty: Qty(45),
+                },
+                SnapshotLevel {
+                    price: 105.00,
+                    qty: Qty(55),
+                },
+            ],
         }
     }
 
@@ -142,21 +417,25 @@ mod tests {
         let deque = BatchedDeque::new(10);
         let levels: Vec<Result<UpdateLevel, ()>> = vec![
             Ok(UpdateLevel {
-                side: 0,
+                side: Side::Bid,
                 price: 99.50,
-                qty: 25,
+                qty: Qty(25),
+                metadata: None,
             }),
             Ok(UpdateLevel {
-                side: 1,
+                side: Side::Ask,
                 price: 100.50,
-                qty: 30,
+                qty: Qty(30),
+                metadata: None,
             }),
         ];
 
         OrderBookUpdate {
             timestamp: 1627846266,
+            capture_timestamp: None,
             seq_no,
             security_id,
+            thinned_from: None,
             updates: deque.push_back_batch(levels.into_iter()).unwrap(),
         }
     }
@@ -187,7 +466,7 @@ mod tests {
         let update = create_test_update(security_id, 102);
         let result = buffered_book.apply_update(update);
 
-        assert!(matches!(result, Err(Errors::SequenceNumberGap)));
+        assert!(matches!(result, Err(Errors::SequenceNumberGap(_))));
         assert_eq!(buffered_book.order_book.seq_no, 100);
         assert_eq!(buffered_book.pending_updates.len(), 1);
         assert!(buffered_book.pending_updates.contains_key(&102));
@@ -203,11 +482,11 @@ mod tests {
         // Add an update with a sequence number gap
         let update = create_test_update(security_id, 102);
         let result = buffered_book.apply_update(update);
-        assert!(matches!(result, Err(Errors::SequenceNumberGap)));
+        assert!(matches!(result, Err(Errors::SequenceNumberGap(_))));
 
         let update = create_test_update(security_id, 104);
         let result = buffered_book.apply_update(update);
-        assert!(matches!(result, Err(Errors::SequenceNumberGap)));
+        assert!(matches!(result, Err(Errors::SequenceNumberGap(_))));
 
         // Apply a new snapshot with a higher sequence number
         let snapshot2 = create_test_snapshot(security_id, 103);
@@ -277,6 +556,184 @@ mod tests {
         assert!(buffered_book.pending_updates.contains_key(&new_seq_no));
     }
 
+    #[test]
+    fn test_buffered_overflow_policies() {
+        let security_id = 1001;
+        let full_book = |overflow_policy: OverflowPolicy| {
+            let snapshot = create_test_snapshot(security_id, 100);
+            let mut buffered_book = BufferedOrderBook::new(OrderBook::new(&snapshot).unwrap())
+                .with_overflow_policy(overflow_policy);
+            for seq_no in 102..102 + BufferedOrderBook::MAX_PENDING_UPDATES as u64 {
+                buffered_book
+                    .apply_update(create_test_update(security_id, seq_no))
+                    .unwrap_err();
+            }
+            buffered_book
+        };
+        let last_seq_no = 101 + BufferedOrderBook::MAX_PENDING_UPDATES as u64;
+
+        let mut buffered_book = full_book(OverflowPolicy::ClearAll);
+        let result = buffered_book.apply_update(create_test_update(security_id, last_seq_no + 1));
+        assert!(matches!(result, Err(Errors::SequenceNumberGap(_))));
+        assert_eq!(buffered_book.pending_updates.len(), 1);
+
+        let mut buffered_book = full_book(OverflowPolicy::DropOldest);
+        let result = buffered_book.apply_update(create_test_update(security_id, last_seq_no + 1));
+        assert!(matches!(result, Err(Errors::SequenceNumberGap(_))));
+        assert_eq!(
+            buffered_book.pending_updates.len(),
+            BufferedOrderBook::MAX_PENDING_UPDATES
+        );
+        assert!(!buffered_book.pending_updates.contains_key(&102));
+        assert!(
+            buffered_book
+                .pending_updates
+                .contains_key(&(last_seq_no + 1))
+        );
+
+        let mut buffered_book = full_book(OverflowPolicy::RejectNew);
+        let result = buffered_book.apply_update(create_test_update(security_id, last_seq_no + 1));
+        assert!(matches!(result, Err(Errors::PendingOverflow(_))));
+        assert_eq!(
+            buffered_book.pending_updates.len(),
+            BufferedOrderBook::MAX_PENDING_UPDATES
+        );
+        assert!(
+            !buffered_book
+                .pending_updates
+                .contains_key(&(last_seq_no + 1))
+        );
+        // A seq_no already pending is replaced whatever the policy
+        let result = buffered_book.apply_update(create_test_update(security_id, last_seq_no));
+        assert!(matches!(result, Err(Errors::SequenceNumberGap(_))));
+        // The missing update replays the pending ones
+        buffered_book
+            .apply_update(create_test_update(security_id, 101))
+            .unwrap();
+        assert_eq!(buffered_book.order_book.seq_no, last_seq_no);
+    }
+
+    #[test]
+    fn test_buffered_gap_stats() {
+        let security_id = 1001;
+        let snapshot = create_test_snapshot(security_id, 100);
+        let mut buffered_book = BufferedOrderBook::new(OrderBook::new(&snapshot).unwrap());
+        for seq_no in [103, 104, 104] {
+            buffered_book
+                .apply_update(create_test_update(security_id, seq_no))
+                .unwrap_err();
+        }
+        // 102 is still missing
+        buffered_book
+            .apply_update(create_test_update(security_id, 101))
+            .unwrap();
+        buffered_book
+            .apply_update(create_test_update(security_id, 102))
+            .unwrap();
+        buffered_book
+            .apply_update(create_test_update(security_id, 110))
+            .unwrap_err();
+        buffered_book.resync();
+
+        assert_eq!(
+            buffered_book.stats(),
+            GapStats {
+                security_id,
+                gaps: 2,
+                largest_gap: 5,
+                recovered: 2,
+                // The replaced 104 and 110
+                dropped: 2,
+            }
+        );
+    }
+
+    #[test]
+    fn test_buffered_on_applied_called_for_pending_updates() {
+        let security_id = 1001;
+        let snapshot = create_test_snapshot(security_id, 100);
+        let order_book = OrderBook::new(&snapshot).unwrap();
+        let mut buffered_book = BufferedOrderBook::new(order_book);
+
+        let mut applied_seq_nos = Vec::new();
+        let mut on_applied = |book: &OrderBook, _: AppliedRecord| applied_seq_nos.push(book.seq_no);
+
+        let update = create_test_update(security_id, 102);
+        buffered_book
+            .apply_update_with(update, &mut on_applied)
+            .unwrap_err();
+        let update = create_test_update(security_id, 101);
+        buffered_book
+            .apply_update_with(update, &mut on_applied)
+            .unwrap();
+
+        assert_eq!(applied_seq_nos, vec![101, 102]);
+    }
+
+    #[test]
+    fn test_buffered_resync() {
+        let security_id = 1001;
+        let snapshot = create_test_snapshot(security_id, 100);
+        let order_book = OrderBook::new(&snapshot).unwrap();
+        let mut buffered_book = BufferedOrderBook::new(order_book);
+        buffered_book
+            .apply_update(create_test_update(security_id, 102))
+            .unwrap_err();
+
+        buffered_book.resync();
+        assert_eq!(buffered_book.state, BookState::AwaitingSnapshot);
+        assert!(buffered_book.order_book.bids.is_empty());
+        assert!(buffered_book.order_book.asks.is_empty());
+        assert!(buffered_book.pending_updates.is_empty());
+
+        // Even the next update in sequence waits for the snapshot
+        let result = buffered_book.apply_update(create_test_update(security_id, 101));
+        assert!(matches!(result, Err(Errors::AwaitingSnapshot(_))));
+        let result = buffered_book.apply_update(create_test_update(security_id, 103));
+        assert!(matches!(result, Err(Errors::AwaitingSnapshot(_))));
+        assert_eq!(buffered_book.pending_updates.len(), 2);
+
+        let snapshot = create_test_snapshot(security_id, 102);
+        buffered_book.apply_snapshot(&snapshot).unwrap();
+        assert_eq!(buffered_book.state, BookState::Live);
+        assert_eq!(buffered_book.order_book.seq_no, 103);
+        assert!(buffered_book.pending_updates.is_empty());
+    }
+
+    #[test]
+    fn test_buffered_validate_after_resync() {
+        let security_id = 1001;
+        let order_book = OrderBook::new(&create_test_snapshot(security_id, 100)).unwrap();
+        let mut buffered_book = BufferedOrderBook::new(order_book);
+        assert_eq!(buffered_book.validate(), Ok(()));
+
+        buffered_book.resync();
+        for seq_no in [99, 101, 102, 104] {
+            buffered_book
+                .apply_update(create_test_update(security_id, seq_no))
+                .unwrap_err();
+        }
+        assert_eq!(buffered_book.validate(), Ok(()));
+
+        // The updates covered by the snapshot are dropped, the one after the gap is kept
+        buffered_book
+            .apply_snapshot(&create_test_snapshot(security_id, 102))
+            .unwrap();
+        assert_eq!(
+            buffered_book.pending_updates.keys().collect::<Vec<_>>(),
+            vec![&104]
+        );
+        assert_eq!(buffered_book.validate(), Ok(()));
+
+        buffered_book
+            .pending_updates
+            .insert(103, create_test_update(security_id, 103));
+        assert_eq!(
+            buffered_book.validate(),
+            Err("The update 103 is pending while the book is at seq_no 102".to_string())
+        );
+    }
+
     #[test]
     fn test_buffered_old_update_ignored() {
         let security_id = 1001;
@@ -288,7 +745,7 @@ mod tests {
         let old_update = create_test_update(security_id, 99);
         let result = buffered_book.apply_update(old_update);
 
-        assert!(matches!(result, Err(Errors::OldSequenceNumber)));
+        assert!(matches!(result, Err(Errors::OldSequenceNumber(_))));
         assert_eq!(buffered_book.order_book.seq_no, 100);
         assert!(buffered_book.pending_updates.is_empty());
     }
@@ -335,72 +792,84 @@ mod tests {
         let deque = BatchedDeque::new(10);
         let update102 = {
             let levels: Vec<Result<UpdateLevel, ()>> = vec![Ok(UpdateLevel {
-                side: 0,
+                side: Side::Bid,
                 price: 99.51,
-                qty: 100,
+                qty: Qty(100),
+                metadata: None,
             })];
             deque.push_back_batch(levels.into_iter()).unwrap()
         };
         let result = buffered_book.apply_update(OrderBookUpdate {
             timestamp: 1627846266,
+            capture_timestamp: None,
             seq_no: 102,
             security_id,
+            thinned_from: None,
             updates: update102,
         });
         // Should be added to pending updates
-        assert!(matches!(result, Err(Errors::SequenceNumberGap)));
+        assert!(matches!(result, Err(Errors::SequenceNumberGap(_))));
         assert_eq!(buffered_book.pending_updates.len(), 1);
         assert!(buffered_book.pending_updates.contains_key(&102));
 
         // Create another update with a sequence number gap
         let levels: Vec<Result<UpdateLevel, ()>> = vec![Ok(UpdateLevel {
-            side: 0,
+            side: Side::Bid,
             price: 99.50,
-            qty: 200,
+            qty: Qty(200),
+            metadata: None,
         })];
         let update103 = deque.push_back_batch(levels.into_iter()).unwrap();
         let result = buffered_book.apply_update(OrderBookUpdate {
             timestamp: 1627846266,
+            capture_timestamp: None,
             seq_no: 103,
             security_id,
+            thinned_from: None,
             updates: update103,
         });
         // Should be added to pending updates
-        assert!(matches!(result, Err(Errors::SequenceNumberGap)));
+        assert!(matches!(result, Err(Errors::SequenceNumberGap(_))));
         assert_eq!(buffered_book.pending_updates.len(), 2);
         assert!(buffered_book.pending_updates.contains_key(&102));
         assert!(buffered_book.pending_updates.contains_key(&103));
 
         // Create duplicate update with the same sequence number
         let levels: Vec<Result<UpdateLevel, ()>> = vec![Ok(UpdateLevel {
-            side: 0,
+            side: Side::Bid,
             price: 99.50,
-            qty: 200,
+            qty: Qty(200),
+            metadata: None,
         })];
         let update103 = deque.push_back_batch(levels.into_iter()).unwrap();
         let result = buffered_book.apply_update(OrderBookUpdate {
             timestamp: 1627846266,
+            capture_timestamp: None,
             seq_no: 103,
             security_id,
+            thinned_from: None,
             updates: update103,
         });
         // Still should have only two pending updates
-        assert!(matches!(result, Err(Errors::SequenceNumberGap)));
+        assert!(matches!(result, Err(Errors::SequenceNumberGap(_))));
         assert_eq!(buffered_book.pending_updates.len(), 2);
         assert!(buffered_book.pending_updates.contains_key(&102));
         assert!(buffered_book.pending_updates.contains_key(&103));
 
         // Now fill the gap and apply pending updates
         let levels: Vec<Result<UpdateLevel, ()>> = vec![Ok(UpdateLevel {
-            side: 0,
+            side: Side::Bid,
             price: 99.52,
-            qty: 99,
+            qty: Qty(99),
+            metadata: None,
         })];
         let update101 = deque.push_back_batch(levels.into_iter()).unwrap();
         let result = buffered_book.apply_update(OrderBookUpdate {
             timestamp: 1627846266,
+            capture_timestamp: None,
             seq_no: 101,
             security_id,
+            thinned_from: None,
             updates: update101,
         });
         // Should successfully apply both the gap-filling update and the pending update
@@ -414,21 +883,21 @@ mod tests {
                 .order_book
                 .bids
                 .get(&Decimal::from_f64(99.51).unwrap()),
-            Some(&100)
+            Some(&Qty(100))
         );
         assert_eq!(
             buffered_book
                 .order_book
                 .bids
                 .get(&Decimal::from_f64(99.50).unwrap()),
-            Some(&200)
+            Some(&Qty(200))
         );
         assert_eq!(
             buffered_book
                 .order_book
                 .bids
                 .get(&Decimal::from_f64(99.52).unwrap()),
-            Some(&99)
+            Some(&Qty(99))
         );
     }
 }
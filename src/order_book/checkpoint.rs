@@ -0,0 +1,420 @@
+use rust_decimal::Decimal;
+use std::collections::BTreeMap;
+use std::io::{self, Read, Write};
+use std::str::FromStr;
+
+use crate::order_book::manager::Manager;
+use crate::order_book::order_book::OrderBook;
+use crate::order_book::units::Qty;
+use crate::parsing::order_book_snapshot::OrderBookSnapshot;
+use crate::parsing::order_book_update::LevelMetadata;
+use crate::parsing::parser::ParserError;
+use crate::replay::AsOf;
+
+// Checkpoints hold every level of every book, unlike snapshot records. Each one is the
+// data time of the manager and the number of books, followed per book by timestamp,
+// capture_timestamp (a flag byte and the value), seq_no, security_id, the numbers of bid
+// and ask levels and the levels: price as a serialized Decimal, qty and metadata (a flag
+// byte, order_count and action). Numbers are little-endian.
+
+const MAGIC: [u8; 4] = *b"L2CK";
+
+// When to take a checkpoint: after a number of applied records, or once the data time
+// moved on by a number of millis since the previous one
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CheckpointInterval {
+    Records(u64),
+    EventMillis(u64),
+}
+
+// A number of records, or of seconds of event time with an "s" suffix
+impl FromStr for CheckpointInterval {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("Invalid checkpoint interval: {}", s);
+        let interval = match s.strip_suffix('s') {
+            Some(seconds) => seconds
+                .parse::<u64>()
+                .ok()
+                .and_then(|seconds| seconds.checked_mul(1000))
+                .map(CheckpointInterval::EventMillis),
+            None => s.parse().ok().map(CheckpointInterval::Records),
+        };
+        match interval {
+            Some(CheckpointInterval::Records(0) | CheckpointInterval::EventMillis(0)) | None => {
+                Err(invalid())
+            }
+            Some(interval) => Ok(interval),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Checkpoint {
+    pub data_time: u64,
+    pub books: Vec<OrderBook>,
+}
+
+impl Checkpoint {
+    // Whether the books are not past the moment, so replaying on top of them can reach it
+    pub fn precedes(&self, as_of: AsOf) -> bool {
+        self.books
+            .iter()
+            .all(|book| as_of.includes(book.seq_no, book.timestamp))
+    }
+}
+
+// Tells when an interval has passed since the previous checkpoint
+#[derive(Debug, Clone, Copy)]
+pub struct CheckpointSchedule {
+    interval: CheckpointInterval,
+    records_since: u64,
+    last_data_time: Option<u64>,
+}
+
+impl CheckpointSchedule {
+    pub fn new(interval: CheckpointInterval) -> Self {
+        Self {
+            interval,
+            records_since: 0,
+            last_data_time: None,
+        }
+    }
+
+    // Called after every record handed to the manager, whether a checkpoint is due
+    pub fn on_record(&mut self, data_time: u64) -> bool {
+        self.records_since += 1;
+        match self.interval {
+            CheckpointInterval::Records(records) => self.records_since >= records,
+            CheckpointInterval::EventMillis(millis) => match self.last_data_time {
+                Some(last) => data_time >= last.saturating_add(millis),
+                None => {
+                    self.last_data_time = Some(data_time);
+                    false
+                }
+            },
+        }
+    }
+
+    pub fn on_checkpoint(&mut self, data_time: u64) {
+        self.records_since = 0;
+        self.last_data_time = Some(data_time);
+    }
+}
+
+// Writes the books of a manager to a store every interval, flushed so that a crashed
+// replay can resume from the last complete checkpoint
+pub struct CheckpointWriter<W: Write> {
+    writer: W,
+    schedule: CheckpointSchedule,
+    written: usize,
+}
+
+impl<W: Write> CheckpointWriter<W> {
+    pub fn new(writer: W, interval: CheckpointInterval) -> Self {
+        Self {
+            writer,
+            schedule: CheckpointSchedule::new(interval),
+            written: 0,
+        }
+    }
+
+    pub fn written(&self) -> usize {
+        self.written
+    }
+
+    // Called after every record handed to the manager
+    pub fn on_record(&mut self, manager: &Manager) -> io::Result<()> {
+        if self.schedule.on_record(manager.data_time()) {
+            self.write(manager)?;
+        }
+        Ok(())
+    }
+
+    pub fn write(&mut self, manager: &Manager) -> io::Result<()> {
+        write_checkpoint(&mut self.writer, manager)?;
+        self.writer.flush()?;
+        self.schedule.on_checkpoint(manager.data_time());
+        self.written += 1;
+        Ok(())
+    }
+}
+
+fn write_levels<W: Write>(
+    writer: &mut W,
+    levels: &BTreeMap<Decimal, Qty>,
+    levels_metadata: &BTreeMap<Decimal, LevelMetadata>,
+) -> io::Result<()> {
+    for (price, qty) in levels {
+        writer.write_all(&price.serialize())?;
+        writer.write_all(&qty.value().to_le_bytes())?;
+        match levels_metadata.get(price) {
+            Some(metadata) => {
+                writer.write_all(&[1])?;
+                writer.write_all(&metadata.order_count.to_le_bytes())?;
+                writer.write_all(&[metadata.action])?;
+            }
+            None => writer.write_all(&[0; 6])?,
+        }
+    }
+    Ok(())
+}
+
+pub fn write_checkpoint<W: Write>(writer: &mut W, manager: &Manager) -> io::Result<()> {
+    writer.write_all(&MAGIC)?;
+    writer.write_all(&manager.data_time().to_le_bytes())?;
+    let books = &manager.buffered_order_books;
+    writer.write_all(&(books.len() as u64).to_le_bytes())?;
+    for buffered_order_book in books.values() {
+        let book = &buffered_order_book.order_book;
+        writer.write_all(&book.timestamp.to_le_bytes())?;
+        writer.write_all(&[book.capture_timestamp.is_some() as u8])?;
+        writer.write_all(&book.capture_timestamp.unwrap_or(0).to_le_bytes())?;
+        writer.write_all(&book.seq_no.to_le_bytes())?;
+        writer.write_all(&book.security_id.to_le_bytes())?;
+        writer.write_all(&(book.bids.len() as u64).to_le_bytes())?;
+        writer.write_all(&(book.asks.len() as u64).to_le_bytes())?;
+        write_levels(writer, &book.bids, &book.bid_metadata)?;
+        write_levels(writer, &book.asks, &book.ask_metadata)?;
+    }
+    Ok(())
+}
+
+fn read_array<R: Read, const N: usize>(reader: &mut R) -> Result<[u8; N], ParserError> {
+    let mut bytes = [0; N];
+    reader.read_exact(&mut bytes).map_err(ParserError::Io)?;
+    Ok(bytes)
+}
+
+fn read_u64<R: Read>(reader: &mut R) -> Result<u64, ParserError> {
+    Ok(u64::from_le_bytes(read_array(reader)?))
+}
+
+fn read_levels<R: Read>(
+    reader: &mut R,
+    count: u64,
+    levels: &mut BTreeMap<Decimal, Qty>,
+    levels_metadata: &mut BTreeMap<Decimal, LevelMetadata>,
+) -> Result<(), ParserError> {
+    for _ in 0..count {
+        let price = Decimal::deserialize(read_array(reader)?);
+        let qty = Qty(read_u64(reader)?);
+        let [has_metadata] = read_array(reader)?;
+        let order_count = u32::from_le_bytes(read_array(reader)?);
+        let [action] = read_array(reader)?;
+        levels.insert(price, qty);
+        if has_metadata != 0 {
+            levels_metadata.insert(
+                price,
+                LevelMetadata {
+                    order_count,
+                    action,
+                },
+            );
+        }
+    }
+    Ok(())
+}
+
+fn read_book<R: Read>(reader: &mut R) -> Result<OrderBook, ParserError> {
+    let timestamp = read_u64(reader)?;
+    let [has_capture_timestamp] = read_array(reader)?;
+    let capture_timestamp = read_u64(reader)?;
+    let seq_no = read_u64(reader)?;
+    let security_id = read_u64(reader)?;
+    let num_bids = read_u64(reader)?;
+    let num_asks = read_u64(reader)?;
+
+    let mut book = OrderBook::new(&OrderBookSnapshot {
+        timestamp,
+        seq_no,
+        security_id,
+        bids: Vec::new(),
+        asks: Vec::new(),
+    })
+    .map_err(|e| ParserError::Custom(e.to_string()))?;
+    book.capture_timestamp = (has_capture_timestamp != 0).then_some(capture_timestamp);
+    read_levels(reader, num_bids, &mut book.bids, &mut book.bid_metadata)?;
+    read_levels(reader, num_asks, &mut book.asks, &mut book.ask_metadata)?;
+    Ok(book)
+}
+
+pub fn read_checkpoint<R: Read>(reader: &mut R) -> Result<Checkpoint, ParserError> {
+    let mut magic = [0; 4];
+    match reader.read_exact(&mut magic) {
+        Ok(_) => (),
+        Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => {
+            return Err(ParserError::ExpectedEof);
+        }
+        Err(e) => return Err(ParserError::Io(e)),
+    }
+    if magic != MAGIC {
+        return Err(ParserError::Custom("Not a checkpoint".to_string()));
+    }
+    let data_time = read_u64(reader)?;
+    let num_books = read_u64(reader)?;
+    let books = (0..num_books)
+        .map(|_| read_book(reader))
+        .collect::<Result<_, _>>()?;
+    Ok(Checkpoint { data_time, books })
+}
+
+// The last complete checkpoint of the store preceding `as_of`, or the last one without
+// it. A checkpoint cut short by a crash ends the store.
+pub fn find_checkpoint<R: Read>(
+    reader: &mut R,
+    as_of: Option<AsOf>,
+) -> Result<Option<Checkpoint>, ParserError> {
+    let mut found = None;
+    loop {
+        match read_checkpoint(reader) {
+            Ok(checkpoint) => {
+                if as_of.is_none_or(|as_of| checkpoint.precedes(as_of)) {
+                    found = Some(checkpoint);
+                }
+            }
+            Err(ParserError::ExpectedEof) => return Ok(found),
+            Err(ParserError::Io(e)) if e.kind() == io::ErrorKind::UnexpectedEof => {
+                return Ok(found);
+            }
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::batched_deque::batched_deque::BatchedDeque;
+    use crate::parsing::order_book_snapshot::test_support::create_test_snapshot;
+    use crate::parsing::order_book_update::{Level as UpdateLevel, OrderBookUpdate, Side};
+    use rust_decimal::dec;
+
+    fn apply_test_update(manager: &mut Manager, seq_no: u64, timestamp: u64) {
+        let deque = BatchedDeque::new(4);
+        let level = UpdateLevel {
+            side: Side::Bid,
+            price: 95.0,
+            qty: Qty(seq_no),
+            metadata: Some(LevelMetadata {
+                order_count: 3,
+                action: 0,
+            }),
+        };
+        let update = OrderBookUpdate {
+            timestamp,
+            capture_timestamp: Some(timestamp + 1),
+            seq_no,
+            security_id: 1,
+            thinned_from: None,
+            updates: deque
+                .push_back_batch([Ok::<_, ()>(level)].into_iter())
+                .unwrap(),
+        };
+        assert!(manager.apply_update(update).is_applied());
+    }
+
+    #[test]
+    fn test_parse_interval() {
+        assert_eq!("500".parse(), Ok(CheckpointInterval::Records(500)));
+        assert_eq!("60s".parse(), Ok(CheckpointInterval::EventMillis(60_000)));
+        assert!("0".parse::<CheckpointInterval>().is_err());
+        assert!("1m".parse::<CheckpointInterval>().is_err());
+    }
+
+    #[test]
+    fn test_checkpoint_round_trip() {
+        let mut manager = Manager::default();
+        manager.apply_snapshot(&OrderBookSnapshot {
+            timestamp: 1_000,
+            ..create_test_snapshot(1, 10)
+        });
+        manager.apply_snapshot(&OrderBookSnapshot {
+            timestamp: 1_000,
+            ..create_test_snapshot(2, 20)
+        });
+        apply_test_update(&mut manager, 11, 2_000);
+
+        let mut written = Vec::new();
+        write_checkpoint(&mut written, &manager).unwrap();
+        let checkpoint = read_checkpoint(&mut written.as_slice()).unwrap();
+        assert_eq!(checkpoint.data_time, 2_000);
+
+        let mut restored = Manager::default();
+        restored.restore_books(checkpoint.books);
+        assert_eq!(restored.data_time(), 2_000);
+        for (security_id, buffered_order_book) in &manager.buffered_order_books {
+            let book = &buffered_order_book.order_book;
+            let restored_book = &restored.buffered_order_books[security_id].order_book;
+            assert_eq!(restored_book.seq_no, book.seq_no);
+            assert_eq!(restored_book.timestamp, book.timestamp);
+            assert_eq!(restored_book.capture_timestamp, book.capture_timestamp);
+            assert_eq!(restored_book.bids, book.bids);
+            assert_eq!(restored_book.asks, book.asks);
+            assert_eq!(restored_book.bid_metadata, book.bid_metadata);
+        }
+        assert_eq!(restored.buffered_order_books[&1].order_book.bids.len(), 6);
+        assert_eq!(
+            restored.buffered_order_books[&1].order_book.bid_metadata[&dec!(95)].order_count,
+            3
+        );
+
+        // The replay continues where the checkpoint was taken
+        apply_test_update(&mut restored, 12, 3_000);
+    }
+
+    #[test]
+    fn test_checkpoint_writer_intervals() {
+        let mut manager = Manager::default();
+        manager.apply_snapshot(&OrderBookSnapshot {
+            timestamp: 1_000,
+            ..create_test_snapshot(1, 10)
+        });
+
+        let mut writer = CheckpointWriter::new(Vec::new(), CheckpointInterval::Records(2));
+        for seq_no in 11..16 {
+            apply_test_update(&mut manager, seq_no, seq_no * 1_000);
+            writer.on_record(&manager).unwrap();
+        }
+        assert_eq!(writer.written(), 2);
+
+        let mut writer = CheckpointWriter::new(Vec::new(), CheckpointInterval::EventMillis(2_500));
+        for seq_no in 16..23 {
+            apply_test_update(&mut manager, seq_no, seq_no * 1_000);
+            writer.on_record(&manager).unwrap();
+        }
+        // At 19s and 22s of event time, after starting at 16s
+        assert_eq!(writer.written(), 2);
+    }
+
+    #[test]
+    fn test_find_checkpoint() {
+        let mut manager = Manager::default();
+        manager.apply_snapshot(&OrderBookSnapshot {
+            timestamp: 1_000,
+            ..create_test_snapshot(1, 10)
+        });
+        let mut store = Vec::new();
+        for seq_no in 11..14 {
+            apply_test_update(&mut manager, seq_no, seq_no * 1_000);
+            write_checkpoint(&mut store, &manager).unwrap();
+        }
+        // A checkpoint cut short by a crash
+        let complete = store.len();
+        write_checkpoint(&mut store, &manager).unwrap();
+        store.truncate(complete + 20);
+
+        let seq_no_of = |checkpoint: Option<Checkpoint>| {
+            checkpoint.map(|checkpoint| checkpoint.books[0].seq_no)
+        };
+        let last = find_checkpoint(&mut store.as_slice(), None).unwrap();
+        assert_eq!(seq_no_of(last), Some(13));
+        let as_of = find_checkpoint(&mut store.as_slice(), Some(AsOf::SeqNo(12))).unwrap();
+        assert_eq!(seq_no_of(as_of), Some(12));
+        let as_of = find_checkpoint(&mut store.as_slice(), Some(AsOf::Timestamp(11_500))).unwrap();
+        assert_eq!(seq_no_of(as_of), Some(11));
+        let as_of = find_checkpoint(&mut store.as_slice(), Some(AsOf::SeqNo(10))).unwrap();
+        assert_eq!(seq_no_of(as_of), None);
+    }
+}
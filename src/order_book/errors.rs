@@ -1,15 +1,98 @@
-#[derive(Debug)]
+use std::fmt::Display;
+use std::io;
+use thiserror::Error;
+
+// The record an error is about
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct UpdateMessageInfo {
     pub security_id: u64,
     pub seq_no: u64,
 }
 
-#[derive(Debug)]
+impl UpdateMessageInfo {
+    pub fn new(security_id: u64, seq_no: u64) -> Self {
+        Self {
+            security_id,
+            seq_no,
+        }
+    }
+}
+
+impl Display for UpdateMessageInfo {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "security {} with seq_no {}",
+            self.security_id, self.seq_no
+        )
+    }
+}
+
+#[derive(Debug, Error)]
 pub enum Errors {
-    SequenceNumberGap,
-    OldSequenceNumber,
+    #[error("Sequence number gap before {0}")]
+    SequenceNumberGap(UpdateMessageInfo),
+    #[error("Old sequence number for {0}")]
+    OldSequenceNumber(UpdateMessageInfo),
+    #[error("Invalid price for {0}: {1}")]
     InvalidPrice(UpdateMessageInfo, String),
-    InvalidSide(UpdateMessageInfo, String),
-    SecurityIdMismatch,
-    OrderBookNotFound,
+    #[error("Price too far from the book for {0}: {1}")]
+    PriceDeviation(UpdateMessageInfo, String),
+    #[error("Invalid quantity for {0}: {1}")]
+    InvalidQuantity(UpdateMessageInfo, String),
+    #[error("Timestamp going back in time for {0}: {1}")]
+    TimestampRegression(UpdateMessageInfo, String),
+    #[error("Security ID mismatch for {0}")]
+    SecurityIdMismatch(UpdateMessageInfo),
+    #[error("Order book not found for {0}")]
+    OrderBookNotFound(UpdateMessageInfo),
+    #[error("Awaiting snapshot for {0}")]
+    AwaitingSnapshot(UpdateMessageInfo),
+    #[error("Too many pending updates for {0}")]
+    PendingOverflow(UpdateMessageInfo),
+}
+
+impl Errors {
+    pub fn info(&self) -> &UpdateMessageInfo {
+        match self {
+            Errors::SequenceNumberGap(info)
+            | Errors::OldSequenceNumber(info)
+            | Errors::InvalidPrice(info, _)
+            | Errors::InvalidQuantity(info, _)
+            | Errors::PriceDeviation(info, _)
+            | Errors::TimestampRegression(info, _)
+            | Errors::SecurityIdMismatch(info)
+            | Errors::OrderBookNotFound(info)
+            | Errors::AwaitingSnapshot(info)
+            | Errors::PendingOverflow(info) => info,
+        }
+    }
+}
+
+impl From<Errors> for io::Error {
+    fn from(error: Errors) -> Self {
+        io::Error::new(io::ErrorKind::InvalidData, error)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_error_display() {
+        let error = Errors::InvalidPrice(UpdateMessageInfo::new(1001, 101), "NaN".to_string());
+        assert_eq!(
+            error.to_string(),
+            "Invalid price for security 1001 with seq_no 101: NaN"
+        );
+        assert_eq!(error.info(), &UpdateMessageInfo::new(1001, 101));
+
+        let error = io::Error::from(Errors::SequenceNumberGap(UpdateMessageInfo::new(1001, 103)));
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+        assert_eq!(
+            error.to_string(),
+            "Sequence number gap before security 1001 with seq_no 103"
+        );
+    }
 }
@@ -0,0 +1,169 @@
+use std::fmt::Write;
+
+use crate::order_book::buffered_order_book::{BookState, BufferedOrderBook};
+use crate::parsing::order_book_snapshot::OrderBookSnapshot;
+use crate::parsing::order_book_update::OrderBookUpdate;
+
+pub type EventSink = Box<dyn FnMut(&BookEvent)>;
+
+// A record that was just applied to its book, after validation
+#[derive(Debug, Clone, Copy)]
+pub enum AppliedRecord<'a> {
+    Snapshot(&'a OrderBookSnapshot),
+    Update(&'a OrderBookUpdate),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RecordKind {
+    Snapshot,
+    Update,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SyncState {
+    Live,
+    // Updates are waiting for a missing seq_no
+    Gap,
+    AwaitingSnapshot,
+    NoBook,
+}
+
+impl SyncState {
+    pub fn of(buffered_order_book: Option<&BufferedOrderBook>) -> Self {
+        match buffered_order_book {
+            None => SyncState::NoBook,
+            Some(book) if book.state == BookState::AwaitingSnapshot => SyncState::AwaitingSnapshot,
+            Some(book) if !book.pending_updates.is_empty() => SyncState::Gap,
+            Some(_) => SyncState::Live,
+        }
+    }
+}
+
+// Why pending updates were dropped without being applied
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PendingPolicy {
+    // The buffer was full and has been cleared
+    Overflow,
+    // An update with the same seq_no was already waiting
+    DuplicateReplaced,
+    // The snapshot already contains them
+    CoveredBySnapshot,
+}
+
+// What the manager did with one record and the state the book was left in
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BookEvent {
+    pub record: RecordKind,
+    pub security_id: u64,
+    pub seq_no: u64,
+    pub decision: &'static str,
+    pub reason: Option<String>,
+    // Pending updates applied right after the record
+    pub replayed: usize,
+    pub dropped: Option<(PendingPolicy, usize)>,
+    pub state: SyncState,
+    pub pending: usize,
+}
+
+impl BookEvent {
+    pub fn to_json(&self) -> String {
+        let record = match self.record {
+            RecordKind::Snapshot => "snapshot",
+            RecordKind::Update => "update",
+        };
+        let state = match self.state {
+            SyncState::Live => "live",
+            SyncState::Gap => "gap",
+            SyncState::AwaitingSnapshot => "awaiting_snapshot",
+            SyncState::NoBook => "no_book",
+        };
+
+        let mut json = format!(
+            "{{\"record\":\"{}\",\"security_id\":{},\"seq_no\":{},\"decision\":\"{}\"",
+            record, self.security_id, self.seq_no, self.decision
+        );
+        if let Some(reason) = &self.reason {
+            let _ = write!(json, ",\"reason\":\"{}\"", escape_json(reason));
+        }
+        let _ = write!(json, ",\"replayed\":{}", self.replayed);
+        if let Some((policy, count)) = self.dropped {
+            let policy = match policy {
+                PendingPolicy::Overflow => "pending_overflow",
+                PendingPolicy::DuplicateReplaced => "duplicate_replaced",
+                PendingPolicy::CoveredBySnapshot => "covered_by_snapshot",
+            };
+            let _ = write!(json, ",\"policy\":\"{}\",\"dropped\":{}", policy, count);
+        }
+        let _ = write!(
+            json,
+            ",\"state\":\"{}\",\"pending\":{}}}",
+            state, self.pending
+        );
+        json
+    }
+}
+
+fn escape_json(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            c if (c as u32) < 0x20 => {
+                let _ = write!(escaped, "\\u{:04x}", c as u32);
+            }
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_to_json() {
+        let event = BookEvent {
+            record: RecordKind::Update,
+            security_id: 1,
+            seq_no: 5,
+            decision: "buffered",
+            reason: Some("expected seq_no 3".to_string()),
+            replayed: 0,
+            dropped: Some((PendingPolicy::Overflow, 10)),
+            state: SyncState::Gap,
+            pending: 1,
+        };
+
+        assert_eq!(
+            event.to_json(),
+            "{\"record\":\"update\",\"security_id\":1,\"seq_no\":5,\"decision\":\"buffered\",\
+             \"reason\":\"expected seq_no 3\",\"replayed\":0,\"policy\":\"pending_overflow\",\
+             \"dropped\":10,\"state\":\"gap\",\"pending\":1}"
+        );
+    }
+
+    #[test]
+    fn test_to_json_escapes_reason() {
+        let event = BookEvent {
+            record: RecordKind::Snapshot,
+            security_id: 1,
+            seq_no: 5,
+            decision: "rejected",
+            reason: Some("InvalidPrice(\"1.001\")\n".to_string()),
+            replayed: 0,
+            dropped: None,
+            state: SyncState::NoBook,
+            pending: 0,
+        };
+
+        assert_eq!(
+            event.to_json(),
+            "{\"record\":\"snapshot\",\"security_id\":1,\"seq_no\":5,\"decision\":\"rejected\",\
+             \"reason\":\"InvalidPrice(\\\"1.001\\\")\\n\",\"replayed\":0,\"state\":\"no_book\",\
+             \"pending\":0}"
+        );
+    }
+}
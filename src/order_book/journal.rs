@@ -0,0 +1,572 @@
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::Path;
+
+use crate::order_book::checkpoint::{CheckpointInterval, CheckpointSchedule};
+use crate::order_book::events::AppliedRecord;
+use crate::order_book::manager::Manager;
+use crate::parsing::order_book_snapshot::{
+    OrderBookSnapshot, OrderBookSnapshotParser, SNAPSHOT_DEPTH, SnapshotFormat,
+};
+use crate::parsing::order_book_update::{OrderBookUpdate, OrderBookUpdateParser, UpdateFormat};
+use crate::parsing::parser::{Endianness, Parser, ParserError};
+
+// A journal starts with its magic bytes and format version, followed by an entry per
+// applied record: a kind byte (0 for V1 snapshots of 5 levels a side, 4 for V2 ones of
+// any depth, 1 for V1 updates, 2 for V2 ones), the
+// index of the record in the apply order, the length and CRC32 of the record and the
+// record itself, in the layout of the files it was read from, so that it reads back the
+// same. Numbers are little-endian.
+// Entries are only ever appended, so a journal holds every record applied by the runs
+// that wrote to it, in order. Checksum entries (kind 3) hold the seq_no and
+// OrderBook::checksum of every book after the records before them, as u64 count then
+// security_id, seq_no and u32 checksum per book, with the index of the next record.
+
+const MAGIC: [u8; 4] = *b"L2JN";
+const VERSION: u32 = 1;
+const HEADER_SIZE: u64 = 8;
+const SNAPSHOT_ENTRY: u8 = 0;
+const UPDATE_V1_ENTRY: u8 = 1;
+const UPDATE_V2_ENTRY: u8 = 2;
+const CHECKSUMS_ENTRY: u8 = 3;
+const SNAPSHOT_V2_ENTRY: u8 = 4;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BookChecksum {
+    pub security_id: u64,
+    pub seq_no: u64,
+    pub checksum: u32,
+}
+
+impl BookChecksum {
+    fn of(manager: &Manager) -> Vec<Self> {
+        manager
+            .buffered_order_books
+            .values()
+            .map(|buffered_order_book| {
+                let book = &buffered_order_book.order_book;
+                Self {
+                    security_id: book.security_id,
+                    seq_no: book.seq_no,
+                    checksum: book.checksum(),
+                }
+            })
+            .collect()
+    }
+}
+
+pub enum JournalRecord {
+    Snapshot(OrderBookSnapshot),
+    Update(OrderBookUpdate),
+    Checksums(Vec<BookChecksum>),
+}
+
+pub struct JournalEntry {
+    // Position of the record in the apply order, from 0
+    pub index: u64,
+    pub record: JournalRecord,
+}
+
+// Appends the applied records to a journal, as a record hook of a Manager, and the
+// checksums of the books every interval when told about every record. Writing stops at
+// the first error, see take_error.
+pub struct JournalWriter<W: Write> {
+    writer: W,
+    next_index: u64,
+    entry: Vec<u8>,
+    checksums: Option<CheckpointSchedule>,
+    error: Option<io::Error>,
+}
+
+impl JournalWriter<BufWriter<File>> {
+    // Opens the journal for appending, creating it when missing. An entry cut short by a
+    // crash is dropped, so that the next ones can be read.
+    pub fn open(path: &Path) -> io::Result<Self> {
+        let file = OpenOptions::new()
+            .read(true)
+            .append(true)
+            .create(true)
+            .open(path)?;
+        if file.metadata()?.len() == 0 {
+            return Self::new(BufWriter::new(file));
+        }
+        let mut reader = JournalReader::new(BufReader::new(&file)).map_err(into_io_error)?;
+        let mut next_index = 0;
+        loop {
+            match reader.read_entry() {
+                Ok(JournalEntry {
+                    index,
+                    record: JournalRecord::Checksums(_),
+                }) => next_index = index,
+                Ok(entry) => next_index = entry.index + 1,
+                Err(ParserError::ExpectedEof) => break,
+                Err(ParserError::Io(e)) if e.kind() == io::ErrorKind::UnexpectedEof => {
+                    file.set_len(reader.offset)?;
+                    break;
+                }
+                Err(e) => return Err(into_io_error(e)),
+            }
+        }
+        Ok(Self {
+            writer: BufWriter::new(file),
+            next_index,
+            entry: Vec::new(),
+            checksums: None,
+            error: None,
+        })
+    }
+}
+
+impl<W: Write> JournalWriter<W> {
+    // Starts a new journal
+    pub fn new(mut writer: W) -> io::Result<Self> {
+        writer.write_all(&MAGIC)?;
+        writer.write_all(&VERSION.to_le_bytes())?;
+        Ok(Self {
+            writer,
+            next_index: 0,
+            entry: Vec::new(),
+            checksums: None,
+            error: None,
+        })
+    }
+
+    pub fn with_checksums(mut self, interval: CheckpointInterval) -> Self {
+        self.checksums = Some(CheckpointSchedule::new(interval));
+        self
+    }
+
+    // Index of the next record written
+    pub fn next_index(&self) -> u64 {
+        self.next_index
+    }
+
+    pub fn write_record(&mut self, record: &AppliedRecord) {
+        if self.error.is_some() {
+            return;
+        }
+        if let Err(e) = self.try_write_record(record) {
+            self.error = Some(e);
+        }
+    }
+
+    fn try_write_record(&mut self, record: &AppliedRecord) -> io::Result<()> {
+        self.entry.clear();
+        let kind = match record {
+            // Snapshots of the usual depth read back as V1 records
+            AppliedRecord::Snapshot(snapshot) if snapshot.depth() == SNAPSHOT_DEPTH => {
+                snapshot.write(&mut self.entry, SnapshotFormat::V1)?;
+                SNAPSHOT_ENTRY
+            }
+            AppliedRecord::Snapshot(snapshot) => {
+                snapshot.write(&mut self.entry, SnapshotFormat::V2)?;
+                SNAPSHOT_V2_ENTRY
+            }
+            // Only V2 records have capture timestamps
+            AppliedRecord::Update(update) if update.capture_timestamp.is_some() => {
+                update.write(&mut self.entry, UpdateFormat::V2)?;
+                UPDATE_V2_ENTRY
+            }
+            AppliedRecord::Update(update) => {
+                update.write(&mut self.entry, UpdateFormat::V1)?;
+                UPDATE_V1_ENTRY
+            }
+        };
+        self.write_entry(kind)?;
+        self.next_index += 1;
+        Ok(())
+    }
+
+    fn write_entry(&mut self, kind: u8) -> io::Result<()> {
+        self.writer.write_all(&[kind])?;
+        self.writer.write_all(&self.next_index.to_le_bytes())?;
+        self.writer
+            .write_all(&(self.entry.len() as u32).to_le_bytes())?;
+        self.writer
+            .write_all(&crc32fast::hash(&self.entry).to_le_bytes())?;
+        self.writer.write_all(&self.entry)
+    }
+
+    // Called after every record handed to the manager, writes the checksums of its books
+    // when they are due
+    pub fn on_record(&mut self, manager: &Manager) {
+        if let Some(checksums) = self.checksums.as_mut()
+            && checksums.on_record(manager.data_time())
+        {
+            self.write_checksums(manager);
+        }
+    }
+
+    pub fn write_checksums(&mut self, manager: &Manager) {
+        if self.error.is_some() {
+            return;
+        }
+        let checksums = BookChecksum::of(manager);
+        self.entry.clear();
+        self.entry
+            .extend_from_slice(&(checksums.len() as u64).to_le_bytes());
+        for book in &checksums {
+            self.entry
+                .extend_from_slice(&book.security_id.to_le_bytes());
+            self.entry.extend_from_slice(&book.seq_no.to_le_bytes());
+            self.entry.extend_from_slice(&book.checksum.to_le_bytes());
+        }
+        if let Err(e) = self.write_entry(CHECKSUMS_ENTRY) {
+            self.error = Some(e);
+        }
+        if let Some(checksums) = self.checksums.as_mut() {
+            checksums.on_checkpoint(manager.data_time());
+        }
+    }
+
+    // The error that stopped the writing, if any
+    pub fn take_error(&mut self) -> Option<io::Error> {
+        self.error.take()
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+fn into_io_error(e: ParserError) -> io::Error {
+    match e {
+        ParserError::Io(e) => e,
+        ParserError::ExpectedEof => io::Error::from(io::ErrorKind::UnexpectedEof),
+        ParserError::Custom(message) => io::Error::new(io::ErrorKind::InvalidData, message),
+    }
+}
+
+pub struct JournalReader<R: Read> {
+    reader: R,
+    snapshot_parser: OrderBookSnapshotParser,
+    v2_snapshot_parser: OrderBookSnapshotParser,
+    v1_update_parser: OrderBookUpdateParser,
+    v2_update_parser: OrderBookUpdateParser,
+    entry: Vec<u8>,
+    // Length of the header and of the entries read so far
+    offset: u64,
+}
+
+impl<R: Read> JournalReader<R> {
+    pub fn new(mut reader: R) -> Result<Self, ParserError> {
+        let mut header = [0; HEADER_SIZE as usize];
+        reader.read_exact(&mut header).map_err(ParserError::Io)?;
+        if header[..MAGIC.len()] != MAGIC {
+            return Err(ParserError::Custom("Not a journal".to_string()));
+        }
+        let version = u32::from_le_bytes(header[MAGIC.len()..].try_into().unwrap());
+        if version != VERSION {
+            return Err(ParserError::Custom(format!(
+                "Unsupported journal version: {}",
+                version
+            )));
+        }
+        Ok(Self {
+            reader,
+            snapshot_parser: OrderBookSnapshotParser::new(Endianness::Little),
+            v2_snapshot_parser: OrderBookSnapshotParser::new(Endianness::Little)
+                .with_format(SnapshotFormat::V2),
+            v1_update_parser: OrderBookUpdateParser::new(UpdateFormat::V1),
+            v2_update_parser: OrderBookUpdateParser::new(UpdateFormat::V2),
+            entry: Vec::new(),
+            offset: HEADER_SIZE,
+        })
+    }
+
+    // The next entry, ExpectedEof at the end of the journal and an UnexpectedEof I/O
+    // error for an entry cut short
+    pub fn read_entry(&mut self) -> Result<JournalEntry, ParserError> {
+        let mut kind = [0; 1];
+        match self.reader.read_exact(&mut kind) {
+            Ok(_) => (),
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => {
+                return Err(ParserError::ExpectedEof);
+            }
+            Err(e) => return Err(ParserError::Io(e)),
+        }
+        let mut header = [0; 16];
+        self.reader
+            .read_exact(&mut header)
+            .map_err(ParserError::Io)?;
+        let index = u64::from_le_bytes(header[..8].try_into().unwrap());
+        let len = u32::from_le_bytes(header[8..12].try_into().unwrap());
+        let crc = u32::from_le_bytes(header[12..].try_into().unwrap());
+        self.entry.resize(len as usize, 0);
+        self.reader
+            .read_exact(&mut self.entry)
+            .map_err(ParserError::Io)?;
+        if crc32fast::hash(&self.entry) != crc {
+            return Err(ParserError::Custom(format!(
+                "Checksum mismatch of the journal entry {}",
+                index
+            )));
+        }
+        let record = match kind[0] {
+            SNAPSHOT_ENTRY => {
+                JournalRecord::Snapshot(self.snapshot_parser.read(&mut self.entry.as_slice())?)
+            }
+            SNAPSHOT_V2_ENTRY => {
+                JournalRecord::Snapshot(self.v2_snapshot_parser.read(&mut self.entry.as_slice())?)
+            }
+            UPDATE_V1_ENTRY => {
+                JournalRecord::Update(self.v1_update_parser.read(&mut self.entry.as_slice())?)
+            }
+            UPDATE_V2_ENTRY => {
+                JournalRecord::Update(self.v2_update_parser.read(&mut self.entry.as_slice())?)
+            }
+            CHECKSUMS_ENTRY => JournalRecord::Checksums(read_checksums(&self.entry)?),
+            kind => {
+                return Err(ParserError::Custom(format!(
+                    "Unknown journal entry kind: {}",
+                    kind
+                )));
+            }
+        };
+        self.offset += 1 + header.len() as u64 + len as u64;
+        Ok(JournalEntry { index, record })
+    }
+}
+
+fn read_checksums(entry: &[u8]) -> Result<Vec<BookChecksum>, ParserError> {
+    const BOOK_SIZE: usize = 20;
+    let invalid = || ParserError::Custom("Invalid journal checksums".to_string());
+    let (count, books) = entry.split_at_checked(8).ok_or_else(invalid)?;
+    let count = u64::from_le_bytes(count.try_into().unwrap());
+    if books.len() as u64 != count.saturating_mul(BOOK_SIZE as u64) {
+        return Err(invalid());
+    }
+    Ok(books
+        .chunks_exact(BOOK_SIZE)
+        .map(|book| BookChecksum {
+            security_id: u64::from_le_bytes(book[..8].try_into().unwrap()),
+            seq_no: u64::from_le_bytes(book[8..16].try_into().unwrap()),
+            checksum: u32::from_le_bytes(book[16..].try_into().unwrap()),
+        })
+        .collect())
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct JournalReplayStats {
+    pub records: u64,
+    pub verified_checksums: u64,
+}
+
+// Applies the records of a journal to the manager in the order they were applied, and
+// checks the books against the checksums stored along. Reconstructs the books exactly
+// when the manager starts as the one that wrote the journal did, with the same settings:
+// every record then applies again. Stops at the first record that doesn't, or at the
+// first book that doesn't match its checksum. An entry cut short by a crash ends the
+// journal.
+pub fn replay_journal<R: Read>(
+    reader: R,
+    manager: &mut Manager,
+) -> Result<JournalReplayStats, ParserError> {
+    let mut reader = JournalReader::new(reader)?;
+    let mut stats = JournalReplayStats::default();
+    let mut next_index = 0;
+    loop {
+        let entry = match reader.read_entry() {
+            Ok(entry) => entry,
+            Err(ParserError::ExpectedEof) => return Ok(stats),
+            Err(ParserError::Io(e)) if e.kind() == io::ErrorKind::UnexpectedEof => {
+                return Ok(stats);
+            }
+            Err(e) => return Err(e),
+        };
+        if entry.index != next_index {
+            return Err(ParserError::Custom(format!(
+                "The journal entry {} found where {} was expected",
+                entry.index, next_index
+            )));
+        }
+        let outcome = match entry.record {
+            JournalRecord::Snapshot(snapshot) => manager.apply_snapshot(&snapshot),
+            JournalRecord::Update(update) => manager.apply_update(update),
+            JournalRecord::Checksums(checksums) => {
+                verify_checksums(manager, &checksums)?;
+                stats.verified_checksums += 1;
+                continue;
+            }
+        };
+        if !outcome.is_applied() {
+            return Err(ParserError::Custom(format!(
+                "The journal record {} was not applied again: {:?}",
+                entry.index, outcome
+            )));
+        }
+        stats.records += 1;
+        next_index += 1;
+    }
+}
+
+fn verify_checksums(manager: &Manager, checksums: &[BookChecksum]) -> Result<(), ParserError> {
+    let books = BookChecksum::of(manager);
+    if books == checksums {
+        return Ok(());
+    }
+    let mismatch = match checksums.iter().find(|book| !books.contains(book)) {
+        Some(expected) => match books
+            .iter()
+            .find(|book| book.security_id == expected.security_id)
+        {
+            Some(book) => format!(
+                "The book {} is at seq_no {} with checksum {:08x}, the journal expects seq_no {} with checksum {:08x}",
+                book.security_id, book.seq_no, book.checksum, expected.seq_no, expected.checksum
+            ),
+            None => format!("The journal expects a book {}", expected.security_id),
+        },
+        None => format!(
+            "{} books, the journal expects {}",
+            books.len(),
+            checksums.len()
+        ),
+    };
+    Err(ParserError::Custom(mismatch))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::batched_deque::batched_deque::BatchedDeque;
+    use crate::order_book::units::Qty;
+    use crate::parsing::order_book_snapshot::Level as SnapshotLevel;
+    use crate::parsing::order_book_snapshot::test_support::create_test_snapshot;
+    use crate::parsing::order_book_update::{Level as UpdateLevel, Side};
+    use std::cell::RefCell;
+    use std::env;
+    use std::fs;
+    use std::rc::Rc;
+
+    fn create_test_update(security_id: u64, seq_no: u64) -> OrderBookUpdate {
+        let deque = BatchedDeque::new(4);
+        let level = UpdateLevel {
+            side: Side::Bid,
+            price: 99.5,
+            qty: Qty(seq_no),
+            metadata: None,
+        };
+        OrderBookUpdate {
+            timestamp: 1627846266,
+            capture_timestamp: None,
+            seq_no,
+            security_id,
+            thinned_from: None,
+            updates: deque
+                .push_back_batch([Ok::<_, ()>(level)].into_iter())
+                .unwrap(),
+        }
+    }
+
+    // The index, kind, security_id and seq_no of every entry of the journal, the number of
+    // books for checksums
+    fn read_journal(data: &[u8]) -> Vec<(u64, &'static str, u64, u64)> {
+        let mut reader = JournalReader::new(data).unwrap();
+        let mut entries = Vec::new();
+        loop {
+            match reader.read_entry() {
+                Ok(JournalEntry { index, record }) => entries.push(match record {
+                    JournalRecord::Snapshot(snapshot) => {
+                        (index, "snapshot", snapshot.security_id, snapshot.seq_no)
+                    }
+                    JournalRecord::Update(update) => {
+                        (index, "update", update.security_id, update.seq_no)
+                    }
+                    JournalRecord::Checksums(checksums) => {
+                        (index, "checksums", checksums.len() as u64, 0)
+                    }
+                }),
+                Err(ParserError::ExpectedEof) => return entries,
+                Err(e) => panic!("{:?}", e),
+            }
+        }
+    }
+
+    #[test]
+    fn test_journal_in_apply_order() {
+        let journal = Rc::new(RefCell::new(JournalWriter::new(Vec::new()).unwrap()));
+        let mut manager = Manager::default();
+        let hook_journal = journal.clone();
+        manager.add_record_hook(Box::new(move |record: &AppliedRecord| {
+            hook_journal.borrow_mut().write_record(record)
+        }));
+        manager.apply_snapshot(&create_test_snapshot(1001, 100));
+        manager.apply_update(create_test_update(1001, 102));
+        // Old, not applied
+        manager.apply_update(create_test_update(1001, 99));
+        manager.apply_update(create_test_update(1001, 101));
+
+        let data = journal.borrow().writer.clone();
+        assert_eq!(
+            read_journal(&data),
+            vec![
+                (0, "snapshot", 1001, 100),
+                (1, "update", 1001, 101),
+                (2, "update", 1001, 102),
+            ]
+        );
+
+        // Reopened after a crash cut the last entry short
+        let path = env::temp_dir().join(format!("order_book_journal_{}", std::process::id()));
+        fs::write(&path, &data[..data.len() - 1]).unwrap();
+        let mut journal = JournalWriter::open(&path).unwrap();
+        assert_eq!(journal.next_index(), 2);
+        journal.write_record(&AppliedRecord::Snapshot(&create_test_snapshot(1002, 7)));
+        journal.flush().unwrap();
+        assert!(journal.take_error().is_none());
+        let data = fs::read(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(read_journal(&data)[2], (2, "snapshot", 1002, 7));
+    }
+
+    #[test]
+    fn test_replay_journal() {
+        let journal = Rc::new(RefCell::new(
+            JournalWriter::new(Vec::new())
+                .unwrap()
+                .with_checksums(CheckpointInterval::Records(2)),
+        ));
+        let mut manager = Manager::default();
+        let hook_journal = journal.clone();
+        manager.add_record_hook(Box::new(move |record: &AppliedRecord| {
+            hook_journal.borrow_mut().write_record(record)
+        }));
+        manager.apply_snapshot(&create_test_snapshot(1001, 100));
+        journal.borrow_mut().on_record(&manager);
+        for seq_no in [102, 101, 103] {
+            manager.apply_update(create_test_update(1001, seq_no));
+            journal.borrow_mut().on_record(&manager);
+        }
+        // Deeper than V1 snapshot entries, written as a V2 one
+        let mut snapshot = create_test_snapshot(1002, 7);
+        snapshot.bids.push(SnapshotLevel {
+            price: 95.0,
+            qty: Qty(60),
+        });
+        manager.apply_snapshot(&snapshot);
+        journal.borrow_mut().write_checksums(&manager);
+
+        let data = journal.borrow().writer.clone();
+        let mut replayed = Manager::default();
+        let stats = replay_journal(data.as_slice(), &mut replayed).unwrap();
+        assert_eq!(
+            stats,
+            JournalReplayStats {
+                records: 5,
+                verified_checksums: 3,
+            }
+        );
+        assert_eq!(replayed.to_string(), manager.to_string());
+
+        // Replayed on books the journal didn't start from
+        let mut replayed = Manager::default();
+        let mut snapshot = create_test_snapshot(1002, 1);
+        snapshot.bids[0].qty = Qty(11);
+        replayed.apply_snapshot(&snapshot);
+        let Err(ParserError::Custom(message)) = replay_journal(data.as_slice(), &mut replayed)
+        else {
+            panic!("The replay should fail");
+        };
+        assert_eq!(message, "2 books, the journal expects 1");
+    }
+}
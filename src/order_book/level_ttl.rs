@@ -0,0 +1,221 @@
+use num_traits::FromPrimitive;
+use rust_decimal::Decimal;
+use std::collections::{BTreeSet, HashMap};
+use std::str::FromStr;
+
+use crate::order_book::order_book::{OrderBook, Side};
+use crate::order_book::units::Qty;
+use crate::parsing::order_book_update::OrderBookUpdate;
+
+// Quote lifetime of one security, as SECURITY_ID:MILLIS
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LevelTtlConfig {
+    pub security_id: u64,
+    pub ttl_millis: u64,
+}
+
+impl FromStr for LevelTtlConfig {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (security_id, ttl_millis) = s
+            .split_once(':')
+            .ok_or_else(|| format!("Expected SECURITY_ID:MILLIS, got {}", s))?;
+        Ok(LevelTtlConfig {
+            security_id: security_id
+                .parse()
+                .map_err(|e| format!("Invalid security id {}: {}", security_id, e))?,
+            ttl_millis: ttl_millis
+                .parse()
+                .map_err(|e| format!("Invalid TTL {}: {}", ttl_millis, e))?,
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ExpiredLevel {
+    pub security_id: u64,
+    pub side: Side,
+    pub price: Decimal,
+    pub qty: Qty,
+    pub refreshed_at: u64,
+}
+
+// Tracks when every level of a book was last set, for feeds whose quotes expire unless
+// refreshed. Time is the exchange timestamp of the records, in millis.
+#[derive(Debug)]
+pub struct LevelTtl {
+    pub ttl_millis: u64,
+    refreshed: HashMap<(Side, Decimal), u64>,
+    // Same levels ordered by refresh time, oldest first
+    by_time: BTreeSet<(u64, Side, Decimal)>,
+}
+
+impl LevelTtl {
+    pub fn new(ttl_millis: u64) -> Self {
+        Self {
+            ttl_millis,
+            refreshed: HashMap::new(),
+            by_time: BTreeSet::new(),
+        }
+    }
+
+    fn forget(&mut self, side: Side, price: Decimal) {
+        if let Some(refreshed_at) = self.refreshed.remove(&(side, price)) {
+            self.by_time.remove(&(refreshed_at, side, price));
+        }
+    }
+
+    fn refresh(&mut self, side: Side, price: Decimal, at: u64) {
+        self.forget(side, price);
+        self.refreshed.insert((side, price), at);
+        self.by_time.insert((at, side, price));
+    }
+
+    // Every level of the book was set by the snapshot
+    pub fn on_snapshot(&mut self, book: &OrderBook) {
+        self.refreshed.clear();
+        self.by_time.clear();
+        for (side, levels) in [(Side::Bid, &book.bids), (Side::Ask, &book.asks)] {
+            for price in levels.keys() {
+                self.refresh(side, *price, book.timestamp);
+            }
+        }
+    }
+
+    // Called with the book the update has just been applied to
+    pub fn on_update(&mut self, update: &OrderBookUpdate, book: &OrderBook) {
+        let _ = update.updates.for_each(|level| {
+            let Some(price) = Decimal::from_f64(level.price) else {
+                return Ok::<(), ()>(());
+            };
+            let levels = match level.side {
+                Side::Bid => &book.bids,
+                Side::Ask => &book.asks,
+            };
+            if levels.contains_key(&price) {
+                self.refresh(level.side, price, update.timestamp);
+            } else {
+                self.forget(level.side, price);
+            }
+            Ok(())
+        });
+    }
+
+    // Time after which the oldest level expires
+    pub fn next_expiry(&self) -> Option<u64> {
+        self.by_time
+            .first()
+            .map(|(refreshed_at, _, _)| refreshed_at.saturating_add(self.ttl_millis))
+    }
+
+    // Removes the levels not refreshed for longer than the TTL at `now`
+    pub fn expire(&mut self, book: &mut OrderBook, now: u64) -> Vec<ExpiredLevel> {
+        let mut expired = Vec::new();
+        while let Some(&(refreshed_at, side, price)) = self.by_time.first()
+            && refreshed_at.saturating_add(self.ttl_millis) < now
+        {
+            self.forget(side, price);
+            if let Some(qty) = book.remove_level(side, price) {
+                expired.push(ExpiredLevel {
+                    security_id: book.security_id,
+                    side,
+                    price,
+                    qty,
+                    refreshed_at,
+                });
+            }
+        }
+        expired
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::batched_deque::batched_deque::BatchedDeque;
+    use crate::parsing::order_book_snapshot::test_support::create_test_snapshot;
+    use crate::parsing::order_book_update::Level as UpdateLevel;
+    use rust_decimal::dec;
+
+    fn create_test_update(
+        deque: &BatchedDeque<UpdateLevel>,
+        security_id: u64,
+        seq_no: u64,
+        timestamp: u64,
+        levels: Vec<(Side, f64, u64)>,
+    ) -> OrderBookUpdate {
+        let levels = levels.into_iter().map(|(side, price, qty)| {
+            Ok::<_, ()>(UpdateLevel {
+                side,
+                price,
+                qty: Qty(qty),
+                metadata: None,
+            })
+        });
+        OrderBookUpdate {
+            timestamp,
+            capture_timestamp: None,
+            seq_no,
+            security_id,
+            thinned_from: None,
+            updates: deque.push_back_batch(levels).unwrap(),
+        }
+    }
+
+    #[test]
+    fn test_parse_config() {
+        assert_eq!(
+            "1001:5000".parse::<LevelTtlConfig>(),
+            Ok(LevelTtlConfig {
+                security_id: 1001,
+                ttl_millis: 5000
+            })
+        );
+        assert!("1001".parse::<LevelTtlConfig>().is_err());
+        assert!("1001:soon".parse::<LevelTtlConfig>().is_err());
+    }
+
+    #[test]
+    fn test_expire_levels_not_refreshed() {
+        let deque = BatchedDeque::new(10);
+        let mut book = OrderBook::new(&create_test_snapshot(1001, 100)).unwrap();
+        let mut level_ttl = LevelTtl::new(1000);
+        level_ttl.on_snapshot(&book);
+        let t0 = book.timestamp;
+        assert_eq!(level_ttl.next_expiry(), Some(t0 + 1000));
+
+        // Refreshes the best bid, removes the best ask and adds a bid
+        let update = create_test_update(
+            &deque,
+            1001,
+            101,
+            t0 + 500,
+            vec![
+                (Side::Bid, 100.0, 11),
+                (Side::Ask, 101.0, 0),
+                (Side::Bid, 99.5, 5),
+            ],
+        );
+        book.apply_update(&update).unwrap();
+        level_ttl.on_update(&update, &book);
+
+        // Still within the TTL
+        assert!(level_ttl.expire(&mut book, t0 + 1000).is_empty());
+
+        let expired = level_ttl.expire(&mut book, t0 + 1001);
+        assert_eq!(expired.len(), 8);
+        assert!(expired.iter().all(|level| level.refreshed_at == t0));
+        assert_eq!(
+            book.bids.iter().collect::<Vec<_>>(),
+            vec![(&dec!(99.5), &Qty(5)), (&dec!(100), &Qty(11))]
+        );
+        assert!(book.asks.is_empty());
+        assert_eq!(level_ttl.next_expiry(), Some(t0 + 1500));
+
+        let expired = level_ttl.expire(&mut book, t0 + 1501);
+        assert_eq!(expired.len(), 2);
+        assert!(book.bids.is_empty());
+        assert_eq!(level_ttl.next_expiry(), None);
+    }
+}
@@ -0,0 +1,72 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use crate::order_book::errors::Errors;
+use crate::order_book::level_ttl::ExpiredLevel;
+use crate::order_book::manager::GapInfo;
+use crate::order_book::order_book::OrderBook;
+
+// Callbacks from a Manager for embedders reacting to the books as they change.
+// Every method does nothing by default, so a listener only implements what it needs.
+pub trait BookListener {
+    fn on_snapshot_applied(&mut self, _book: &OrderBook) {}
+
+    // Also called for every pending update applied after the record that closed its gap
+    fn on_update_applied(&mut self, _book: &OrderBook) {}
+
+    // An update was buffered until the missing seq_nos or a snapshot arrive
+    fn on_gap_detected(&mut self, _security_id: u64, _gap_info: &GapInfo) {}
+
+    // A record failed validation and was not applied
+    fn on_error(&mut self, _security_id: u64, _seq_no: u64, _error: &Errors) {}
+
+    // An update older than its book was applied under TimestampPolicy::Warn, after
+    // on_update_applied. Rejected ones go to on_error.
+    fn on_timestamp_regression(&mut self, _book: &OrderBook, _previous_timestamp: u64) {}
+
+    // Levels not refreshed within the TTL of the security were removed from the book
+    fn on_levels_expired(&mut self, _book: &OrderBook, _expired: &[ExpiredLevel]) {}
+
+    // The book was removed as stale, see Manager::evict_stale
+    fn on_book_evicted(&mut self, _book: &OrderBook) {}
+
+    // Pending updates waited longer than the TTL and were dropped, see
+    // Manager::set_pending_ttl
+    fn on_pending_expired(&mut self, _security_id: u64, _seq_nos: &[u64]) {}
+}
+
+// So that the caller can keep a handle on a registered listener
+impl<L: BookListener> BookListener for Rc<RefCell<L>> {
+    fn on_snapshot_applied(&mut self, book: &OrderBook) {
+        self.borrow_mut().on_snapshot_applied(book)
+    }
+
+    fn on_update_applied(&mut self, book: &OrderBook) {
+        self.borrow_mut().on_update_applied(book)
+    }
+
+    fn on_gap_detected(&mut self, security_id: u64, gap_info: &GapInfo) {
+        self.borrow_mut().on_gap_detected(security_id, gap_info)
+    }
+
+    fn on_error(&mut self, security_id: u64, seq_no: u64, error: &Errors) {
+        self.borrow_mut().on_error(security_id, seq_no, error)
+    }
+
+    fn on_timestamp_regression(&mut self, book: &OrderBook, previous_timestamp: u64) {
+        self.borrow_mut()
+            .on_timestamp_regression(book, previous_timestamp)
+    }
+
+    fn on_levels_expired(&mut self, book: &OrderBook, expired: &[ExpiredLevel]) {
+        self.borrow_mut().on_levels_expired(book, expired)
+    }
+
+    fn on_book_evicted(&mut self, book: &OrderBook) {
+        self.borrow_mut().on_book_evicted(book)
+    }
+
+    fn on_pending_expired(&mut self, security_id: u64, seq_nos: &[u64]) {
+        self.borrow_mut().on_pending_expired(security_id, seq_nos)
+    }
+}
@@ -1,36 +1,944 @@
-use std::collections::BTreeMap;
+use rust_decimal::Decimal;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
 use std::fmt::Display;
+use std::io::{self, Read, Write};
+use std::str::FromStr;
 
-use crate::order_book::buffered_order_book::BufferedOrderBook;
-use crate::order_book::errors::Errors;
-use crate::order_book::order_book::OrderBook;
-use crate::parsing::order_book_snapshot::OrderBookSnapshot;
-use crate::parsing::order_book_update::OrderBookUpdate;
+use crate::clock::Clock;
+use crate::order_book::admin::AdminCommand;
+use crate::order_book::buffered_order_book::{
+    BookState, BufferedOrderBook, GapStats, OverflowPolicy,
+};
+use crate::order_book::checkpoint::{CheckpointInterval, CheckpointSchedule};
+use crate::order_book::errors::{Errors, UpdateMessageInfo};
+use crate::order_book::events::{
+    AppliedRecord, BookEvent, EventSink, PendingPolicy, RecordKind, SyncState,
+};
+use crate::order_book::level_ttl::{ExpiredLevel, LevelTtl};
+use crate::order_book::listener::BookListener;
+use crate::order_book::order_book::{LevelPolicy, OrderBook, QuarantinedLevel, TimestampPolicy};
+use crate::order_book::security_registry::SecurityRegistry;
+use crate::parsing::order_book_snapshot::{OrderBookSnapshot, SNAPSHOT_DEPTH, SnapshotFormat};
+use crate::parsing::order_book_update::{OrderBookUpdate, OrderBookUpdateParser, UpdateFormat};
+use crate::parsing::parser::{Parser, ParserError};
+use crate::parsing::pre_scan::CapacityHints;
+
+pub type ApplyHook = Box<dyn FnMut(&OrderBook)>;
+pub type RecordHook = Box<dyn FnMut(&AppliedRecord)>;
+pub type RecoveryHook = Box<dyn FnMut(u64, &GapInfo)>;
+pub type SnapshotSink = Box<dyn FnMut(&OrderBookSnapshot)>;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct GapInfo {
+    pub expected_seq_no: u64,
+    pub received_seq_no: u64,
+    // The book was resynced, so any update is kept until the next snapshot
+    pub awaiting_snapshot: bool,
+}
+
+// Result of handing a record to the manager. Gaps and stale or unknown records are part
+// of the normal flow, only Rejected reports a record that failed validation.
+#[derive(Debug)]
+pub enum ManagerOutcome {
+    Applied,
+    Buffered(GapInfo),
+    IgnoredOld,
+    IgnoredUnknownSecurity,
+    Rejected(Errors),
+}
+
+impl ManagerOutcome {
+    pub fn is_applied(&self) -> bool {
+        matches!(self, ManagerOutcome::Applied)
+    }
+
+    fn from_result(result: Result<(), Errors>, gap_info: Option<GapInfo>) -> Self {
+        match (result, gap_info) {
+            (Ok(()), _) => ManagerOutcome::Applied,
+            (Err(Errors::SequenceNumberGap(_)), Some(gap_info)) => {
+                ManagerOutcome::Buffered(gap_info)
+            }
+            (Err(Errors::AwaitingSnapshot(_)), Some(gap_info)) => {
+                ManagerOutcome::Buffered(GapInfo {
+                    awaiting_snapshot: true,
+                    ..gap_info
+                })
+            }
+            (Err(Errors::OldSequenceNumber(_)), _) => ManagerOutcome::IgnoredOld,
+            (Err(Errors::OrderBookNotFound(_)), _) => ManagerOutcome::IgnoredUnknownSecurity,
+            (Err(e), _) => ManagerOutcome::Rejected(e),
+        }
+    }
+}
 
 #[derive(Default)]
 pub struct Manager {
     pub buffered_order_books: BTreeMap<u64, BufferedOrderBook>,
+    observers: ApplyObservers,
+    capacity_hints: Option<CapacityHints>,
+    event_sink: Option<EventSink>,
+    book_settings: BookSettings,
+    registry: SecurityRegistry,
+    quarantined: Vec<QuarantinedLevel>,
+    // security_id -> TTL of its levels in millis
+    level_ttls: BTreeMap<u64, u64>,
+    // Latest exchange timestamp of the records, drives the level expiry
+    data_time: u64,
+    gap_recovery: Option<GapRecovery>,
+    stale_eviction: Option<StaleEviction>,
+    periodic_snapshots: Option<PeriodicSnapshots>,
+    // Levels of each side of the snapshots written, SNAPSHOT_DEPTH unless set
+    snapshot_depth: Option<usize>,
+    snapshot_format: SnapshotFormat,
+    pending_expiry: Option<PendingExpiry>,
+}
+
+// How long an update may stay pending before it is dropped
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PendingTtl {
+    // Millis between the timestamp of the update and the latest record
+    EventMillis(u64),
+    // Millis of wall time since the update was buffered
+    WallMillis(u64),
+}
+
+// "event:MILLIS" or "wall:MILLIS"
+impl FromStr for PendingTtl {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("Invalid pending TTL: {}", s);
+        let (clock, millis) = s.split_once(':').ok_or_else(invalid)?;
+        let millis = millis.parse().map_err(|_| invalid())?;
+        match clock {
+            "event" => Ok(PendingTtl::EventMillis(millis)),
+            "wall" => Ok(PendingTtl::WallMillis(millis)),
+            _ => Err(invalid()),
+        }
+    }
+}
+
+struct PendingExpiry {
+    ttl: PendingTtl,
+    clock: Box<dyn Clock>,
+    // Wall time each pending update was buffered at, by security_id and seq_no
+    buffered_at: HashMap<(u64, u64), u64>,
+    // No pending update can expire before this time
+    next_check: u64,
+}
+
+impl PendingExpiry {
+    fn now(&self, data_time: u64) -> u64 {
+        match self.ttl {
+            PendingTtl::EventMillis(_) => data_time,
+            PendingTtl::WallMillis(_) => self.clock.now_millis(),
+        }
+    }
+
+    fn ttl_millis(&self) -> u64 {
+        match self.ttl {
+            PendingTtl::EventMillis(millis) | PendingTtl::WallMillis(millis) => millis,
+        }
+    }
+}
+
+struct PeriodicSnapshots {
+    schedule: CheckpointSchedule,
+    sink: SnapshotSink,
+}
+
+struct StaleEviction {
+    max_age: u64,
+    // No book can be stale before this data time
+    next_check: u64,
+}
+
+// Settings of the manager applied to every book
+#[derive(Debug, Clone, Copy, Default)]
+struct BookSettings {
+    level_policy: LevelPolicy,
+    overflow_policy: OverflowPolicy,
+    max_depth: Option<usize>,
+    max_price_deviation: Option<Decimal>,
+    allow_crossed: bool,
+    timestamp_policy: TimestampPolicy,
+}
+
+impl BookSettings {
+    fn apply_to(&self, buffered_order_book: &mut BufferedOrderBook) {
+        buffered_order_book.level_policy = self.level_policy;
+        buffered_order_book.overflow_policy = self.overflow_policy;
+        let order_book = &mut buffered_order_book.order_book;
+        order_book.set_max_depth(self.max_depth);
+        order_book.set_max_price_deviation(self.max_price_deviation);
+        order_book.set_allow_crossed(self.allow_crossed);
+        order_book.set_timestamp_policy(self.timestamp_policy);
+    }
+}
+
+struct GapRecovery {
+    max_gap: u64,
+    hook: RecoveryHook,
+}
+
+// Everything that has to know when a book changed
+#[derive(Default)]
+struct ApplyObservers {
+    hooks: Vec<ApplyHook>,
+    record_hooks: Vec<RecordHook>,
+    listeners: Vec<Box<dyn BookListener>>,
+    // Securities changed since the last drain_dirty
+    dirty: BTreeSet<u64>,
+    applied_count: usize,
+    // The next book applied is a snapshot, the ones replayed after it are updates
+    applying_snapshot: bool,
+}
+
+impl ApplyObservers {
+    fn on_applied(&mut self, book: &OrderBook, record: AppliedRecord) {
+        self.applied_count += 1;
+        self.dirty.insert(book.security_id);
+        for hook in self.record_hooks.iter_mut() {
+            hook(&record);
+        }
+        for hook in self.hooks.iter_mut() {
+            hook(book);
+        }
+        let applying_snapshot = std::mem::take(&mut self.applying_snapshot);
+        for listener in self.listeners.iter_mut() {
+            if applying_snapshot {
+                listener.on_snapshot_applied(book);
+            } else {
+                listener.on_update_applied(book);
+                if let Some(previous_timestamp) = book.regressed_from() {
+                    listener.on_timestamp_regression(book, previous_timestamp);
+                }
+            }
+        }
+    }
+
+    fn on_expired(&mut self, book: &OrderBook, expired: &[ExpiredLevel]) {
+        self.dirty.insert(book.security_id);
+        for listener in self.listeners.iter_mut() {
+            listener.on_levels_expired(book, expired);
+        }
+    }
+
+    fn on_evicted(&mut self, book: &OrderBook) {
+        self.dirty.insert(book.security_id);
+        for listener in self.listeners.iter_mut() {
+            listener.on_book_evicted(book);
+        }
+    }
+
+    fn on_pending_expired(&mut self, security_id: u64, seq_nos: &[u64]) {
+        for listener in self.listeners.iter_mut() {
+            listener.on_pending_expired(security_id, seq_nos);
+        }
+    }
+
+    fn on_outcome(&mut self, security_id: u64, seq_no: u64, outcome: &ManagerOutcome) {
+        for listener in self.listeners.iter_mut() {
+            match outcome {
+                ManagerOutcome::Buffered(gap_info) => {
+                    listener.on_gap_detected(security_id, gap_info)
+                }
+                ManagerOutcome::Rejected(e) => listener.on_error(security_id, seq_no, e),
+                _ => {}
+            }
+        }
+    }
 }
 
 impl Manager {
-    pub fn apply_update(&mut self, update: OrderBookUpdate) -> Result<(), Errors> {
-        if let Some(order_book) = self.buffered_order_books.get_mut(&update.security_id) {
-            order_book.apply_update(update)
-        } else {
-            Err(Errors::OrderBookNotFound)
+    // Registers a hook called with the book every time a snapshot or an update
+    // (including a buffered one) has been applied to it
+    pub fn add_apply_hook(&mut self, hook: ApplyHook) {
+        self.observers.hooks.push(hook);
+    }
+
+    // Registers a hook called with every record applied to a book, in the order they
+    // are applied: buffered updates follow the record that closed their gap
+    pub fn add_record_hook(&mut self, hook: RecordHook) {
+        self.observers.record_hooks.push(hook);
+    }
+
+    pub fn add_listener(&mut self, listener: Box<dyn BookListener>) {
+        self.observers.listeners.push(listener);
+    }
+
+    // Sizes the pending buffers of the books created from now on
+    pub fn set_capacity_hints(&mut self, capacity_hints: CapacityHints) {
+        self.capacity_hints = Some(capacity_hints);
+    }
+
+    // Reports what happened to every record handed to the manager, see BookEvent
+    pub fn set_event_sink(&mut self, event_sink: EventSink) {
+        self.event_sink = Some(event_sink);
+    }
+
+    fn apply_book_settings(&mut self) {
+        for buffered_order_book in self.buffered_order_books.values_mut() {
+            self.book_settings.apply_to(buffered_order_book);
         }
     }
 
-    pub fn apply_snapshot(&mut self, snapshot: &OrderBookSnapshot) -> Result<(), Errors> {
-        match self.buffered_order_books.entry(snapshot.security_id) {
+    // Applies to the existing books and the ones created from now on
+    pub fn set_level_policy(&mut self, level_policy: LevelPolicy) {
+        self.book_settings.level_policy = level_policy;
+        self.apply_book_settings();
+    }
+
+    // Applies to the existing books and the ones created from now on
+    pub fn set_overflow_policy(&mut self, overflow_policy: OverflowPolicy) {
+        self.book_settings.overflow_policy = overflow_policy;
+        self.apply_book_settings();
+    }
+
+    // Applies to the existing books and the ones created from now on, see
+    // OrderBook::set_max_depth
+    pub fn set_max_depth(&mut self, max_depth: Option<usize>) {
+        self.book_settings.max_depth = max_depth;
+        self.apply_book_settings();
+    }
+
+    // Checks the prices of the records of the registered securities against their
+    // reference data, and prints their symbols with the books
+    pub fn set_security_registry(&mut self, registry: SecurityRegistry) {
+        for (security_id, buffered_order_book) in self.buffered_order_books.iter_mut() {
+            buffered_order_book
+                .order_book
+                .set_security_info(registry.get(*security_id).cloned());
+        }
+        self.registry = registry;
+    }
+
+    pub fn security_registry(&self) -> &SecurityRegistry {
+        &self.registry
+    }
+
+    // Applies to the existing books and the ones created from now on, see
+    // OrderBook::set_max_price_deviation
+    pub fn set_max_price_deviation(&mut self, max_deviation: Option<Decimal>) {
+        self.book_settings.max_price_deviation = max_deviation;
+        self.apply_book_settings();
+    }
+
+    // Applies to the existing books and the ones created from now on, see
+    // OrderBook::set_allow_crossed
+    pub fn set_allow_crossed(&mut self, allow_crossed: bool) {
+        self.book_settings.allow_crossed = allow_crossed;
+        self.apply_book_settings();
+    }
+
+    // Applies to the existing books and the ones created from now on
+    pub fn set_timestamp_policy(&mut self, timestamp_policy: TimestampPolicy) {
+        self.book_settings.timestamp_policy = timestamp_policy;
+        self.apply_book_settings();
+    }
+
+    // Calls the hook with the security and the gap when an update arrives more than
+    // `max_gap` seq_nos ahead of its book, e.g. to request a new snapshot from a live
+    // feed instead of waiting for the next one. Called once per gap.
+    pub fn set_recovery_hook(&mut self, max_gap: u64, hook: RecoveryHook) {
+        self.gap_recovery = Some(GapRecovery { max_gap, hook });
+    }
+
+    // Levels of the security not refreshed for longer than the TTL are removed as the
+    // time of the records goes by, for feeds whose quotes expire
+    pub fn set_level_ttl(&mut self, security_id: u64, ttl_millis: u64) {
+        self.level_ttls.insert(security_id, ttl_millis);
+        if let Some(buffered_order_book) = self.buffered_order_books.get_mut(&security_id) {
+            let mut level_ttl = LevelTtl::new(ttl_millis);
+            level_ttl.on_snapshot(&buffered_order_book.order_book);
+            buffered_order_book.level_ttl = Some(level_ttl);
+        }
+    }
+
+    // Evicts the books whose last record is more than `max_age` millis older than the
+    // latest record as the time of the records goes by, so that long-running sessions
+    // don't keep the books of securities that stopped trading
+    pub fn set_stale_book_eviction(&mut self, max_age: u64) {
+        self.stale_eviction = Some(StaleEviction {
+            max_age,
+            next_check: 0,
+        });
+    }
+
+    // Drops the updates pending for longer than the TTL as records arrive, so that a book
+    // whose gap never closes doesn't hoard them until the next snapshot. The clock is only
+    // read for PendingTtl::WallMillis.
+    pub fn set_pending_ttl(&mut self, ttl: PendingTtl, clock: Box<dyn Clock>) {
+        let mut buffered_at = HashMap::new();
+        if let PendingTtl::WallMillis(_) = ttl {
+            // The updates already pending wait from now on
+            let now = clock.now_millis();
+            for (security_id, buffered_order_book) in self.buffered_order_books.iter() {
+                for seq_no in buffered_order_book.pending_updates.keys() {
+                    buffered_at.insert((*security_id, *seq_no), now);
+                }
+            }
+        }
+        self.pending_expiry = Some(PendingExpiry {
+            ttl,
+            clock,
+            buffered_at,
+            next_check: 0,
+        });
+    }
+
+    // Drops the updates pending for longer than the TTL set by set_pending_ttl, telling the
+    // listeners. Called with every record, and by embedders to expire updates while no
+    // records arrive. Returns the number of updates dropped.
+    pub fn expire_pending_updates(&mut self) -> usize {
+        let Some(pending_expiry) = self.pending_expiry.as_mut() else {
+            return 0;
+        };
+        let now = pending_expiry.now(self.data_time);
+        if now < pending_expiry.next_check {
+            return 0;
+        }
+        let ttl_millis = pending_expiry.ttl_millis();
+        let is_expired = |since: u64| now.saturating_sub(since) > ttl_millis;
+        let mut dropped_count = 0;
+        let mut oldest = u64::MAX;
+        for (security_id, buffered_order_book) in self.buffered_order_books.iter_mut() {
+            let dropped = match pending_expiry.ttl {
+                PendingTtl::EventMillis(_) => {
+                    buffered_order_book.drop_pending_where(|_, update| {
+                        if is_expired(update.timestamp) {
+                            return true;
+                        }
+                        oldest = oldest.min(update.timestamp);
+                        false
+                    })
+                }
+                PendingTtl::WallMillis(_) => {
+                    let buffered_at = &pending_expiry.buffered_at;
+                    buffered_order_book.drop_pending_where(|seq_no, _| {
+                        buffered_at
+                            .get(&(*security_id, seq_no))
+                            .is_some_and(|since| is_expired(*since))
+                    })
+                }
+            };
+            if !dropped.is_empty() {
+                dropped_count += dropped.len();
+                self.observers.on_pending_expired(*security_id, &dropped);
+            }
+        }
+        if let PendingTtl::WallMillis(_) = pending_expiry.ttl {
+            // Also forgets the updates applied or dropped since they were buffered
+            let books = &self.buffered_order_books;
+            pending_expiry
+                .buffered_at
+                .retain(|(security_id, seq_no), since| {
+                    let pending = books.get(security_id).is_some_and(|buffered_order_book| {
+                        buffered_order_book.pending_updates.contains_key(seq_no)
+                    });
+                    if pending {
+                        oldest = oldest.min(*since);
+                    }
+                    pending
+                });
+        }
+        pending_expiry.next_check = oldest.saturating_add(ttl_millis).saturating_add(1);
+        dropped_count
+    }
+
+    fn on_buffered(&mut self, security_id: u64, seq_no: u64, timestamp: u64) {
+        let Some(pending_expiry) = self.pending_expiry.as_mut() else {
+            return;
+        };
+        let since = match pending_expiry.ttl {
+            PendingTtl::EventMillis(_) => timestamp,
+            PendingTtl::WallMillis(_) => {
+                let now = pending_expiry.clock.now_millis();
+                *pending_expiry
+                    .buffered_at
+                    .entry((security_id, seq_no))
+                    .or_insert(now)
+            }
+        };
+        let expires_after = since.saturating_add(pending_expiry.ttl_millis());
+        pending_expiry.next_check = pending_expiry
+            .next_check
+            .min(expires_after.saturating_add(1));
+    }
+
+    // Levels of each side of the snapshots written by write_snapshots and the periodic
+    // snapshots, e.g. the depth of the snapshots read so that they can be read back alike
+    pub fn set_snapshot_depth(&mut self, depth: usize) {
+        self.snapshot_depth = Some(depth);
+    }
+
+    pub fn snapshot_depth(&self) -> usize {
+        self.snapshot_depth.unwrap_or(SNAPSHOT_DEPTH)
+    }
+
+    // Layout of the snapshots written by write_snapshots
+    pub fn set_snapshot_format(&mut self, format: SnapshotFormat) {
+        self.snapshot_format = format;
+    }
+
+    pub fn snapshot_format(&self) -> SnapshotFormat {
+        self.snapshot_format
+    }
+
+    // Hands the top levels of every book as snapshot records of the snapshot depth to the
+    // sink every interval, so that consumers joining a live session late can sync from
+    // them. Books waiting for a snapshot after a resync are skipped.
+    pub fn set_periodic_snapshots(&mut self, interval: CheckpointInterval, sink: SnapshotSink) {
+        self.periodic_snapshots = Some(PeriodicSnapshots {
+            schedule: CheckpointSchedule::new(interval),
+            sink,
+        });
+    }
+
+    fn emit_periodic_snapshots(&mut self) {
+        let depth = self.snapshot_depth();
+        let Some(periodic_snapshots) = self.periodic_snapshots.as_mut() else {
+            return;
+        };
+        if !periodic_snapshots.schedule.on_record(self.data_time) {
+            return;
+        }
+        for buffered_order_book in self.buffered_order_books.values() {
+            if buffered_order_book.state == BookState::AwaitingSnapshot {
+                continue;
+            }
+            (periodic_snapshots.sink)(
+                &buffered_order_book.order_book.to_snapshot_with_depth(depth),
+            );
+        }
+        periodic_snapshots.schedule.on_checkpoint(self.data_time);
+    }
+
+    // Removes the books whose last record is more than `max_age` millis older than the
+    // latest record, telling the listeners with their last state. Their pending updates
+    // are dropped and later updates are ignored until a new snapshot arrives. Returns the
+    // evicted securities in ascending order.
+    pub fn evict_stale(&mut self, max_age: u64) -> Vec<u64> {
+        let cutoff = self.data_time.saturating_sub(max_age);
+        let mut evicted = Vec::new();
+        self.buffered_order_books
+            .retain(|security_id, buffered_order_book| {
+                if buffered_order_book.order_book.timestamp >= cutoff {
+                    return true;
+                }
+                self.observers.on_evicted(&buffered_order_book.order_book);
+                evicted.push(*security_id);
+                false
+            });
+        evicted
+    }
+
+    // Expires the levels that are stale at `now`. Called with the timestamp of every
+    // record, and by embedders to expire levels while no records arrive.
+    pub fn advance_time(&mut self, now: u64) {
+        if now <= self.data_time {
+            return;
+        }
+        self.data_time = now;
+        if let Some(stale_eviction) = &self.stale_eviction
+            && now > stale_eviction.next_check
+        {
+            let max_age = stale_eviction.max_age;
+            self.evict_stale(max_age);
+            // The oldest book is the next one to become stale, books created later are
+            // newer than now
+            let oldest = self
+                .buffered_order_books
+                .values()
+                .map(|buffered_order_book| buffered_order_book.order_book.timestamp)
+                .min()
+                .map_or(now, |oldest| oldest.min(now));
+            if let Some(stale_eviction) = self.stale_eviction.as_mut() {
+                stale_eviction.next_check = oldest.saturating_add(max_age);
+            }
+        }
+        for security_id in self.level_ttls.keys() {
+            let Some(buffered_order_book) = self.buffered_order_books.get_mut(security_id) else {
+                continue;
+            };
+            let Some(level_ttl) = buffered_order_book.level_ttl.as_mut() else {
+                continue;
+            };
+            if level_ttl
+                .next_expiry()
+                .is_none_or(|next_expiry| next_expiry >= now)
+            {
+                continue;
+            }
+            let expired = level_ttl.expire(&mut buffered_order_book.order_book, now);
+            if !expired.is_empty() {
+                self.observers
+                    .on_expired(&buffered_order_book.order_book, &expired);
+            }
+        }
+    }
+
+    // Returns the levels quarantined by LevelPolicy::BestEffort since the previous call.
+    // They are kept until then, so callers using the policy should drain them regularly.
+    pub fn drain_quarantined(&mut self) -> Vec<QuarantinedLevel> {
+        std::mem::take(&mut self.quarantined)
+    }
+
+    pub fn apply_update(&mut self, update: OrderBookUpdate) -> ManagerOutcome {
+        let (security_id, seq_no, timestamp) =
+            (update.security_id, update.seq_no, update.timestamp);
+        self.advance_time(update.timestamp);
+        self.expire_pending_updates();
+        let before = self
+            .event_sink
+            .is_some()
+            .then(|| self.pending_and_applied(security_id));
+        let outcome = self.apply_update_record(update);
+        self.observers.on_outcome(security_id, seq_no, &outcome);
+        if let ManagerOutcome::Buffered(gap_info) = &outcome {
+            self.check_gap(security_id, gap_info);
+            self.on_buffered(security_id, seq_no, timestamp);
+        }
+        let quarantined = self.collect_quarantined(security_id);
+        if let Some(before) = before {
+            self.emit_event(
+                RecordKind::Update,
+                security_id,
+                seq_no,
+                &outcome,
+                before,
+                quarantined,
+            );
+        }
+        self.emit_periodic_snapshots();
+        outcome
+    }
+
+    fn apply_update_record(&mut self, update: OrderBookUpdate) -> ManagerOutcome {
+        let observers = &mut self.observers;
+        let Some(order_book) = self.buffered_order_books.get_mut(&update.security_id) else {
+            return ManagerOutcome::IgnoredUnknownSecurity;
+        };
+        let gap_info = GapInfo {
+            expected_seq_no: order_book.order_book.seq_no + 1,
+            received_seq_no: update.seq_no,
+            awaiting_snapshot: false,
+        };
+        let result = order_book.apply_update_with(update, &mut |book, record| {
+            observers.on_applied(book, record)
+        });
+        ManagerOutcome::from_result(result, Some(gap_info))
+    }
+
+    pub fn apply_snapshot(&mut self, snapshot: &OrderBookSnapshot) -> ManagerOutcome {
+        let (security_id, seq_no) = (snapshot.security_id, snapshot.seq_no);
+        self.advance_time(snapshot.timestamp);
+        self.expire_pending_updates();
+        let before = self
+            .event_sink
+            .is_some()
+            .then(|| self.pending_and_applied(security_id));
+        let outcome = self.apply_snapshot_record(snapshot);
+        self.observers.on_outcome(security_id, seq_no, &outcome);
+        let quarantined = self.collect_quarantined(security_id);
+        if let Some(before) = before {
+            self.emit_event(
+                RecordKind::Snapshot,
+                security_id,
+                seq_no,
+                &outcome,
+                before,
+                quarantined,
+            );
+        }
+        self.emit_periodic_snapshots();
+        outcome
+    }
+
+    fn apply_snapshot_record(&mut self, snapshot: &OrderBookSnapshot) -> ManagerOutcome {
+        self.observers.applying_snapshot = true;
+        let result = match self.buffered_order_books.entry(snapshot.security_id) {
             std::collections::btree_map::Entry::Vacant(entry) => {
-                let order_book = OrderBook::new(snapshot)?;
-                let buffered_order_book = BufferedOrderBook::new(order_book);
-                entry.insert(buffered_order_book);
-                Ok(())
+                let security_info = self.registry.get(snapshot.security_id).cloned();
+                OrderBook::from_snapshot_with_info(snapshot, security_info).map(|order_book| {
+                    let buffered_order_book = Self::new_buffered_order_book(
+                        order_book,
+                        self.capacity_hints.as_ref(),
+                        &self.book_settings,
+                        &self.level_ttls,
+                    );
+                    let buffered_order_book = entry.insert(buffered_order_book);
+                    self.observers.on_applied(
+                        &buffered_order_book.order_book,
+                        AppliedRecord::Snapshot(snapshot),
+                    );
+                })
             }
             std::collections::btree_map::Entry::Occupied(mut entry) => {
-                entry.get_mut().apply_snapshot(snapshot)
+                let observers = &mut self.observers;
+                entry
+                    .get_mut()
+                    .apply_snapshot_with(snapshot, &mut |book, record| {
+                        observers.on_applied(book, record)
+                    })
+            }
+        };
+        // In case the snapshot was not applied
+        self.observers.applying_snapshot = false;
+        // Snapshots are never buffered
+        ManagerOutcome::from_result(result, None)
+    }
+
+    // A book set up with the settings of the manager for its security
+    fn new_buffered_order_book(
+        order_book: OrderBook,
+        capacity_hints: Option<&CapacityHints>,
+        book_settings: &BookSettings,
+        level_ttls: &BTreeMap<u64, u64>,
+    ) -> BufferedOrderBook {
+        let security_id = order_book.security_id;
+        let pending_capacity = capacity_hints
+            .and_then(|hints| hints.get(security_id))
+            .map(|hint| hint.max_pending_updates)
+            .unwrap_or(0);
+        let mut buffered_order_book =
+            BufferedOrderBook::with_pending_capacity(order_book, pending_capacity);
+        book_settings.apply_to(&mut buffered_order_book);
+        if let Some(ttl_millis) = level_ttls.get(&security_id) {
+            let mut level_ttl = LevelTtl::new(*ttl_millis);
+            level_ttl.on_snapshot(&buffered_order_book.order_book);
+            buffered_order_book.level_ttl = Some(level_ttl);
+        }
+        buffered_order_book
+    }
+
+    fn check_gap(&mut self, security_id: u64, gap_info: &GapInfo) {
+        let Some(gap_recovery) = self.gap_recovery.as_mut() else {
+            return;
+        };
+        // A resynced book is already waiting for a snapshot
+        if gap_info.awaiting_snapshot
+            || gap_info.received_seq_no - gap_info.expected_seq_no <= gap_recovery.max_gap
+        {
+            return;
+        }
+        if let Some(buffered_order_book) = self.buffered_order_books.get_mut(&security_id)
+            && !buffered_order_book.recovery_requested
+        {
+            buffered_order_book.recovery_requested = true;
+            (gap_recovery.hook)(security_id, gap_info);
+        }
+    }
+
+    // Moves the levels quarantined by the book into the manager and returns their number
+    fn collect_quarantined(&mut self, security_id: u64) -> usize {
+        match self.buffered_order_books.get_mut(&security_id) {
+            Some(buffered_order_book) => {
+                let count = buffered_order_book.quarantined.len();
+                self.quarantined
+                    .append(&mut buffered_order_book.quarantined);
+                count
+            }
+            None => 0,
+        }
+    }
+
+    fn pending_and_applied(&self, security_id: u64) -> (usize, usize) {
+        let pending = self
+            .buffered_order_books
+            .get(&security_id)
+            .map(|buffered_order_book| buffered_order_book.pending_updates.len())
+            .unwrap_or(0);
+        (pending, self.observers.applied_count)
+    }
+
+    fn emit_event(
+        &mut self,
+        record: RecordKind,
+        security_id: u64,
+        seq_no: u64,
+        outcome: &ManagerOutcome,
+        (pending_before, applied_before): (usize, usize),
+        quarantined: usize,
+    ) {
+        let (decision, reason) = match outcome {
+            ManagerOutcome::Applied if quarantined > 0 => (
+                "applied",
+                Some(format!("{} invalid levels quarantined", quarantined)),
+            ),
+            ManagerOutcome::Applied => ("applied", None),
+            ManagerOutcome::Buffered(gap_info) if gap_info.awaiting_snapshot => {
+                ("buffered", Some("awaiting snapshot".to_string()))
+            }
+            ManagerOutcome::Buffered(gap_info) => (
+                "buffered",
+                Some(format!("expected seq_no {}", gap_info.expected_seq_no)),
+            ),
+            ManagerOutcome::IgnoredOld => ("ignored_old", None),
+            ManagerOutcome::IgnoredUnknownSecurity => ("ignored_unknown_security", None),
+            ManagerOutcome::Rejected(e) => ("rejected", Some(e.to_string())),
+        };
+        let applied = self.observers.applied_count - applied_before;
+        let replayed = applied.saturating_sub(outcome.is_applied() as usize);
+
+        let buffered_order_book = self.buffered_order_books.get(&security_id);
+        let pending = buffered_order_book
+            .map(|buffered_order_book| buffered_order_book.pending_updates.len())
+            .unwrap_or(0);
+        let buffered = matches!(outcome, ManagerOutcome::Buffered(_)) as usize;
+        let dropped_count = (pending_before + buffered).saturating_sub(replayed + pending);
+        let dropped = (dropped_count > 0).then(|| {
+            let policy = match record {
+                RecordKind::Snapshot => PendingPolicy::CoveredBySnapshot,
+                RecordKind::Update if pending_before >= BufferedOrderBook::MAX_PENDING_UPDATES => {
+                    PendingPolicy::Overflow
+                }
+                RecordKind::Update => PendingPolicy::DuplicateReplaced,
+            };
+            (policy, dropped_count)
+        });
+
+        let event = BookEvent {
+            record,
+            security_id,
+            seq_no,
+            decision,
+            reason,
+            replayed,
+            dropped,
+            state: SyncState::of(buffered_order_book),
+            pending,
+        };
+        if let Some(event_sink) = self.event_sink.as_mut() {
+            event_sink(&event);
+        }
+    }
+
+    pub fn resync(&mut self, security_id: u64) -> Result<(), Errors> {
+        match self.buffered_order_books.get_mut(&security_id) {
+            Some(buffered_order_book) => {
+                buffered_order_book.resync();
+                self.observers.dirty.insert(security_id);
+                Ok(())
+            }
+            // No record behind an admin command
+            None => Err(Errors::OrderBookNotFound(UpdateMessageInfo::new(
+                security_id,
+                0,
+            ))),
+        }
+    }
+
+    pub fn execute(&mut self, command: AdminCommand) -> Result<(), Errors> {
+        match command {
+            AdminCommand::Resync(security_id) => self.resync(security_id),
+        }
+    }
+
+    // The books of the given securities in ascending order of security_id. Unknown
+    // securities are skipped.
+    pub fn filtered_books<'a>(
+        &'a self,
+        security_ids: &'a [u64],
+    ) -> impl Iterator<Item = &'a BufferedOrderBook> {
+        self.buffered_order_books
+            .iter()
+            .filter(|(security_id, _)| security_ids.contains(security_id))
+            .map(|(_, buffered_order_book)| buffered_order_book)
+    }
+
+    // Displays the books of the given securities only, as the Manager displays all of them
+    pub fn fmt_filtered<'a>(&'a self, security_ids: &'a [u64]) -> FilteredBooks<'a> {
+        FilteredBooks {
+            manager: self,
+            security_ids,
+        }
+    }
+
+    // Gap statistics of every book, in security order
+    pub fn gap_stats(&self) -> Vec<GapStats> {
+        self.buffered_order_books
+            .values()
+            .map(|buffered_order_book| buffered_order_book.stats())
+            .collect()
+    }
+
+    // Returns the securities whose books changed since the previous call, in ascending
+    // order, so that consumers can refresh only those
+    pub fn drain_dirty(&mut self) -> Vec<u64> {
+        std::mem::take(&mut self.observers.dirty)
+            .into_iter()
+            .collect()
+    }
+
+    // Writes the top levels of every book as snapshot records of the snapshot depth, in
+    // security order. Books waiting for a snapshot after a resync are skipped.
+    pub fn write_snapshots<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        for buffered_order_book in self.buffered_order_books.values() {
+            if buffered_order_book.state == BookState::AwaitingSnapshot {
+                continue;
+            }
+            buffered_order_book
+                .order_book
+                .to_snapshot_with_depth(self.snapshot_depth())
+                .write(writer, self.snapshot_format)?;
+        }
+        Ok(())
+    }
+
+    // Replaces the books of the securities with the given ones, e.g. read from a
+    // checkpoint, dropping their pending updates. Listeners are not called.
+    pub fn restore_books<I: IntoIterator<Item = OrderBook>>(&mut self, books: I) {
+        for mut order_book in books {
+            order_book.set_security_info(self.registry.get(order_book.security_id).cloned());
+            self.data_time = self.data_time.max(order_book.timestamp);
+            let buffered_order_book = Self::new_buffered_order_book(
+                order_book,
+                self.capacity_hints.as_ref(),
+                &self.book_settings,
+                &self.level_ttls,
+            );
+            self.buffered_order_books.insert(
+                buffered_order_book.order_book.security_id,
+                buffered_order_book,
+            );
+        }
+    }
+
+    // Latest exchange timestamp of the records handed to the manager
+    pub fn data_time(&self) -> u64 {
+        self.data_time
+    }
+
+    pub fn write_pending_updates<W: Write>(
+        &self,
+        writer: &mut W,
+        format: UpdateFormat,
+    ) -> io::Result<()> {
+        for buffered_order_book in self.buffered_order_books.values() {
+            buffered_order_book.write_pending_updates(writer, format)?;
+        }
+        Ok(())
+    }
+
+    // Feeds updates saved by write_pending_updates back to the books, which buffers them
+    // again while the gap is still open. Updates for unknown securities or already covered
+    // by the books are dropped. Returns the number of updates read.
+    pub fn restore_pending_updates<R: Read>(
+        &mut self,
+        reader: &mut R,
+        format: UpdateFormat,
+    ) -> Result<usize, ParserError> {
+        let mut parser = OrderBookUpdateParser::new(format);
+        let mut count = 0;
+        loop {
+            match parser.read(reader) {
+                Ok(update) => {
+                    count += 1;
+                    self.apply_update(update);
+                }
+                Err(ParserError::ExpectedEof) => return Ok(count),
+                Err(e) => return Err(e),
             }
         }
     }
@@ -45,58 +953,84 @@ impl Display for Manager {
     }
 }
 
+// The books of some of the securities of a Manager, see Manager::fmt_filtered
+pub struct FilteredBooks<'a> {
+    manager: &'a Manager,
+    security_ids: &'a [u64],
+}
+
+impl Display for FilteredBooks<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for buffered_order_book in self.manager.filtered_books(self.security_ids) {
+            write!(f, "{}", buffered_order_book)?;
+        }
+        Ok(())
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
     use crate::batched_deque::batched_deque::BatchedDeque;
+    use crate::clock::TestClock;
+    use crate::order_book::units::Qty;
     use crate::parsing::order_book_snapshot::Level as SnapshotLevel;
-    use crate::parsing::order_book_update::Level as UpdateLevel;
+    use crate::parsing::order_book_snapshot::OrderBookSnapshotParser;
+    use crate::parsing::order_book_update::{Level as UpdateLevel, Side};
+    use crate::parsing::pre_scan::SecurityCapacityHint;
+    use rust_decimal::dec;
+    use std::cell::RefCell;
+    use std::rc::Rc;
 
     fn create_test_snapshot(security_id: u64, seq_no: u64) -> OrderBookSnapshot {
         OrderBookSnapshot {
             timestamp: 1627846265,
             seq_no,
             security_id,
-            bid1: SnapshotLevel {
-                price: 100.00,
-                qty: 10,
-            },
-            ask1: SnapshotLevel {
-                price: 101.00,
-                qty: 15,
-            },
-            bid2: SnapshotLevel {
-                price: 99.00,
-                qty: 20,
-            },
-            ask2: SnapshotLevel {
-                price: 102.00,
-                qty: 25,
-            },
-            bid3: SnapshotLevel {
-                price: 98.00,
-                qty: 30,
-            },
-            ask3: SnapshotLevel {
-                price: 103.00,
-                qty: 35,
-            },
-            bid4: SnapshotLevel {
-                price: 97.00,
-                qty: 40,
-            },
-            ask4: SnapshotLevel {
-                price: 104.00,
-                qty: 45,
-            },
-            bid5: SnapshotLevel {
-                price: 96.00,
-                qty: 50,
-            },
-            ask5: SnapshotLevel {
-                price: 105.00,
-                qty: 55,
-            },
+            bids: vec![
+                SnapshotLevel {
+                    price: 100.00,
+                    qty: Qty(10),
+                },
+                SnapshotLevel {
+                    price: 99.00,
+                    qty: Qty(20),
+                },
+                SnapshotLevel {
+                    price: 98.00,
+                    qty: Qty(30),
+                },
+                SnapshotLevel {
+                    price: 97.00,
+                    qty: Qty(40),
+                },
+                SnapshotLevel {
+                    price: 96.00,
+                    qty: Qty(50),
+                },
+            ],
+            asks: vec![
+                SnapshotLevel {
+                    price: 101.00,
+                    qty: Qty(15),
+                },
+                SnapshotLevel {
+                    price: 102.00,
+                    qty: Qty(25),
+                },
+                SnapshotLevel {
+                    price: 103.00,
+                    qty: Qty(35),
+                },
+                SnapshotLevel {
+                    price: 104.00,
+                    qty: Qty(45),
+                },
+                SnapshotLevel {
+                    price: 105.00,
+                    qty: Qty(55),
+                },
+            ],
         }
     }
 
@@ -105,21 +1039,25 @@ mod tests {
         let deque = BatchedDeque::new(10);
         let levels: Vec<Result<UpdateLevel, ()>> = vec![
             Ok(UpdateLevel {
-                side: 0,
+                side: Side::Bid,
                 price: 99.00,
-                qty: 25,
+                qty: Qty(25),
+                metadata: None,
             }),
             Ok(UpdateLevel {
-                side: 1,
+                side: Side::Ask,
                 price: 101.00,
-                qty: 30,
+                qty: Qty(30),
+                metadata: None,
             }),
         ];
 
         OrderBookUpdate {
             timestamp: 1627846266,
+            capture_timestamp: None,
             seq_no,
             security_id,
+            thinned_from: None,
             updates: deque.push_back_batch(levels.into_iter()).unwrap(),
         }
     }
@@ -132,7 +1070,7 @@ mod tests {
 
         let result = manager.apply_snapshot(&snapshot);
 
-        assert!(result.is_ok());
+        assert!(result.is_applied());
         assert!(manager.buffered_order_books.contains_key(&security_id));
         assert_eq!(manager.buffered_order_books.len(), 1);
     }
@@ -150,7 +1088,7 @@ mod tests {
         let snapshot2 = create_test_snapshot(security_id, 101);
         let result = manager.apply_snapshot(&snapshot2);
 
-        assert!(result.is_ok());
+        assert!(result.is_applied());
         assert_eq!(manager.buffered_order_books.len(), 1);
     }
 
@@ -162,7 +1100,7 @@ mod tests {
 
         let result = manager.apply_update(update);
 
-        assert!(matches!(result, Err(Errors::OrderBookNotFound)));
+        assert!(matches!(result, ManagerOutcome::IgnoredUnknownSecurity));
         assert!(manager.buffered_order_books.is_empty());
     }
 
@@ -179,7 +1117,750 @@ mod tests {
         let update = create_test_update(security_id, 101);
         let result = manager.apply_update(update);
 
-        assert!(result.is_ok());
+        assert!(result.is_applied());
+    }
+
+    #[test]
+    fn test_apply_outcomes() {
+        let mut manager = Manager::default();
+        let security_id = 1001;
+        assert!(
+            manager
+                .apply_snapshot(&create_test_snapshot(security_id, 100))
+                .is_applied()
+        );
+
+        let outcome = manager.apply_update(create_test_update(security_id, 103));
+        assert!(matches!(
+            outcome,
+            ManagerOutcome::Buffered(GapInfo {
+                expected_seq_no: 101,
+                received_seq_no: 103,
+                awaiting_snapshot: false,
+            })
+        ));
+
+        let outcome = manager.apply_snapshot(&create_test_snapshot(security_id, 99));
+        assert!(matches!(outcome, ManagerOutcome::IgnoredOld));
+
+        let mut snapshot = create_test_snapshot(security_id, 101);
+        snapshot.bids[0].price = 100.001;
+        let outcome = manager.apply_snapshot(&snapshot);
+        assert!(matches!(
+            outcome,
+            ManagerOutcome::Rejected(Errors::InvalidPrice(_, _))
+        ));
+    }
+
+    #[test]
+    fn test_apply_hooks() {
+        let mut manager = Manager::default();
+        let security_id = 1001;
+
+        let applied = Rc::new(RefCell::new(Vec::new()));
+        let hook_applied = applied.clone();
+        manager.add_apply_hook(Box::new(move |book: &OrderBook| {
+            hook_applied.borrow_mut().push(book.seq_no)
+        }));
+
+        assert!(
+            manager
+                .apply_snapshot(&create_test_snapshot(security_id, 100))
+                .is_applied()
+        );
+        assert!(
+            manager
+                .apply_update(create_test_update(security_id, 101))
+                .is_applied()
+        );
+        // Ignored records don't trigger the hooks
+        assert!(matches!(
+            manager.apply_update(create_test_update(security_id, 101)),
+            ManagerOutcome::IgnoredOld
+        ));
+        assert!(
+            manager
+                .apply_snapshot(&create_test_snapshot(security_id, 102))
+                .is_applied()
+        );
+
+        assert_eq!(*applied.borrow(), vec![100, 101, 102]);
+    }
+
+    #[test]
+    fn test_capacity_hints() {
+        let mut manager = Manager::default();
+        let mut hints = CapacityHints::default();
+        hints.securities.insert(
+            1001,
+            SecurityCapacityHint {
+                updates: 1000,
+                levels: 2000,
+                max_pending_updates: 64,
+            },
+        );
+        manager.set_capacity_hints(hints);
+
+        manager.apply_snapshot(&create_test_snapshot(1001, 100));
+        manager.apply_snapshot(&create_test_snapshot(1002, 100));
+
+        let pending_updates = &manager.buffered_order_books[&1001].pending_updates;
+        assert!(pending_updates.capacity() >= 64);
+        let pending_updates = &manager.buffered_order_books[&1002].pending_updates;
+        assert_eq!(pending_updates.capacity(), 0);
+    }
+
+    #[test]
+    fn test_event_sink() {
+        let mut manager = Manager::default();
+        let events = Rc::new(RefCell::new(Vec::new()));
+        let sink_events = events.clone();
+        manager.set_event_sink(Box::new(move |event: &BookEvent| {
+            sink_events.borrow_mut().push(event.clone())
+        }));
+
+        manager.apply_update(create_test_update(1001, 101));
+        manager.apply_snapshot(&create_test_snapshot(1001, 100));
+        manager.apply_update(create_test_update(1001, 103));
+        manager.apply_update(create_test_update(1001, 103));
+        manager.apply_update(create_test_update(1001, 105));
+        manager.apply_update(create_test_update(1001, 101));
+        manager.apply_snapshot(&create_test_snapshot(1001, 104));
+
+        let events = events.borrow();
+        let summary: Vec<_> = events
+            .iter()
+            .map(|event| {
+                (
+                    event.decision,
+                    event.replayed,
+                    event.dropped,
+                    event.state,
+                    event.pending,
+                )
+            })
+            .collect();
+        assert_eq!(
+            summary,
+            vec![
+                ("ignored_unknown_security", 0, None, SyncState::NoBook, 0),
+                ("applied", 0, None, SyncState::Live, 0),
+                ("buffered", 0, None, SyncState::Gap, 1),
+                (
+                    "buffered",
+                    0,
+                    Some((PendingPolicy::DuplicateReplaced, 1)),
+                    SyncState::Gap,
+                    1
+                ),
+                ("buffered", 0, None, SyncState::Gap, 2),
+                // 102 is still missing, so only 101 itself is applied
+                ("applied", 0, None, SyncState::Gap, 2),
+                // 103 is covered by the snapshot, 105 is replayed on top of it
+                (
+                    "applied",
+                    1,
+                    Some((PendingPolicy::CoveredBySnapshot, 1)),
+                    SyncState::Live,
+                    0
+                ),
+            ]
+        );
+        assert_eq!(events[2].reason.as_deref(), Some("expected seq_no 101"));
+        assert_eq!(events[6].record, RecordKind::Snapshot);
+    }
+
+    #[test]
+    fn test_level_policy() {
+        let invalid_update = |seq_no| {
+            let deque = BatchedDeque::new(10);
+            let levels: Vec<Result<UpdateLevel, ()>> = vec![
+                Ok(UpdateLevel {
+                    side: Side::Bid,
+                    price: 99.00,
+                    qty: Qty(25),
+                    metadata: None,
+                }),
+                Ok(UpdateLevel {
+                    side: Side::Ask,
+                    price: 101.005,
+                    qty: Qty(30),
+                    metadata: None,
+                }),
+            ];
+            OrderBookUpdate {
+                timestamp: 1627846266,
+                capture_timestamp: None,
+                seq_no,
+                security_id: 1001,
+                thinned_from: None,
+                updates: deque.push_back_batch(levels.into_iter()).unwrap(),
+            }
+        };
+
+        let mut manager = Manager::default();
+        let events = Rc::new(RefCell::new(Vec::new()));
+        let sink_events = events.clone();
+        manager.set_event_sink(Box::new(move |event: &BookEvent| {
+            sink_events.borrow_mut().push(event.clone())
+        }));
+        manager.apply_snapshot(&create_test_snapshot(1001, 100));
+
+        assert!(matches!(
+            manager.apply_update(invalid_update(101)),
+            ManagerOutcome::Rejected(Errors::InvalidPrice(_, _))
+        ));
+        assert!(manager.drain_quarantined().is_empty());
+
+        manager.set_level_policy(LevelPolicy::BestEffort);
+        // 102 waits for 101 and is quarantined when replayed
+        assert!(matches!(
+            manager.apply_update(invalid_update(102)),
+            ManagerOutcome::Buffered(_)
+        ));
+        assert!(manager.apply_update(invalid_update(101)).is_applied());
+        let book = &manager.buffered_order_books[&1001].order_book;
+        assert_eq!(book.seq_no, 102);
+        assert_eq!(book.bids[&dec!(99)], Qty(25));
+
+        let quarantined: Vec<u64> = manager
+            .drain_quarantined()
+            .iter()
+            .map(|level| level.seq_no)
+            .collect();
+        assert_eq!(quarantined, vec![101, 102]);
+        assert!(manager.drain_quarantined().is_empty());
+        assert_eq!(
+            events.borrow().last().unwrap().reason.as_deref(),
+            Some("2 invalid levels quarantined")
+        );
+    }
+
+    #[test]
+    fn test_listener() {
+        #[derive(Default)]
+        struct RecordingListener {
+            calls: Vec<String>,
+        }
+
+        impl BookListener for RecordingListener {
+            fn on_snapshot_applied(&mut self, book: &OrderBook) {
+                self.calls.push(format!("snapshot {}", book.seq_no));
+            }
+
+            fn on_update_applied(&mut self, book: &OrderBook) {
+                self.calls.push(format!("update {}", book.seq_no));
+            }
+
+            fn on_gap_detected(&mut self, _security_id: u64, gap_info: &GapInfo) {
+                self.calls.push(format!(
+                    "gap {} {}",
+                    gap_info.expected_seq_no, gap_info.received_seq_no
+                ));
+            }
+
+            fn on_error(&mut self, _security_id: u64, seq_no: u64, _error: &Errors) {
+                self.calls.push(format!("error {}", seq_no));
+            }
+        }
+
+        let listener = Rc::new(RefCell::new(RecordingListener::default()));
+        let mut manager = Manager::default();
+        manager.add_listener(Box::new(listener.clone()));
+
+        manager.apply_snapshot(&create_test_snapshot(1001, 100));
+        manager.apply_update(create_test_update(1001, 102));
+        manager.apply_update(create_test_update(1001, 101));
+        manager.apply_update(create_test_update(1001, 104));
+        manager.apply_snapshot(&create_test_snapshot(1001, 103));
+        let mut invalid_snapshot = create_test_snapshot(1001, 105);
+        invalid_snapshot.bids[0].price = 100.005;
+        manager.apply_snapshot(&invalid_snapshot);
+        // Ignored records are not reported
+        manager.apply_update(create_test_update(1001, 100));
+        manager.apply_update(create_test_update(1002, 101));
+
+        assert_eq!(
+            listener.borrow().calls,
+            vec![
+                "snapshot 100",
+                "gap 101 102",
+                "update 101",
+                "update 102",
+                "gap 103 104",
+                "snapshot 103",
+                "update 104",
+                "error 105",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_level_ttl() {
+        #[derive(Default)]
+        struct ExpiryListener {
+            expired: Vec<(u64, usize)>,
+        }
+
+        impl BookListener for ExpiryListener {
+            fn on_levels_expired(&mut self, book: &OrderBook, expired: &[ExpiredLevel]) {
+                self.expired.push((book.security_id, expired.len()));
+            }
+        }
+
+        let listener = Rc::new(RefCell::new(ExpiryListener::default()));
+        let mut manager = Manager::default();
+        manager.add_listener(Box::new(listener.clone()));
+        manager.set_level_ttl(1001, 5000);
+        manager.apply_snapshot(&create_test_snapshot(1001, 100));
+        manager.apply_snapshot(&create_test_snapshot(1002, 100));
+        manager.drain_dirty();
+
+        // Refreshes 99 and 101 of 1001 at 1627846266
+        manager.apply_update(create_test_update(1001, 101));
+        assert!(listener.borrow().expired.is_empty());
+
+        // The records of another security move the time forward
+        let mut update = create_test_update(1002, 101);
+        update.timestamp = 1627846265 + 5001;
+        manager.apply_update(update);
+        assert_eq!(listener.borrow().expired, vec![(1001, 8)]);
+        let book = &manager.buffered_order_books[&1001].order_book;
+        assert_eq!(book.bids.keys().collect::<Vec<_>>(), vec![&dec!(99)]);
+        assert_eq!(book.asks.keys().collect::<Vec<_>>(), vec![&dec!(101)]);
+        // 1002 has no TTL
+        assert_eq!(manager.buffered_order_books[&1002].order_book.bids.len(), 5);
+        assert_eq!(manager.drain_dirty(), vec![1001, 1002]);
+
+        manager.advance_time(1627846266 + 5001);
+        assert_eq!(listener.borrow().expired, vec![(1001, 8), (1001, 2)]);
+        assert!(
+            manager.buffered_order_books[&1001]
+                .order_book
+                .bids
+                .is_empty()
+        );
+    }
+
+    #[test]
+    fn test_stale_book_eviction() {
+        #[derive(Default)]
+        struct EvictionListener {
+            evicted: Vec<(u64, u64)>,
+        }
+
+        impl BookListener for EvictionListener {
+            fn on_book_evicted(&mut self, book: &OrderBook) {
+                self.evicted.push((book.security_id, book.seq_no));
+            }
+        }
+
+        let listener = Rc::new(RefCell::new(EvictionListener::default()));
+        let mut manager = Manager::default();
+        manager.add_listener(Box::new(listener.clone()));
+        manager.apply_snapshot(&create_test_snapshot(1001, 100));
+        manager.apply_snapshot(&create_test_snapshot(1002, 100));
+        manager.apply_update(create_test_update(1002, 101));
+        manager.drain_dirty();
+
+        // 1001 is 1000 millis old, 1002 999
+        manager.advance_time(1627846265 + 1000);
+        assert!(manager.evict_stale(1000).is_empty());
+        assert_eq!(manager.evict_stale(999), vec![1001]);
+        assert_eq!(listener.borrow().evicted, vec![(1001, 100)]);
+        assert_eq!(manager.drain_dirty(), vec![1001]);
+        assert!(matches!(
+            manager.apply_update(create_test_update(1001, 101)),
+            ManagerOutcome::IgnoredUnknownSecurity
+        ));
+
+        // Books come back with their next snapshot
+        manager.set_stale_book_eviction(5000);
+        let mut snapshot = create_test_snapshot(1001, 110);
+        snapshot.timestamp = 1627846266 + 3000;
+        manager.apply_snapshot(&snapshot);
+        assert_eq!(manager.buffered_order_books.len(), 2);
+        let mut update = create_test_update(1001, 111);
+        update.timestamp = 1627846266 + 5001;
+        manager.apply_update(update);
+        assert_eq!(listener.borrow().evicted, vec![(1001, 100), (1002, 101)]);
+        assert_eq!(
+            manager.buffered_order_books.keys().collect::<Vec<_>>(),
+            vec![&1001]
+        );
+    }
+
+    #[test]
+    fn test_timestamp_policy() {
+        #[derive(Default)]
+        struct RegressionListener {
+            regressions: Vec<(u64, u64, u64)>,
+            errors: Vec<u64>,
+        }
+
+        impl BookListener for RegressionListener {
+            fn on_timestamp_regression(&mut self, book: &OrderBook, previous_timestamp: u64) {
+                self.regressions
+                    .push((book.seq_no, book.timestamp, previous_timestamp));
+            }
+
+            fn on_error(&mut self, _security_id: u64, seq_no: u64, _error: &Errors) {
+                self.errors.push(seq_no);
+            }
+        }
+
+        let listener = Rc::new(RefCell::new(RegressionListener::default()));
+        let mut manager = Manager::default();
+        manager.add_listener(Box::new(listener.clone()));
+        manager.apply_snapshot(&create_test_snapshot(1001, 100));
+        manager.set_timestamp_policy(TimestampPolicy::Warn);
+        let mut update = create_test_update(1001, 101);
+        update.timestamp = 1627846265 - 5;
+        assert!(manager.apply_update(update).is_applied());
+        assert_eq!(
+            listener.borrow().regressions,
+            vec![(101, 1627846265 - 5, 1627846265)]
+        );
+
+        manager.set_timestamp_policy(TimestampPolicy::Reject);
+        let mut update = create_test_update(1001, 102);
+        update.timestamp = 1627846265 - 10;
+        assert!(matches!(
+            manager.apply_update(update),
+            ManagerOutcome::Rejected(Errors::TimestampRegression(_, _))
+        ));
+        assert_eq!(listener.borrow().errors, vec![102]);
+        assert_eq!(listener.borrow().regressions.len(), 1);
+    }
+
+    #[test]
+    fn test_max_depth() {
+        let mut manager = Manager::default();
+        manager.apply_snapshot(&create_test_snapshot(1001, 100));
+        manager.set_max_depth(Some(2));
+        manager.apply_snapshot(&create_test_snapshot(1002, 100));
+
+        for security_id in [1001, 1002] {
+            let book = &manager.buffered_order_books[&security_id].order_book;
+            assert_eq!(
+                book.bids.keys().collect::<Vec<_>>(),
+                vec![&dec!(99), &dec!(100)]
+            );
+            assert_eq!(
+                book.asks.keys().collect::<Vec<_>>(),
+                vec![&dec!(101), &dec!(102)]
+            );
+        }
+    }
+
+    #[test]
+    fn test_security_registry() {
+        let registry = SecurityRegistry::from_csv("1001,ESZ4,1,,95,105\n".as_bytes()).unwrap();
+        let mut manager = Manager::default();
+        manager.apply_snapshot(&create_test_snapshot(1002, 100));
+        manager.set_security_registry(registry);
+
+        let mut snapshot = create_test_snapshot(1001, 100);
+        snapshot.asks[4].price = 105.5;
+        assert!(matches!(
+            manager.apply_snapshot(&snapshot),
+            ManagerOutcome::Rejected(Errors::InvalidPrice(_, _))
+        ));
+        assert!(
+            manager
+                .apply_snapshot(&create_test_snapshot(1001, 100))
+                .is_applied()
+        );
+        // 99 and 101 are multiples of the tick size, 99.5 is not
+        assert!(
+            manager
+                .apply_update(create_test_update(1001, 101))
+                .is_applied()
+        );
+        let mut update = create_test_update(1001, 102);
+        update.updates = BatchedDeque::new(1)
+            .push_back_batch(
+                [Ok::<_, ()>(UpdateLevel {
+                    side: Side::Bid,
+                    price: 99.5,
+                    qty: Qty(1),
+                    metadata: None,
+                })]
+                .into_iter(),
+            )
+            .unwrap();
+        assert!(matches!(
+            manager.apply_update(update),
+            ManagerOutcome::Rejected(Errors::InvalidPrice(_, _))
+        ));
+
+        let printed = manager.to_string();
+        assert!(printed.contains("security_id: 1001 (ESZ4)\n"));
+        assert!(printed.contains("security_id: 1002\n"));
+    }
+
+    #[test]
+    fn test_recovery_hook() {
+        let requests = Rc::new(RefCell::new(Vec::new()));
+        let hook_requests = requests.clone();
+        let mut manager = Manager::default();
+        manager.set_recovery_hook(
+            2,
+            Box::new(move |security_id, gap_info: &GapInfo| {
+                hook_requests
+                    .borrow_mut()
+                    .push((security_id, gap_info.received_seq_no))
+            }),
+        );
+        manager.apply_snapshot(&create_test_snapshot(1001, 100));
+
+        // 101 and 102 missing, within the threshold
+        manager.apply_update(create_test_update(1001, 103));
+        assert!(requests.borrow().is_empty());
+        manager.apply_update(create_test_update(1001, 104));
+        manager.apply_update(create_test_update(1001, 105));
+        assert_eq!(*requests.borrow(), vec![(1001, 104)]);
+
+        // The gap closes, so the next one is reported again
+        manager.apply_update(create_test_update(1001, 101));
+        manager.apply_update(create_test_update(1001, 102));
+        assert_eq!(manager.buffered_order_books[&1001].order_book.seq_no, 105);
+        manager.apply_update(create_test_update(1001, 109));
+        assert_eq!(*requests.borrow(), vec![(1001, 104), (1001, 109)]);
+
+        // Not while waiting for a snapshot after a resync
+        manager.resync(1001).unwrap();
+        manager.apply_update(create_test_update(1001, 120));
+        assert_eq!(requests.borrow().len(), 2);
+    }
+
+    #[test]
+    fn test_periodic_snapshots() {
+        let snapshots = Rc::new(RefCell::new(Vec::new()));
+        let sink = snapshots.clone();
+        let mut manager = Manager::default();
+        manager.set_periodic_snapshots(
+            CheckpointInterval::Records(3),
+            Box::new(move |snapshot: &OrderBookSnapshot| {
+                sink.borrow_mut()
+                    .push((snapshot.security_id, snapshot.seq_no))
+            }),
+        );
+        manager.apply_snapshot(&create_test_snapshot(1001, 100));
+        manager.apply_snapshot(&create_test_snapshot(1002, 100));
+        assert!(snapshots.borrow().is_empty());
+        // Records that aren't applied count as well
+        manager.apply_update(create_test_update(1003, 101));
+        assert_eq!(*snapshots.borrow(), vec![(1001, 100), (1002, 100)]);
+
+        manager.apply_update(create_test_update(1001, 101));
+        manager.execute(AdminCommand::Resync(1002)).unwrap();
+        manager.apply_update(create_test_update(1001, 102));
+        manager.apply_update(create_test_update(1001, 103));
+        assert_eq!(
+            *snapshots.borrow(),
+            vec![(1001, 100), (1002, 100), (1001, 103)]
+        );
+    }
+
+    #[test]
+    fn test_pending_ttl() {
+        #[derive(Default)]
+        struct ExpiryListener {
+            expired: Vec<(u64, Vec<u64>)>,
+        }
+
+        impl BookListener for ExpiryListener {
+            fn on_pending_expired(&mut self, security_id: u64, seq_nos: &[u64]) {
+                self.expired.push((security_id, seq_nos.to_vec()));
+            }
+        }
+
+        let update_at = |seq_no: u64, timestamp: u64| {
+            let mut update = create_test_update(1001, seq_no);
+            update.timestamp = timestamp;
+            update
+        };
+
+        let listener = Rc::new(RefCell::new(ExpiryListener::default()));
+        let clock = Rc::new(TestClock::new(0));
+        let mut manager = Manager::default();
+        manager.add_listener(Box::new(listener.clone()));
+        manager.set_pending_ttl(PendingTtl::EventMillis(1000), Box::new(clock.clone()));
+        manager.apply_snapshot(&create_test_snapshot(1001, 100));
+        manager.apply_update(update_at(102, 1627846266));
+        manager.apply_update(update_at(103, 1627846766));
+        manager.apply_snapshot(&create_test_snapshot(1002, 100));
+        // 102 waited exactly the TTL
+        manager.advance_time(1627846266 + 1000);
+        manager.expire_pending_updates();
+        assert!(listener.borrow().expired.is_empty());
+        manager.advance_time(1627846266 + 1001);
+        assert_eq!(manager.expire_pending_updates(), 1);
+        assert_eq!(listener.borrow().expired, vec![(1001, vec![102])]);
+        let pending = &manager.buffered_order_books[&1001].pending_updates;
+        assert!(pending.contains_key(&103));
+
+        // The wall time the updates waited, whatever their timestamps
+        listener.borrow_mut().expired.clear();
+        manager.set_pending_ttl(PendingTtl::WallMillis(50), Box::new(clock.clone()));
+        clock.advance(30);
+        manager.apply_update(update_at(104, 1));
+        clock.advance(30);
+        assert_eq!(manager.expire_pending_updates(), 1);
+        assert_eq!(listener.borrow().expired, vec![(1001, vec![103])]);
+        clock.advance(30);
+        manager.apply_update(update_at(106, 1627846266));
+        assert_eq!(
+            listener.borrow().expired,
+            vec![(1001, vec![103]), (1001, vec![104])]
+        );
+        assert_eq!(manager.buffered_order_books[&1001].pending_updates.len(), 1);
+
+        assert_eq!("event:500".parse(), Ok(PendingTtl::EventMillis(500)));
+        assert_eq!("wall:60000".parse(), Ok(PendingTtl::WallMillis(60000)));
+        assert!("500".parse::<PendingTtl>().is_err());
+    }
+
+    #[test]
+    fn test_drain_dirty() {
+        let mut manager = Manager::default();
+        assert!(manager.drain_dirty().is_empty());
+
+        manager.apply_snapshot(&create_test_snapshot(1002, 100));
+        manager.apply_snapshot(&create_test_snapshot(1001, 100));
+        assert_eq!(manager.drain_dirty(), vec![1001, 1002]);
+        assert!(manager.drain_dirty().is_empty());
+
+        // Only applied records mark the book
+        manager.apply_update(create_test_update(1001, 102));
+        assert!(manager.drain_dirty().is_empty());
+        manager.apply_update(create_test_update(1001, 101));
+        assert_eq!(manager.drain_dirty(), vec![1001]);
+
+        manager.resync(1002).unwrap();
+        assert_eq!(manager.drain_dirty(), vec![1002]);
+    }
+
+    #[test]
+    fn test_fmt_filtered() {
+        let mut manager = Manager::default();
+        for security_id in [1001, 1002, 1003] {
+            manager.apply_snapshot(&create_test_snapshot(security_id, 100));
+        }
+        let printed = manager.fmt_filtered(&[1003, 1001, 9999]).to_string();
+        let expected = format!(
+            "{}{}",
+            manager.buffered_order_books[&1001], manager.buffered_order_books[&1003]
+        );
+        assert_eq!(printed, expected);
+        assert!(manager.fmt_filtered(&[]).to_string().is_empty());
+    }
+
+    #[test]
+    fn test_write_snapshots() {
+        let mut manager = Manager::default();
+        manager.apply_snapshot(&create_test_snapshot(1002, 100));
+        manager.apply_snapshot(&create_test_snapshot(1001, 200));
+        manager.apply_snapshot(&create_test_snapshot(1003, 300));
+        manager.apply_update(create_test_update(1001, 201));
+        manager.resync(1003).unwrap();
+
+        let mut written = Vec::new();
+        manager.write_snapshots(&mut written).unwrap();
+
+        let mut reader = written.as_slice();
+        let mut parser = OrderBookSnapshotParser::default();
+        let mut restarted = Manager::default();
+        let mut security_ids = Vec::new();
+        while let Ok(snapshot) = parser.read(&mut reader) {
+            security_ids.push(snapshot.security_id);
+            assert!(restarted.apply_snapshot(&snapshot).is_applied());
+        }
+        assert_eq!(security_ids, vec![1001, 1002]);
+        let restored = &restarted.buffered_order_books[&1001].order_book;
+        let original = &manager.buffered_order_books[&1001].order_book;
+        assert_eq!(restored.seq_no, 201);
+        assert_eq!(restored.bids, original.bids);
+        assert_eq!(restored.asks, original.asks);
+    }
+
+    #[test]
+    fn test_pending_updates_round_trip() {
+        let security_id = 1001;
+        let mut manager = Manager::default();
+        assert!(
+            manager
+                .apply_snapshot(&create_test_snapshot(security_id, 100))
+                .is_applied()
+        );
+        assert!(matches!(
+            manager.apply_update(create_test_update(security_id, 103)),
+            ManagerOutcome::Buffered(_)
+        ));
+        assert!(matches!(
+            manager.apply_update(create_test_update(security_id, 102)),
+            ManagerOutcome::Buffered(_)
+        ));
+
+        let mut saved = Vec::new();
+        manager
+            .write_pending_updates(&mut saved, UpdateFormat::V1)
+            .unwrap();
+
+        // Restart: the books come back from the snapshots, then the buffered updates
+        let mut restarted = Manager::default();
+        assert!(
+            restarted
+                .apply_snapshot(&create_test_snapshot(security_id, 100))
+                .is_applied()
+        );
+        let restored = restarted
+            .restore_pending_updates(&mut saved.as_slice(), UpdateFormat::V1)
+            .unwrap();
+        assert_eq!(restored, 2);
+        let buffered_order_book = &restarted.buffered_order_books[&security_id];
+        assert_eq!(buffered_order_book.pending_updates.len(), 2);
+
+        // The update closing the gap replays the restored ones
+        assert!(
+            restarted
+                .apply_update(create_test_update(security_id, 101))
+                .is_applied()
+        );
+        let buffered_order_book = &restarted.buffered_order_books[&security_id];
+        assert_eq!(buffered_order_book.order_book.seq_no, 103);
+        assert!(buffered_order_book.pending_updates.is_empty());
+    }
+
+    #[test]
+    fn test_execute_resync() {
+        let mut manager = Manager::default();
+        let security_id = 1001;
+        assert!(
+            manager
+                .apply_snapshot(&create_test_snapshot(security_id, 100))
+                .is_applied()
+        );
+
+        let result = manager.execute("resync 1002".parse().unwrap());
+        assert!(matches!(result, Err(Errors::OrderBookNotFound(_))));
+
+        manager.execute("resync 1001".parse().unwrap()).unwrap();
+        let result = manager.apply_update(create_test_update(security_id, 101));
+        assert!(matches!(
+            result,
+            ManagerOutcome::Buffered(GapInfo {
+                awaiting_snapshot: true,
+                ..
+            })
+        ));
+        assert!(
+            manager.buffered_order_books[&security_id]
+                .order_book
+                .bids
+                .is_empty()
+        );
     }
 
     #[test]
@@ -196,8 +1877,8 @@ mod tests {
         let result1 = manager.apply_snapshot(&snapshot1);
         let result2 = manager.apply_snapshot(&snapshot2);
 
-        assert!(result1.is_ok());
-        assert!(result2.is_ok());
+        assert!(result1.is_applied());
+        assert!(result2.is_applied());
         assert_eq!(manager.buffered_order_books.len(), 2);
         assert!(manager.buffered_order_books.contains_key(&security_id1));
         assert!(manager.buffered_order_books.contains_key(&security_id2));
@@ -1,229 +1,659 @@
-use num_traits::FromPrimitive;
+use num_traits::ToPrimitive;
 use rust_decimal::{Decimal, dec};
+use smallvec::SmallVec;
 use std::collections::BTreeMap;
 use std::fmt::Display;
+use std::str::FromStr;
+use std::sync::Arc;
 
+use crate::order_book::book_side::BookSide;
 use crate::order_book::errors::Errors;
 use crate::order_book::errors::UpdateMessageInfo;
-use crate::parsing::order_book_snapshot::OrderBookSnapshot;
+use crate::order_book::price::{PRICE_TICK, PriceKey};
+use crate::order_book::security_registry::SecurityInfo;
+use crate::order_book::units::{Price, Qty};
+use crate::parsing::order_book_snapshot::Level as SnapshotLevel;
+use crate::parsing::order_book_snapshot::{OrderBookSnapshot, SNAPSHOT_DEPTH};
 use crate::parsing::order_book_update::Level as UpdateLevel;
+use crate::parsing::order_book_update::LevelContainer;
+use crate::parsing::order_book_update::LevelMetadata;
 use crate::parsing::order_book_update::OrderBookUpdate;
 
+pub use crate::parsing::order_book_update::Side;
+
+// What to do with an update carrying invalid levels, e.g. junk levels some venues ship
+// inside otherwise good messages
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum LevelPolicy {
+    // The whole update is rejected
+    #[default]
+    Atomic,
+    // The valid levels are applied and the invalid ones quarantined
+    BestEffort,
+}
+
+impl FromStr for LevelPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "atomic" => Ok(LevelPolicy::Atomic),
+            "best-effort" => Ok(LevelPolicy::BestEffort),
+            _ => Err(format!("Unknown level policy: {}", s)),
+        }
+    }
+}
+
+// What to do with an update whose timestamp is before the one of its book, e.g. from a
+// feed handler that stamps the records of several sessions
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum TimestampPolicy {
+    // The update is applied and the book goes back in time
+    #[default]
+    Ignore,
+    // The update is applied and the listeners are told, see BookListener
+    Warn,
+    // The update is rejected
+    Reject,
+}
+
+impl FromStr for TimestampPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "ignore" => Ok(TimestampPolicy::Ignore),
+            "warn" => Ok(TimestampPolicy::Warn),
+            "reject" => Ok(TimestampPolicy::Reject),
+            _ => Err(format!("Unknown timestamp policy: {}", s)),
+        }
+    }
+}
+
+// A level left out of an update applied with LevelPolicy::BestEffort
 #[derive(Debug)]
-pub struct OrderBook {
-    pub timestamp: u64,
+pub struct QuarantinedLevel {
+    pub security_id: u64,
     pub seq_no: u64,
+    // Position of the level in the update
+    pub index: usize,
+    pub side: Side,
+    pub price: f64,
+    pub qty: Qty,
+    pub error: Errors,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct BookLevel {
+    pub price: Price,
+    pub qty: Qty,
+    pub order_count: Option<u32>,
+}
+
+// An immutable copy of a book for readers on other threads or kept for later, see
+// OrderBook::snapshot_state. Clones share the levels.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct OrderBookState {
     pub security_id: u64,
-    pub bids: BTreeMap<Decimal, u64>,
-    pub asks: BTreeMap<Decimal, u64>,
+    pub seq_no: u64,
+    pub timestamp: u64,
+    pub capture_timestamp: Option<u64>,
+    // Best level first
+    pub bids: Arc<[BookLevel]>,
+    pub asks: Arc<[BookLevel]>,
+}
+
+impl OrderBookState {
+    pub fn best_bid(&self) -> Option<&BookLevel> {
+        self.bids.first()
+    }
+
+    pub fn best_ask(&self) -> Option<&BookLevel> {
+        self.asks.first()
+    }
+}
+
+// Levels of each side validated before any is applied. Snapshots and most updates fit in
+// the inline buffer, which spares the book the heap allocations of its first records.
+const STAGED_LEVELS: usize = 8;
+type StagedLevels<P> = SmallVec<[(P, Qty, Option<LevelMetadata>); STAGED_LEVELS]>;
 
-    bid_updates: Vec<(Decimal, u64)>,
-    ask_updates: Vec<(Decimal, u64)>,
+// Levels are keyed by Decimal prices unless another PriceKey is given, e.g. Ticks for a
+// faster apply path, and stored in a BTreeMap unless another BookSide is given
+#[derive(Debug, Clone)]
+pub struct OrderBook<P = Decimal, S = BTreeMap<P, Qty>> {
+    pub timestamp: u64,
+    pub capture_timestamp: Option<u64>,
+    pub seq_no: u64,
+    pub security_id: u64,
+    pub bids: S,
+    pub asks: S,
+    // Per-level metadata, only present for levels last updated by a V2 update
+    pub bid_metadata: BTreeMap<P, LevelMetadata>,
+    pub ask_metadata: BTreeMap<P, LevelMetadata>,
+    // Levels kept per side, the worst ones beyond it are dropped after every record
+    max_depth: Option<usize>,
+    // Largest distance of an update price from the mid, in percent of the mid
+    max_price_deviation: Option<Decimal>,
+    // Bids may meet or pass the asks, e.g. during auctions
+    allow_crossed: bool,
+    timestamp_policy: TimestampPolicy,
+    // Timestamp of the book before the last update when that update went back in time
+    // under TimestampPolicy::Warn
+    regressed_from: Option<u64>,
+    // Reference data the prices are checked against and the symbol is printed from
+    security_info: Option<Arc<SecurityInfo>>,
+
+    bid_updates: StagedLevels<P>,
+    ask_updates: StagedLevels<P>,
 }
 
 impl OrderBook {
-    pub const PRICE_TICK: Decimal = dec!(0.01);
+    pub const PRICE_TICK: Decimal = PRICE_TICK;
 
     pub fn new(snapshot: &OrderBookSnapshot) -> Result<Self, Errors> {
-        let mut order_book = Self {
-            timestamp: snapshot.timestamp,
-            seq_no: snapshot.seq_no,
-            security_id: snapshot.security_id,
-            bids: BTreeMap::new(),
-            asks: BTreeMap::new(),
-            bid_updates: Vec::new(),
-            ask_updates: Vec::new(),
-        };
+        Self::from_snapshot(snapshot)
+    }
+}
+
+impl<P: PriceKey, S: BookSide<P>> OrderBook<P, S> {
+    pub const CHECKSUM_DEPTH: usize = 10;
+
+    pub fn from_snapshot(snapshot: &OrderBookSnapshot) -> Result<Self, Errors> {
+        Self::from_snapshot_with_info(snapshot, None)
+    }
+
+    // Also checks the prices of the snapshot against the reference data
+    pub fn from_snapshot_with_info(
+        snapshot: &OrderBookSnapshot,
+        security_info: Option<Arc<SecurityInfo>>,
+    ) -> Result<Self, Errors> {
+        let mut order_book = Self::empty(snapshot.security_id, snapshot.seq_no, snapshot.timestamp);
+        order_book.security_info = security_info;
         Self::apply_snapshot_sides(&mut order_book, snapshot)?;
 
         Ok(order_book)
     }
 
-    pub fn apply_update(&mut self, update: &OrderBookUpdate) -> Result<(), Errors> {
+    pub(crate) fn empty(security_id: u64, seq_no: u64, timestamp: u64) -> Self {
+        Self {
+            timestamp,
+            capture_timestamp: None,
+            seq_no,
+            security_id,
+            bids: S::default(),
+            asks: S::default(),
+            bid_metadata: BTreeMap::new(),
+            ask_metadata: BTreeMap::new(),
+            max_depth: None,
+            max_price_deviation: None,
+            allow_crossed: false,
+            timestamp_policy: TimestampPolicy::default(),
+            regressed_from: None,
+            security_info: None,
+            bid_updates: SmallVec::new(),
+            ask_updates: SmallVec::new(),
+        }
+    }
+
+    pub fn apply_update<L: LevelContainer>(
+        &mut self,
+        update: &OrderBookUpdate<L>,
+    ) -> Result<(), Errors> {
+        self.apply_update_with_policy(update, LevelPolicy::Atomic, &mut Vec::new())
+    }
+
+    // With LevelPolicy::BestEffort, the invalid levels are added to `quarantined` and the
+    // update is applied without them
+    pub fn apply_update_with_policy<L: LevelContainer>(
+        &mut self,
+        update: &OrderBookUpdate<L>,
+        policy: LevelPolicy,
+        quarantined: &mut Vec<QuarantinedLevel>,
+    ) -> Result<(), Errors> {
+        let info = UpdateMessageInfo::new(update.security_id, update.seq_no);
         if update.security_id != self.security_id {
-            return Err(Errors::SecurityIdMismatch);
+            return Err(Errors::SecurityIdMismatch(info));
         }
         if update.seq_no <= self.seq_no {
-            return Err(Errors::OldSequenceNumber);
+            return Err(Errors::OldSequenceNumber(info));
+        }
+        if update.seq_no != self.seq_no + 1 && update.thinned_from != Some(self.seq_no) {
+            return Err(Errors::SequenceNumberGap(info));
         }
-        if update.seq_no != self.seq_no + 1 {
-            return Err(Errors::SequenceNumberGap);
+        let regressed_from = (update.timestamp < self.timestamp).then_some(self.timestamp);
+        if let Some(timestamp) = regressed_from
+            && self.timestamp_policy == TimestampPolicy::Reject
+        {
+            return Err(Errors::TimestampRegression(
+                info,
+                format!("{} is before {}", update.timestamp, timestamp),
+            ));
         }
 
         self.ask_updates.clear();
         self.bid_updates.clear();
 
         // Prepare updates
-        update
-            .updates
-            .for_each(|upd: &UpdateLevel| -> Result<(), Errors> {
-                let price = Self::normalized_price(update.security_id, update.seq_no, upd.price)?;
-                match upd.side {
-                    0 => self.bid_updates.push((price, upd.qty)),
-                    1 => self.ask_updates.push((price, upd.qty)),
-                    _ => {
-                        return Err(Errors::InvalidSide(
-                            UpdateMessageInfo {
-                                security_id: update.security_id,
-                                seq_no: update.seq_no,
-                            },
-                            format!("{}", upd.side),
-                        ));
-                    }
+        let reference_price = self
+            .max_price_deviation
+            .and_then(|_| self.reference_price());
+        for (index, upd) in update.updates.iter().enumerate() {
+            match (self.prepare_level(update, &upd, reference_price), policy) {
+                (Err(error), LevelPolicy::BestEffort) => {
+                    quarantined.push(QuarantinedLevel {
+                        security_id: update.security_id,
+                        seq_no: update.seq_no,
+                        index,
+                        side: upd.side,
+                        price: upd.price,
+                        qty: upd.qty,
+                        error,
+                    });
                 }
-                Ok(())
-            })?;
+                (prepared, _) => prepared?,
+            }
+        }
 
         // Apply updates atomically
-        for (price, qty) in self.bid_updates.drain(..) {
-            if qty == 0 {
-                self.bids.remove(&price);
-            } else {
-                self.bids.insert(price, qty);
-            }
+        for (price, qty, metadata) in self.bid_updates.drain(..) {
+            Self::apply_level(&mut self.bids, &mut self.bid_metadata, price, qty, metadata);
         }
-        for (price, qty) in self.ask_updates.drain(..) {
-            if qty == 0 {
-                self.asks.remove(&price);
-            } else {
-                self.asks.insert(price, qty);
-            }
+        for (price, qty, metadata) in self.ask_updates.drain(..) {
+            Self::apply_level(&mut self.asks, &mut self.ask_metadata, price, qty, metadata);
         }
+        self.trim_to_max_depth();
 
         self.timestamp = update.timestamp;
+        self.capture_timestamp = update.capture_timestamp;
         self.seq_no = update.seq_no;
+        self.regressed_from =
+            regressed_from.filter(|_| self.timestamp_policy == TimestampPolicy::Warn);
 
         Ok(())
     }
 
     pub fn apply_snapshot(&mut self, snapshot: &OrderBookSnapshot) -> Result<(), Errors> {
+        let info = UpdateMessageInfo::new(snapshot.security_id, snapshot.seq_no);
         if snapshot.security_id != self.security_id {
-            return Err(Errors::SecurityIdMismatch);
+            return Err(Errors::SecurityIdMismatch(info));
         }
         if snapshot.seq_no <= self.seq_no {
-            return Err(Errors::OldSequenceNumber);
+            return Err(Errors::OldSequenceNumber(info));
         }
 
         Self::apply_snapshot_sides(self, snapshot)?;
 
         self.timestamp = snapshot.timestamp;
+        self.capture_timestamp = None;
         self.seq_no = snapshot.seq_no;
+        self.regressed_from = None;
 
         Ok(())
     }
 
-    fn apply_snapshot_sides(&mut self, snapshot: &OrderBookSnapshot) -> Result<(), Errors> {
-        self.ask_updates.clear();
-        self.bid_updates.clear();
+    // Removes the level with its metadata and returns its quantity
+    pub fn remove_level(&mut self, side: Side, price: P) -> Option<Qty> {
+        let (levels, levels_metadata) = match side {
+            Side::Bid => (&mut self.bids, &mut self.bid_metadata),
+            Side::Ask => (&mut self.asks, &mut self.ask_metadata),
+        };
+        levels_metadata.remove(&price);
+        levels.remove(&price)
+    }
 
-        // Prepare asks
-        if snapshot.ask1.qty > 0 {
-            self.ask_updates.push((
-                Self::normalized_price(snapshot.security_id, snapshot.seq_no, snapshot.ask1.price)?,
-                snapshot.ask1.qty,
-            ));
+    // The mid of the book, or its only best price while one side is empty
+    pub fn reference_price(&self) -> Option<Decimal> {
+        match (self.best_bid(), self.best_ask()) {
+            (Some((bid, _)), Some((ask, _))) => Some((bid + ask) / dec!(2)),
+            (Some((price, _)), None) | (None, Some((price, _))) => Some(price),
+            (None, None) => None,
         }
-        if snapshot.ask2.qty > 0 {
-            self.ask_updates.push((
-                Self::normalized_price(snapshot.security_id, snapshot.seq_no, snapshot.ask2.price)?,
-                snapshot.ask2.qty,
-            ));
+    }
+
+    pub fn max_price_deviation(&self) -> Option<Decimal> {
+        self.max_price_deviation
+    }
+
+    // Rejects the levels of the next updates priced more than `max_deviation` percent
+    // away from the reference price of the book when they arrive, which catches
+    // fat-fingered and corrupted prices. Snapshots are trusted and reset the reference.
+    pub fn set_max_price_deviation(&mut self, max_deviation: Option<Decimal>) {
+        self.max_price_deviation = max_deviation;
+    }
+
+    // Lets validate accept books whose best bid is not below their best ask, for venues
+    // and sessions where they legitimately cross
+    pub fn set_allow_crossed(&mut self, allow_crossed: bool) {
+        self.allow_crossed = allow_crossed;
+    }
+
+    pub fn set_timestamp_policy(&mut self, policy: TimestampPolicy) {
+        self.timestamp_policy = policy;
+    }
+
+    // The timestamp the last update went back from under TimestampPolicy::Warn
+    pub fn regressed_from(&self) -> Option<u64> {
+        self.regressed_from
+    }
+
+    // Checks the invariants the apply paths keep, for debugging them and new feeds:
+    // no level without quantity, no metadata without its level, no more levels than the
+    // maximum depth and the best bid below the best ask unless crossing is allowed.
+    // Returns the first broken one.
+    pub fn validate(&self) -> Result<(), String> {
+        for (name, levels, levels_metadata) in [
+            ("bid", &self.bids, &self.bid_metadata),
+            ("ask", &self.asks, &self.ask_metadata),
+        ] {
+            if let Some((price, _)) = levels.iter().find(|(_, qty)| qty.is_zero()) {
+                return Err(format!(
+                    "The {} level {} has no quantity",
+                    name,
+                    price.to_decimal()
+                ));
+            }
+            if let Some(price) = levels_metadata
+                .keys()
+                .find(|price| levels.get(price).is_none())
+            {
+                return Err(format!(
+                    "The {} level {} has metadata but no quantity",
+                    name,
+                    price.to_decimal()
+                ));
+            }
+            if let Some(max_depth) = self.max_depth
+                && levels.len() > max_depth
+            {
+                return Err(format!(
+                    "{} {} levels, more than the maximum depth {}",
+                    levels.len(),
+                    name,
+                    max_depth
+                ));
+            }
         }
-        if snapshot.ask3.qty > 0 {
-            self.ask_updates.push((
-                Self::normalized_price(snapshot.security_id, snapshot.seq_no, snapshot.ask3.price)?,
-                snapshot.ask3.qty,
+        if !self.allow_crossed
+            && let (Some((bid, _)), Some((ask, _))) = (self.best_bid(), self.best_ask())
+            && bid >= ask
+        {
+            return Err(format!(
+                "The best bid {} is not below the best ask {}",
+                bid, ask
             ));
         }
-        if snapshot.ask4.qty > 0 {
-            self.ask_updates.push((
-                Self::normalized_price(snapshot.security_id, snapshot.seq_no, snapshot.ask4.price)?,
-                snapshot.ask4.qty,
-            ));
+        Ok(())
+    }
+
+    pub fn max_depth(&self) -> Option<usize> {
+        self.max_depth
+    }
+
+    // Keeps at most `max_depth` levels per side from now on, as venues that only publish
+    // a bounded depth do, and drops the worst levels beyond it right away
+    pub fn set_max_depth(&mut self, max_depth: Option<usize>) {
+        self.max_depth = max_depth;
+        self.trim_to_max_depth();
+    }
+
+    fn trim_to_max_depth(&mut self) {
+        let Some(max_depth) = self.max_depth else {
+            return;
+        };
+        for (side, levels, levels_metadata) in [
+            (Side::Bid, &mut self.bids, &mut self.bid_metadata),
+            (Side::Ask, &mut self.asks, &mut self.ask_metadata),
+        ] {
+            while levels.len() > max_depth {
+                if let Some((price, _)) = levels.pop_worst(side) {
+                    levels_metadata.remove(&price);
+                }
+            }
         }
-        if snapshot.ask5.qty > 0 {
-            self.ask_updates.push((
-                Self::normalized_price(snapshot.security_id, snapshot.seq_no, snapshot.ask5.price)?,
-                snapshot.ask5.qty,
-            ));
+    }
+
+    pub fn security_info(&self) -> Option<&SecurityInfo> {
+        self.security_info.as_deref()
+    }
+
+    // Checks the prices of the next records against the reference data. The levels
+    // already in the book are kept as they are.
+    pub fn set_security_info(&mut self, security_info: Option<Arc<SecurityInfo>>) {
+        self.security_info = security_info;
+    }
+
+    pub fn clear_levels(&mut self) {
+        self.bids.clear();
+        self.asks.clear();
+        self.bid_metadata.clear();
+        self.ask_metadata.clear();
+    }
+
+    pub fn best_bid(&self) -> Option<(Decimal, Qty)> {
+        self.bids
+            .best(Side::Bid)
+            .map(|(price, qty)| (price.to_decimal(), *qty))
+    }
+
+    pub fn best_ask(&self) -> Option<(Decimal, Qty)> {
+        self.asks
+            .best(Side::Ask)
+            .map(|(price, qty)| (price.to_decimal(), *qty))
+    }
+
+    // Returns up to `depth` levels of the side, best price first
+    pub fn top_levels(&self, side: Side, depth: usize) -> Vec<BookLevel> {
+        let (levels, levels_metadata) = match side {
+            Side::Bid => (&self.bids, &self.bid_metadata),
+            Side::Ask => (&self.asks, &self.ask_metadata),
+        };
+        let to_book_level = |(price, qty): (&P, &Qty)| BookLevel {
+            price: Price::from_key(*price),
+            qty: *qty,
+            order_count: levels_metadata
+                .get(price)
+                .map(|metadata| metadata.order_count),
+        };
+        match side {
+            Side::Bid => levels.iter().rev().take(depth).map(to_book_level).collect(),
+            Side::Ask => levels.iter().take(depth).map(to_book_level).collect(),
         }
+    }
 
-        // Prepare bids
-        if snapshot.bid1.qty > 0 {
-            self.bid_updates.push((
-                Self::normalized_price(snapshot.security_id, snapshot.seq_no, snapshot.bid1.price)?,
-                snapshot.bid1.qty,
-            ));
+    // CRC32 of the top levels in the form crypto venues publish with their books:
+    // "bid_price:bid_qty:ask_price:ask_qty:..." from the best level down, prices without
+    // trailing zeros. A side with fewer levels stops contributing where it ends.
+    pub fn checksum(&self) -> u32 {
+        self.checksum_with_depth(Self::CHECKSUM_DEPTH)
+    }
+
+    pub fn checksum_with_depth(&self, depth: usize) -> u32 {
+        let bids = self.top_levels(Side::Bid, depth);
+        let asks = self.top_levels(Side::Ask, depth);
+        let mut fields = Vec::with_capacity((bids.len() + asks.len()) * 2);
+        for i in 0..depth {
+            for level in [bids.get(i), asks.get(i)].into_iter().flatten() {
+                fields.push(level.price.value().normalize().to_string());
+                fields.push(level.qty.to_string());
+            }
         }
-        if snapshot.bid2.qty > 0 {
-            self.bid_updates.push((
-                Self::normalized_price(snapshot.security_id, snapshot.seq_no, snapshot.bid2.price)?,
-                snapshot.bid2.qty,
-            ));
+        crc32fast::hash(fields.join(":").as_bytes())
+    }
+
+    // Copies every level of the book
+    pub fn snapshot_state(&self) -> OrderBookState {
+        OrderBookState {
+            security_id: self.security_id,
+            seq_no: self.seq_no,
+            timestamp: self.timestamp,
+            capture_timestamp: self.capture_timestamp,
+            bids: self.top_levels(Side::Bid, usize::MAX).into(),
+            asks: self.top_levels(Side::Ask, usize::MAX).into(),
         }
-        if snapshot.bid3.qty > 0 {
-            self.bid_updates.push((
-                Self::normalized_price(snapshot.security_id, snapshot.seq_no, snapshot.bid3.price)?,
-                snapshot.bid3.qty,
-            ));
+    }
+
+    // Top 5 levels of the book as a snapshot record, missing levels have a zero quantity
+    pub fn to_snapshot(&self) -> OrderBookSnapshot {
+        self.to_snapshot_with_depth(SNAPSHOT_DEPTH)
+    }
+
+    pub fn to_snapshot_with_depth(&self, depth: usize) -> OrderBookSnapshot {
+        let side = |side: Side| {
+            let mut levels: Vec<SnapshotLevel> = self
+                .top_levels(side, depth)
+                .iter()
+                .map(|level| SnapshotLevel {
+                    price: level.price.value().to_f64().unwrap_or(0.0),
+                    qty: level.qty,
+                })
+                .collect();
+            levels.resize_with(depth, || SnapshotLevel {
+                price: 0.0,
+                qty: Qty::ZERO,
+            });
+            levels
+        };
+        OrderBookSnapshot {
+            timestamp: self.timestamp,
+            seq_no: self.seq_no,
+            security_id: self.security_id,
+            bids: side(Side::Bid),
+            asks: side(Side::Ask),
         }
-        if snapshot.bid4.qty > 0 {
-            self.bid_updates.push((
-                Self::normalized_price(snapshot.security_id, snapshot.seq_no, snapshot.bid4.price)?,
-                snapshot.bid4.qty,
+    }
+
+    fn apply_snapshot_sides(&mut self, snapshot: &OrderBookSnapshot) -> Result<(), Errors> {
+        self.ask_updates.clear();
+        self.bid_updates.clear();
+
+        // Prepare asks
+        for level in snapshot.asks.iter().filter(|level| !level.qty.is_zero()) {
+            self.ask_updates.push((
+                self.normalized_price(snapshot.security_id, snapshot.seq_no, level.price)?,
+                self.checked_qty(snapshot.security_id, snapshot.seq_no, level.qty)?,
+                None,
             ));
         }
-        if snapshot.bid5.qty > 0 {
+
+        // Prepare bids
+        for level in snapshot.bids.iter().filter(|level| !level.qty.is_zero()) {
             self.bid_updates.push((
-                Self::normalized_price(snapshot.security_id, snapshot.seq_no, snapshot.bid5.price)?,
-                snapshot.bid5.qty,
+                self.normalized_price(snapshot.security_id, snapshot.seq_no, level.price)?,
+                self.checked_qty(snapshot.security_id, snapshot.seq_no, level.qty)?,
+                None,
             ));
         }
 
         // Apply updates atomically
         self.asks.clear();
-        for (price, qty) in self.ask_updates.drain(..) {
+        self.ask_metadata.clear();
+        for (price, qty, _) in self.ask_updates.drain(..) {
             self.asks.insert(price, qty);
         }
         self.bids.clear();
-        for (price, qty) in self.bid_updates.drain(..) {
+        self.bid_metadata.clear();
+        for (price, qty, _) in self.bid_updates.drain(..) {
             self.bids.insert(price, qty);
         }
+        self.trim_to_max_depth();
 
         Ok(())
     }
 
-    fn normalized_price(security_id: u64, seq_no: u64, price: f64) -> Result<Decimal, Errors> {
-        match Decimal::from_f64(price) {
-            Some(dec) => {
-                if dec % Self::PRICE_TICK == dec!(0.0) {
-                    Ok(dec)
-                } else {
-                    Err(Errors::InvalidPrice(
-                        UpdateMessageInfo {
-                            security_id,
-                            seq_no,
-                        },
-                        format!(
-                            "The price {} is not a multiple of {}",
-                            price,
-                            Self::PRICE_TICK
-                        ),
-                    ))
-                }
+    fn apply_level(
+        levels: &mut S,
+        levels_metadata: &mut BTreeMap<P, LevelMetadata>,
+        price: P,
+        qty: Qty,
+        metadata: Option<LevelMetadata>,
+    ) {
+        if qty.is_zero() {
+            levels.remove(&price);
+            levels_metadata.remove(&price);
+            return;
+        }
+        levels.insert(price, qty);
+        match metadata {
+            Some(metadata) => levels_metadata.insert(price, metadata),
+            None => levels_metadata.remove(&price),
+        };
+    }
+
+    fn prepare_level<L: LevelContainer>(
+        &mut self,
+        update: &OrderBookUpdate<L>,
+        upd: &UpdateLevel,
+        reference_price: Option<Decimal>,
+    ) -> Result<(), Errors> {
+        let price = self.normalized_price(update.security_id, update.seq_no, upd.price)?;
+        let qty = self.checked_qty(update.security_id, update.seq_no, upd.qty)?;
+        // Far levels can always be removed
+        if let (Some(max_deviation), Some(reference_price)) =
+            (self.max_price_deviation, reference_price)
+            && !qty.is_zero()
+        {
+            let price = price.to_decimal();
+            if (price - reference_price).abs() * dec!(100) > max_deviation * reference_price {
+                return Err(Errors::PriceDeviation(
+                    UpdateMessageInfo::new(update.security_id, update.seq_no),
+                    format!(
+                        "The price {} is more than {}% away from {}",
+                        price,
+                        max_deviation,
+                        reference_price.normalize()
+                    ),
+                ));
             }
-            None => Err(Errors::InvalidPrice(
-                UpdateMessageInfo {
-                    security_id,
-                    seq_no,
-                },
-                format!("Failed to convert f64 value {} to Decimal", price),
-            )),
+        }
+        match upd.side {
+            Side::Bid => self.bid_updates.push((price, qty, upd.metadata)),
+            Side::Ask => self.ask_updates.push((price, qty, upd.metadata)),
+        }
+        Ok(())
+    }
+
+    fn normalized_price(&self, security_id: u64, seq_no: u64, price: f64) -> Result<P, Errors> {
+        let invalid_price =
+            |reason| Errors::InvalidPrice(UpdateMessageInfo::new(security_id, seq_no), reason);
+        let price = P::from_f64(price).map_err(invalid_price)?;
+        if let Some(security_info) = &self.security_info {
+            security_info
+                .price(price.to_decimal())
+                .map_err(invalid_price)?;
+        }
+        Ok(price)
+    }
+
+    fn checked_qty(&self, security_id: u64, seq_no: u64, qty: Qty) -> Result<Qty, Errors> {
+        match &self.security_info {
+            Some(security_info) => security_info.qty(qty.value()).map_err(|reason| {
+                Errors::InvalidQuantity(UpdateMessageInfo::new(security_id, seq_no), reason)
+            }),
+            None => Ok(qty),
+        }
+    }
+
+    fn fmt_level(
+        f: &mut std::fmt::Formatter<'_>,
+        price: Decimal,
+        qty: Qty,
+        metadata: Option<&LevelMetadata>,
+    ) -> std::fmt::Result {
+        match metadata {
+            Some(metadata) => writeln!(
+                f,
+                "    {:.2} @ {} (orders: {}, action: {})",
+                price, qty, metadata.order_count, metadata.action
+            ),
+            None => writeln!(f, "    {:.2} @ {}", price, qty),
         }
     }
 }
 
-impl Display for OrderBook {
+impl<P: PriceKey, S: BookSide<P>> Display for OrderBook<P, S> {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         writeln!(f, "OrderBook {{")?;
 
@@ -233,19 +663,29 @@ impl Display for OrderBook {
             .map(|dt| dt.format("%Y-%m-%d %H:%M:%S%.3f UTC").to_string())
             .unwrap_or_else(|| "Invalid timestamp".to_string());
         writeln!(f, "  timestamp: {} ({})", self.timestamp, formatted_time)?;
+        if let Some(capture_timestamp) = self.capture_timestamp {
+            writeln!(f, "  capture_timestamp: {}", capture_timestamp)?;
+        }
 
         writeln!(f, "  seq_no: {}", self.seq_no)?;
-        writeln!(f, "  security_id: {}", self.security_id)?;
+        match &self.security_info {
+            Some(security_info) => writeln!(
+                f,
+                "  security_id: {} ({})",
+                self.security_id, security_info.symbol
+            )?,
+            None => writeln!(f, "  security_id: {}", self.security_id)?,
+        }
 
         writeln!(f, "  asks: [")?;
         for (price, qty) in self.asks.iter().rev() {
-            writeln!(f, "    {:.2} @ {}", price, qty)?;
+            Self::fmt_level(f, price.to_decimal(), *qty, self.ask_metadata.get(price))?;
         }
         writeln!(f, "  ]")?;
 
         writeln!(f, "  bids: [")?;
         for (price, qty) in self.bids.iter().rev() {
-            writeln!(f, "    {:.2} @ {}", price, qty)?;
+            Self::fmt_level(f, price.to_decimal(), *qty, self.bid_metadata.get(price))?;
         }
         writeln!(f, "  ]")?;
 
@@ -253,57 +693,159 @@ impl Display for OrderBook {
     }
 }
 
+// Books serialize the prices of their levels as strings, rust_decimal is built without
+// serde support. The settings and the reference data of a book are not part of its state
+// and come back as defaults.
+#[cfg(feature = "serde")]
+mod serde_support {
+    use super::*;
+    use serde::de::Error;
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+    #[derive(Serialize, Deserialize)]
+    struct SerializedLevel {
+        price: String,
+        qty: Qty,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        metadata: Option<LevelMetadata>,
+    }
+
+    #[derive(Serialize, Deserialize)]
+    struct SerializedBook {
+        security_id: u64,
+        seq_no: u64,
+        timestamp: u64,
+        capture_timestamp: Option<u64>,
+        bids: Vec<SerializedLevel>,
+        asks: Vec<SerializedLevel>,
+    }
+
+    fn serialize_levels(
+        levels: &BTreeMap<Decimal, Qty>,
+        levels_metadata: &BTreeMap<Decimal, LevelMetadata>,
+    ) -> Vec<SerializedLevel> {
+        levels
+            .iter()
+            .map(|(price, qty)| SerializedLevel {
+                price: price.to_string(),
+                qty: *qty,
+                metadata: levels_metadata.get(price).copied(),
+            })
+            .collect()
+    }
+
+    fn deserialize_levels(
+        serialized: Vec<SerializedLevel>,
+        levels: &mut BTreeMap<Decimal, Qty>,
+        levels_metadata: &mut BTreeMap<Decimal, LevelMetadata>,
+    ) -> Result<(), String> {
+        for level in serialized {
+            let price = level
+                .price
+                .parse::<Decimal>()
+                .map_err(|_| format!("Invalid price: {}", level.price))?;
+            if level.qty.is_zero() {
+                return Err(format!("Level {} without quantity", price));
+            }
+            if levels.insert(price, level.qty).is_some() {
+                return Err(format!("Duplicate level {}", price));
+            }
+            if let Some(metadata) = level.metadata {
+                levels_metadata.insert(price, metadata);
+            }
+        }
+        Ok(())
+    }
+
+    impl Serialize for OrderBook {
+        fn serialize<Ser: Serializer>(&self, serializer: Ser) -> Result<Ser::Ok, Ser::Error> {
+            SerializedBook {
+                security_id: self.security_id,
+                seq_no: self.seq_no,
+                timestamp: self.timestamp,
+                capture_timestamp: self.capture_timestamp,
+                bids: serialize_levels(&self.bids, &self.bid_metadata),
+                asks: serialize_levels(&self.asks, &self.ask_metadata),
+            }
+            .serialize(serializer)
+        }
+    }
+
+    impl<'de> Deserialize<'de> for OrderBook {
+        fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+            let serialized = SerializedBook::deserialize(deserializer)?;
+            let mut book = OrderBook::empty(
+                serialized.security_id,
+                serialized.seq_no,
+                serialized.timestamp,
+            );
+            book.capture_timestamp = serialized.capture_timestamp;
+            deserialize_levels(serialized.bids, &mut book.bids, &mut book.bid_metadata)
+                .map_err(D::Error::custom)?;
+            deserialize_levels(serialized.asks, &mut book.asks, &mut book.ask_metadata)
+                .map_err(D::Error::custom)?;
+            Ok(book)
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
     use crate::batched_deque::batched_deque::BatchedDeque;
-    use crate::parsing::order_book_snapshot::Level as SnapshotLevel;
+    use crate::order_book::price::Ticks;
+    use crate::order_book::sorted_vec_side::SortedVecSide;
+    use rust_decimal::dec;
 
     fn create_test_snapshot(security_id: u64, seq_no: u64) -> OrderBookSnapshot {
         OrderBookSnapshot {
             timestamp: 1627846265,
             seq_no,
             security_id,
-            bid1: SnapshotLevel {
-                price: 100.00,
-                qty: 10,
-            },
-            ask1: SnapshotLevel {
-                price: 101.00,
-                qty: 15,
-            },
-            bid2: SnapshotLevel {
-                price: 99.00,
-                qty: 20,
-            },
-            ask2: SnapshotLevel {
-                price: 102.00,
-                qty: 25,
-            },
-            bid3: SnapshotLevel {
-                price: 98.00,
-                qty: 30,
-            },
-            ask3: SnapshotLevel {
-                price: 103.00,
-                qty: 35,
-            },
-            bid4: SnapshotLevel {
-                price: 97.00,
-                qty: 40,
-            },
-            ask4: SnapshotLevel {
-                price: 104.00,
-                qty: 45,
-            },
-            bid5: SnapshotLevel {
-                price: 96.00,
-                qty: 50,
-            },
-            ask5: SnapshotLevel {
-                price: 105.00,
-                qty: 55,
-            },
+            bids: vec![
+                SnapshotLevel {
+                    price: 100.00,
+                    qty: Qty(10),
+                },
+                SnapshotLevel {
+                    price: 99.00,
+                    qty: Qty(20),
+                },
+                SnapshotLevel {
+                    price: 98.00,
+                    qty: Qty(30),
+                },
+                SnapshotLevel {
+                    price: 97.00,
+                    qty: Qty(40),
+                },
+                SnapshotLevel {
+                    price: 96.00,
+                    qty: Qty(50),
+                },
+            ],
+            asks: vec![
+                SnapshotLevel {
+                    price: 101.00,
+                    qty: Qty(15),
+                },
+                SnapshotLevel {
+                    price: 102.00,
+                    qty: Qty(25),
+                },
+                SnapshotLevel {
+                    price: 103.00,
+                    qty: Qty(35),
+                },
+                SnapshotLevel {
+                    price: 104.00,
+                    qty: Qty(45),
+                },
+                SnapshotLevel {
+                    price: 105.00,
+                    qty: Qty(55),
+                },
+            ],
         }
     }
 
@@ -312,21 +854,25 @@ mod tests {
         let deque = BatchedDeque::new(10);
         let levels: Vec<Result<UpdateLevel, ()>> = vec![
             Ok(UpdateLevel {
-                side: 0,
+                side: Side::Bid,
                 price: 99.50,
-                qty: 25,
+                qty: Qty(25),
+                metadata: None,
             }),
             Ok(UpdateLevel {
-                side: 1,
+                side: Side::Ask,
                 price: 100.50,
-                qty: 30,
+                qty: Qty(30),
+                metadata: None,
             }),
         ];
 
         OrderBookUpdate {
             timestamp: 1627846266,
+            capture_timestamp: None,
             seq_no,
             security_id,
+            thinned_from: None,
             updates: deque.push_back_batch(levels.into_iter()).unwrap(),
         }
     }
@@ -334,7 +880,7 @@ mod tests {
     fn create_invalid_price_snapshot(security_id: u64, seq_no: u64) -> OrderBookSnapshot {
         let mut snapshot = create_test_snapshot(security_id, seq_no);
         // Make price invalid by setting it to a non-multiple of PRICE_TICK
-        snapshot.ask5.price += 0.005;
+        snapshot.asks[4].price += 0.005;
         snapshot
     }
 
@@ -355,11 +901,11 @@ mod tests {
 
         assert_eq!(
             order_book.bids.get(&Decimal::from_f64(99.50).unwrap()),
-            Some(&25)
+            Some(&Qty(25))
         );
         assert_eq!(
             order_book.asks.get(&Decimal::from_f64(100.50).unwrap()),
-            Some(&30)
+            Some(&Qty(30))
         );
     }
 
@@ -374,7 +920,7 @@ mod tests {
         let update = create_test_update(security_id, 102);
         let result = order_book.apply_update(&update);
 
-        assert!(matches!(result, Err(Errors::SequenceNumberGap)));
+        assert!(matches!(result, Err(Errors::SequenceNumberGap(_))));
 
         assert_eq!(order_book.seq_no, 100);
         assert_eq!(order_book.timestamp, snapshot.timestamp);
@@ -392,13 +938,16 @@ mod tests {
         let update = create_test_update(different_security_id, 101);
         let result = order_book.apply_update(&update);
 
-        assert!(matches!(result, Err(Errors::SecurityIdMismatch)));
+        assert!(matches!(result, Err(Errors::SecurityIdMismatch(_))));
 
         // Try to apply a snapshot with a different security ID
         let different_snapshot = create_test_snapshot(different_security_id, 101);
         let snapshot_result = order_book.apply_snapshot(&different_snapshot);
 
-        assert!(matches!(snapshot_result, Err(Errors::SecurityIdMismatch)));
+        assert!(matches!(
+            snapshot_result,
+            Err(Errors::SecurityIdMismatch(_))
+        ));
     }
 
     #[test]
@@ -440,21 +989,25 @@ mod tests {
         let deque = BatchedDeque::new(10);
         let levels: Vec<Result<UpdateLevel, ()>> = vec![
             Ok(UpdateLevel {
-                side: 0,
+                side: Side::Bid,
                 price: 99.50,
-                qty: 25,
+                qty: Qty(25),
+                metadata: None,
             }),
             Ok(UpdateLevel {
-                side: 1,
+                side: Side::Ask,
                 price: 100.505, // Invalid price
-                qty: 30,
+                qty: Qty(30),
+                metadata: None,
             }),
         ];
 
         let invalid_update = OrderBookUpdate {
             timestamp: 1627846266,
+            capture_timestamp: None,
             seq_no: 101,
             security_id,
+            thinned_from: None,
             updates: deque.push_back_batch(levels.into_iter()).unwrap(),
         };
 
@@ -477,21 +1030,25 @@ mod tests {
         let deque = BatchedDeque::new(10);
         let levels: Vec<Result<UpdateLevel, ()>> = vec![
             Ok(UpdateLevel {
-                side: 0,
+                side: Side::Bid,
                 price: 99.50,
-                qty: 25,
+                qty: Qty(25),
+                metadata: None,
             }),
             Ok(UpdateLevel {
-                side: 1,
+                side: Side::Ask,
                 price: f64::NAN, // Invalid price
-                qty: 30,
+                qty: Qty(30),
+                metadata: None,
             }),
         ];
 
         let invalid_update = OrderBookUpdate {
             timestamp: 1627846266,
+            capture_timestamp: None,
             seq_no: 101,
             security_id,
+            thinned_from: None,
             updates: deque.push_back_batch(levels.into_iter()).unwrap(),
         };
 
@@ -503,40 +1060,57 @@ mod tests {
     }
 
     #[test]
-    fn test_invalid_side_in_update() {
-        // Create order book
+    fn test_best_effort_quarantines_invalid_levels() {
         let security_id = 1001;
         let snapshot = create_test_snapshot(security_id, 100);
         let mut order_book = OrderBook::new(&snapshot).unwrap();
 
-        // Apply an update with an invalid side
-        // Create a deque and add test levels with an invalid side
         let deque = BatchedDeque::new(10);
         let levels: Vec<Result<UpdateLevel, ()>> = vec![
             Ok(UpdateLevel {
-                side: 0,
+                side: Side::Ask,
+                price: f64::NAN, // Invalid price
+                qty: Qty(30),
+                metadata: None,
+            }),
+            Ok(UpdateLevel {
+                side: Side::Bid,
                 price: 99.50,
-                qty: 25,
+                qty: Qty(25),
+                metadata: None,
             }),
             Ok(UpdateLevel {
-                side: 2, // Invalid side (not 0 or 1)
-                price: 100.50,
-                qty: 30,
+                side: Side::Ask,
+                price: 101.505, // Invalid price
+                qty: Qty(35),
+                metadata: None,
             }),
         ];
-
-        let invalid_update = OrderBookUpdate {
+        let update = OrderBookUpdate {
             timestamp: 1627846266,
+            capture_timestamp: None,
             seq_no: 101,
             security_id,
+            thinned_from: None,
             updates: deque.push_back_batch(levels.into_iter()).unwrap(),
         };
 
-        let result = order_book.apply_update(&invalid_update);
-
-        assert!(matches!(result, Err(Errors::InvalidSide(_, _))));
+        let mut quarantined = Vec::new();
+        order_book
+            .apply_update_with_policy(&update, LevelPolicy::BestEffort, &mut quarantined)
+            .unwrap();
 
-        assert_eq!(order_book.seq_no, 100);
+        assert_eq!(order_book.seq_no, 101);
+        assert_eq!(order_book.bids.get(&dec!(99.50)), Some(&Qty(25)));
+        assert_eq!(order_book.asks.len(), 5);
+        let quarantined: Vec<(usize, Side)> = quarantined
+            .iter()
+            .map(|level| {
+                assert_eq!((level.security_id, level.seq_no), (security_id, 101));
+                (level.index, level.side)
+            })
+            .collect();
+        assert_eq!(quarantined, vec![(0, Side::Ask), (2, Side::Ask)]);
     }
 
     #[test]
@@ -550,7 +1124,7 @@ mod tests {
         let old_snapshot = create_test_snapshot(security_id, 99);
         let result = order_book.apply_snapshot(&old_snapshot);
 
-        assert!(matches!(result, Err(Errors::OldSequenceNumber)));
+        assert!(matches!(result, Err(Errors::OldSequenceNumber(_))));
 
         assert_eq!(order_book.seq_no, 100);
     }
@@ -566,11 +1140,70 @@ mod tests {
         let old_update = create_test_update(security_id, 100);
         let result = order_book.apply_update(&old_update);
 
-        assert!(matches!(result, Err(Errors::OldSequenceNumber)));
+        assert!(matches!(result, Err(Errors::OldSequenceNumber(_))));
 
         assert_eq!(order_book.seq_no, 100);
     }
 
+    #[test]
+    fn test_update_with_vec_of_levels() {
+        let security_id = 1001;
+        let snapshot = create_test_snapshot(security_id, 100);
+        let mut order_book = OrderBook::new(&snapshot).unwrap();
+        let update = OrderBookUpdate {
+            timestamp: 1627846266,
+            capture_timestamp: None,
+            seq_no: 101,
+            security_id,
+            thinned_from: None,
+            updates: vec![
+                UpdateLevel {
+                    side: Side::Bid,
+                    price: 99.50,
+                    qty: Qty(25),
+                    metadata: None,
+                },
+                UpdateLevel {
+                    side: Side::Ask,
+                    price: 101.00,
+                    qty: Qty(0),
+                    metadata: None,
+                },
+            ],
+        };
+
+        order_book.apply_update(&update).unwrap();
+        assert_eq!(order_book.seq_no, 101);
+        assert_eq!(order_book.bids.get(&dec!(99.50)), Some(&Qty(25)));
+        assert_eq!(order_book.asks.get(&dec!(101.00)), None);
+    }
+
+    #[test]
+    fn test_timestamp_policy() {
+        let security_id = 1001;
+        let snapshot = create_test_snapshot(security_id, 100);
+        let mut order_book = OrderBook::new(&snapshot).unwrap();
+        let mut update = create_test_update(security_id, 101);
+        update.timestamp = snapshot.timestamp - 1;
+
+        order_book.set_timestamp_policy(TimestampPolicy::Reject);
+        let result = order_book.apply_update(&update);
+        assert!(matches!(result, Err(Errors::TimestampRegression(_, _))));
+        assert_eq!(order_book.seq_no, 100);
+        assert_eq!(order_book.timestamp, snapshot.timestamp);
+
+        order_book.set_timestamp_policy(TimestampPolicy::Warn);
+        order_book.apply_update(&update).unwrap();
+        assert_eq!(order_book.regressed_from(), Some(snapshot.timestamp));
+        assert_eq!(order_book.timestamp, update.timestamp);
+
+        // Equal timestamps are in order
+        let mut update = create_test_update(security_id, 102);
+        update.timestamp = order_book.timestamp;
+        order_book.apply_update(&update).unwrap();
+        assert_eq!(order_book.regressed_from(), None);
+    }
+
     #[test]
     fn test_apply_snapshot_clears_previous_state() {
         // Create order book
@@ -608,15 +1241,18 @@ mod tests {
         // Create a deque with a level that has qty=0
         let deque = BatchedDeque::new(10);
         let levels: Vec<Result<UpdateLevel, ()>> = vec![Ok(UpdateLevel {
-            side: 0,
+            side: Side::Bid,
             price: 100.00, // This price exists in the initial snapshot
-            qty: 0,        // Setting to 0 should remove it
+            qty: Qty(0),   // Setting to 0 should remove it
+            metadata: None,
         })];
 
         let update = OrderBookUpdate {
             timestamp: 1627846266,
+            capture_timestamp: None,
             seq_no: 101,
             security_id,
+            thinned_from: None,
             updates: deque.push_back_batch(levels.into_iter()).unwrap(),
         };
 
@@ -630,6 +1266,255 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_level_metadata_passthrough() {
+        // Create order book
+        let security_id = 1001;
+        let snapshot = create_test_snapshot(security_id, 100);
+        let mut order_book = OrderBook::new(&snapshot).unwrap();
+
+        // Apply an update carrying V2 level metadata
+        let deque = BatchedDeque::new(10);
+        let metadata = LevelMetadata {
+            order_count: 3,
+            action: 1,
+        };
+        let levels: Vec<Result<UpdateLevel, ()>> = vec![
+            Ok(UpdateLevel {
+                side: Side::Bid,
+                price: 99.50,
+                qty: Qty(25),
+                metadata: Some(metadata),
+            }),
+            Ok(UpdateLevel {
+                side: Side::Ask,
+                price: 101.00,
+                qty: Qty(0),
+                metadata: Some(metadata),
+            }),
+        ];
+        let update = OrderBookUpdate {
+            timestamp: 1627846266,
+            capture_timestamp: None,
+            seq_no: 101,
+            security_id,
+            thinned_from: None,
+            updates: deque.push_back_batch(levels.into_iter()).unwrap(),
+        };
+        order_book.apply_update(&update).unwrap();
+
+        assert_eq!(
+            order_book
+                .bid_metadata
+                .get(&Decimal::from_f64(99.50).unwrap()),
+            Some(&metadata)
+        );
+        // Removed levels don't keep metadata
+        assert!(order_book.ask_metadata.is_empty());
+
+        // A V1 update of the same level drops the stale metadata
+        let update = create_test_update(security_id, 102);
+        order_book.apply_update(&update).unwrap();
+        assert!(order_book.bid_metadata.is_empty());
+    }
+
+    #[test]
+    fn test_top_levels_with_order_counts() {
+        // Create order book
+        let security_id = 1001;
+        let snapshot = create_test_snapshot(security_id, 100);
+        let mut order_book = OrderBook::new(&snapshot).unwrap();
+
+        // Add order counts to the best bid
+        let deque = BatchedDeque::new(10);
+        let levels: Vec<Result<UpdateLevel, ()>> = vec![Ok(UpdateLevel {
+            side: Side::Bid,
+            price: 100.00,
+            qty: Qty(12),
+            metadata: Some(LevelMetadata {
+                order_count: 4,
+                action: 1,
+            }),
+        })];
+        let update = OrderBookUpdate {
+            timestamp: 1627846266,
+            capture_timestamp: None,
+            seq_no: 101,
+            security_id,
+            thinned_from: None,
+            updates: deque.push_back_batch(levels.into_iter()).unwrap(),
+        };
+        order_book.apply_update(&update).unwrap();
+
+        let bids = order_book.top_levels(Side::Bid, 2);
+        assert_eq!(
+            bids,
+            vec![
+                BookLevel {
+                    price: Price(dec!(100)),
+                    qty: Qty(12),
+                    order_count: Some(4),
+                },
+                BookLevel {
+                    price: Price(dec!(99)),
+                    qty: Qty(20),
+                    order_count: None,
+                },
+            ]
+        );
+
+        let asks = order_book.top_levels(Side::Ask, 10);
+        assert_eq!(asks.len(), 5);
+        assert_eq!(asks[0].price.value(), dec!(101));
+        assert_eq!(asks[4].price, Price(dec!(105)));
+    }
+
+    #[test]
+    fn test_checksum() {
+        let security_id = 1001;
+        let snapshot = create_test_snapshot(security_id, 100);
+        let mut order_book = OrderBook::new(&snapshot).unwrap();
+
+        // "100:10:101:15:99:20:102:25:...:96:50:105:55"
+        assert_eq!(order_book.checksum(), 25107887);
+        assert_eq!(order_book.checksum_with_depth(1), 3105829036);
+        // Same levels, same checksum
+        assert_eq!(
+            OrderBook::new(&snapshot).unwrap().checksum(),
+            order_book.checksum()
+        );
+
+        // One more level on each side: "100:10:100.5:30:99.5:25:101:15:...:96:50:105:55"
+        order_book
+            .apply_update(&create_test_update(security_id, 101))
+            .unwrap();
+        assert_eq!(order_book.checksum(), 2391173930);
+    }
+
+    #[test]
+    fn test_snapshot_state() {
+        let security_id = 1001;
+        let mut order_book = OrderBook::new(&create_test_snapshot(security_id, 100)).unwrap();
+        let state = order_book.snapshot_state();
+        order_book
+            .apply_update(&create_test_update(security_id, 101))
+            .unwrap();
+
+        // The state stays as it was when taken
+        assert_eq!(state.seq_no, 100);
+        assert_eq!(state.bids.len(), 5);
+        assert_eq!(
+            state.best_bid().map(|level| level.price),
+            Some(Price(dec!(100)))
+        );
+        assert_eq!(state.best_ask().map(|level| level.qty), Some(Qty(15)));
+        assert_eq!(order_book.snapshot_state().asks.len(), 6);
+
+        let shared = state.clone();
+        assert!(Arc::ptr_eq(&shared.bids, &state.bids));
+        std::thread::spawn(move || assert_eq!(shared.asks[4].price.value(), dec!(105)))
+            .join()
+            .unwrap();
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_serde_round_trip() {
+        use crate::order_book::buffered_order_book::BookState;
+
+        let security_id = 1001;
+        let mut order_book = OrderBook::new(&create_test_snapshot(security_id, 100)).unwrap();
+        let update = create_test_update(security_id, 101);
+        let json = serde_json::to_string(&update).unwrap();
+        order_book.apply_update(&update).unwrap();
+
+        let book: OrderBook =
+            serde_json::from_str(&serde_json::to_string(&order_book).unwrap()).unwrap();
+        assert_eq!(book.to_string(), order_book.to_string());
+        assert!(serde_json::from_str::<OrderBook>(&json).is_err());
+
+        // Updates come back holding their levels
+        let mut replayed = OrderBook::new(&create_test_snapshot(security_id, 100)).unwrap();
+        let update: OrderBookUpdate<Vec<UpdateLevel>> = serde_json::from_str(&json).unwrap();
+        replayed.apply_update(&update).unwrap();
+        assert_eq!(replayed.to_string(), order_book.to_string());
+
+        let snapshot = create_test_snapshot(security_id, 100);
+        let snapshot: OrderBookSnapshot =
+            serde_json::from_str(&serde_json::to_string(&snapshot).unwrap()).unwrap();
+        assert_eq!(snapshot.asks[4].price, 105.0);
+        assert_eq!(
+            serde_json::to_string(&BookState::AwaitingSnapshot).unwrap(),
+            "\"AwaitingSnapshot\""
+        );
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_state_serde_round_trip() {
+        let security_id = 1001;
+        let mut order_book = OrderBook::new(&create_test_snapshot(security_id, 100)).unwrap();
+        order_book
+            .apply_update(&create_test_update(security_id, 101))
+            .unwrap();
+        let state = order_book.snapshot_state();
+
+        let json = serde_json::to_string(&state).unwrap();
+        let deserialized: OrderBookState = serde_json::from_str(&json).unwrap();
+        assert_eq!(deserialized, state);
+        assert_eq!(
+            serde_json::to_value(state.best_bid()).unwrap(),
+            serde_json::json!({"price": "100", "qty": 10, "order_count": null})
+        );
+
+        // Prices off the finest tick are rejected
+        let json = json.replacen("\"100\"", "\"100.001\"", 1);
+        assert!(serde_json::from_str::<OrderBookState>(&json).is_err());
+    }
+
+    #[test]
+    fn test_to_snapshot() {
+        let security_id = 1001;
+        let snapshot = create_test_snapshot(security_id, 100);
+        let mut order_book = OrderBook::new(&snapshot).unwrap();
+        // Three levels left on the ask side
+        order_book.asks.pop_first();
+        order_book.asks.pop_last();
+
+        let written = order_book.to_snapshot();
+        assert_eq!(written.seq_no, 100);
+        assert_eq!(written.timestamp, snapshot.timestamp);
+        assert_eq!(
+            (written.bids[0].price, written.bids[0].qty),
+            (100.00, Qty(10))
+        );
+        assert_eq!(
+            (written.bids[4].price, written.bids[4].qty),
+            (96.00, Qty(50))
+        );
+        assert_eq!(
+            (written.asks[0].price, written.asks[0].qty),
+            (102.00, Qty(25))
+        );
+        assert_eq!(
+            (written.asks[2].price, written.asks[2].qty),
+            (104.00, Qty(45))
+        );
+        assert_eq!(written.asks[3].qty, Qty(0));
+        assert_eq!(written.asks[4].qty, Qty(0));
+
+        // Reading it back gives the same book
+        let restored = OrderBook::new(&written).unwrap();
+        assert_eq!(restored.bids, order_book.bids);
+        assert_eq!(restored.asks, order_book.asks);
+
+        let deeper = order_book.to_snapshot_with_depth(10);
+        assert_eq!((deeper.bids.len(), deeper.asks.len()), (10, 10));
+        assert_eq!(deeper.bids[4].qty, Qty(50));
+        assert_eq!(deeper.bids[5].qty, Qty(0));
+        assert_eq!(OrderBook::new(&deeper).unwrap().bids, order_book.bids);
+    }
+
     #[test]
     fn test_valid_update_after_invalid_update() {
         // Create order book
@@ -641,21 +1526,25 @@ mod tests {
         let deque = BatchedDeque::new(10);
         let levels: Vec<Result<UpdateLevel, ()>> = vec![
             Ok(UpdateLevel {
-                side: 0,
+                side: Side::Bid,
                 price: 98.50,
-                qty: 25,
+                qty: Qty(25),
+                metadata: None,
             }),
             Ok(UpdateLevel {
-                side: 1,
+                side: Side::Ask,
                 price: 100.505, // Invalid price (not a multiple of PRICE_TICK)
-                qty: 30,
+                qty: Qty(30),
+                metadata: None,
             }),
         ];
 
         let invalid_update = OrderBookUpdate {
             timestamp: 1627846266,
+            capture_timestamp: None,
             seq_no: 101,
             security_id,
+            thinned_from: None,
             updates: deque.push_back_batch(levels.into_iter()).unwrap(),
         };
 
@@ -693,10 +1582,10 @@ mod tests {
 
         // Try to apply an invalid snapshot
         let mut invalid_snapshot = create_test_snapshot(security_id, 101);
-        invalid_snapshot.ask4.price = 104.01;
-        invalid_snapshot.bid4.price = 97.01;
+        invalid_snapshot.asks[3].price = 104.01;
+        invalid_snapshot.bids[3].price = 97.01;
         // Make price invalid by setting it to NaN
-        invalid_snapshot.ask5.price = f64::NAN;
+        invalid_snapshot.asks[4].price = f64::NAN;
         let result = order_book.apply_snapshot(&invalid_snapshot);
         assert!(matches!(result, Err(Errors::InvalidPrice(_, _))));
 
@@ -721,4 +1610,271 @@ mod tests {
                 .contains_key(&Decimal::from_f64(97.01).unwrap())
         );
     }
+
+    #[test]
+    fn test_update_with_more_levels_than_staged() {
+        let mut order_book = OrderBook::new(&create_test_snapshot(1001, 100)).unwrap();
+        let deque = BatchedDeque::new(64);
+        let levels: Vec<Result<UpdateLevel, ()>> = (0..2 * STAGED_LEVELS as u64 + 2)
+            .map(|i| {
+                Ok(UpdateLevel {
+                    side: if i % 2 == 0 { Side::Bid } else { Side::Ask },
+                    price: if i % 2 == 0 {
+                        90.0 - i as f64
+                    } else {
+                        110.0 + i as f64
+                    },
+                    qty: Qty(i + 1),
+                    metadata: None,
+                })
+            })
+            .collect();
+        let update = OrderBookUpdate {
+            timestamp: 1627846266,
+            capture_timestamp: None,
+            seq_no: 101,
+            security_id: 1001,
+            thinned_from: None,
+            updates: deque.push_back_batch(levels.into_iter()).unwrap(),
+        };
+        order_book.apply_update(&update).unwrap();
+
+        assert_eq!(order_book.bids.len(), 5 + STAGED_LEVELS + 1);
+        assert_eq!(order_book.asks.len(), 5 + STAGED_LEVELS + 1);
+        assert_eq!(order_book.asks.get(&dec!(111)), Some(&Qty(2)));
+    }
+
+    #[test]
+    fn test_quantities_checked_against_lot_size() {
+        let security_info = Arc::new(SecurityInfo {
+            security_id: 1001,
+            symbol: "TEST".to_string(),
+            tick_size: None,
+            lot_size: Some(5),
+            price_band: None,
+        });
+        let mut snapshot = create_test_snapshot(1001, 100);
+        snapshot.bids[4].qty = Qty(51);
+        assert!(matches!(
+            OrderBook::<Decimal>::from_snapshot_with_info(&snapshot, Some(security_info.clone())),
+            Err(Errors::InvalidQuantity(_, _))
+        ));
+
+        let mut order_book = OrderBook::<Decimal>::from_snapshot_with_info(
+            &create_test_snapshot(1001, 100),
+            Some(security_info),
+        )
+        .unwrap();
+        // 25 and 30 are whole lots
+        order_book
+            .apply_update(&create_test_update(1001, 101))
+            .unwrap();
+        let deque = BatchedDeque::new(10);
+        let update = OrderBookUpdate {
+            timestamp: 1627846267,
+            capture_timestamp: None,
+            seq_no: 102,
+            security_id: 1001,
+            thinned_from: None,
+            updates: deque
+                .push_back_batch(
+                    [Ok::<_, ()>(UpdateLevel {
+                        side: Side::Ask,
+                        price: 101.0,
+                        qty: Qty(7),
+                        metadata: None,
+                    })]
+                    .into_iter(),
+                )
+                .unwrap(),
+        };
+        let result = order_book.apply_update(&update);
+        assert!(matches!(result, Err(Errors::InvalidQuantity(_, _))));
+        assert_eq!(
+            result.unwrap_err().to_string(),
+            "Invalid quantity for security 1001 with seq_no 102: \
+             The quantity 7 is not a multiple of the lot size 5 of TEST"
+        );
+        assert_eq!(order_book.seq_no, 101);
+    }
+
+    #[test]
+    fn test_max_price_deviation() {
+        let mut order_book = OrderBook::new(&create_test_snapshot(1001, 100)).unwrap();
+        order_book.set_max_price_deviation(Some(dec!(5)));
+        assert_eq!(order_book.reference_price(), Some(dec!(100.5)));
+
+        let deque = BatchedDeque::new(10);
+        let level = |side: Side, price: f64, qty: u64| {
+            Ok::<_, ()>(UpdateLevel {
+                side,
+                price,
+                qty: Qty(qty),
+                metadata: None,
+            })
+        };
+        let update = |seq_no: u64, levels: Vec<Result<UpdateLevel, ()>>| OrderBookUpdate {
+            timestamp: 1627846266,
+            capture_timestamp: None,
+            seq_no,
+            security_id: 1001,
+            thinned_from: None,
+            updates: deque.push_back_batch(levels.into_iter()).unwrap(),
+        };
+
+        // 5% of 100.5 is 5.025
+        let result = order_book.apply_update(&update(101, vec![level(Side::Ask, 105.6, 10)]));
+        assert!(matches!(result, Err(Errors::PriceDeviation(_, _))));
+        assert_eq!(
+            result.unwrap_err().to_string(),
+            "Price too far from the book for security 1001 with seq_no 101: \
+             The price 105.6 is more than 5% away from 100.5"
+        );
+        order_book
+            .apply_update(&update(
+                101,
+                vec![level(Side::Ask, 105.5, 10), level(Side::Bid, 95.0, 0)],
+            ))
+            .unwrap();
+
+        // Flagged and left out with the best-effort policy
+        let mut quarantined = Vec::new();
+        order_book
+            .apply_update_with_policy(
+                &update(
+                    102,
+                    vec![level(Side::Bid, 10.0, 1), level(Side::Bid, 99.5, 1)],
+                ),
+                LevelPolicy::BestEffort,
+                &mut quarantined,
+            )
+            .unwrap();
+        assert_eq!(quarantined.len(), 1);
+        assert!(matches!(quarantined[0].error, Errors::PriceDeviation(_, _)));
+        assert_eq!(order_book.bids.get(&dec!(99.5)), Some(&Qty(1)));
+    }
+
+    #[test]
+    fn test_validate() {
+        let mut order_book = OrderBook::new(&create_test_snapshot(1001, 100)).unwrap();
+        assert_eq!(order_book.validate(), Ok(()));
+
+        order_book.bids.insert(dec!(101), Qty(5));
+        assert_eq!(
+            order_book.validate(),
+            Err("The best bid 101 is not below the best ask 101".to_string())
+        );
+        order_book.set_allow_crossed(true);
+        assert_eq!(order_book.validate(), Ok(()));
+
+        order_book.asks.insert(dec!(110), Qty(0));
+        assert_eq!(
+            order_book.validate(),
+            Err("The ask level 110 has no quantity".to_string())
+        );
+        order_book.asks.remove(&dec!(110));
+        order_book.bid_metadata.insert(
+            dec!(90),
+            LevelMetadata {
+                order_count: 1,
+                action: 0,
+            },
+        );
+        assert_eq!(
+            order_book.validate(),
+            Err("The bid level 90 has metadata but no quantity".to_string())
+        );
+    }
+
+    #[test]
+    fn test_max_depth() {
+        let snapshot = create_test_snapshot(1001, 100);
+        let mut order_book = OrderBook::new(&snapshot).unwrap();
+        let mut vec_book =
+            OrderBook::<Ticks, SortedVecSide<Ticks, Qty>>::from_snapshot(&snapshot).unwrap();
+        order_book.set_max_depth(Some(3));
+        vec_book.set_max_depth(Some(3));
+        assert_eq!(
+            order_book.bids.keys().collect::<Vec<_>>(),
+            vec![&dec!(98), &dec!(99), &dec!(100)]
+        );
+
+        // The new best levels push out the worst ones
+        let update = create_test_update(1001, 101);
+        order_book.apply_update(&update).unwrap();
+        vec_book.apply_update(&update).unwrap();
+        assert_eq!(
+            order_book.bids.keys().collect::<Vec<_>>(),
+            vec![&dec!(99), &dec!(99.5), &dec!(100)]
+        );
+        assert_eq!(
+            order_book.asks.keys().collect::<Vec<_>>(),
+            vec![&dec!(100.5), &dec!(101), &dec!(102)]
+        );
+        assert_eq!(vec_book.to_string(), order_book.to_string());
+
+        order_book
+            .apply_snapshot(&create_test_snapshot(1001, 102))
+            .unwrap();
+        assert_eq!(order_book.bids.len(), 3);
+        assert_eq!(order_book.asks.len(), 3);
+        assert_eq!(order_book.max_depth(), Some(3));
+    }
+
+    #[test]
+    fn test_other_representations_match_decimal_book() {
+        let snapshot = create_test_snapshot(1001, 100);
+        let mut decimal_book = OrderBook::new(&snapshot).unwrap();
+        let mut ticks_book = OrderBook::<Ticks>::from_snapshot(&snapshot).unwrap();
+        let mut vec_book =
+            OrderBook::<Ticks, SortedVecSide<Ticks, Qty>>::from_snapshot(&snapshot).unwrap();
+
+        let deque = BatchedDeque::new(10);
+        let levels: Vec<Result<UpdateLevel, ()>> = vec![
+            Ok(UpdateLevel {
+                side: Side::Bid,
+                price: 99.0,
+                qty: Qty(0),
+                metadata: None,
+            }),
+            Ok(UpdateLevel {
+                side: Side::Ask,
+                price: 101.01,
+                qty: Qty(7),
+                metadata: None,
+            }),
+        ];
+        let update = OrderBookUpdate {
+            timestamp: 1627846267,
+            capture_timestamp: None,
+            seq_no: 102,
+            security_id: 1001,
+            thinned_from: None,
+            updates: deque.push_back_batch(levels.into_iter()).unwrap(),
+        };
+        for update in [create_test_update(1001, 101), update] {
+            decimal_book.apply_update(&update).unwrap();
+            ticks_book.apply_update(&update).unwrap();
+            vec_book.apply_update(&update).unwrap();
+        }
+
+        assert_eq!(ticks_book.best_bid(), decimal_book.best_bid());
+        assert_eq!(ticks_book.best_ask(), Some((dec!(100.50), Qty(30))));
+        assert_eq!(
+            ticks_book.top_levels(Side::Ask, 10),
+            decimal_book.top_levels(Side::Ask, 10)
+        );
+        assert_eq!(ticks_book.checksum(), decimal_book.checksum());
+        assert_eq!(ticks_book.to_string(), decimal_book.to_string());
+        assert_eq!(vec_book.to_string(), decimal_book.to_string());
+        assert_eq!(vec_book.best_bid(), decimal_book.best_bid());
+        assert_eq!(vec_book.best_ask(), decimal_book.best_ask());
+
+        let mut invalid_snapshot = create_test_snapshot(1001, 103);
+        invalid_snapshot.bids[0].price = 100.001;
+        assert!(matches!(
+            ticks_book.apply_snapshot(&invalid_snapshot),
+            Err(Errors::InvalidPrice(_, _))
+        ));
+    }
 }
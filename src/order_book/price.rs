@@ -0,0 +1,90 @@
+use num_traits::FromPrimitive;
+use rust_decimal::{Decimal, dec};
+use std::fmt::Debug;
+
+pub const PRICE_TICK: Decimal = dec!(0.01);
+// Ticks in a unit of price
+const TICKS_PER_UNIT: f64 = 100.0;
+
+// How a book keys its levels. Prices are converted from the records on the way in and to
+// Decimal on the way out, so only the ordering of the levels depends on it.
+pub trait PriceKey: Copy + Ord + Debug {
+    // The key of a record price, an error message when it is not a multiple of PRICE_TICK
+    fn from_f64(price: f64) -> Result<Self, String>;
+
+    fn to_decimal(self) -> Decimal;
+}
+
+impl PriceKey for Decimal {
+    fn from_f64(price: f64) -> Result<Self, String> {
+        match <Decimal as FromPrimitive>::from_f64(price) {
+            Some(dec) if dec % PRICE_TICK == dec!(0.0) => Ok(dec),
+            Some(_) => Err(format!(
+                "The price {} is not a multiple of {}",
+                price, PRICE_TICK
+            )),
+            None => Err(format!("Failed to convert f64 value {} to Decimal", price)),
+        }
+    }
+
+    fn to_decimal(self) -> Decimal {
+        self
+    }
+}
+
+// A price as a number of PRICE_TICK steps. Comparing i64 keys is much cheaper than
+// comparing Decimals, which dominates applying updates to large books.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Ticks(pub i64);
+
+impl PriceKey for Ticks {
+    fn from_f64(price: f64) -> Result<Self, String> {
+        let scaled = price * TICKS_PER_UNIT;
+        let ticks = scaled.round();
+        if !ticks.is_finite() || ticks.abs() >= i64::MAX as f64 {
+            return Err(format!("Failed to convert f64 value {} to ticks", price));
+        }
+        // Tolerates the representation error of the f64, as Decimal::from_f64 does
+        if (scaled - ticks).abs() > 1e-9 * ticks.abs().max(1.0) {
+            return Err(format!(
+                "The price {} is not a multiple of {}",
+                price, PRICE_TICK
+            ));
+        }
+        Ok(Ticks(ticks as i64))
+    }
+
+    fn to_decimal(self) -> Decimal {
+        Decimal::new(self.0, PRICE_TICK.scale())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ticks_conversions() {
+        assert_eq!(Ticks::from_f64(5000.75), Ok(Ticks(500075)));
+        assert_eq!(Ticks::from_f64(0.1 + 0.2), Ok(Ticks(30)));
+        assert_eq!(Ticks::from_f64(-1.5), Ok(Ticks(-150)));
+        assert_eq!(Ticks(500075).to_decimal(), dec!(5000.75));
+        assert!(Ticks::from_f64(1.001).is_err());
+        assert!(Ticks::from_f64(f64::NAN).is_err());
+        assert!(Ticks::from_f64(1e300).is_err());
+    }
+
+    #[test]
+    fn test_same_prices_as_decimal() {
+        for price in [
+            0.01, 99.99, 100.1, 5001.2, 600700.0, 123456.78, 1.005, 2.5e-3,
+        ] {
+            let decimal = <Decimal as PriceKey>::from_f64(price);
+            let ticks = Ticks::from_f64(price);
+            assert_eq!(decimal.is_ok(), ticks.is_ok(), "{}", price);
+            if let (Ok(decimal), Ok(ticks)) = (decimal, ticks) {
+                assert_eq!(decimal, ticks.to_decimal());
+            }
+        }
+    }
+}
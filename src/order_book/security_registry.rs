@@ -0,0 +1,400 @@
+use num_traits::FromPrimitive;
+use rust_decimal::Decimal;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::collections::hash_map::Entry;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Read};
+use std::path::Path;
+use std::sync::Arc;
+
+use crate::order_book::price::PRICE_TICK;
+use crate::order_book::units::{Price, Qty};
+
+// Prices a security may trade at, both inclusive
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PriceBand {
+    pub low: Decimal,
+    pub high: Decimal,
+}
+
+// Reference data of a security. The tick size has to be a multiple of PRICE_TICK, the
+// finest tick the records can carry.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SecurityInfo {
+    pub security_id: u64,
+    pub symbol: String,
+    pub tick_size: Option<Decimal>,
+    pub lot_size: Option<u64>,
+    pub price_band: Option<PriceBand>,
+}
+
+impl SecurityInfo {
+    fn validate(&self) -> Result<(), String> {
+        if let Some(tick_size) = self.tick_size
+            && (tick_size <= Decimal::ZERO || !(tick_size % PRICE_TICK).is_zero())
+        {
+            return Err(format!(
+                "The tick size {} is not a positive multiple of {}",
+                tick_size, PRICE_TICK
+            ));
+        }
+        if self.lot_size == Some(0) {
+            return Err("The lot size must be positive".to_string());
+        }
+        if let Some(band) = self.price_band
+            && band.low > band.high
+        {
+            return Err(format!(
+                "The price band {} to {} is empty",
+                band.low, band.high
+            ));
+        }
+        Ok(())
+    }
+
+    // The price when it is a valid price of the security, an error message otherwise
+    pub fn price(&self, price: Decimal) -> Result<Price, String> {
+        let tick_size = self.tick_size.unwrap_or(PRICE_TICK);
+        let price = Price::new(price, tick_size).map_err(|_| {
+            format!(
+                "The price {} is not a multiple of the tick size {} of {}",
+                price, tick_size, self.symbol
+            )
+        })?;
+        if let Some(band) = self.price_band
+            && (price.value() < band.low || price.value() > band.high)
+        {
+            return Err(format!(
+                "The price {} is outside the band {} to {} of {}",
+                price, band.low, band.high, self.symbol
+            ));
+        }
+        Ok(price)
+    }
+
+    // The quantity when it is a multiple of the lot size of the security, an error
+    // message otherwise. A zero quantity removes a level and is always valid.
+    pub fn qty(&self, qty: u64) -> Result<Qty, String> {
+        let lot_size = self.lot_size.unwrap_or(1);
+        Qty::new(qty, lot_size).map_err(|_| {
+            format!(
+                "The quantity {} is not a multiple of the lot size {} of {}",
+                qty, lot_size, self.symbol
+            )
+        })
+    }
+}
+
+// A JSON entry of the reference file
+struct SecurityEntry {
+    security_id: u64,
+    symbol: String,
+    tick_size: Option<f64>,
+    lot_size: Option<u64>,
+    price_band: Option<PriceBandEntry>,
+}
+
+struct PriceBandEntry {
+    low: f64,
+    high: f64,
+}
+
+// The field of a JSON object, None when it is missing or null
+fn json_field<'a, T>(
+    object: &'a Value,
+    name: &str,
+    convert: impl FnOnce(&'a Value) -> Option<T>,
+) -> Result<Option<T>, String> {
+    object
+        .get(name)
+        .filter(|value| !value.is_null())
+        .map(|value| convert(value).ok_or_else(|| format!("Invalid {}: {}", name, value)))
+        .transpose()
+}
+
+fn required_json_field<'a, T>(
+    object: &'a Value,
+    name: &str,
+    convert: impl FnOnce(&'a Value) -> Option<T>,
+) -> Result<T, String> {
+    json_field(object, name, convert)?.ok_or_else(|| format!("Missing {}", name))
+}
+
+impl SecurityEntry {
+    fn from_json(entry: &Value) -> Result<Self, String> {
+        if !entry.is_object() {
+            return Err(format!("Expected an object, got {}", entry));
+        }
+        let price_band = json_field(entry, "price_band", Some)?
+            .map(|band| -> Result<PriceBandEntry, String> {
+                Ok(PriceBandEntry {
+                    low: required_json_field(band, "low", Value::as_f64)?,
+                    high: required_json_field(band, "high", Value::as_f64)?,
+                })
+            })
+            .transpose()?;
+        Ok(Self {
+            security_id: required_json_field(entry, "security_id", Value::as_u64)?,
+            symbol: required_json_field(entry, "symbol", Value::as_str)?.to_string(),
+            tick_size: json_field(entry, "tick_size", Value::as_f64)?,
+            lot_size: json_field(entry, "lot_size", Value::as_u64)?,
+            price_band,
+        })
+    }
+}
+
+fn to_decimal(value: f64, name: &str) -> Result<Decimal, String> {
+    Decimal::from_f64(value).ok_or_else(|| format!("Invalid {}: {}", name, value))
+}
+
+fn invalid_data(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+// Reference data of the securities by security_id, loaded from a file published by the
+// venue or kept by hand. Books share the entry of their security.
+#[derive(Debug, Clone, Default)]
+pub struct SecurityRegistry {
+    securities: HashMap<u64, Arc<SecurityInfo>>,
+}
+
+impl SecurityRegistry {
+    // Reads files ending in .json as JSON and the others as CSV
+    pub fn load(path: &Path) -> io::Result<Self> {
+        let reader = BufReader::new(File::open(path)?);
+        match path.extension().and_then(|extension| extension.to_str()) {
+            Some("json") => Self::from_json(reader),
+            _ => Self::from_csv(reader),
+        }
+    }
+
+    // One security per line as security_id,symbol,tick_size,lot_size,band_low,band_high.
+    // The trailing fields are optional and may be left empty. Empty lines, lines starting
+    // with # and header lines starting with "security_id" are skipped.
+    pub fn from_csv<R: BufRead>(reader: R) -> io::Result<Self> {
+        let mut registry = Self::default();
+        for (index, line) in reader.lines().enumerate() {
+            let line = line?;
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') || line.starts_with("security_id") {
+                continue;
+            }
+            Self::parse_csv_line(line)
+                .and_then(|info| registry.insert(info))
+                .map_err(|e| invalid_data(format!("Line {}: {}", index + 1, e)))?;
+        }
+        Ok(registry)
+    }
+
+    fn parse_csv_line(line: &str) -> Result<SecurityInfo, String> {
+        let fields: Vec<&str> = line.split(',').map(str::trim).collect();
+        if !(2..=6).contains(&fields.len()) {
+            return Err(format!("Expected 2 to 6 fields, got {}", fields.len()));
+        }
+        let field = |index: usize| fields.get(index).copied().filter(|field| !field.is_empty());
+        let decimal = |index: usize, name: &str| {
+            field(index)
+                .map(|field| {
+                    field
+                        .parse::<Decimal>()
+                        .map_err(|_| format!("Invalid {}: {}", name, field))
+                })
+                .transpose()
+        };
+        let price_band = match (decimal(4, "band low")?, decimal(5, "band high")?) {
+            (Some(low), Some(high)) => Some(PriceBand { low, high }),
+            (None, None) => None,
+            _ => return Err("The price band needs both a low and a high".to_string()),
+        };
+        Ok(SecurityInfo {
+            security_id: fields[0]
+                .parse()
+                .map_err(|_| format!("Invalid security_id: {}", fields[0]))?,
+            symbol: fields[1].to_string(),
+            tick_size: decimal(2, "tick size")?,
+            lot_size: field(3)
+                .map(|field| {
+                    field
+                        .parse()
+                        .map_err(|_| format!("Invalid lot size: {}", field))
+                })
+                .transpose()?,
+            price_band,
+        })
+    }
+
+    // An array of objects such as {"security_id":1,"symbol":"ESZ4","tick_size":0.25,
+    // "lot_size":1,"price_band":{"low":4000.0,"high":6000.0}}, all but the first two
+    // fields optional
+    pub fn from_json<R: Read>(reader: R) -> io::Result<Self> {
+        let entries: Value =
+            serde_json::from_reader(reader).map_err(|e| invalid_data(e.to_string()))?;
+        let entries = entries
+            .as_array()
+            .ok_or_else(|| invalid_data("Expected an array of securities".to_string()))?;
+        let mut registry = Self::default();
+        for (index, entry) in entries.iter().enumerate() {
+            let entry = SecurityEntry::from_json(entry)
+                .map_err(|e| invalid_data(format!("Entry {}: {}", index, e)))?;
+            let security_id = entry.security_id;
+            Self::from_entry(entry)
+                .and_then(|info| registry.insert(info))
+                .map_err(|e| invalid_data(format!("Security {}: {}", security_id, e)))?;
+        }
+        Ok(registry)
+    }
+
+    fn from_entry(entry: SecurityEntry) -> Result<SecurityInfo, String> {
+        Ok(SecurityInfo {
+            security_id: entry.security_id,
+            symbol: entry.symbol,
+            tick_size: entry
+                .tick_size
+                .map(|tick_size| to_decimal(tick_size, "tick size"))
+                .transpose()?,
+            lot_size: entry.lot_size,
+            price_band: entry
+                .price_band
+                .map(|band| -> Result<PriceBand, String> {
+                    Ok(PriceBand {
+                        low: to_decimal(band.low, "band low")?,
+                        high: to_decimal(band.high, "band high")?,
+                    })
+                })
+                .transpose()?,
+        })
+    }
+
+    // Fails for invalid reference data and securities already registered
+    pub fn insert(&mut self, info: SecurityInfo) -> Result<(), String> {
+        info.validate()?;
+        match self.securities.entry(info.security_id) {
+            Entry::Occupied(_) => Err(format!("Duplicate security_id {}", info.security_id)),
+            Entry::Vacant(entry) => {
+                entry.insert(Arc::new(info));
+                Ok(())
+            }
+        }
+    }
+
+    pub fn get(&self, security_id: u64) -> Option<&Arc<SecurityInfo>> {
+        self.securities.get(&security_id)
+    }
+
+    pub fn symbol(&self, security_id: u64) -> Option<&str> {
+        self.get(security_id).map(|info| info.symbol.as_str())
+    }
+
+    pub fn len(&self) -> usize {
+        self.securities.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.securities.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rust_decimal::dec;
+
+    #[test]
+    fn test_load_csv() {
+        let csv = "security_id,symbol,tick_size,lot_size,band_low,band_high\n\
+                   # Futures\n\
+                   1,ESZ4,0.25,1,4000,6000\n\
+                   \n\
+                   2, AAPL ,,100\n\
+                   3,BTC-USD\n";
+        let registry = SecurityRegistry::from_csv(csv.as_bytes()).unwrap();
+        assert_eq!(registry.len(), 3);
+        assert_eq!(
+            registry.get(1).map(|info| info.as_ref()),
+            Some(&SecurityInfo {
+                security_id: 1,
+                symbol: "ESZ4".to_string(),
+                tick_size: Some(dec!(0.25)),
+                lot_size: Some(1),
+                price_band: Some(PriceBand {
+                    low: dec!(4000),
+                    high: dec!(6000),
+                }),
+            })
+        );
+        assert_eq!(registry.symbol(2), Some("AAPL"));
+        assert_eq!(registry.get(2).unwrap().lot_size, Some(100));
+        assert_eq!(registry.get(3).unwrap().tick_size, None);
+        assert_eq!(registry.symbol(4), None);
+    }
+
+    #[test]
+    fn test_load_json() {
+        let json = r#"[
+            {"security_id": 1, "symbol": "ESZ4", "tick_size": 0.25, "lot_size": 1,
+             "price_band": {"low": 4000.0, "high": 6000.0}},
+            {"security_id": 2, "symbol": "AAPL", "exchange": "XNAS"}
+        ]"#;
+        let registry = SecurityRegistry::from_json(json.as_bytes()).unwrap();
+        assert_eq!(registry.get(1).unwrap().tick_size, Some(dec!(0.25)));
+        assert_eq!(
+            registry.get(1).unwrap().price_band,
+            Some(PriceBand {
+                low: dec!(4000),
+                high: dec!(6000),
+            })
+        );
+        assert_eq!(registry.symbol(2), Some("AAPL"));
+    }
+
+    #[test]
+    fn test_invalid_reference_data() {
+        for csv in [
+            "1",
+            "x,ESZ4",
+            "1,ESZ4,0.001",
+            "1,ESZ4,0.25,0",
+            "1,ESZ4,,,4000",
+            "1,ESZ4,,,6000,4000",
+            "1,ESZ4\n1,ESZ5",
+        ] {
+            let err = SecurityRegistry::from_csv(csv.as_bytes()).unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::InvalidData, "{}", csv);
+        }
+        let err = SecurityRegistry::from_json(r#"[{"security_id": 1}]"#.as_bytes()).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn test_price() {
+        let info = SecurityInfo {
+            security_id: 1,
+            symbol: "ESZ4".to_string(),
+            tick_size: Some(dec!(0.25)),
+            lot_size: None,
+            price_band: Some(PriceBand {
+                low: dec!(4000),
+                high: dec!(6000),
+            }),
+        };
+        assert_eq!(info.price(dec!(4000)).map(Price::value), Ok(dec!(4000)));
+        assert!(info.price(dec!(5000.75)).is_ok());
+        assert!(info.price(dec!(5000.10)).is_err());
+        assert!(info.price(dec!(6000.25)).is_err());
+    }
+
+    #[test]
+    fn test_qty() {
+        let info = SecurityInfo {
+            security_id: 2,
+            symbol: "AAPL".to_string(),
+            tick_size: None,
+            lot_size: Some(100),
+            price_band: None,
+        };
+        assert_eq!(info.qty(0), Ok(Qty::ZERO));
+        assert_eq!(info.qty(300).map(Qty::value), Ok(300));
+        assert!(info.qty(150).is_err());
+    }
+}
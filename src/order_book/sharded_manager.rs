@@ -0,0 +1,358 @@
+use std::collections::BTreeMap;
+use std::collections::hash_map::DefaultHasher;
+use std::fmt::Display;
+use std::hash::{Hash, Hasher};
+use std::io::Read;
+use std::ops::AddAssign;
+use std::panic;
+use std::sync::mpsc::{self, Receiver, SyncSender};
+use std::thread::{self, JoinHandle};
+
+use crate::order_book::buffered_order_book::BufferedOrderBook;
+use crate::order_book::manager::{Manager, ManagerOutcome};
+use crate::order_book::order_book::OrderBook;
+use crate::parsing::framing::{Framing, read_frame};
+use crate::parsing::order_book_snapshot::{OrderBookSnapshot, OrderBookSnapshotParser};
+use crate::parsing::order_book_update::{OrderBookUpdate, OrderBookUpdateParser, UpdateFormat};
+use crate::parsing::parser::{Endianness, Parser, ParserError};
+
+// Records waiting in the channel of a shard before the dispatcher blocks
+const CHANNEL_CAPACITY: usize = 1024;
+
+// Parsed records hold batches shared with the parser, so they can't leave the thread that
+// parsed them. The dispatcher only frames the records and the shards parse them.
+enum ShardMessage {
+    Snapshot(Vec<u8>),
+    Update(Vec<u8>),
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ShardStats {
+    pub records: usize,
+    pub applied: usize,
+    pub buffered: usize,
+    pub ignored: usize,
+    pub rejected: usize,
+    // Framed but failed to parse
+    pub corrupted: usize,
+    // Still waiting for a gap to close at the end
+    pub pending: usize,
+}
+
+impl ShardStats {
+    fn count(&mut self, outcome: &ManagerOutcome) {
+        match outcome {
+            ManagerOutcome::Applied => self.applied += 1,
+            ManagerOutcome::Buffered(_) => self.buffered += 1,
+            ManagerOutcome::IgnoredOld | ManagerOutcome::IgnoredUnknownSecurity => {
+                self.ignored += 1
+            }
+            ManagerOutcome::Rejected(_) => self.rejected += 1,
+        }
+    }
+}
+
+impl AddAssign for ShardStats {
+    fn add_assign(&mut self, other: Self) {
+        self.records += other.records;
+        self.applied += other.applied;
+        self.buffered += other.buffered;
+        self.ignored += other.ignored;
+        self.rejected += other.rejected;
+        self.corrupted += other.corrupted;
+        self.pending += other.pending;
+    }
+}
+
+impl Display for ShardStats {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} records: {} applied, {} buffered, {} ignored, {} rejected, {} corrupted, {} pending",
+            self.records,
+            self.applied,
+            self.buffered,
+            self.ignored,
+            self.rejected,
+            self.corrupted,
+            self.pending
+        )
+    }
+}
+
+// What a shard hands back when its channel is closed
+struct ShardReport {
+    books: Vec<OrderBook>,
+    stats: ShardStats,
+}
+
+fn run_shard(
+    receiver: Receiver<ShardMessage>,
+    update_format: UpdateFormat,
+    endianness: Endianness,
+) -> ShardReport {
+    let mut manager = Manager::default();
+    let mut snapshot_parser = OrderBookSnapshotParser::new(endianness);
+    let mut update_parser = OrderBookUpdateParser::new(update_format).with_endianness(endianness);
+    let mut stats = ShardStats::default();
+
+    for message in receiver {
+        stats.records += 1;
+        let outcome = match message {
+            ShardMessage::Snapshot(frame) => snapshot_parser
+                .read_from_slice(&frame)
+                .map(|(snapshot, _)| manager.apply_snapshot(&snapshot)),
+            ShardMessage::Update(frame) => update_parser
+                .read_from_slice(&frame)
+                .map(|(update, _)| manager.apply_update(update)),
+        };
+        match outcome {
+            Ok(outcome) => stats.count(&outcome),
+            Err(_) => stats.corrupted += 1,
+        }
+    }
+
+    let books = manager
+        .buffered_order_books
+        .into_values()
+        .map(|buffered_order_book| {
+            stats.pending += buffered_order_book.pending_updates.len();
+            buffered_order_book.order_book
+        })
+        .collect();
+    ShardReport { books, stats }
+}
+
+struct Shard {
+    sender: SyncSender<ShardMessage>,
+    worker: JoinHandle<ShardReport>,
+}
+
+// Final books of a sharded replay
+#[derive(Debug, Default)]
+pub struct ShardedReplay {
+    pub books: BTreeMap<u64, OrderBook>,
+    pub stats: Vec<ShardStats>,
+}
+
+impl ShardedReplay {
+    pub fn total_stats(&self) -> ShardStats {
+        let mut total = ShardStats::default();
+        for stats in &self.stats {
+            total += *stats;
+        }
+        total
+    }
+
+    // Pending updates are dropped with the shards, the books start without any
+    pub fn into_manager(self) -> Manager {
+        let mut manager = Manager::default();
+        manager.buffered_order_books = self
+            .books
+            .into_iter()
+            .map(|(security_id, book)| (security_id, BufferedOrderBook::new(book)))
+            .collect();
+        manager
+    }
+}
+
+// Applies records on worker threads, each owning the books of the securities hashed to it.
+// The calling thread is the dispatcher: it reads records and sends them to their shard, so
+// the records of a security are applied in the order they were read.
+pub struct ShardedManager {
+    shards: Vec<Shard>,
+    snapshot_parser: OrderBookSnapshotParser,
+    update_parser: OrderBookUpdateParser,
+    buffer: Vec<u8>,
+}
+
+impl ShardedManager {
+    pub fn new(num_shards: usize, update_format: UpdateFormat, endianness: Endianness) -> Self {
+        assert!(num_shards > 0, "At least one shard is required");
+        let shards = (0..num_shards)
+            .map(|i| {
+                let (sender, receiver) = mpsc::sync_channel(CHANNEL_CAPACITY);
+                let worker = thread::Builder::new()
+                    .name(format!("shard-{}", i))
+                    .spawn(move || run_shard(receiver, update_format, endianness))
+                    .expect("Failed to spawn shard thread");
+                Shard { sender, worker }
+            })
+            .collect();
+        Self {
+            shards,
+            snapshot_parser: OrderBookSnapshotParser::new(endianness),
+            update_parser: OrderBookUpdateParser::new(update_format).with_endianness(endianness),
+            buffer: Vec::new(),
+        }
+    }
+
+    pub fn num_shards(&self) -> usize {
+        self.shards.len()
+    }
+
+    pub fn shard_of(&self, security_id: u64) -> usize {
+        let mut hasher = DefaultHasher::new();
+        security_id.hash(&mut hasher);
+        (hasher.finish() % self.shards.len() as u64) as usize
+    }
+
+    fn send(&self, security_id: u64, message: ShardMessage) -> Result<(), ParserError> {
+        self.shards[self.shard_of(security_id)]
+            .sender
+            .send(message)
+            .map_err(|_| ParserError::Custom("Shard worker stopped".to_string()))
+    }
+
+    // Sends every record of the reader to its shard and returns their number
+    pub fn dispatch_snapshots<R: Read>(&mut self, reader: &mut R) -> Result<usize, ParserError> {
+        let mut count = 0;
+        loop {
+            match read_frame::<OrderBookSnapshot, _, _>(
+                reader,
+                &self.snapshot_parser,
+                &mut self.buffer,
+            ) {
+                Ok(()) => {}
+                Err(ParserError::ExpectedEof) => return Ok(count),
+                Err(e) => return Err(e),
+            }
+            let security_id =
+                Framing::<OrderBookSnapshot>::security_id(&self.snapshot_parser, &self.buffer);
+            self.send(security_id, ShardMessage::Snapshot(self.buffer.clone()))?;
+            count += 1;
+        }
+    }
+
+    pub fn dispatch_updates<R: Read>(&mut self, reader: &mut R) -> Result<usize, ParserError> {
+        let mut count = 0;
+        loop {
+            match read_frame::<OrderBookUpdate, _, _>(reader, &self.update_parser, &mut self.buffer)
+            {
+                Ok(()) => {}
+                Err(ParserError::ExpectedEof) => return Ok(count),
+                Err(e) => return Err(e),
+            }
+            let security_id =
+                Framing::<OrderBookUpdate>::security_id(&self.update_parser, &self.buffer);
+            self.send(security_id, ShardMessage::Update(self.buffer.clone()))?;
+            count += 1;
+        }
+    }
+
+    // Waits for the shards to apply everything sent to them
+    pub fn finish(self) -> ShardedReplay {
+        let workers: Vec<JoinHandle<ShardReport>> = self
+            .shards
+            .into_iter()
+            .map(|shard| {
+                drop(shard.sender);
+                shard.worker
+            })
+            .collect();
+
+        let mut replay = ShardedReplay::default();
+        for worker in workers {
+            let report = worker.join().unwrap_or_else(|e| panic::resume_unwind(e));
+            replay.books.extend(
+                report
+                    .books
+                    .into_iter()
+                    .map(|book| (book.security_id, book)),
+            );
+            replay.stats.push(report.stats);
+        }
+        replay
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::order_book::units::Qty;
+    use rust_decimal::dec;
+
+    fn create_test_snapshot_data(data: &mut Vec<u8>, security_id: u64, seq_no: u64) {
+        data.extend_from_slice(&1627846265u64.to_le_bytes()); // timestamp
+        data.extend_from_slice(&seq_no.to_le_bytes()); // seq_no
+        data.extend_from_slice(&security_id.to_le_bytes()); // security_id
+        for i in 0..5u64 {
+            // bid, then ask
+            data.extend_from_slice(&(100.0 - i as f64).to_le_bytes());
+            data.extend_from_slice(&((i + 1) * 10).to_le_bytes());
+            data.extend_from_slice(&(101.0 + i as f64).to_le_bytes());
+            data.extend_from_slice(&((i + 1) * 10 + 5).to_le_bytes());
+        }
+    }
+
+    fn create_test_update_data(data: &mut Vec<u8>, security_id: u64, seq_no: u64, qty: u64) {
+        data.extend_from_slice(&1627846266u64.to_le_bytes()); // timestamp
+        data.extend_from_slice(&seq_no.to_le_bytes()); // seq_no
+        data.extend_from_slice(&security_id.to_le_bytes()); // security_id
+        data.extend_from_slice(&1u64.to_le_bytes()); // num_updates
+        data.push(0); // side
+        data.extend_from_slice(&100.0f64.to_le_bytes()); // price
+        data.extend_from_slice(&qty.to_le_bytes()); // qty
+    }
+
+    #[test]
+    fn test_sharded_replay() {
+        let mut snapshots = Vec::new();
+        let mut updates = Vec::new();
+        for security_id in 1000..1020 {
+            create_test_snapshot_data(&mut snapshots, security_id, 100);
+            // 104 waits for the missing 103
+            for seq_no in [101, 102, 104] {
+                create_test_update_data(&mut updates, security_id, seq_no, security_id + seq_no);
+            }
+        }
+        // Unknown security
+        create_test_update_data(&mut updates, 2000, 101, 1);
+
+        let mut manager = ShardedManager::new(4, UpdateFormat::V1, Endianness::Little);
+        assert_eq!(
+            manager
+                .dispatch_snapshots(&mut snapshots.as_slice())
+                .unwrap(),
+            20
+        );
+        assert_eq!(
+            manager.dispatch_updates(&mut updates.as_slice()).unwrap(),
+            61
+        );
+        let replay = manager.finish();
+
+        assert_eq!(replay.books.len(), 20);
+        for (security_id, book) in &replay.books {
+            assert_eq!(book.seq_no, 102);
+            assert_eq!(book.bids[&dec!(100)], Qty(security_id + 102));
+        }
+        assert_eq!(replay.stats.len(), 4);
+        assert_eq!(
+            replay.total_stats(),
+            ShardStats {
+                records: 81,
+                applied: 60,
+                buffered: 20,
+                ignored: 1,
+                rejected: 0,
+                corrupted: 0,
+                pending: 20,
+            }
+        );
+    }
+
+    #[test]
+    fn test_truncated_file() {
+        let mut updates = Vec::new();
+        create_test_update_data(&mut updates, 1001, 101, 1);
+        create_test_update_data(&mut updates, 1001, 102, 1);
+        updates.truncate(updates.len() - 1);
+
+        let mut manager = ShardedManager::new(2, UpdateFormat::V1, Endianness::Little);
+        let result = manager.dispatch_updates(&mut updates.as_slice());
+        assert!(matches!(result, Err(ParserError::Io(_))));
+        // The record before the truncated one was still sent
+        assert_eq!(manager.finish().total_stats().ignored, 1);
+    }
+}
@@ -0,0 +1,213 @@
+use std::slice;
+
+// Up to this many levels a scan finds a price faster than a binary search
+const LINEAR_SEARCH_LEVELS: usize = 32;
+
+// Levels of one side of a book in a Vec sorted by price, lowest first. Books rarely hold
+// more than a few dozen levels, so searches and shifts within contiguous storage beat the
+// pointer chasing of a BTreeMap, especially for top-of-book reads.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SortedVecSide<K, V> {
+    levels: Vec<(K, V)>,
+}
+
+impl<K, V> Default for SortedVecSide<K, V> {
+    fn default() -> Self {
+        Self { levels: Vec::new() }
+    }
+}
+
+impl<K: Ord, V> SortedVecSide<K, V> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_capacity(capacity: usize) -> Self {
+        Self {
+            levels: Vec::with_capacity(capacity),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.levels.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.levels.is_empty()
+    }
+
+    // Keeps the allocation for the next levels
+    pub fn clear(&mut self) {
+        self.levels.clear();
+    }
+
+    fn position(&self, key: &K) -> Result<usize, usize> {
+        if self.levels.len() > LINEAR_SEARCH_LEVELS {
+            return self
+                .levels
+                .binary_search_by(|(level_key, _)| level_key.cmp(key));
+        }
+        match self
+            .levels
+            .iter()
+            .position(|(level_key, _)| level_key >= key)
+        {
+            Some(index) if self.levels[index].0 == *key => Ok(index),
+            Some(index) => Err(index),
+            None => Err(self.levels.len()),
+        }
+    }
+
+    pub fn get(&self, key: &K) -> Option<&V> {
+        self.position(key).ok().map(|index| &self.levels[index].1)
+    }
+
+    pub fn contains_key(&self, key: &K) -> bool {
+        self.position(key).is_ok()
+    }
+
+    // Returns the previous value of the level, as BTreeMap::insert
+    pub fn insert(&mut self, key: K, value: V) -> Option<V> {
+        match self.position(&key) {
+            Ok(index) => Some(std::mem::replace(&mut self.levels[index].1, value)),
+            Err(index) => {
+                self.levels.insert(index, (key, value));
+                None
+            }
+        }
+    }
+
+    pub fn remove(&mut self, key: &K) -> Option<V> {
+        self.position(key)
+            .ok()
+            .map(|index| self.levels.remove(index).1)
+    }
+
+    pub fn first_key_value(&self) -> Option<(&K, &V)> {
+        self.levels.first().map(|(key, value)| (key, value))
+    }
+
+    pub fn last_key_value(&self) -> Option<(&K, &V)> {
+        self.levels.last().map(|(key, value)| (key, value))
+    }
+
+    pub fn pop_first(&mut self) -> Option<(K, V)> {
+        if self.levels.is_empty() {
+            None
+        } else {
+            Some(self.levels.remove(0))
+        }
+    }
+
+    pub fn pop_last(&mut self) -> Option<(K, V)> {
+        self.levels.pop()
+    }
+
+    // Lowest price first, as BTreeMap::iter
+    pub fn iter(&self) -> Iter<'_, K, V> {
+        Iter {
+            levels: self.levels.iter(),
+        }
+    }
+
+    pub fn keys(&self) -> impl DoubleEndedIterator<Item = &K> + ExactSizeIterator {
+        self.levels.iter().map(|(key, _)| key)
+    }
+
+    pub fn values(&self) -> impl DoubleEndedIterator<Item = &V> + ExactSizeIterator {
+        self.levels.iter().map(|(_, value)| value)
+    }
+}
+
+pub struct Iter<'a, K, V> {
+    levels: slice::Iter<'a, (K, V)>,
+}
+
+impl<'a, K, V> Iterator for Iter<'a, K, V> {
+    type Item = (&'a K, &'a V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.levels.next().map(|(key, value)| (key, value))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.levels.size_hint()
+    }
+}
+
+impl<K, V> DoubleEndedIterator for Iter<'_, K, V> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        self.levels.next_back().map(|(key, value)| (key, value))
+    }
+}
+
+impl<K, V> ExactSizeIterator for Iter<'_, K, V> {}
+
+impl<'a, K: Ord, V> IntoIterator for &'a SortedVecSide<K, V> {
+    type Item = (&'a K, &'a V);
+    type IntoIter = Iter<'a, K, V>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+impl<K: Ord, V> FromIterator<(K, V)> for SortedVecSide<K, V> {
+    fn from_iter<I: IntoIterator<Item = (K, V)>>(iter: I) -> Self {
+        let mut side = Self::new();
+        for (key, value) in iter {
+            side.insert(key, value);
+        }
+        side
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::BTreeMap;
+
+    #[test]
+    fn test_insert_get_remove() {
+        let mut side = SortedVecSide::new();
+        assert_eq!(side.insert(100, 5), None);
+        assert_eq!(side.insert(98, 7), None);
+        assert_eq!(side.insert(99, 1), None);
+        assert_eq!(side.insert(99, 2), Some(1));
+
+        assert_eq!(side.len(), 3);
+        assert_eq!(side.get(&99), Some(&2));
+        assert!(!side.contains_key(&101));
+        assert_eq!(side.first_key_value(), Some((&98, &7)));
+        assert_eq!(side.last_key_value(), Some((&100, &5)));
+
+        assert_eq!(side.remove(&98), Some(7));
+        assert_eq!(side.remove(&98), None);
+        assert_eq!(side.pop_last(), Some((100, 5)));
+        assert_eq!(side.pop_first(), Some((99, 2)));
+        assert_eq!(side.pop_first(), None);
+        assert!(side.is_empty());
+    }
+
+    #[test]
+    fn test_same_order_as_btree_map() {
+        let mut side = SortedVecSide::new();
+        let mut map = BTreeMap::new();
+        // Pseudo-random prices, some repeated and removed
+        for i in 0u64..500 {
+            let price = (i * 7919) % 97;
+            if i % 5 == 0 {
+                assert_eq!(side.remove(&price), map.remove(&price));
+            } else {
+                assert_eq!(side.insert(price, i), map.insert(price, i));
+            }
+        }
+        assert!(side.iter().eq(map.iter()));
+        assert!(side.iter().rev().eq(map.iter().rev()));
+        assert!(side.keys().eq(map.keys()));
+        assert_eq!(
+            side.iter().collect::<SortedVecSide<_, _>>().len(),
+            map.len()
+        );
+    }
+}
@@ -0,0 +1,201 @@
+use rust_decimal::Decimal;
+use std::fmt::Display;
+use std::iter::Sum;
+use std::ops::{Add, AddAssign, Sub, SubAssign};
+
+use crate::order_book::price::PriceKey;
+
+// The price of a level, a multiple of the tick size it was checked against. Kept apart
+// from quantities and from the f64 prices of the records so they can't be mixed up. The
+// records keep their f64 prices as the tick size is the one of the book they go to, whose
+// SecurityInfo checks them with Price::new. Only the crate builds prices of levels
+// already in a book without checking them again.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Price(pub(crate) Decimal);
+
+impl Price {
+    // An error message when the price is not a multiple of `tick_size`
+    pub fn new(value: Decimal, tick_size: Decimal) -> Result<Self, String> {
+        if tick_size <= Decimal::ZERO {
+            return Err(format!("The tick size {} is not positive", tick_size));
+        }
+        if !(value % tick_size).is_zero() {
+            return Err(format!(
+                "The price {} is not a multiple of {}",
+                value, tick_size
+            ));
+        }
+        Ok(Self(value))
+    }
+
+    // The price of a level key of a book, always a multiple of PRICE_TICK
+    pub(crate) fn from_key<P: PriceKey>(key: P) -> Self {
+        Self(key.to_decimal())
+    }
+
+    pub fn value(self) -> Decimal {
+        self.0
+    }
+}
+
+impl Display for Price {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        Display::fmt(&self.0, f)
+    }
+}
+
+impl From<Price> for Decimal {
+    fn from(price: Price) -> Self {
+        price.0
+    }
+}
+
+// Prices serialize as strings like the levels of the books, and have to be multiples of
+// PRICE_TICK when they are deserialized
+#[cfg(feature = "serde")]
+impl serde::Serialize for Price {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(&self.0)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for Price {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        use serde::de::Error;
+
+        let price = String::deserialize(deserializer)?;
+        let value = price
+            .parse::<Decimal>()
+            .map_err(|_| D::Error::custom(format!("Invalid price: {}", price)))?;
+        Price::new(value, crate::order_book::price::PRICE_TICK).map_err(D::Error::custom)
+    }
+}
+
+// The quantity of a level, in the records, in the books and in their states. Kept apart
+// from prices and other counts so they can't be mixed up. A multiple of the lot size of
+// the security when the book knows it, see Qty::new.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(transparent))]
+pub struct Qty(pub(crate) u64);
+
+impl Qty {
+    // Removes a level
+    pub const ZERO: Qty = Qty(0);
+
+    // An error message when the quantity is not a multiple of `lot_size`. A zero
+    // quantity removes a level and is always valid.
+    pub fn new(value: u64, lot_size: u64) -> Result<Self, String> {
+        if lot_size == 0 {
+            return Err("The lot size must be positive".to_string());
+        }
+        if !value.is_multiple_of(lot_size) {
+            return Err(format!(
+                "The quantity {} is not a multiple of {}",
+                value, lot_size
+            ));
+        }
+        Ok(Self(value))
+    }
+
+    pub fn value(self) -> u64 {
+        self.0
+    }
+
+    pub fn is_zero(self) -> bool {
+        self.0 == 0
+    }
+}
+
+impl Display for Qty {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        Display::fmt(&self.0, f)
+    }
+}
+
+// A quantity taken as it is, e.g. of a level built by hand
+impl From<u64> for Qty {
+    fn from(value: u64) -> Self {
+        Self(value)
+    }
+}
+
+impl From<Qty> for u64 {
+    fn from(qty: Qty) -> Self {
+        qty.0
+    }
+}
+
+// Quantities only add up to and subtract from other quantities, so a count of orders or
+// a price can't slip into a level
+impl Add for Qty {
+    type Output = Qty;
+
+    fn add(self, other: Qty) -> Qty {
+        Qty(self.0 + other.0)
+    }
+}
+
+impl Sub for Qty {
+    type Output = Qty;
+
+    fn sub(self, other: Qty) -> Qty {
+        Qty(self.0 - other.0)
+    }
+}
+
+impl AddAssign for Qty {
+    fn add_assign(&mut self, other: Qty) {
+        self.0 += other.0;
+    }
+}
+
+impl SubAssign for Qty {
+    fn sub_assign(&mut self, other: Qty) {
+        self.0 -= other.0;
+    }
+}
+
+impl Sum for Qty {
+    fn sum<I: Iterator<Item = Qty>>(iter: I) -> Qty {
+        Qty(iter.map(|qty| qty.0).sum())
+    }
+}
+
+impl<'a> Sum<&'a Qty> for Qty {
+    fn sum<I: Iterator<Item = &'a Qty>>(iter: I) -> Qty {
+        iter.copied().sum()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rust_decimal::dec;
+
+    #[test]
+    fn test_price_and_qty() {
+        assert_eq!(
+            Price::new(dec!(5000.75), dec!(0.25)).map(Price::value),
+            Ok(dec!(5000.75))
+        );
+        assert_eq!(
+            Price::new(dec!(5000.10), dec!(0.25)),
+            Err("The price 5000.10 is not a multiple of 0.25".to_string())
+        );
+        assert!(Price::new(dec!(1), Decimal::ZERO).is_err());
+        assert_eq!(format!("{:.2}", Price(dec!(100.5))), "100.50");
+
+        assert_eq!(Qty::new(300, 100), Ok(Qty(300)));
+        assert!(Qty::new(0, 100).unwrap().is_zero());
+        assert_eq!(
+            Qty::new(150, 100),
+            Err("The quantity 150 is not a multiple of 100".to_string())
+        );
+        assert!(Qty::new(1, 0).is_err());
+        assert_eq!(u64::from(Qty(7)), 7);
+        assert_eq!(Qty(7) + Qty(3) - Qty(4), Qty(6));
+        assert_eq!([Qty(1), Qty(2)].iter().sum::<Qty>(), Qty(3));
+    }
+}
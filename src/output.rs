@@ -0,0 +1,13 @@
+pub mod error_sink;
+#[cfg(feature = "grpc")]
+pub mod grpc_service;
+#[cfg(feature = "http")]
+pub mod http_api;
+pub mod ladder;
+pub mod query_server;
+#[cfg(feature = "redis")]
+pub mod redis_publisher;
+pub mod reject_log;
+pub mod report;
+pub mod signing;
+pub mod sink;
@@ -0,0 +1,266 @@
+use serde_json::{Map, Value};
+use std::fmt::Display;
+use std::io::{self, Write};
+
+use crate::order_book::errors::Errors;
+use crate::order_book::order_book::QuarantinedLevel;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReplayErrorKind {
+    // A record failed validation and was ignored
+    InvalidRecord,
+    // A level was left out of an update by LevelPolicy::BestEffort
+    InvalidLevel,
+    // An update was dropped as its book had too many pending updates
+    PendingOverflow,
+    // A bug rather than bad data
+    Internal,
+    // A record could not be read, the rest of the source is skipped
+    Corrupted,
+}
+
+impl ReplayErrorKind {
+    // The name of the kind in the JSON errors
+    pub fn name(self) -> &'static str {
+        match self {
+            ReplayErrorKind::InvalidRecord => "invalid_record",
+            ReplayErrorKind::InvalidLevel => "invalid_level",
+            ReplayErrorKind::PendingOverflow => "pending_overflow",
+            ReplayErrorKind::Internal => "internal",
+            ReplayErrorKind::Corrupted => "corrupted",
+        }
+    }
+}
+
+// Something skipped while replaying a source, with the record it is about when known
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ReplayError {
+    pub kind: ReplayErrorKind,
+    pub source: String,
+    pub security_id: Option<u64>,
+    pub seq_no: Option<u64>,
+    // Index of the level in its update, for InvalidLevel
+    pub level: Option<usize>,
+    pub message: String,
+}
+
+impl ReplayError {
+    // None for the errors that are part of the normal flow, gaps and old or unknown
+    // records
+    pub fn rejected(source: &str, error: &Errors) -> Option<Self> {
+        let kind = match error {
+            Errors::InvalidPrice(..)
+            | Errors::InvalidQuantity(..)
+            | Errors::PriceDeviation(..)
+            | Errors::TimestampRegression(..) => ReplayErrorKind::InvalidRecord,
+            Errors::PendingOverflow(_) => ReplayErrorKind::PendingOverflow,
+            Errors::SecurityIdMismatch(_) => ReplayErrorKind::Internal,
+            Errors::OrderBookNotFound(_)
+            | Errors::SequenceNumberGap(_)
+            | Errors::OldSequenceNumber(_)
+            | Errors::AwaitingSnapshot(_) => return None,
+        };
+        let info = error.info();
+        Some(Self {
+            kind,
+            source: source.to_string(),
+            security_id: Some(info.security_id),
+            seq_no: Some(info.seq_no),
+            level: None,
+            message: error.to_string(),
+        })
+    }
+
+    pub fn quarantined(source: &str, level: &QuarantinedLevel) -> Self {
+        Self {
+            kind: ReplayErrorKind::InvalidLevel,
+            source: source.to_string(),
+            security_id: Some(level.security_id),
+            seq_no: Some(level.seq_no),
+            level: Some(level.index),
+            message: level.error.to_string(),
+        }
+    }
+
+    pub fn corrupted(source: &str, message: String) -> Self {
+        Self {
+            kind: ReplayErrorKind::Corrupted,
+            source: source.to_string(),
+            security_id: None,
+            seq_no: None,
+            level: None,
+            message,
+        }
+    }
+
+    // An object without the fields that are not known
+    pub fn to_json(&self) -> Value {
+        let mut object = Map::new();
+        object.insert("kind".to_string(), self.kind.name().into());
+        object.insert("source".to_string(), self.source.clone().into());
+        if let Some(security_id) = self.security_id {
+            object.insert("security_id".to_string(), security_id.into());
+        }
+        if let Some(seq_no) = self.seq_no {
+            object.insert("seq_no".to_string(), seq_no.into());
+        }
+        if let Some(level) = self.level {
+            object.insert("level".to_string(), level.into());
+        }
+        object.insert("message".to_string(), self.message.clone().into());
+        Value::Object(object)
+    }
+}
+
+impl Display for ReplayError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:?} in {}", self.kind, self.source)?;
+        if let (Some(security_id), Some(seq_no)) = (self.security_id, self.seq_no) {
+            write!(f, ", security {} seq_no {}", security_id, seq_no)?;
+        }
+        if let Some(level) = self.level {
+            write!(f, ", level {}", level)?;
+        }
+        write!(f, ": {}", self.message)
+    }
+}
+
+// Where a replay reports the records it skipped, so that pipelines can act on them
+// instead of scraping the logs
+pub trait ErrorSink {
+    fn report(&mut self, error: &ReplayError);
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+// Prints every error as a line on stderr
+#[derive(Debug, Default)]
+pub struct StderrErrorSink;
+
+impl ErrorSink for StderrErrorSink {
+    fn report(&mut self, error: &ReplayError) {
+        eprintln!("{}", error);
+    }
+}
+
+// Writes every error as a JSON object per line. The first write error is kept and
+// returned by flush, the errors after it are lost.
+pub struct JsonErrorSink<W: Write> {
+    writer: W,
+    error: Option<io::Error>,
+}
+
+impl<W: Write> JsonErrorSink<W> {
+    pub fn new(writer: W) -> Self {
+        Self {
+            writer,
+            error: None,
+        }
+    }
+}
+
+impl<W: Write> ErrorSink for JsonErrorSink<W> {
+    fn report(&mut self, error: &ReplayError) {
+        if self.error.is_some() {
+            return;
+        }
+        let written = serde_json::to_writer(&mut self.writer, &error.to_json())
+            .map_err(io::Error::from)
+            .and_then(|_| self.writer.write_all(b"\n"));
+        if let Err(e) = written {
+            self.error = Some(e);
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        if let Some(e) = self.error.take() {
+            return Err(e);
+        }
+        self.writer.flush()
+    }
+}
+
+// Hands every error to the callback
+pub struct CallbackErrorSink {
+    on_error: Box<dyn FnMut(&ReplayError)>,
+}
+
+impl CallbackErrorSink {
+    pub fn new(on_error: Box<dyn FnMut(&ReplayError)>) -> Self {
+        Self { on_error }
+    }
+}
+
+impl ErrorSink for CallbackErrorSink {
+    fn report(&mut self, error: &ReplayError) {
+        (self.on_error)(error)
+    }
+}
+
+// Reports every error to all the sinks
+impl ErrorSink for Vec<Box<dyn ErrorSink>> {
+    fn report(&mut self, error: &ReplayError) {
+        for sink in self.iter_mut() {
+            sink.report(error);
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.iter_mut().try_for_each(|sink| sink.flush())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::order_book::errors::UpdateMessageInfo;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    #[test]
+    fn test_error_sinks() {
+        let info = UpdateMessageInfo::new(1001, 102);
+        assert_eq!(
+            ReplayError::rejected("updates.bin", &Errors::SequenceNumberGap(info)),
+            None
+        );
+        let error = ReplayError::rejected(
+            "updates.bin",
+            &Errors::InvalidQuantity(info, "Quantity 0 of a new level".to_string()),
+        )
+        .unwrap();
+
+        let reported = Rc::new(RefCell::new(Vec::new()));
+        let callback_reported = reported.clone();
+        let mut sinks: Vec<Box<dyn ErrorSink>> = vec![
+            Box::new(JsonErrorSink::new(Vec::new())),
+            Box::new(CallbackErrorSink::new(Box::new(
+                move |error: &ReplayError| callback_reported.borrow_mut().push(error.clone()),
+            ))),
+        ];
+        sinks.report(&error);
+        sinks.report(&ReplayError::corrupted(
+            "updates.bin",
+            "Unexpected end of file".to_string(),
+        ));
+        sinks.flush().unwrap();
+        assert_eq!(reported.borrow().len(), 2);
+        assert_eq!(reported.borrow()[0], error);
+
+        let mut json = JsonErrorSink::new(Vec::new());
+        json.report(&error);
+        assert_eq!(
+            String::from_utf8(json.writer).unwrap(),
+            "{\"kind\":\"invalid_record\",\"source\":\"updates.bin\",\"security_id\":1001,\
+             \"seq_no\":102,\"message\":\"Invalid quantity for security 1001 with seq_no 102: \
+             Quantity 0 of a new level\"}\n"
+        );
+        assert_eq!(
+            error.to_string(),
+            "InvalidRecord in updates.bin, security 1001 seq_no 102: Invalid quantity for \
+             security 1001 with seq_no 102: Quantity 0 of a new level"
+        );
+    }
+}
@@ -0,0 +1,315 @@
+use num_traits::ToPrimitive;
+use prost::Message;
+use std::collections::HashMap;
+use std::io;
+use std::net::TcpListener;
+use std::pin::Pin;
+use tokio::sync::broadcast;
+use tokio_stream::wrappers::errors::BroadcastStreamRecvError;
+use tokio_stream::wrappers::{BroadcastStream, TcpListenerStream};
+use tokio_stream::{Stream, StreamExt};
+use tonic::transport::Server;
+use tonic::{Request, Response, Status};
+
+use crate::order_book::level_ttl::ExpiredLevel;
+use crate::order_book::listener::BookListener;
+use crate::order_book::order_book::{BookLevel as OrderBookLevel, OrderBook, OrderBookState, Side};
+use crate::output::query_server::SharedBooks;
+
+include!(concat!(env!("OUT_DIR"), "/order_book.BookService.rs"));
+
+use book_service_server::{BookService, BookServiceServer};
+
+// The messages of proto/book_service.proto, declared by hand so building needs no protoc.
+// The service itself is generated by build.rs.
+
+// Changes of the tops kept for streams that fall behind; slower streams skip the oldest
+const TOPS_AHEAD: usize = 1024;
+
+#[derive(Clone, PartialEq, Message)]
+pub struct BookRequest {
+    #[prost(uint64, tag = "1")]
+    pub security_id: u64,
+    #[prost(uint32, tag = "2")]
+    pub depth: u32,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct BookLevel {
+    #[prost(double, tag = "1")]
+    pub price: f64,
+    #[prost(uint64, tag = "2")]
+    pub qty: u64,
+    #[prost(uint32, optional, tag = "3")]
+    pub order_count: Option<u32>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct Book {
+    #[prost(uint64, tag = "1")]
+    pub security_id: u64,
+    #[prost(uint64, tag = "2")]
+    pub seq_no: u64,
+    #[prost(uint64, tag = "3")]
+    pub timestamp: u64,
+    #[prost(message, repeated, tag = "4")]
+    pub bids: Vec<BookLevel>,
+    #[prost(message, repeated, tag = "5")]
+    pub asks: Vec<BookLevel>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct TopOfBookRequest {
+    #[prost(uint64, tag = "1")]
+    pub security_id: u64,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct TopOfBook {
+    #[prost(uint64, tag = "1")]
+    pub security_id: u64,
+    #[prost(uint64, tag = "2")]
+    pub seq_no: u64,
+    #[prost(uint64, tag = "3")]
+    pub timestamp: u64,
+    #[prost(message, optional, tag = "4")]
+    pub bid: Option<BookLevel>,
+    #[prost(message, optional, tag = "5")]
+    pub ask: Option<BookLevel>,
+}
+
+impl From<&OrderBookLevel> for BookLevel {
+    fn from(level: &OrderBookLevel) -> Self {
+        Self {
+            price: level.price.value().to_f64().unwrap_or(0.0),
+            qty: level.qty.value(),
+            order_count: level.order_count,
+        }
+    }
+}
+
+impl TopOfBook {
+    fn from_state(state: &OrderBookState) -> Self {
+        Self {
+            security_id: state.security_id,
+            seq_no: state.seq_no,
+            timestamp: state.timestamp,
+            bid: state.best_bid().map(BookLevel::from),
+            ask: state.best_ask().map(BookLevel::from),
+        }
+    }
+}
+
+// Keeps the books for the service up to date, as a listener of the Manager replaying the
+// records. Copies of the books answer GetBook, see SharedBooks, and every change of the
+// best bid or ask of a book is sent to the StreamTopOfBook streams of its security.
+pub struct GrpcBooks {
+    books: SharedBooks,
+    tops: broadcast::Sender<TopOfBook>,
+    // Best bid and ask last sent for each security
+    last_tops: HashMap<u64, (Option<OrderBookLevel>, Option<OrderBookLevel>)>,
+}
+
+impl Default for GrpcBooks {
+    fn default() -> Self {
+        Self {
+            books: SharedBooks::default(),
+            tops: broadcast::channel(TOPS_AHEAD).0,
+            last_tops: HashMap::new(),
+        }
+    }
+}
+
+impl GrpcBooks {
+    // The service answering from these books, to be served with serve_grpc
+    pub fn service(&self) -> BookGrpcService {
+        BookGrpcService {
+            books: self.books.clone(),
+            tops: self.tops.clone(),
+        }
+    }
+
+    fn publish_top(&mut self, book: &OrderBook) {
+        let bid = book.top_levels(Side::Bid, 1).first().copied();
+        let ask = book.top_levels(Side::Ask, 1).first().copied();
+        if self.last_tops.get(&book.security_id) == Some(&(bid, ask)) {
+            return;
+        }
+        self.last_tops.insert(book.security_id, (bid, ask));
+        // Fails only when no stream is open
+        let _ = self.tops.send(TopOfBook {
+            security_id: book.security_id,
+            seq_no: book.seq_no,
+            timestamp: book.timestamp,
+            bid: bid.as_ref().map(BookLevel::from),
+            ask: ask.as_ref().map(BookLevel::from),
+        });
+    }
+}
+
+impl BookListener for GrpcBooks {
+    fn on_snapshot_applied(&mut self, book: &OrderBook) {
+        self.books.on_snapshot_applied(book);
+        self.publish_top(book);
+    }
+
+    fn on_update_applied(&mut self, book: &OrderBook) {
+        self.books.on_update_applied(book);
+        self.publish_top(book);
+    }
+
+    fn on_levels_expired(&mut self, book: &OrderBook, expired: &[ExpiredLevel]) {
+        self.books.on_levels_expired(book, expired);
+        self.publish_top(book);
+    }
+
+    fn on_book_evicted(&mut self, book: &OrderBook) {
+        self.books.on_book_evicted(book);
+        self.last_tops.remove(&book.security_id);
+    }
+}
+
+#[derive(Clone)]
+pub struct BookGrpcService {
+    books: SharedBooks,
+    tops: broadcast::Sender<TopOfBook>,
+}
+
+type TopOfBookStream = Pin<Box<dyn Stream<Item = Result<TopOfBook, Status>> + Send>>;
+
+#[tonic::async_trait]
+impl BookService for BookGrpcService {
+    async fn get_book(&self, request: Request<BookRequest>) -> Result<Response<Book>, Status> {
+        let request = request.into_inner();
+        let Some(state) = self.books.get(request.security_id) else {
+            return Err(Status::not_found(format!(
+                "No book for security_id {}",
+                request.security_id
+            )));
+        };
+        let depth = match request.depth {
+            0 => usize::MAX,
+            depth => depth as usize,
+        };
+        let levels = |levels: &[OrderBookLevel]| {
+            levels
+                .iter()
+                .take(depth)
+                .map(BookLevel::from)
+                .collect::<Vec<_>>()
+        };
+        Ok(Response::new(Book {
+            security_id: state.security_id,
+            seq_no: state.seq_no,
+            timestamp: state.timestamp,
+            bids: levels(&state.bids),
+            asks: levels(&state.asks),
+        }))
+    }
+
+    type StreamTopOfBookStream = TopOfBookStream;
+
+    async fn stream_top_of_book(
+        &self,
+        request: Request<TopOfBookRequest>,
+    ) -> Result<Response<TopOfBookStream>, Status> {
+        let security_id = request.into_inner().security_id;
+        // Subscribed before the current top is read, so no change in between is missed
+        let changes = BroadcastStream::new(self.tops.subscribe());
+        let current = self
+            .books
+            .get(security_id)
+            .map(|state| TopOfBook::from_state(&state));
+        let changes = changes.filter_map(move |top| match top {
+            Ok(top) if top.security_id == security_id => Some(Ok(top)),
+            // A stream too slow for the replay skips to the latest changes
+            Ok(_) | Err(BroadcastStreamRecvError::Lagged(_)) => None,
+        });
+        let stream = tokio_stream::iter(current.map(Ok)).chain(changes);
+        Ok(Response::new(Box::pin(stream)))
+    }
+}
+
+// Answers the requests of the clients on a runtime of its own. Returns once the listener
+// fails.
+pub fn serve_grpc(listener: TcpListener, service: BookGrpcService) -> io::Result<()> {
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()?;
+    runtime.block_on(async move {
+        listener.set_nonblocking(true)?;
+        let listener = tokio::net::TcpListener::from_std(listener)?;
+        Server::builder()
+            .add_service(BookServiceServer::new(service))
+            .serve_with_incoming(TcpListenerStream::new(listener))
+            .await
+            .map_err(io::Error::other)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::book_service_client::BookServiceClient;
+    use super::*;
+    use crate::parsing::order_book_snapshot::test_support::create_test_snapshot;
+    use std::thread;
+
+    fn create_test_book(seq_no: u64, best_bid: f64) -> OrderBook {
+        let mut snapshot = create_test_snapshot(1001, seq_no);
+        snapshot.bids[0].price = best_bid;
+        OrderBook::new(&snapshot).unwrap()
+    }
+
+    #[test]
+    fn test_serve_grpc() {
+        let mut books = GrpcBooks::default();
+        books.on_snapshot_applied(&create_test_book(100, 100.0));
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let service = books.service();
+        thread::spawn(move || serve_grpc(listener, service));
+
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap();
+        runtime.block_on(async {
+            let mut client = BookServiceClient::connect(format!("http://{}", address))
+                .await
+                .unwrap();
+            let book = client
+                .get_book(BookRequest {
+                    security_id: 1001,
+                    depth: 1,
+                })
+                .await
+                .unwrap()
+                .into_inner();
+            assert_eq!(book.seq_no, 100);
+            assert_eq!(book.bids.len(), 1);
+            assert_eq!(book.bids[0].price, 100.0);
+            assert_eq!(book.asks[0].qty, 15);
+            let err = client
+                .get_book(BookRequest {
+                    security_id: 1002,
+                    depth: 0,
+                })
+                .await
+                .unwrap_err();
+            assert_eq!(err.code(), tonic::Code::NotFound);
+
+            let mut tops = client
+                .stream_top_of_book(TopOfBookRequest { security_id: 1001 })
+                .await
+                .unwrap()
+                .into_inner();
+            let top = tops.message().await.unwrap().unwrap();
+            assert_eq!(top.bid.unwrap().price, 100.0);
+            // Records leaving the top as it was are not streamed
+            books.on_snapshot_applied(&create_test_book(101, 100.0));
+            books.on_snapshot_applied(&create_test_book(102, 100.5));
+            let top = tops.message().await.unwrap().unwrap();
+            assert_eq!((top.seq_no, top.bid.unwrap().price), (102, 100.5));
+        });
+    }
+}
@@ -0,0 +1,289 @@
+use axum::Router;
+use axum::extract::{Path, RawQuery, State};
+use axum::http::{StatusCode, header};
+use axum::response::{IntoResponse, Response};
+use axum::routing::get;
+use num_traits::ToPrimitive;
+use serde::Serialize;
+use std::io;
+use std::net::TcpListener;
+use std::sync::{Arc, Mutex};
+
+use crate::order_book::errors::Errors;
+use crate::order_book::level_ttl::ExpiredLevel;
+use crate::order_book::listener::BookListener;
+use crate::order_book::manager::GapInfo;
+use crate::order_book::order_book::{BookLevel, OrderBook, OrderBookState};
+use crate::output::query_server::SharedBooks;
+
+// What the replay did so far, as counted by the listener of the books
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct ReplayStats {
+    pub snapshots_applied: u64,
+    pub updates_applied: u64,
+    pub gaps_detected: u64,
+    pub errors: u64,
+    pub timestamp_regressions: u64,
+    pub books_evicted: u64,
+}
+
+#[derive(Serialize)]
+struct LevelJson {
+    price: f64,
+    qty: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    order_count: Option<u32>,
+}
+
+impl From<&BookLevel> for LevelJson {
+    fn from(level: &BookLevel) -> Self {
+        Self {
+            price: level.price.value().to_f64().unwrap_or(0.0),
+            qty: level.qty.value(),
+            order_count: level.order_count,
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct TopJson {
+    security_id: u64,
+    seq_no: u64,
+    timestamp: u64,
+    bid: Option<LevelJson>,
+    ask: Option<LevelJson>,
+}
+
+#[derive(Serialize)]
+struct BookJson {
+    security_id: u64,
+    seq_no: u64,
+    timestamp: u64,
+    bids: Vec<LevelJson>,
+    asks: Vec<LevelJson>,
+}
+
+#[derive(Serialize)]
+struct StatsJson {
+    books: usize,
+    #[serde(flatten)]
+    replay: ReplayStats,
+}
+
+// Keeps the books and the stats for the API up to date, as a listener of the Manager
+// replaying the records, see SharedBooks
+#[derive(Debug, Clone, Default)]
+pub struct HttpBooks {
+    books: SharedBooks,
+    stats: Arc<Mutex<ReplayStats>>,
+}
+
+impl HttpBooks {
+    pub fn stats(&self) -> ReplayStats {
+        *self.stats.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn count(&self, count: impl FnOnce(&mut ReplayStats)) {
+        count(&mut self.stats.lock().unwrap_or_else(|e| e.into_inner()));
+    }
+}
+
+impl BookListener for HttpBooks {
+    fn on_snapshot_applied(&mut self, book: &OrderBook) {
+        self.books.on_snapshot_applied(book);
+        self.count(|stats| stats.snapshots_applied += 1);
+    }
+
+    fn on_update_applied(&mut self, book: &OrderBook) {
+        self.books.on_update_applied(book);
+        self.count(|stats| stats.updates_applied += 1);
+    }
+
+    fn on_gap_detected(&mut self, _security_id: u64, _gap_info: &GapInfo) {
+        self.count(|stats| stats.gaps_detected += 1);
+    }
+
+    fn on_error(&mut self, _security_id: u64, _seq_no: u64, _error: &Errors) {
+        self.count(|stats| stats.errors += 1);
+    }
+
+    fn on_timestamp_regression(&mut self, _book: &OrderBook, _previous_timestamp: u64) {
+        self.count(|stats| stats.timestamp_regressions += 1);
+    }
+
+    fn on_levels_expired(&mut self, book: &OrderBook, expired: &[ExpiredLevel]) {
+        self.books.on_levels_expired(book, expired);
+    }
+
+    fn on_book_evicted(&mut self, book: &OrderBook) {
+        self.books.on_book_evicted(book);
+        self.count(|stats| stats.books_evicted += 1);
+    }
+}
+
+fn json<T: Serialize>(status: StatusCode, value: &T) -> Response {
+    match serde_json::to_string(value) {
+        Ok(body) => (status, [(header::CONTENT_TYPE, "application/json")], body).into_response(),
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
+    }
+}
+
+fn error(status: StatusCode, message: String) -> Response {
+    json(status, &serde_json::json!({ "error": message }))
+}
+
+fn top(state: &OrderBookState) -> TopJson {
+    TopJson {
+        security_id: state.security_id,
+        seq_no: state.seq_no,
+        timestamp: state.timestamp,
+        bid: state.best_bid().map(LevelJson::from),
+        ask: state.best_ask().map(LevelJson::from),
+    }
+}
+
+async fn books(State(books): State<HttpBooks>) -> Response {
+    let tops: Vec<TopJson> = books
+        .books
+        .security_ids()
+        .into_iter()
+        .filter_map(|security_id| books.books.get(security_id))
+        .map(|state| top(&state))
+        .collect();
+    json(StatusCode::OK, &tops)
+}
+
+// The depth of a depth=N query, all the levels by default
+fn parse_depth(query: Option<&str>) -> Result<usize, String> {
+    let mut depth = usize::MAX;
+    for pair in query.unwrap_or_default().split('&') {
+        if let Some(value) = pair.strip_prefix("depth=") {
+            depth = value
+                .parse()
+                .map_err(|_| format!("Invalid depth: {}", value))?;
+        }
+    }
+    Ok(depth)
+}
+
+async fn book(
+    State(books): State<HttpBooks>,
+    Path(security_id): Path<String>,
+    RawQuery(query): RawQuery,
+) -> Response {
+    let Ok(security_id) = security_id.parse::<u64>() else {
+        return error(
+            StatusCode::BAD_REQUEST,
+            format!("Invalid security_id: {}", security_id),
+        );
+    };
+    let depth = match parse_depth(query.as_deref()) {
+        Ok(depth) => depth,
+        Err(e) => return error(StatusCode::BAD_REQUEST, e),
+    };
+    let Some(state) = books.books.get(security_id) else {
+        return error(
+            StatusCode::NOT_FOUND,
+            format!("No book for security_id {}", security_id),
+        );
+    };
+    let levels = |levels: &[BookLevel]| levels.iter().take(depth).map(LevelJson::from).collect();
+    json(
+        StatusCode::OK,
+        &BookJson {
+            security_id: state.security_id,
+            seq_no: state.seq_no,
+            timestamp: state.timestamp,
+            bids: levels(&state.bids),
+            asks: levels(&state.asks),
+        },
+    )
+}
+
+async fn stats(State(books): State<HttpBooks>) -> Response {
+    json(
+        StatusCode::OK,
+        &StatsJson {
+            books: books.books.security_ids().len(),
+            replay: books.stats(),
+        },
+    )
+}
+
+// GET /books            the best bid and ask of every book, by security_id
+// GET /books/{id}       the levels of a book, depth=N of them a side if given
+// GET /stats            the number of books and what the replay did so far
+pub fn router(books: HttpBooks) -> Router {
+    Router::new()
+        .route("/books", get(self::books))
+        .route("/books/{security_id}", get(book))
+        .route("/stats", get(stats))
+        .with_state(books)
+}
+
+// Answers the requests on a runtime of its own. Returns once the listener fails.
+pub fn serve_http(listener: TcpListener, books: HttpBooks) -> io::Result<()> {
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()?;
+    runtime.block_on(async move {
+        listener.set_nonblocking(true)?;
+        let listener = tokio::net::TcpListener::from_std(listener)?;
+        axum::serve(listener, router(books)).await
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parsing::order_book_snapshot::test_support::create_test_snapshot;
+    use std::io::{Read, Write};
+    use std::net::{SocketAddr, TcpStream};
+    use std::thread;
+
+    fn create_test_books() -> HttpBooks {
+        let book = OrderBook::new(&create_test_snapshot(1001, 100)).unwrap();
+        let mut books = HttpBooks::default();
+        books.on_snapshot_applied(&book);
+        books
+    }
+
+    // The status line and the body of the response
+    fn get(address: SocketAddr, path: &str) -> (String, String) {
+        let mut stream = TcpStream::connect(address).unwrap();
+        write!(stream, "GET {} HTTP/1.0\r\n\r\n", path).unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        let (head, body) = response.split_once("\r\n\r\n").unwrap();
+        (head.lines().next().unwrap().to_string(), body.to_string())
+    }
+
+    #[test]
+    fn test_parse_depth() {
+        assert_eq!(parse_depth(None), Ok(usize::MAX));
+        assert_eq!(parse_depth(Some("format=json&depth=2")), Ok(2));
+        assert!(parse_depth(Some("depth=x")).is_err());
+    }
+
+    #[test]
+    fn test_serve_http() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        thread::spawn(move || serve_http(listener, create_test_books()));
+
+        let (status, body) = get(address, "/books/1001?depth=1");
+        assert_eq!(status, "HTTP/1.0 200 OK");
+        assert_eq!(
+            body,
+            r#"{"security_id":1001,"seq_no":100,"timestamp":1627846265,"bids":[{"price":100.0,"qty":10}],"asks":[{"price":101.0,"qty":15}]}"#
+        );
+        let (_, body) = get(address, "/books");
+        assert!(body.starts_with(r#"[{"security_id":1001,"seq_no":100"#));
+        let (_, body) = get(address, "/stats");
+        assert!(body.starts_with(r#"{"books":1,"snapshots_applied":1,"updates_applied":0"#));
+        let (status, _) = get(address, "/books/1002");
+        assert_eq!(status, "HTTP/1.0 404 Not Found");
+        let (status, _) = get(address, "/books/x");
+        assert_eq!(status, "HTTP/1.0 400 Bad Request");
+    }
+}
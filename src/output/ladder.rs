@@ -0,0 +1,407 @@
+use std::io::{self, Write};
+use std::str::FromStr;
+
+use crate::order_book::order_book::{BookLevel, OrderBook, OrderBookState, Side};
+
+const BID_COLOR: &str = "\x1b[32m";
+const ASK_COLOR: &str = "\x1b[31m";
+const RESET_COLOR: &str = "\x1b[0m";
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Alignment {
+    Left,
+    #[default]
+    Right,
+}
+
+impl FromStr for Alignment {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "left" => Ok(Alignment::Left),
+            "right" => Ok(Alignment::Right),
+            _ => Err(format!("Unknown alignment: {}", s)),
+        }
+    }
+}
+
+// Characters of the bar of the largest quantity shown
+const BAR_WIDTH: usize = 20;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum LadderLayout {
+    // A row per level with its side in the first column
+    #[default]
+    Rows,
+    // The bid columns left of the prices and the ask columns right of them
+    Columns,
+}
+
+impl FromStr for LadderLayout {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "rows" => Ok(LadderLayout::Rows),
+            "columns" => Ok(LadderLayout::Columns),
+            _ => Err(format!("Unknown ladder layout: {}", s)),
+        }
+    }
+}
+
+// A book as a price ladder: the asks above the bids with the best prices in the middle,
+// a row per level
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LadderFormat {
+    pub depth: usize,
+    pub layout: LadderLayout,
+    pub alignment: Alignment,
+    // Adds the quantity up to each level from the best one of its side
+    pub cumulative: bool,
+    // Adds a bar of the quantity of each level, or of its cumulative quantity, scaled to
+    // the largest one shown
+    pub bars: bool,
+    // ANSI colors for the rows of each side
+    pub color: bool,
+}
+
+impl Default for LadderFormat {
+    fn default() -> Self {
+        Self {
+            depth: 5,
+            layout: LadderLayout::default(),
+            alignment: Alignment::default(),
+            cumulative: false,
+            bars: false,
+            color: false,
+        }
+    }
+}
+
+struct Row {
+    side: Side,
+    cells: Vec<String>,
+}
+
+// The cells of a level besides its price and side
+struct LevelCells {
+    // Quantity, cumulative quantity and order count as shown
+    quantities: Vec<String>,
+    bar: String,
+}
+
+impl LadderFormat {
+    fn level_cells(
+        &self,
+        levels: &[BookLevel],
+        with_orders: bool,
+        max_qty: u64,
+    ) -> Vec<LevelCells> {
+        let mut cumulative_qty = 0;
+        levels
+            .iter()
+            .map(|level| {
+                cumulative_qty += level.qty.value();
+                let mut quantities = vec![level.qty.to_string()];
+                if self.cumulative {
+                    quantities.push(cumulative_qty.to_string());
+                }
+                if with_orders {
+                    quantities.push(
+                        level
+                            .order_count
+                            .map_or_else(String::new, |count| count.to_string()),
+                    );
+                }
+                let bar_qty = if self.cumulative {
+                    cumulative_qty
+                } else {
+                    level.qty.value()
+                };
+                let bar_len =
+                    (bar_qty as u128 * BAR_WIDTH as u128).div_ceil(max_qty.max(1) as u128);
+                LevelCells {
+                    quantities,
+                    bar: "#".repeat(bar_len as usize),
+                }
+            })
+            .collect()
+    }
+
+    fn quantity_names(&self, with_orders: bool) -> Vec<&'static str> {
+        let mut names = vec!["qty"];
+        if self.cumulative {
+            names.push("cum_qty");
+        }
+        if with_orders {
+            names.push("orders");
+        }
+        names
+    }
+
+    // The header and the rows, the worst of the shown asks at the top. Bars are aligned
+    // towards the prices whatever the alignment of the other columns.
+    fn table(
+        &self,
+        bids: &[BookLevel],
+        asks: &[BookLevel],
+        with_orders: bool,
+    ) -> (Vec<String>, Vec<Option<Alignment>>, Vec<Row>) {
+        let max_qty = [bids, asks]
+            .iter()
+            .filter_map(|levels| match self.cumulative {
+                true => Some(levels.iter().map(|level| level.qty.value()).sum()),
+                false => levels.iter().map(|level| level.qty.value()).max(),
+            })
+            .max()
+            .unwrap_or(0);
+        let price = |level: &BookLevel| format!("{:.2}", level.price);
+        let names = self.quantity_names(with_orders);
+        let ask_cells = self.level_cells(asks, with_orders, max_qty);
+        let bid_cells = self.level_cells(bids, with_orders, max_qty);
+        let mut header = Vec::new();
+        let mut alignments = Vec::new();
+        let mut rows = Vec::new();
+        match self.layout {
+            LadderLayout::Rows => {
+                header.extend(["side", "price"].map(String::from));
+                header.extend(names.iter().map(|name| name.to_string()));
+                alignments.resize(header.len(), None);
+                if self.bars {
+                    header.push(String::new());
+                    alignments.push(Some(Alignment::Left));
+                }
+                for (side, name, levels, cells) in [
+                    (Side::Ask, "ask", asks, &ask_cells),
+                    (Side::Bid, "bid", bids, &bid_cells),
+                ] {
+                    let side_rows = levels.iter().zip(cells).map(|(level, cells)| {
+                        let mut row = vec![name.to_string(), price(level)];
+                        row.extend(cells.quantities.iter().cloned());
+                        if self.bars {
+                            row.push(cells.bar.clone());
+                        }
+                        Row { side, cells: row }
+                    });
+                    match side {
+                        Side::Ask => rows.extend(side_rows.rev()),
+                        Side::Bid => rows.extend(side_rows),
+                    }
+                }
+            }
+            LadderLayout::Columns => {
+                let side_width = names.len() + self.bars as usize;
+                if self.bars {
+                    header.push(String::new());
+                    alignments.push(Some(Alignment::Right));
+                }
+                header.extend(names.iter().rev().map(|name| format!("bid_{}", name)));
+                header.push("price".to_string());
+                header.extend(names.iter().map(|name| format!("ask_{}", name)));
+                alignments.resize(header.len(), None);
+                if self.bars {
+                    header.push(String::new());
+                    alignments.push(Some(Alignment::Left));
+                }
+                for (level, cells) in asks.iter().zip(&ask_cells).rev() {
+                    let mut row = vec![String::new(); side_width];
+                    row.push(price(level));
+                    row.extend(cells.quantities.iter().cloned());
+                    if self.bars {
+                        row.push(cells.bar.clone());
+                    }
+                    rows.push(Row {
+                        side: Side::Ask,
+                        cells: row,
+                    });
+                }
+                for (level, cells) in bids.iter().zip(&bid_cells) {
+                    let mut row = Vec::new();
+                    if self.bars {
+                        row.push(cells.bar.clone());
+                    }
+                    row.extend(cells.quantities.iter().rev().cloned());
+                    row.push(price(level));
+                    row.resize(header.len(), String::new());
+                    rows.push(Row {
+                        side: Side::Bid,
+                        cells: row,
+                    });
+                }
+            }
+        }
+        (header, alignments, rows)
+    }
+
+    pub fn write<W: Write>(&self, writer: &mut W, book: &OrderBook) -> io::Result<()> {
+        let bids = book.top_levels(Side::Bid, self.depth);
+        let asks = book.top_levels(Side::Ask, self.depth);
+        self.write_levels(
+            writer,
+            (book.security_id, book.seq_no, book.timestamp),
+            &bids,
+            &asks,
+        )
+    }
+
+    // The same ladder from a copy of a book, e.g. one shared with another thread
+    pub fn write_state<W: Write>(&self, writer: &mut W, state: &OrderBookState) -> io::Result<()> {
+        let bids = &state.bids[..self.depth.min(state.bids.len())];
+        let asks = &state.asks[..self.depth.min(state.asks.len())];
+        self.write_levels(
+            writer,
+            (state.security_id, state.seq_no, state.timestamp),
+            bids,
+            asks,
+        )
+    }
+
+    // The book is given by its security_id, seq_no and timestamp
+    fn write_levels<W: Write>(
+        &self,
+        writer: &mut W,
+        (security_id, seq_no, timestamp): (u64, u64, u64),
+        bids: &[BookLevel],
+        asks: &[BookLevel],
+    ) -> io::Result<()> {
+        let with_orders = bids
+            .iter()
+            .chain(asks.iter())
+            .any(|level| level.order_count.is_some());
+        let (header, alignments, rows) = self.table(bids, asks, with_orders);
+
+        let widths: Vec<usize> = (0..header.len())
+            .map(|column| {
+                rows.iter()
+                    .map(|row| row.cells[column].len())
+                    .chain([header[column].len()])
+                    .max()
+                    .unwrap_or(0)
+            })
+            .collect();
+        let format_row = |cells: &mut dyn Iterator<Item = &str>| -> String {
+            cells
+                .zip(&widths)
+                .zip(&alignments)
+                .map(
+                    |((cell, &width), alignment)| match alignment.unwrap_or(self.alignment) {
+                        Alignment::Left => format!("{:<width$}", cell),
+                        Alignment::Right => format!("{:>width$}", cell),
+                    },
+                )
+                .collect::<Vec<_>>()
+                .join("  ")
+                .trim_end()
+                .to_string()
+        };
+
+        writeln!(
+            writer,
+            "security_id: {} seq_no: {} timestamp: {}",
+            security_id, seq_no, timestamp
+        )?;
+        writeln!(
+            writer,
+            "  {}",
+            format_row(&mut header.iter().map(String::as_str))
+        )?;
+        for row in &rows {
+            let line = format_row(&mut row.cells.iter().map(String::as_str));
+            if self.color {
+                let color = match row.side {
+                    Side::Bid => BID_COLOR,
+                    Side::Ask => ASK_COLOR,
+                };
+                writeln!(writer, "  {}{}{}", color, line, RESET_COLOR)?;
+            } else {
+                writeln!(writer, "  {}", line)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parsing::order_book_snapshot::test_support::create_test_snapshot;
+
+    fn render(format: LadderFormat) -> String {
+        let book = OrderBook::new(&create_test_snapshot(1001, 100)).unwrap();
+        let mut written = Vec::new();
+        format.write(&mut written, &book).unwrap();
+        String::from_utf8(written).unwrap()
+    }
+
+    #[test]
+    fn test_ladder_depth_and_cumulative() {
+        let format = LadderFormat {
+            depth: 2,
+            cumulative: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            render(format),
+            "security_id: 1001 seq_no: 100 timestamp: 1627846265\n\
+             \x20 side   price  qty  cum_qty\n\
+             \x20  ask  102.00   25       40\n\
+             \x20  ask  101.00   15       15\n\
+             \x20  bid  100.00   10       10\n\
+             \x20  bid   99.00   20       30\n"
+        );
+    }
+
+    #[test]
+    fn test_ladder_left_aligned() {
+        let format = LadderFormat {
+            depth: 1,
+            alignment: Alignment::Left,
+            ..Default::default()
+        };
+        assert_eq!(
+            render(format),
+            "security_id: 1001 seq_no: 100 timestamp: 1627846265\n\
+             \x20 side  price   qty\n\
+             \x20 ask   101.00  15\n\
+             \x20 bid   100.00  10\n"
+        );
+    }
+
+    #[test]
+    fn test_ladder_columns_with_bars() {
+        let format = LadderFormat {
+            depth: 2,
+            layout: LadderLayout::Columns,
+            bars: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            render(format),
+            "security_id: 1001 seq_no: 100 timestamp: 1627846265\n\
+             \x20                   bid_qty   price  ask_qty\n\
+             \x20                            102.00       25  ####################\n\
+             \x20                            101.00       15  ############\n\
+             \x20         ########       10  100.00\n\
+             \x20 ################       20   99.00\n"
+        );
+    }
+
+    #[test]
+    fn test_ladder_colors() {
+        let format = LadderFormat {
+            depth: 1,
+            color: true,
+            ..Default::default()
+        };
+        let rendered = render(format);
+        assert!(rendered.contains(&format!(
+            "  {} ask  101.00   15{}\n",
+            ASK_COLOR, RESET_COLOR
+        )));
+        assert!(rendered.contains(&format!(
+            "  {} bid  100.00   10{}\n",
+            BID_COLOR, RESET_COLOR
+        )));
+    }
+}
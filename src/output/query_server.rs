@@ -0,0 +1,197 @@
+use std::collections::HashMap;
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::{Arc, RwLock};
+use std::thread;
+
+use crate::order_book::level_ttl::ExpiredLevel;
+use crate::order_book::listener::BookListener;
+use crate::order_book::order_book::{OrderBook, OrderBookState};
+use crate::output::ladder::LadderFormat;
+
+// Copies of the books kept up to date by the replay for readers on other threads. Every
+// applied record copies the levels of its book, the price of reading a consistent state
+// without holding up the replay.
+#[derive(Debug, Clone, Default)]
+pub struct SharedBooks {
+    books: Arc<RwLock<HashMap<u64, OrderBookState>>>,
+}
+
+impl SharedBooks {
+    pub fn get(&self, security_id: u64) -> Option<OrderBookState> {
+        self.books
+            .read()
+            .unwrap_or_else(|e| e.into_inner())
+            .get(&security_id)
+            .cloned()
+    }
+
+    pub fn security_ids(&self) -> Vec<u64> {
+        let mut security_ids: Vec<u64> = self
+            .books
+            .read()
+            .unwrap_or_else(|e| e.into_inner())
+            .keys()
+            .copied()
+            .collect();
+        security_ids.sort_unstable();
+        security_ids
+    }
+
+    fn publish(&self, book: &OrderBook) {
+        let state = book.snapshot_state();
+        self.books
+            .write()
+            .unwrap_or_else(|e| e.into_inner())
+            .insert(book.security_id, state);
+    }
+}
+
+impl BookListener for SharedBooks {
+    fn on_snapshot_applied(&mut self, book: &OrderBook) {
+        self.publish(book);
+    }
+
+    fn on_update_applied(&mut self, book: &OrderBook) {
+        self.publish(book);
+    }
+
+    fn on_levels_expired(&mut self, book: &OrderBook, _expired: &[ExpiredLevel]) {
+        self.publish(book);
+    }
+
+    fn on_book_evicted(&mut self, book: &OrderBook) {
+        self.books
+            .write()
+            .unwrap_or_else(|e| e.into_inner())
+            .remove(&book.security_id);
+    }
+}
+
+// Answers requests of one line each, every answer ending with an empty line:
+//   BOOK <security_id> [depth]  the ladder of the book, of the default depth if not given
+//   SECURITIES                  the security_ids of the books, one per line
+//   QUIT                        closes the connection
+// Failed requests are answered with a line starting with ERR.
+pub fn answer(request: &str, books: &SharedBooks, format: LadderFormat) -> String {
+    let mut words = request.split_whitespace();
+    let command = words.next().unwrap_or_default().to_ascii_uppercase();
+    let arguments: Vec<&str> = words.collect();
+    let mut answer = Vec::new();
+    match (command.as_str(), arguments.as_slice()) {
+        ("BOOK", [security_id, depth @ ..]) if depth.len() <= 1 => {
+            let Ok(security_id) = security_id.parse::<u64>() else {
+                return format!("ERR Invalid security_id: {}\n\n", security_id);
+            };
+            let depth = match depth.first().map(|depth| depth.parse::<usize>()) {
+                Some(Ok(depth)) => depth,
+                Some(Err(_)) => return format!("ERR Invalid depth: {}\n\n", depth[0]),
+                None => format.depth,
+            };
+            let Some(state) = books.get(security_id) else {
+                return format!("ERR No book for security_id {}\n\n", security_id);
+            };
+            let format = LadderFormat { depth, ..format };
+            // Writing to a Vec doesn't fail
+            let _ = format.write_state(&mut answer, &state);
+        }
+        ("SECURITIES", []) => {
+            for security_id in books.security_ids() {
+                answer.extend_from_slice(format!("{}\n", security_id).as_bytes());
+            }
+        }
+        _ => {
+            return format!(
+                "ERR Unknown request, expected BOOK <security_id> [depth], SECURITIES or QUIT: {}\n\n",
+                request.trim()
+            );
+        }
+    }
+    answer.push(b'\n');
+    String::from_utf8_lossy(&answer).into_owned()
+}
+
+fn serve_connection(
+    stream: TcpStream,
+    books: &SharedBooks,
+    format: LadderFormat,
+) -> io::Result<()> {
+    let mut writer = stream.try_clone()?;
+    for line in BufReader::new(stream).lines() {
+        let line = line?;
+        if line.trim().eq_ignore_ascii_case("QUIT") {
+            break;
+        }
+        if line.trim().is_empty() {
+            continue;
+        }
+        writer.write_all(answer(&line, books, format).as_bytes())?;
+    }
+    Ok(())
+}
+
+// Answers the requests of each connection on a thread of its own, see answer, without
+// colors whatever the format says. Returns once the listener fails.
+pub fn serve(listener: TcpListener, books: SharedBooks, format: LadderFormat) -> io::Result<()> {
+    let format = LadderFormat {
+        color: false,
+        ..format
+    };
+    for stream in listener.incoming() {
+        let stream = stream?;
+        let books = books.clone();
+        thread::spawn(move || {
+            // A client going away only ends its connection
+            let _ = serve_connection(stream, &books, format);
+        });
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parsing::order_book_snapshot::test_support::create_test_snapshot;
+    use std::io::Read;
+
+    fn create_test_books() -> SharedBooks {
+        let book = OrderBook::new(&create_test_snapshot(1001, 100)).unwrap();
+        let mut books = SharedBooks::default();
+        books.on_snapshot_applied(&book);
+        books
+    }
+
+    #[test]
+    fn test_answer() {
+        let books = create_test_books();
+        let format = LadderFormat::default();
+
+        let ladder = answer("book 1001 1", &books, format);
+        assert_eq!(
+            ladder,
+            "security_id: 1001 seq_no: 100 timestamp: 1627846265\n\
+             \x20 side   price  qty\n\
+             \x20  ask  101.00   15\n\
+             \x20  bid  100.00   10\n\n"
+        );
+        assert_eq!(answer("SECURITIES", &books, format), "1001\n\n");
+        assert!(answer("BOOK 1002", &books, format).starts_with("ERR No book"));
+        assert!(answer("BOOK x", &books, format).starts_with("ERR Invalid security_id"));
+        assert!(answer("BOOK 1001 x", &books, format).starts_with("ERR Invalid depth"));
+        assert!(answer("TRADES 1001", &books, format).starts_with("ERR Unknown request"));
+    }
+
+    #[test]
+    fn test_serve() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let books = create_test_books();
+        thread::spawn(move || serve(listener, books, LadderFormat::default()));
+
+        let mut stream = TcpStream::connect(address).unwrap();
+        stream.write_all(b"SECURITIES\nQUIT\n").unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        assert_eq!(response, "1001\n\n");
+    }
+}
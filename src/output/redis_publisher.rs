@@ -0,0 +1,188 @@
+use num_traits::ToPrimitive;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::TcpStream;
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+use crate::order_book::level_ttl::ExpiredLevel;
+use crate::order_book::listener::BookListener;
+use crate::order_book::order_book::{BookLevel, OrderBook, Side};
+
+pub const DEFAULT_CHANNEL_PREFIX: &str = "top_of_book.";
+
+#[derive(Serialize)]
+struct LevelJson {
+    price: f64,
+    qty: u64,
+}
+
+#[derive(Serialize)]
+struct TopOfBookJson {
+    security_id: u64,
+    seq_no: u64,
+    timestamp: u64,
+    bid: Option<LevelJson>,
+    ask: Option<LevelJson>,
+}
+
+fn level_json(level: &BookLevel) -> LevelJson {
+    LevelJson {
+        price: level.price.value().to_f64().unwrap_or(0.0),
+        qty: level.qty.value(),
+    }
+}
+
+// Publishes every change of the best bid or ask of a book to the Redis channel of its
+// security, e.g. top_of_book.1001, as JSON such as {"security_id":1001,"seq_no":100,
+// "timestamp":1627846265,"bid":{"price":100.0,"qty":10},"ask":null}. The PUBLISH commands
+// are sent without waiting for their replies, which are read on a thread of their own.
+// Publishing stops at the first error, see take_error.
+pub struct RedisPublisher<W: Write> {
+    writer: W,
+    channel_prefix: String,
+    // Best bid and ask last published for each security
+    last_tops: HashMap<u64, (Option<BookLevel>, Option<BookLevel>)>,
+    error: Option<io::Error>,
+    // The first error reply of the server
+    server_error: Arc<Mutex<Option<String>>>,
+}
+
+impl RedisPublisher<TcpStream> {
+    pub fn connect(address: &str) -> io::Result<Self> {
+        let stream = TcpStream::connect(address)?;
+        let replies = BufReader::new(stream.try_clone()?);
+        let publisher = Self::new(stream);
+        let server_error = publisher.server_error.clone();
+        thread::spawn(move || {
+            for reply in replies.lines() {
+                let Ok(reply) = reply else {
+                    break;
+                };
+                if let Some(message) = reply.strip_prefix('-') {
+                    server_error
+                        .lock()
+                        .unwrap_or_else(|e| e.into_inner())
+                        .get_or_insert_with(|| message.to_string());
+                }
+            }
+        });
+        Ok(publisher)
+    }
+}
+
+impl<W: Write> RedisPublisher<W> {
+    pub fn new(writer: W) -> Self {
+        Self {
+            writer,
+            channel_prefix: DEFAULT_CHANNEL_PREFIX.to_string(),
+            last_tops: HashMap::new(),
+            error: None,
+            server_error: Arc::new(Mutex::new(None)),
+        }
+    }
+
+    // Channels are named by the prefix followed by the security_id
+    pub fn with_channel_prefix(mut self, channel_prefix: &str) -> Self {
+        self.channel_prefix = channel_prefix.to_string();
+        self
+    }
+
+    // The error that stopped the publishing, if any
+    pub fn take_error(&mut self) -> Option<io::Error> {
+        self.error.take().or_else(|| {
+            self.server_error
+                .lock()
+                .unwrap_or_else(|e| e.into_inner())
+                .take()
+                .map(|message| io::Error::other(format!("Redis error: {}", message)))
+        })
+    }
+
+    fn publish(&mut self, channel: &str, message: &str) -> io::Result<()> {
+        let mut command = Vec::new();
+        write!(command, "*3\r\n$7\r\nPUBLISH\r\n")?;
+        for argument in [channel, message] {
+            write!(command, "${}\r\n{}\r\n", argument.len(), argument)?;
+        }
+        self.writer.write_all(&command)?;
+        self.writer.flush()
+    }
+
+    fn publish_top(&mut self, book: &OrderBook) {
+        if self.error.is_some() {
+            return;
+        }
+        let bid = book.top_levels(Side::Bid, 1).first().copied();
+        let ask = book.top_levels(Side::Ask, 1).first().copied();
+        if self.last_tops.get(&book.security_id) == Some(&(bid, ask)) {
+            return;
+        }
+        self.last_tops.insert(book.security_id, (bid, ask));
+        let top = TopOfBookJson {
+            security_id: book.security_id,
+            seq_no: book.seq_no,
+            timestamp: book.timestamp,
+            bid: bid.as_ref().map(level_json),
+            ask: ask.as_ref().map(level_json),
+        };
+        let channel = format!("{}{}", self.channel_prefix, book.security_id);
+        let result = serde_json::to_string(&top)
+            .map_err(io::Error::other)
+            .and_then(|message| self.publish(&channel, &message));
+        if let Err(e) = result {
+            self.error = Some(e);
+        }
+    }
+}
+
+impl<W: Write> BookListener for RedisPublisher<W> {
+    fn on_snapshot_applied(&mut self, book: &OrderBook) {
+        self.publish_top(book);
+    }
+
+    fn on_update_applied(&mut self, book: &OrderBook) {
+        self.publish_top(book);
+    }
+
+    fn on_levels_expired(&mut self, book: &OrderBook, _expired: &[ExpiredLevel]) {
+        self.publish_top(book);
+    }
+
+    fn on_book_evicted(&mut self, book: &OrderBook) {
+        self.last_tops.remove(&book.security_id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parsing::order_book_snapshot::test_support::create_test_snapshot;
+
+    fn create_test_book(seq_no: u64, best_ask: f64) -> OrderBook {
+        let mut snapshot = create_test_snapshot(1001, seq_no);
+        snapshot.asks[0].price = best_ask;
+        OrderBook::new(&snapshot).unwrap()
+    }
+
+    #[test]
+    fn test_publish_top_changes() {
+        let mut publisher = RedisPublisher::new(Vec::new()).with_channel_prefix("top.");
+        publisher.on_snapshot_applied(&create_test_book(100, 101.0));
+        // The top didn't change
+        publisher.on_snapshot_applied(&create_test_book(101, 101.0));
+        publisher.on_snapshot_applied(&create_test_book(102, 100.5));
+        let message = r#"{"security_id":1001,"seq_no":100,"timestamp":1627846265,"bid":{"price":100.0,"qty":10},"ask":{"price":101.0,"qty":15}}"#;
+        let expected = format!(
+            "*3\r\n$7\r\nPUBLISH\r\n$8\r\ntop.1001\r\n${}\r\n{}\r\n",
+            message.len(),
+            message
+        );
+        let commands = String::from_utf8(publisher.writer.clone()).unwrap();
+        assert!(commands.starts_with(&expected));
+        assert_eq!(commands.matches("PUBLISH").count(), 2);
+        assert!(commands.contains(r#""seq_no":102"#));
+        assert!(publisher.take_error().is_none());
+    }
+}
@@ -0,0 +1,222 @@
+use serde_json::{Map, Value};
+use std::io::{self, Write};
+
+use crate::order_book::errors::Errors;
+use crate::order_book::manager::ManagerOutcome;
+
+// Why a record was not applied when it arrived
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RejectReason {
+    InvalidPrice,
+    InvalidQuantity,
+    PriceDeviation,
+    TimestampRegression,
+    SecurityIdMismatch,
+    PendingOverflow,
+    // Already covered by the book
+    OldSeq,
+    // Buffered until the missing seq_nos arrive, it may still be applied
+    Gap,
+    // Buffered until the next snapshot of a resynced book
+    AwaitingSnapshot,
+    // No snapshot of the security was applied yet
+    NoBook,
+}
+
+// Whether a record that was not applied when it arrived is gone for good
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RecordStatus {
+    Rejected,
+    // Kept as a pending update, applied once its gap closes unless it is dropped later
+    Buffered,
+}
+
+impl RecordStatus {
+    // None for an applied record
+    pub fn of(outcome: &ManagerOutcome) -> Option<Self> {
+        match outcome {
+            ManagerOutcome::Applied => None,
+            ManagerOutcome::Buffered(_) => Some(RecordStatus::Buffered),
+            _ => Some(RecordStatus::Rejected),
+        }
+    }
+}
+
+impl RejectReason {
+    // None for an applied record. Buffered records have the reason they wait for.
+    pub fn of(outcome: &ManagerOutcome) -> Option<Self> {
+        let reason = match outcome {
+            ManagerOutcome::Applied => return None,
+            ManagerOutcome::Buffered(gap_info) if gap_info.awaiting_snapshot => {
+                RejectReason::AwaitingSnapshot
+            }
+            ManagerOutcome::Buffered(_) => RejectReason::Gap,
+            ManagerOutcome::IgnoredOld => RejectReason::OldSeq,
+            ManagerOutcome::IgnoredUnknownSecurity => RejectReason::NoBook,
+            ManagerOutcome::Rejected(e) => match e {
+                Errors::InvalidPrice(..) => RejectReason::InvalidPrice,
+                Errors::InvalidQuantity(..) => RejectReason::InvalidQuantity,
+                Errors::PriceDeviation(..) => RejectReason::PriceDeviation,
+                Errors::TimestampRegression(..) => RejectReason::TimestampRegression,
+                Errors::SecurityIdMismatch(_) => RejectReason::SecurityIdMismatch,
+                Errors::PendingOverflow(_) => RejectReason::PendingOverflow,
+                Errors::OldSequenceNumber(_) => RejectReason::OldSeq,
+                Errors::SequenceNumberGap(_) => RejectReason::Gap,
+                Errors::AwaitingSnapshot(_) => RejectReason::AwaitingSnapshot,
+                Errors::OrderBookNotFound(_) => RejectReason::NoBook,
+            },
+        };
+        Some(reason)
+    }
+}
+
+// A record that was not applied, with the bytes of its binary layout
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RejectedRecord<'a> {
+    pub reason: RejectReason,
+    pub status: RecordStatus,
+    pub source: &'a str,
+    pub security_id: u64,
+    pub seq_no: u64,
+    // Offset of the record in the decompressed source, None when it isn't known
+    pub offset: Option<u64>,
+    pub raw: &'a [u8],
+}
+
+impl RejectedRecord<'_> {
+    // The line of the record in the log, the reason code is the name of its variant
+    fn to_json(&self) -> Value {
+        let mut object = Map::new();
+        object.insert("reason".to_string(), format!("{:?}", self.reason).into());
+        object.insert("status".to_string(), format!("{:?}", self.status).into());
+        object.insert("source".to_string(), self.source.into());
+        object.insert("security_id".to_string(), self.security_id.into());
+        object.insert("seq_no".to_string(), self.seq_no.into());
+        if let Some(offset) = self.offset {
+            object.insert("offset".to_string(), offset.into());
+        }
+        object.insert("length".to_string(), self.raw.len().into());
+        let raw: String = self
+            .raw
+            .iter()
+            .map(|byte| format!("{:02x}", byte))
+            .collect();
+        object.insert("raw".to_string(), raw.into());
+        Value::Object(object)
+    }
+}
+
+// Writes every record that was not applied as a JSON object per line, with a reason code
+// and the record as hex, for postmortems of the quality of a feed. The first write error
+// is kept and returned by flush, the records after it are lost.
+pub struct RejectLog<W: Write> {
+    writer: W,
+    error: Option<io::Error>,
+    written: usize,
+    buffered: usize,
+}
+
+impl<W: Write> RejectLog<W> {
+    pub fn new(writer: W) -> Self {
+        Self {
+            writer,
+            error: None,
+            written: 0,
+            buffered: 0,
+        }
+    }
+
+    pub fn write(&mut self, record: &RejectedRecord) {
+        if self.error.is_some() {
+            return;
+        }
+        let written = serde_json::to_writer(&mut self.writer, &record.to_json())
+            .map_err(io::Error::from)
+            .and_then(|_| self.writer.write_all(b"\n"));
+        match written {
+            Ok(()) => {
+                self.written += 1;
+                if record.status == RecordStatus::Buffered {
+                    self.buffered += 1;
+                }
+            }
+            Err(e) => self.error = Some(e),
+        }
+    }
+
+    pub fn written(&self) -> usize {
+        self.written
+    }
+
+    // The records written as Buffered, the others were rejected
+    pub fn buffered(&self) -> usize {
+        self.buffered
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        if let Some(e) = self.error.take() {
+            return Err(e);
+        }
+        self.writer.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::order_book::errors::UpdateMessageInfo;
+    use crate::order_book::manager::GapInfo;
+
+    #[test]
+    fn test_reject_log() {
+        let info = UpdateMessageInfo::new(1001, 102);
+        assert_eq!(RejectReason::of(&ManagerOutcome::Applied), None);
+        assert_eq!(RecordStatus::of(&ManagerOutcome::Applied), None);
+        let buffered = ManagerOutcome::Buffered(GapInfo {
+            expected_seq_no: 101,
+            received_seq_no: 102,
+            awaiting_snapshot: false,
+        });
+        assert_eq!(RejectReason::of(&buffered), Some(RejectReason::Gap));
+        assert_eq!(RecordStatus::of(&buffered), Some(RecordStatus::Buffered));
+        assert_eq!(
+            RecordStatus::of(&ManagerOutcome::IgnoredOld),
+            Some(RecordStatus::Rejected)
+        );
+        let reason = RejectReason::of(&ManagerOutcome::Rejected(Errors::InvalidPrice(
+            info,
+            "Price 0".to_string(),
+        )));
+        assert_eq!(reason, Some(RejectReason::InvalidPrice));
+
+        let mut log = RejectLog::new(Vec::new());
+        log.write(&RejectedRecord {
+            reason: RejectReason::InvalidPrice,
+            status: RecordStatus::Rejected,
+            source: "updates.bin",
+            security_id: 1001,
+            seq_no: 102,
+            offset: Some(57),
+            raw: &[0x01, 0xab],
+        });
+        log.write(&RejectedRecord {
+            reason: RejectReason::Gap,
+            status: RecordStatus::Buffered,
+            source: "updates.bin",
+            security_id: 1002,
+            seq_no: 7,
+            offset: None,
+            raw: &[],
+        });
+        log.flush().unwrap();
+        assert_eq!(log.written(), 2);
+        assert_eq!(log.buffered(), 1);
+        assert_eq!(
+            String::from_utf8(log.writer).unwrap(),
+            "{\"reason\":\"InvalidPrice\",\"status\":\"Rejected\",\"source\":\"updates.bin\",\"security_id\":1001,\
+             \"seq_no\":102,\"offset\":57,\"length\":2,\"raw\":\"01ab\"}\n\
+             {\"reason\":\"Gap\",\"status\":\"Buffered\",\"source\":\"updates.bin\",\"security_id\":1002,\
+             \"seq_no\":7,\"length\":0,\"raw\":\"\"}\n"
+        );
+    }
+}
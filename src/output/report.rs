@@ -0,0 +1,370 @@
+use num_traits::ToPrimitive;
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::io::{self, Write};
+
+use crate::order_book::buffered_order_book::GapStats;
+use crate::order_book::errors::Errors;
+use crate::order_book::listener::BookListener;
+use crate::order_book::manager::GapInfo;
+use crate::order_book::order_book::OrderBook;
+
+// Points of a spread chart, longer series are sampled down
+const MAX_CHART_POINTS: usize = 800;
+const CHART_WIDTH: f64 = 800.0;
+const CHART_HEIGHT: f64 = 160.0;
+const TIMELINE_HEIGHT: f64 = 24.0;
+
+// Time a security spent waiting for missing seq_nos
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct GapSpan {
+    pub start: u64,
+    // Still open at the end of the replay
+    pub end: Option<u64>,
+    pub from_seq_no: u64,
+    pub to_seq_no: u64,
+}
+
+#[derive(Debug, Default)]
+struct SecurityReport {
+    snapshots: usize,
+    updates: usize,
+    buffered: usize,
+    rejected: usize,
+    gaps: Vec<GapSpan>,
+    open_gap: Option<GapSpan>,
+    // From the book at the end of the replay, see set_gap_stats
+    gap_stats: Option<GapStats>,
+    // (timestamp, spread) after every applied record with both sides
+    spreads: Vec<(u64, f64)>,
+    first_timestamp: Option<u64>,
+    last_timestamp: u64,
+}
+
+impl SecurityReport {
+    fn on_applied(&mut self, book: &OrderBook) {
+        self.first_timestamp.get_or_insert(book.timestamp);
+        self.last_timestamp = book.timestamp;
+        if let (Some((bid, _)), Some((ask, _))) = (book.best_bid(), book.best_ask())
+            && let Some(spread) = (ask - bid).to_f64()
+        {
+            self.spreads.push((book.timestamp, spread));
+        }
+        if let Some(gap) = self.open_gap
+            && book.seq_no >= gap.to_seq_no
+        {
+            self.gaps.push(GapSpan {
+                end: Some(book.timestamp),
+                ..gap
+            });
+            self.open_gap = None;
+        }
+    }
+
+    fn all_gaps(&self) -> impl Iterator<Item = &GapSpan> {
+        self.gaps.iter().chain(self.open_gap.iter())
+    }
+}
+
+// Summary of a replay as a single HTML page for people who don't read the logs:
+// per-security counters, a timeline of the gaps and a chart of the spread.
+// Registered on a Manager as a BookListener.
+#[derive(Debug, Default)]
+pub struct ReplayReport {
+    title: String,
+    securities: BTreeMap<u64, SecurityReport>,
+}
+
+impl ReplayReport {
+    pub fn new(title: &str) -> Self {
+        Self {
+            title: title.to_string(),
+            securities: BTreeMap::new(),
+        }
+    }
+
+    pub fn gaps(&self, security_id: u64) -> Vec<GapSpan> {
+        self.securities
+            .get(&security_id)
+            .map(|report| report.all_gaps().copied().collect())
+            .unwrap_or_default()
+    }
+
+    // Adds the largest gap and the recovered and dropped pending updates of the book to
+    // its row, e.g. from Manager::gap_stats once the replay is over
+    pub fn set_gap_stats(&mut self, gap_stats: GapStats) {
+        self.securities
+            .entry(gap_stats.security_id)
+            .or_default()
+            .gap_stats = Some(gap_stats);
+    }
+
+    pub fn write_html<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        let mut html = String::new();
+        let _ = self.render(&mut html);
+        writer.write_all(html.as_bytes())
+    }
+
+    fn render(&self, html: &mut String) -> std::fmt::Result {
+        let title = escape_html(&self.title);
+        writeln!(html, "<!DOCTYPE html>")?;
+        writeln!(html, "<html><head><meta charset=\"utf-8\">")?;
+        writeln!(html, "<title>{}</title>", title)?;
+        writeln!(
+            html,
+            "<style>body{{font-family:sans-serif;margin:2em}}\
+             table{{border-collapse:collapse}}\
+             td,th{{border:1px solid #ccc;padding:4px 8px;text-align:right}}\
+             th{{background:#f0f0f0}}svg{{display:block;margin:8px 0;background:#fafafa}}</style>"
+        )?;
+        writeln!(html, "</head><body>")?;
+        writeln!(html, "<h1>{}</h1>", title)?;
+
+        writeln!(html, "<h2>Securities</h2>")?;
+        writeln!(
+            html,
+            "<table><tr><th>Security</th><th>Snapshots</th><th>Updates</th><th>Buffered</th>\
+             <th>Gaps</th><th>Largest gap</th><th>Recovered</th><th>Dropped</th>\
+             <th>Rejected</th><th>Min spread</th><th>Mean spread</th><th>Max spread</th></tr>"
+        )?;
+        for (security_id, report) in &self.securities {
+            let spreads = report.spreads.iter().map(|(_, spread)| *spread);
+            let (min, max) = spreads
+                .clone()
+                .fold((f64::MAX, f64::MIN), |(min, max), spread| {
+                    (min.min(spread), max.max(spread))
+                });
+            let fmt_spread = |value: f64| match report.spreads.is_empty() {
+                true => "n/a".to_string(),
+                false => format!("{:.4}", value),
+            };
+            let mean = spreads.sum::<f64>() / report.spreads.len().max(1) as f64;
+            let fmt_gap_stat = |value: fn(&GapStats) -> u64| match &report.gap_stats {
+                Some(gap_stats) => value(gap_stats).to_string(),
+                None => "n/a".to_string(),
+            };
+            writeln!(
+                html,
+                "<tr><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td>\
+                 <td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td></tr>",
+                security_id,
+                report.snapshots,
+                report.updates,
+                report.buffered,
+                report.all_gaps().count(),
+                fmt_gap_stat(|gap_stats| gap_stats.largest_gap),
+                fmt_gap_stat(|gap_stats| gap_stats.recovered),
+                fmt_gap_stat(|gap_stats| gap_stats.dropped),
+                report.rejected,
+                fmt_spread(min),
+                fmt_spread(mean),
+                fmt_spread(max)
+            )?;
+        }
+        writeln!(html, "</table>")?;
+
+        for (security_id, report) in &self.securities {
+            writeln!(html, "<h2>Security {}</h2>", security_id)?;
+            let start = report.first_timestamp.unwrap_or(0);
+            let end = report.last_timestamp.max(start + 1);
+            writeln!(html, "<h3>Gaps</h3>")?;
+            render_gap_timeline(html, report, start, end)?;
+            writeln!(html, "<h3>Spread</h3>")?;
+            render_spread_chart(html, &report.spreads, start, end)?;
+        }
+        writeln!(html, "</body></html>")
+    }
+}
+
+fn x_of(timestamp: u64, start: u64, end: u64) -> f64 {
+    (timestamp.saturating_sub(start)) as f64 / (end - start) as f64 * CHART_WIDTH
+}
+
+fn render_gap_timeline(
+    html: &mut String,
+    report: &SecurityReport,
+    start: u64,
+    end: u64,
+) -> std::fmt::Result {
+    writeln!(
+        html,
+        "<svg width=\"{}\" height=\"{}\">",
+        CHART_WIDTH, TIMELINE_HEIGHT
+    )?;
+    for gap in report.all_gaps() {
+        let x = x_of(gap.start, start, end);
+        let width = (x_of(gap.end.unwrap_or(end), start, end) - x).max(1.0);
+        writeln!(
+            html,
+            "<rect x=\"{:.1}\" y=\"0\" width=\"{:.1}\" height=\"{}\" fill=\"#d9534f\">\
+             <title>seq_nos {} to {}{}</title></rect>",
+            x,
+            width,
+            TIMELINE_HEIGHT,
+            gap.from_seq_no,
+            gap.to_seq_no,
+            if gap.end.is_none() {
+                ", still open"
+            } else {
+                ""
+            }
+        )?;
+    }
+    writeln!(html, "</svg>")?;
+    writeln!(html, "<p>{} to {}</p>", start, end)
+}
+
+fn render_spread_chart(
+    html: &mut String,
+    spreads: &[(u64, f64)],
+    start: u64,
+    end: u64,
+) -> std::fmt::Result {
+    if spreads.is_empty() {
+        return writeln!(html, "<p>No book with both sides.</p>");
+    }
+    let step = spreads.len().div_ceil(MAX_CHART_POINTS);
+    let (min, max) = spreads
+        .iter()
+        .fold((f64::MAX, f64::MIN), |(min, max), (_, spread)| {
+            (min.min(*spread), max.max(*spread))
+        });
+    let range = if max > min { max - min } else { 1.0 };
+    let mut points = String::new();
+    for (timestamp, spread) in spreads.iter().step_by(step) {
+        let y = CHART_HEIGHT - (spread - min) / range * (CHART_HEIGHT - 10.0) - 5.0;
+        let _ = write!(points, "{:.1},{:.1} ", x_of(*timestamp, start, end), y);
+    }
+    writeln!(
+        html,
+        "<svg width=\"{}\" height=\"{}\"><polyline fill=\"none\" stroke=\"#337ab7\" \
+         points=\"{}\"/></svg>",
+        CHART_WIDTH,
+        CHART_HEIGHT,
+        points.trim_end()
+    )?;
+    writeln!(html, "<p>Spread from {:.4} to {:.4}</p>", min, max)
+}
+
+fn escape_html(value: &str) -> String {
+    value
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+impl BookListener for ReplayReport {
+    fn on_snapshot_applied(&mut self, book: &OrderBook) {
+        let report = self.securities.entry(book.security_id).or_default();
+        report.snapshots += 1;
+        report.on_applied(book);
+    }
+
+    fn on_update_applied(&mut self, book: &OrderBook) {
+        let report = self.securities.entry(book.security_id).or_default();
+        report.updates += 1;
+        report.on_applied(book);
+    }
+
+    fn on_gap_detected(&mut self, security_id: u64, gap_info: &GapInfo) {
+        let report = self.securities.entry(security_id).or_default();
+        report.buffered += 1;
+        let last_timestamp = report.last_timestamp;
+        let gap = report.open_gap.get_or_insert(GapSpan {
+            start: last_timestamp,
+            end: None,
+            from_seq_no: gap_info.expected_seq_no,
+            to_seq_no: gap_info.received_seq_no,
+        });
+        gap.to_seq_no = gap.to_seq_no.max(gap_info.received_seq_no);
+    }
+
+    fn on_error(&mut self, security_id: u64, _seq_no: u64, _error: &Errors) {
+        self.securities.entry(security_id).or_default().rejected += 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::batched_deque::batched_deque::BatchedDeque;
+    use crate::order_book::manager::Manager;
+    use crate::order_book::units::Qty;
+    use crate::parsing::order_book_snapshot::test_support::create_test_snapshot;
+    use crate::parsing::order_book_update::{Level as UpdateLevel, OrderBookUpdate, Side};
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    fn create_test_update(security_id: u64, seq_no: u64) -> OrderBookUpdate {
+        let deque = BatchedDeque::new(10);
+        let level = UpdateLevel {
+            side: Side::Bid,
+            price: 100.50,
+            qty: Qty(seq_no),
+            metadata: None,
+        };
+        OrderBookUpdate {
+            timestamp: 1627846265 + seq_no,
+            capture_timestamp: None,
+            seq_no,
+            security_id,
+            thinned_from: None,
+            updates: deque
+                .push_back_batch([Ok::<_, ()>(level)].into_iter())
+                .unwrap(),
+        }
+    }
+
+    #[test]
+    fn test_replay_report() {
+        let report = Rc::new(RefCell::new(ReplayReport::new("Replay of <capture>")));
+        let mut manager = Manager::default();
+        manager.add_listener(Box::new(report.clone()));
+
+        manager.apply_snapshot(&create_test_snapshot(1001, 100));
+        manager.apply_update(create_test_update(1001, 101));
+        // 102 and 103 arrive late
+        manager.apply_update(create_test_update(1001, 104));
+        manager.apply_update(create_test_update(1001, 105));
+        manager.apply_update(create_test_update(1001, 102));
+        manager.apply_update(create_test_update(1001, 103));
+        // Still open at the end
+        manager.apply_update(create_test_update(1001, 110));
+
+        for gap_stats in manager.gap_stats() {
+            report.borrow_mut().set_gap_stats(gap_stats);
+        }
+        let report = report.borrow();
+        assert_eq!(
+            report.gaps(1001),
+            vec![
+                GapSpan {
+                    start: 1627846265 + 101,
+                    end: Some(1627846265 + 105),
+                    from_seq_no: 102,
+                    to_seq_no: 105,
+                },
+                GapSpan {
+                    start: 1627846265 + 105,
+                    end: None,
+                    from_seq_no: 106,
+                    to_seq_no: 110,
+                },
+            ]
+        );
+
+        let mut html = Vec::new();
+        report.write_html(&mut html).unwrap();
+        let html = String::from_utf8(html).unwrap();
+        assert!(html.contains("<title>Replay of &lt;capture&gt;</title>"));
+        // Security, snapshots, updates, buffered, gaps, largest gap, recovered, dropped,
+        // rejected, spreads
+        assert!(html.contains(
+            "<tr><td>1001</td><td>1</td><td>5</td><td>3</td><td>2</td><td>4</td><td>2</td>\
+             <td>0</td><td>0</td><td>0.5000</td><td>0.5833</td><td>1.0000</td></tr>"
+        ));
+        assert!(html.contains("seq_nos 106 to 110, still open"));
+        assert!(html.contains("<polyline"));
+    }
+}
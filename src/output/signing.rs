@@ -0,0 +1,143 @@
+use ed25519_dalek::{Signature, Signer, SigningKey, Verifier, VerifyingKey};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+// Keys and signatures are stored as hex text, so that they can be copied around
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+fn from_hex<const N: usize>(text: &str) -> io::Result<[u8; N]> {
+    let text = text.trim();
+    let invalid = || {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("Expected {} hex encoded bytes", N),
+        )
+    };
+    if text.len() != N * 2 || !text.is_ascii() {
+        return Err(invalid());
+    }
+    let mut bytes = [0; N];
+    for (i, byte) in bytes.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(&text[i * 2..i * 2 + 2], 16).map_err(|_| invalid())?;
+    }
+    Ok(bytes)
+}
+
+fn set_readonly(path: &Path) -> io::Result<()> {
+    let mut permissions = fs::metadata(path)?.permissions();
+    permissions.set_readonly(true);
+    fs::set_permissions(path, permissions)
+}
+
+// Detached signature written next to a signed file
+pub fn signature_path(path: &Path) -> PathBuf {
+    let mut signature_path = path.as_os_str().to_owned();
+    signature_path.push(".sig");
+    PathBuf::from(signature_path)
+}
+
+pub fn generate_key(private_path: &Path, public_path: &Path) -> io::Result<()> {
+    let mut secret = [0; 32];
+    getrandom::fill(&mut secret).map_err(io::Error::other)?;
+    let key = SigningKey::from_bytes(&secret);
+    fs::write(private_path, to_hex(&key.to_bytes()))?;
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        fs::set_permissions(private_path, fs::Permissions::from_mode(0o400))?;
+    }
+    #[cfg(not(unix))]
+    set_readonly(private_path)?;
+    fs::write(public_path, to_hex(&key.verifying_key().to_bytes()))
+}
+
+pub fn read_signing_key(path: &Path) -> io::Result<SigningKey> {
+    Ok(SigningKey::from_bytes(&from_hex(&fs::read_to_string(
+        path,
+    )?)?))
+}
+
+pub fn read_verifying_key(path: &Path) -> io::Result<VerifyingKey> {
+    VerifyingKey::from_bytes(&from_hex(&fs::read_to_string(path)?)?)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+// Signs the file and makes it and its signature read-only
+pub fn sign_file(path: &Path, key: &SigningKey) -> io::Result<()> {
+    let signature = key.sign(&fs::read(path)?);
+    let signature_path = signature_path(path);
+    fs::write(&signature_path, to_hex(&signature.to_bytes()))?;
+    set_readonly(path)?;
+    set_readonly(&signature_path)
+}
+
+pub fn verify_file(path: &Path, key: &VerifyingKey) -> io::Result<()> {
+    let signature_path = signature_path(path);
+    let signature = fs::read_to_string(&signature_path)
+        .map_err(|e| io::Error::new(e.kind(), format!("{}: {}", signature_path.display(), e)))?;
+    let signature = Signature::from_bytes(&from_hex(&signature)?);
+    key.verify(&fs::read(path)?, &signature).map_err(|_| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!(
+                "Signature {} doesn't match {}",
+                signature_path.display(),
+                path.display()
+            ),
+        )
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+
+    fn remove_signed_file(path: &Path) {
+        let mut permissions = fs::metadata(path).unwrap().permissions();
+        #[allow(clippy::permissions_set_readonly_false)]
+        permissions.set_readonly(false);
+        fs::set_permissions(path, permissions).unwrap();
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_hex_round_trip() {
+        let bytes = [0x00, 0x7f, 0xab, 0xff];
+        assert_eq!(to_hex(&bytes), "007fabff");
+        assert_eq!(from_hex::<4>("007fabff\n").unwrap(), bytes);
+        assert!(from_hex::<4>("007fab").is_err());
+        assert!(from_hex::<4>("007fabzz").is_err());
+    }
+
+    #[test]
+    fn test_sign_and_verify_file() {
+        let dir = env::temp_dir().join(format!("order_book_signing_{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let private_path = dir.join("key");
+        let public_path = dir.join("key.pub");
+        let path = dir.join("snapshot.bin");
+        fs::write(&path, b"snapshot records").unwrap();
+
+        generate_key(&private_path, &public_path).unwrap();
+        let signing_key = read_signing_key(&private_path).unwrap();
+        let verifying_key = read_verifying_key(&public_path).unwrap();
+        sign_file(&path, &signing_key).unwrap();
+        assert!(fs::metadata(&path).unwrap().permissions().readonly());
+        verify_file(&path, &verifying_key).unwrap();
+
+        // Altered after signing
+        remove_signed_file(&path);
+        fs::write(&path, b"snapshot record5").unwrap();
+        let result = verify_file(&path, &verifying_key);
+        assert_eq!(result.unwrap_err().kind(), io::ErrorKind::InvalidData);
+
+        remove_signed_file(&signature_path(&path));
+        remove_signed_file(&private_path);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
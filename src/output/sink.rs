@@ -0,0 +1,436 @@
+use rust_decimal::Decimal;
+use std::fmt::Display;
+use std::io::{self, Write};
+
+use crate::analytics::book_metrics::BookMetrics;
+use crate::analytics::candles::Candle;
+use crate::analytics::latency::LatencySummary;
+use crate::analytics::midprice::MidPricePoint;
+use crate::analytics::order_flow::OrderFlowImbalance;
+use crate::analytics::pricing::FairValuePoint;
+use crate::analytics::spread_stats::SpreadSummary;
+use crate::analytics::trade_inference::Trade;
+use crate::order_book::book_diff::{BookDiff, LevelChange};
+use crate::order_book::buffered_order_book::GapStats;
+use crate::order_book::events::BookEvent;
+use crate::order_book::order_book::{OrderBook, Side};
+use crate::output::ladder::LadderFormat;
+
+#[derive(Debug, Clone, Copy)]
+pub enum OutputEvent<'a> {
+    Trade(&'a Trade),
+    Candle(&'a Candle),
+    Metrics(&'a BookMetrics),
+    LatencySummary(&'a LatencySummary),
+    Book(&'a BookEvent),
+    FairValue(&'a FairValuePoint<'a>),
+    Delta(&'a BookDiff),
+    MidPrice(&'a MidPricePoint),
+    SpreadSummary(&'a SpreadSummary),
+    OrderFlow(&'a OrderFlowImbalance),
+    GapStats(&'a GapStats),
+}
+
+impl OutputEvent<'_> {
+    fn kind(&self) -> &'static str {
+        match self {
+            OutputEvent::Trade(_) => "trade",
+            OutputEvent::Candle(_) => "candle",
+            OutputEvent::Metrics(_) => "metrics",
+            OutputEvent::LatencySummary(_) => "latency_summary",
+            OutputEvent::Book(_) => "book_event",
+            OutputEvent::FairValue(_) => "fair_value",
+            OutputEvent::Delta(_) => "delta",
+            OutputEvent::MidPrice(_) => "midprice",
+            OutputEvent::SpreadSummary(_) => "spread_summary",
+            OutputEvent::OrderFlow(_) => "order_flow",
+            OutputEvent::GapStats(_) => "gap_stats",
+        }
+    }
+
+    fn write_csv_header<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        match self {
+            OutputEvent::Trade(_) => {
+                writeln!(writer, "timestamp,seq_no,security_id,price,qty,aggressor")
+            }
+            OutputEvent::Candle(candle) => {
+                Candle::write_csv_header(writer, candle.spread.is_some())
+            }
+            OutputEvent::Metrics(_) => writeln!(
+                writer,
+                "security_id,levels,bid_vwap,ask_vwap,imbalance,microprice"
+            ),
+            OutputEvent::LatencySummary(_) => {
+                writeln!(writer, "security_id,count,min,p50,p90,p99,max")
+            }
+            OutputEvent::Book(_) => writeln!(
+                writer,
+                "record,security_id,seq_no,decision,reason,replayed,policy,dropped,state,pending"
+            ),
+            OutputEvent::FairValue(_) => {
+                writeln!(writer, "timestamp,seq_no,security_id,function,fair_value")
+            }
+            OutputEvent::Delta(_) => writeln!(
+                writer,
+                "security_id,from_seq_no,seq_no,timestamp,side,price,qty,change"
+            ),
+            OutputEvent::MidPrice(_) => writeln!(writer, "timestamp,security_id,mid,spread"),
+            OutputEvent::SpreadSummary(_) => writeln!(
+                writer,
+                "security_id,duration,twa_spread,min_spread,max_spread,one_sided_pct,crossed_pct"
+            ),
+            OutputEvent::OrderFlow(_) => {
+                writeln!(writer, "timestamp,seq_no,security_id,bid_flow,ask_flow,ofi")
+            }
+            OutputEvent::GapStats(_) => {
+                writeln!(writer, "security_id,gaps,largest_gap,recovered,dropped")
+            }
+        }
+    }
+
+    fn write_csv_row<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        let fmt_value = |value: Option<Decimal>| {
+            value
+                .map(|value| value.normalize().to_string())
+                .unwrap_or_default()
+        };
+        match self {
+            OutputEvent::Trade(trade) => writeln!(
+                writer,
+                "{},{},{},{},{},{:?}",
+                trade.timestamp,
+                trade.seq_no,
+                trade.security_id,
+                trade.price,
+                trade.qty,
+                trade.aggressor
+            ),
+            OutputEvent::Candle(candle) => candle.write_csv_row(writer),
+            OutputEvent::Metrics(metrics) => writeln!(
+                writer,
+                "{},{},{},{},{},{}",
+                metrics.security_id,
+                metrics.levels,
+                fmt_value(metrics.bid_vwap),
+                fmt_value(metrics.ask_vwap),
+                fmt_value(metrics.imbalance),
+                fmt_value(metrics.microprice)
+            ),
+            OutputEvent::LatencySummary(summary) => writeln!(
+                writer,
+                "{},{},{},{},{},{},{}",
+                summary.security_id,
+                summary.count,
+                summary.min,
+                summary.p50,
+                summary.p90,
+                summary.p99,
+                summary.max
+            ),
+            OutputEvent::Book(event) => {
+                let (policy, dropped) = match event.dropped {
+                    Some((policy, dropped)) => (format!("{:?}", policy), dropped.to_string()),
+                    None => (String::new(), String::new()),
+                };
+                writeln!(
+                    writer,
+                    "{:?},{},{},{},\"{}\",{},{},{},{:?},{}",
+                    event.record,
+                    event.security_id,
+                    event.seq_no,
+                    event.decision,
+                    event.reason.as_deref().unwrap_or("").replace('"', "\"\""),
+                    event.replayed,
+                    policy,
+                    dropped,
+                    event.state,
+                    event.pending
+                )
+            }
+            OutputEvent::FairValue(point) => writeln!(
+                writer,
+                "{},{},{},{},{}",
+                point.timestamp,
+                point.seq_no,
+                point.security_id,
+                point.function,
+                point.value.normalize()
+            ),
+            // A row per changed level, qty is 0 for removed ones
+            OutputEvent::Delta(diff) => diff.levels.iter().try_for_each(|level| {
+                let side = match level.side {
+                    Side::Bid => "bid",
+                    Side::Ask => "ask",
+                };
+                let change = match level.change {
+                    LevelChange::Added => "added",
+                    LevelChange::Removed => "removed",
+                    LevelChange::Changed => "changed",
+                };
+                writeln!(
+                    writer,
+                    "{},{},{},{},{},{},{},{}",
+                    diff.security_id,
+                    diff.from_seq_no,
+                    diff.to_seq_no,
+                    diff.timestamp,
+                    side,
+                    level.price,
+                    level.qty,
+                    change
+                )
+            }),
+            OutputEvent::MidPrice(point) => writeln!(
+                writer,
+                "{},{},{},{}",
+                point.timestamp, point.security_id, point.mid, point.spread
+            ),
+            OutputEvent::SpreadSummary(summary) => writeln!(
+                writer,
+                "{},{},{},{},{},{},{}",
+                summary.security_id,
+                summary.duration,
+                fmt_value(summary.twa_spread),
+                fmt_value(summary.min_spread),
+                fmt_value(summary.max_spread),
+                fmt_value(summary.one_sided_pct),
+                fmt_value(summary.crossed_pct)
+            ),
+            OutputEvent::OrderFlow(imbalance) => writeln!(
+                writer,
+                "{},{},{},{},{},{}",
+                imbalance.timestamp,
+                imbalance.seq_no,
+                imbalance.security_id,
+                imbalance.bid_flow,
+                imbalance.ask_flow,
+                imbalance.ofi()
+            ),
+            OutputEvent::GapStats(stats) => writeln!(
+                writer,
+                "{},{},{},{},{}",
+                stats.security_id, stats.gaps, stats.largest_gap, stats.recovered, stats.dropped
+            ),
+        }
+    }
+}
+
+impl Display for OutputEvent<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            OutputEvent::Trade(trade) => write!(f, "{}", trade),
+            OutputEvent::Candle(candle) => write!(f, "{:?}", candle),
+            OutputEvent::Metrics(metrics) => write!(f, "{}", metrics),
+            OutputEvent::LatencySummary(summary) => write!(f, "{}", summary),
+            OutputEvent::Book(event) => write!(f, "{}", event.to_json()),
+            OutputEvent::FairValue(point) => write!(f, "{}", point),
+            OutputEvent::Delta(diff) => write!(f, "{}", diff),
+            OutputEvent::MidPrice(point) => write!(f, "{}", point),
+            OutputEvent::SpreadSummary(summary) => write!(f, "{}", summary),
+            OutputEvent::OrderFlow(imbalance) => write!(f, "{}", imbalance),
+            OutputEvent::GapStats(stats) => write!(f, "{}", stats),
+        }
+    }
+}
+
+// Destination of everything the replay produces, so that exporters don't depend on
+// where their output goes
+pub trait OutputSink {
+    fn write_book_state(&mut self, book: &OrderBook) -> io::Result<()>;
+    fn write_event(&mut self, event: OutputEvent) -> io::Result<()>;
+    fn flush(&mut self) -> io::Result<()>;
+    fn close(&mut self) -> io::Result<()> {
+        self.flush()
+    }
+}
+
+// Human-readable output, one event per line, e.g. to stdout or a log file
+pub struct TextSink<W: Write> {
+    writer: W,
+    // Book states as price ladders instead of all their levels
+    ladder: Option<LadderFormat>,
+}
+
+impl<W: Write> TextSink<W> {
+    pub fn new(writer: W) -> Self {
+        Self {
+            writer,
+            ladder: None,
+        }
+    }
+
+    pub fn with_ladder(self, ladder: LadderFormat) -> Self {
+        Self {
+            ladder: Some(ladder),
+            ..self
+        }
+    }
+}
+
+impl TextSink<io::Stdout> {
+    pub fn stdout() -> Self {
+        Self::new(io::stdout())
+    }
+}
+
+impl<W: Write> OutputSink for TextSink<W> {
+    fn write_book_state(&mut self, book: &OrderBook) -> io::Result<()> {
+        match &self.ladder {
+            Some(ladder) => ladder.write(&mut self.writer, book),
+            None => write!(self.writer, "{}", book),
+        }
+    }
+
+    fn write_event(&mut self, event: OutputEvent) -> io::Result<()> {
+        let text = event.to_string();
+        if text.ends_with('\n') {
+            write!(self.writer, "{}", text)
+        } else {
+            writeln!(self.writer, "{}", text)
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+// CSV with a header for the first row. A sink holds a single kind of event, book states
+// are written as one row per level.
+pub struct CsvSink<W: Write> {
+    writer: W,
+    kind: Option<&'static str>,
+}
+
+impl<W: Write> CsvSink<W> {
+    pub fn new(writer: W) -> Self {
+        Self { writer, kind: None }
+    }
+
+    fn start(&mut self, kind: &'static str) -> io::Result<bool> {
+        match self.kind {
+            None => {
+                self.kind = Some(kind);
+                Ok(true)
+            }
+            Some(current) if current == kind => Ok(false),
+            Some(current) => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("Can't write {} rows to a CSV of {} rows", kind, current),
+            )),
+        }
+    }
+}
+
+impl<W: Write> OutputSink for CsvSink<W> {
+    fn write_book_state(&mut self, book: &OrderBook) -> io::Result<()> {
+        if self.start("book_state")? {
+            writeln!(self.writer, "security_id,seq_no,timestamp,side,price,qty")?;
+        }
+        // Best price first on both sides
+        let bids = book.bids.iter().rev().map(|level| ("bid", level));
+        let asks = book.asks.iter().map(|level| ("ask", level));
+        for (side, (price, qty)) in bids.chain(asks) {
+            writeln!(
+                self.writer,
+                "{},{},{},{},{},{}",
+                book.security_id, book.seq_no, book.timestamp, side, price, qty
+            )?;
+        }
+        Ok(())
+    }
+
+    fn write_event(&mut self, event: OutputEvent) -> io::Result<()> {
+        if self.start(event.kind())? {
+            event.write_csv_header(&mut self.writer)?;
+        }
+        event.write_csv_row(&mut self.writer)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::analytics::trade_inference::Aggressor;
+    use crate::parsing::order_book_snapshot::test_support::create_test_snapshot;
+    use rust_decimal::dec;
+
+    fn create_test_trade(seq_no: u64) -> Trade {
+        Trade {
+            timestamp: 1627846266,
+            seq_no,
+            security_id: 1001,
+            price: dec!(101.00),
+            qty: 5,
+            aggressor: Aggressor::Buyer,
+        }
+    }
+
+    #[test]
+    fn test_text_sink() {
+        let book = OrderBook::new(&create_test_snapshot(1001, 100)).unwrap();
+        let trade = create_test_trade(101);
+
+        let mut sink = TextSink::new(Vec::new());
+        sink.write_event(OutputEvent::Trade(&trade)).unwrap();
+        sink.write_book_state(&book).unwrap();
+        sink.close().unwrap();
+
+        let expected = format!("{}\n{}", trade, book);
+        assert_eq!(String::from_utf8(sink.writer).unwrap(), expected);
+    }
+
+    #[test]
+    fn test_csv_sink_events() {
+        let mut sink = CsvSink::new(Vec::new());
+        sink.write_event(OutputEvent::Trade(&create_test_trade(101)))
+            .unwrap();
+        sink.write_event(OutputEvent::Trade(&create_test_trade(102)))
+            .unwrap();
+
+        assert_eq!(
+            String::from_utf8(sink.writer).unwrap(),
+            "timestamp,seq_no,security_id,price,qty,aggressor\n\
+             1627846266,101,1001,101.00,5,Buyer\n\
+             1627846266,102,1001,101.00,5,Buyer\n"
+        );
+    }
+
+    #[test]
+    fn test_csv_sink_book_state() {
+        let book = OrderBook::new(&create_test_snapshot(1001, 100)).unwrap();
+
+        let mut sink = CsvSink::new(Vec::new());
+        sink.write_book_state(&book).unwrap();
+
+        assert_eq!(
+            String::from_utf8(sink.writer).unwrap(),
+            "security_id,seq_no,timestamp,side,price,qty\n\
+             1001,100,1627846265,bid,100,10\n\
+             1001,100,1627846265,bid,99,20\n\
+             1001,100,1627846265,bid,98,30\n\
+             1001,100,1627846265,bid,97,40\n\
+             1001,100,1627846265,bid,96,50\n\
+             1001,100,1627846265,ask,101,15\n\
+             1001,100,1627846265,ask,102,25\n\
+             1001,100,1627846265,ask,103,35\n\
+             1001,100,1627846265,ask,104,45\n\
+             1001,100,1627846265,ask,105,55\n"
+        );
+    }
+
+    #[test]
+    fn test_csv_sink_rejects_mixed_rows() {
+        let book = OrderBook::new(&create_test_snapshot(1001, 100)).unwrap();
+
+        let mut sink = CsvSink::new(Vec::new());
+        sink.write_event(OutputEvent::Trade(&create_test_trade(101)))
+            .unwrap();
+        let result = sink.write_book_state(&book);
+        assert_eq!(result.unwrap_err().kind(), io::ErrorKind::InvalidInput);
+    }
+}
@@ -1,4 +1,21 @@
+#[cfg(feature = "async")]
+pub mod async_parser;
 pub mod binary_file_iterator;
+pub mod compression;
+pub mod csv_parser;
+pub mod dedup;
+pub mod file_header;
+pub mod follow;
+pub mod framing;
+pub mod json_parser;
+pub mod layout;
+pub mod merged_files;
+pub mod mmap_file_iterator;
 pub mod order_book_snapshot;
 pub mod order_book_update;
 pub mod parser;
+pub mod pre_scan;
+#[cfg(feature = "proto")]
+pub mod proto_parser;
+pub mod text_file_iterator;
+pub mod validation;
@@ -0,0 +1,176 @@
+use futures_util::Stream;
+use futures_util::stream;
+use tokio::io::{AsyncRead, AsyncReadExt};
+use tokio::net::UdpSocket;
+
+use crate::parsing::framing::Framing;
+use crate::parsing::parser::{Parser, ParserError};
+
+const MAX_DATAGRAM_SIZE: usize = 65_536;
+
+// Reads records from a tokio reader, e.g. a TcpStream
+pub struct AsyncRecordReader<R, P> {
+    reader: R,
+    parser: P,
+    buffer: Vec<u8>,
+}
+
+impl<R: AsyncRead + Unpin, P> AsyncRecordReader<R, P> {
+    pub fn new(reader: R, parser: P) -> Self {
+        Self {
+            reader,
+            parser,
+            buffer: Vec::new(),
+        }
+    }
+
+    // ParserError::ExpectedEof when the reader ends between two records
+    pub async fn read<T>(&mut self) -> Result<T, ParserError>
+    where
+        P: Framing<T>,
+    {
+        loop {
+            let header_size = self.parser.header_size();
+            self.buffer.resize(header_size, 0);
+            let read = self
+                .reader
+                .read(&mut self.buffer)
+                .await
+                .map_err(ParserError::Io)?;
+            if read == 0 {
+                return Err(ParserError::ExpectedEof);
+            }
+            self.reader
+                .read_exact(&mut self.buffer[read..])
+                .await
+                .map_err(ParserError::Io)?;
+
+            let body_size = self.parser.body_size(&self.buffer)?;
+            self.buffer.resize(header_size + body_size, 0);
+            self.reader
+                .read_exact(&mut self.buffer[header_size..])
+                .await
+                .map_err(ParserError::Io)?;
+
+            match self.parser.read_from_slice(&self.buffer) {
+                Ok((record, _)) => return Ok(record),
+                // The record was skipped by the filters of the parser
+                Err(ParserError::ExpectedEof) => continue,
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    // Ends after the last record or the first error
+    pub fn into_stream<T>(self) -> impl Stream<Item = Result<T, ParserError>>
+    where
+        P: Framing<T>,
+    {
+        stream::unfold(Some(self), |reader| async move {
+            let mut reader = reader?;
+            match reader.read().await {
+                Ok(record) => Some((Ok(record), Some(reader))),
+                Err(ParserError::ExpectedEof) => None,
+                Err(e) => Some((Err(e), None)),
+            }
+        })
+    }
+}
+
+// One record per datagram. A bad datagram yields an error and the stream goes on.
+pub fn udp_records<T, P: Parser<T>>(
+    socket: UdpSocket,
+    parser: P,
+) -> impl Stream<Item = Result<T, ParserError>> {
+    let buffer = vec![0; MAX_DATAGRAM_SIZE];
+    stream::unfold(
+        (socket, parser, buffer),
+        |(socket, mut parser, mut buffer)| async move {
+            let record = loop {
+                match socket.recv(&mut buffer).await {
+                    Ok(len) => match parser.read_from_slice(&buffer[..len]) {
+                        Ok((record, _)) => break Ok(record),
+                        // Records skipped by the filters of the parser
+                        Err(ParserError::ExpectedEof) if len > 0 => continue,
+                        Err(e) => break Err(e),
+                    },
+                    Err(e) => break Err(ParserError::Io(e)),
+                }
+            };
+            Some((record, (socket, parser, buffer)))
+        },
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parsing::order_book_update::{OrderBookUpdate, OrderBookUpdateParser, UpdateFormat};
+    use futures_util::StreamExt;
+
+    fn create_test_update_data(data: &mut Vec<u8>, seq_no: u64, levels: u64) {
+        data.extend_from_slice(&1234567890u64.to_le_bytes()); // timestamp
+        data.extend_from_slice(&seq_no.to_le_bytes()); // seq_no
+        data.extend_from_slice(&123456u64.to_le_bytes()); // security_id
+        data.extend_from_slice(&levels.to_le_bytes()); // num_updates
+        for _ in 0..levels {
+            data.push(0); // side
+            data.extend_from_slice(&100.0f64.to_le_bytes()); // price
+            data.extend_from_slice(&10u64.to_le_bytes()); // qty
+        }
+    }
+
+    #[tokio::test]
+    async fn test_async_record_reader() {
+        let mut data = Vec::new();
+        create_test_update_data(&mut data, 42, 2);
+        create_test_update_data(&mut data, 43, 0);
+        create_test_update_data(&mut data, 44, 3);
+
+        let reader = AsyncRecordReader::new(data.as_slice(), OrderBookUpdateParser::default());
+        let updates: Vec<Result<OrderBookUpdate, ParserError>> =
+            reader.into_stream().collect().await;
+
+        let seq_nos: Vec<u64> = updates
+            .into_iter()
+            .map(|update| update.unwrap().seq_no)
+            .collect();
+        assert_eq!(seq_nos, vec![42, 43, 44]);
+    }
+
+    #[tokio::test]
+    async fn test_async_record_reader_truncated() {
+        let mut data = Vec::new();
+        create_test_update_data(&mut data, 42, 2);
+        create_test_update_data(&mut data, 43, 2);
+        data.truncate(data.len() - 1);
+
+        let reader = AsyncRecordReader::new(data.as_slice(), OrderBookUpdateParser::default());
+        let updates: Vec<Result<OrderBookUpdate, ParserError>> =
+            reader.into_stream().collect().await;
+
+        assert_eq!(updates.len(), 2);
+        assert!(updates[0].is_ok());
+        assert!(matches!(updates[1], Err(ParserError::Io(_))));
+    }
+
+    #[tokio::test]
+    async fn test_udp_records() {
+        let receiver = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let sender = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        sender
+            .connect(receiver.local_addr().unwrap())
+            .await
+            .unwrap();
+
+        let mut data = Vec::new();
+        create_test_update_data(&mut data, 42, 1);
+        sender.send(&data).await.unwrap();
+        sender.send(&data[..10]).await.unwrap();
+
+        let parser = OrderBookUpdateParser::new(UpdateFormat::V1);
+        let mut updates = Box::pin(udp_records::<OrderBookUpdate, _>(receiver, parser));
+        assert_eq!(updates.next().await.unwrap().unwrap().seq_no, 42);
+        assert!(updates.next().await.unwrap().is_err());
+    }
+}
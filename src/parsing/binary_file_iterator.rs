@@ -1,19 +1,240 @@
-use crate::parsing::parser::ParserError;
+use crate::parsing::compression::open_file;
+use crate::parsing::file_header::{HEADER_SIZE, UNVERSIONED, strip_file_header};
+use crate::parsing::framing::Framing;
 use crate::parsing::parser::{DefaultParser, Parser};
-use std::fs::File;
-use std::io::{self, BufReader};
+use crate::parsing::parser::{ParserError, truncated_record};
+use std::io::{self, BufRead, BufReader, Read};
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard};
+
+// Offset in the decompressed stream right after the last record returned by an iterator,
+// which can be read while the iterator is consumed elsewhere
+#[derive(Debug, Clone, Default)]
+pub struct StreamPosition(Arc<AtomicU64>);
+
+impl StreamPosition {
+    pub fn get(&self) -> u64 {
+        self.0.load(Ordering::Relaxed)
+    }
+
+    pub(crate) fn advance(&self, bytes: u64) {
+        self.0.fetch_add(bytes, Ordering::Relaxed);
+    }
+}
+
+// The last record returned by an iterator as it is stored in its source
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RecordFrame {
+    // The file of the record for iterators reading several, None for the others
+    pub source: Option<Arc<str>>,
+    // Offset of the first byte of the record in the decompressed source
+    pub offset: u64,
+    pub bytes: Vec<u8>,
+}
+
+// The frame of the last record returned by an iterator, shared like StreamPosition.
+// Iterators only copy the records into it once they are given one, e.g. to report the
+// records the books reject.
+#[derive(Debug, Clone, Default)]
+pub struct LastFrame(Arc<Mutex<RecordFrame>>);
+
+impl LastFrame {
+    pub fn get(&self) -> MutexGuard<'_, RecordFrame> {
+        self.0.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    pub(crate) fn set(&self, source: Option<&Arc<str>>, offset: u64, bytes: &[u8]) {
+        let mut frame = self.get();
+        frame.source = source.cloned();
+        frame.offset = offset;
+        frame.bytes.clear();
+        frame.bytes.extend_from_slice(bytes);
+    }
+}
+
+fn frame_size<T, F: Framing<T>>(framing: &F, data: &[u8]) -> Result<usize, ParserError> {
+    let header_size = framing.header_size();
+    if data.len() < header_size {
+        return Err(truncated_record());
+    }
+    Ok(header_size + framing.body_size(&data[..header_size])?)
+}
+
+// Keeps the frames of the records returned by a binary iterator
+pub(crate) struct FrameCapture<P> {
+    last: LastFrame,
+    frame_size: fn(&P, &[u8]) -> Result<usize, ParserError>,
+}
+
+impl<P> FrameCapture<P> {
+    pub(crate) fn new<T>(last: LastFrame) -> Self
+    where
+        P: Framing<T>,
+    {
+        Self {
+            last,
+            frame_size: frame_size::<T, P>,
+        }
+    }
+
+    // `data` starts at `offset` and holds the records the parser skipped before the one it
+    // returned, which ends `data`
+    pub(crate) fn capture(&self, parser: &P, offset: u64, data: &[u8]) {
+        let mut start = 0;
+        while let Ok(size) = (self.frame_size)(parser, &data[start..])
+            && start + size < data.len()
+        {
+            start += size;
+        }
+        self.last.set(None, offset + start as u64, &data[start..]);
+    }
+}
+
+// Counts the bytes read from the inner reader, and keeps them when given a buffer
+struct CountingReader<'a, R> {
+    reader: &'a mut R,
+    read: u64,
+    recorded: Option<&'a mut Vec<u8>>,
+}
+
+impl<R: Read> Read for CountingReader<'_, R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.reader.read(buf)?;
+        self.read += read as u64;
+        if let Some(recorded) = self.recorded.as_mut() {
+            recorded.extend_from_slice(&buf[..read]);
+        }
+        Ok(read)
+    }
+}
 
 pub struct BinaryFileIterator<T: DefaultParser<T>> {
-    reader: BufReader<File>,
+    reader: BufReader<Box<dyn Read>>,
     parser: T::ParserType,
+    version: u32,
+    position: StreamPosition,
+    frames: Option<FrameCapture<T::ParserType>>,
+    // The bytes of a record read across refills of the buffer, when its frame is kept
+    frame_bytes: Vec<u8>,
 }
 
 impl<T: DefaultParser<T>> BinaryFileIterator<T> {
-    pub fn new(file: File) -> Self {
-        Self {
-            reader: BufReader::new(file),
-            parser: T::default_parser(),
+    // Any byte source, e.g. a File, the standard input or a socket
+    pub fn new<R: Read + 'static>(reader: R) -> io::Result<Self> {
+        Self::with_parser(reader, T::default_parser())
+    }
+
+    pub fn with_parser<R: Read + 'static>(reader: R, parser: T::ParserType) -> io::Result<Self> {
+        Self::from_reader(Box::new(reader), parser)
+    }
+
+    // Fails on a file header of an unsupported version
+    pub fn from_reader(reader: Box<dyn Read>, parser: T::ParserType) -> io::Result<Self> {
+        let (version, reader) = strip_file_header(reader)?;
+        let position = StreamPosition::default();
+        if version != UNVERSIONED {
+            position.advance(HEADER_SIZE as u64);
         }
+        Ok(Self {
+            reader: BufReader::new(Box::new(reader)),
+            parser,
+            version,
+            position,
+            frames: None,
+            frame_bytes: Vec::new(),
+        })
+    }
+
+    // Keeps the frame of every record returned in `frames`
+    pub fn with_frames(mut self, frames: LastFrame) -> Self
+    where
+        T::ParserType: Framing<T>,
+    {
+        self.frames = Some(FrameCapture::new(frames));
+        self
+    }
+
+    // Plain, gzip or zstd file
+    pub fn open(path: &Path, parser: T::ParserType) -> io::Result<Self> {
+        Self::from_reader(open_file(path)?, parser)
+    }
+
+    pub fn format_version(&self) -> u32 {
+        self.version
+    }
+
+    // Filtered records are skipped by the parser, so the position can be past the end of
+    // the previous record returned
+    pub fn position(&self) -> StreamPosition {
+        self.position.clone()
+    }
+
+    // Decodes the record in place when the buffer holds all of it, and reads the ones
+    // spanning a refill of the buffer field by field
+    fn read_record(&mut self) -> Result<T, ParserError> {
+        let buffer = self.reader.fill_buf().map_err(ParserError::Io)?;
+        match self.parser.read_from_slice(buffer) {
+            Ok((item, consumed)) => {
+                if let Some(frames) = &self.frames {
+                    frames.capture(&self.parser, self.position.get(), &buffer[..consumed]);
+                }
+                self.reader.consume(consumed);
+                self.position.advance(consumed as u64);
+                Ok(item)
+            }
+            Err(ParserError::ExpectedEof) if buffer.is_empty() => Err(ParserError::ExpectedEof),
+            Err(ParserError::ExpectedEof) => self.read_record_across_refills(),
+            Err(ParserError::Io(e)) if e.kind() == io::ErrorKind::UnexpectedEof => {
+                self.read_record_across_refills()
+            }
+            Err(e) => Err(e),
+        }
+    }
+
+    fn read_record_across_refills(&mut self) -> Result<T, ParserError> {
+        self.frame_bytes.clear();
+        let mut reader = CountingReader {
+            reader: &mut self.reader,
+            read: 0,
+            recorded: self.frames.is_some().then_some(&mut self.frame_bytes),
+        };
+        let item = self.parser.read(&mut reader)?;
+        let read = reader.read;
+        if let Some(frames) = &self.frames {
+            frames.capture(&self.parser, self.position.get(), &self.frame_bytes);
+        }
+        self.position.advance(read);
+        Ok(item)
+    }
+
+    // Parses up to `n` records into `batch` and returns how many were added, 0 at the end
+    // of the file. The records before an error are still added to `batch`.
+    pub fn next_batch(&mut self, batch: &mut Vec<T>, n: usize) -> io::Result<usize> {
+        batch.reserve(n);
+        for added in 0..n {
+            match self.read_record() {
+                Ok(item) => batch.push(item),
+                Err(ParserError::ExpectedEof) => return Ok(added),
+                Err(e) => return Err(into_io_error(e)),
+            }
+        }
+        Ok(n)
+    }
+
+    // next_batch with as many records as `batch` has spare capacity for, so the same
+    // allocation serves every batch once it is cleared
+    pub fn fill_batch(&mut self, batch: &mut Vec<T>) -> io::Result<usize> {
+        let n = batch.capacity() - batch.len();
+        self.next_batch(batch, n)
+    }
+}
+
+fn into_io_error(err: ParserError) -> io::Error {
+    match err {
+        ParserError::Io(io_err) => io_err,
+        ParserError::ExpectedEof => io::Error::from(io::ErrorKind::UnexpectedEof),
+        ParserError::Custom(msg) => io::Error::new(io::ErrorKind::InvalidData, msg),
     }
 }
 
@@ -21,15 +242,112 @@ impl<T: DefaultParser<T>> Iterator for BinaryFileIterator<T> {
     type Item = io::Result<T>;
 
     fn next(&mut self) -> Option<Self::Item> {
-        match self.parser.read(&mut self.reader) {
+        match self.read_record() {
             Ok(item) => Some(Ok(item)),
-            Err(err) => match err {
-                ParserError::Io(io_err) => Some(Err(io_err)),
-                ParserError::ExpectedEof => None,
-                ParserError::Custom(msg) => {
-                    Some(Err(io::Error::new(io::ErrorKind::InvalidData, msg)))
-                }
-            },
+            Err(ParserError::ExpectedEof) => None,
+            Err(err) => Some(Err(into_io_error(err))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::order_book::manager::Manager;
+    use crate::output::reject_log::RejectReason;
+    use crate::parsing::order_book_update::{OrderBookUpdate, OrderBookUpdateParser, UpdateFormat};
+    use crate::parsing::parser::SecurityFilter;
+
+    fn update(seq_no: u64, num_updates: u64) -> Vec<u8> {
+        let mut data = Vec::new();
+        data.extend_from_slice(&1627846265u64.to_le_bytes()); // timestamp
+        data.extend_from_slice(&seq_no.to_le_bytes());
+        data.extend_from_slice(&7u64.to_le_bytes()); // security_id
+        data.extend_from_slice(&num_updates.to_le_bytes());
+        for i in 0..num_updates {
+            data.push((i % 2) as u8); // side
+            data.extend_from_slice(&(100.0 + i as f64).to_le_bytes()); // price
+            data.extend_from_slice(&10u64.to_le_bytes()); // qty
         }
+        data
+    }
+
+    fn iterator(data: Vec<u8>) -> BinaryFileIterator<OrderBookUpdate> {
+        let parser = OrderBookUpdateParser::new(UpdateFormat::V1);
+        BinaryFileIterator::with_parser(io::Cursor::new(data), parser).unwrap()
+    }
+
+    #[test]
+    fn test_position() {
+        // The second record spans a refill of the buffer
+        let mut data = update(1, 1);
+        data.extend(update(2, 1000));
+        let mut records = iterator(data);
+        let position = records.position();
+        records.next().unwrap().unwrap();
+        assert_eq!(position.get(), 49);
+        records.next().unwrap().unwrap();
+        assert_eq!(position.get(), 49 + 32 + 1000 * 17);
+        assert!(records.next().is_none());
+    }
+
+    #[test]
+    fn test_frames() {
+        // A record, one of a filtered security, then one spanning a refill of the buffer
+        let mut data = update(1, 1);
+        let mut filtered = update(2, 1);
+        filtered[16..24].copy_from_slice(&8u64.to_le_bytes());
+        data.extend(filtered);
+        data.extend(update(3, 1000));
+        let parser = OrderBookUpdateParser::new(UpdateFormat::V1)
+            .with_security_filter(SecurityFilter::only([7]));
+        let frame = LastFrame::default();
+        let mut records =
+            BinaryFileIterator::<OrderBookUpdate>::with_parser(io::Cursor::new(data), parser)
+                .unwrap()
+                .with_frames(frame.clone());
+
+        records.next().unwrap().unwrap();
+        assert_eq!(frame.get().offset, 0);
+        assert_eq!(frame.get().bytes, update(1, 1));
+
+        // No snapshot of the security was applied, so the books reject the update
+        let mut manager = Manager::default();
+        let outcome = manager.apply_update(records.next().unwrap().unwrap());
+        assert_eq!(RejectReason::of(&outcome), Some(RejectReason::NoBook));
+        let rejected = frame.get();
+        assert_eq!(rejected.source, None);
+        assert_eq!(rejected.offset, 2 * 49);
+        assert_eq!(rejected.bytes, update(3, 1000));
+    }
+
+    #[test]
+    fn test_next_batch() {
+        // Records larger than the buffer of the reader are read across its refills
+        let data: Vec<u8> = (1..=5)
+            .flat_map(|seq_no| update(seq_no, seq_no * 200))
+            .collect();
+        let mut records = iterator(data);
+
+        let mut batch = Vec::new();
+        assert_eq!(records.next_batch(&mut batch, 2).unwrap(), 2);
+        assert_eq!(records.next_batch(&mut batch, 2).unwrap(), 2);
+        assert_eq!(records.next_batch(&mut batch, 2).unwrap(), 1);
+        assert_eq!(records.next_batch(&mut batch, 2).unwrap(), 0);
+        let seq_nos: Vec<u64> = batch.iter().map(|update| update.seq_no).collect();
+        assert_eq!(seq_nos, vec![1, 2, 3, 4, 5]);
+    }
+
+    #[test]
+    fn test_fill_batch_stops_at_error() {
+        let mut data: Vec<u8> = (1..=3).flat_map(|seq_no| update(seq_no, 2)).collect();
+        data.truncate(data.len() - 5);
+        let mut records = iterator(data);
+
+        let mut batch = Vec::with_capacity(8);
+        let err = records.fill_batch(&mut batch).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+        assert_eq!(batch.len(), 2);
+        assert!(records.next().is_none());
     }
 }
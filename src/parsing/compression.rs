@@ -0,0 +1,123 @@
+use flate2::bufread::MultiGzDecoder;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Read};
+use std::path::Path;
+
+const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+const ZSTD_MAGIC: [u8; 4] = [0x28, 0xb5, 0x2f, 0xfd];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Compression {
+    None,
+    Gzip,
+    Zstd,
+}
+
+impl Compression {
+    // The extension wins, the magic bytes cover captures renamed without it
+    pub fn detect(path: Option<&Path>, header: &[u8]) -> Self {
+        let extension = path
+            .and_then(|path| path.extension())
+            .and_then(|extension| extension.to_str());
+        match extension {
+            Some("gz" | "gzip") => Compression::Gzip,
+            Some("zst" | "zstd") => Compression::Zstd,
+            _ if header.starts_with(&GZIP_MAGIC) => Compression::Gzip,
+            _ if header.starts_with(&ZSTD_MAGIC) => Compression::Zstd,
+            _ => Compression::None,
+        }
+    }
+}
+
+pub fn decompress<R: BufRead + 'static>(
+    reader: R,
+    compression: Compression,
+) -> io::Result<Box<dyn Read>> {
+    Ok(match compression {
+        Compression::None => Box::new(reader),
+        Compression::Gzip => Box::new(MultiGzDecoder::new(reader)),
+        Compression::Zstd => Box::new(zstd::Decoder::with_buffer(reader)?),
+    })
+}
+
+// The file argument standing for the standard input
+pub const STDIN_PATH: &str = "-";
+
+pub fn is_stdin(path: &Path) -> bool {
+    path == Path::new(STDIN_PATH)
+}
+
+// Opens a snapshot or incremental file, decompressing it if needed. "-" reads the standard
+// input, whose compression is told by its magic bytes.
+pub fn open_file(path: &Path) -> io::Result<Box<dyn Read>> {
+    if is_stdin(path) {
+        let mut reader = BufReader::new(io::stdin());
+        let compression = Compression::detect(None, reader.fill_buf()?);
+        return decompress(reader, compression);
+    }
+    let mut reader = BufReader::new(File::open(path)?);
+    let compression = Compression::detect(Some(path), reader.fill_buf()?);
+    decompress(reader, compression)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flate2::write::GzEncoder;
+    use std::io::{Cursor, Write};
+
+    fn create_test_data() -> Vec<u8> {
+        (0..=255u8).cycle().take(1000).collect()
+    }
+
+    fn read_all(mut reader: Box<dyn Read>) -> Vec<u8> {
+        let mut data = Vec::new();
+        reader.read_to_end(&mut data).unwrap();
+        data
+    }
+
+    #[test]
+    fn test_stdin_path() {
+        assert!(is_stdin(Path::new("-")));
+        assert!(!is_stdin(Path::new("./-")));
+        assert!(!is_stdin(Path::new("incremental.bin")));
+    }
+
+    #[test]
+    fn test_detect() {
+        let gz = Path::new("incremental.bin.gz");
+        let zst = Path::new("incremental.bin.zst");
+        let bin = Path::new("incremental.bin");
+
+        assert_eq!(Compression::detect(Some(gz), &[]), Compression::Gzip);
+        assert_eq!(Compression::detect(Some(zst), &[]), Compression::Zstd);
+        assert_eq!(Compression::detect(Some(bin), &[0; 4]), Compression::None);
+        assert_eq!(
+            Compression::detect(Some(bin), &[0x1f, 0x8b, 0x08]),
+            Compression::Gzip
+        );
+        assert_eq!(Compression::detect(None, &ZSTD_MAGIC), Compression::Zstd);
+    }
+
+    #[test]
+    fn test_decompress_gzip() {
+        let data = create_test_data();
+        let mut encoder = GzEncoder::new(Vec::new(), flate2::Compression::default());
+        encoder.write_all(&data).unwrap();
+        let compressed = encoder.finish().unwrap();
+
+        let compression = Compression::detect(None, &compressed);
+        let reader = decompress(Cursor::new(compressed), compression).unwrap();
+        assert_eq!(read_all(reader), data);
+    }
+
+    #[test]
+    fn test_decompress_zstd() {
+        let data = create_test_data();
+        let compressed = zstd::encode_all(Cursor::new(&data), 0).unwrap();
+
+        let compression = Compression::detect(None, &compressed);
+        let reader = decompress(Cursor::new(compressed), compression).unwrap();
+        assert_eq!(read_all(reader), data);
+    }
+}
@@ -0,0 +1,278 @@
+use std::collections::HashMap;
+use std::io::Read;
+use std::str::FromStr;
+
+use crate::batched_deque::batched_deque::BatchedDeque;
+use crate::order_book::units::Qty;
+use crate::parsing::order_book_snapshot::{
+    Level as SnapshotLevel, MAX_SNAPSHOT_DEPTH, OrderBookSnapshot, SNAPSHOT_DEPTH,
+};
+use crate::parsing::order_book_update::{
+    DEFAULT_UPDATE_DEQUE_CAPACITY, Level as UpdateLevel, LevelMetadata, MAX_NUM_UPDATES,
+    OrderBookUpdate, Side, UpdateFormat, update_deque,
+};
+use crate::parsing::parser::{Parser, ParserError};
+use crate::parsing::text_file_iterator::read_record_line;
+
+// Text versions of the binary records, one record per line with comma separated fields.
+// Snapshot lines hold timestamp, seq_no, security_id and price, qty of bid1, ask1, bid2,
+// ask2 and so on, 5 to 20 levels a side as in the binary snapshots of that depth. Update
+// lines hold timestamp, seq_no, security_id and side, price, qty of every level, V2 lines
+// add capture_timestamp after the timestamp and order_count, action after the qty of each
+// level. Sides are bid, ask or their codes.
+// Empty lines, lines starting with # and header lines starting with "timestamp" are
+// skipped.
+
+// timestamp, seq_no and security_id, then price and qty of a bid and an ask per level
+const SNAPSHOT_HEADER_FIELDS: usize = 3;
+const SNAPSHOT_LEVEL_FIELDS: usize = 4;
+
+fn is_record(line: &str) -> bool {
+    !line.starts_with('#') && !line.starts_with("timestamp")
+}
+
+fn parse_field<T: FromStr>(field: &str, name: &str, line_no: usize) -> Result<T, ParserError> {
+    field
+        .trim()
+        .parse()
+        .map_err(|_| ParserError::Custom(format!("Line {}: invalid {}: {}", line_no, name, field)))
+}
+
+fn parse_side(field: &str, line_no: usize) -> Result<Side, ParserError> {
+    match field.trim() {
+        "bid" | "0" => Ok(Side::Bid),
+        "ask" | "1" => Ok(Side::Ask),
+        _ => Err(ParserError::Custom(format!(
+            "Line {}: invalid side: {}",
+            line_no, field
+        ))),
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct CsvSnapshotParser {
+    line: String,
+    line_no: usize,
+}
+
+impl Parser<OrderBookSnapshot> for CsvSnapshotParser {
+    fn read<R: Read>(&mut self, reader: &mut R) -> Result<OrderBookSnapshot, ParserError> {
+        read_record_line(reader, &mut self.line, &mut self.line_no, is_record)?;
+        let line_no = self.line_no;
+        let fields: Vec<&str> = self.line.split(',').collect();
+        let depth = fields.len().saturating_sub(SNAPSHOT_HEADER_FIELDS) / SNAPSHOT_LEVEL_FIELDS;
+        if fields.len() != SNAPSHOT_HEADER_FIELDS + depth * SNAPSHOT_LEVEL_FIELDS
+            || !(SNAPSHOT_DEPTH..=MAX_SNAPSHOT_DEPTH).contains(&depth)
+        {
+            return Err(ParserError::Custom(format!(
+                "Line {}: expected {} fields and {} per level, {} to {} levels, got {}",
+                line_no,
+                SNAPSHOT_HEADER_FIELDS,
+                SNAPSHOT_LEVEL_FIELDS,
+                SNAPSHOT_DEPTH,
+                MAX_SNAPSHOT_DEPTH,
+                fields.len()
+            )));
+        }
+        let level = |index: usize| -> Result<SnapshotLevel, ParserError> {
+            let offset = 3 + index * 2;
+            Ok(SnapshotLevel {
+                price: parse_field(fields[offset], "price", line_no)?,
+                qty: Qty(parse_field(fields[offset + 1], "qty", line_no)?),
+            })
+        };
+        Ok(OrderBookSnapshot {
+            timestamp: parse_field(fields[0], "timestamp", line_no)?,
+            seq_no: parse_field(fields[1], "seq_no", line_no)?,
+            security_id: parse_field(fields[2], "security_id", line_no)?,
+            bids: (0..depth)
+                .map(|index| level(2 * index))
+                .collect::<Result<_, _>>()?,
+            asks: (0..depth)
+                .map(|index| level(2 * index + 1))
+                .collect::<Result<_, _>>()?,
+        })
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct CsvUpdateParser {
+    format: UpdateFormat,
+    security_id_to_deque: HashMap<u64, BatchedDeque<UpdateLevel>>,
+    max_held_levels: Option<usize>,
+    line: String,
+    line_no: usize,
+}
+
+impl CsvUpdateParser {
+    pub fn new(format: UpdateFormat) -> Self {
+        Self {
+            format,
+            ..Default::default()
+        }
+    }
+
+    // See OrderBookUpdateParser::with_max_held_levels
+    pub fn with_max_held_levels(mut self, max_held_levels: Option<usize>) -> Self {
+        self.max_held_levels = max_held_levels;
+        self
+    }
+}
+
+impl Parser<OrderBookUpdate> for CsvUpdateParser {
+    fn read<R: Read>(&mut self, reader: &mut R) -> Result<OrderBookUpdate, ParserError> {
+        read_record_line(reader, &mut self.line, &mut self.line_no, is_record)?;
+        let line_no = self.line_no;
+        let fields: Vec<&str> = self.line.split(',').collect();
+        let (header_fields, level_fields) = match self.format {
+            UpdateFormat::V1 => (3, 3),
+            UpdateFormat::V2 => (4, 5),
+        };
+        if fields.len() < header_fields
+            || !(fields.len() - header_fields).is_multiple_of(level_fields)
+        {
+            return Err(ParserError::Custom(format!(
+                "Line {}: expected {} fields and {} per level, got {}",
+                line_no,
+                header_fields,
+                level_fields,
+                fields.len()
+            )));
+        }
+        let num_updates = (fields.len() - header_fields) / level_fields;
+        if num_updates > MAX_NUM_UPDATES {
+            return Err(ParserError::Custom(format!(
+                "Number of updates is too large: {}",
+                num_updates
+            )));
+        }
+
+        let timestamp = parse_field(fields[0], "timestamp", line_no)?;
+        let capture_timestamp = match self.format {
+            UpdateFormat::V1 => None,
+            UpdateFormat::V2 => Some(parse_field(fields[1], "capture_timestamp", line_no)?),
+        };
+        let seq_no = parse_field(fields[header_fields - 2], "seq_no", line_no)?;
+        let security_id = parse_field(fields[header_fields - 1], "security_id", line_no)?;
+
+        let format = self.format;
+        let levels = fields[header_fields..].chunks(level_fields).map(
+            |level| -> Result<UpdateLevel, ParserError> {
+                let metadata = match format {
+                    UpdateFormat::V1 => None,
+                    UpdateFormat::V2 => Some(LevelMetadata {
+                        order_count: parse_field(level[3], "order_count", line_no)?,
+                        action: parse_field(level[4], "action", line_no)?,
+                    }),
+                };
+                Ok(UpdateLevel {
+                    side: parse_side(level[0], line_no)?,
+                    price: parse_field(level[1], "price", line_no)?,
+                    qty: Qty(parse_field(level[2], "qty", line_no)?),
+                    metadata,
+                })
+            },
+        );
+        let deque = self
+            .security_id_to_deque
+            .entry(security_id)
+            .or_insert_with(|| update_deque(DEFAULT_UPDATE_DEQUE_CAPACITY, self.max_held_levels));
+        Ok(OrderBookUpdate {
+            timestamp,
+            capture_timestamp,
+            seq_no,
+            security_id,
+            thinned_from: None,
+            updates: deque.push_back_batch(levels)?,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parsing::text_file_iterator::TextFileIterator;
+    use std::io;
+
+    #[test]
+    fn test_csv_snapshots() {
+        let data = "timestamp,seq_no,security_id,bid1_price,bid1_qty,ask1_price,ask1_qty\n\
+            # A hand-crafted book\n\
+            1627846265,100,1001,100,10,101,15,99,20,102,25,98,30,103,35,97,40,104,45,96,50,105,55\n\
+            \n\
+            1627846266,101,1002,50.5,1,51,2,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0";
+        let snapshots: Vec<OrderBookSnapshot> =
+            TextFileIterator::from_reader(Box::new(data.as_bytes()), CsvSnapshotParser::default())
+                .collect::<io::Result<_>>()
+                .unwrap();
+
+        assert_eq!(snapshots.len(), 2);
+        assert_eq!(snapshots[0].seq_no, 100);
+        assert_eq!(snapshots[0].security_id, 1001);
+        assert_eq!(snapshots[0].bids[0].price, 100.0);
+        assert_eq!(snapshots[0].asks[4].qty, Qty(55));
+        assert_eq!(snapshots[1].bids[0].price, 50.5);
+        assert_eq!(snapshots[1].asks[0].qty, Qty(2));
+    }
+
+    #[test]
+    fn test_csv_updates() {
+        let data = "1627846266,101,1001,bid,100.5,10,ask,101,0\r\n\
+            1627846267,102,1001,0,100,5\n";
+        let mut parser = CsvUpdateParser::new(UpdateFormat::V1);
+        let mut reader = data.as_bytes();
+
+        let update = parser.read(&mut reader).unwrap();
+        assert_eq!(update.timestamp, 1627846266);
+        assert_eq!(update.seq_no, 101);
+        assert_eq!(update.security_id, 1001);
+        let mut levels = Vec::new();
+        update
+            .updates
+            .for_each(|level| {
+                levels.push((level.side, level.price, level.qty.value()));
+                Ok::<(), ()>(())
+            })
+            .unwrap();
+        assert_eq!(levels, vec![(Side::Bid, 100.5, 10), (Side::Ask, 101.0, 0)]);
+
+        assert_eq!(parser.read(&mut reader).unwrap().seq_no, 102);
+        assert!(matches!(
+            parser.read(&mut reader),
+            Err(ParserError::ExpectedEof)
+        ));
+    }
+
+    #[test]
+    fn test_csv_updates_v2() {
+        let data = "1627846266,1627846267,101,1001,ask,101,15,3,1";
+        let mut parser = CsvUpdateParser::new(UpdateFormat::V2);
+        let update = parser.read(&mut data.as_bytes()).unwrap();
+        assert_eq!(update.capture_timestamp, Some(1627846267));
+        update
+            .updates
+            .for_each(|level| {
+                assert_eq!(
+                    level.metadata,
+                    Some(LevelMetadata {
+                        order_count: 3,
+                        action: 1,
+                    })
+                );
+                Ok::<(), ()>(())
+            })
+            .unwrap();
+    }
+
+    #[test]
+    fn test_csv_invalid_lines() {
+        let mut parser = CsvUpdateParser::new(UpdateFormat::V1);
+        let result = parser.read(&mut "1627846266,101,1001,bid,100.5".as_bytes());
+        assert!(matches!(result, Err(ParserError::Custom(msg)) if msg.starts_with("Line 1:")));
+
+        let result = parser.read(&mut "\n1627846266,101,1001,buy,100.5,10".as_bytes());
+        assert!(
+            matches!(result, Err(ParserError::Custom(msg)) if msg == "Line 3: invalid side: buy")
+        );
+    }
+}
@@ -0,0 +1,191 @@
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::Display;
+use std::hash::{Hash, Hasher};
+
+use crate::parsing::order_book_snapshot::{Level as SnapshotLevel, OrderBookSnapshot};
+use crate::parsing::order_book_update::OrderBookUpdate;
+
+// Seq_nos kept per security, overlaps between captures are much shorter
+const DEFAULT_DEDUP_WINDOW: u64 = 100_000;
+
+// Identity of a record: security_id, seq_no and a hash of everything else
+pub trait DedupKey {
+    fn dedup_key(&self) -> (u64, u64, u64);
+}
+
+impl DedupKey for OrderBookUpdate {
+    fn dedup_key(&self) -> (u64, u64, u64) {
+        let mut hasher = DefaultHasher::new();
+        self.timestamp.hash(&mut hasher);
+        self.capture_timestamp.hash(&mut hasher);
+        let _ = self.updates.for_each(|level| {
+            level.side.hash(&mut hasher);
+            level.price.to_bits().hash(&mut hasher);
+            level.qty.hash(&mut hasher);
+            level
+                .metadata
+                .map(|metadata| (metadata.order_count, metadata.action))
+                .hash(&mut hasher);
+            Ok::<(), ()>(())
+        });
+        (self.security_id, self.seq_no, hasher.finish())
+    }
+}
+
+impl DedupKey for OrderBookSnapshot {
+    fn dedup_key(&self) -> (u64, u64, u64) {
+        let mut hasher = DefaultHasher::new();
+        self.timestamp.hash(&mut hasher);
+        for SnapshotLevel { price, qty } in self.bids.iter().chain(&self.asks) {
+            price.to_bits().hash(&mut hasher);
+            qty.hash(&mut hasher);
+        }
+        (self.security_id, self.seq_no, hasher.finish())
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct DedupStats {
+    pub records: usize,
+    pub duplicates: usize,
+    // Same security_id and seq_no as a seen record but a different content, both are kept
+    pub conflicts: usize,
+}
+
+impl Display for DedupStats {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Dedup: {} records, {} duplicates removed, {} conflicting records",
+            self.records, self.duplicates, self.conflicts
+        )
+    }
+}
+
+// Drops records already seen, e.g. where captures restarted with an overlap
+#[derive(Debug)]
+pub struct Deduplicator {
+    window: u64,
+    // security_id -> seq_no -> content hashes
+    seen: HashMap<u64, BTreeMap<u64, Vec<u64>>>,
+    pub stats: DedupStats,
+}
+
+impl Default for Deduplicator {
+    fn default() -> Self {
+        Self::new(DEFAULT_DEDUP_WINDOW)
+    }
+}
+
+impl Deduplicator {
+    // Records more than `window` seq_nos behind the newest one of their security are
+    // forgotten
+    pub fn new(window: u64) -> Self {
+        Self {
+            window,
+            seen: HashMap::new(),
+            stats: DedupStats::default(),
+        }
+    }
+
+    pub fn is_duplicate<T: DedupKey>(&mut self, record: &T) -> bool {
+        let (security_id, seq_no, hash) = record.dedup_key();
+        self.stats.records += 1;
+
+        let seen = self.seen.entry(security_id).or_default();
+        let hashes = seen.entry(seq_no).or_default();
+        if hashes.contains(&hash) {
+            self.stats.duplicates += 1;
+            return true;
+        }
+        if !hashes.is_empty() {
+            self.stats.conflicts += 1;
+        }
+        hashes.push(hash);
+
+        let newest = seen.last_key_value().map_or(seq_no, |(newest, _)| *newest);
+        let oldest_kept = newest.saturating_sub(self.window);
+        while let Some(oldest) = seen.first_entry()
+            && *oldest.key() < oldest_kept
+        {
+            oldest.remove();
+        }
+        false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::batched_deque::batched_deque::BatchedDeque;
+    use crate::order_book::units::Qty;
+    use crate::parsing::order_book_update::{Level as UpdateLevel, Side};
+
+    fn create_test_update(
+        deque: &BatchedDeque<UpdateLevel>,
+        security_id: u64,
+        seq_no: u64,
+        qty: u64,
+    ) -> OrderBookUpdate {
+        let level = UpdateLevel {
+            side: Side::Bid,
+            price: 100.00,
+            qty: Qty(qty),
+            metadata: None,
+        };
+        OrderBookUpdate {
+            timestamp: 1627846266,
+            capture_timestamp: None,
+            seq_no,
+            security_id,
+            thinned_from: None,
+            updates: deque
+                .push_back_batch([Ok::<_, ()>(level)].into_iter())
+                .unwrap(),
+        }
+    }
+
+    #[test]
+    fn test_dedup_overlapping_files() {
+        let deque = BatchedDeque::new(10);
+        let mut deduplicator = Deduplicator::default();
+
+        // The second capture restarted at 102 and overlaps the first one
+        for seq_no in 100..105 {
+            let update = create_test_update(&deque, 1001, seq_no, seq_no);
+            assert!(!deduplicator.is_duplicate(&update));
+        }
+        for seq_no in 102..107 {
+            let update = create_test_update(&deque, 1001, seq_no, seq_no);
+            assert_eq!(deduplicator.is_duplicate(&update), seq_no < 105);
+        }
+        // Another security with the same seq_no
+        assert!(!deduplicator.is_duplicate(&create_test_update(&deque, 1002, 102, 102)));
+
+        assert_eq!(
+            deduplicator.stats,
+            DedupStats {
+                records: 11,
+                duplicates: 3,
+                conflicts: 0,
+            }
+        );
+    }
+
+    #[test]
+    fn test_dedup_conflicts_and_window() {
+        let deque = BatchedDeque::new(10);
+        let mut deduplicator = Deduplicator::new(10);
+
+        assert!(!deduplicator.is_duplicate(&create_test_update(&deque, 1001, 100, 1)));
+        // Same seq_no, different levels
+        assert!(!deduplicator.is_duplicate(&create_test_update(&deque, 1001, 100, 2)));
+        assert!(deduplicator.is_duplicate(&create_test_update(&deque, 1001, 100, 2)));
+        assert_eq!(deduplicator.stats.conflicts, 1);
+
+        // 100 falls out of the window
+        assert!(!deduplicator.is_duplicate(&create_test_update(&deque, 1001, 111, 1)));
+        assert!(!deduplicator.is_duplicate(&create_test_update(&deque, 1001, 100, 1)));
+    }
+}
@@ -0,0 +1,101 @@
+use std::io::{self, Chain, Cursor, Read, Write};
+
+// Starts the files with a header, the 4 magic bytes followed by the format version as a
+// little-endian u32. Files without it are v0 and start with their first record. A v0
+// file could only be mistaken for a versioned one if its first timestamp started with
+// the magic bytes.
+pub const MAGIC: [u8; 4] = *b"L2OB";
+pub const HEADER_SIZE: usize = 8;
+pub const UNVERSIONED: u32 = 0;
+// Written by this crate, the records have the same layout as in v0 files
+pub const CURRENT_VERSION: u32 = 1;
+
+// Version of the file and the size of its header, which is 0 for v0 files
+pub fn parse_file_header(bytes: &[u8]) -> io::Result<(u32, usize)> {
+    if !bytes.starts_with(&MAGIC) {
+        return Ok((UNVERSIONED, 0));
+    }
+    if bytes.len() < HEADER_SIZE {
+        return Err(io::Error::new(
+            io::ErrorKind::UnexpectedEof,
+            "Truncated file header",
+        ));
+    }
+    let version = u32::from_le_bytes(bytes[MAGIC.len()..HEADER_SIZE].try_into().unwrap());
+    if version > CURRENT_VERSION {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("Unsupported format version: {}", version),
+        ));
+    }
+    Ok((version, HEADER_SIZE))
+}
+
+// The bytes read past the header while looking for it, followed by the rest of the file
+pub type HeaderlessReader<R> = Chain<Cursor<Vec<u8>>, R>;
+
+// Reads the header if there is one and returns the version with a reader starting at the
+// first record
+pub fn strip_file_header<R: Read>(mut reader: R) -> io::Result<(u32, HeaderlessReader<R>)> {
+    let mut prefix = [0; HEADER_SIZE];
+    let mut len = 0;
+    while len < HEADER_SIZE {
+        match reader.read(&mut prefix[len..]) {
+            Ok(0) => break,
+            Ok(read) => len += read,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+    let (version, header_size) = parse_file_header(&prefix[..len])?;
+    let rest = prefix[header_size..len].to_vec();
+    Ok((version, Cursor::new(rest).chain(reader)))
+}
+
+pub fn write_file_header<W: Write>(writer: &mut W) -> io::Result<()> {
+    writer.write_all(&MAGIC)?;
+    writer.write_all(&CURRENT_VERSION.to_le_bytes())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_versioned_and_unversioned() {
+        let records: Vec<u8> = (0..20).collect();
+
+        let mut data = Vec::new();
+        write_file_header(&mut data).unwrap();
+        data.extend_from_slice(&records);
+        let (version, mut reader) = strip_file_header(data.as_slice()).unwrap();
+        assert_eq!(version, CURRENT_VERSION);
+        let mut read = Vec::new();
+        reader.read_to_end(&mut read).unwrap();
+        assert_eq!(read, records);
+
+        let (version, mut reader) = strip_file_header(records.as_slice()).unwrap();
+        assert_eq!(version, UNVERSIONED);
+        let mut read = Vec::new();
+        reader.read_to_end(&mut read).unwrap();
+        assert_eq!(read, records);
+
+        // Shorter than a header
+        let (version, mut reader) = strip_file_header(&records[..3]).unwrap();
+        assert_eq!(version, UNVERSIONED);
+        let mut read = Vec::new();
+        reader.read_to_end(&mut read).unwrap();
+        assert_eq!(read, &records[..3]);
+    }
+
+    #[test]
+    fn test_invalid_header() {
+        let mut data = MAGIC.to_vec();
+        data.extend_from_slice(&(CURRENT_VERSION + 1).to_le_bytes());
+        let error = strip_file_header(data.as_slice()).err().unwrap();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+
+        let error = strip_file_header(&MAGIC[..]).err().unwrap();
+        assert_eq!(error.kind(), io::ErrorKind::UnexpectedEof);
+    }
+}
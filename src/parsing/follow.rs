@@ -0,0 +1,236 @@
+use notify::{RecommendedWatcher, RecursiveMode, Watcher};
+use std::fs::{self, File};
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError};
+use std::time::{Duration, Instant};
+
+use crate::parsing::file_header::{HEADER_SIZE, MAGIC, parse_file_header};
+
+// Checked for new bytes this often even without a change event, as events can be missed
+// or coalesced by the watcher
+const POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+// Tails a capture as it is written: the bytes appended to a growing file, or to the files
+// of a directory in name order, moving to the next file once one appears. Reads wait for
+// new bytes instead of returning the end of the input, until the idle timeout if any.
+// The file header of each file is stripped, so the bytes are records only. Compressed
+// files can't be tailed.
+pub struct TailReader {
+    // The directory whose files are followed, None when following a single file
+    dir: Option<PathBuf>,
+    current: Option<(PathBuf, File)>,
+    // Bytes read past the file header of the current file, not returned yet
+    prefix: Vec<u8>,
+    // Whether the file header of the current file is still to be read
+    at_file_start: bool,
+    idle_timeout: Option<Duration>,
+    events: Receiver<notify::Result<notify::Event>>,
+    // Dropping the watcher stops the events
+    _watcher: RecommendedWatcher,
+}
+
+impl TailReader {
+    // A directory or a single file, which may not exist yet when in a directory
+    pub fn open(path: &Path) -> io::Result<Self> {
+        let (sender, events) = mpsc::channel();
+        let mut watcher = notify::recommended_watcher(sender).map_err(io::Error::other)?;
+        watcher
+            .watch(path, RecursiveMode::NonRecursive)
+            .map_err(io::Error::other)?;
+        let (dir, current) = if path.is_dir() {
+            (Some(path.to_path_buf()), None)
+        } else {
+            (None, Some((path.to_path_buf(), File::open(path)?)))
+        };
+        Ok(Self {
+            dir,
+            current,
+            prefix: Vec::new(),
+            at_file_start: true,
+            idle_timeout: None,
+            events,
+            _watcher: watcher,
+        })
+    }
+
+    // Ends the input after waiting this long for new bytes
+    pub fn with_idle_timeout(mut self, idle_timeout: Duration) -> Self {
+        self.idle_timeout = Some(idle_timeout);
+        self
+    }
+
+    pub fn current_path(&self) -> Option<&Path> {
+        self.current.as_ref().map(|(path, _)| path.as_path())
+    }
+
+    // The first file of the directory after the current one in name order
+    fn next_file(&self) -> io::Result<Option<PathBuf>> {
+        let Some(dir) = &self.dir else {
+            return Ok(None);
+        };
+        let current = self.current_path();
+        let mut next: Option<PathBuf> = None;
+        for entry in fs::read_dir(dir)? {
+            let path = entry?.path();
+            if path.is_file()
+                && current.is_none_or(|current| path.as_path() > current)
+                && next.as_ref().is_none_or(|next| path < *next)
+            {
+                next = Some(path);
+            }
+        }
+        Ok(next)
+    }
+
+    // Waits for a change or the poll interval. False once idle for the timeout.
+    fn wait(&self, idle_since: Instant) -> bool {
+        let timeout = match self.idle_timeout {
+            Some(idle_timeout) => match idle_timeout.checked_sub(idle_since.elapsed()) {
+                Some(left) => left.min(POLL_INTERVAL),
+                None => return false,
+            },
+            None => POLL_INTERVAL,
+        };
+        match self.events.recv_timeout(timeout) {
+            // Errors of the watcher leave the polling
+            Ok(_) | Err(RecvTimeoutError::Timeout) => {}
+            Err(RecvTimeoutError::Disconnected) => std::thread::sleep(timeout),
+        }
+        // Several events may come for a single write
+        while self.events.try_recv().is_ok() {}
+        true
+    }
+
+    // Reads the file header of the current file, leaving at_file_start set while the bytes
+    // read may be the start of a header and the file is still the last one
+    fn read_file_header(&mut self, file_done: bool) -> io::Result<()> {
+        let Some((_, file)) = self.current.as_mut() else {
+            return Ok(());
+        };
+        let mut bytes = [0; HEADER_SIZE];
+        let len = self.prefix.len();
+        bytes[..len].copy_from_slice(&self.prefix);
+        let read = file.read(&mut bytes[len..])?;
+        self.prefix.extend_from_slice(&bytes[len..len + read]);
+        let may_be_header = MAGIC.starts_with(&self.prefix) || self.prefix.starts_with(&MAGIC);
+        if self.prefix.len() < HEADER_SIZE && may_be_header && !file_done {
+            return Ok(());
+        }
+        let (_, header_size) = parse_file_header(&self.prefix)?;
+        self.prefix.drain(..header_size);
+        self.at_file_start = false;
+        Ok(())
+    }
+}
+
+impl Read for TailReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        let idle_since = Instant::now();
+        loop {
+            if self.current.is_some() {
+                if self.at_file_start {
+                    let file_done = self.next_file()?.is_some();
+                    self.read_file_header(file_done)?;
+                }
+                if !self.at_file_start {
+                    if !self.prefix.is_empty() {
+                        let len = self.prefix.len().min(buf.len());
+                        buf[..len].copy_from_slice(&self.prefix[..len]);
+                        self.prefix.drain(..len);
+                        return Ok(len);
+                    }
+                    let (_, file) = self.current.as_mut().expect("Current file");
+                    let read = file.read(buf)?;
+                    if read > 0 {
+                        return Ok(read);
+                    }
+                }
+            }
+            if let Some(next) = self.next_file()? {
+                // Bytes written to the current file before the next one appeared
+                if let Some((_, file)) = self.current.as_mut()
+                    && !self.at_file_start
+                {
+                    let read = file.read(buf)?;
+                    if read > 0 {
+                        return Ok(read);
+                    }
+                }
+                let file = File::open(&next)?;
+                self.current = Some((next, file));
+                self.prefix.clear();
+                self.at_file_start = true;
+                continue;
+            }
+            if !self.wait(idle_since) {
+                return Ok(0);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parsing::file_header::write_file_header;
+    use std::env;
+    use std::io::Write;
+    use std::thread;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = env::temp_dir().join(format!("order_book_{}_{}", name, std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn test_tail_growing_file() {
+        let dir = temp_dir("tail_file");
+        let path = dir.join("updates.bin");
+        let mut file = File::create(&path).unwrap();
+        file.write_all(b"abc").unwrap();
+
+        let mut reader = TailReader::open(&path)
+            .unwrap()
+            .with_idle_timeout(Duration::from_secs(5));
+        let writer = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(50));
+            file.write_all(b"def").unwrap();
+        });
+        let mut data = [0; 6];
+        reader.read_exact(&mut data).unwrap();
+        assert_eq!(&data, b"abcdef");
+        writer.join().unwrap();
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_tail_rotated_files() {
+        let dir = temp_dir("tail_dir");
+        fs::write(dir.join("updates-00.bin"), b"abc").unwrap();
+        let mut reader = TailReader::open(&dir)
+            .unwrap()
+            .with_idle_timeout(Duration::from_millis(300));
+
+        let mut data = [0; 3];
+        reader.read_exact(&mut data).unwrap();
+        assert_eq!(&data, b"abc");
+        // The next file is read after the current one, without its file header
+        let mut next = Vec::new();
+        write_file_header(&mut next).unwrap();
+        next.extend_from_slice(b"def");
+        fs::write(dir.join("updates-01.bin"), next).unwrap();
+        let mut rest = Vec::new();
+        reader.read_to_end(&mut rest).unwrap();
+        assert_eq!(rest, b"def");
+        assert_eq!(
+            reader.current_path(),
+            Some(dir.join("updates-01.bin").as_path())
+        );
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,142 @@
+use std::io::{self, Read};
+
+use crate::parsing::order_book_snapshot::{
+    LEVEL_PAIR_SIZE, MAX_NUM_LEVELS, OrderBookSnapshot, OrderBookSnapshotParser, SnapshotFormat,
+};
+use crate::parsing::order_book_update::{MAX_NUM_UPDATES, OrderBookUpdate, OrderBookUpdateParser};
+use crate::parsing::parser::{Endianness, Parser, ParserError};
+
+// Where a record ends, so that it can be read whole, e.g. from an async reader or to hand
+// it to another thread, and then decoded by the parser
+pub trait Framing<T>: Parser<T> {
+    fn header_size(&self) -> usize;
+    // Bytes following the header
+    fn body_size(&self, header: &[u8]) -> Result<usize, ParserError>;
+    // Also works on a whole record
+    fn security_id(&self, header: &[u8]) -> u64;
+}
+
+fn read_u64_at(bytes: &[u8], offset: usize, endianness: Endianness) -> u64 {
+    endianness.u64_from(bytes[offset..offset + 8].try_into().unwrap())
+}
+
+impl Framing<OrderBookSnapshot> for OrderBookSnapshotParser {
+    // V1 records are all header
+    fn header_size(&self) -> usize {
+        match self.format() {
+            SnapshotFormat::V1 => self.snapshot_size(),
+            SnapshotFormat::V2 => SnapshotFormat::V2.header_size(),
+        }
+    }
+
+    fn body_size(&self, header: &[u8]) -> Result<usize, ParserError> {
+        if self.format() == SnapshotFormat::V1 {
+            return Ok(0);
+        }
+        // num_levels ends the header
+        let num_levels = read_u64_at(header, 24, self.endianness()) as usize;
+        if num_levels > MAX_NUM_LEVELS {
+            return Err(ParserError::Custom(format!(
+                "Number of levels is too large: {}",
+                num_levels
+            )));
+        }
+        Ok(num_levels * LEVEL_PAIR_SIZE)
+    }
+
+    fn security_id(&self, header: &[u8]) -> u64 {
+        // After timestamp and seq_no
+        read_u64_at(header, 16, self.endianness())
+    }
+}
+
+impl Framing<OrderBookUpdate> for OrderBookUpdateParser {
+    fn header_size(&self) -> usize {
+        self.format().header_size()
+    }
+
+    fn body_size(&self, header: &[u8]) -> Result<usize, ParserError> {
+        // num_updates ends the header
+        let num_updates = read_u64_at(header, self.header_size() - 8, self.endianness()) as usize;
+        if num_updates > MAX_NUM_UPDATES {
+            return Err(ParserError::Custom(format!(
+                "Number of updates is too large: {}",
+                num_updates
+            )));
+        }
+        Ok(num_updates * self.format().level_size())
+    }
+
+    fn security_id(&self, header: &[u8]) -> u64 {
+        // Followed by num_updates
+        read_u64_at(header, self.header_size() - 16, self.endianness())
+    }
+}
+
+// Reads the bytes of the next record into the buffer, ParserError::ExpectedEof when the
+// reader ends between two records
+pub fn read_frame<T, R: Read, F: Framing<T>>(
+    reader: &mut R,
+    framing: &F,
+    buffer: &mut Vec<u8>,
+) -> Result<(), ParserError> {
+    let header_size = framing.header_size();
+    buffer.resize(header_size, 0);
+    match reader.read_exact(&mut buffer[..1]) {
+        Ok(_) => (),
+        Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => {
+            return Err(ParserError::ExpectedEof);
+        }
+        Err(e) => return Err(ParserError::Io(e)),
+    }
+    reader
+        .read_exact(&mut buffer[1..])
+        .map_err(ParserError::Io)?;
+
+    let body_size = framing.body_size(buffer)?;
+    buffer.resize(header_size + body_size, 0);
+    reader
+        .read_exact(&mut buffer[header_size..])
+        .map_err(ParserError::Io)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parsing::order_book_update::UpdateFormat;
+    use std::io::Cursor;
+
+    fn create_test_update_data(data: &mut Vec<u8>, security_id: u64, seq_no: u64, levels: u64) {
+        data.extend_from_slice(&1234567890u64.to_le_bytes()); // timestamp
+        data.extend_from_slice(&seq_no.to_le_bytes()); // seq_no
+        data.extend_from_slice(&security_id.to_le_bytes()); // security_id
+        data.extend_from_slice(&levels.to_le_bytes()); // num_updates
+        for _ in 0..levels {
+            data.push(0); // side
+            data.extend_from_slice(&100.0f64.to_le_bytes()); // price
+            data.extend_from_slice(&10u64.to_le_bytes()); // qty
+        }
+    }
+
+    #[test]
+    fn test_read_frame() {
+        let mut data = Vec::new();
+        create_test_update_data(&mut data, 7, 42, 2);
+        create_test_update_data(&mut data, 8, 43, 1);
+        let mut reader = Cursor::new(data);
+        let mut parser = OrderBookUpdateParser::new(UpdateFormat::V1);
+        let mut buffer = Vec::new();
+
+        read_frame(&mut reader, &parser, &mut buffer).unwrap();
+        assert_eq!(buffer.len(), 32 + 2 * 17);
+        assert_eq!(Framing::<OrderBookUpdate>::security_id(&parser, &buffer), 7);
+        let update = parser.read(&mut buffer.as_slice()).unwrap();
+        assert_eq!(update.seq_no, 42);
+
+        read_frame(&mut reader, &parser, &mut buffer).unwrap();
+        assert_eq!(Framing::<OrderBookUpdate>::security_id(&parser, &buffer), 8);
+
+        let result = read_frame(&mut reader, &parser, &mut buffer);
+        assert!(matches!(result, Err(ParserError::ExpectedEof)));
+    }
+}
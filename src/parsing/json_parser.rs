@@ -0,0 +1,314 @@
+use serde_json::Value;
+use std::collections::HashMap;
+use std::io::Read;
+
+use crate::batched_deque::batched_deque::BatchedDeque;
+use crate::order_book::units::Qty;
+use crate::parsing::order_book_snapshot::{
+    Level as SnapshotLevel, MAX_SNAPSHOT_DEPTH, OrderBookSnapshot, SNAPSHOT_DEPTH,
+};
+use crate::parsing::order_book_update::{
+    DEFAULT_UPDATE_DEQUE_CAPACITY, Level as UpdateLevel, LevelMetadata, MAX_NUM_UPDATES,
+    OrderBookUpdate, Side, update_deque,
+};
+use crate::parsing::parser::{Parser, ParserError};
+use crate::parsing::text_file_iterator::read_record_line;
+
+// Newline-delimited JSON records, e.g. messages captured from a web API. Snapshots look
+// like {"timestamp":1,"seq_no":2,"security_id":3,"bids":[{"price":100.0,"qty":10}],
+// "asks":[...]} with up to 20 levels a side, best first. Updates look like {"timestamp":1,
+// "seq_no":2,"security_id":3,"levels":[{"side":"bid","price":100.0,"qty":10}]} where the
+// side may also be its code, "capture_timestamp" and the "order_count" and "action" of
+// the levels are optional. Unknown fields are ignored.
+
+type JsonObject = serde_json::Map<String, Value>;
+
+// Prefixes the messages of the errors of a line with its number
+fn line_error(line_no: usize) -> impl Fn(String) -> ParserError + Copy {
+    move |message| ParserError::Custom(format!("Line {}: {}", line_no, message))
+}
+
+fn parse_line(line: &str, line_no: usize) -> Result<JsonObject, ParserError> {
+    let line_error = line_error(line_no);
+    match serde_json::from_str(line).map_err(|e| line_error(e.to_string()))? {
+        Value::Object(object) => Ok(object),
+        _ => Err(line_error("expected an object".to_string())),
+    }
+}
+
+// The fields of an object, a null one is missing
+struct Fields<'a>(&'a JsonObject);
+
+impl<'a> Fields<'a> {
+    fn get(&self, name: &str) -> Option<&'a Value> {
+        self.0.get(name).filter(|value| !value.is_null())
+    }
+
+    fn optional<T>(
+        &self,
+        name: &str,
+        convert: impl FnOnce(&'a Value) -> Option<T>,
+    ) -> Result<Option<T>, String> {
+        self.get(name)
+            .map(|value| convert(value).ok_or_else(|| format!("invalid {}: {}", name, value)))
+            .transpose()
+    }
+
+    fn required<T>(
+        &self,
+        name: &str,
+        convert: impl FnOnce(&'a Value) -> Option<T>,
+    ) -> Result<T, String> {
+        self.optional(name, convert)?
+            .ok_or_else(|| format!("missing field `{}`", name))
+    }
+
+    fn u64(&self, name: &str) -> Result<u64, String> {
+        self.required(name, Value::as_u64)
+    }
+
+    fn f64(&self, name: &str) -> Result<f64, String> {
+        self.required(name, Value::as_f64)
+    }
+
+    // Objects of an array, empty when it is missing
+    fn objects(&self, name: &str) -> Result<Vec<Fields<'a>>, String> {
+        let values = self.optional(name, Value::as_array)?;
+        values
+            .into_iter()
+            .flatten()
+            .map(|value| {
+                value
+                    .as_object()
+                    .map(Fields)
+                    .ok_or_else(|| format!("invalid {}: {}", name, value))
+            })
+            .collect()
+    }
+}
+
+fn snapshot_level(level: &Fields) -> Result<SnapshotLevel, String> {
+    Ok(SnapshotLevel {
+        price: level.f64("price")?,
+        qty: Qty(level.u64("qty")?),
+    })
+}
+
+fn update_level(level: &Fields) -> Result<UpdateLevel, String> {
+    let side = match level.required("side", Some)? {
+        Value::Number(code) if code.as_u64() == Some(0) => Side::Bid,
+        Value::Number(code) if code.as_u64() == Some(1) => Side::Ask,
+        Value::String(name) if name == "bid" => Side::Bid,
+        Value::String(name) if name == "ask" => Side::Ask,
+        Value::String(name) => return Err(format!("invalid side: {}", name)),
+        side => return Err(format!("invalid side: {}", side)),
+    };
+    let order_count = level.optional("order_count", |value| {
+        value.as_u64().and_then(|value| u32::try_from(value).ok())
+    })?;
+    let action = level.optional("action", |value| {
+        value.as_u64().and_then(|value| u8::try_from(value).ok())
+    })?;
+    let metadata = match (order_count, action) {
+        (None, None) => None,
+        (order_count, action) => Some(LevelMetadata {
+            order_count: order_count.unwrap_or(0),
+            action: action.unwrap_or(0),
+        }),
+    };
+    Ok(UpdateLevel {
+        side,
+        price: level.f64("price")?,
+        qty: Qty(level.u64("qty")?),
+        metadata,
+    })
+}
+
+fn is_record(_line: &str) -> bool {
+    true
+}
+
+#[derive(Debug, Default)]
+pub struct JsonSnapshotParser {
+    line: String,
+    line_no: usize,
+}
+
+impl Parser<OrderBookSnapshot> for JsonSnapshotParser {
+    fn read<R: Read>(&mut self, reader: &mut R) -> Result<OrderBookSnapshot, ParserError> {
+        read_record_line(reader, &mut self.line, &mut self.line_no, is_record)?;
+        let line_no = self.line_no;
+        let line_error = line_error(line_no);
+        let object = parse_line(&self.line, line_no)?;
+        let snapshot = Fields(&object);
+        let side = |name: &str| -> Result<Vec<SnapshotLevel>, String> {
+            snapshot.objects(name)?.iter().map(snapshot_level).collect()
+        };
+        let (mut bids, mut asks) = (
+            side("bids").map_err(line_error)?,
+            side("asks").map_err(line_error)?,
+        );
+        if bids.len() > MAX_SNAPSHOT_DEPTH || asks.len() > MAX_SNAPSHOT_DEPTH {
+            return Err(line_error(format!(
+                "more than {} levels a side",
+                MAX_SNAPSHOT_DEPTH
+            )));
+        }
+        // Missing levels are empty, as in the binary snapshots of the same depth
+        let depth = SNAPSHOT_DEPTH.max(bids.len()).max(asks.len());
+        bids.resize_with(depth, || SnapshotLevel {
+            price: 0.0,
+            qty: Qty::ZERO,
+        });
+        asks.resize_with(depth, || SnapshotLevel {
+            price: 0.0,
+            qty: Qty::ZERO,
+        });
+        Ok(OrderBookSnapshot {
+            timestamp: snapshot.u64("timestamp").map_err(line_error)?,
+            seq_no: snapshot.u64("seq_no").map_err(line_error)?,
+            security_id: snapshot.u64("security_id").map_err(line_error)?,
+            bids,
+            asks,
+        })
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct JsonUpdateParser {
+    security_id_to_deque: HashMap<u64, BatchedDeque<UpdateLevel>>,
+    max_held_levels: Option<usize>,
+    line: String,
+    line_no: usize,
+}
+
+impl JsonUpdateParser {
+    // See OrderBookUpdateParser::with_max_held_levels
+    pub fn with_max_held_levels(mut self, max_held_levels: Option<usize>) -> Self {
+        self.max_held_levels = max_held_levels;
+        self
+    }
+}
+
+impl Parser<OrderBookUpdate> for JsonUpdateParser {
+    fn read<R: Read>(&mut self, reader: &mut R) -> Result<OrderBookUpdate, ParserError> {
+        read_record_line(reader, &mut self.line, &mut self.line_no, is_record)?;
+        let line_no = self.line_no;
+        let line_error = line_error(line_no);
+        let object = parse_line(&self.line, line_no)?;
+        let update = Fields(&object);
+        let timestamp = update.u64("timestamp").map_err(line_error)?;
+        let capture_timestamp = update
+            .optional("capture_timestamp", Value::as_u64)
+            .map_err(line_error)?;
+        let seq_no = update.u64("seq_no").map_err(line_error)?;
+        let security_id = update.u64("security_id").map_err(line_error)?;
+        update
+            .required("levels", Value::as_array)
+            .map_err(line_error)?;
+        let levels = update.objects("levels").map_err(line_error)?;
+        if levels.len() > MAX_NUM_UPDATES {
+            return Err(ParserError::Custom(format!(
+                "Number of updates is too large: {}",
+                levels.len()
+            )));
+        }
+
+        let levels = levels
+            .iter()
+            .map(|level| update_level(level).map_err(line_error));
+        let deque = self
+            .security_id_to_deque
+            .entry(security_id)
+            .or_insert_with(|| update_deque(DEFAULT_UPDATE_DEQUE_CAPACITY, self.max_held_levels));
+        Ok(OrderBookUpdate {
+            timestamp,
+            capture_timestamp,
+            seq_no,
+            security_id,
+            thinned_from: None,
+            updates: deque.push_back_batch(levels)?,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_json_snapshot() {
+        let data = r#"{"timestamp":1627846265,"seq_no":100,"security_id":1001,"venue":"x","bids":[{"price":100.0,"qty":10},{"price":99.0,"qty":20}],"asks":[{"price":101.0,"qty":15}]}"#;
+        let snapshot = JsonSnapshotParser::default()
+            .read(&mut data.as_bytes())
+            .unwrap();
+        assert_eq!(snapshot.timestamp, 1627846265);
+        assert_eq!(snapshot.seq_no, 100);
+        assert_eq!(snapshot.security_id, 1001);
+        assert_eq!(snapshot.bids[1].price, 99.0);
+        assert_eq!(snapshot.bids[1].qty, Qty(20));
+        assert_eq!(snapshot.asks[0].qty, Qty(15));
+        assert_eq!(snapshot.asks[1].qty, Qty(0));
+    }
+
+    #[test]
+    fn test_json_updates() {
+        let data = concat!(
+            r#"{"timestamp":1627846266,"seq_no":101,"security_id":1001,"levels":[{"side":"bid","price":100.5,"qty":7},{"side":1,"price":101.0,"qty":0,"order_count":0,"action":2}]}"#,
+            "\n\n",
+            r#"{"timestamp":1627846267,"capture_timestamp":1627846268,"seq_no":102,"security_id":1001,"levels":[]}"#,
+            "\n",
+        );
+        let mut parser = JsonUpdateParser::default();
+        let mut reader = data.as_bytes();
+
+        let update = parser.read(&mut reader).unwrap();
+        assert_eq!(update.seq_no, 101);
+        assert_eq!(update.capture_timestamp, None);
+        let mut levels = Vec::new();
+        update
+            .updates
+            .for_each(|level| {
+                levels.push((level.side, level.price, level.qty.value(), level.metadata));
+                Ok::<(), ()>(())
+            })
+            .unwrap();
+        assert_eq!(
+            levels,
+            vec![
+                (Side::Bid, 100.5, 7, None),
+                (
+                    Side::Ask,
+                    101.0,
+                    0,
+                    Some(LevelMetadata {
+                        order_count: 0,
+                        action: 2,
+                    })
+                ),
+            ]
+        );
+
+        let update = parser.read(&mut reader).unwrap();
+        assert_eq!(update.capture_timestamp, Some(1627846268));
+        assert!(matches!(
+            parser.read(&mut reader),
+            Err(ParserError::ExpectedEof)
+        ));
+    }
+
+    #[test]
+    fn test_json_invalid_lines() {
+        let mut parser = JsonUpdateParser::default();
+        let result = parser.read(&mut r#"{"timestamp":1,"seq_no":2}"#.as_bytes());
+        assert!(matches!(result, Err(ParserError::Custom(msg)) if msg.starts_with("Line 1:")));
+
+        let result = parser.read(
+            &mut r#"{"timestamp":1,"seq_no":2,"security_id":3,"levels":[{"side":"buy","price":1.0,"qty":1}]}"#
+                .as_bytes(),
+        );
+        assert!(
+            matches!(result, Err(ParserError::Custom(msg)) if msg == "Line 2: invalid side: buy")
+        );
+    }
+}
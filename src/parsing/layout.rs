@@ -0,0 +1,195 @@
+use std::io::{self, Read};
+
+use crate::order_book::units::Qty;
+use crate::parsing::parser::{Endianness, ParserError, field};
+
+// Largest fixed-size part of a record, the stack buffer of BinaryLayout::read
+pub(crate) const MAX_LAYOUT_SIZE: usize = 64;
+
+// A number stored in a record, in the byte order of its capture
+pub trait FieldBytes: Sized {
+    const SIZE: usize;
+
+    // Decodes the field from the start of `bytes`, which holds at least SIZE bytes
+    fn from_bytes(bytes: &[u8], endianness: Endianness) -> Self;
+}
+
+impl FieldBytes for u8 {
+    const SIZE: usize = 1;
+
+    fn from_bytes(bytes: &[u8], _endianness: Endianness) -> Self {
+        bytes[0]
+    }
+}
+
+impl FieldBytes for u32 {
+    const SIZE: usize = 4;
+
+    fn from_bytes(bytes: &[u8], endianness: Endianness) -> Self {
+        endianness.u32_from(field(bytes, 0))
+    }
+}
+
+impl FieldBytes for u64 {
+    const SIZE: usize = 8;
+
+    fn from_bytes(bytes: &[u8], endianness: Endianness) -> Self {
+        endianness.u64_from(field(bytes, 0))
+    }
+}
+
+impl FieldBytes for f64 {
+    const SIZE: usize = 8;
+
+    fn from_bytes(bytes: &[u8], endianness: Endianness) -> Self {
+        endianness.f64_from(field(bytes, 0))
+    }
+}
+
+// A u64 quantity, checked against the lot size of the security by the book it goes to
+impl FieldBytes for Qty {
+    const SIZE: usize = u64::SIZE;
+
+    fn from_bytes(bytes: &[u8], endianness: Endianness) -> Self {
+        Qty(u64::from_bytes(bytes, endianness))
+    }
+}
+
+// Decodes the field at `offset` and moves the offset past it
+pub(crate) fn take_field<T: FieldBytes>(
+    data: &[u8],
+    offset: &mut usize,
+    endianness: Endianness,
+) -> T {
+    let value = T::from_bytes(&data[*offset..], endianness);
+    *offset += T::SIZE;
+    value
+}
+
+// A fixed-size part of a record, a header or a level, declared with binary_layout! so
+// that the stream and the slice parsers of a record decode the same fields in the same
+// order
+pub trait BinaryLayout: Sized {
+    const SIZE: usize;
+    // Bytes of the first field, see read_record_start
+    const FIRST_FIELD_SIZE: usize;
+
+    // Decodes the fields from the start of `data`, which holds at least SIZE bytes
+    fn from_slice(data: &[u8], endianness: Endianness) -> Self;
+
+    fn read<R: Read>(reader: &mut R, endianness: Endianness) -> Result<Self, ParserError> {
+        let mut bytes = [0; MAX_LAYOUT_SIZE];
+        let bytes = &mut bytes[..Self::SIZE];
+        reader.read_exact(bytes).map_err(ParserError::Io)?;
+        Ok(Self::from_slice(bytes, endianness))
+    }
+
+    // Like read, for the part starting a record. The input ending within its first field
+    // is the end of the records, ExpectedEof.
+    fn read_record_start<R: Read>(
+        reader: &mut R,
+        endianness: Endianness,
+    ) -> Result<Self, ParserError> {
+        let mut bytes = [0; MAX_LAYOUT_SIZE];
+        let (first, rest) = bytes[..Self::SIZE].split_at_mut(Self::FIRST_FIELD_SIZE);
+        reader.read_exact(first).map_err(|e| {
+            if e.kind() == io::ErrorKind::UnexpectedEof {
+                return ParserError::ExpectedEof;
+            }
+            ParserError::Io(e)
+        })?;
+        reader.read_exact(rest).map_err(ParserError::Io)?;
+        Ok(Self::from_slice(&bytes, endianness))
+    }
+}
+
+// Declares a struct whose fields are stored one after the other in a record, without
+// padding, and implements BinaryLayout for it. The fields are FieldBytes numbers and the
+// struct has at least two of them.
+macro_rules! binary_layout {
+    (
+        $(#[$meta:meta])*
+        $vis:vis struct $name:ident {
+            $first_vis:vis $first:ident: $first_ty:ty,
+            $($field_vis:vis $field:ident: $ty:ty),+ $(,)?
+        }
+    ) => {
+        $(#[$meta])*
+        $vis struct $name {
+            $first_vis $first: $first_ty,
+            $($field_vis $field: $ty),+
+        }
+
+        impl $crate::parsing::layout::BinaryLayout for $name {
+            const SIZE: usize = <$first_ty as $crate::parsing::layout::FieldBytes>::SIZE
+                $(+ <$ty as $crate::parsing::layout::FieldBytes>::SIZE)+;
+            const FIRST_FIELD_SIZE: usize =
+                <$first_ty as $crate::parsing::layout::FieldBytes>::SIZE;
+
+            fn from_slice(data: &[u8], endianness: $crate::parsing::parser::Endianness) -> Self {
+                let mut offset = 0;
+                let $first = $crate::parsing::layout::take_field(data, &mut offset, endianness);
+                $(
+                    let $field =
+                        $crate::parsing::layout::take_field(data, &mut offset, endianness);
+                )+
+                Self { $first, $($field),+ }
+            }
+        }
+
+        const _: () = assert!(
+            <$name as $crate::parsing::layout::BinaryLayout>::SIZE
+                <= $crate::parsing::layout::MAX_LAYOUT_SIZE
+        );
+    };
+}
+
+pub(crate) use binary_layout;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    binary_layout! {
+        #[derive(Debug, PartialEq)]
+        struct TestLevel {
+            side: u8,
+            price: f64,
+            qty: u64,
+            order_count: u32,
+        }
+    }
+
+    #[test]
+    fn test_binary_layout() {
+        assert_eq!(<TestLevel as BinaryLayout>::SIZE, 21);
+        let level = TestLevel {
+            side: 1,
+            price: 100.5,
+            qty: 300,
+            order_count: 7,
+        };
+        let mut data = vec![1];
+        data.extend_from_slice(&100.5f64.to_be_bytes());
+        data.extend_from_slice(&300u64.to_be_bytes());
+        data.extend_from_slice(&7u32.to_be_bytes());
+        assert_eq!(TestLevel::from_slice(&data, Endianness::Big), level);
+        assert_eq!(
+            TestLevel::read_record_start(&mut data.as_slice(), Endianness::Big).unwrap(),
+            level
+        );
+
+        assert!(matches!(
+            TestLevel::read_record_start(&mut &data[..0], Endianness::Big),
+            Err(ParserError::ExpectedEof)
+        ));
+        assert!(matches!(
+            TestLevel::read_record_start(&mut &data[..5], Endianness::Big),
+            Err(ParserError::Io(_))
+        ));
+        assert!(matches!(
+            TestLevel::read(&mut &data[..0], Endianness::Big),
+            Err(ParserError::Io(_))
+        ));
+    }
+}
@@ -0,0 +1,369 @@
+use rayon::{ThreadPool, ThreadPoolBuilder};
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+use std::io::{self, BufReader};
+use std::mem;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::sync::mpsc::{self, Receiver, SyncSender};
+
+use crate::parsing::binary_file_iterator::LastFrame;
+use crate::parsing::compression::open_file;
+use crate::parsing::file_header::{HEADER_SIZE, UNVERSIONED, strip_file_header};
+use crate::parsing::framing::read_frame;
+use crate::parsing::order_book_update::{OrderBookUpdate, OrderBookUpdateParser, UpdateFormat};
+use crate::parsing::parser::{Endianness, Parser, ParserError, SecurityFilter, TimeWindow};
+
+// Records framed by a worker before it hands them over
+const FRAMES_PER_BATCH: usize = 256;
+// Batches a worker frames ahead of the merge
+const BATCHES_AHEAD: usize = 4;
+
+// The bytes of consecutive records of a file
+#[derive(Default)]
+struct FrameBatch {
+    // Offset of the first record in the decompressed file
+    offset: u64,
+    data: Vec<u8>,
+    // Where each record ends in data
+    ends: Vec<usize>,
+}
+
+struct FileStream {
+    path: PathBuf,
+    // The path as the source of the frames
+    source: Arc<str>,
+    receiver: Receiver<Result<FrameBatch, ParserError>>,
+    batch: FrameBatch,
+    // Index in batch.ends of the next record
+    next: usize,
+    // Next record of the file, waiting for its turn in the merge
+    head: Option<OrderBookUpdate>,
+    // Where the head starts and ends in batch.data
+    head_frame: (usize, usize),
+}
+
+// Reads several binary update files at once and yields their records merged by timestamp,
+// then seq_no. Each file is read, decompressed and framed on a thread of its own and the
+// records are decoded in place on the calling thread: parsed updates hold batches shared
+// with the parser, so only their bytes can cross threads.
+pub struct MergedUpdateFiles {
+    parser: OrderBookUpdateParser,
+    streams: Vec<FileStream>,
+    // Timestamp, seq_no and stream of the heads, earliest first
+    order: BinaryHeap<Reverse<(u64, u64, usize)>>,
+    started: bool,
+    // Reported after the record read before it
+    error: Option<io::Error>,
+    done: bool,
+    frames: Option<LastFrame>,
+    // A thread per file, as a worker blocked on a full channel must not keep the file
+    // the merge waits for from being read
+    _pool: ThreadPool,
+}
+
+impl MergedUpdateFiles {
+    pub fn open(
+        paths: &[PathBuf],
+        format: UpdateFormat,
+        endianness: Endianness,
+    ) -> io::Result<Self> {
+        let pool = ThreadPoolBuilder::new()
+            .num_threads(paths.len().max(1))
+            .thread_name(|i| format!("read-updates-{}", i))
+            .build()
+            .map_err(io::Error::other)?;
+        let streams = paths
+            .iter()
+            .map(|path| {
+                let (sender, receiver) = mpsc::sync_channel(BATCHES_AHEAD);
+                let worker_path = path.clone();
+                pool.spawn(move || frame_file(&worker_path, format, endianness, sender));
+                FileStream {
+                    path: path.clone(),
+                    source: path.display().to_string().into(),
+                    receiver,
+                    batch: FrameBatch::default(),
+                    next: 0,
+                    head: None,
+                    head_frame: (0, 0),
+                }
+            })
+            .collect();
+        Ok(Self {
+            parser: OrderBookUpdateParser::new(format).with_endianness(endianness),
+            streams,
+            order: BinaryHeap::new(),
+            started: false,
+            error: None,
+            done: false,
+            frames: None,
+            _pool: pool,
+        })
+    }
+
+    // Skips the records of the securities the filter doesn't accept
+    pub fn with_security_filter(mut self, security_filter: SecurityFilter) -> Self {
+        self.parser = self.parser.with_security_filter(security_filter);
+        self
+    }
+
+    // Skips the records sent outside the window
+    pub fn with_time_window(mut self, time_window: TimeWindow) -> Self {
+        self.parser = self.parser.with_time_window(time_window);
+        self
+    }
+
+    // See OrderBookUpdateParser::with_max_held_levels
+    pub fn with_max_held_levels(mut self, max_held_levels: Option<usize>) -> Self {
+        self.parser = self.parser.with_max_held_levels(max_held_levels);
+        self
+    }
+
+    // Keeps the frame of every record returned in `frames`, with the file it comes from
+    pub fn with_frames(mut self, frames: LastFrame) -> Self {
+        self.frames = Some(frames);
+        self
+    }
+
+    // Decodes the next record of the stream and queues it for the merge
+    fn advance(&mut self, index: usize) -> io::Result<()> {
+        let stream = &mut self.streams[index];
+        loop {
+            if stream.next == stream.batch.ends.len() {
+                match stream.receiver.recv() {
+                    Ok(Ok(batch)) => {
+                        stream.batch = batch;
+                        stream.next = 0;
+                    }
+                    Ok(Err(e)) => return Err(file_error(&stream.path, e)),
+                    // The worker reached the end of the file
+                    Err(_) => return Ok(()),
+                }
+            }
+            let start = match stream.next {
+                0 => 0,
+                next => stream.batch.ends[next - 1],
+            };
+            let end = stream.batch.ends[stream.next];
+            stream.next += 1;
+            let update = match self.parser.read_from_slice(&stream.batch.data[start..end]) {
+                Ok((update, _)) => update,
+                // The record was skipped by the filters of the parser
+                Err(ParserError::ExpectedEof) => continue,
+                Err(e) => return Err(file_error(&stream.path, e)),
+            };
+            self.order
+                .push(Reverse((update.timestamp, update.seq_no, index)));
+            stream.head = Some(update);
+            stream.head_frame = (start, end);
+            return Ok(());
+        }
+    }
+}
+
+impl Iterator for MergedUpdateFiles {
+    type Item = io::Result<OrderBookUpdate>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        if let Some(e) = self.error.take() {
+            self.done = true;
+            return Some(Err(e));
+        }
+        if !self.started {
+            self.started = true;
+            for index in 0..self.streams.len() {
+                if let Err(e) = self.advance(index) {
+                    self.done = true;
+                    return Some(Err(e));
+                }
+            }
+        }
+        let Reverse((_, _, index)) = self.order.pop()?;
+        let stream = &mut self.streams[index];
+        let update = stream.head.take().expect("Queued head");
+        if let Some(frames) = &self.frames {
+            let (start, end) = stream.head_frame;
+            let offset = stream.batch.offset + start as u64;
+            frames.set(Some(&stream.source), offset, &stream.batch.data[start..end]);
+        }
+        if let Err(e) = self.advance(index) {
+            self.error = Some(e);
+        }
+        Some(Ok(update))
+    }
+}
+
+fn file_error(path: &Path, err: ParserError) -> io::Error {
+    match err {
+        ParserError::Io(e) => io::Error::new(e.kind(), format!("{}: {}", path.display(), e)),
+        ParserError::ExpectedEof => io::Error::new(
+            io::ErrorKind::UnexpectedEof,
+            format!("{}: unexpected end of file", path.display()),
+        ),
+        ParserError::Custom(msg) => io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("{}: {}", path.display(), msg),
+        ),
+    }
+}
+
+// Sends the records of the file in batches, then the error that ended it if any. Stops
+// early once the merge is dropped.
+fn frame_file(
+    path: &Path,
+    format: UpdateFormat,
+    endianness: Endianness,
+    sender: SyncSender<Result<FrameBatch, ParserError>>,
+) {
+    let framing = OrderBookUpdateParser::new(format).with_endianness(endianness);
+    let reader = open_file(path)
+        .and_then(strip_file_header)
+        .map_err(ParserError::Io);
+    let (mut reader, mut offset) = match reader {
+        Ok((version, reader)) if version == UNVERSIONED => (BufReader::new(reader), 0),
+        Ok((_, reader)) => (BufReader::new(reader), HEADER_SIZE as u64),
+        Err(e) => {
+            let _ = sender.send(Err(e));
+            return;
+        }
+    };
+    let mut frame = Vec::new();
+    let mut batch = FrameBatch {
+        offset,
+        ..FrameBatch::default()
+    };
+    loop {
+        let error = match read_frame::<OrderBookUpdate, _, _>(&mut reader, &framing, &mut frame) {
+            Ok(()) => None,
+            Err(ParserError::ExpectedEof) => break,
+            Err(e) => Some(e),
+        };
+        if let Some(e) = error {
+            if !batch.ends.is_empty() && sender.send(Ok(batch)).is_err() {
+                return;
+            }
+            let _ = sender.send(Err(e));
+            return;
+        }
+        batch.data.extend_from_slice(&frame);
+        batch.ends.push(batch.data.len());
+        offset += frame.len() as u64;
+        if batch.ends.len() == FRAMES_PER_BATCH {
+            let next = FrameBatch {
+                offset,
+                ..FrameBatch::default()
+            };
+            if sender.send(Ok(mem::replace(&mut batch, next))).is_err() {
+                return;
+            }
+        }
+    }
+    if !batch.ends.is_empty() {
+        let _ = sender.send(Ok(batch));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::fs;
+
+    fn update(data: &mut Vec<u8>, timestamp: u64, seq_no: u64, security_id: u64) {
+        data.extend_from_slice(&timestamp.to_le_bytes());
+        data.extend_from_slice(&seq_no.to_le_bytes());
+        data.extend_from_slice(&security_id.to_le_bytes());
+        data.extend_from_slice(&1u64.to_le_bytes()); // num_updates
+        data.push(0); // side
+        data.extend_from_slice(&100.0f64.to_le_bytes()); // price
+        data.extend_from_slice(&10u64.to_le_bytes()); // qty
+    }
+
+    fn write_files(name: &str, files: &[Vec<u8>]) -> Vec<PathBuf> {
+        let dir = env::temp_dir().join(format!("order_book_{}_{}", name, std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        files
+            .iter()
+            .enumerate()
+            .map(|(i, data)| {
+                let path = dir.join(format!("updates_{}.bin", i));
+                fs::write(&path, data).unwrap();
+                path
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_merged_by_timestamp() {
+        let mut first = Vec::new();
+        let mut second = Vec::new();
+        // More records than a batch, interleaved between the files
+        for i in 0..600 {
+            update(&mut first, 2 * i, i + 1, 7);
+            update(&mut second, 2 * i + 1, i + 1, 8);
+        }
+        let paths = write_files("merged", &[first, second, Vec::new()]);
+
+        let records = MergedUpdateFiles::open(&paths, UpdateFormat::V1, Endianness::Little)
+            .unwrap()
+            .map(|update| {
+                let update = update.unwrap();
+                (update.timestamp, update.security_id)
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(records.len(), 1200);
+        assert!(
+            records
+                .iter()
+                .enumerate()
+                .all(|(i, (timestamp, security_id))| {
+                    *timestamp == i as u64 && *security_id == 7 + i as u64 % 2
+                })
+        );
+        fs::remove_dir_all(paths[0].parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn test_security_filter() {
+        let mut first = Vec::new();
+        let mut second = Vec::new();
+        for i in 0..10 {
+            update(&mut first, 2 * i, i + 1, 7 + i % 2);
+            update(&mut second, 2 * i + 1, i + 1, 9);
+        }
+        let paths = write_files("filtered", &[first, second]);
+
+        let records = MergedUpdateFiles::open(&paths, UpdateFormat::V1, Endianness::Little)
+            .unwrap()
+            .with_security_filter(SecurityFilter::only([8, 9]))
+            .map(|update| update.unwrap().security_id)
+            .collect::<Vec<_>>();
+        assert_eq!(records.len(), 15);
+        assert!(records.iter().all(|security_id| *security_id != 7));
+        fs::remove_dir_all(paths[0].parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn test_error_after_records_read() {
+        let mut first = Vec::new();
+        update(&mut first, 1, 1, 7);
+        update(&mut first, 3, 2, 7);
+        first.truncate(first.len() - 4);
+        let mut second = Vec::new();
+        update(&mut second, 2, 1, 8);
+        let paths = write_files("truncated", &[first, second]);
+
+        let mut records =
+            MergedUpdateFiles::open(&paths, UpdateFormat::V1, Endianness::Little).unwrap();
+        assert_eq!(records.next().unwrap().unwrap().timestamp, 1);
+        let err = records.next().unwrap().unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+        assert!(err.to_string().contains("updates_0.bin"));
+        assert!(records.next().is_none());
+        fs::remove_dir_all(paths[0].parent().unwrap()).unwrap();
+    }
+}
@@ -0,0 +1,107 @@
+use crate::parsing::binary_file_iterator::{FrameCapture, LastFrame, StreamPosition};
+use crate::parsing::compression::Compression;
+use crate::parsing::file_header::parse_file_header;
+use crate::parsing::framing::Framing;
+use crate::parsing::parser::ParserError;
+use crate::parsing::parser::{DefaultParser, Parser};
+use memmap2::Mmap;
+use std::fs::File;
+use std::io;
+use std::path::Path;
+
+// Parses the records straight from a memory map of the file, without the copies and
+// read calls of a BufReader. Only for uncompressed files.
+pub struct MmapFileIterator<T: DefaultParser<T>> {
+    mmap: Mmap,
+    offset: usize,
+    parser: T::ParserType,
+    version: u32,
+    position: StreamPosition,
+    frames: Option<FrameCapture<T::ParserType>>,
+}
+
+impl<T: DefaultParser<T>> MmapFileIterator<T> {
+    pub fn new(file: &File) -> io::Result<Self> {
+        Self::with_parser(file, T::default_parser())
+    }
+
+    pub fn with_parser(file: &File, parser: T::ParserType) -> io::Result<Self> {
+        // SAFETY: the captures are not modified while they are replayed, a truncated
+        // file would fault the same way a concurrent write corrupts a buffered read
+        let mmap = unsafe { Mmap::map(file)? };
+        if Compression::detect(None, &mmap) != Compression::None {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "Compressed files can't be memory-mapped",
+            ));
+        }
+        let (version, header_size) = parse_file_header(&mmap)?;
+        let position = StreamPosition::default();
+        position.advance(header_size as u64);
+        Ok(Self {
+            mmap,
+            offset: header_size,
+            parser,
+            version,
+            position,
+            frames: None,
+        })
+    }
+
+    // See BinaryFileIterator::with_frames
+    pub fn with_frames(mut self, frames: LastFrame) -> Self
+    where
+        T::ParserType: Framing<T>,
+    {
+        self.frames = Some(FrameCapture::new(frames));
+        self
+    }
+
+    pub fn open(path: &Path, parser: T::ParserType) -> io::Result<Self> {
+        if Compression::detect(Some(path), &[]) != Compression::None {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "Compressed files can't be memory-mapped",
+            ));
+        }
+        Self::with_parser(&File::open(path)?, parser)
+    }
+
+    pub fn format_version(&self) -> u32 {
+        self.version
+    }
+
+    // See BinaryFileIterator::position
+    pub fn position(&self) -> StreamPosition {
+        self.position.clone()
+    }
+}
+
+impl<T: DefaultParser<T>> Iterator for MmapFileIterator<T> {
+    type Item = io::Result<T>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self.parser.read_from_slice(&self.mmap[self.offset..]) {
+            Ok((item, consumed)) => {
+                if let Some(frames) = &self.frames {
+                    let data = &self.mmap[self.offset..self.offset + consumed];
+                    frames.capture(&self.parser, self.position.get(), data);
+                }
+                self.offset += consumed;
+                self.position.advance(consumed as u64);
+                Some(Ok(item))
+            }
+            Err(err) => {
+                // The record can't be skipped, so the iteration ends after the error
+                self.offset = self.mmap.len();
+                match err {
+                    ParserError::Io(io_err) => Some(Err(io_err)),
+                    ParserError::ExpectedEof => None,
+                    ParserError::Custom(msg) => {
+                        Some(Err(io::Error::new(io::ErrorKind::InvalidData, msg)))
+                    }
+                }
+            }
+        }
+    }
+}
@@ -1,102 +1,387 @@
-use crate::parsing::parser::{DefaultParser, Parser, ParserError};
-use std::io::{self, Read};
+use crate::order_book::units::Qty;
+use crate::parsing::layout::{BinaryLayout, binary_layout};
+use crate::parsing::parser::{
+    DefaultParser, Endianness, Parser, ParserError, SecurityFilter, TimeWindow, skip_bytes,
+    truncated_record,
+};
+use std::io::{self, Read, Write};
+use std::str::FromStr;
+
+// Levels of each side of a snapshot unless the parser is given another depth
+pub const SNAPSHOT_DEPTH: usize = 5;
+// Timestamp, seq_no and security_id followed by 5 bid and 5 ask levels of price and qty
+pub const SNAPSHOT_SIZE: usize = snapshot_size(SNAPSHOT_DEPTH);
+// Deepest V1 snapshots the parser reads, venues publish 5, 10 or 20 levels
+pub const MAX_SNAPSHOT_DEPTH: usize = 20;
+// Larger numbers of levels of V2 snapshots are taken for corrupt data rather than allocated
+pub const MAX_NUM_LEVELS: usize = 10_000;
+// Price and qty of a bid level followed by those of an ask level
+pub const LEVEL_PAIR_SIZE: usize = 2 * <Level as BinaryLayout>::SIZE;
+
+// Bytes of a V1 snapshot with `depth` bid and `depth` ask levels, which alternate from the
+// best bid and ask down
+pub const fn snapshot_size(depth: usize) -> usize {
+    <SnapshotHeader as BinaryLayout>::SIZE + depth * LEVEL_PAIR_SIZE
+}
 
-#[derive(Debug)]
-pub struct Level {
-    pub price: f64,
-    pub qty: u64,
+// V1 snapshots hold as many levels a side as the depth of their parser, 5 unless it is
+// given another one. V2 snapshots add the number of levels a side (u64) after the
+// security_id, followed by that many pairs of a bid and an ask level, so that the
+// snapshots of venues with an irregular depth are kept whole.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum SnapshotFormat {
+    #[default]
+    V1,
+    V2,
+}
+
+impl SnapshotFormat {
+    // Bytes of a record before its levels
+    pub fn header_size(self) -> usize {
+        match self {
+            SnapshotFormat::V1 => <SnapshotHeader as BinaryLayout>::SIZE,
+            SnapshotFormat::V2 => <SnapshotV2Header as BinaryLayout>::SIZE,
+        }
+    }
+}
+
+impl FromStr for SnapshotFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "v1" => Ok(SnapshotFormat::V1),
+            "v2" => Ok(SnapshotFormat::V2),
+            _ => Err(format!("Unknown snapshot format: {}", s)),
+        }
+    }
+}
+
+fn num_levels_too_large(num_levels: usize) -> ParserError {
+    ParserError::Custom(format!("Number of levels is too large: {}", num_levels))
+}
+
+binary_layout! {
+    #[derive(Debug)]
+    #[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+    pub struct Level {
+        pub price: f64,
+        pub qty: Qty,
+    }
+}
+
+binary_layout! {
+    struct SnapshotHeader {
+        timestamp: u64,
+        seq_no: u64,
+        security_id: u64,
+    }
+}
+
+binary_layout! {
+    struct SnapshotV2Header {
+        timestamp: u64,
+        seq_no: u64,
+        security_id: u64,
+        num_levels: u64,
+    }
+}
+
+impl From<SnapshotV2Header> for SnapshotHeader {
+    fn from(header: SnapshotV2Header) -> Self {
+        Self {
+            timestamp: header.timestamp,
+            seq_no: header.seq_no,
+            security_id: header.security_id,
+        }
+    }
 }
 
 #[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct OrderBookSnapshot {
     pub timestamp: u64,
     pub seq_no: u64,
     pub security_id: u64,
-    pub bid1: Level,
-    pub ask1: Level,
-    pub bid2: Level,
-    pub ask2: Level,
-    pub bid3: Level,
-    pub ask3: Level,
-    pub bid4: Level,
-    pub ask4: Level,
-    pub bid5: Level,
-    pub ask5: Level,
-}
-
-struct LevelParser;
-
-impl Parser<Level> for LevelParser {
-    fn read<R: Read>(&mut self, reader: &mut R) -> Result<Level, ParserError> {
-        let price = {
-            let mut price = [0; 8];
-            reader.read_exact(&mut price).map_err(ParserError::Io)?;
-            f64::from_le_bytes(price)
-        };
-        let qty = {
-            let mut qty = [0; 8];
-            reader.read_exact(&mut qty).map_err(ParserError::Io)?;
-            u64::from_le_bytes(qty)
+    // Best level first, a level with a zero quantity is missing from the book
+    pub bids: Vec<Level>,
+    pub asks: Vec<Level>,
+}
+
+impl Level {
+    fn write<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        writer.write_all(&self.price.to_le_bytes())?;
+        writer.write_all(&self.qty.value().to_le_bytes())
+    }
+}
+
+impl OrderBookSnapshot {
+    // Levels of each side, the shorter side is written with empty levels
+    pub fn depth(&self) -> usize {
+        self.bids.len().max(self.asks.len())
+    }
+
+    // Encodes the snapshot in the layout read by an OrderBookSnapshotParser of the format,
+    // and for V1 of its depth
+    pub fn write<W: Write>(&self, writer: &mut W, format: SnapshotFormat) -> io::Result<()> {
+        writer.write_all(&self.timestamp.to_le_bytes())?;
+        writer.write_all(&self.seq_no.to_le_bytes())?;
+        writer.write_all(&self.security_id.to_le_bytes())?;
+        if format == SnapshotFormat::V2 {
+            writer.write_all(&(self.depth() as u64).to_le_bytes())?;
+        }
+        let empty = Level {
+            price: 0.0,
+            qty: Qty::ZERO,
         };
-        Ok(Level { price, qty })
+        for index in 0..self.depth() {
+            self.bids.get(index).unwrap_or(&empty).write(writer)?;
+            self.asks.get(index).unwrap_or(&empty).write(writer)?;
+        }
+        Ok(())
     }
 }
 
-#[derive(Debug, Default)]
-pub struct OrderBookSnapshotParser;
+#[derive(Debug, Clone)]
+pub struct OrderBookSnapshotParser {
+    endianness: Endianness,
+    format: SnapshotFormat,
+    depth: usize,
+    security_filter: SecurityFilter,
+    time_window: TimeWindow,
+}
+
+impl Default for OrderBookSnapshotParser {
+    fn default() -> Self {
+        Self::new(Endianness::default())
+    }
+}
+
+impl OrderBookSnapshotParser {
+    pub fn new(endianness: Endianness) -> Self {
+        Self {
+            endianness,
+            format: SnapshotFormat::V1,
+            depth: SNAPSHOT_DEPTH,
+            security_filter: SecurityFilter::default(),
+            time_window: TimeWindow::default(),
+        }
+    }
+
+    pub fn with_format(mut self, format: SnapshotFormat) -> Self {
+        self.format = format;
+        self
+    }
+
+    // Reads V1 snapshots of `depth` levels per side, from 1 to MAX_SNAPSHOT_DEPTH
+    pub fn with_depth(mut self, depth: usize) -> Result<Self, ParserError> {
+        if depth == 0 || depth > MAX_SNAPSHOT_DEPTH {
+            return Err(ParserError::Custom(format!(
+                "Snapshot depth {} is not between 1 and {}",
+                depth, MAX_SNAPSHOT_DEPTH
+            )));
+        }
+        self.depth = depth;
+        Ok(self)
+    }
+
+    // Skips the snapshots of the securities the filter doesn't accept
+    pub fn with_security_filter(mut self, security_filter: SecurityFilter) -> Self {
+        self.security_filter = security_filter;
+        self
+    }
+
+    // Skips the snapshots taken outside the window
+    pub fn with_time_window(mut self, time_window: TimeWindow) -> Self {
+        self.time_window = time_window;
+        self
+    }
+
+    pub fn endianness(&self) -> Endianness {
+        self.endianness
+    }
+
+    pub fn format(&self) -> SnapshotFormat {
+        self.format
+    }
+
+    pub fn depth(&self) -> usize {
+        self.depth
+    }
+
+    // Bytes of every V1 snapshot read by the parser
+    pub fn snapshot_size(&self) -> usize {
+        snapshot_size(self.depth)
+    }
+
+    pub fn security_filter(&self) -> &SecurityFilter {
+        &self.security_filter
+    }
+
+    pub fn time_window(&self) -> TimeWindow {
+        self.time_window
+    }
+
+    fn skips(&self, security_id: u64, timestamp: u64) -> bool {
+        !self.security_filter.accepts(security_id) || !self.time_window.includes(timestamp)
+    }
+}
 
 impl DefaultParser<OrderBookSnapshot> for OrderBookSnapshot {
     type ParserType = OrderBookSnapshotParser;
 
     fn default_parser() -> OrderBookSnapshotParser {
-        OrderBookSnapshotParser
+        OrderBookSnapshotParser::default()
     }
 }
 
 impl Parser<OrderBookSnapshot> for OrderBookSnapshotParser {
     fn read<R: Read>(&mut self, reader: &mut R) -> Result<OrderBookSnapshot, ParserError> {
-        let timestamp = {
-            let mut timestamp = [0; 8];
-            match reader.read_exact(&mut timestamp) {
-                Ok(_) => (),
-                Err(e) => {
-                    if e.kind() == io::ErrorKind::UnexpectedEof {
-                        return Err(ParserError::ExpectedEof);
+        loop {
+            let (header, depth) = match self.format {
+                SnapshotFormat::V1 => (
+                    SnapshotHeader::read_record_start(reader, self.endianness)?,
+                    self.depth,
+                ),
+                SnapshotFormat::V2 => {
+                    let header = SnapshotV2Header::read_record_start(reader, self.endianness)?;
+                    let num_levels = header.num_levels as usize;
+                    if num_levels > MAX_NUM_LEVELS {
+                        return Err(num_levels_too_large(num_levels));
                     }
-                    return Err(ParserError::Io(e));
+                    (header.into(), num_levels)
                 }
+            };
+            if self.skips(header.security_id, header.timestamp) {
+                skip_bytes(reader, depth * LEVEL_PAIR_SIZE)?;
+                continue;
             }
-            u64::from_le_bytes(timestamp)
-        };
-        let seq_no = {
-            let mut seq_no = [0; 8];
-            reader.read_exact(&mut seq_no).map_err(ParserError::Io)?;
-            u64::from_le_bytes(seq_no)
+
+            let mut bids = Vec::with_capacity(depth);
+            let mut asks = Vec::with_capacity(depth);
+            for _ in 0..depth {
+                bids.push(Level::read(reader, self.endianness)?);
+                asks.push(Level::read(reader, self.endianness)?);
+            }
+            return Ok(OrderBookSnapshot {
+                timestamp: header.timestamp,
+                seq_no: header.seq_no,
+                security_id: header.security_id,
+                bids,
+                asks,
+            });
+        }
+    }
+
+    // Snapshots skipped by the filters at the start of `data` are counted in the bytes
+    // taken. ExpectedEof then means `data` held no other record.
+    fn read_from_slice(&mut self, data: &[u8]) -> Result<(OrderBookSnapshot, usize), ParserError> {
+        let mut skipped = 0;
+        while let Some(record_size) = self.skipped_record_size(&data[skipped..]) {
+            skipped += record_size;
+        }
+        let (snapshot, consumed) = self.read_record_from_slice(&data[skipped..])?;
+        Ok((snapshot, skipped + consumed))
+    }
+}
+
+impl OrderBookSnapshotParser {
+    // The header of the record starting `data`, which holds it whole, with the levels a
+    // side of the record
+    fn header_from_slice(&self, data: &[u8]) -> (SnapshotHeader, usize) {
+        match self.format {
+            SnapshotFormat::V1 => (
+                SnapshotHeader::from_slice(data, self.endianness),
+                self.depth,
+            ),
+            SnapshotFormat::V2 => {
+                let header = SnapshotV2Header::from_slice(data, self.endianness);
+                let num_levels = header.num_levels as usize;
+                (header.into(), num_levels)
+            }
+        }
+    }
+
+    // The size of the record at the start of `data` when it is whole and skipped by the
+    // filters
+    fn skipped_record_size(&self, data: &[u8]) -> Option<usize> {
+        let header_size = self.format.header_size();
+        if data.len() < header_size {
+            return None;
+        }
+        let (header, depth) = self.header_from_slice(data);
+        if !self.skips(header.security_id, header.timestamp) || depth > MAX_NUM_LEVELS {
+            return None;
+        }
+        let record_size = header_size + depth * LEVEL_PAIR_SIZE;
+        (data.len() >= record_size).then_some(record_size)
+    }
+
+    fn read_record_from_slice(
+        &mut self,
+        data: &[u8],
+    ) -> Result<(OrderBookSnapshot, usize), ParserError> {
+        if data.len() < 8 {
+            return Err(ParserError::ExpectedEof);
+        }
+        let header_size = self.format.header_size();
+        if data.len() < header_size {
+            return Err(truncated_record());
+        }
+        let (header, depth) = self.header_from_slice(data);
+        if depth > MAX_NUM_LEVELS {
+            return Err(num_levels_too_large(depth));
+        }
+        let record_size = header_size + depth * LEVEL_PAIR_SIZE;
+        if data.len() < record_size {
+            return Err(truncated_record());
+        }
+        let level = |index: usize| {
+            let offset = header_size + index * <Level as BinaryLayout>::SIZE;
+            Level::from_slice(&data[offset..], self.endianness)
         };
-        let security_id = {
-            let mut security_id = [0; 8];
-            reader
-                .read_exact(&mut security_id)
-                .map_err(ParserError::Io)?;
-            u64::from_le_bytes(security_id)
+        let snapshot = OrderBookSnapshot {
+            timestamp: header.timestamp,
+            seq_no: header.seq_no,
+            security_id: header.security_id,
+            bids: (0..depth).map(|index| level(2 * index)).collect(),
+            asks: (0..depth).map(|index| level(2 * index + 1)).collect(),
         };
+        Ok((snapshot, record_size))
+    }
+}
 
-        let mut level_parser = LevelParser;
-        Ok(OrderBookSnapshot {
-            timestamp,
+// Snapshots shared by the tests of the books and of what is built on them
+#[cfg(test)]
+pub(crate) mod test_support {
+    use super::*;
+
+    // Five levels a side, bids from 100 and asks from 101 one apart, with quantities
+    // growing away from the touch
+    pub(crate) fn create_test_snapshot(security_id: u64, seq_no: u64) -> OrderBookSnapshot {
+        let level = |price: f64, qty: u64| Level {
+            price,
+            qty: Qty(qty),
+        };
+        OrderBookSnapshot {
+            timestamp: 1627846265,
             seq_no,
             security_id,
-            bid1: level_parser.read(reader)?,
-            ask1: level_parser.read(reader)?,
-            bid2: level_parser.read(reader)?,
-            ask2: level_parser.read(reader)?,
-            bid3: level_parser.read(reader)?,
-            ask3: level_parser.read(reader)?,
-            bid4: level_parser.read(reader)?,
-            ask4: level_parser.read(reader)?,
-            bid5: level_parser.read(reader)?,
-            ask5: level_parser.read(reader)?,
-        })
+            bids: vec![
+                level(100.0, 10),
+                level(99.0, 20),
+                level(98.0, 30),
+                level(97.0, 40),
+                level(96.0, 50),
+            ],
+            asks: vec![
+                level(101.0, 15),
+                level(102.0, 25),
+                level(103.0, 35),
+                level(104.0, 45),
+                level(105.0, 55),
+            ],
+        }
     }
 }
 
@@ -133,7 +418,7 @@ mod tests {
     fn test_parse_order_book_snapshot() {
         let test_data = create_test_data();
         let mut cursor = Cursor::new(test_data);
-        let mut parser = OrderBookSnapshotParser;
+        let mut parser = OrderBookSnapshotParser::default();
 
         let result = parser.read(&mut cursor);
         assert!(result.is_ok(), "Failed to parse order book snapshot");
@@ -144,36 +429,69 @@ mod tests {
         assert_eq!(snapshot.security_id, 123456);
 
         // Check all bid levels
-        assert_eq!(snapshot.bid1.price, 1000.0);
-        assert_eq!(snapshot.bid1.qty, 100);
+        assert_eq!(snapshot.bids[0].price, 1000.0);
+        assert_eq!(snapshot.bids[0].qty, Qty(100));
 
-        assert_eq!(snapshot.bid2.price, 1001.0);
-        assert_eq!(snapshot.bid2.qty, 120);
+        assert_eq!(snapshot.bids[1].price, 1001.0);
+        assert_eq!(snapshot.bids[1].qty, Qty(120));
 
-        assert_eq!(snapshot.bid3.price, 1002.0);
-        assert_eq!(snapshot.bid3.qty, 140);
+        assert_eq!(snapshot.bids[2].price, 1002.0);
+        assert_eq!(snapshot.bids[2].qty, Qty(140));
 
-        assert_eq!(snapshot.bid4.price, 1003.0);
-        assert_eq!(snapshot.bid4.qty, 160);
+        assert_eq!(snapshot.bids[3].price, 1003.0);
+        assert_eq!(snapshot.bids[3].qty, Qty(160));
 
-        assert_eq!(snapshot.bid5.price, 1004.0);
-        assert_eq!(snapshot.bid5.qty, 180);
+        assert_eq!(snapshot.bids[4].price, 1004.0);
+        assert_eq!(snapshot.bids[4].qty, Qty(180));
 
         // Check all ask levels
-        assert_eq!(snapshot.ask1.price, 1000.5);
-        assert_eq!(snapshot.ask1.qty, 110);
+        assert_eq!(snapshot.asks[0].price, 1000.5);
+        assert_eq!(snapshot.asks[0].qty, Qty(110));
+
+        assert_eq!(snapshot.asks[1].price, 1001.5);
+        assert_eq!(snapshot.asks[1].qty, Qty(130));
+
+        assert_eq!(snapshot.asks[2].price, 1002.5);
+        assert_eq!(snapshot.asks[2].qty, Qty(150));
+
+        assert_eq!(snapshot.asks[3].price, 1003.5);
+        assert_eq!(snapshot.asks[3].qty, Qty(170));
 
-        assert_eq!(snapshot.ask2.price, 1001.5);
-        assert_eq!(snapshot.ask2.qty, 130);
+        assert_eq!(snapshot.asks[4].price, 1004.5);
+        assert_eq!(snapshot.asks[4].qty, Qty(190));
+    }
 
-        assert_eq!(snapshot.ask3.price, 1002.5);
-        assert_eq!(snapshot.ask3.qty, 150);
+    #[test]
+    fn test_parse_big_endian() {
+        let mut data = Vec::new();
+        data.extend_from_slice(&1234567890u64.to_be_bytes()); // timestamp
+        data.extend_from_slice(&42u64.to_be_bytes()); // seq_no
+        data.extend_from_slice(&123456u64.to_be_bytes()); // security_id
+        for i in 0..10 {
+            data.extend_from_slice(&(1000.0 + (i as f64) * 0.5).to_be_bytes()); // price
+            data.extend_from_slice(&(100 + (i as u64) * 10).to_be_bytes()); // qty
+        }
 
-        assert_eq!(snapshot.ask4.price, 1003.5);
-        assert_eq!(snapshot.ask4.qty, 170);
+        let mut parser = OrderBookSnapshotParser::new(Endianness::Big);
+        let snapshot = parser.read(&mut Cursor::new(data)).unwrap();
+        assert_eq!(snapshot.timestamp, 1234567890);
+        assert_eq!(snapshot.seq_no, 42);
+        assert_eq!(snapshot.security_id, 123456);
+        assert_eq!(snapshot.bids[0].price, 1000.0);
+        assert_eq!(snapshot.asks[4].price, 1004.5);
+        assert_eq!(snapshot.asks[4].qty, Qty(190));
+    }
 
-        assert_eq!(snapshot.ask5.price, 1004.5);
-        assert_eq!(snapshot.ask5.qty, 190);
+    #[test]
+    fn test_write_round_trip() {
+        let test_data = create_test_data();
+        let snapshot = OrderBookSnapshotParser::default()
+            .read(&mut Cursor::new(test_data.clone()))
+            .unwrap();
+
+        let mut written = Vec::new();
+        snapshot.write(&mut written, SnapshotFormat::V1).unwrap();
+        assert_eq!(written, test_data);
     }
 
     #[test]
@@ -181,7 +499,7 @@ mod tests {
         // Test with incomplete data (only timestamp)
         let incomplete_data = 1234567890u64.to_le_bytes().to_vec();
         let mut cursor = Cursor::new(incomplete_data);
-        let mut parser = OrderBookSnapshotParser;
+        let mut parser = OrderBookSnapshotParser::default();
 
         let result = parser.read(&mut cursor);
         assert!(result.is_err());
@@ -197,7 +515,7 @@ mod tests {
         // Test with empty data
         let empty_data: Vec<u8> = Vec::new();
         let mut cursor = Cursor::new(empty_data);
-        let mut parser = OrderBookSnapshotParser;
+        let mut parser = OrderBookSnapshotParser::default();
 
         let result = parser.read(&mut cursor);
         assert!(result.is_err());
@@ -215,13 +533,145 @@ mod tests {
         data.extend_from_slice(&789u64.to_le_bytes()); // qty
 
         let mut cursor = Cursor::new(data);
-        let mut parser = LevelParser;
-
-        let result = parser.read(&mut cursor);
+        let result = Level::read(&mut cursor, Endianness::Little);
         assert!(result.is_ok());
 
         let level = result.unwrap();
         assert_eq!(level.price, 123.45);
-        assert_eq!(level.qty, 789);
+        assert_eq!(level.qty, Qty(789));
+    }
+
+    #[test]
+    fn test_read_from_slice() {
+        let mut data = create_test_data();
+        data.extend_from_slice(&[0; 3]);
+        let mut parser = OrderBookSnapshotParser::default();
+
+        let (snapshot, consumed) = parser.read_from_slice(&data).unwrap();
+        assert_eq!(consumed, SNAPSHOT_SIZE);
+        let mut written = Vec::new();
+        snapshot.write(&mut written, SnapshotFormat::V1).unwrap();
+        assert_eq!(written, data[..SNAPSHOT_SIZE]);
+
+        assert!(matches!(
+            parser.read_from_slice(&data[SNAPSHOT_SIZE..]),
+            Err(ParserError::ExpectedEof)
+        ));
+        assert!(matches!(
+            parser.read_from_slice(&data[..SNAPSHOT_SIZE - 1]),
+            Err(ParserError::Io(_))
+        ));
+    }
+
+    #[test]
+    fn test_snapshot_depth() {
+        for depth in [10, 20] {
+            let mut data = create_test_data()[..24].to_vec();
+            for i in 0..2 * depth {
+                data.extend_from_slice(&(1000.0 + (i as f64) * 0.5).to_le_bytes());
+                data.extend_from_slice(&(100 + (i as u64) * 10).to_le_bytes());
+            }
+            let mut parser = OrderBookSnapshotParser::default()
+                .with_depth(depth)
+                .unwrap();
+            assert_eq!(parser.snapshot_size(), data.len());
+
+            let snapshot = parser.read(&mut Cursor::new(data.clone())).unwrap();
+            assert_eq!((snapshot.bids.len(), snapshot.depth()), (depth, depth));
+            assert_eq!(snapshot.bids[depth - 1].price, 1000.0 + (depth - 1) as f64);
+            assert_eq!(
+                snapshot.asks[depth - 1].qty,
+                Qty(110 + (depth as u64 - 1) * 20)
+            );
+            let (from_slice, consumed) = parser.read_from_slice(&data).unwrap();
+            assert_eq!(consumed, data.len());
+            let mut written = Vec::new();
+            from_slice.write(&mut written, SnapshotFormat::V1).unwrap();
+            assert_eq!(written, data);
+
+            // A 5-level parser takes the deeper records for several shorter ones
+            let mut parser = OrderBookSnapshotParser::default();
+            assert_eq!(parser.read_from_slice(&data).unwrap().1, SNAPSHOT_SIZE);
+        }
+        assert!(OrderBookSnapshotParser::default().with_depth(0).is_err());
+        assert!(
+            OrderBookSnapshotParser::default()
+                .with_depth(MAX_SNAPSHOT_DEPTH + 1)
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn test_v2_snapshots() {
+        let mut data = Vec::new();
+        for (security_id, num_levels) in [(1, 3u64), (2, 0), (3, 25)] {
+            data.extend_from_slice(&1234567890u64.to_le_bytes());
+            data.extend_from_slice(&42u64.to_le_bytes());
+            data.extend_from_slice(&(security_id as u64).to_le_bytes());
+            data.extend_from_slice(&num_levels.to_le_bytes());
+            for i in 0..2 * num_levels {
+                data.extend_from_slice(&(1000.0 + (i as f64) * 0.5).to_le_bytes());
+                data.extend_from_slice(&(100 + i * 10).to_le_bytes());
+            }
+        }
+        let parser = || OrderBookSnapshotParser::default().with_format(SnapshotFormat::V2);
+
+        let mut reader = data.as_slice();
+        let mut written = Vec::new();
+        for depth in [3, 0, 25] {
+            let snapshot = parser().read(&mut reader).unwrap();
+            assert_eq!((snapshot.bids.len(), snapshot.asks.len()), (depth, depth));
+            snapshot.write(&mut written, SnapshotFormat::V2).unwrap();
+        }
+        assert_eq!(written, data);
+        let snapshot = parser()
+            .read(&mut &data[..32 + 3 * LEVEL_PAIR_SIZE])
+            .unwrap();
+        assert_eq!(snapshot.asks[2].price, 1002.5);
+        assert_eq!(snapshot.asks[2].qty, Qty(150));
+
+        let mut parser = parser().with_security_filter(SecurityFilter::only([3]));
+        let (snapshot, consumed) = parser.read_from_slice(&data).unwrap();
+        assert_eq!((snapshot.security_id, snapshot.depth()), (3, 25));
+        assert_eq!(consumed, data.len());
+        assert!(matches!(
+            parser.read_from_slice(&data[..data.len() - 1]),
+            Err(ParserError::Io(_))
+        ));
+
+        let mut corrupt = data[..32].to_vec();
+        corrupt[24..32].copy_from_slice(&(MAX_NUM_LEVELS as u64 + 1).to_le_bytes());
+        assert!(matches!(
+            OrderBookSnapshotParser::default()
+                .with_format(SnapshotFormat::V2)
+                .read(&mut corrupt.as_slice()),
+            Err(ParserError::Custom(_))
+        ));
+    }
+
+    #[test]
+    fn test_security_filter() {
+        let mut skipped = create_test_data();
+        skipped[16..24].copy_from_slice(&7u64.to_le_bytes());
+        let mut data = skipped.clone();
+        data.extend_from_slice(&create_test_data());
+        data.extend_from_slice(&skipped);
+        let mut parser =
+            OrderBookSnapshotParser::default().with_security_filter(SecurityFilter::only([123456]));
+
+        let mut reader = data.as_slice();
+        assert_eq!(parser.read(&mut reader).unwrap().security_id, 123456);
+        assert!(matches!(
+            parser.read(&mut reader),
+            Err(ParserError::ExpectedEof)
+        ));
+
+        let (snapshot, consumed) = parser.read_from_slice(&data).unwrap();
+        assert_eq!(snapshot.security_id, 123456);
+        assert_eq!(consumed, 2 * SNAPSHOT_SIZE);
+        assert!(matches!(
+            parser.read_from_slice(&data[consumed..]),
+            Err(ParserError::ExpectedEof)
+        ));
     }
 }
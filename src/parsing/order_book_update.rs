@@ -1,59 +1,562 @@
 use crate::batched_deque::batched_deque::BatchGuard;
 use crate::batched_deque::batched_deque::BatchedDeque;
+use crate::order_book::units::Qty;
+use crate::parsing::layout::{BinaryLayout, binary_layout};
 use crate::parsing::parser::ParserError;
-use crate::parsing::parser::{DefaultParser, Parser};
+use crate::parsing::parser::{
+    DefaultParser, Endianness, Parser, SecurityFilter, TimeWindow, skip_bytes, truncated_record,
+};
+use crate::parsing::pre_scan::CapacityHints;
+use std::cell::Ref;
 use std::collections::HashMap;
-use std::io::{self, Read};
+use std::io::{self, Read, Write};
+use std::ops::Deref;
+use std::str::FromStr;
 
-const DEFAULT_UPDATE_DEQUE_CAPACITY: usize = 10_000;
-const MAX_NUM_UPDATES: usize = 100_000;
+pub(crate) const DEFAULT_UPDATE_DEQUE_CAPACITY: usize = 10_000;
 
-#[derive(Debug)]
+// The deque of the levels of a security, bounded when a parser was given max_held_levels
+pub(crate) fn update_deque(capacity: usize, max_held_levels: Option<usize>) -> BatchedDeque<Level> {
+    let deque = BatchedDeque::new(capacity);
+    match max_held_levels {
+        Some(max_held_levels) => deque.with_max_len(max_held_levels),
+        None => deque,
+    }
+}
+
+pub const MAX_NUM_UPDATES: usize = 100_000;
+// Side code of the marker level of a thinned V2 record, see OrderBookUpdate::thinned_from
+pub const THINNING_MARKER_SIDE: u8 = 0xff;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "lowercase"))]
+pub enum Side {
+    Bid,
+    Ask,
+}
+
+impl Side {
+    // The code of the side in the binary formats
+    pub fn code(self) -> u8 {
+        match self {
+            Side::Bid => 0,
+            Side::Ask => 1,
+        }
+    }
+}
+
+impl TryFrom<u8> for Side {
+    type Error = ParserError;
+
+    fn try_from(code: u8) -> Result<Self, Self::Error> {
+        match code {
+            0 => Ok(Side::Bid),
+            1 => Ok(Side::Ask),
+            _ => Err(ParserError::Custom(format!("Invalid side: {}", code))),
+        }
+    }
+}
+
+binary_layout! {
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    #[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+    pub struct LevelMetadata {
+        pub order_count: u32,
+        pub action: u8,
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Level {
-    pub side: u8,
+    pub side: Side,
     pub price: f64,
-    pub qty: u64,
+    pub qty: Qty,
+    pub metadata: Option<LevelMetadata>,
+}
+
+// V1 records carry the exchange timestamp only and levels carry side, price and qty.
+// V2 records add the capture timestamp (u64) right after the exchange timestamp, and
+// each level is followed by the number of orders at the level (u32) and the venue
+// action code (u8).
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum UpdateFormat {
+    #[default]
+    V1,
+    V2,
+}
+
+impl UpdateFormat {
+    // Bytes of a record before its levels
+    pub fn header_size(self) -> usize {
+        match self {
+            UpdateFormat::V1 => <V1Header as BinaryLayout>::SIZE,
+            UpdateFormat::V2 => <V2Header as BinaryLayout>::SIZE,
+        }
+    }
+
+    pub fn level_size(self) -> usize {
+        match self {
+            UpdateFormat::V1 => <LevelFields as BinaryLayout>::SIZE,
+            UpdateFormat::V2 => {
+                <LevelFields as BinaryLayout>::SIZE + <LevelMetadata as BinaryLayout>::SIZE
+            }
+        }
+    }
+}
+
+impl FromStr for UpdateFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "v1" => Ok(UpdateFormat::V1),
+            "v2" => Ok(UpdateFormat::V2),
+            _ => Err(format!("Unknown update format: {}", s)),
+        }
+    }
+}
+
+// The levels of an update. The parsers keep them in the BatchGuards of their deques,
+// updates built by hand or by other crates may keep them anywhere else.
+pub trait LevelContainer {
+    type LevelRef<'a>: Deref<Target = Level>
+    where
+        Self: 'a;
+
+    fn iter(&self) -> impl Iterator<Item = Self::LevelRef<'_>>;
+
+    fn for_each<E>(&self, mut f: impl FnMut(&Level) -> Result<(), E>) -> Result<(), E> {
+        for level in self.iter() {
+            f(&level)?;
+        }
+        Ok(())
+    }
+}
+
+impl LevelContainer for Vec<Level> {
+    type LevelRef<'a> = &'a Level;
+
+    fn iter(&self) -> impl Iterator<Item = &Level> {
+        self.as_slice().iter()
+    }
+}
+
+impl LevelContainer for BatchGuard<Level> {
+    type LevelRef<'a> = Ref<'a, Level>;
+
+    fn iter(&self) -> impl Iterator<Item = Ref<'_, Level>> {
+        BatchGuard::iter(self)
+    }
+
+    fn for_each<E>(&self, f: impl FnMut(&Level) -> Result<(), E>) -> Result<(), E> {
+        BatchGuard::for_each(self, f)
+    }
 }
 
+// Updates of any container serialize their levels as a sequence, only the ones owning
+// them in a Vec deserialize
 #[derive(Debug)]
-pub struct OrderBookUpdate {
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct OrderBookUpdate<L = BatchGuard<Level>> {
     pub timestamp: u64,
+    // Time the gateway captured the message, only present in the V2 format
+    pub capture_timestamp: Option<u64>,
     pub seq_no: u64,
     pub security_id: u64,
-    pub updates: BatchGuard<Level>,
+    // seq_no the record follows when it was written by the thinning tool. Thinned archives
+    // replace runs of updates with one record holding the net change of the levels, which
+    // the replay accepts after the seq_no. Binary V2 records carry it as a marker level
+    // with the side THINNING_MARKER_SIDE and the seq_no as qty, before the other levels.
+    pub thinned_from: Option<u64>,
+    #[cfg_attr(
+        feature = "serde",
+        serde(
+            serialize_with = "serialize_levels",
+            bound(serialize = "L: LevelContainer")
+        )
+    )]
+    pub updates: L,
 }
 
-#[derive(Debug)]
-struct LevelParser;
-
-impl Parser<Level> for LevelParser {
-    fn read<R: Read>(&mut self, reader: &mut R) -> Result<Level, ParserError> {
-        // parse side
-        let side = {
-            let mut side = [0; 1];
-            reader.read_exact(&mut side).map_err(ParserError::Io)?;
-            side[0]
-        };
-        // parse price
-        let price = {
-            let mut price = [0; 8];
-            reader.read_exact(&mut price).map_err(ParserError::Io)?;
-            f64::from_le_bytes(price)
+#[cfg(feature = "serde")]
+fn serialize_levels<L: LevelContainer, S: serde::Serializer>(
+    levels: &L,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    serializer.collect_seq(levels.iter().map(|level| *level))
+}
+
+impl Level {
+    fn write<W: Write>(&self, writer: &mut W, format: UpdateFormat) -> io::Result<()> {
+        write_level(
+            writer,
+            format,
+            self.side.code(),
+            self.price,
+            self.qty.value(),
+            self.metadata,
+        )
+    }
+}
+
+fn write_level<W: Write>(
+    writer: &mut W,
+    format: UpdateFormat,
+    side: u8,
+    price: f64,
+    qty: u64,
+    metadata: Option<LevelMetadata>,
+) -> io::Result<()> {
+    writer.write_all(&[side])?;
+    writer.write_all(&price.to_le_bytes())?;
+    writer.write_all(&qty.to_le_bytes())?;
+    if format == UpdateFormat::V2 {
+        let metadata = metadata.unwrap_or(LevelMetadata {
+            order_count: 0,
+            action: 0,
+        });
+        writer.write_all(&metadata.order_count.to_le_bytes())?;
+        writer.write_all(&[metadata.action])?;
+    }
+    Ok(())
+}
+
+impl<L: LevelContainer> OrderBookUpdate<L> {
+    // A copy holding its levels, which can be sent to other threads unlike the parsed
+    // updates whose levels stay in the deque of their parser
+    pub fn with_owned_levels(&self) -> OrderBookUpdate<Vec<Level>> {
+        OrderBookUpdate {
+            timestamp: self.timestamp,
+            capture_timestamp: self.capture_timestamp,
+            seq_no: self.seq_no,
+            security_id: self.security_id,
+            thinned_from: self.thinned_from,
+            updates: self.updates.iter().map(|level| *level).collect(),
+        }
+    }
+
+    // Encodes the update in the layout read by OrderBookUpdateParser. Writing V2 records
+    // from V1 data uses the exchange timestamp as capture timestamp and zeroed metadata.
+    // V1 records can't carry thinned_from, which is lost.
+    pub fn write<W: Write>(&self, writer: &mut W, format: UpdateFormat) -> io::Result<()> {
+        writer.write_all(&self.timestamp.to_le_bytes())?;
+        if format == UpdateFormat::V2 {
+            let capture_timestamp = self.capture_timestamp.unwrap_or(self.timestamp);
+            writer.write_all(&capture_timestamp.to_le_bytes())?;
+        }
+        writer.write_all(&self.seq_no.to_le_bytes())?;
+        writer.write_all(&self.security_id.to_le_bytes())?;
+
+        let thinned_from = self.thinned_from.filter(|_| format == UpdateFormat::V2);
+        let mut num_updates = thinned_from.map_or(0u64, |_| 1);
+        self.updates.for_each(|_| {
+            num_updates += 1;
+            Ok::<(), io::Error>(())
+        })?;
+        writer.write_all(&num_updates.to_le_bytes())?;
+        if let Some(from_seq_no) = thinned_from {
+            write_level(writer, format, THINNING_MARKER_SIDE, 0.0, from_seq_no, None)?;
+        }
+        self.updates.for_each(|level| level.write(writer, format))
+    }
+}
+
+binary_layout! {
+    struct V1Header {
+        timestamp: u64,
+        seq_no: u64,
+        security_id: u64,
+        num_updates: u64,
+    }
+}
+
+binary_layout! {
+    struct V2Header {
+        timestamp: u64,
+        capture_timestamp: u64,
+        seq_no: u64,
+        security_id: u64,
+        num_updates: u64,
+    }
+}
+
+// The header of a record of either format
+struct UpdateHeader {
+    timestamp: u64,
+    capture_timestamp: Option<u64>,
+    seq_no: u64,
+    security_id: u64,
+    num_updates: usize,
+}
+
+impl From<V1Header> for UpdateHeader {
+    fn from(header: V1Header) -> Self {
+        Self {
+            timestamp: header.timestamp,
+            capture_timestamp: None,
+            seq_no: header.seq_no,
+            security_id: header.security_id,
+            num_updates: header.num_updates as usize,
+        }
+    }
+}
+
+impl From<V2Header> for UpdateHeader {
+    fn from(header: V2Header) -> Self {
+        Self {
+            timestamp: header.timestamp,
+            capture_timestamp: Some(header.capture_timestamp),
+            seq_no: header.seq_no,
+            security_id: header.security_id,
+            num_updates: header.num_updates as usize,
+        }
+    }
+}
+
+impl UpdateHeader {
+    fn read_record_start<R: Read>(
+        reader: &mut R,
+        format: UpdateFormat,
+        endianness: Endianness,
+    ) -> Result<Self, ParserError> {
+        match format {
+            UpdateFormat::V1 => V1Header::read_record_start(reader, endianness).map(Self::from),
+            UpdateFormat::V2 => V2Header::read_record_start(reader, endianness).map(Self::from),
+        }
+    }
+
+    // `data` holds the whole header
+    fn from_slice(data: &[u8], format: UpdateFormat, endianness: Endianness) -> Self {
+        match format {
+            UpdateFormat::V1 => V1Header::from_slice(data, endianness).into(),
+            UpdateFormat::V2 => V2Header::from_slice(data, endianness).into(),
+        }
+    }
+
+    fn check_num_updates(&self) -> Result<(), ParserError> {
+        if self.num_updates > MAX_NUM_UPDATES {
+            return Err(ParserError::Custom(format!(
+                "Number of updates is too large: {}",
+                self.num_updates
+            )));
+        }
+        Ok(())
+    }
+}
+
+binary_layout! {
+    // The fields of a level in both formats, the V2 ones are followed by LevelMetadata
+    struct LevelFields {
+        side: u8,
+        price: f64,
+        qty: u64,
+    }
+}
+
+// A level as stored, before its side is decoded
+struct RawLevel {
+    side: u8,
+    price: f64,
+    qty: u64,
+    metadata: Option<LevelMetadata>,
+}
+
+impl RawLevel {
+    fn new(fields: LevelFields, metadata: Option<LevelMetadata>) -> Self {
+        Self {
+            side: fields.side,
+            price: fields.price,
+            qty: fields.qty,
+            metadata,
+        }
+    }
+
+    fn read<R: Read>(
+        reader: &mut R,
+        format: UpdateFormat,
+        endianness: Endianness,
+    ) -> Result<Self, ParserError> {
+        let fields = LevelFields::read(reader, endianness)?;
+        let metadata = match format {
+            UpdateFormat::V1 => None,
+            UpdateFormat::V2 => Some(LevelMetadata::read(reader, endianness)?),
         };
-        // parse qty
-        let qty = {
-            let mut qty = [0; 8];
-            reader.read_exact(&mut qty).map_err(ParserError::Io)?;
-            u64::from_le_bytes(qty)
+        Ok(Self::new(fields, metadata))
+    }
+
+    // `data` holds the whole level
+    fn from_slice(data: &[u8], format: UpdateFormat, endianness: Endianness) -> Self {
+        let fields = LevelFields::from_slice(data, endianness);
+        let metadata = match format {
+            UpdateFormat::V1 => None,
+            UpdateFormat::V2 => Some(LevelMetadata::from_slice(
+                &data[<LevelFields as BinaryLayout>::SIZE..],
+                endianness,
+            )),
         };
-        Ok(Level { side, price, qty })
+        Self::new(fields, metadata)
+    }
+
+    fn thinned_from(&self) -> Option<u64> {
+        match (self.side, self.metadata) {
+            (THINNING_MARKER_SIDE, Some(_)) => Some(self.qty),
+            _ => None,
+        }
     }
+
+    fn decode(self) -> Result<Level, ParserError> {
+        Ok(Level {
+            side: Side::try_from(self.side)?,
+            price: self.price,
+            qty: Qty(self.qty),
+            metadata: self.metadata,
+        })
+    }
+}
+
+// Takes the marker level of a thinned record off the levels and returns the seq_no it
+// carries with the decoded levels
+fn split_thinning_marker(
+    mut levels: impl Iterator<Item = Result<RawLevel, ParserError>>,
+) -> Result<
+    (
+        Option<u64>,
+        impl Iterator<Item = Result<Level, ParserError>>,
+    ),
+    ParserError,
+> {
+    let mut first = levels.next().transpose()?;
+    let thinned_from = first.as_ref().and_then(RawLevel::thinned_from);
+    if thinned_from.is_some() {
+        first = None;
+    }
+    let levels = first
+        .map(Ok)
+        .into_iter()
+        .chain(levels)
+        .map(|level| level.and_then(RawLevel::decode));
+    Ok((thinned_from, levels))
 }
 
 #[derive(Debug, Default)]
 pub struct OrderBookUpdateParser {
-    // Each security_id has its own deque for updates
+    format: UpdateFormat,
+    endianness: Endianness,
+    // Each security_id has its own deque for updates. It works as an arena for the levels:
+    // the storage of a batch is reused once its BatchGuard drops, so parsing does not
+    // allocate per record once the deque fits the backlog of pending updates.
     security_id_to_deque: HashMap<u64, BatchedDeque<Level>>,
+    // Deque capacities from a pre-scan, DEFAULT_UPDATE_DEQUE_CAPACITY otherwise
+    deque_capacities: HashMap<u64, usize>,
+    // Levels a deque holds at most, unbounded when None
+    max_held_levels: Option<usize>,
+    security_filter: SecurityFilter,
+    time_window: TimeWindow,
+}
+
+impl OrderBookUpdateParser {
+    pub fn new(format: UpdateFormat) -> Self {
+        Self {
+            format,
+            endianness: Endianness::Little,
+            security_id_to_deque: HashMap::new(),
+            deque_capacities: HashMap::new(),
+            max_held_levels: None,
+            security_filter: SecurityFilter::default(),
+            time_window: TimeWindow::default(),
+        }
+    }
+
+    pub fn with_capacity_hints(format: UpdateFormat, hints: &CapacityHints) -> Self {
+        let deque_capacities = hints
+            .securities
+            .iter()
+            .map(|(security_id, hint)| {
+                // Enough for the largest backlog of pending updates, but never more
+                // than all the levels of the security
+                let levels_per_update = hint.levels.div_ceil(hint.updates.max(1));
+                let backlog_levels = levels_per_update * (hint.max_pending_updates + 1);
+                let capacity = hint
+                    .levels
+                    .min(backlog_levels.max(DEFAULT_UPDATE_DEQUE_CAPACITY));
+                (*security_id, capacity)
+            })
+            .collect();
+        Self {
+            format,
+            endianness: Endianness::Little,
+            security_id_to_deque: HashMap::with_capacity(hints.securities.len()),
+            deque_capacities,
+            max_held_levels: None,
+            security_filter: SecurityFilter::default(),
+            time_window: TimeWindow::default(),
+        }
+    }
+
+    pub fn with_endianness(mut self, endianness: Endianness) -> Self {
+        self.endianness = endianness;
+        self
+    }
+
+    // Skips the records of the securities the filter doesn't accept. Their levels are
+    // neither parsed nor stored.
+    pub fn with_security_filter(mut self, security_filter: SecurityFilter) -> Self {
+        self.security_filter = security_filter;
+        self
+    }
+
+    // Skips the records sent outside the window, like those of filtered securities
+    pub fn with_time_window(mut self, time_window: TimeWindow) -> Self {
+        self.time_window = time_window;
+        self
+    }
+
+    // Bounds the levels of the updates of a security held at once, mostly by its pending
+    // updates, so that memory use stays predictable in long sessions. A record that would
+    // go past the bound fails to parse.
+    pub fn with_max_held_levels(mut self, max_held_levels: Option<usize>) -> Self {
+        self.max_held_levels = max_held_levels;
+        self
+    }
+
+    pub fn format(&self) -> UpdateFormat {
+        self.format
+    }
+
+    pub fn endianness(&self) -> Endianness {
+        self.endianness
+    }
+
+    pub fn security_filter(&self) -> &SecurityFilter {
+        &self.security_filter
+    }
+
+    pub fn time_window(&self) -> TimeWindow {
+        self.time_window
+    }
+
+    pub fn max_held_levels(&self) -> Option<usize> {
+        self.max_held_levels
+    }
+
+    fn skips(&self, security_id: u64, timestamp: u64) -> bool {
+        !self.security_filter.accepts(security_id) || !self.time_window.includes(timestamp)
+    }
+
+    fn deque(&mut self, security_id: u64) -> &BatchedDeque<Level> {
+        let (deque_capacities, max_held_levels) = (&self.deque_capacities, self.max_held_levels);
+        self.security_id_to_deque
+            .entry(security_id)
+            .or_insert_with(|| {
+                let capacity = deque_capacities
+                    .get(&security_id)
+                    .copied()
+                    .unwrap_or(DEFAULT_UPDATE_DEQUE_CAPACITY);
+                update_deque(capacity, max_held_levels)
+            })
+    }
 }
 
 impl DefaultParser<OrderBookUpdate> for OrderBookUpdate {
@@ -66,63 +569,108 @@ impl DefaultParser<OrderBookUpdate> for OrderBookUpdate {
 
 impl Parser<OrderBookUpdate> for OrderBookUpdateParser {
     fn read<R: Read>(&mut self, reader: &mut R) -> Result<OrderBookUpdate, ParserError> {
-        // parse timestamp
-        let timestamp = {
-            let mut timestamp = [0; 8];
-            match reader.read_exact(&mut timestamp) {
-                Ok(_) => (),
-                Err(e) => {
-                    if e.kind() == io::ErrorKind::UnexpectedEof {
-                        return Err(ParserError::ExpectedEof);
-                    }
-                    return Err(ParserError::Io(e));
-                }
+        loop {
+            let header = UpdateHeader::read_record_start(reader, self.format, self.endianness)?;
+            header.check_num_updates()?;
+            let UpdateHeader {
+                timestamp,
+                capture_timestamp,
+                seq_no,
+                security_id,
+                num_updates,
+            } = header;
+            if self.skips(security_id, timestamp) {
+                skip_bytes(reader, num_updates * self.format.level_size())?;
+                continue;
             }
-            u64::from_le_bytes(timestamp)
-        };
-        // parse seq_no
-        let seq_no = {
-            let mut seq_no = [0; 8];
-            reader.read_exact(&mut seq_no).map_err(ParserError::Io)?;
-            u64::from_le_bytes(seq_no)
-        };
-        // parse security_id
-        let security_id = {
-            let mut security_id = [0; 8];
-            reader
-                .read_exact(&mut security_id)
-                .map_err(ParserError::Io)?;
-            u64::from_le_bytes(security_id)
-        };
-        // parse num_updates
-        let num_updates = {
-            let mut num_updates = [0; 8];
-            reader
-                .read_exact(&mut num_updates)
-                .map_err(ParserError::Io)?;
-            let num_updates = u64::from_le_bytes(num_updates) as usize;
-            if num_updates > MAX_NUM_UPDATES {
-                return Err(ParserError::Custom(format!(
-                    "Number of updates is too large: {}",
-                    num_updates
-                )));
-            }
-            num_updates
-        };
 
-        let deque = self
-            .security_id_to_deque
-            .entry(security_id)
-            .or_insert_with(|| BatchedDeque::new(DEFAULT_UPDATE_DEQUE_CAPACITY));
+            let (format, endianness) = (self.format, self.endianness);
+            let deque = self.deque(security_id);
+            let (thinned_from, levels_iter) = split_thinning_marker(
+                (0..num_updates).map(move |_| RawLevel::read(reader, format, endianness)),
+            )?;
+
+            return Ok(OrderBookUpdate {
+                timestamp,
+                capture_timestamp,
+                seq_no,
+                security_id,
+                thinned_from,
+                updates: deque.push_back_batch(levels_iter)?,
+            });
+        }
+    }
+
+    // Records skipped by the filters at the start of `data` are counted in the bytes
+    // taken. ExpectedEof then means `data` held no other record.
+    fn read_from_slice(&mut self, data: &[u8]) -> Result<(OrderBookUpdate, usize), ParserError> {
+        let mut skipped = 0;
+        while let Some(record_size) = self.skipped_record_size(&data[skipped..]) {
+            skipped += record_size;
+        }
+        let (update, consumed) = self.read_record_from_slice(&data[skipped..])?;
+        Ok((update, skipped + consumed))
+    }
+}
+
+impl OrderBookUpdateParser {
+    // The size of the record at the start of `data` when it is whole and skipped by the
+    // filters
+    fn skipped_record_size(&self, data: &[u8]) -> Option<usize> {
+        let header_size = self.format.header_size();
+        let filtered = !self.security_filter.accepts_all() || !self.time_window.is_unbounded();
+        if !filtered || data.len() < header_size {
+            return None;
+        }
+        let header = UpdateHeader::from_slice(data, self.format, self.endianness);
+        if !self.skips(header.security_id, header.timestamp) || header.num_updates > MAX_NUM_UPDATES
+        {
+            return None;
+        }
+        let record_size = header_size + header.num_updates * self.format.level_size();
+        (data.len() >= record_size).then_some(record_size)
+    }
 
-        let levels_iter = (0..num_updates).map(move |_| LevelParser.read(reader));
+    fn read_record_from_slice(
+        &mut self,
+        data: &[u8],
+    ) -> Result<(OrderBookUpdate, usize), ParserError> {
+        if data.len() < 8 {
+            return Err(ParserError::ExpectedEof);
+        }
+        let (format, endianness) = (self.format, self.endianness);
+        let header_size = format.header_size();
+        if data.len() < header_size {
+            return Err(truncated_record());
+        }
+        let header = UpdateHeader::from_slice(data, format, endianness);
+        header.check_num_updates()?;
+        let UpdateHeader {
+            timestamp,
+            capture_timestamp,
+            seq_no,
+            security_id,
+            num_updates,
+        } = header;
+        let level_size = format.level_size();
+        let record_size = header_size + num_updates * level_size;
+        if data.len() < record_size {
+            return Err(truncated_record());
+        }
 
-        Ok(OrderBookUpdate {
+        let levels_iter = data[header_size..record_size]
+            .chunks_exact(level_size)
+            .map(|level| Ok(RawLevel::from_slice(level, format, endianness)));
+        let (thinned_from, levels_iter) = split_thinning_marker(levels_iter)?;
+        let update = OrderBookUpdate {
             timestamp,
+            capture_timestamp,
             seq_no,
             security_id,
-            updates: deque.push_back_batch(levels_iter)?,
-        })
+            thinned_from,
+            updates: self.deque(security_id).push_back_batch(levels_iter)?,
+        };
+        Ok((update, record_size))
     }
 }
 
@@ -180,9 +728,12 @@ mod tests {
         update
             .updates
             .for_each(|level| {
-                assert_eq!(level.side, if count % 2 == 0 { 0 } else { 1 });
+                assert_eq!(
+                    level.side,
+                    if count % 2 == 0 { Side::Bid } else { Side::Ask }
+                );
                 assert_eq!(level.price, 1000.0 + (count as f64) * 0.5);
-                assert_eq!(level.qty, 100 + (count as u64) * 10);
+                assert_eq!(level.qty, Qty(100 + (count as u64) * 10));
                 count += 1;
                 Ok::<(), ()>(())
             })
@@ -190,6 +741,45 @@ mod tests {
         assert_eq!(count, num_updates);
     }
 
+    #[test]
+    fn test_parse_big_endian_v2() {
+        let mut data = Vec::new();
+        data.extend_from_slice(&1234567890u64.to_be_bytes()); // timestamp
+        data.extend_from_slice(&1234567891u64.to_be_bytes()); // capture_timestamp
+        data.extend_from_slice(&42u64.to_be_bytes()); // seq_no
+        data.extend_from_slice(&123456u64.to_be_bytes()); // security_id
+        data.extend_from_slice(&1u64.to_be_bytes()); // num_updates
+        data.push(1); // side
+        data.extend_from_slice(&123.45f64.to_be_bytes()); // price
+        data.extend_from_slice(&789u64.to_be_bytes()); // qty
+        data.extend_from_slice(&12u32.to_be_bytes()); // order_count
+        data.push(2); // action
+
+        let mut parser =
+            OrderBookUpdateParser::new(UpdateFormat::V2).with_endianness(Endianness::Big);
+        let update = parser.read(&mut Cursor::new(data)).unwrap();
+        assert_eq!(update.timestamp, 1234567890);
+        assert_eq!(update.capture_timestamp, Some(1234567891));
+        assert_eq!(update.seq_no, 42);
+        assert_eq!(update.security_id, 123456);
+        update
+            .updates
+            .for_each(|level| {
+                assert_eq!(level.side, Side::Ask);
+                assert_eq!(level.price, 123.45);
+                assert_eq!(level.qty, Qty(789));
+                assert_eq!(
+                    level.metadata,
+                    Some(LevelMetadata {
+                        order_count: 12,
+                        action: 2,
+                    })
+                );
+                Ok::<(), ()>(())
+            })
+            .unwrap();
+    }
+
     #[test]
     fn test_multiple_updates_same_security_id() {
         let num_updates = 3;
@@ -240,6 +830,64 @@ mod tests {
         assert_eq!(count, num_updates);
     }
 
+    #[test]
+    fn test_max_held_levels() {
+        let mut parser = OrderBookUpdateParser::default().with_max_held_levels(Some(5));
+
+        // The levels of the first update are held as long as it is alive
+        let update1 = parser
+            .read(&mut Cursor::new(create_test_update_data(42, 3)))
+            .unwrap();
+        let result = parser.read(&mut Cursor::new(create_test_update_data(43, 3)));
+        assert!(matches!(result, Err(ParserError::Custom(_))));
+
+        // Once it is dropped the next update fits
+        drop(update1);
+        let update2 = parser
+            .read(&mut Cursor::new(create_test_update_data(44, 3)))
+            .unwrap();
+        assert_eq!(update2.seq_no, 44);
+    }
+
+    #[test]
+    fn test_write_round_trip() {
+        let data = create_test_update_data(42, 3);
+        let mut parser = OrderBookUpdateParser::default();
+        let update = parser.read(&mut Cursor::new(data.clone())).unwrap();
+
+        let mut written = Vec::new();
+        update.write(&mut written, UpdateFormat::V1).unwrap();
+        assert_eq!(written, data);
+
+        // V1 data written as V2 gets default capture timestamp and metadata
+        let mut written = Vec::new();
+        update.write(&mut written, UpdateFormat::V2).unwrap();
+        let mut parser = OrderBookUpdateParser::new(UpdateFormat::V2);
+        let update_v2 = parser.read(&mut Cursor::new(written)).unwrap();
+        assert_eq!(update_v2.capture_timestamp, Some(1234567890));
+        assert_eq!(update_v2.seq_no, 42);
+        let mut levels = Vec::new();
+        update_v2
+            .updates
+            .for_each(|level| {
+                levels.push((level.side, level.price, level.qty.value(), level.metadata));
+                Ok::<(), ()>(())
+            })
+            .unwrap();
+        let no_orders = Some(LevelMetadata {
+            order_count: 0,
+            action: 0,
+        });
+        assert_eq!(
+            levels,
+            vec![
+                (Side::Bid, 1000.0, 100, no_orders),
+                (Side::Ask, 1000.5, 110, no_orders),
+                (Side::Bid, 1001.0, 120, no_orders),
+            ]
+        );
+    }
+
     #[test]
     fn test_empty_data() {
         // Test with empty data
@@ -309,10 +957,80 @@ mod tests {
         data.extend_from_slice(&789u64.to_le_bytes()); // qty
 
         let mut cursor = Cursor::new(data);
-        let level = LevelParser.read(&mut cursor).unwrap();
-        assert_eq!(level.side, 1);
+        let level = RawLevel::read(&mut cursor, UpdateFormat::V1, Endianness::Little)
+            .and_then(RawLevel::decode)
+            .unwrap();
+        assert_eq!(level.side, Side::Ask);
         assert_eq!(level.price, 123.45);
-        assert_eq!(level.qty, 789);
+        assert_eq!(level.qty, Qty(789));
+        assert!(level.metadata.is_none());
+    }
+
+    #[test]
+    fn test_level_parser_v2() {
+        let mut data = Vec::new();
+        data.push(0); // side (bid)
+        data.extend_from_slice(&123.45f64.to_le_bytes()); // price
+        data.extend_from_slice(&789u64.to_le_bytes()); // qty
+        data.extend_from_slice(&12u32.to_le_bytes()); // order_count
+        data.push(2); // action
+
+        let mut cursor = Cursor::new(data);
+        let level = RawLevel::read(&mut cursor, UpdateFormat::V2, Endianness::Little)
+            .and_then(RawLevel::decode)
+            .unwrap();
+        assert_eq!(level.side, Side::Bid);
+
+        // Sides other than 0 and 1 fail the record
+        let mut data = vec![2];
+        data.extend_from_slice(&[0; 21]);
+        let result = RawLevel::read(&mut Cursor::new(data), UpdateFormat::V2, Endianness::Little)
+            .and_then(RawLevel::decode);
+        assert!(matches!(result, Err(ParserError::Custom(msg)) if msg == "Invalid side: 2"));
+        assert_eq!(level.price, 123.45);
+        assert_eq!(level.qty, Qty(789));
+        assert_eq!(
+            level.metadata,
+            Some(LevelMetadata {
+                order_count: 12,
+                action: 2,
+            })
+        );
+    }
+
+    #[test]
+    fn test_parse_order_book_update_v2() {
+        let mut data = Vec::new();
+        data.extend_from_slice(&1234567890u64.to_le_bytes()); // timestamp
+        data.extend_from_slice(&1234567895u64.to_le_bytes()); // capture_timestamp
+        data.extend_from_slice(&42u64.to_le_bytes()); // seq_no
+        data.extend_from_slice(&123456u64.to_le_bytes()); // security_id
+        data.extend_from_slice(&2u64.to_le_bytes()); // num_updates
+        for i in 0..2 {
+            data.push(i as u8); // side
+            data.extend_from_slice(&(1000.0 + i as f64).to_le_bytes()); // price
+            data.extend_from_slice(&(100 + i as u64).to_le_bytes()); // qty
+            data.extend_from_slice(&(5 + i as u32).to_le_bytes()); // order_count
+            data.push(1); // action
+        }
+
+        let mut cursor = Cursor::new(data);
+        let mut parser = OrderBookUpdateParser::new(UpdateFormat::V2);
+        let update = parser.read(&mut cursor).unwrap();
+        assert_eq!(update.timestamp, 1234567890);
+        assert_eq!(update.capture_timestamp, Some(1234567895));
+        assert_eq!(update.seq_no, 42);
+
+        let mut order_counts = Vec::new();
+        update
+            .updates
+            .for_each(|level| {
+                order_counts.push(level.metadata.unwrap().order_count);
+                Ok::<(), ()>(())
+            })
+            .unwrap();
+        assert_eq!(order_counts, vec![5, 6]);
+        assert_eq!(cursor.position() as usize, cursor.get_ref().len());
     }
 
     #[test]
@@ -377,9 +1095,16 @@ mod tests {
         update1
             .updates
             .for_each(|level| {
-                assert_eq!(level.side, if count1 % 2 == 0 { 0 } else { 1 });
+                assert_eq!(
+                    level.side,
+                    if count1 % 2 == 0 {
+                        Side::Bid
+                    } else {
+                        Side::Ask
+                    }
+                );
                 assert_eq!(level.price, 1000.0 + (count1 as f64) * 0.5);
-                assert_eq!(level.qty, 100 + (count1 as u64) * 10);
+                assert_eq!(level.qty, Qty(100 + (count1 as u64) * 10));
                 count1 += 1;
                 Ok::<(), ()>(())
             })
@@ -391,13 +1116,126 @@ mod tests {
         update2
             .updates
             .for_each(|level| {
-                assert_eq!(level.side, if count2 % 2 == 0 { 0 } else { 1 });
+                assert_eq!(
+                    level.side,
+                    if count2 % 2 == 0 {
+                        Side::Bid
+                    } else {
+                        Side::Ask
+                    }
+                );
                 assert_eq!(level.price, 2000.0 + (count2 as f64) * 0.5);
-                assert_eq!(level.qty, 200 + (count2 as u64) * 10);
+                assert_eq!(level.qty, Qty(200 + (count2 as u64) * 10));
                 count2 += 1;
                 Ok::<(), ()>(())
             })
             .unwrap();
         assert_eq!(count2, num_updates);
     }
+
+    #[test]
+    fn test_read_from_slice() {
+        let mut data = create_test_update_data(42, 3);
+        let record_size = data.len();
+        data.extend_from_slice(&create_test_update_data(43, 2));
+        let mut parser = OrderBookUpdateParser::default();
+
+        let (update, consumed) = parser.read_from_slice(&data).unwrap();
+        assert_eq!(consumed, record_size);
+        let mut written = Vec::new();
+        update.write(&mut written, UpdateFormat::V1).unwrap();
+        assert_eq!(written, data[..record_size]);
+        let (update, consumed) = parser.read_from_slice(&data[record_size..]).unwrap();
+        assert_eq!(update.seq_no, 43);
+        assert_eq!(record_size + consumed, data.len());
+
+        // The same errors as reading the bytes
+        assert!(matches!(
+            parser.read_from_slice(&[]),
+            Err(ParserError::ExpectedEof)
+        ));
+        for len in [12, 20, record_size - 1] {
+            match parser.read_from_slice(&data[..len]) {
+                Err(ParserError::Io(e)) => assert_eq!(e.kind(), io::ErrorKind::UnexpectedEof),
+                other => panic!("Expected a truncated record, got {:?}", other),
+            }
+        }
+    }
+
+    #[test]
+    fn test_security_filter() {
+        let mut skipped = create_test_update_data(42, 3);
+        skipped[16..24].copy_from_slice(&7u64.to_le_bytes());
+        let mut data = skipped.clone();
+        data.extend_from_slice(&create_test_update_data(43, 2));
+        data.extend_from_slice(&skipped);
+        let filter = SecurityFilter::only([123456]);
+
+        let mut parser = OrderBookUpdateParser::default().with_security_filter(filter.clone());
+        let mut reader = data.as_slice();
+        assert_eq!(parser.read(&mut reader).unwrap().seq_no, 43);
+        assert!(matches!(
+            parser.read(&mut reader),
+            Err(ParserError::ExpectedEof)
+        ));
+        assert!(!parser.security_id_to_deque.contains_key(&7));
+
+        let mut parser = OrderBookUpdateParser::default().with_security_filter(filter);
+        let (update, consumed) = parser.read_from_slice(&data).unwrap();
+        assert_eq!(update.seq_no, 43);
+        assert_eq!(consumed, data.len() - skipped.len());
+        assert!(matches!(
+            parser.read_from_slice(&data[consumed..]),
+            Err(ParserError::ExpectedEof)
+        ));
+        // A skipped record cut short is still an error
+        match parser.read_from_slice(&data[consumed..data.len() - 1]) {
+            Err(ParserError::Io(e)) => assert_eq!(e.kind(), io::ErrorKind::UnexpectedEof),
+            other => panic!("Expected a truncated record, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_time_window() {
+        let mut data = Vec::new();
+        for (seq_no, timestamp) in [(1, 1_000u64), (2, 2_000), (3, 3_000)] {
+            let mut record = create_test_update_data(seq_no, 2);
+            record[..8].copy_from_slice(&timestamp.to_le_bytes());
+            data.extend_from_slice(&record);
+        }
+        let window = TimeWindow::new(Some(1_500), Some(2_000)).unwrap();
+
+        let mut parser = OrderBookUpdateParser::default().with_time_window(window);
+        let mut reader = data.as_slice();
+        assert_eq!(parser.read(&mut reader).unwrap().seq_no, 2);
+        assert!(matches!(
+            parser.read(&mut reader),
+            Err(ParserError::ExpectedEof)
+        ));
+
+        let (update, consumed) = parser.read_from_slice(&data).unwrap();
+        assert_eq!(update.seq_no, 2);
+        assert!(matches!(
+            parser.read_from_slice(&data[consumed..]),
+            Err(ParserError::ExpectedEof)
+        ));
+        assert!(TimeWindow::new(Some(2_000), Some(1_000)).is_err());
+    }
+
+    #[test]
+    fn test_read_from_slice_v2() {
+        let data = create_test_update_data(42, 3);
+        let update = OrderBookUpdateParser::default()
+            .read(&mut data.as_slice())
+            .unwrap();
+        let mut written = Vec::new();
+        update.write(&mut written, UpdateFormat::V2).unwrap();
+
+        let mut parser = OrderBookUpdateParser::new(UpdateFormat::V2);
+        let (update_v2, consumed) = parser.read_from_slice(&written).unwrap();
+        assert_eq!(consumed, written.len());
+        let mut rewritten = Vec::new();
+        update_v2.write(&mut rewritten, UpdateFormat::V2).unwrap();
+        assert_eq!(rewritten, written);
+    }
 }
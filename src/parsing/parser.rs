@@ -1,4 +1,9 @@
+use std::collections::HashSet;
 use std::io::{self, Read};
+use std::str::FromStr;
+use std::sync::Arc;
+
+use crate::batched_deque::batched_deque::PushError;
 
 #[derive(Debug)]
 pub enum ParserError {
@@ -7,8 +12,105 @@ pub enum ParserError {
     Io(io::Error),
 }
 
+impl From<PushError<ParserError>> for ParserError {
+    fn from(err: PushError<ParserError>) -> Self {
+        match err {
+            PushError::Item(err) => err,
+            PushError::Full { max_len } => {
+                ParserError::Custom(format!("Update deque full, {max_len} levels held"))
+            }
+        }
+    }
+}
+
 pub trait Parser<T> {
     fn read<R: Read>(&mut self, reader: &mut R) -> Result<T, ParserError>;
+
+    // Parses the record at the start of `data` and returns it with the number of bytes it
+    // takes. Parsers of fixed layouts decode it in place, without a read call per field.
+    fn read_from_slice(&mut self, data: &[u8]) -> Result<(T, usize), ParserError> {
+        let mut remaining = data;
+        let item = self.read(&mut remaining)?;
+        Ok((item, data.len() - remaining.len()))
+    }
+}
+
+// The bytes of a field at `offset`, which the caller checked to be within `data`
+pub(crate) fn field<const N: usize>(data: &[u8], offset: usize) -> [u8; N] {
+    data[offset..offset + N]
+        .try_into()
+        .expect("Field within the checked bounds")
+}
+
+// What read_exact reports for a record cut short
+pub(crate) fn truncated_record() -> ParserError {
+    ParserError::Io(io::Error::from(io::ErrorKind::UnexpectedEof))
+}
+
+// Reads and discards `len` bytes, the rest of a record that is skipped
+pub(crate) fn skip_bytes<R: Read>(reader: &mut R, len: usize) -> Result<(), ParserError> {
+    let skipped =
+        io::copy(&mut reader.take(len as u64), &mut io::sink()).map_err(ParserError::Io)?;
+    if skipped < len as u64 {
+        return Err(truncated_record());
+    }
+    Ok(())
+}
+
+// The securities whose records the binary parsers return. Records of the others are
+// skipped right after their security_id is read, without parsing their levels. Accepts
+// every security by default.
+#[derive(Debug, Clone, Default)]
+pub struct SecurityFilter {
+    security_ids: Option<Arc<HashSet<u64>>>,
+}
+
+impl SecurityFilter {
+    pub fn only<I: IntoIterator<Item = u64>>(security_ids: I) -> Self {
+        Self {
+            security_ids: Some(Arc::new(security_ids.into_iter().collect())),
+        }
+    }
+
+    pub fn accepts(&self, security_id: u64) -> bool {
+        self.security_ids
+            .as_ref()
+            .is_none_or(|security_ids| security_ids.contains(&security_id))
+    }
+
+    pub fn accepts_all(&self) -> bool {
+        self.security_ids.is_none()
+    }
+}
+
+// The exchange timestamps, in millis and both bounds inclusive, of the records the binary
+// parsers return. The others are skipped like the records of filtered securities.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct TimeWindow {
+    pub from: Option<u64>,
+    pub to: Option<u64>,
+}
+
+impl TimeWindow {
+    pub fn new(from: Option<u64>, to: Option<u64>) -> Result<Self, String> {
+        if let (Some(from), Some(to)) = (from, to)
+            && from > to
+        {
+            return Err(format!(
+                "The window starts at {} after its end at {}",
+                from, to
+            ));
+        }
+        Ok(Self { from, to })
+    }
+
+    pub fn includes(&self, timestamp: u64) -> bool {
+        self.from.is_none_or(|from| timestamp >= from) && self.to.is_none_or(|to| timestamp <= to)
+    }
+
+    pub fn is_unbounded(&self) -> bool {
+        self.from.is_none() && self.to.is_none()
+    }
 }
 
 pub trait DefaultParser<T> {
@@ -16,3 +118,46 @@ pub trait DefaultParser<T> {
 
     fn default_parser() -> Self::ParserType;
 }
+
+// Byte order of the numbers in a capture, the files written by this crate are little-endian
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Endianness {
+    #[default]
+    Little,
+    Big,
+}
+
+impl Endianness {
+    pub fn u64_from(self, bytes: [u8; 8]) -> u64 {
+        match self {
+            Endianness::Little => u64::from_le_bytes(bytes),
+            Endianness::Big => u64::from_be_bytes(bytes),
+        }
+    }
+
+    pub fn u32_from(self, bytes: [u8; 4]) -> u32 {
+        match self {
+            Endianness::Little => u32::from_le_bytes(bytes),
+            Endianness::Big => u32::from_be_bytes(bytes),
+        }
+    }
+
+    pub fn f64_from(self, bytes: [u8; 8]) -> f64 {
+        match self {
+            Endianness::Little => f64::from_le_bytes(bytes),
+            Endianness::Big => f64::from_be_bytes(bytes),
+        }
+    }
+}
+
+impl FromStr for Endianness {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "little" => Ok(Endianness::Little),
+            "big" => Ok(Endianness::Big),
+            _ => Err(format!("Unknown endianness: {}", s)),
+        }
+    }
+}
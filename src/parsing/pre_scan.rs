@@ -0,0 +1,166 @@
+use std::collections::{HashMap, HashSet};
+use std::io::{self, Read};
+
+use crate::parsing::order_book_update::UpdateFormat;
+use crate::parsing::parser::{Endianness, ParserError};
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SecurityCapacityHint {
+    pub updates: usize,
+    pub levels: usize,
+    // Largest number of updates waiting for a gap to close, replaying the file alone
+    pub max_pending_updates: usize,
+}
+
+// What a replay of the incremental file will need, to size the containers upfront
+#[derive(Debug, Default)]
+pub struct CapacityHints {
+    pub securities: HashMap<u64, SecurityCapacityHint>,
+}
+
+impl CapacityHints {
+    pub fn get(&self, security_id: u64) -> Option<&SecurityCapacityHint> {
+        self.securities.get(&security_id)
+    }
+}
+
+#[derive(Default)]
+struct SecurityScanState {
+    hint: SecurityCapacityHint,
+    next_seq_no: u64,
+    ahead: HashSet<u64>,
+}
+
+impl SecurityScanState {
+    fn observe(&mut self, seq_no: u64, num_levels: usize) {
+        self.hint.updates += 1;
+        self.hint.levels += num_levels;
+        if self.hint.updates == 1 {
+            self.next_seq_no = seq_no + 1;
+            return;
+        }
+        if seq_no < self.next_seq_no {
+            return;
+        }
+        if seq_no > self.next_seq_no {
+            self.ahead.insert(seq_no);
+            self.hint.max_pending_updates = self.hint.max_pending_updates.max(self.ahead.len());
+            return;
+        }
+        self.next_seq_no += 1;
+        while self.ahead.remove(&self.next_seq_no) {
+            self.next_seq_no += 1;
+        }
+    }
+}
+
+fn read_u64<R: Read>(reader: &mut R, endianness: Endianness) -> Result<u64, ParserError> {
+    let mut value = [0; 8];
+    reader.read_exact(&mut value).map_err(ParserError::Io)?;
+    Ok(endianness.u64_from(value))
+}
+
+// Reads only the record headers of an incremental file and skips the levels
+pub fn pre_scan_updates<R: Read>(
+    reader: &mut R,
+    format: UpdateFormat,
+    endianness: Endianness,
+) -> Result<CapacityHints, ParserError> {
+    let level_size = format.level_size();
+    let mut states: HashMap<u64, SecurityScanState> = HashMap::new();
+
+    loop {
+        // parse timestamp
+        match read_u64(reader, endianness) {
+            Ok(_) => (),
+            Err(ParserError::Io(e)) if e.kind() == io::ErrorKind::UnexpectedEof => break,
+            Err(e) => return Err(e),
+        }
+        if format == UpdateFormat::V2 {
+            read_u64(reader, endianness)?;
+        }
+        let seq_no = read_u64(reader, endianness)?;
+        let security_id = read_u64(reader, endianness)?;
+        let num_levels = read_u64(reader, endianness)? as usize;
+
+        let skip_len = (num_levels * level_size) as u64;
+        let skipped =
+            io::copy(&mut reader.take(skip_len), &mut io::sink()).map_err(ParserError::Io)?;
+        if skipped != skip_len {
+            return Err(ParserError::Io(io::ErrorKind::UnexpectedEof.into()));
+        }
+
+        states
+            .entry(security_id)
+            .or_default()
+            .observe(seq_no, num_levels);
+    }
+
+    Ok(CapacityHints {
+        securities: states
+            .into_iter()
+            .map(|(security_id, state)| (security_id, state.hint))
+            .collect(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    fn create_test_update_data(data: &mut Vec<u8>, security_id: u64, seq_no: u64, levels: u64) {
+        data.extend_from_slice(&1234567890u64.to_le_bytes()); // timestamp
+        data.extend_from_slice(&seq_no.to_le_bytes()); // seq_no
+        data.extend_from_slice(&security_id.to_le_bytes()); // security_id
+        data.extend_from_slice(&levels.to_le_bytes()); // num_updates
+        for _ in 0..levels {
+            data.push(0); // side
+            data.extend_from_slice(&100.0f64.to_le_bytes()); // price
+            data.extend_from_slice(&10u64.to_le_bytes()); // qty
+        }
+    }
+
+    #[test]
+    fn test_pre_scan_updates() {
+        let mut data = Vec::new();
+        create_test_update_data(&mut data, 1, 10, 2);
+        create_test_update_data(&mut data, 2, 5, 1);
+        // Two updates ahead of the missing 11, then the gap closes
+        create_test_update_data(&mut data, 1, 12, 3);
+        create_test_update_data(&mut data, 1, 13, 1);
+        create_test_update_data(&mut data, 1, 11, 1);
+        create_test_update_data(&mut data, 1, 14, 1);
+
+        let hints =
+            pre_scan_updates(&mut Cursor::new(data), UpdateFormat::V1, Endianness::Little).unwrap();
+
+        assert_eq!(hints.securities.len(), 2);
+        assert_eq!(
+            hints.get(1),
+            Some(&SecurityCapacityHint {
+                updates: 5,
+                levels: 8,
+                max_pending_updates: 2,
+            })
+        );
+        assert_eq!(
+            hints.get(2),
+            Some(&SecurityCapacityHint {
+                updates: 1,
+                levels: 1,
+                max_pending_updates: 0,
+            })
+        );
+    }
+
+    #[test]
+    fn test_pre_scan_truncated_levels() {
+        let mut data = Vec::new();
+        create_test_update_data(&mut data, 1, 10, 2);
+        data.truncate(data.len() - 1);
+
+        let result = pre_scan_updates(&mut Cursor::new(data), UpdateFormat::V1, Endianness::Little);
+        assert!(matches!(result, Err(ParserError::Io(_))));
+    }
+}
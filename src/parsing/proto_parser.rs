@@ -0,0 +1,327 @@
+use prost::Message;
+use std::collections::HashMap;
+use std::io::{self, Read};
+
+use crate::batched_deque::batched_deque::BatchedDeque;
+use crate::order_book::units::Qty;
+use crate::parsing::order_book_snapshot::{
+    Level as SnapshotLevel, MAX_SNAPSHOT_DEPTH, OrderBookSnapshot, SNAPSHOT_DEPTH,
+};
+use crate::parsing::order_book_update::{
+    DEFAULT_UPDATE_DEQUE_CAPACITY, Level as UpdateLevel, LevelMetadata, MAX_NUM_UPDATES,
+    OrderBookUpdate, Side, update_deque,
+};
+use crate::parsing::parser::{Parser, ParserError};
+
+// Protobuf records as defined in proto/order_book.proto, each prefixed with its length
+// as a varint. The messages are declared by hand so building needs no protoc.
+
+// Larger lengths are taken for corrupt data rather than allocated
+const MAX_MESSAGE_SIZE: u64 = 1 << 20;
+
+#[derive(Clone, PartialEq, Message)]
+pub struct ProtoLevel {
+    #[prost(double, tag = "1")]
+    pub price: f64,
+    #[prost(uint64, tag = "2")]
+    pub qty: u64,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct ProtoSnapshot {
+    #[prost(uint64, tag = "1")]
+    pub timestamp: u64,
+    #[prost(uint64, tag = "2")]
+    pub seq_no: u64,
+    #[prost(uint64, tag = "3")]
+    pub security_id: u64,
+    #[prost(message, repeated, tag = "4")]
+    pub bids: Vec<ProtoLevel>,
+    #[prost(message, repeated, tag = "5")]
+    pub asks: Vec<ProtoLevel>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct ProtoUpdateLevel {
+    #[prost(uint32, tag = "1")]
+    pub side: u32,
+    #[prost(double, tag = "2")]
+    pub price: f64,
+    #[prost(uint64, tag = "3")]
+    pub qty: u64,
+    #[prost(uint32, optional, tag = "4")]
+    pub order_count: Option<u32>,
+    #[prost(uint32, optional, tag = "5")]
+    pub action: Option<u32>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct ProtoUpdate {
+    #[prost(uint64, tag = "1")]
+    pub timestamp: u64,
+    #[prost(uint64, optional, tag = "2")]
+    pub capture_timestamp: Option<u64>,
+    #[prost(uint64, tag = "3")]
+    pub seq_no: u64,
+    #[prost(uint64, tag = "4")]
+    pub security_id: u64,
+    #[prost(message, repeated, tag = "5")]
+    pub levels: Vec<ProtoUpdateLevel>,
+}
+
+// Reads the next length-delimited message into `buffer`
+fn read_message<R: Read>(reader: &mut R, buffer: &mut Vec<u8>) -> Result<(), ParserError> {
+    let mut len = 0u64;
+    let mut byte = [0; 1];
+    for index in 0..10 {
+        match reader.read_exact(&mut byte) {
+            Ok(_) => (),
+            Err(e) if index == 0 && e.kind() == io::ErrorKind::UnexpectedEof => {
+                return Err(ParserError::ExpectedEof);
+            }
+            Err(e) => return Err(ParserError::Io(e)),
+        }
+        len |= u64::from(byte[0] & 0x7f) << (7 * index);
+        if byte[0] & 0x80 == 0 {
+            if len > MAX_MESSAGE_SIZE {
+                return Err(ParserError::Custom(format!(
+                    "Message length is too large: {}",
+                    len
+                )));
+            }
+            buffer.resize(len as usize, 0);
+            return reader.read_exact(buffer).map_err(ParserError::Io);
+        }
+    }
+    Err(ParserError::Custom("Invalid message length".to_string()))
+}
+
+fn decode<M: Message + Default>(buffer: &[u8]) -> Result<M, ParserError> {
+    M::decode(buffer).map_err(|e| ParserError::Custom(format!("Invalid message: {}", e)))
+}
+
+#[derive(Debug, Default)]
+pub struct ProtoSnapshotParser {
+    buffer: Vec<u8>,
+}
+
+impl Parser<OrderBookSnapshot> for ProtoSnapshotParser {
+    fn read<R: Read>(&mut self, reader: &mut R) -> Result<OrderBookSnapshot, ParserError> {
+        read_message(reader, &mut self.buffer)?;
+        let snapshot: ProtoSnapshot = decode(&self.buffer)?;
+        if snapshot.bids.len() > MAX_SNAPSHOT_DEPTH || snapshot.asks.len() > MAX_SNAPSHOT_DEPTH {
+            return Err(ParserError::Custom(format!(
+                "More than {} levels a side",
+                MAX_SNAPSHOT_DEPTH
+            )));
+        }
+        let depth = SNAPSHOT_DEPTH
+            .max(snapshot.bids.len())
+            .max(snapshot.asks.len());
+        let side = |levels: &[ProtoLevel]| {
+            let mut side: Vec<SnapshotLevel> = levels
+                .iter()
+                .map(|level| SnapshotLevel {
+                    price: level.price,
+                    qty: Qty(level.qty),
+                })
+                .collect();
+            side.resize_with(depth, || SnapshotLevel {
+                price: 0.0,
+                qty: Qty::ZERO,
+            });
+            side
+        };
+        Ok(OrderBookSnapshot {
+            timestamp: snapshot.timestamp,
+            seq_no: snapshot.seq_no,
+            security_id: snapshot.security_id,
+            bids: side(&snapshot.bids),
+            asks: side(&snapshot.asks),
+        })
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct ProtoUpdateParser {
+    security_id_to_deque: HashMap<u64, BatchedDeque<UpdateLevel>>,
+    max_held_levels: Option<usize>,
+    buffer: Vec<u8>,
+}
+
+impl ProtoUpdateParser {
+    // See OrderBookUpdateParser::with_max_held_levels
+    pub fn with_max_held_levels(mut self, max_held_levels: Option<usize>) -> Self {
+        self.max_held_levels = max_held_levels;
+        self
+    }
+}
+
+impl Parser<OrderBookUpdate> for ProtoUpdateParser {
+    fn read<R: Read>(&mut self, reader: &mut R) -> Result<OrderBookUpdate, ParserError> {
+        read_message(reader, &mut self.buffer)?;
+        let update: ProtoUpdate = decode(&self.buffer)?;
+        if update.levels.len() > MAX_NUM_UPDATES {
+            return Err(ParserError::Custom(format!(
+                "Number of updates is too large: {}",
+                update.levels.len()
+            )));
+        }
+
+        let levels = update
+            .levels
+            .into_iter()
+            .map(|level| -> Result<UpdateLevel, ParserError> {
+                let side = match level.side {
+                    0 => Side::Bid,
+                    1 => Side::Ask,
+                    side => return Err(ParserError::Custom(format!("Invalid side: {}", side))),
+                };
+                let metadata = match (level.order_count, level.action) {
+                    (None, None) => None,
+                    (order_count, action) => Some(LevelMetadata {
+                        order_count: order_count.unwrap_or(0),
+                        action: u8::try_from(action.unwrap_or(0)).map_err(|_| {
+                            ParserError::Custom(format!("Invalid action: {:?}", action))
+                        })?,
+                    }),
+                };
+                Ok(UpdateLevel {
+                    side,
+                    price: level.price,
+                    qty: Qty(level.qty),
+                    metadata,
+                })
+            });
+        let deque = self
+            .security_id_to_deque
+            .entry(update.security_id)
+            .or_insert_with(|| update_deque(DEFAULT_UPDATE_DEQUE_CAPACITY, self.max_held_levels));
+        Ok(OrderBookUpdate {
+            timestamp: update.timestamp,
+            capture_timestamp: update.capture_timestamp,
+            seq_no: update.seq_no,
+            security_id: update.security_id,
+            thinned_from: None,
+            updates: deque.push_back_batch(levels)?,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_proto_snapshots() {
+        let snapshot = ProtoSnapshot {
+            timestamp: 1627846265,
+            seq_no: 100,
+            security_id: 1001,
+            bids: vec![
+                ProtoLevel {
+                    price: 100.0,
+                    qty: 10,
+                },
+                ProtoLevel {
+                    price: 99.0,
+                    qty: 20,
+                },
+            ],
+            asks: vec![ProtoLevel {
+                price: 101.0,
+                qty: 15,
+            }],
+        };
+        let mut data = Vec::new();
+        snapshot.encode_length_delimited(&mut data).unwrap();
+        snapshot.encode_length_delimited(&mut data).unwrap();
+
+        let mut parser = ProtoSnapshotParser::default();
+        let mut reader = data.as_slice();
+        for _ in 0..2 {
+            let snapshot = parser.read(&mut reader).unwrap();
+            assert_eq!(snapshot.seq_no, 100);
+            assert_eq!(snapshot.security_id, 1001);
+            assert_eq!(snapshot.bids[1].price, 99.0);
+            assert_eq!(snapshot.asks[0].qty, Qty(15));
+            assert_eq!(snapshot.asks[1].qty, Qty(0));
+        }
+        assert!(matches!(
+            parser.read(&mut reader),
+            Err(ParserError::ExpectedEof)
+        ));
+    }
+
+    #[test]
+    fn test_proto_updates() {
+        let update = ProtoUpdate {
+            timestamp: 1627846266,
+            capture_timestamp: Some(1627846267),
+            seq_no: 101,
+            security_id: 1001,
+            levels: vec![
+                ProtoUpdateLevel {
+                    side: 0,
+                    price: 100.5,
+                    qty: 7,
+                    order_count: None,
+                    action: None,
+                },
+                ProtoUpdateLevel {
+                    side: 1,
+                    price: 101.0,
+                    qty: 0,
+                    order_count: Some(0),
+                    action: Some(2),
+                },
+            ],
+        };
+        let mut data = Vec::new();
+        update.encode_length_delimited(&mut data).unwrap();
+
+        let update = ProtoUpdateParser::default()
+            .read(&mut data.as_slice())
+            .unwrap();
+        assert_eq!(update.seq_no, 101);
+        assert_eq!(update.capture_timestamp, Some(1627846267));
+        let mut levels = Vec::new();
+        update
+            .updates
+            .for_each(|level| {
+                levels.push((level.side, level.price, level.qty.value(), level.metadata));
+                Ok::<(), ()>(())
+            })
+            .unwrap();
+        assert_eq!(
+            levels,
+            vec![
+                (Side::Bid, 100.5, 7, None),
+                (
+                    Side::Ask,
+                    101.0,
+                    0,
+                    Some(LevelMetadata {
+                        order_count: 0,
+                        action: 2,
+                    })
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_proto_truncated_message() {
+        let mut data = Vec::new();
+        ProtoUpdate::default()
+            .encode_length_delimited(&mut data)
+            .unwrap();
+        data.push(10);
+        data.push(1);
+
+        let mut parser = ProtoUpdateParser::default();
+        let mut reader = data.as_slice();
+        assert!(parser.read(&mut reader).is_ok());
+        assert!(matches!(parser.read(&mut reader), Err(ParserError::Io(_))));
+    }
+}
@@ -0,0 +1,147 @@
+use crate::parsing::binary_file_iterator::LastFrame;
+use crate::parsing::parser::{Parser, ParserError};
+use std::io::{self, BufReader, Read};
+use std::marker::PhantomData;
+
+// Records of a text file with one record per line, or of any other stream read record by
+// record, from any reader as the file may be compressed
+pub struct TextFileIterator<T, P: Parser<T>> {
+    reader: BufReader<Box<dyn Read>>,
+    parser: P,
+    record: PhantomData<T>,
+    // Bytes read so far, only counted when the frames are kept
+    offset: u64,
+    frames: Option<LastFrame>,
+    // The frame of a record is its line rather than all the bytes read for it
+    line_frames: bool,
+    read: Vec<u8>,
+}
+
+impl<T, P: Parser<T>> TextFileIterator<T, P> {
+    pub fn from_reader(reader: Box<dyn Read>, parser: P) -> Self {
+        Self {
+            reader: BufReader::new(reader),
+            parser,
+            record: PhantomData,
+            offset: 0,
+            frames: None,
+            line_frames: false,
+            read: Vec::new(),
+        }
+    }
+
+    // Keeps the bytes read for every record returned in `frames`
+    pub fn with_frames(mut self, frames: LastFrame) -> Self {
+        self.frames = Some(frames);
+        self
+    }
+
+    // Keeps the line of every record returned in `frames`, without the lines skipped
+    // before it and its line break
+    pub fn with_line_frames(mut self, frames: LastFrame) -> Self {
+        self.line_frames = true;
+        self.with_frames(frames)
+    }
+
+    fn read_framed(&mut self, frames: &LastFrame) -> Result<T, ParserError> {
+        self.read.clear();
+        let mut reader = RecordingReader {
+            reader: &mut self.reader,
+            read: &mut self.read,
+        };
+        let item = self.parser.read(&mut reader);
+        let mut start = 0;
+        let mut end = self.read.len();
+        if self.line_frames {
+            let line = self.read.strip_suffix(b"\n").unwrap_or(&self.read);
+            end = line.strip_suffix(b"\r").unwrap_or(line).len();
+            start = self.read[..end]
+                .iter()
+                .rposition(|&byte| byte == b'\n')
+                .map_or(0, |line_break| line_break + 1);
+        }
+        if item.is_ok() {
+            frames.set(None, self.offset + start as u64, &self.read[start..end]);
+        }
+        self.offset += self.read.len() as u64;
+        item
+    }
+}
+
+// Keeps the bytes read from the inner reader
+struct RecordingReader<'a, R> {
+    reader: &'a mut R,
+    read: &'a mut Vec<u8>,
+}
+
+impl<R: Read> Read for RecordingReader<'_, R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.reader.read(buf)?;
+        self.read.extend_from_slice(&buf[..read]);
+        Ok(read)
+    }
+}
+
+impl<T, P: Parser<T>> Iterator for TextFileIterator<T, P> {
+    type Item = io::Result<T>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let item = match self.frames.clone() {
+            Some(frames) => self.read_framed(&frames),
+            None => self.parser.read(&mut self.reader),
+        };
+        match item {
+            Ok(item) => Some(Ok(item)),
+            Err(err) => match err {
+                ParserError::Io(io_err) => Some(Err(io_err)),
+                ParserError::ExpectedEof => None,
+                ParserError::Custom(msg) => {
+                    Some(Err(io::Error::new(io::ErrorKind::InvalidData, msg)))
+                }
+            },
+        }
+    }
+}
+
+// Reads the next trimmed line for which `is_record` holds into `line`, without reading past
+// it. Parsers only get a Read, so the line is read byte by byte and the reader is expected
+// to be buffered.
+pub(crate) fn read_record_line<R: Read>(
+    reader: &mut R,
+    line: &mut String,
+    line_no: &mut usize,
+    is_record: fn(&str) -> bool,
+) -> Result<(), ParserError> {
+    loop {
+        let mut bytes = Vec::new();
+        let mut at_eof = true;
+        let mut byte = [0; 1];
+        loop {
+            match reader.read(&mut byte) {
+                Ok(0) => break,
+                Ok(_) if byte[0] == b'\n' => {
+                    at_eof = false;
+                    break;
+                }
+                Ok(_) => bytes.push(byte[0]),
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => return Err(ParserError::Io(e)),
+            }
+        }
+        if at_eof && bytes.is_empty() {
+            return Err(ParserError::ExpectedEof);
+        }
+        *line_no += 1;
+        let text = std::str::from_utf8(&bytes)
+            .map_err(|_| ParserError::Custom(format!("Line {}: not UTF-8", line_no)))?
+            .trim();
+        if !text.is_empty() && is_record(text) {
+            line.clear();
+            line.push_str(text);
+            return Ok(());
+        }
+        if at_eof {
+            return Err(ParserError::ExpectedEof);
+        }
+    }
+}
@@ -0,0 +1,257 @@
+use serde_json::{Value, json};
+use std::collections::{HashMap, HashSet};
+use std::io::{self, Read};
+
+use crate::parsing::dedup::DedupKey;
+use crate::parsing::parser::{Parser, ParserError};
+
+// Issues of each kind listed in a summary, the rest are only counted
+const MAX_LISTED_ISSUES: usize = 100;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RecordIssue {
+    // Index of the record in the file, from 0
+    pub record: u64,
+    // Position of the first byte of the record in the decoded file
+    pub offset: u64,
+    pub description: String,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct IssueList {
+    pub count: u64,
+    pub listed: Vec<RecordIssue>,
+}
+
+impl RecordIssue {
+    pub fn to_json(&self) -> Value {
+        json!({
+            "record": self.record,
+            "offset": self.offset,
+            "description": self.description,
+        })
+    }
+}
+
+impl IssueList {
+    fn push(&mut self, issue: RecordIssue) {
+        self.count += 1;
+        if self.listed.len() < MAX_LISTED_ISSUES {
+            self.listed.push(issue);
+        }
+    }
+
+    pub fn to_json(&self) -> Value {
+        json!({
+            "count": self.count,
+            "listed": self.listed.iter().map(RecordIssue::to_json).collect::<Vec<_>>(),
+        })
+    }
+}
+
+// What checking a file record by record found, without building books
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct FileValidation {
+    pub records: u64,
+    pub securities: usize,
+    pub structural_errors: IssueList,
+    // Offset of the record cut short by the end of the file
+    pub truncated_at: Option<u64>,
+    pub out_of_order: IssueList,
+    pub duplicates: IssueList,
+}
+
+impl FileValidation {
+    pub fn is_valid(&self) -> bool {
+        self.structural_errors.count == 0
+            && self.truncated_at.is_none()
+            && self.out_of_order.count == 0
+            && self.duplicates.count == 0
+    }
+
+    // An object with a field per field of the validation
+    pub fn to_json(&self) -> Value {
+        json!({
+            "records": self.records,
+            "securities": self.securities,
+            "structural_errors": self.structural_errors.to_json(),
+            "truncated_at": self.truncated_at,
+            "out_of_order": self.out_of_order.to_json(),
+            "duplicates": self.duplicates.to_json(),
+        })
+    }
+}
+
+// Counts the bytes read, to locate the records
+struct CountingReader<'a, R> {
+    reader: &'a mut R,
+    offset: u64,
+}
+
+impl<R: Read> Read for CountingReader<'_, R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.reader.read(buf)?;
+        self.offset += read as u64;
+        Ok(read)
+    }
+}
+
+#[derive(Default)]
+struct SecuritySeqNos {
+    last: u64,
+    seen: HashSet<u64>,
+}
+
+// Parses every record of the reader, which starts at `offset` in the file. Binary records
+// have no delimiters, so the first malformed one ends the check unless `resumable` says
+// the parser goes on with the next record, as with one record per line.
+pub fn validate_records<T: DedupKey, R: Read, P: Parser<T>>(
+    reader: &mut R,
+    parser: &mut P,
+    offset: u64,
+    resumable: bool,
+) -> FileValidation {
+    let mut validation = FileValidation::default();
+    let mut securities: HashMap<u64, SecuritySeqNos> = HashMap::new();
+    let mut reader = CountingReader { reader, offset };
+    loop {
+        let record_offset = reader.offset;
+        let record = validation.records;
+        let issue = |description: String| RecordIssue {
+            record,
+            offset: record_offset,
+            description,
+        };
+        match parser.read(&mut reader) {
+            Ok(item) => {
+                let (security_id, seq_no, _) = item.dedup_key();
+                let seq_nos = securities.entry(security_id).or_default();
+                if !seq_nos.seen.insert(seq_no) {
+                    validation.duplicates.push(issue(format!(
+                        "Duplicate seq_no {} of security {}",
+                        seq_no, security_id
+                    )));
+                } else if seq_no < seq_nos.last {
+                    validation.out_of_order.push(issue(format!(
+                        "Seq_no {} of security {} after {}",
+                        seq_no, security_id, seq_nos.last
+                    )));
+                }
+                seq_nos.last = seq_nos.last.max(seq_no);
+            }
+            // Binary parsers also report the end of the file within the first field as its
+            // end, while text parsers skip blank lines before it
+            Err(ParserError::ExpectedEof) if resumable || reader.offset == record_offset => {
+                break;
+            }
+            Err(ParserError::ExpectedEof) => {
+                validation.truncated_at = Some(record_offset);
+                break;
+            }
+            Err(ParserError::Io(e)) if e.kind() == io::ErrorKind::UnexpectedEof => {
+                validation.truncated_at = Some(record_offset);
+                break;
+            }
+            Err(ParserError::Io(e)) => {
+                validation.structural_errors.push(issue(e.to_string()));
+                break;
+            }
+            Err(ParserError::Custom(msg)) => {
+                validation.structural_errors.push(issue(msg));
+                if !resumable {
+                    break;
+                }
+            }
+        }
+        validation.records += 1;
+    }
+    validation.securities = securities.len();
+    validation
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parsing::csv_parser::CsvUpdateParser;
+    use crate::parsing::order_book_update::{OrderBookUpdateParser, UpdateFormat};
+
+    fn update(seq_no: u64, security_id: u64) -> Vec<u8> {
+        let mut data = Vec::new();
+        data.extend_from_slice(&1627846265u64.to_le_bytes()); // timestamp
+        data.extend_from_slice(&seq_no.to_le_bytes());
+        data.extend_from_slice(&security_id.to_le_bytes());
+        data.extend_from_slice(&1u64.to_le_bytes()); // num_updates
+        data.push(0); // side
+        data.extend_from_slice(&100.0f64.to_le_bytes()); // price
+        data.extend_from_slice(&10u64.to_le_bytes()); // qty
+        data
+    }
+
+    #[test]
+    fn test_valid_file() {
+        let data = [update(1, 7), update(2, 7), update(1, 8)].concat();
+        let validation = validate_records(
+            &mut data.as_slice(),
+            &mut OrderBookUpdateParser::new(UpdateFormat::V1),
+            0,
+            false,
+        );
+        assert!(validation.is_valid());
+        assert_eq!(validation.truncated_at, None);
+        assert_eq!(validation.records, 3);
+        assert_eq!(validation.securities, 2);
+    }
+
+    #[test]
+    fn test_seq_no_issues_and_truncation() {
+        let record_size = update(1, 7).len() as u64;
+        let mut data = [update(2, 7), update(1, 7), update(2, 7), update(3, 7)].concat();
+        data.truncate(data.len() - 4);
+        let validation = validate_records(
+            &mut data.as_slice(),
+            &mut OrderBookUpdateParser::new(UpdateFormat::V1),
+            8,
+            false,
+        );
+
+        assert!(!validation.is_valid());
+        assert_eq!(validation.records, 3);
+        assert_eq!(validation.out_of_order.count, 1);
+        assert_eq!(validation.out_of_order.listed[0].record, 1);
+        assert_eq!(validation.out_of_order.listed[0].offset, 8 + record_size);
+        assert_eq!(validation.duplicates.count, 1);
+        assert_eq!(validation.duplicates.listed[0].record, 2);
+        assert_eq!(validation.truncated_at, Some(8 + 3 * record_size));
+    }
+
+    #[test]
+    fn test_resumable_structural_errors() {
+        let data = "1,1,7,bid,100,10\n1,2,7,buy,100,10\n1,3,7,ask,101,5\n\n";
+        let validation = validate_records(
+            &mut data.as_bytes(),
+            &mut CsvUpdateParser::new(UpdateFormat::V1),
+            0,
+            true,
+        );
+        assert_eq!(validation.records, 3);
+        assert_eq!(validation.structural_errors.count, 1);
+        assert_eq!(validation.structural_errors.listed[0].record, 1);
+        assert_eq!(validation.structural_errors.listed[0].offset, 17);
+        assert_eq!(validation.out_of_order.count, 0);
+        assert_eq!(validation.truncated_at, None);
+    }
+
+    #[test]
+    fn test_truncated_first_field() {
+        let mut data = update(1, 7);
+        data.extend_from_slice(&[0; 4]);
+        let validation = validate_records(
+            &mut data.as_slice(),
+            &mut OrderBookUpdateParser::new(UpdateFormat::V1),
+            0,
+            false,
+        );
+        assert_eq!(validation.records, 1);
+        assert_eq!(validation.truncated_at, Some(update(1, 7).len() as u64));
+    }
+}
@@ -0,0 +1,291 @@
+use std::str::FromStr;
+
+use crate::clock::Clock;
+use crate::order_book::manager::Manager;
+use crate::parsing::order_book_snapshot::OrderBookSnapshot;
+use crate::parsing::order_book_update::OrderBookUpdate;
+
+// Holds events back until the clock catches up with their timestamps, relative to the
+// first event and scaled by `speed` (2.0 plays twice as fast as recorded). Events older
+// than the previous ones are released immediately.
+pub struct Pacer<C: Clock> {
+    clock: C,
+    speed: f64,
+    // (first event timestamp, clock time when it was released)
+    origin: Option<(u64, u64)>,
+}
+
+impl<C: Clock> Pacer<C> {
+    pub fn new(clock: C, speed: f64) -> Self {
+        assert!(speed > 0.0, "Replay speed must be positive, got {}", speed);
+        Self {
+            clock,
+            speed,
+            origin: None,
+        }
+    }
+
+    pub fn wait_until(&mut self, event_timestamp: u64) {
+        let now = self.clock.now_millis();
+        let (origin_timestamp, origin_now) = *self.origin.get_or_insert((event_timestamp, now));
+        if event_timestamp <= origin_timestamp {
+            return;
+        }
+        let offset = ((event_timestamp - origin_timestamp) as f64 / self.speed) as u64;
+        let release_at = origin_now + offset;
+        if release_at > now {
+            self.clock.sleep_millis(release_at - now);
+        }
+    }
+}
+
+// The moment of a historical book state: the records up to a seq_no of each security, or
+// up to an exchange timestamp in millis
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AsOf {
+    SeqNo(u64),
+    Timestamp(u64),
+}
+
+impl AsOf {
+    pub fn includes(&self, seq_no: u64, timestamp: u64) -> bool {
+        match *self {
+            AsOf::SeqNo(last) => seq_no <= last,
+            AsOf::Timestamp(last) => timestamp <= last,
+        }
+    }
+}
+
+// An exchange timestamp in millis since the epoch, or an RFC 3339 date and time
+pub fn parse_event_time(s: &str) -> Result<u64, String> {
+    match s.parse() {
+        Ok(millis) => Ok(millis),
+        Err(_) => chrono::DateTime::parse_from_rfc3339(s)
+            .ok()
+            .and_then(|datetime| u64::try_from(datetime.timestamp_millis()).ok())
+            .ok_or_else(|| format!("Invalid time, expected millis or RFC 3339: {}", s)),
+    }
+}
+
+// "seq_no:N", or "timestamp:" followed by millis or an RFC 3339 date and time
+impl FromStr for AsOf {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("Invalid as-of, expected seq_no:N or timestamp:T: {}", s);
+        match s.split_once(':') {
+            Some(("seq_no", seq_no)) => seq_no.parse().map(AsOf::SeqNo).map_err(|_| invalid()),
+            Some(("timestamp", timestamp)) => parse_event_time(timestamp)
+                .map(AsOf::Timestamp)
+                .map_err(|_| invalid()),
+            _ => Err(invalid()),
+        }
+    }
+}
+
+// The seq_no of the updates to apply, both bounds inclusive and compared with the seq_no
+// of each security, to replay an isolated window of a capture
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SeqRange {
+    pub start: Option<u64>,
+    pub end: Option<u64>,
+}
+
+impl SeqRange {
+    pub fn new(start: Option<u64>, end: Option<u64>) -> Result<Self, String> {
+        if let (Some(start), Some(end)) = (start, end)
+            && start > end
+        {
+            return Err(format!(
+                "The start seq_no {} is after the end seq_no {}",
+                start, end
+            ));
+        }
+        Ok(Self { start, end })
+    }
+
+    pub fn includes(&self, seq_no: u64) -> bool {
+        self.start.is_none_or(|start| seq_no >= start) && self.end.is_none_or(|end| seq_no <= end)
+    }
+
+    pub fn is_unbounded(&self) -> bool {
+        self.start.is_none() && self.end.is_none()
+    }
+}
+
+// Applies the snapshots and then the updates up to `as_of`, leaving the books of the
+// manager as they were at that moment
+pub fn replay_as_of<S, U>(manager: &mut Manager, snapshots: S, updates: U, as_of: AsOf)
+where
+    S: IntoIterator<Item = OrderBookSnapshot>,
+    U: IntoIterator<Item = OrderBookUpdate>,
+{
+    for snapshot in snapshots {
+        if as_of.includes(snapshot.seq_no, snapshot.timestamp) {
+            manager.apply_snapshot(&snapshot);
+        }
+    }
+    for update in updates {
+        if as_of.includes(update.seq_no, update.timestamp) {
+            manager.apply_update(update);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::batched_deque::batched_deque::BatchedDeque;
+    use crate::clock::TestClock;
+    use crate::order_book::units::Qty;
+    use crate::parsing::order_book_snapshot::test_support::create_test_snapshot;
+    use crate::parsing::order_book_update::{Level as UpdateLevel, Side};
+    use rust_decimal::dec;
+
+    #[test]
+    fn test_real_time_pacing() {
+        let clock = TestClock::new(1_000);
+        let mut pacer = Pacer::new(&clock, 1.0);
+
+        pacer.wait_until(50_000);
+        assert_eq!(clock.now_millis(), 1_000);
+        pacer.wait_until(50_250);
+        assert_eq!(clock.now_millis(), 1_250);
+        pacer.wait_until(50_250);
+        assert_eq!(clock.now_millis(), 1_250);
+    }
+
+    #[test]
+    fn test_scaled_pacing() {
+        let clock = TestClock::new(0);
+        let mut pacer = Pacer::new(&clock, 4.0);
+
+        pacer.wait_until(10_000);
+        pacer.wait_until(11_000);
+        assert_eq!(clock.now_millis(), 250);
+
+        // Time spent between events counts towards the wait
+        clock.advance(500);
+        pacer.wait_until(12_000);
+        assert_eq!(clock.now_millis(), 750);
+    }
+
+    #[test]
+    fn test_out_of_order_event_not_delayed() {
+        let clock = TestClock::new(0);
+        let mut pacer = Pacer::new(&clock, 1.0);
+
+        pacer.wait_until(10_000);
+        pacer.wait_until(9_000);
+        assert_eq!(clock.now_millis(), 0);
+    }
+
+    fn create_test_update(
+        deque: &BatchedDeque<UpdateLevel>,
+        security_id: u64,
+        seq_no: u64,
+        timestamp: u64,
+    ) -> OrderBookUpdate {
+        let level = UpdateLevel {
+            side: Side::Bid,
+            price: 100.0,
+            qty: Qty(seq_no),
+            metadata: None,
+        };
+        OrderBookUpdate {
+            timestamp,
+            capture_timestamp: None,
+            seq_no,
+            security_id,
+            thinned_from: None,
+            updates: deque
+                .push_back_batch([Ok::<_, ()>(level)].into_iter())
+                .unwrap(),
+        }
+    }
+
+    #[test]
+    fn test_parse_as_of() {
+        assert_eq!("seq_no:42".parse(), Ok(AsOf::SeqNo(42)));
+        assert_eq!(
+            "timestamp:1705717810000".parse(),
+            Ok(AsOf::Timestamp(1705717810000))
+        );
+        assert_eq!(
+            "timestamp:2024-01-20T02:30:10.5Z".parse(),
+            Ok(AsOf::Timestamp(1705717810500))
+        );
+        assert!("42".parse::<AsOf>().is_err());
+        assert!("timestamp:yesterday".parse::<AsOf>().is_err());
+    }
+
+    #[test]
+    fn test_replay_as_of_seq_no() {
+        let deque = BatchedDeque::new(16);
+        let snapshots = vec![
+            OrderBookSnapshot {
+                timestamp: 1_000,
+                ..create_test_snapshot(1, 10)
+            },
+            OrderBookSnapshot {
+                timestamp: 1_000,
+                ..create_test_snapshot(2, 20)
+            },
+        ];
+        let updates = vec![
+            create_test_update(&deque, 1, 11, 2_000),
+            create_test_update(&deque, 2, 21, 2_000),
+            create_test_update(&deque, 1, 12, 3_000),
+            create_test_update(&deque, 2, 22, 3_000),
+        ];
+
+        let mut manager = Manager::default();
+        replay_as_of(&mut manager, snapshots, updates, AsOf::SeqNo(21));
+
+        let book = &manager.buffered_order_books[&1].order_book;
+        assert_eq!(book.seq_no, 12);
+        let book = &manager.buffered_order_books[&2].order_book;
+        assert_eq!(book.seq_no, 21);
+        assert_eq!(book.best_bid(), Some((dec!(100), Qty(21))));
+    }
+
+    #[test]
+    fn test_replay_as_of_timestamp() {
+        let deque = BatchedDeque::new(16);
+        let snapshots = vec![
+            OrderBookSnapshot {
+                timestamp: 1_000,
+                ..create_test_snapshot(1, 10)
+            },
+            OrderBookSnapshot {
+                timestamp: 2_500,
+                ..create_test_snapshot(2, 20)
+            },
+        ];
+        let updates = vec![
+            create_test_update(&deque, 1, 11, 2_000),
+            create_test_update(&deque, 1, 12, 3_000),
+        ];
+
+        let mut manager = Manager::default();
+        replay_as_of(&mut manager, snapshots, updates, AsOf::Timestamp(2_000));
+
+        assert_eq!(manager.buffered_order_books[&1].order_book.seq_no, 11);
+        assert!(!manager.buffered_order_books.contains_key(&2));
+    }
+
+    #[test]
+    fn test_seq_range() {
+        let range = SeqRange::new(Some(10), Some(20)).unwrap();
+        assert!(!range.includes(9));
+        assert!(range.includes(10));
+        assert!(range.includes(20));
+        assert!(!range.includes(21));
+
+        let range = SeqRange::new(Some(10), None).unwrap();
+        assert!(range.includes(u64::MAX));
+        assert!(SeqRange::default().includes(0));
+        assert!(SeqRange::default().is_unbounded());
+        assert!(SeqRange::new(Some(20), Some(10)).is_err());
+    }
+}
@@ -0,0 +1,284 @@
+use std::cell::UnsafeCell;
+use std::mem::MaybeUninit;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::thread;
+
+// Failed pushes and pops spin this many times before yielding the thread
+const SPINS_BEFORE_YIELD: u32 = 64;
+
+// Keeps the indices written by each side on cache lines of their own
+#[repr(align(64))]
+struct CachePadded<T>(T);
+
+struct Ring<T> {
+    slots: Box<[UnsafeCell<MaybeUninit<T>>]>,
+    // Next slot to pop, only written by the consumer. Both indices grow without bound
+    // and wrap around the slots.
+    head: CachePadded<AtomicUsize>,
+    // Next slot to push, only written by the producer
+    tail: CachePadded<AtomicUsize>,
+    // The other side was dropped
+    disconnected: AtomicBool,
+}
+
+// SAFETY: a slot is only accessed by the producer before the tail moves past it and by
+// the consumer after, and the indices are published with release/acquire ordering
+unsafe impl<T: Send> Sync for Ring<T> {}
+
+impl<T> Ring<T> {
+    fn slot(&self, index: usize) -> *mut MaybeUninit<T> {
+        self.slots[index % self.slots.len()].get()
+    }
+}
+
+impl<T> Drop for Ring<T> {
+    fn drop(&mut self) {
+        let tail = *self.tail.0.get_mut();
+        for index in *self.head.0.get_mut()..tail {
+            // SAFETY: the slots between head and tail hold values pushed and not popped
+            unsafe { (*self.slot(index)).assume_init_drop() };
+        }
+    }
+}
+
+// A bounded queue handing values from one thread to another without locks, e.g. the
+// updates parsed by an I/O thread to the thread building the books. Pushing blocks while
+// the ring is full, so a slow consumer holds the producer back instead of letting the
+// backlog grow.
+pub fn channel<T: Send>(capacity: usize) -> (Producer<T>, Consumer<T>) {
+    assert!(capacity > 0, "The capacity of the ring must be positive");
+    let ring = Arc::new(Ring {
+        slots: (0..capacity)
+            .map(|_| UnsafeCell::new(MaybeUninit::uninit()))
+            .collect(),
+        head: CachePadded(AtomicUsize::new(0)),
+        tail: CachePadded(AtomicUsize::new(0)),
+        disconnected: AtomicBool::new(false),
+    });
+    (
+        Producer {
+            ring: ring.clone(),
+            cached_head: 0,
+        },
+        Consumer {
+            ring,
+            cached_tail: 0,
+        },
+    )
+}
+
+pub struct Producer<T> {
+    ring: Arc<Ring<T>>,
+    // Last head seen, so the shared index is only read when the ring looks full
+    cached_head: usize,
+}
+
+impl<T> Producer<T> {
+    // Gives the value back when the ring is full
+    pub fn try_push(&mut self, value: T) -> Result<(), T> {
+        let ring = &self.ring;
+        let tail = ring.tail.0.load(Ordering::Relaxed);
+        if tail - self.cached_head == ring.slots.len() {
+            self.cached_head = ring.head.0.load(Ordering::Acquire);
+            if tail - self.cached_head == ring.slots.len() {
+                return Err(value);
+            }
+        }
+        // SAFETY: the slot at tail is free, the consumer doesn't read it before the tail
+        // moves past it
+        unsafe { (*ring.slot(tail)).write(value) };
+        ring.tail.0.store(tail + 1, Ordering::Release);
+        Ok(())
+    }
+
+    // Waits for a free slot. Gives the value back when the consumer was dropped.
+    pub fn push(&mut self, mut value: T) -> Result<(), T> {
+        let mut spins = 0;
+        loop {
+            if self.ring.disconnected.load(Ordering::Acquire) {
+                return Err(value);
+            }
+            match self.try_push(value) {
+                Ok(()) => return Ok(()),
+                Err(rejected) => value = rejected,
+            }
+            backoff(&mut spins);
+        }
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.ring.slots.len()
+    }
+}
+
+impl<T> Drop for Producer<T> {
+    fn drop(&mut self) {
+        self.ring.disconnected.store(true, Ordering::Release);
+    }
+}
+
+pub struct Consumer<T> {
+    ring: Arc<Ring<T>>,
+    // Last tail seen, so the shared index is only read when the ring looks empty
+    cached_tail: usize,
+}
+
+impl<T> Consumer<T> {
+    pub fn try_pop(&mut self) -> Option<T> {
+        let ring = &self.ring;
+        let head = ring.head.0.load(Ordering::Relaxed);
+        if head == self.cached_tail {
+            self.cached_tail = ring.tail.0.load(Ordering::Acquire);
+            if head == self.cached_tail {
+                return None;
+            }
+        }
+        // SAFETY: the slot at head was written before the tail moved past it, and the
+        // producer doesn't reuse it before the head moves past it
+        let value = unsafe { (*ring.slot(head)).assume_init_read() };
+        ring.head.0.store(head + 1, Ordering::Release);
+        Some(value)
+    }
+
+    // Waits for a value. Returns None once the producer was dropped and the ring drained.
+    pub fn pop(&mut self) -> Option<T> {
+        let mut spins = 0;
+        loop {
+            // Checked before the ring, so values pushed before the drop are not lost
+            let disconnected = self.ring.disconnected.load(Ordering::Acquire);
+            if let Some(value) = self.try_pop() {
+                return Some(value);
+            }
+            if disconnected {
+                return None;
+            }
+            backoff(&mut spins);
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        let tail = self.ring.tail.0.load(Ordering::Acquire);
+        tail - self.ring.head.0.load(Ordering::Relaxed)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl<T> Iterator for Consumer<T> {
+    type Item = T;
+
+    fn next(&mut self) -> Option<T> {
+        self.pop()
+    }
+}
+
+impl<T> Drop for Consumer<T> {
+    fn drop(&mut self) {
+        self.ring.disconnected.store(true, Ordering::Release);
+    }
+}
+
+fn backoff(spins: &mut u32) {
+    if *spins < SPINS_BEFORE_YIELD {
+        *spins += 1;
+        std::hint::spin_loop();
+    } else {
+        thread::yield_now();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parsing::order_book_update::{OrderBookUpdateParser, UpdateFormat};
+    use crate::parsing::parser::Parser;
+
+    #[test]
+    fn test_try_push_full_ring() {
+        let (mut producer, mut consumer) = channel(2);
+        assert_eq!(producer.try_push(1), Ok(()));
+        assert_eq!(producer.try_push(2), Ok(()));
+        assert_eq!(producer.try_push(3), Err(3));
+        assert_eq!(consumer.len(), 2);
+        assert_eq!(consumer.try_pop(), Some(1));
+        assert_eq!(producer.try_push(3), Ok(()));
+        assert_eq!(consumer.try_pop(), Some(2));
+        assert_eq!(consumer.try_pop(), Some(3));
+        assert_eq!(consumer.try_pop(), None);
+        assert!(consumer.is_empty());
+    }
+
+    #[test]
+    fn test_hand_off_between_threads() {
+        let (mut producer, consumer) = channel(16);
+        let handle = thread::spawn(move || {
+            for i in 0..100_000u64 {
+                producer.push(i).unwrap();
+            }
+        });
+        let received = consumer.collect::<Vec<_>>();
+        handle.join().unwrap();
+        assert_eq!(received.len(), 100_000);
+        assert!(received.iter().enumerate().all(|(i, x)| *x == i as u64));
+    }
+
+    #[test]
+    fn test_disconnected() {
+        let (mut producer, consumer) = channel(1);
+        drop(consumer);
+        assert_eq!(producer.push(1), Err(1));
+
+        let (mut producer, mut consumer) = channel(4);
+        producer.push(1).unwrap();
+        drop(producer);
+        assert_eq!(consumer.pop(), Some(1));
+        assert_eq!(consumer.pop(), None);
+    }
+
+    #[test]
+    fn test_values_left_are_dropped() {
+        let value = Arc::new(());
+        let (mut producer, mut consumer) = channel(4);
+        for _ in 0..3 {
+            producer.push(value.clone()).unwrap();
+        }
+        consumer.pop();
+        drop((producer, consumer));
+        assert_eq!(Arc::strong_count(&value), 1);
+    }
+
+    #[test]
+    fn test_hand_off_parsed_updates() {
+        let mut data = Vec::new();
+        for seq_no in 1..=3u64 {
+            data.extend_from_slice(&1627846266u64.to_le_bytes()); // timestamp
+            data.extend_from_slice(&seq_no.to_le_bytes());
+            data.extend_from_slice(&1001u64.to_le_bytes()); // security_id
+            data.extend_from_slice(&1u64.to_le_bytes()); // num_updates
+            data.push(0); // side
+            data.extend_from_slice(&100.0f64.to_le_bytes()); // price
+            data.extend_from_slice(&seq_no.to_le_bytes()); // qty
+        }
+        let (mut producer, consumer) = channel(2);
+        let handle = thread::spawn(move || {
+            let mut parser = OrderBookUpdateParser::new(UpdateFormat::V1);
+            let mut reader = data.as_slice();
+            while let Ok(update) = parser.read(&mut reader) {
+                // Parsed levels stay in the deque of the parser, owned ones can be sent
+                producer.push(update.with_owned_levels()).unwrap();
+            }
+        });
+        let updates = consumer.collect::<Vec<_>>();
+        handle.join().unwrap();
+        assert_eq!(
+            updates
+                .iter()
+                .map(|update| (update.seq_no, update.updates[0].qty.value()))
+                .collect::<Vec<_>>(),
+            vec![(1, 1), (2, 2), (3, 3)]
+        );
+    }
+}